@@ -134,7 +134,7 @@ pub fn write_object(criterion: &mut Criterion) {
             |bencher, config| {
                 bencher.iter_batched(
                     || {
-                        let mut repo = open_repo(config).unwrap();
+                        let repo = open_repo(config).unwrap();
                         repo.insert(String::from(TEST_KEY));
                         (repo, random_bytes(*OBJECT_SIZE as usize))
                     },
@@ -173,7 +173,7 @@ pub fn read_object(criterion: &mut Criterion) {
                 bencher.iter_batched(
                     || {
                         // Write data to the object.
-                        let mut repo = open_repo(config).unwrap();
+                        let repo = open_repo(config).unwrap();
                         let mut object = repo.insert(String::from(TEST_KEY));
                         let data = random_bytes(*OBJECT_SIZE as usize);
                         object.write_all(data.as_slice()).unwrap();
@@ -0,0 +1,78 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Wrappers around `ioctl` for commands which pass a buffer of bytes.
+//!
+//! The request code for each command is generated from the length of the buffer which is passed
+//! to it, so the kernel never reads or writes past the end of the buffer.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use nix::{request_code_none, request_code_read, request_code_write};
+
+/// The maximum size of the data passed to an ioctl.
+///
+/// The size field of a request code is 13 bits on some platforms and 14 bits on others.
+const MAX_SIZE: usize = (1 << 13) - 1;
+
+/// Return an error if `data` is too large to be passed to an ioctl.
+fn check_size(data: &[u8]) -> io::Result<()> {
+    if data.len() > MAX_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "ioctl data is too large",
+        ));
+    }
+    Ok(())
+}
+
+/// Convert the return value of an ioctl to a `Result`.
+fn ioctl_result(result: libc::c_int) -> io::Result<()> {
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Send command `number` of type `kind`, which passes no data, to `file`.
+pub fn ioctl_none(file: &File, kind: u8, number: u8) -> io::Result<()> {
+    let request = request_code_none!(kind, number);
+
+    // This is safe because the request code specifies that no data is passed.
+    ioctl_result(unsafe { libc::ioctl(file.as_raw_fd(), request) })
+}
+
+/// Send command `number` of type `kind`, which passes `data` to the kernel, to `file`.
+pub fn ioctl_write(file: &File, kind: u8, number: u8, data: &[u8]) -> io::Result<()> {
+    check_size(data)?;
+    let request = request_code_write!(kind, number, data.len());
+
+    // This is safe because the request code specifies that `data.len()` bytes are read from
+    // `data`.
+    ioctl_result(unsafe { libc::ioctl(file.as_raw_fd(), request, data.as_ptr()) })
+}
+
+/// Send command `number` of type `kind`, which fills `data` from the kernel, to `file`.
+pub fn ioctl_read(file: &File, kind: u8, number: u8, data: &mut [u8]) -> io::Result<()> {
+    check_size(data)?;
+    let request = request_code_read!(kind, number, data.len());
+
+    // This is safe because the request code specifies that `data.len()` bytes are written to
+    // `data`.
+    ioctl_result(unsafe { libc::ioctl(file.as_raw_fd(), request, data.as_mut_ptr()) })
+}
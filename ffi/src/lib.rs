@@ -0,0 +1,28 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Safe wrappers around the platform APIs used by `acid-store`.
+//!
+//! `acid-store` forbids unsafe code, so the few operating system calls it needs which aren't
+//! exposed by the standard library or another crate are wrapped here instead. Every function in
+//! this crate is safe to call with any arguments.
+
+#[cfg(unix)]
+pub mod ioctl;
+#[cfg(target_os = "macos")]
+pub mod macos;
+#[cfg(windows)]
+pub mod windows;
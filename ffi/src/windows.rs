@@ -0,0 +1,267 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Wrappers around Win32 file system APIs.
+
+use std::convert::TryInto;
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+use std::path::Path;
+
+use winapi::ctypes::c_void;
+use winapi::shared::minwindef::MAX_PATH;
+use winapi::shared::winerror::ERROR_HANDLE_EOF;
+use winapi::um::fileapi::{
+    FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard, SetFileAttributesW,
+};
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::securitybaseapi::{GetFileSecurityW, IsValidSecurityDescriptor, SetFileSecurityW};
+use winapi::um::winnt::{
+    DACL_SECURITY_INFORMATION, GROUP_SECURITY_INFORMATION, OWNER_SECURITY_INFORMATION,
+    SE_DACL_PRESENT, SE_SACL_PRESENT, SE_SELF_RELATIVE,
+};
+
+/// Encode `path` as a null-terminated wide string.
+fn wide_path(path: &Path) -> Vec<u16> {
+    path.as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+/// The `WIN32_FIND_STREAM_DATA` struct, which `winapi` doesn't define.
+#[repr(C)]
+struct FindStreamData {
+    stream_size: i64,
+    stream_name: [u16; MAX_PATH + 36],
+}
+
+/// Return the names of the alternate data streams of the file at `path`.
+///
+/// The unnamed default stream is not included.
+pub fn stream_names(path: &Path) -> io::Result<Vec<String>> {
+    let path = wide_path(path);
+    let mut names = Vec::new();
+    let mut stream_data = FindStreamData {
+        stream_size: 0,
+        stream_name: [0; MAX_PATH + 36],
+    };
+
+    // This is safe because `path` is null-terminated and `stream_data` is the struct which
+    // `FindStreamInfoStandard` requires.
+    let handle = unsafe {
+        FindFirstStreamW(
+            path.as_ptr(),
+            FindStreamInfoStandard,
+            &mut stream_data as *mut _ as *mut c_void,
+            0,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        let error = io::Error::last_os_error();
+        // This means the file has no streams, which is the case for most directories.
+        return match error.raw_os_error() {
+            Some(code) if code == ERROR_HANDLE_EOF as i32 => Ok(names),
+            _ => Err(error),
+        };
+    }
+
+    loop {
+        let name_len = stream_data
+            .stream_name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(stream_data.stream_name.len());
+        let name = String::from_utf16_lossy(&stream_data.stream_name[..name_len]);
+
+        // Stream names have the form `:name:$DATA`. The default stream has an empty name.
+        if let Some(name) = name
+            .strip_prefix(':')
+            .and_then(|name| name.strip_suffix(":$DATA"))
+        {
+            if !name.is_empty() {
+                names.push(name.to_owned());
+            }
+        }
+
+        // This is safe because `handle` is a valid search handle until it is closed below.
+        if unsafe { FindNextStreamW(handle, &mut stream_data as *mut _ as *mut c_void) } == 0 {
+            let error = io::Error::last_os_error();
+            unsafe { FindClose(handle) };
+            return match error.raw_os_error() {
+                Some(code) if code == ERROR_HANDLE_EOF as i32 => Ok(names),
+                _ => Err(error),
+            };
+        }
+    }
+}
+
+/// Return the self-relative security descriptor of the file at `path`.
+///
+/// This contains the owner, primary group, and DACL of the file.
+pub fn get_security_descriptor(path: &Path) -> io::Result<Vec<u8>> {
+    let path = wide_path(path);
+    let information =
+        OWNER_SECURITY_INFORMATION | GROUP_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION;
+
+    // Call once to get the size of the buffer and then again to fill it. This is safe because
+    // `path` is null-terminated and a null buffer with a length of zero is allowed.
+    let mut length = 0;
+    unsafe {
+        GetFileSecurityW(
+            path.as_ptr(),
+            information,
+            std::ptr::null_mut(),
+            0,
+            &mut length,
+        )
+    };
+    if length == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // This is safe because `descriptor` is `length` bytes long.
+    let mut descriptor = vec![0u8; length as usize];
+    let result = unsafe {
+        GetFileSecurityW(
+            path.as_ptr(),
+            information,
+            descriptor.as_mut_ptr() as *mut c_void,
+            length,
+            &mut length,
+        )
+    };
+    if result == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(descriptor)
+}
+
+/// Read a little-endian `u16` from `bytes` at `offset`.
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let bytes = bytes.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Read a little-endian `u32` from `bytes` at `offset`.
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Return whether the SID at `offset` in `descriptor` is contained within it.
+fn sid_in_bounds(descriptor: &[u8], offset: usize) -> bool {
+    // A SID is an 8-byte header followed by `SubAuthorityCount` 4-byte sub-authorities.
+    match descriptor.get(offset..) {
+        Some(sid) if sid.len() >= 8 => sid.len() >= 8 + 4 * sid[1] as usize,
+        _ => false,
+    }
+}
+
+/// Return whether the ACL at `offset` in `descriptor` is contained within it.
+fn acl_in_bounds(descriptor: &[u8], offset: usize) -> bool {
+    // An ACL starts with an 8-byte header which contains its total size as `AclSize`.
+    match descriptor.get(offset..) {
+        Some(acl) => match read_u16(acl, 2) {
+            Some(size) => size >= 8 && size as usize <= acl.len(),
+            None => false,
+        },
+        None => false,
+    }
+}
+
+/// Return whether `descriptor` is a well-formed self-relative security descriptor.
+///
+/// The Win32 APIs trust the offsets in a self-relative security descriptor, so they must be
+/// checked against the length of the buffer before it's passed to them.
+fn is_valid_descriptor(descriptor: &[u8]) -> bool {
+    // The header is `Revision`, `Sbz1`, `Control`, and the offsets of the owner, group, SACL,
+    // and DACL.
+    let control = match read_u16(descriptor, 2) {
+        Some(control) if descriptor.len() >= 20 => control,
+        _ => return false,
+    };
+    if control & SE_SELF_RELATIVE == 0 {
+        return false;
+    }
+
+    let offset = |index: usize| read_u32(descriptor, 4 + 4 * index).unwrap_or(0) as usize;
+    let (owner, group, sacl, dacl) = (offset(0), offset(1), offset(2), offset(3));
+
+    if (owner != 0 && !sid_in_bounds(descriptor, owner))
+        || (group != 0 && !sid_in_bounds(descriptor, group))
+        || (control & SE_SACL_PRESENT != 0 && sacl != 0 && !acl_in_bounds(descriptor, sacl))
+        || (control & SE_DACL_PRESENT != 0 && dacl != 0 && !acl_in_bounds(descriptor, dacl))
+    {
+        return false;
+    }
+
+    // This is safe because we've checked that every part of the descriptor is in bounds.
+    unsafe { IsValidSecurityDescriptor(descriptor.as_ptr() as *mut c_void) != 0 }
+}
+
+/// Write the self-relative security `descriptor` to the file at `path`.
+///
+/// Setting the owner and group requires special privileges, so if they can't be set, only the
+/// DACL is written.
+///
+/// # Errors
+/// - `io::ErrorKind::InvalidData`: The `descriptor` is not a valid self-relative security
+///   descriptor.
+pub fn set_security_descriptor(path: &Path, descriptor: &[u8]) -> io::Result<()> {
+    if !is_valid_descriptor(descriptor) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid security descriptor",
+        ));
+    }
+
+    let path = wide_path(path);
+    let mut descriptor = descriptor.to_vec();
+
+    for information in &[
+        OWNER_SECURITY_INFORMATION | GROUP_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION,
+        DACL_SECURITY_INFORMATION,
+    ] {
+        // This is safe because `path` is null-terminated and we've checked that `descriptor` is
+        // valid.
+        let result = unsafe {
+            SetFileSecurityW(
+                path.as_ptr(),
+                *information,
+                descriptor.as_mut_ptr() as *mut c_void,
+            )
+        };
+        if result != 0 {
+            return Ok(());
+        }
+    }
+
+    Err(io::Error::last_os_error())
+}
+
+/// Set the attributes of the file at `path` to `attributes`.
+///
+/// This overwrites all the attributes of the file, so `FILE_ATTRIBUTE_NORMAL` must be used to
+/// clear them.
+pub fn set_file_attributes(path: &Path, attributes: u32) -> io::Result<()> {
+    // This is safe because `path` is null-terminated.
+    if unsafe { SetFileAttributesW(wide_path(path).as_ptr(), attributes) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
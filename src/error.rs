@@ -13,16 +13,30 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+use std::fmt::{self, Display, Formatter};
 use std::io;
 use std::result;
 
 use thiserror::Error as DeriveError;
+use uuid::Uuid;
+
+use crate::store::StoreFailure;
 
 /// The error type for operations with a repository.
 ///
 /// This type can be converted `From` and `Into` an `io::Error` for compatibility with types from
 /// `std::io` like `Read`, `Write`, and `Seek`. Even if the payload of the `io::Error` cannot be
 /// downcast to a value of this type, it will be converted to `Error::Io`.
+///
+/// Each variant has a stable error code which is returned by [`code`] and which can be used to
+/// handle errors programmatically or correlate them in logs. Variants which wrap an underlying
+/// error return it from [`source`], and errors which occurred in the data store carry an
+/// [`ErrorContext`] which is returned by [`context`].
+///
+/// [`code`]: crate::Error::code
+/// [`source`]: std::error::Error::source
+/// [`ErrorContext`]: crate::ErrorContext
+/// [`context`]: crate::Error::context
 #[derive(Debug, DeriveError)]
 #[non_exhaustive]
 pub enum Error {
@@ -42,10 +56,47 @@ pub enum Error {
     #[error("A resource is locked.")]
     Locked,
 
+    /// Another writer committed changes to the repository since it was opened or refreshed.
+    #[error(
+        "Another writer committed changes to the repository since it was opened or refreshed."
+    )]
+    Conflict,
+
+    /// The repository was opened in read-only mode.
+    #[error("The repository was opened in read-only mode.")]
+    ReadOnly,
+
+    /// A thread panicked while it was modifying the repository.
+    ///
+    /// The changes which were in progress may be half-finished, so the repository must be rolled
+    /// back before it can be committed.
+    #[error("A thread panicked while it was modifying the repository.")]
+    Poisoned,
+
+    /// A hook vetoed the operation.
+    ///
+    /// This wraps the error returned by the hook.
+    #[error("A hook vetoed the operation.")]
+    Vetoed(#[source] anyhow::Error),
+
+    /// The operation was cancelled.
+    #[error("The operation was cancelled.")]
+    Cancelled,
+
     /// The repository is corrupt.
     #[error("The repository is corrupt.")]
     Corrupt,
 
+    /// A block in the data store failed its checksum.
+    ///
+    /// This wraps the ID of the block. This means the block was corrupted on disk or in transit
+    /// after it was written. This is only detected in repositories which were created with
+    /// [`RepoConfig::block_checksums`] enabled.
+    ///
+    /// [`RepoConfig::block_checksums`]: crate::repo::RepoConfig::block_checksums
+    #[error("The block {0} failed its checksum.")]
+    CorruptBlock(Uuid),
+
     /// This data store is an unsupported format.
     #[error("This data store is an unsupported format.")]
     UnsupportedStore,
@@ -62,6 +113,10 @@ pub enum Error {
     #[error("This object is no longer valid.")]
     InvalidObject,
 
+    /// The branch is the current branch of the repository.
+    #[error("The branch is the current branch of the repository.")]
+    CurrentBranch,
+
     /// A transaction is currently in progress for this object.
     #[error("A transaction is currently in progress for this object.")]
     TransactionInProgress,
@@ -74,6 +129,10 @@ pub enum Error {
     #[error("The provided file path is invalid.")]
     InvalidPath,
 
+    /// The provided path pattern is invalid.
+    #[error("The provided path pattern is invalid.")]
+    InvalidPattern,
+
     /// The directory is not empty.
     #[error("The directory is not empty.")]
     NotEmpty,
@@ -86,6 +145,10 @@ pub enum Error {
     #[error("The file is not a regular file.")]
     NotFile,
 
+    /// The operation would exceed the quota of a directory.
+    #[error("The operation would exceed the quota of a directory.")]
+    QuotaExceeded,
+
     /// A value could not be serialized.
     #[error("A value could not be serialized.")]
     Serialize,
@@ -99,14 +162,125 @@ pub enum Error {
     InvalidData,
 
     /// An I/O error occurred.
-    #[error("{0}")]
+    #[error(transparent)]
     Io(io::Error),
 
-    /// An error occurred with the data store.
+    /// The data store ran out of space or exceeded a quota.
     ///
     /// This wraps the error provided by the data store.
-    #[error("{0}")]
-    Store(anyhow::Error),
+    #[error("The data store is full.")]
+    StoreFull(#[source] anyhow::Error),
+
+    /// The data store denied access.
+    ///
+    /// This wraps the error provided by the data store. This means the credentials used to open the
+    /// data store are invalid or don't grant permission to read or write its blocks.
+    #[error("Permission to access the data store was denied.")]
+    PermissionDenied(#[source] anyhow::Error),
+
+    /// The data store couldn't be reached or was too busy.
+    ///
+    /// This wraps the error provided by the data store. This error is usually temporary, so the
+    /// operation may succeed if it's retried.
+    #[error("The data store is unavailable.")]
+    Unavailable(#[source] anyhow::Error),
+
+    /// An error occurred with the data store.
+    ///
+    /// This wraps the error provided by the data store. Errors which are known to be caused by the
+    /// data store being full, denying access, or being unavailable are returned as
+    /// `Error::StoreFull`, `Error::PermissionDenied`, or `Error::Unavailable` instead.
+    #[error("An error occurred with the data store.")]
+    Store(#[source] anyhow::Error),
+}
+
+impl Error {
+    /// Return the stable error code for this error.
+    ///
+    /// Unlike the message returned by `Display`, this code will not change between versions of
+    /// this crate.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::AlreadyExists => "already_exists",
+            Error::NotFound => "not_found",
+            Error::Password => "password",
+            Error::Locked => "locked",
+            Error::Conflict => "conflict",
+            Error::ReadOnly => "read_only",
+            Error::Poisoned => "poisoned",
+            Error::Vetoed(_) => "vetoed",
+            Error::Cancelled => "cancelled",
+            Error::Corrupt => "corrupt",
+            Error::CorruptBlock(_) => "corrupt_block",
+            Error::UnsupportedStore => "unsupported_store",
+            Error::UnsupportedRepo => "unsupported_repo",
+            Error::InvalidSavepoint => "invalid_savepoint",
+            Error::InvalidObject => "invalid_object",
+            Error::CurrentBranch => "current_branch",
+            Error::TransactionInProgress => "transaction_in_progress",
+            Error::FileType => "file_type",
+            Error::InvalidPath => "invalid_path",
+            Error::InvalidPattern => "invalid_pattern",
+            Error::NotEmpty => "not_empty",
+            Error::NotDirectory => "not_directory",
+            Error::NotFile => "not_file",
+            Error::QuotaExceeded => "quota_exceeded",
+            Error::Serialize => "serialize",
+            Error::Deserialize => "deserialize",
+            Error::InvalidData => "invalid_data",
+            Error::Io(_) => "io",
+            Error::StoreFull(_) => "store_full",
+            Error::PermissionDenied(_) => "permission_denied",
+            Error::Unavailable(_) => "unavailable",
+            Error::Store(_) => "store",
+        }
+    }
+
+    /// Return context about the data store operation which caused this error.
+    ///
+    /// This returns `None` if this error didn't occur in the data store or the data store
+    /// operation is unknown.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Error::StoreFull(error)
+            | Error::PermissionDenied(error)
+            | Error::Unavailable(error)
+            | Error::Store(error) => error.downcast_ref::<ErrorContext>(),
+            _ => None,
+        }
+    }
+
+    /// Convert an error returned by a data store into an `Error`.
+    ///
+    /// Data stores which wrap other data stores can return an `Error`, like
+    /// `Error::CorruptBlock`, which is returned as-is instead of being wrapped in `Error::Store`.
+    /// Errors caused by a known kind of failure, like a full disk, are classified as
+    /// `Error::StoreFull`, `Error::PermissionDenied`, or `Error::Unavailable`.
+    pub(crate) fn from_store(error: anyhow::Error) -> Self {
+        // Downcasting an error with context discards the context, so it must be reattached.
+        let context = error.downcast_ref::<ErrorContext>().cloned();
+        match error.downcast::<Error>() {
+            Ok(crate_error) => match context {
+                Some(context) => crate_error.with_context(context),
+                None => crate_error,
+            },
+            Err(other_error) => match StoreFailure::classify(&other_error) {
+                Some(failure) => failure.into_error(other_error),
+                None => Error::Store(other_error),
+            },
+        }
+    }
+
+    /// Attach `context` to this error if it wraps an error returned by a data store.
+    fn with_context(self, context: ErrorContext) -> Self {
+        match self {
+            Error::StoreFull(error) => Error::StoreFull(error.context(context)),
+            Error::PermissionDenied(error) => Error::PermissionDenied(error.context(context)),
+            Error::Unavailable(error) => Error::Unavailable(error.context(context)),
+            Error::Store(error) => Error::Store(error.context(context)),
+            other => other,
+        }
+    }
 }
 
 impl From<Error> for io::Error {
@@ -128,5 +302,35 @@ impl From<io::Error> for Error {
     }
 }
 
+/// Context about the data store operation which caused an [`Error`].
+///
+/// [`Error`]: crate::Error
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ErrorContext {
+    /// The name of the [`DataStore`] method which failed, like `"write_block"`.
+    ///
+    /// [`DataStore`]: crate::store::DataStore
+    pub operation: &'static str,
+
+    /// The ID of the block the operation was performed on.
+    ///
+    /// This is `None` if the operation was performed on more than one block.
+    pub block: Option<Uuid>,
+
+    /// The type name of the data store which returned the error.
+    pub backend: &'static str,
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in {}", self.operation, self.backend)?;
+        if let Some(id) = self.block {
+            write!(f, " for block {}", id)?;
+        }
+        Ok(())
+    }
+}
+
 /// The result type for operations with a repository.
 pub type Result<T> = result::Result<T, Error>;
@@ -52,7 +52,7 @@
 //!
 //! fn main() -> acid_store::Result<()> {
 //!     // Create a `KeyRepo` with the default configuration that stores data in memory.
-//!     let mut repo: KeyRepo<String> = OpenOptions::new()
+//!     let repo: KeyRepo<String> = OpenOptions::new()
 //!         .mode(OpenMode::CreateNew)
 //!         .open(&MemoryConfig::new())?;
 //!
@@ -88,19 +88,48 @@
 //! --- | --- | ---
 //! `encryption` | Encrypt repositories | No
 //! `compression` | Compress repositories | No
+//! `erasure-coding` | Store parity blocks which can be used to repair repositories | No
 //! `file-metadata` | Store file metadata and special file types in [`FileRepo`] | No
 //! `hash-algorithms` | Use hash algorithms other than BLAKE3 in [`ContentRepo`] | No
 //! `fuse-mount` | Mount a [`FileRepo`] as a FUSE file system | No
+//! `dokan-mount` | Mount a [`FileRepo`] as a file system on Windows using [Dokan] | No
+//! `file-tar` | Import and export tar archives in a [`FileRepo`] | No
+//! `file-mime` | Detect the MIME type of files in a [`FileRepo`] | No
+//! `file-webdav` | Serve a [`FileRepo`] over WebDAV | No
 //! `store-directory` | Store data in a directory in the local file system | No
 //! `store-sqlite` | Store data in a SQLite database | No
 //! `store-redis` | Store data on a Redis server | No
 //! `store-s3` | Store data in an Amazon S3 bucket | No
 //! `store-sftp` | Store data on an SFTP server | No
 //! `store-rclone` | Store data in cloud storage via [rclone] | No
+//! `async` | Access repositories and data stores from async code | No
+//! `testing` | Inject faults into data stores with [`FaultyStore`] | No
+//! `benchmark` | Measure how a [`RepoConfig`] performs with your data using [`benchmark`] | No
+//! `rayon` | Hash, compress, and encrypt chunks in parallel using [rayon] | No
+//! `tracing` | Emit spans for repository and data store operations using [tracing] | No
+//! `metrics` | Export repository metrics using the [metrics] facade | No
 //!
 //! To use a feature which is not enabled by default, you must enable it in your `Cargo.toml`.
 //!
+//! # Metrics
+//! When the `metrics` feature is enabled, repositories report the following metrics to whichever
+//! recorder is installed for the [metrics] facade. To scrape them with Prometheus, install a
+//! recorder like [metrics-exporter-prometheus] in your application.
+//!
+//! Metric | Type | Description
+//! --- | --- | ---
+//! `acid_store_chunks_written_total` | Counter | New chunks written to a repository
+//! `acid_store_chunks_deduplicated_total` | Counter | Chunks which were already in a repository
+//! `acid_store_store_bytes_written_total` | Counter | Bytes written to a data store
+//! `acid_store_store_bytes_read_total` | Counter | Bytes read from a data store
+//! `acid_store_commit_duration_seconds` | Histogram | The time it takes to commit changes
+//!
+//! [Dokan]: https://dokan-dev.github.io/
 //! [rclone]: https://rclone.org/
+//! [rayon]: https://docs.rs/rayon
+//! [tracing]: https://docs.rs/tracing
+//! [metrics]: https://docs.rs/metrics
+//! [metrics-exporter-prometheus]: https://docs.rs/metrics-exporter-prometheus
 //!
 //! [`KeyRepo`]: crate::repo::key::KeyRepo
 //! [`FileRepo`]: crate::repo::file::FileRepo
@@ -117,14 +146,18 @@
 //! [`SftpStore`]: crate::store::SftpStore
 //! [`RcloneStore`]: crate::store::RcloneStore
 //! [`MemoryStore`]: crate::store::MemoryStore
+//! [`FaultyStore`]: crate::store::FaultyStore
+//! [`RepoConfig`]: crate::repo::RepoConfig
+//! [`benchmark`]: crate::repo::benchmark
 
 #![allow(dead_code)]
 #![forbid(unsafe_code)]
 
 pub use anyhow;
+pub use bytes;
 pub use uuid;
 
-pub use error::{Error, Result};
+pub use error::{Error, ErrorContext, Result};
 
 mod error;
 pub mod repo;
@@ -0,0 +1,111 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::io::{self, Read, Write};
+
+use rmp_serde::{from_read, to_vec};
+
+use super::key::Key;
+
+/// The magic bytes at the start of every export archive.
+pub const ARCHIVE_MAGIC: [u8; 8] = *b"ACIDEXP1";
+
+/// The tag which precedes each entry in an export archive.
+const ENTRY_TAG: u8 = 1;
+
+/// The tag which marks the end of an export archive.
+const END_TAG: u8 = 0;
+
+/// Write the header of an export archive to `writer`.
+pub fn write_header(mut writer: impl Write) -> crate::Result<()> {
+    writer.write_all(&ARCHIVE_MAGIC)?;
+    Ok(())
+}
+
+/// Read the header of an export archive from `reader`.
+pub fn read_header(mut reader: impl Read) -> crate::Result<()> {
+    let mut magic = [0u8; ARCHIVE_MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if magic != ARCHIVE_MAGIC {
+        return Err(crate::Error::Deserialize);
+    }
+    Ok(())
+}
+
+/// Write the header of an entry with the given `key` and `size` to `writer`.
+///
+/// This must be followed by exactly `size` bytes of data.
+pub fn write_entry_header<K: Key>(mut writer: impl Write, key: &K, size: u64) -> crate::Result<()> {
+    let serialized_key = to_vec(key).map_err(|_| crate::Error::Serialize)?;
+    writer.write_all(&[ENTRY_TAG])?;
+    writer.write_all(&(serialized_key.len() as u32).to_le_bytes())?;
+    writer.write_all(&serialized_key)?;
+    writer.write_all(&size.to_le_bytes())?;
+    Ok(())
+}
+
+/// Write the marker for the end of the archive to `writer`.
+pub fn write_end(mut writer: impl Write) -> crate::Result<()> {
+    writer.write_all(&[END_TAG])?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read the header of the next entry from `reader`.
+///
+/// This returns the key and the size of the entry's data, or `None` if the end of the archive was
+/// reached.
+pub fn read_entry_header<K: Key>(mut reader: impl Read) -> crate::Result<Option<(K, u64)>> {
+    let mut tag = [0u8; 1];
+    reader.read_exact(&mut tag)?;
+    match tag[0] {
+        END_TAG => return Ok(None),
+        ENTRY_TAG => (),
+        _ => return Err(crate::Error::Deserialize),
+    }
+
+    let mut key_size = [0u8; 4];
+    reader.read_exact(&mut key_size)?;
+    let key_size = u32::from_le_bytes(key_size) as u64;
+
+    // Don't trust the key size enough to allocate a buffer of that size up front, because a
+    // corrupt archive could claim a key of up to 4 GiB.
+    let mut serialized_key = Vec::new();
+    (&mut reader)
+        .take(key_size)
+        .read_to_end(&mut serialized_key)?;
+    if serialized_key.len() as u64 != key_size {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    let key = from_read(serialized_key.as_slice()).map_err(|_| crate::Error::Deserialize)?;
+
+    let mut data_size = [0u8; 8];
+    reader.read_exact(&mut data_size)?;
+
+    Ok(Some((key, u64::from_le_bytes(data_size))))
+}
+
+/// Copy exactly `size` bytes from `reader` to `writer`.
+///
+/// # Errors
+/// - `Error::Io`: An I/O error occurred or `reader` contained fewer than `size` bytes.
+pub fn copy_exact(reader: impl Read, mut writer: impl Write, size: u64) -> crate::Result<()> {
+    let bytes_copied = io::copy(&mut reader.take(size), &mut writer)?;
+    if bytes_copied != size {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,402 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(feature = "async")]
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task;
+
+use super::commit::Commit;
+use super::key::Key;
+use super::object::Object;
+use super::poison::RecoverPoison;
+use super::repository::KeyRepo;
+
+/// The number of values an `AsyncStream` can buffer before the operation producing them waits.
+const STREAM_BUFFER_SIZE: usize = 64;
+
+/// Run the given `function` with the value in `shared` on tokio's blocking thread pool.
+async fn run_blocking<T, R, F>(shared: &Arc<Mutex<T>>, function: F) -> R
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: FnOnce(&mut T) -> R + Send + 'static,
+{
+    let shared = Arc::clone(shared);
+    task::spawn_blocking(move || function(&mut shared.lock().recover()))
+        .await
+        .expect("The blocking operation panicked.")
+}
+
+/// Wait for any blocking operations on the value in `shared` to finish and return the value.
+async fn unwrap_shared<T: Send + 'static>(mut shared: Arc<Mutex<T>>) -> T {
+    loop {
+        match Arc::try_unwrap(shared) {
+            Ok(mutex) => return mutex.into_inner().recover(),
+            Err(still_shared) => {
+                // Wait for the operation which holds the lock to finish. It may not have released
+                // its reference to the value yet when the lock is released, so we try again.
+                shared = task::spawn_blocking(move || {
+                    drop(still_shared.lock().recover());
+                    still_shared
+                })
+                .await
+                .expect("The blocking operation panicked.");
+            }
+        }
+    }
+}
+
+/// A wrapper which provides async variants of the operations of a repository.
+///
+/// Repository operations which access the data store are run on tokio's blocking thread pool so
+/// that they don't block the worker threads of the async runtime. The repository is still only
+/// accessed by one operation at a time.
+///
+/// If a future returned by one of these methods is dropped before it completes, the operation
+/// still runs to completion in the background. You can use [`run`] to perform any operation which
+/// doesn't have a dedicated async variant.
+///
+/// This must be used from within a tokio runtime.
+///
+/// [`run`]: crate::repo::AsyncRepo::run
+#[derive(Debug)]
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+pub struct AsyncRepo<R: Send + 'static>(Arc<Mutex<R>>);
+
+impl<R: Send + 'static> AsyncRepo<R> {
+    /// Wrap the given `repo` in a new `AsyncRepo`.
+    pub fn new(repo: R) -> Self {
+        AsyncRepo(Arc::new(Mutex::new(repo)))
+    }
+
+    /// Consume this wrapper and return the wrapped repository.
+    ///
+    /// If an operation which was cancelled by dropping its future is still running, this waits for
+    /// it to finish.
+    pub async fn into_inner(self) -> R {
+        unwrap_shared(self.0).await
+    }
+
+    /// Run the given `function` with the wrapped repository on the blocking thread pool.
+    pub async fn run<T, F>(&mut self, function: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut R) -> T + Send + 'static,
+    {
+        run_blocking(&self.0, function).await
+    }
+
+    /// Run `function` with the wrapped repository on the blocking thread pool and return a stream
+    /// of the values it produces.
+    ///
+    /// `function` passes each value to the given callback, which returns `false` once the stream
+    /// has been dropped. If `function` returns an error, it's the last item of the stream. If
+    /// `start_sender` is given, the result of `function` is sent to it instead if it returns before
+    /// producing any values, and `Ok` is sent to it once it produces its first value.
+    pub(crate) fn start_stream<T, F>(
+        &mut self,
+        function: F,
+        start_sender: Option<oneshot::Sender<crate::Result<()>>>,
+    ) -> AsyncStream<'_, T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut R, &mut dyn FnMut(T) -> bool) -> crate::Result<()> + Send + 'static,
+    {
+        let (value_sender, value_receiver) = mpsc::unbounded_channel();
+        let (slot_sender, slot_receiver) = sync_channel(STREAM_BUFFER_SIZE);
+        let shared = Arc::clone(&self.0);
+
+        task::spawn_blocking(move || {
+            let mut repo = shared.lock().recover();
+            let mut start_sender = start_sender;
+            let result = function(&mut *repo, &mut |value| {
+                if let Some(sender) = start_sender.take() {
+                    sender.send(Ok(())).ok();
+                }
+                // This waits until there is room in the buffer, and it fails once the stream has
+                // been dropped.
+                slot_sender.send(()).is_ok() && value_sender.send(Ok(value)).is_ok()
+            });
+            match (start_sender, result) {
+                (Some(sender), result) => {
+                    sender.send(result).ok();
+                }
+                (None, Err(error)) => {
+                    value_sender.send(Err(error)).ok();
+                }
+                (None, Ok(())) => {}
+            }
+        });
+
+        AsyncStream {
+            receiver: value_receiver,
+            slots: slot_receiver,
+            repo: PhantomData,
+        }
+    }
+
+    /// Run `function` with the wrapped repository on the blocking thread pool and return a stream
+    /// of the values it produces.
+    ///
+    /// This is like `start_stream`, except it returns once `function` produces its first value or
+    /// returns. If `function` returns an error before producing any values, this returns that
+    /// error. Otherwise, the error is the last item of the stream.
+    pub(crate) async fn stream<T, F>(&mut self, function: F) -> crate::Result<AsyncStream<'_, T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut R, &mut dyn FnMut(T) -> bool) -> crate::Result<()> + Send + 'static,
+    {
+        let (start_sender, start_receiver) = oneshot::channel();
+        let stream = self.start_stream(function, Some(start_sender));
+        start_receiver
+            .await
+            .expect("The blocking operation panicked.")?;
+        Ok(stream)
+    }
+}
+
+impl<R: Commit + Send + 'static> AsyncRepo<R> {
+    /// Commit changes which have been made to the repository.
+    ///
+    /// See [`Commit::commit`].
+    ///
+    /// [`Commit::commit`]: crate::repo::Commit::commit
+    pub async fn commit(&mut self) -> crate::Result<()> {
+        self.run(|repo| repo.commit()).await
+    }
+
+    /// Roll back all changes made since the last commit.
+    ///
+    /// See [`Commit::rollback`].
+    ///
+    /// [`Commit::rollback`]: crate::repo::Commit::rollback
+    pub async fn rollback(&mut self) -> crate::Result<()> {
+        self.run(|repo| repo.rollback()).await
+    }
+
+    /// Clean up the repository to reclaim space in the backing data store.
+    ///
+    /// See [`Commit::clean`].
+    ///
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    pub async fn clean(&mut self) -> crate::Result<()> {
+        self.run(|repo| repo.clean()).await
+    }
+}
+
+impl<K: Key + Send + 'static> AsyncRepo<KeyRepo<K>> {
+    /// Add a new object with the given `key` to the repository and return it.
+    ///
+    /// See [`KeyRepo::insert`].
+    ///
+    /// [`KeyRepo::insert`]: crate::repo::key::KeyRepo::insert
+    pub async fn insert(&mut self, key: K) -> AsyncObject {
+        AsyncObject::new(self.run(move |repo| repo.insert(key)).await)
+    }
+
+    /// Return an object for reading and writing the object with the given `key`.
+    ///
+    /// See [`KeyRepo::object`].
+    ///
+    /// [`KeyRepo::object`]: crate::repo::key::KeyRepo::object
+    pub async fn object(&mut self, key: K) -> Option<AsyncObject> {
+        self.run(move |repo| repo.object(&key))
+            .await
+            .map(AsyncObject::new)
+    }
+
+    /// Return a stream of all the keys of objects in this repository.
+    ///
+    /// See [`KeyRepo::keys`] and [`AsyncStream`].
+    ///
+    /// [`KeyRepo::keys`]: crate::repo::key::KeyRepo::keys
+    /// [`AsyncStream`]: crate::repo::AsyncStream
+    pub async fn keys(&mut self) -> AsyncStream<'_, K> {
+        self.start_stream(
+            |repo, send| {
+                // This stops once the stream has been dropped.
+                repo.inner().keys().cloned().all(send);
+                Ok(())
+            },
+            None,
+        )
+    }
+}
+
+/// A stream of values produced by an operation on an [`AsyncRepo`].
+///
+/// Values are produced on tokio's blocking thread pool and buffered until they are consumed. Once
+/// the buffer is full, the operation waits for values to be consumed. Operations which list the
+/// contents of a repository read them from the repository as they go, so huge listings can be
+/// consumed without collecting them into memory first.
+///
+/// If the operation fails after it has started producing values, the error is the last item of
+/// the stream.
+///
+/// The repository is locked until the operation finishes, which happens once all the values have
+/// been produced or shortly after the stream is dropped. Because the stream borrows the
+/// [`AsyncRepo`] mutably, other operations can't be started until it's dropped.
+///
+/// [`AsyncRepo`]: crate::repo::AsyncRepo
+#[derive(Debug)]
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+pub struct AsyncStream<'a, T> {
+    /// The receiver for values produced by the operation.
+    receiver: mpsc::UnboundedReceiver<crate::Result<T>>,
+
+    /// The receiver which frees a slot in the buffer each time a value is consumed.
+    ///
+    /// The operation sends to this before producing each value, so it waits once the buffer is
+    /// full.
+    slots: Receiver<()>,
+
+    /// The repository which the operation is running on.
+    repo: PhantomData<&'a mut ()>,
+}
+
+impl<'a, T> Stream for AsyncStream<'a, T> {
+    type Item = crate::Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.receiver.poll_recv(cx);
+        if let Poll::Ready(Some(_)) = poll {
+            self.slots.try_recv().ok();
+        }
+        poll
+    }
+}
+
+/// A wrapper which provides async variants of the operations of an [`Object`].
+///
+/// Reads and writes are run on tokio's blocking thread pool so that they don't block the worker
+/// threads of the async runtime. See [`AsyncRepo`] for details.
+///
+/// [`Object`]: crate::repo::Object
+/// [`AsyncRepo`]: crate::repo::AsyncRepo
+#[derive(Debug)]
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+pub struct AsyncObject(Arc<Mutex<Object>>);
+
+impl AsyncObject {
+    /// Wrap the given `object` in a new `AsyncObject`.
+    pub fn new(object: Object) -> Self {
+        AsyncObject(Arc::new(Mutex::new(object)))
+    }
+
+    /// Consume this wrapper and return the wrapped object.
+    ///
+    /// If an operation which was cancelled by dropping its future is still running, this waits for
+    /// it to finish.
+    pub async fn into_inner(self) -> Object {
+        unwrap_shared(self.0).await
+    }
+
+    /// Read up to `len` bytes from the object at the current seek position.
+    ///
+    /// This returns an empty buffer once the end of the object has been reached.
+    ///
+    /// # Errors
+    /// - `Error::TransactionInProgress`: A transaction is currently in progress for this object.
+    /// - `Error::InvalidObject`: The object has been invalidated.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub async fn read(&mut self, len: usize) -> crate::Result<Vec<u8>> {
+        run_blocking(&self.0, move |object| {
+            let mut buffer = vec![0u8; len];
+            let bytes_read = object.read(&mut buffer)?;
+            buffer.truncate(bytes_read);
+            Ok(buffer)
+        })
+        .await
+    }
+
+    /// Read from the current seek position to the end of the object.
+    ///
+    /// # Errors
+    /// - `Error::TransactionInProgress`: A transaction is currently in progress for this object.
+    /// - `Error::InvalidObject`: The object has been invalidated.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub async fn read_to_end(&mut self) -> crate::Result<Vec<u8>> {
+        run_blocking(&self.0, |object| {
+            let mut buffer = Vec::new();
+            object.read_to_end(&mut buffer)?;
+            Ok(buffer)
+        })
+        .await
+    }
+
+    /// Write all of `data` to the object at the current seek position.
+    ///
+    /// Like writing to an [`Object`], this starts a transaction which must be completed by calling
+    /// [`commit`].
+    ///
+    /// # Errors
+    /// - `Error::InvalidObject`: The object has been invalidated.
+    /// - `Error::ReadOnly`: The repository was opened in read-only mode.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`Object`]: crate::repo::Object
+    /// [`commit`]: crate::repo::AsyncObject::commit
+    pub async fn write_all(&mut self, data: &[u8]) -> crate::Result<()> {
+        let data = data.to_vec();
+        run_blocking(&self.0, move |object| Ok(object.write_all(&data)?)).await
+    }
+
+    /// Seek to the given `position` in the object and return the new seek position.
+    ///
+    /// # Errors
+    /// - `Error::TransactionInProgress`: A transaction is currently in progress for this object.
+    /// - `Error::InvalidObject`: The object has been invalidated.
+    /// - `Error::Io`: An I/O error occurred.
+    pub async fn seek(&mut self, position: SeekFrom) -> crate::Result<u64> {
+        run_blocking(&self.0, move |object| Ok(object.seek(position)?)).await
+    }
+
+    /// Commit changes to this object to the repository.
+    ///
+    /// This flushes data written with [`write_all`]. Like [`Object::commit`], this does not call
+    /// [`AsyncRepo::commit`].
+    ///
+    /// [`write_all`]: crate::repo::AsyncObject::write_all
+    /// [`AsyncRepo::commit`]: crate::repo::AsyncRepo::commit
+    ///
+    /// [`Object::commit`]: crate::repo::Object::commit
+    pub async fn commit(&mut self) -> crate::Result<()> {
+        run_blocking(&self.0, |object| object.commit()).await
+    }
+
+    /// Return the size of the object in bytes.
+    ///
+    /// See [`Object::size`].
+    ///
+    /// [`Object::size`]: crate::repo::Object::size
+    pub async fn size(&mut self) -> crate::Result<u64> {
+        run_blocking(&self.0, |object| object.size()).await
+    }
+}
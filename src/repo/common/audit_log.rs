@@ -0,0 +1,153 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use hex_literal::hex;
+use rmp_serde::from_read;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::chunk_store::EncodeBlock;
+use super::poison::RecoverPoison;
+use super::state::RepoState;
+
+/// The block ID of the block which stores the audit log.
+pub const AUDIT_LOG_BLOCK_ID: Uuid = Uuid::from_bytes(hex!("e3a1c6f4 7b2d 4f08 b5c9 61d8a0e4f2b7"));
+
+/// An operation which is recorded in the audit log.
+///
+/// See [`RepoConfig::audit_log`] for details.
+///
+/// [`RepoConfig::audit_log`]: crate::repo::RepoConfig::audit_log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum AuditEvent {
+    /// The repository was created.
+    Create,
+
+    /// The repository was opened.
+    Open,
+
+    /// Changes were committed.
+    Commit,
+
+    /// Changes were rolled back.
+    Rollback,
+
+    /// The repository was restored to a savepoint.
+    Restore,
+
+    /// The repository was cleaned.
+    Clean,
+
+    /// An object was removed.
+    Remove {
+        /// The key of the object, serialized with [rmp-serde].
+        ///
+        /// [rmp-serde]: https://docs.rs/rmp-serde
+        key: Vec<u8>,
+    },
+
+    /// A branch was removed.
+    RemoveBranch {
+        /// The name of the branch.
+        name: String,
+    },
+
+    /// Every object in an instance was removed.
+    ClearInstance,
+}
+
+/// An entry in the audit log.
+///
+/// See [`RepoConfig::audit_log`] for details.
+///
+/// [`RepoConfig::audit_log`]: crate::repo::RepoConfig::audit_log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct AuditEntry {
+    /// The time at which the operation happened.
+    pub time: SystemTime,
+
+    /// The ID of the instance which was open when the operation happened.
+    pub instance: Uuid,
+
+    /// The operation which happened.
+    pub event: AuditEvent,
+}
+
+/// The entries in the audit log which haven't been written to the data store yet.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    pending: Mutex<Vec<AuditEntry>>,
+}
+
+impl AuditLog {
+    /// Add an entry for `event` in `instance` to the entries waiting to be written.
+    pub fn record(&self, instance: Uuid, event: AuditEvent) {
+        self.pending.lock().recover().push(AuditEntry {
+            time: SystemTime::now(),
+            instance,
+            event,
+        });
+    }
+
+    /// Return the entries waiting to be written.
+    pub fn pending(&self) -> Vec<AuditEntry> {
+        self.pending.lock().recover().clone()
+    }
+
+    /// Append the pending entries to the audit log in the data store of the repository `state`.
+    ///
+    /// If the entries can't be written, they are kept so that they can be written by the next
+    /// call to this method.
+    pub fn flush(&self, state: &RepoState) -> crate::Result<()> {
+        let mut pending = self.pending.lock().recover();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut entries = read_audit_log(state)?;
+        entries.extend(pending.iter().cloned());
+        let encoded_entries = state.encode_value(&entries)?;
+        state
+            .store
+            .lock()
+            .recover()
+            .write_block(AUDIT_LOG_BLOCK_ID, &encoded_entries)
+            .map_err(crate::Error::from_store)?;
+
+        pending.clear();
+        Ok(())
+    }
+}
+
+/// Read the entries in the audit log from the data store of the repository `state`.
+pub fn read_audit_log(state: &RepoState) -> crate::Result<Vec<AuditEntry>> {
+    let encoded_entries = match state
+        .store
+        .lock()
+        .recover()
+        .read_block(AUDIT_LOG_BLOCK_ID)
+        .map_err(crate::Error::from_store)?
+    {
+        Some(encoded_entries) => encoded_entries,
+        None => return Ok(Vec::new()),
+    };
+    from_read(state.decode_reader(encoded_entries)?).map_err(|_| crate::Error::Corrupt)
+}
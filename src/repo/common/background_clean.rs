@@ -0,0 +1,107 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+
+use uuid::Uuid;
+
+use super::poison::RecoverPoison;
+use super::state::RepoState;
+
+/// The number of blocks to remove from the data store at a time when cleaning in the background.
+///
+/// The repository state is locked while each batch is removed, so this bounds how long other
+/// operations on the repository can be blocked.
+const BATCH_SIZE: usize = 64;
+
+/// A pass which removes unreferenced blocks from the data store on a background thread.
+///
+/// The pass is stopped after the current batch when this value is dropped.
+#[derive(Debug)]
+pub struct BackgroundClean {
+    /// Whether the pass has been asked to stop.
+    stopped: Arc<AtomicBool>,
+
+    /// Whether the pass has removed every block or given up.
+    finished: Arc<AtomicBool>,
+
+    /// The handle of the thread running the pass.
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BackgroundClean {
+    /// Start removing the given unreferenced `blocks` from the data store of the repository with
+    /// the given `state`.
+    ///
+    /// The pass stops early if the repository is dropped or removing a batch of blocks fails. Any
+    /// blocks which are not removed are removed by a later pass or by `Commit::clean`.
+    pub fn start(state: &Arc<RwLock<RepoState>>, blocks: Vec<Uuid>) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let finished = Arc::new(AtomicBool::new(false));
+        let thread_stopped = Arc::clone(&stopped);
+        let thread_finished = Arc::clone(&finished);
+        let state = Arc::downgrade(state);
+
+        let handle = thread::spawn(move || {
+            for batch in blocks.chunks(BATCH_SIZE) {
+                if thread_stopped.load(Ordering::Acquire) {
+                    break;
+                }
+                let state = match state.upgrade() {
+                    Some(state) => state,
+                    None => break,
+                };
+                let state_guard = state.read().recover();
+                let result = state_guard.store.lock().recover().remove_blocks(batch);
+                if result.is_err() {
+                    break;
+                }
+            }
+            thread_finished.store(true, Ordering::Release);
+        });
+
+        BackgroundClean {
+            stopped,
+            finished,
+            handle: Some(handle),
+        }
+    }
+
+    /// Return whether the pass has finished.
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    /// Stop the pass and wait for the batch which is being removed to finish.
+    ///
+    /// This must not be called while holding a lock on the repository state.
+    pub fn stop(mut self) {
+        self.stopped.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            // The pass only removes blocks, so a panic on the background thread doesn't need to be
+            // propagated.
+            handle.join().ok();
+        }
+    }
+}
+
+impl Drop for BackgroundClean {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+    }
+}
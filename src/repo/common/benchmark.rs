@@ -0,0 +1,235 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(feature = "benchmark")]
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::time::Instant;
+
+use uuid::Uuid;
+
+use super::chunking::IncrementalChunker;
+use super::config::RepoConfig;
+use super::encryption::EncryptionKey;
+use super::handle::chunk_hash;
+use super::stats::OperationStats;
+use crate::store::{DataStore, OpenStore};
+
+/// The results of benchmarking a repository configuration with [`benchmark`].
+///
+/// Each [`OperationStats`] records how many times a stage of the pipeline was performed, how many
+/// bytes it processed, and how long it took, so [`OperationStats::throughput`] can be used to
+/// compare configurations.
+///
+/// [`benchmark`]: crate::repo::benchmark
+/// [`OperationStats`]: crate::repo::OperationStats
+/// [`OperationStats::throughput`]: crate::repo::OperationStats::throughput
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(docsrs, doc(cfg(feature = "benchmark")))]
+#[non_exhaustive]
+pub struct BenchmarkReport {
+    /// The size of the data sample in bytes.
+    pub sample_size: u64,
+
+    /// The number of chunks the sample was split into.
+    pub chunks: u64,
+
+    /// The number of chunks which were not duplicates of another chunk in the sample.
+    pub unique_chunks: u64,
+
+    /// The total size of the unique chunks in bytes.
+    pub unique_size: u64,
+
+    /// The total size of the unique chunks in bytes after they were compressed and encrypted.
+    pub encoded_size: u64,
+
+    /// Splitting the sample into chunks.
+    pub chunking: OperationStats,
+
+    /// Hashing each chunk.
+    pub hashing: OperationStats,
+
+    /// Compressing each unique chunk.
+    pub compression: OperationStats,
+
+    /// Encrypting each compressed chunk.
+    pub encryption: OperationStats,
+
+    /// Writing each encoded chunk to the data store.
+    pub store_write: OperationStats,
+
+    /// Reading each encoded chunk from the data store.
+    pub store_read: OperationStats,
+}
+
+impl BenchmarkReport {
+    /// The average size of a chunk in bytes, or `None` if the sample was empty.
+    pub fn mean_chunk_size(&self) -> Option<u64> {
+        if self.chunks == 0 {
+            return None;
+        }
+        Some(self.sample_size / self.chunks)
+    }
+
+    /// The size of the unique chunks as a fraction of the size of the sample.
+    ///
+    /// Smaller values mean more data was deduplicated. This is `None` if the sample was empty.
+    pub fn deduplication_ratio(&self) -> Option<f64> {
+        if self.sample_size == 0 {
+            return None;
+        }
+        Some(self.unique_size as f64 / self.sample_size as f64)
+    }
+
+    /// The size of the encoded chunks as a fraction of the size of the unique chunks.
+    ///
+    /// Smaller values mean the data compressed better. This includes the overhead of encryption.
+    /// This is `None` if the sample was empty.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        if self.unique_size == 0 {
+            return None;
+        }
+        Some(self.encoded_size as f64 / self.unique_size as f64)
+    }
+}
+
+/// Run `operation` and add its duration and the number of `bytes` it processed to `stats`.
+fn time<R>(stats: &mut OperationStats, bytes: usize, operation: impl FnOnce() -> R) -> R {
+    let start = Instant::now();
+    let result = operation();
+    stats.count += 1;
+    stats.bytes += bytes as u64;
+    stats.duration += start.elapsed();
+    result
+}
+
+/// Measure how a repository with the given `config` would perform with the given data `sample`.
+///
+/// This splits `sample` into chunks, hashes them, and then compresses, encrypts, writes, and reads
+/// back each unique chunk the same way a repository would, timing each stage. This can be used to
+/// choose settings like [`RepoConfig::chunking`] and [`RepoConfig::compression`] for your data
+/// before creating a repository. The sample should be representative of the data you intend to
+/// store.
+///
+/// Chunks are written to and read from the data store opened with `store_config` as new blocks,
+/// which are removed before this function returns. To exclude the data store from the
+/// measurements, use a [`MemoryConfig`]. Chunks are encrypted with a randomly generated key, and
+/// [`RepoConfig::packing`] is not taken into account.
+///
+/// # Errors
+/// - `Error::Corrupt`: A block which was written to the data store could not be read back.
+/// - `Error::Store`: An error occurred with the data store.
+/// - `Error::Io`: An I/O error occurred.
+///
+/// [`RepoConfig::chunking`]: crate::repo::RepoConfig::chunking
+/// [`RepoConfig::compression`]: crate::repo::RepoConfig::compression
+/// [`RepoConfig::packing`]: crate::repo::RepoConfig::packing
+/// [`MemoryConfig`]: crate::store::MemoryConfig
+#[cfg_attr(docsrs, doc(cfg(feature = "benchmark")))]
+pub fn benchmark(
+    config: &RepoConfig,
+    store_config: &impl OpenStore,
+    sample: &[u8],
+) -> crate::Result<BenchmarkReport> {
+    let mut store = store_config.open()?;
+
+    let mut report = BenchmarkReport {
+        sample_size: sample.len() as u64,
+        chunks: 0,
+        unique_chunks: 0,
+        unique_size: 0,
+        encoded_size: 0,
+        chunking: OperationStats::default(),
+        hashing: OperationStats::default(),
+        compression: OperationStats::default(),
+        encryption: OperationStats::default(),
+        store_write: OperationStats::default(),
+        store_read: OperationStats::default(),
+    };
+
+    let mut chunker = IncrementalChunker::new(config.to_chunker());
+    let chunks = time(&mut report.chunking, sample.len(), || {
+        chunker.write_all(sample)?;
+        chunker.flush()?;
+        crate::Result::Ok(chunker.chunks())
+    })?;
+    report.chunks = chunks.len() as u64;
+
+    let mut hashes = HashSet::new();
+    let mut unique_chunks = Vec::new();
+    for chunk in chunks {
+        let hash = time(&mut report.hashing, chunk.len(), || chunk_hash(&chunk));
+        if hashes.insert(hash) {
+            unique_chunks.push(chunk);
+        }
+    }
+    report.unique_chunks = unique_chunks.len() as u64;
+    report.unique_size = unique_chunks.iter().map(|chunk| chunk.len() as u64).sum();
+
+    let key = if config.encryption.key_size() == 0 {
+        EncryptionKey::new(Vec::new())
+    } else {
+        EncryptionKey::generate(config.encryption.key_size())
+    };
+    let mut encoded_chunks = Vec::with_capacity(unique_chunks.len());
+    for chunk in unique_chunks {
+        let compressed_chunk = time(&mut report.compression, chunk.len(), || {
+            config.compression.compress(&chunk)
+        })?;
+        let encrypted_chunk = time(&mut report.encryption, compressed_chunk.len(), || {
+            config.encryption.encrypt(&compressed_chunk, &key)
+        });
+        report.encoded_size += encrypted_chunk.len() as u64;
+        encoded_chunks.push((Uuid::new_v4(), encrypted_chunk));
+    }
+
+    let result = benchmark_store(&mut store, &encoded_chunks, &mut report);
+
+    // Remove the blocks we wrote even if reading or writing them failed.
+    let block_ids = encoded_chunks.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    let cleanup_result = store
+        .remove_blocks(&block_ids)
+        .map_err(crate::Error::from_store);
+
+    result?;
+    cleanup_result?;
+
+    Ok(report)
+}
+
+/// Write the given `blocks` to `store` and read them back, recording the results in `report`.
+fn benchmark_store(
+    store: &mut impl DataStore,
+    blocks: &[(Uuid, Vec<u8>)],
+    report: &mut BenchmarkReport,
+) -> crate::Result<()> {
+    for (id, data) in blocks {
+        time(&mut report.store_write, data.len(), || {
+            store.write_block(*id, data)
+        })
+        .map_err(crate::Error::from_store)?;
+    }
+
+    for (id, _) in blocks {
+        let data = time(&mut report.store_read, 0, || store.read_block(*id))
+            .map_err(crate::Error::from_store)?
+            .ok_or(crate::Error::Corrupt)?;
+        report.store_read.bytes += data.len() as u64;
+    }
+
+    Ok(())
+}
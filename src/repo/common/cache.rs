@@ -0,0 +1,92 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt::{self, Debug, Formatter};
+
+use bytes::Bytes;
+use lru::LruCache;
+
+use super::handle::Chunk;
+
+/// An in-memory cache of the decoded contents of recently read chunks.
+///
+/// When the total size of the cached chunks exceeds the capacity, the least recently used chunks
+/// are evicted first.
+pub struct ChunkCache {
+    /// A map of chunks to their decoded contents.
+    chunks: LruCache<Chunk, Bytes>,
+
+    /// The total size of the cached chunks in bytes.
+    size: usize,
+
+    /// The maximum total size of the cached chunks in bytes.
+    capacity: usize,
+}
+
+impl Debug for ChunkCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChunkCache")
+            .field("len", &self.chunks.len())
+            .field("size", &self.size)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl ChunkCache {
+    /// Create a new empty cache which holds up to `capacity` bytes of chunks.
+    ///
+    /// If `capacity` is `0`, nothing is ever cached.
+    pub fn new(capacity: usize) -> Self {
+        ChunkCache {
+            chunks: LruCache::unbounded(),
+            size: 0,
+            capacity,
+        }
+    }
+
+    /// Return whether the contents of `chunk` are in the cache.
+    pub fn contains(&self, chunk: &Chunk) -> bool {
+        self.chunks.contains(chunk)
+    }
+
+    /// Return the contents of `chunk` if it is in the cache.
+    ///
+    /// The returned buffer shares its memory with the cache, so this doesn't copy the chunk. This
+    /// marks the chunk as the most recently used.
+    pub fn get(&mut self, chunk: &Chunk) -> Option<Bytes> {
+        self.chunks.get(chunk).cloned()
+    }
+
+    /// Add the decoded contents of `chunk` to the cache, evicting other chunks to make room.
+    ///
+    /// Chunks which are larger than the capacity of the cache are not cached.
+    pub fn insert(&mut self, chunk: Chunk, data: &Bytes) {
+        if data.len() > self.capacity || self.chunks.contains(&chunk) {
+            return;
+        }
+
+        while self.size + data.len() > self.capacity {
+            match self.chunks.pop_lru() {
+                Some((_, evicted)) => self.size -= evicted.len(),
+                None => break,
+            }
+        }
+
+        self.size += data.len();
+        self.chunks.put(chunk, data.clone());
+    }
+}
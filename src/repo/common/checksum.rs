@@ -0,0 +1,305 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cmp::min;
+
+use uuid::Uuid;
+
+use crate::store::DataStore;
+
+use super::config::RepoConfig;
+use super::lease::LEASE_BLOCK_ID;
+use super::repository::{METADATA_BLOCK_ID, VERSION_BLOCK_ID};
+
+/// The size of each checksum in bytes.
+const CHECKSUM_SIZE: usize = 4;
+
+/// The size of each segment of a block which has its own checksum, in bytes.
+///
+/// Because each segment is checksummed separately, reading a range of a block only requires
+/// reading and verifying the segments which overlap it.
+const SEGMENT_SIZE: usize = 64 * 1024;
+
+/// The size of each segment of a block in the data store, including its checksum.
+const STORED_SEGMENT_SIZE: usize = SEGMENT_SIZE + CHECKSUM_SIZE;
+
+/// Return whether the block with the given `id` is stored without a checksum.
+///
+/// These blocks are read before the repository config is known, so we can't know whether they
+/// would have a checksum.
+fn is_unchecked(id: Uuid) -> bool {
+    id == METADATA_BLOCK_ID || id == VERSION_BLOCK_ID || id == LEASE_BLOCK_ID
+}
+
+/// Return a copy of `data` with a checksum appended to each segment.
+///
+/// The last segment is always shorter than `SEGMENT_SIZE`, even if that means it's empty, so that
+/// a block which was truncated at the end of a segment can be detected.
+fn append_checksums(data: &[u8]) -> Vec<u8> {
+    let num_segments = data.len() / SEGMENT_SIZE + 1;
+    let mut block = Vec::with_capacity(data.len() + num_segments * CHECKSUM_SIZE);
+    for index in 0..num_segments {
+        let start = index * SEGMENT_SIZE;
+        let segment = &data[start..min(start + SEGMENT_SIZE, data.len())];
+        block.extend_from_slice(segment);
+        block.extend_from_slice(&crc32c::crc32c(segment).to_le_bytes());
+    }
+    block
+}
+
+/// Verify and remove the checksums from `stored`, which is part of the block with the given `id`.
+///
+/// `stored` must start at the beginning of a segment. If `requested_len` is `None`, `stored` must
+/// extend to the end of the block. Otherwise, it's the number of bytes which were requested from
+/// the data store, and `stored` may be shorter than that only if it extends to the end of the
+/// block.
+fn strip_checksums(id: Uuid, stored: &[u8], requested_len: Option<u64>) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(stored.len());
+    let mut found_last = false;
+    for segment in stored.chunks(STORED_SEGMENT_SIZE) {
+        if segment.len() < CHECKSUM_SIZE {
+            return Err(crate::Error::CorruptBlock(id).into());
+        }
+        let (contents, checksum) = segment.split_at(segment.len() - CHECKSUM_SIZE);
+        if crc32c::crc32c(contents).to_le_bytes() != checksum {
+            return Err(crate::Error::CorruptBlock(id).into());
+        }
+        data.extend_from_slice(contents);
+        found_last = segment.len() < STORED_SEGMENT_SIZE;
+    }
+
+    // If we didn't get as much as we asked for, we should have reached the last segment, which is
+    // shorter than the others. If we didn't, the block was truncated.
+    if !found_last && requested_len != Some(stored.len() as u64) {
+        return Err(crate::Error::CorruptBlock(id).into());
+    }
+
+    Ok(data)
+}
+
+/// Return the `len` bytes of `data` starting at `offset`, or fewer if it extends past the end.
+fn slice_range(data: &[u8], offset: u64, len: u64) -> Vec<u8> {
+    let start = min(offset, data.len() as u64) as usize;
+    let end = min(offset.saturating_add(len), data.len() as u64) as usize;
+    data[start..end].to_vec()
+}
+
+/// A data store which appends CRC32C checksums to each block and verifies them when the block is
+/// read.
+///
+/// This detects blocks which were corrupted on disk or in transit independently of encryption, and
+/// reports them as `Error::CorruptBlock`. Each `SEGMENT_SIZE` bytes of a block has its own
+/// checksum, so reading a range of a block only reads and verifies the segments which overlap it.
+pub struct ChecksumStore(Box<dyn DataStore + Send>);
+
+impl ChecksumStore {
+    /// Wrap `store` so that blocks are checksummed if `config` enables it.
+    pub fn wrap(
+        store: Box<dyn DataStore + Send>,
+        config: &RepoConfig,
+    ) -> Box<dyn DataStore + Send> {
+        if config.block_checksums {
+            Box::new(ChecksumStore(store))
+        } else {
+            store
+        }
+    }
+}
+
+impl DataStore for ChecksumStore {
+    fn write_block(&mut self, id: Uuid, data: &[u8]) -> anyhow::Result<()> {
+        if is_unchecked(id) {
+            return self.0.write_block(id, data);
+        }
+        self.0.write_block(id, &append_checksums(data))
+    }
+
+    fn read_block(&mut self, id: Uuid) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.0.read_block(id)? {
+            Some(block) if !is_unchecked(id) => Ok(Some(strip_checksums(id, &block, None)?)),
+            block => Ok(block),
+        }
+    }
+
+    fn remove_block(&mut self, id: Uuid) -> anyhow::Result<()> {
+        self.0.remove_block(id)
+    }
+
+    fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>> {
+        self.0.list_blocks()
+    }
+
+    fn write_blocks(&mut self, blocks: &[(Uuid, &[u8])]) -> anyhow::Result<()> {
+        let checksummed_blocks = blocks
+            .iter()
+            .map(|(id, data)| {
+                if is_unchecked(*id) {
+                    data.to_vec()
+                } else {
+                    append_checksums(data)
+                }
+            })
+            .collect::<Vec<_>>();
+        let batch = blocks
+            .iter()
+            .zip(checksummed_blocks.iter())
+            .map(|((id, _), data)| (*id, data.as_slice()))
+            .collect::<Vec<_>>();
+        self.0.write_blocks(&batch)
+    }
+
+    fn read_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        self.0
+            .read_blocks(ids)?
+            .into_iter()
+            .zip(ids.iter())
+            .map(|(block, id)| match block {
+                Some(block) if !is_unchecked(*id) => Ok(Some(strip_checksums(*id, &block, None)?)),
+                block => Ok(block),
+            })
+            .collect()
+    }
+
+    fn read_block_range(
+        &mut self,
+        id: Uuid,
+        offset: u64,
+        len: u64,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        // An empty range doesn't return any data which would need to be verified, so this only
+        // checks whether the block exists.
+        if is_unchecked(id) || len == 0 {
+            return self.0.read_block_range(id, offset, len);
+        }
+
+        // Read every segment which overlaps the range, including their checksums.
+        let segment_size = SEGMENT_SIZE as u64;
+        let first_segment = offset / segment_size;
+        let last_segment = (offset.saturating_add(len) - 1) / segment_size;
+        let stored_offset = first_segment.saturating_mul(STORED_SEGMENT_SIZE as u64);
+        let stored_len =
+            (last_segment - first_segment + 1).saturating_mul(STORED_SEGMENT_SIZE as u64);
+        let stored = match self.0.read_block_range(id, stored_offset, stored_len)? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+
+        // If the range starts past the end of the block, there are no segments to verify, but we
+        // can only tell that apart from a truncated block by verifying the whole block.
+        if stored.is_empty() && first_segment > 0 {
+            return Ok(self
+                .read_block(id)?
+                .map(|block| slice_range(&block, offset, len)));
+        }
+
+        let data = strip_checksums(id, &stored, Some(stored_len))?;
+        Ok(Some(slice_range(
+            &data,
+            offset - first_segment * segment_size,
+            len,
+        )))
+    }
+
+    fn remove_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<()> {
+        self.0.remove_blocks(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::store::{DataStore, MemoryConfig, OpenStore};
+
+    use super::{ChecksumStore, SEGMENT_SIZE, STORED_SEGMENT_SIZE};
+
+    /// Return whether `result` failed because the block with the given `id` is corrupt.
+    fn is_corrupt<T>(result: anyhow::Result<T>, id: Uuid) -> bool {
+        match result {
+            Ok(_) => false,
+            Err(error) => matches!(
+                error.downcast_ref::<crate::Error>(),
+                Some(crate::Error::CorruptBlock(corrupt_id)) if *corrupt_id == id
+            ),
+        }
+    }
+
+    #[test]
+    fn ranged_read_of_corrupt_block_fails() -> anyhow::Result<()> {
+        let config = MemoryConfig::new();
+        let mut store = ChecksumStore(Box::new(config.open()?));
+        let id = Uuid::new_v4();
+        store.write_block(id, b"block data")?;
+        assert_eq!(store.read_block_range(id, 2, 4)?, Some(b"ock ".to_vec()));
+
+        // Corrupt a byte outside of the range being read.
+        let mut inner = config.open()?;
+        let mut block = inner.read_block(id)?.unwrap();
+        block[8] ^= 0xff;
+        inner.write_block(id, &block)?;
+
+        assert!(is_corrupt(store.read_block_range(id, 2, 4), id));
+        Ok(())
+    }
+
+    #[test]
+    fn ranged_read_only_verifies_overlapping_segments() -> anyhow::Result<()> {
+        let config = MemoryConfig::new();
+        let mut store = ChecksumStore(Box::new(config.open()?));
+        let id = Uuid::new_v4();
+        let data = (0..SEGMENT_SIZE * 3).map(|i| i as u8).collect::<Vec<_>>();
+        store.write_block(id, &data)?;
+
+        // Corrupt a byte in the last segment.
+        let mut inner = config.open()?;
+        let mut block = inner.read_block(id)?.unwrap();
+        block[STORED_SEGMENT_SIZE * 2 + 8] ^= 0xff;
+        inner.write_block(id, &block)?;
+
+        let offset = SEGMENT_SIZE - 2;
+        assert_eq!(
+            store.read_block_range(id, offset as u64, 4)?,
+            Some(data[offset..offset + 4].to_vec())
+        );
+        assert!(is_corrupt(
+            store.read_block_range(id, SEGMENT_SIZE as u64 * 2, 4),
+            id
+        ));
+        assert!(is_corrupt(store.read_block(id), id));
+        Ok(())
+    }
+
+    #[test]
+    fn block_truncated_at_segment_boundary_fails() -> anyhow::Result<()> {
+        let config = MemoryConfig::new();
+        let mut store = ChecksumStore(Box::new(config.open()?));
+        let id = Uuid::new_v4();
+        store.write_block(id, &vec![1u8; SEGMENT_SIZE * 2])?;
+
+        // Remove the empty last segment and the segment before it.
+        let mut inner = config.open()?;
+        let mut block = inner.read_block(id)?.unwrap();
+        block.truncate(STORED_SEGMENT_SIZE);
+        inner.write_block(id, &block)?;
+
+        assert!(is_corrupt(
+            store.read_block_range(id, SEGMENT_SIZE as u64 - 2, 4),
+            id
+        ));
+        assert!(is_corrupt(store.read_block(id), id));
+        Ok(())
+    }
+}
@@ -15,14 +15,26 @@
  */
 
 use std::cmp::min;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::mem::take;
 
+use bytes::Bytes;
+use rmp_serde::encode::write;
+use serde::Serialize;
 use uuid::Uuid;
 
+use super::compression::Compression;
+use super::encryption::Encryption;
 use super::handle::{chunk_hash, Chunk};
 use super::id_table::UniqueId;
 use super::packing::Packing;
+use super::parallel::map_parallel;
+use super::poison::RecoverPoison;
 use super::state::{ChunkInfo, Pack, PackIndex, RepoState};
+use super::store_pool::{PendingRead, PendingUpload};
+#[cfg(feature = "metrics")]
+use super::telemetry;
 
 /// Encode and decode blocks of data.
 pub trait EncodeBlock {
@@ -31,31 +43,81 @@ pub trait EncodeBlock {
 
     /// Decrypt and decompress the given `data` and return it.
     fn decode_data(&self, data: &[u8]) -> crate::Result<Vec<u8>>;
+
+    /// Serialize the given `value`, compress and encrypt it, and return it.
+    ///
+    /// The value is serialized directly into the compressor, so the serialized value is never
+    /// buffered in memory.
+    fn encode_value<T: Serialize + ?Sized>(&self, value: &T) -> crate::Result<Vec<u8>>;
+
+    /// Decrypt the given `data` in place and return a reader which decompresses it.
+    ///
+    /// This can be passed to `from_read` to deserialize a value without buffering the decompressed
+    /// data in memory.
+    fn decode_reader(&self, data: Vec<u8>) -> crate::Result<Box<dyn Read>>;
 }
 
 impl EncodeBlock for RepoState {
     fn encode_data(&self, data: &[u8]) -> crate::Result<Vec<u8>> {
-        let compressed_data = self.metadata.config.compression.compress(data)?;
+        self.stats.encode.time(
+            || {
+                let compressed_data = self.metadata.config.compression.compress(data)?;
+
+                Ok(self
+                    .metadata
+                    .config
+                    .encryption
+                    .encrypt(compressed_data.as_slice(), &self.master_key))
+            },
+            |_| data.len() as u64,
+        )
+    }
+
+    fn decode_data(&self, data: &[u8]) -> crate::Result<Vec<u8>> {
+        self.stats.decode.time(
+            || {
+                let decrypted_data = self
+                    .metadata
+                    .config
+                    .encryption
+                    .decrypt(data, &self.master_key)?;
+
+                self.metadata
+                    .config
+                    .compression
+                    .decompress(decrypted_data.as_slice())
+            },
+            |_| data.len() as u64,
+        )
+    }
+
+    fn encode_value<T: Serialize + ?Sized>(&self, value: &T) -> crate::Result<Vec<u8>> {
+        let compressed_data = self
+            .metadata
+            .config
+            .compression
+            .compress_with(|mut writer| {
+                write(&mut writer, value).map_err(|_| crate::Error::Serialize)
+            })?;
 
         Ok(self
             .metadata
             .config
             .encryption
-            .encrypt(compressed_data.as_slice(), &self.master_key))
+            .encrypt_in_place(compressed_data, &self.master_key))
     }
 
-    fn decode_data(&self, data: &[u8]) -> crate::Result<Vec<u8>> {
+    fn decode_reader(&self, data: Vec<u8>) -> crate::Result<Box<dyn Read>> {
         let decrypted_data = self
             .metadata
             .config
             .encryption
-            .decrypt(data, &self.master_key)?;
+            .decrypt_in_place(data, &self.master_key)?;
 
-        Ok(self
-            .metadata
+        self.metadata
             .config
             .compression
-            .decompress(decrypted_data.as_slice())?)
+            .decompress_reader(decrypted_data)
     }
 }
 
@@ -85,7 +147,7 @@ struct PackingBlockReader<'a> {
 
 impl<'a> ReadBlock for PackingBlockReader<'a> {
     fn read_block(&mut self, id: Uuid) -> crate::Result<Vec<u8>> {
-        let index_list = match self.repo_state.packs.get(&id) {
+        let index_list = match self.repo_state.pack_indices(&id)? {
             Some(pack_index) => pack_index,
             None => return Err(crate::Error::InvalidData),
         };
@@ -107,9 +169,9 @@ impl<'a> ReadBlock for PackingBlockReader<'a> {
                         .repo_state
                         .store
                         .lock()
-                        .unwrap()
+                        .recover()
                         .read_block(pack_index.id)
-                        .map_err(crate::Error::Store)?
+                        .map_err(crate::Error::from_store)?
                         .ok_or(crate::Error::InvalidData)?;
                     let pack_buffer = self
                         .repo_state
@@ -214,9 +276,9 @@ impl<'a> WriteBlock for PackingBlockWriter<'a> {
                 self.repo_state
                     .store
                     .lock()
-                    .unwrap()
+                    .recover()
                     .write_block(current_pack.id, encoded_pack.as_slice())
-                    .map_err(crate::Error::Store)?;
+                    .map_err(crate::Error::from_store)?;
 
                 // We're starting a new pack, so these need to be reset.
                 current_offset = 0;
@@ -239,9 +301,9 @@ impl<'a> WriteBlock for PackingBlockWriter<'a> {
                 self.repo_state
                     .store
                     .lock()
-                    .unwrap()
+                    .recover()
                     .write_block(current_pack.id, encoded_pack.as_slice())
-                    .map_err(crate::Error::Store)?;
+                    .map_err(crate::Error::from_store)?;
 
                 // We need to update the pack map in the repository state after all data has been
                 // written to the data store. If this method fails early, we can't have the pack map
@@ -254,6 +316,7 @@ impl<'a> WriteBlock for PackingBlockWriter<'a> {
                 // from the data store at this point in case the repository is rolled back, but we
                 // do need to replace the pack indices in the pack map, which we do here.
                 self.repo_state.packs.insert(id, new_packs_indices);
+                self.repo_state.header_changes.packs.insert(id);
 
                 return Ok(());
             }
@@ -261,6 +324,81 @@ impl<'a> WriteBlock for PackingBlockWriter<'a> {
     }
 }
 
+struct VariablePackingBlockWriter<'a> {
+    repo_state: &'a mut RepoState,
+    store_state: &'a mut StoreState,
+    pack_size: u32,
+}
+
+impl<'a> ReadBlock for VariablePackingBlockWriter<'a> {
+    fn read_block(&mut self, id: Uuid) -> crate::Result<Vec<u8>> {
+        let mut reader = PackingBlockReader {
+            repo_state: self.repo_state,
+            store_state: self.store_state,
+            pack_size: self.pack_size,
+        };
+        reader.read_block(id)
+    }
+}
+
+impl<'a> WriteBlock for VariablePackingBlockWriter<'a> {
+    fn write_block(&mut self, id: Uuid, data: &[u8]) -> crate::Result<()> {
+        // Write the current pack to the data store first if this block won't fit in it.
+        if let Some(pack) = &self.store_state.write_buffer {
+            if !pack.buffer.is_empty() && pack.buffer.len() + data.len() > self.pack_size as usize {
+                flush_pack(self.repo_state, self.store_state)?;
+            }
+        }
+
+        let pack_size = self.pack_size;
+        let current_pack = self
+            .store_state
+            .write_buffer
+            .get_or_insert_with(|| Pack::new(pack_size));
+        let pack_index = PackIndex {
+            id: current_pack.id,
+            offset: current_pack.buffer.len() as u32,
+            size: data.len() as u32,
+        };
+        current_pack.buffer.extend_from_slice(data);
+        self.store_state.pack_indices.push((id, pack_index));
+
+        if current_pack.buffer.len() >= self.pack_size as usize {
+            flush_pack(self.repo_state, self.store_state)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write the pack buffered in `store_state` to the data store and add its blocks to the pack map.
+///
+/// This is only used with `Packing::Variable`. If writing the pack fails, the blocks in it are
+/// discarded.
+fn flush_pack(repo_state: &mut RepoState, store_state: &mut StoreState) -> crate::Result<()> {
+    let pack = match store_state.write_buffer.take() {
+        Some(pack) if !pack.buffer.is_empty() => pack,
+        _ => return Ok(()),
+    };
+    let pack_indices = take(&mut store_state.pack_indices);
+
+    let encoded_pack = repo_state.encode_data(pack.buffer.as_slice())?;
+    repo_state
+        .store
+        .lock()
+        .recover()
+        .write_block(pack.id, encoded_pack.as_slice())
+        .map_err(crate::Error::from_store)?;
+
+    // The pack map must only reference blocks once they have been written to the data store.
+    for (block_id, pack_index) in pack_indices {
+        repo_state.packs.insert(block_id, vec![pack_index]);
+        repo_state.header_changes.packs.insert(block_id);
+    }
+
+    Ok(())
+}
+
 struct DirectBlockWriter<'a> {
     state: &'a RepoState,
 }
@@ -273,14 +411,18 @@ impl<'a> DirectBlockWriter<'a> {
 
 impl<'a> ReadBlock for DirectBlockWriter<'a> {
     fn read_block(&mut self, id: Uuid) -> crate::Result<Vec<u8>> {
-        let encoded_block = self
-            .state
-            .store
-            .lock()
-            .unwrap()
-            .read_block(id)
-            .map_err(crate::Error::Store)?
-            .ok_or(crate::Error::InvalidData)?;
+        // Read through the pool if there is one so that multiple threads can read at once.
+        let encoded_block = match &self.state.pool {
+            Some(pool) => pool.read(id)?,
+            None => self
+                .state
+                .store
+                .lock()
+                .recover()
+                .read_block(id)
+                .map_err(crate::Error::from_store)?,
+        }
+        .ok_or(crate::Error::InvalidData)?;
         self.state.decode_data(encoded_block.as_slice())
     }
 }
@@ -291,12 +433,41 @@ impl<'a> WriteBlock for DirectBlockWriter<'a> {
         self.state
             .store
             .lock()
-            .unwrap()
+            .recover()
             .write_block(id, encoded_block.as_slice())
-            .map_err(crate::Error::Store)
+            .map_err(crate::Error::from_store)
     }
 }
 
+/// A chunk which is being written to the data store by a `StorePool`.
+#[derive(Debug)]
+struct PendingChunk {
+    /// The chunk being written.
+    chunk: Chunk,
+
+    /// The ID of the block the chunk is being written to.
+    block_id: Uuid,
+
+    /// The unique ID to add to the chunk's references.
+    id: UniqueId,
+
+    /// The upload which is writing the block.
+    upload: PendingUpload,
+}
+
+/// A chunk which has been added to a pack but not yet to the repository.
+#[derive(Debug)]
+struct PackedChunk {
+    /// The chunk in the pack.
+    chunk: Chunk,
+
+    /// The ID of the block which stores the chunk.
+    block_id: Uuid,
+
+    /// The unique ID to add to the chunk's references.
+    id: UniqueId,
+}
+
 /// The state for a `StoreReader` or `StoreWriter`.
 #[derive(Debug)]
 pub struct StoreState {
@@ -305,6 +476,21 @@ pub struct StoreState {
 
     /// The pack which is currently being written to.
     write_buffer: Option<Pack>,
+
+    /// The chunks which are being written in the background.
+    pending: Vec<PendingChunk>,
+
+    /// The chunks which are being read in the background in anticipation of being read next.
+    prefetched: Vec<(Chunk, PendingRead)>,
+
+    /// The chunks which have been packed with `Packing::Variable` but not added to the repository.
+    packed: Vec<PackedChunk>,
+
+    /// The IDs and locations of the blocks in the pack which is currently being written to.
+    ///
+    /// This is only used with `Packing::Variable`, where blocks are added to the pack map once
+    /// their pack has been written to the data store.
+    pack_indices: Vec<(Uuid, PackIndex)>,
 }
 
 impl StoreState {
@@ -313,6 +499,10 @@ impl StoreState {
         StoreState {
             read_buffer: None,
             write_buffer: None,
+            pending: Vec::new(),
+            prefetched: Vec::new(),
+            packed: Vec::new(),
+            pack_indices: Vec::new(),
         }
     }
 }
@@ -332,6 +522,17 @@ pub trait WriteChunk: ReadChunk {
     ///
     /// This requires a unique `id` which is used for reference counting.
     fn write_chunk(&mut self, data: &[u8], id: UniqueId) -> crate::Result<Chunk>;
+
+    /// Write the given `data` as a new chunk using a `chunk` which was computed in advance.
+    ///
+    /// This is like `write_chunk`, except that it doesn't hash the `data`. The given `chunk` must
+    /// have been computed from `data`.
+    fn write_hashed_chunk(
+        &mut self,
+        chunk: Chunk,
+        data: &[u8],
+        id: UniqueId,
+    ) -> crate::Result<Chunk>;
 }
 
 /// A borrowed type for reading from a data store.
@@ -350,30 +551,199 @@ impl<'a> StoreReader<'a> {
     }
 }
 
+impl<'a> StoreReader<'a> {
+    /// Start reading each of the given `chunks` in the background.
+    ///
+    /// Chunks are only read in the background if the repository has a store pool and packing is
+    /// disabled. Chunks which were previously prefetched but aren't in `chunks` are discarded.
+    pub fn prefetch_chunks(&mut self, chunks: &[Chunk]) {
+        self.store_state
+            .prefetched
+            .retain(|(chunk, _)| chunks.contains(chunk));
+
+        let pool = match &self.repo_state.pool {
+            Some(pool) if self.repo_state.metadata.config.packing == Packing::None => pool,
+            _ => return,
+        };
+
+        for chunk in chunks {
+            if self
+                .store_state
+                .prefetched
+                .iter()
+                .any(|(prefetched_chunk, _)| prefetched_chunk == chunk)
+            {
+                continue;
+            }
+            if self.repo_state.chunk_cache.lock().recover().contains(chunk) {
+                continue;
+            }
+            // If the chunk can't be looked up, the error is returned when the chunk is read.
+            if let Ok(Some(chunk_info)) = self.repo_state.chunk_info(chunk) {
+                if chunk_info.inline.is_some() {
+                    continue;
+                }
+                let pending = pool.start_read(chunk_info.block_id);
+                self.store_state.prefetched.push((*chunk, pending));
+            }
+        }
+    }
+
+    /// Discard any chunks which are being read in the background.
+    pub fn clear_prefetched(&mut self) {
+        self.store_state.prefetched.clear();
+    }
+
+    /// Return the bytes of the given `chunk`, using the repository's chunk cache.
+    ///
+    /// If the chunk is not in the cache, it is read from the data store and added to the cache. The
+    /// returned buffer shares its memory with the cache.
+    pub fn read_cached_chunk(&mut self, chunk: Chunk) -> crate::Result<Bytes> {
+        if let Some(data) = self.repo_state.chunk_cache.lock().recover().get(&chunk) {
+            return Ok(data);
+        }
+
+        let data = Bytes::from(self.read_chunk(chunk)?);
+        self.repo_state
+            .chunk_cache
+            .lock()
+            .recover()
+            .insert(chunk, &data);
+        Ok(data)
+    }
+
+    /// Return `len` bytes of the given `chunk` starting at `offset` without reading all of it.
+    ///
+    /// Only part of a chunk can be read from the data store if blocks are neither compressed nor
+    /// encrypted. This returns `None` if that isn't possible or if the chunk is already available
+    /// without reading it from the data store, in which case the whole chunk should be read
+    /// instead.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, chunk), fields(size = chunk.size))
+    )]
+    pub fn read_chunk_range(
+        &mut self,
+        chunk: Chunk,
+        offset: u64,
+        len: u64,
+    ) -> crate::Result<Option<Bytes>> {
+        let config = &self.repo_state.metadata.config;
+        if config.compression != Compression::None || config.encryption != Encryption::None {
+            return Ok(None);
+        }
+
+        let is_prefetched = self
+            .store_state
+            .prefetched
+            .iter()
+            .any(|(prefetched_chunk, _)| *prefetched_chunk == chunk);
+        if is_prefetched
+            || self
+                .repo_state
+                .chunk_cache
+                .lock()
+                .recover()
+                .contains(&chunk)
+        {
+            return Ok(None);
+        }
+
+        let chunk_info = self
+            .repo_state
+            .chunk_info(&chunk)?
+            .ok_or(crate::Error::InvalidData)?;
+        if chunk_info.inline.is_some() {
+            return Ok(None);
+        }
+
+        // If packing is enabled, the chunk's block must be contained in a single pack which isn't
+        // already in the read buffer.
+        let (block_id, block_offset) = match &config.packing {
+            Packing::None => (chunk_info.block_id, offset),
+            Packing::Fixed(_) | Packing::Variable(_) => {
+                let index_list = self
+                    .repo_state
+                    .pack_indices(&chunk_info.block_id)?
+                    .ok_or(crate::Error::InvalidData)?;
+                match index_list.as_slice() {
+                    [pack_index]
+                        if !matches!(
+                            &self.store_state.read_buffer,
+                            Some(pack) if pack.id == pack_index.id
+                        ) =>
+                    {
+                        (pack_index.id, pack_index.offset as u64 + offset)
+                    }
+                    _ => return Ok(None),
+                }
+            }
+        };
+
+        let len = min(len, (chunk.size as u64).saturating_sub(offset));
+        let data = self
+            .repo_state
+            .store
+            .lock()
+            .recover()
+            .read_block_range(block_id, block_offset, len)
+            .map_err(crate::Error::from_store)?
+            .ok_or(crate::Error::InvalidData)?;
+        if data.len() as u64 != len {
+            return Err(crate::Error::InvalidData);
+        }
+
+        Ok(Some(Bytes::from(data)))
+    }
+}
+
 impl<'a> ReadBlock for StoreReader<'a> {
     fn read_block(&mut self, id: Uuid) -> crate::Result<Vec<u8>> {
         let mut read_block: Box<dyn ReadBlock> = match &self.repo_state.metadata.config.packing {
             Packing::None => Box::new(DirectBlockWriter {
-                state: &self.repo_state,
-            }),
-            Packing::Fixed(pack_size) => Box::new(PackingBlockReader {
-                repo_state: &self.repo_state,
-                store_state: &mut self.store_state,
-                pack_size: *pack_size,
+                state: self.repo_state,
             }),
+            Packing::Fixed(pack_size) | Packing::Variable(pack_size) => {
+                Box::new(PackingBlockReader {
+                    repo_state: self.repo_state,
+                    store_state: self.store_state,
+                    pack_size: *pack_size,
+                })
+            }
         };
         read_block.read_block(id)
     }
 }
 
 impl<'a> ReadChunk for StoreReader<'a> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, chunk), fields(size = chunk.size))
+    )]
     fn read_chunk(&mut self, chunk: Chunk) -> crate::Result<Vec<u8>> {
         let chunk_info = self
             .repo_state
-            .chunks
-            .get(&chunk)
+            .chunk_info(&chunk)?
             .ok_or(crate::Error::InvalidData)?;
-        self.read_block(chunk_info.block_id)
+
+        // Chunks which are stored inline don't need to be read from the data store.
+        if let Some(data) = &chunk_info.inline {
+            return Ok(data.clone());
+        }
+
+        let prefetched_index = self
+            .store_state
+            .prefetched
+            .iter()
+            .position(|(prefetched_chunk, _)| *prefetched_chunk == chunk);
+        if let Some(index) = prefetched_index {
+            let (_, pending) = self.store_state.prefetched.remove(index);
+            let encoded_block = pending.wait()?.ok_or(crate::Error::InvalidData)?;
+            return self.repo_state.decode_data(encoded_block.as_slice());
+        }
+
+        let block_id = chunk_info.block_id;
+        self.read_block(block_id)
     }
 }
 
@@ -391,6 +761,348 @@ impl<'a> StoreWriter<'a> {
             store_state,
         }
     }
+
+    /// Start writing the given `data` as a new chunk using a `chunk` which was computed in advance.
+    ///
+    /// If the repository has a store pool and packing is disabled, the chunk is written to the
+    /// data store by the pool and isn't added to the repository until `finish_uploads` is called.
+    /// If the repository uses `Packing::Variable`, the chunk is added to the current pack and
+    /// isn't added to the repository until `finish_uploads` is called. Otherwise, or if the chunk
+    /// is small enough to be stored inline, this is the same as `write_hashed_chunk`.
+    pub fn upload_hashed_chunk(
+        &mut self,
+        chunk: Chunk,
+        data: &[u8],
+        id: UniqueId,
+    ) -> crate::Result<Chunk> {
+        // Chunks which are stored inline are never written to the data store.
+        if chunk.size < self.repo_state.metadata.config.inline_threshold {
+            return self.write_hashed_chunk(chunk, data, id);
+        }
+
+        if let Packing::Variable(_) = self.repo_state.metadata.config.packing {
+            self.pack_hashed_chunk(chunk, data, id)?;
+            return Ok(chunk);
+        }
+
+        let background = self.repo_state.pool.is_some()
+            && self.repo_state.metadata.config.packing == Packing::None;
+        if !background {
+            return self.write_hashed_chunk(chunk, data, id);
+        }
+
+        if self.repo_state.read_only {
+            return Err(crate::Error::ReadOnly);
+        }
+
+        // Check if the chunk already exists.
+        if let Some(chunk_info) = self.repo_state.chunks.get_mut(&chunk) {
+            chunk_info.references.insert(id);
+            self.repo_state.header_changes.chunks.insert(chunk);
+            #[cfg(feature = "metrics")]
+            telemetry::chunk_deduplicated();
+            return Ok(chunk);
+        }
+
+        #[cfg(feature = "metrics")]
+        telemetry::chunk_written();
+
+        let encoded_block = self.repo_state.encode_data(data)?;
+        self.upload_encoded_chunk(chunk, encoded_block, id)?;
+
+        Ok(chunk)
+    }
+
+    /// Start writing each of the given chunks of `data` and return their checksums in order.
+    ///
+    /// This is like hashing each chunk and calling `upload_hashed_chunk`, except that if the
+    /// repository has a rayon thread pool, the chunks are hashed in parallel, and if packing is
+    /// also disabled, new chunks are compressed and encrypted in parallel.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, data), fields(count = data.len()))
+    )]
+    pub fn upload_chunks(&mut self, data: Vec<Vec<u8>>, id: UniqueId) -> crate::Result<Vec<Chunk>> {
+        let stats = &self.repo_state.stats;
+        let hashed_chunks = map_parallel(self.repo_state, data, |data| {
+            assert!(
+                data.len() <= u32::MAX as usize,
+                "Given data exceeds maximum chunk size."
+            );
+            let chunk = Chunk {
+                hash: stats
+                    .hashing
+                    .time(|| chunk_hash(&data), |_| data.len() as u64),
+                size: data.len() as u32,
+            };
+            (chunk, data)
+        });
+
+        // When packing is enabled, blocks are encoded a whole pack at a time.
+        if self.repo_state.metadata.config.packing != Packing::None {
+            return hashed_chunks
+                .into_iter()
+                .map(|(chunk, data)| self.upload_hashed_chunk(chunk, &data, id))
+                .collect();
+        }
+
+        // Only encode chunks which aren't already in the repository, and only encode each chunk
+        // once.
+        let mut chunks = Vec::with_capacity(hashed_chunks.len());
+        let mut new_chunks = Vec::new();
+        let mut seen_chunks = HashSet::new();
+        for (chunk, data) in hashed_chunks {
+            chunks.push(chunk);
+            if self.repo_state.chunks.contains_key(&chunk) || !seen_chunks.insert(chunk) {
+                #[cfg(feature = "metrics")]
+                telemetry::chunk_deduplicated();
+                continue;
+            }
+
+            if self.repo_state.read_only {
+                return Err(crate::Error::ReadOnly);
+            }
+
+            #[cfg(feature = "metrics")]
+            telemetry::chunk_written();
+
+            if !self.write_inline_chunk(chunk, &data, id) {
+                new_chunks.push((chunk, data));
+            }
+        }
+
+        let repo_state = &*self.repo_state;
+        let encoded_chunks = map_parallel(repo_state, new_chunks, |(chunk, data)| {
+            repo_state
+                .encode_data(&data)
+                .map(|encoded_block| (chunk, encoded_block))
+        });
+
+        let mut encoded_blocks = HashMap::new();
+        for result in encoded_chunks {
+            let (chunk, encoded_block) = result?;
+            encoded_blocks.insert(chunk, encoded_block);
+        }
+
+        for chunk in &chunks {
+            match encoded_blocks.remove(chunk) {
+                Some(encoded_block) => self.upload_encoded_chunk(*chunk, encoded_block, id)?,
+                None => {
+                    // The chunk is either already in the repository or it's a duplicate of a
+                    // chunk in this batch which is still being written with the same `id`.
+                    if let Some(chunk_info) = self.repo_state.chunks.get_mut(chunk) {
+                        chunk_info.references.insert(id);
+                        self.repo_state.header_changes.chunks.insert(*chunk);
+                    }
+                }
+            }
+        }
+
+        Ok(chunks)
+    }
+
+    /// Store the given `data` inline in the header as a new chunk if it is small enough.
+    ///
+    /// This returns `false` without doing anything if the chunk is not smaller than the
+    /// repository's inline threshold. The chunk must not already be in the repository.
+    fn write_inline_chunk(&mut self, chunk: Chunk, data: &[u8], id: UniqueId) -> bool {
+        if chunk.size >= self.repo_state.metadata.config.inline_threshold {
+            return false;
+        }
+
+        let chunk_info = ChunkInfo {
+            block_id: Uuid::nil(),
+            references: {
+                let mut id_set = HashSet::new();
+                id_set.insert(id);
+                id_set
+            },
+            inline: Some(data.to_vec()),
+        };
+        self.repo_state.chunks.insert(chunk, chunk_info);
+        self.repo_state.header_changes.chunks.insert(chunk);
+
+        true
+    }
+
+    /// Add the given `data` to the current pack as a new chunk with the given `chunk`.
+    ///
+    /// This must only be used with `Packing::Variable`.
+    fn pack_hashed_chunk(&mut self, chunk: Chunk, data: &[u8], id: UniqueId) -> crate::Result<()> {
+        if let Some(chunk_info) = self.repo_state.chunks.get_mut(&chunk) {
+            chunk_info.references.insert(id);
+            self.repo_state.header_changes.chunks.insert(chunk);
+            #[cfg(feature = "metrics")]
+            telemetry::chunk_deduplicated();
+            return Ok(());
+        }
+
+        // Don't pack the same data twice if the chunk was already packed in this transaction.
+        let packed_block_id = self
+            .store_state
+            .packed
+            .iter()
+            .find(|packed_chunk| packed_chunk.chunk == chunk)
+            .map(|packed_chunk| packed_chunk.block_id);
+        let block_id = match packed_block_id {
+            Some(block_id) => {
+                #[cfg(feature = "metrics")]
+                telemetry::chunk_deduplicated();
+                block_id
+            }
+            None => {
+                #[cfg(feature = "metrics")]
+                telemetry::chunk_written();
+                let block_id = Uuid::new_v4();
+                self.write_block(block_id, data)?;
+                block_id
+            }
+        };
+
+        self.store_state.packed.push(PackedChunk {
+            chunk,
+            block_id,
+            id,
+        });
+
+        Ok(())
+    }
+
+    /// Write the current pack to the data store if the repository uses `Packing::Variable`.
+    fn flush_pack(&mut self) -> crate::Result<()> {
+        match self.repo_state.metadata.config.packing {
+            Packing::Variable(_) => flush_pack(self.repo_state, self.store_state),
+            _ => Ok(()),
+        }
+    }
+
+    /// Discard any chunks which were written with `upload_hashed_chunk` and haven't been added to
+    /// the repository.
+    pub fn discard_uploads(&mut self) {
+        self.store_state.pending.clear();
+        self.store_state.packed.clear();
+        if !self.store_state.pack_indices.is_empty() {
+            self.store_state.pack_indices.clear();
+            self.store_state.write_buffer = None;
+        }
+    }
+
+    /// Write a new chunk which has already been encoded as `encoded_block`.
+    ///
+    /// If the repository has a store pool, the chunk is written in the background like with
+    /// `upload_hashed_chunk`. This must only be used when packing is disabled.
+    fn upload_encoded_chunk(
+        &mut self,
+        chunk: Chunk,
+        encoded_block: Vec<u8>,
+        id: UniqueId,
+    ) -> crate::Result<()> {
+        let block_id = Uuid::new_v4();
+
+        match &self.repo_state.pool {
+            Some(pool) => {
+                let upload = pool.upload(block_id, encoded_block);
+                self.store_state.pending.push(PendingChunk {
+                    chunk,
+                    block_id,
+                    id,
+                    upload,
+                });
+            }
+            None => {
+                self.repo_state
+                    .store
+                    .lock()
+                    .recover()
+                    .write_block(block_id, encoded_block.as_slice())
+                    .map_err(crate::Error::from_store)?;
+                let chunk_info = ChunkInfo {
+                    block_id,
+                    references: {
+                        let mut id_set = HashSet::new();
+                        id_set.insert(id);
+                        id_set
+                    },
+                    inline: None,
+                };
+                self.repo_state.chunks.insert(chunk, chunk_info);
+                self.repo_state.header_changes.chunks.insert(chunk);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait for chunks written with `upload_hashed_chunk` and add them to the repository.
+    ///
+    /// If any of the chunks could not be written, none of them are added to the repository.
+    pub fn finish_uploads(&mut self) -> crate::Result<()> {
+        if let Err(error) = self.flush_pack() {
+            self.store_state.packed.clear();
+            return Err(error);
+        }
+        for packed_chunk in take(&mut self.store_state.packed) {
+            self.repo_state
+                .header_changes
+                .chunks
+                .insert(packed_chunk.chunk);
+            match self.repo_state.chunks.get_mut(&packed_chunk.chunk) {
+                Some(chunk_info) => {
+                    chunk_info.references.insert(packed_chunk.id);
+                }
+                None => {
+                    let chunk_info = ChunkInfo {
+                        block_id: packed_chunk.block_id,
+                        references: {
+                            let mut id_set = HashSet::new();
+                            id_set.insert(packed_chunk.id);
+                            id_set
+                        },
+                        inline: None,
+                    };
+                    self.repo_state
+                        .chunks
+                        .insert(packed_chunk.chunk, chunk_info);
+                }
+            }
+        }
+
+        let pending = take(&mut self.store_state.pending);
+        for pending_chunk in &pending {
+            pending_chunk.upload.wait()?;
+        }
+
+        for pending_chunk in pending {
+            self.repo_state
+                .header_changes
+                .chunks
+                .insert(pending_chunk.chunk);
+            match self.repo_state.chunks.get_mut(&pending_chunk.chunk) {
+                // The same chunk was added to the repository while this one was being written. The
+                // block we wrote is unreferenced and will be removed when the repository is
+                // cleaned.
+                Some(chunk_info) => {
+                    chunk_info.references.insert(pending_chunk.id);
+                }
+                None => {
+                    let chunk_info = ChunkInfo {
+                        block_id: pending_chunk.block_id,
+                        references: {
+                            let mut id_set = HashSet::new();
+                            id_set.insert(pending_chunk.id);
+                            id_set
+                        },
+                        inline: None,
+                    };
+                    self.repo_state
+                        .chunks
+                        .insert(pending_chunk.chunk, chunk_info);
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl<'a> ReadBlock for StoreWriter<'a> {
@@ -405,14 +1117,23 @@ impl<'a> ReadBlock for StoreWriter<'a> {
 
 impl<'a> WriteBlock for StoreWriter<'a> {
     fn write_block(&mut self, id: Uuid, data: &[u8]) -> crate::Result<()> {
+        if self.repo_state.read_only {
+            return Err(crate::Error::ReadOnly);
+        }
+
         let mut block_writer: Box<dyn WriteBlock> =
             match self.repo_state.metadata.config.packing.clone() {
                 Packing::None => Box::new(DirectBlockWriter {
-                    state: &self.repo_state,
+                    state: self.repo_state,
                 }),
                 Packing::Fixed(pack_size) => Box::new(PackingBlockWriter {
-                    repo_state: &mut self.repo_state,
-                    store_state: &mut self.store_state,
+                    repo_state: self.repo_state,
+                    store_state: self.store_state,
+                    pack_size,
+                }),
+                Packing::Variable(pack_size) => Box::new(VariablePackingBlockWriter {
+                    repo_state: self.repo_state,
+                    store_state: self.store_state,
                     pack_size,
                 }),
             };
@@ -431,6 +1152,10 @@ impl<'a> ReadChunk for StoreWriter<'a> {
 }
 
 impl<'a> WriteChunk for StoreWriter<'a> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, data), fields(size = data.len()))
+    )]
     fn write_chunk(&mut self, data: &[u8], id: UniqueId) -> crate::Result<Chunk> {
         assert!(
             data.len() <= std::u32::MAX as usize,
@@ -439,19 +1164,49 @@ impl<'a> WriteChunk for StoreWriter<'a> {
 
         // Get a checksum of the unencoded data.
         let chunk = Chunk {
-            hash: chunk_hash(data),
+            hash: self
+                .repo_state
+                .stats
+                .hashing
+                .time(|| chunk_hash(data), |_| data.len() as u64),
             size: data.len() as u32,
         };
 
+        self.write_hashed_chunk(chunk, data, id)
+    }
+
+    fn write_hashed_chunk(
+        &mut self,
+        chunk: Chunk,
+        data: &[u8],
+        id: UniqueId,
+    ) -> crate::Result<Chunk> {
         // Check if the chunk already exists.
         if let Some(chunk_info) = self.repo_state.chunks.get_mut(&chunk) {
             chunk_info.references.insert(id);
+            self.repo_state.header_changes.chunks.insert(chunk);
+            #[cfg(feature = "metrics")]
+            telemetry::chunk_deduplicated();
+            return Ok(chunk);
+        }
+
+        if self.repo_state.read_only {
+            return Err(crate::Error::ReadOnly);
+        }
+
+        #[cfg(feature = "metrics")]
+        telemetry::chunk_written();
+
+        if self.write_inline_chunk(chunk, data, id) {
             return Ok(chunk);
         }
 
         let block_id = Uuid::new_v4();
         self.write_block(block_id, data)?;
 
+        // The chunk is added to the repository immediately, so its pack must be written first.
+        self.flush_pack()?;
+
         // Add the chunk to the header.
         let chunk_info = ChunkInfo {
             block_id,
@@ -460,8 +1215,10 @@ impl<'a> WriteChunk for StoreWriter<'a> {
                 id_set.insert(id);
                 id_set
             },
+            inline: None,
         };
         self.repo_state.chunks.insert(chunk, chunk_info);
+        self.repo_state.header_changes.chunks.insert(chunk);
 
         Ok(chunk)
     }
@@ -14,12 +14,15 @@
  * limitations under the License.
  */
 
-use std::io::{self, Write};
+use std::cmp::min;
+use std::io::{self, Read, Write};
 use std::mem::replace;
 
 use cdchunking::{ChunkerImpl, ZPAQ};
 use serde::{Deserialize, Serialize};
 
+use super::handle::{chunk_hash, Chunk};
+
 /// A method for chunking data in a repository.
 ///
 /// Data is deduplicated, read into memory, and written to the data store in chunks. This value
@@ -53,11 +56,19 @@ pub enum Chunking {
 }
 
 impl Chunking {
-    /// Return a chunker for this chunking method.
-    pub(super) fn to_chunker(&self) -> Box<dyn ChunkerImpl> {
-        match self {
+    /// Return a chunker for this chunking method which never produces chunks larger than
+    /// `max_size`.
+    ///
+    /// If `max_size` is `0`, the size of chunks is not limited.
+    pub(super) fn to_chunker(&self, max_size: usize) -> Box<dyn ChunkerImpl + Send + Sync> {
+        let chunker: Box<dyn ChunkerImpl + Send + Sync> = match self {
             Chunking::Fixed { size } => Box::new(FixedChunker::new(*size as usize)),
             Chunking::Zpaq { bits } => Box::new(ZPAQ::new(*bits as usize)),
+        };
+        if max_size == 0 {
+            chunker
+        } else {
+            Box::new(BoundedChunker::new(chunker, max_size))
         }
     }
 
@@ -104,16 +115,58 @@ impl ChunkerImpl for FixedChunker {
     }
 }
 
+/// A `ChunkerImpl` which forces a chunk boundary when a chunk would exceed a maximum size.
+///
+/// Content-defined chunking can produce arbitrarily large chunks, and each chunk must be buffered
+/// in memory until it is complete. This bounds how much data is buffered.
+pub struct BoundedChunker {
+    chunker: Box<dyn ChunkerImpl + Send + Sync>,
+    max_size: usize,
+    bytes_read: usize,
+}
+
+impl BoundedChunker {
+    /// Return a new instance which finds boundaries using `chunker` but never produces chunks
+    /// larger than `max_size`.
+    pub fn new(chunker: Box<dyn ChunkerImpl + Send + Sync>, max_size: usize) -> Self {
+        BoundedChunker {
+            chunker,
+            max_size,
+            bytes_read: 0,
+        }
+    }
+}
+
+impl ChunkerImpl for BoundedChunker {
+    fn find_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        let remaining = self.max_size - self.bytes_read;
+        let window = &data[..min(data.len(), remaining)];
+        match self.chunker.find_boundary(window) {
+            Some(index) => Some(index),
+            None if window.len() == remaining => Some(remaining),
+            None => {
+                self.bytes_read += window.len();
+                None
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.chunker.reset();
+        self.bytes_read = 0;
+    }
+}
+
 /// A chunker which partitions data written to it into chunks.
 pub struct IncrementalChunker {
-    chunker: Box<dyn ChunkerImpl>,
+    chunker: Box<dyn ChunkerImpl + Send + Sync>,
     buffer: Vec<u8>,
     chunks: Vec<Vec<u8>>,
 }
 
 impl IncrementalChunker {
     /// Return a new instance which uses the given `chunker` to determine chunk boundaries.
-    pub fn new(chunker: Box<dyn ChunkerImpl>) -> Self {
+    pub fn new(chunker: Box<dyn ChunkerImpl + Send + Sync>) -> Self {
         Self {
             chunker,
             buffer: Vec::new(),
@@ -137,6 +190,12 @@ impl IncrementalChunker {
         self.chunker.reset();
     }
 
+    /// Return the total size in bytes of the complete chunks which are waiting to be returned by
+    /// `chunks`.
+    pub fn chunked_size(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.len()).sum()
+    }
+
     /// Return whether this chunker contains no data.
     pub fn is_empty(&self) -> bool {
         self.buffer.is_empty() && self.chunks.is_empty()
@@ -173,3 +232,68 @@ impl Write for IncrementalChunker {
         Ok(())
     }
 }
+
+/// A chunk of data which has been split and hashed in advance.
+///
+/// Splitting data into chunks and hashing it doesn't require access to the repository, so it can
+/// be done on another thread.
+#[derive(Debug)]
+pub struct PreparedChunk {
+    /// The hash and size of the chunk.
+    pub(super) chunk: Chunk,
+
+    /// The contents of the chunk.
+    pub(super) data: Vec<u8>,
+}
+
+impl PreparedChunk {
+    /// Return the size of this chunk in bytes.
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+}
+
+/// Read all the data from `reader`, split it into chunks using `chunking`, and hash each chunk.
+///
+/// Chunks are no larger than `max_chunk_size`, or unbounded if it is `0`. Data is read from
+/// `reader` using a buffer of `buffer_size` bytes. Each chunk is passed to `consume` as soon as it
+/// is ready. If `consume` returns `false`, this stops reading and returns early.
+pub fn prepare_chunks(
+    mut reader: impl Read,
+    chunking: &Chunking,
+    max_chunk_size: usize,
+    buffer_size: usize,
+    mut consume: impl FnMut(PreparedChunk) -> bool,
+) -> io::Result<()> {
+    let mut chunker = IncrementalChunker::new(chunking.to_chunker(max_chunk_size));
+    // Reading into an empty buffer would look the same as reaching the end of the data.
+    let mut buffer = vec![0u8; buffer_size.max(1)];
+
+    loop {
+        let bytes_read = match reader.read(&mut buffer) {
+            Ok(bytes_read) => bytes_read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+
+        if bytes_read == 0 {
+            chunker.flush()?;
+        } else {
+            chunker.write_all(&buffer[..bytes_read])?;
+        }
+
+        for data in chunker.chunks() {
+            let chunk = Chunk {
+                hash: chunk_hash(&data),
+                size: data.len() as u32,
+            };
+            if !consume(PreparedChunk { chunk, data }) {
+                return Ok(());
+            }
+        }
+
+        if bytes_read == 0 {
+            return Ok(());
+        }
+    }
+}
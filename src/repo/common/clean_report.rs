@@ -0,0 +1,57 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use uuid::Uuid;
+
+/// A report of the changes which cleaning a repository would make to the data store.
+///
+/// This is returned by [`KeyRepo::clean_dry_run`].
+///
+/// [`KeyRepo::clean_dry_run`]: crate::repo::key::KeyRepo::clean_dry_run
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct CleanReport {
+    /// The IDs of the blocks which would be removed from the data store.
+    ///
+    /// If packing is enabled, these are the IDs of packs.
+    pub removed_blocks: Vec<Uuid>,
+
+    /// The IDs of the blocks which would be rewritten to new packs before their packs are removed.
+    ///
+    /// This is always empty if packing is disabled.
+    pub repacked_blocks: Vec<Uuid>,
+
+    /// The number of bytes in the data store occupied by `removed_blocks`.
+    pub removed_bytes: u64,
+
+    /// The number of bytes of data in `repacked_blocks`.
+    pub repacked_bytes: u64,
+}
+
+impl CleanReport {
+    /// Return whether cleaning the repository would not change the data store.
+    pub fn is_empty(&self) -> bool {
+        self.removed_blocks.is_empty() && self.repacked_blocks.is_empty()
+    }
+
+    /// The approximate number of bytes which would be reclaimed in the data store.
+    ///
+    /// This is the number of bytes which would be removed minus the number of bytes which would be
+    /// rewritten to new packs.
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.removed_bytes.saturating_sub(self.repacked_bytes)
+    }
+}
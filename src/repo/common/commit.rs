@@ -32,13 +32,23 @@ pub trait Commit {
     /// This method commits changes for all instances of the repository.
     ///
     /// # Errors
+    /// - `Error::ReadOnly`: The repository was opened in read-only mode.
+    /// - `Error::Poisoned`: A thread panicked while modifying the repository, and it hasn't been
+    ///   rolled back since.
+    /// - `Error::Vetoed`: A hook registered to run before committing vetoed the commit.
+    /// - `Error::Cancelled`: The commit was cancelled with a [`CancellationToken`].
+    /// - `Error::Conflict`: The repository was opened with optimistic concurrency and another
+    ///   writer committed changes since this repository was opened or refreshed.
+    /// - `Error::Locked`: Leases are enabled and another process acquired the lease on the
+    ///   repository after this repository's lease expired.
     /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
     /// - `Error::InvalidData`: Ciphertext verification failed.
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
     ///
     /// [`clean`]: crate::repo::Commit::clean
-    fn commit(&mut self) -> crate::Result<()>;
+    /// [`CancellationToken`]: crate::repo::CancellationToken
+    fn commit(&self) -> crate::Result<()>;
 
     /// Roll back all changes made since the last commit.
     ///
@@ -55,6 +65,7 @@ pub trait Commit {
     /// with the repository.
     ///
     /// # Errors
+    /// - `Error::Vetoed`: A hook registered to run before rolling back vetoed the rollback.
     /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
     /// - `Error::InvalidData`: Ciphertext verification failed.
     /// - `Error::Store`: An error occurred with the data store.
@@ -62,17 +73,49 @@ pub trait Commit {
     ///
     /// [`Object`]: crate::repo::Object
     /// [`ReadOnlyObject`]: crate::repo::ReadOnlyObject
-    fn rollback(&mut self) -> crate::Result<()>;
+    fn rollback(&self) -> crate::Result<()>;
+
+    /// Discard all changes made since the last commit and load the most recent commit.
+    ///
+    /// This is like [`rollback`], except that it loads changes which other writers have committed
+    /// since this repository was opened. This is used to resolve an `Error::Conflict` when the
+    /// repository was opened with [`OpenOptions::optimistic_concurrency`].
+    ///
+    /// If this method returns `Ok`, the repository has been refreshed. If this method returns
+    /// `Err`, the repository is unchanged.
+    ///
+    /// Refreshing the repository invalidates all [`Object`] and [`ReadOnlyObject`] instances
+    /// associated with the repository.
+    ///
+    /// # Errors
+    /// - `Error::Vetoed`: A hook registered to run before refreshing vetoed the refresh.
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`rollback`]: crate::repo::Commit::rollback
+    /// [`OpenOptions::optimistic_concurrency`]: crate::repo::OpenOptions::optimistic_concurrency
+    /// [`Object`]: crate::repo::Object
+    /// [`ReadOnlyObject`]: crate::repo::ReadOnlyObject
+    fn refresh(&self) -> crate::Result<()>;
 
     /// Clean up the repository to reclaim space in the backing data store.
     ///
     /// When data in a repository is deleted, the space is not reclaimed in the backing data store
     /// until those changes are committed and this method is called.
     ///
+    /// If this method is cancelled with a [`CancellationToken`], the space which was reclaimed
+    /// before it was cancelled is not restored.
+    ///
     /// # Errors
+    /// - `Error::ReadOnly`: The repository was opened in read-only mode.
+    /// - `Error::Cancelled`: The operation was cancelled with a [`CancellationToken`].
     /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
     /// - `Error::InvalidData`: Ciphertext verification failed.
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
-    fn clean(&mut self) -> crate::Result<()>;
+    ///
+    /// [`CancellationToken`]: crate::repo::CancellationToken
+    fn clean(&self) -> crate::Result<()>;
 }
@@ -0,0 +1,157 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use hex_literal::hex;
+use rmp_serde::{from_read, to_vec};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::store::DataStore;
+
+use super::metadata::RepoMetadata;
+use super::repository::METADATA_BLOCK_ID;
+
+/// The block ID of the block which stores the marker for a commit in progress.
+pub const COMMIT_MARKER_BLOCK_ID: Uuid =
+    Uuid::from_bytes(hex!("a4e2d87c 1f35 4b90 8c6e 03b7f9d25a18"));
+
+/// A marker which records a commit in progress so that it can be recovered if it's interrupted.
+///
+/// Committing changes to the data store happens in three phases. First, the commit is prepared
+/// by writing this marker, which records the metadata from before the commit and the blocks the
+/// new header will be written to, and then writing those blocks. Second, the commit is published
+/// by writing the new repository metadata. Third, the commit is finalized by removing this marker.
+///
+/// Data stores which don't support atomic operations on multiple blocks, like [`S3Store`], may
+/// fail partway through writing a header or report that a write succeeded before the block is
+/// durable. If a marker is found when the repository is opened, the commit was interrupted, and
+/// [`recover`] uses it to make sure the metadata never points at a header which is incomplete.
+///
+/// [`S3Store`]: crate::store::S3Store
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitMarker {
+    /// The generation of the repository metadata which the commit publishes.
+    pub generation: u64,
+
+    /// The repository metadata from before the commit.
+    pub previous: RepoMetadata,
+
+    /// The IDs of the blocks which store the new header.
+    pub blocks: Vec<Uuid>,
+}
+
+impl CommitMarker {
+    /// Read the marker from `store`, or return `None` if no commit is in progress.
+    fn read(store: &mut dyn DataStore) -> crate::Result<Option<Self>> {
+        match store
+            .read_block(COMMIT_MARKER_BLOCK_ID)
+            .map_err(crate::Error::from_store)?
+        {
+            Some(serialized_marker) => Ok(Some(
+                from_read(serialized_marker.as_slice()).map_err(|_| crate::Error::Corrupt)?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Prepare the commit by writing this marker to `store`.
+    ///
+    /// This must be called before any of the blocks in `blocks` are written.
+    pub fn prepare(&self, store: &mut dyn DataStore) -> crate::Result<()> {
+        let serialized_marker = to_vec(self).expect("Could not serialize the commit marker.");
+        store
+            .write_block(COMMIT_MARKER_BLOCK_ID, &serialized_marker)
+            .map_err(crate::Error::from_store)
+    }
+
+    /// Finalize the commit by removing the marker from `store`.
+    ///
+    /// This must be called after the new metadata has been written.
+    pub fn finalize(store: &mut dyn DataStore) -> crate::Result<()> {
+        store
+            .remove_block(COMMIT_MARKER_BLOCK_ID)
+            .map_err(crate::Error::from_store)
+    }
+}
+
+/// Return whether every block in `blocks` exists in `store` and passes its checksum.
+fn blocks_are_intact(store: &mut dyn DataStore, blocks: &[Uuid]) -> crate::Result<bool> {
+    for block_id in blocks {
+        match store.read_block(*block_id) {
+            Ok(Some(_)) => {}
+            Ok(None) => return Ok(false),
+            Err(error) => match crate::Error::from_store(error) {
+                crate::Error::CorruptBlock(_) => return Ok(false),
+                error => return Err(error),
+            },
+        }
+    }
+    Ok(true)
+}
+
+/// Recover from a commit which was interrupted, returning the metadata of the last complete commit.
+///
+/// `metadata` is the metadata which was read from `store`. If the commit was never published, the
+/// blocks it wrote are removed. If it was published but its header is incomplete, the metadata from
+/// before the commit is restored with a new generation so that other writers see the change.
+///
+/// If `writable` is `false`, the data store is not modified, but the returned metadata is the same.
+/// This should only be `true` when no other writer can be committing changes at the same time.
+///
+/// # Errors
+/// - `Error::Corrupt`: The commit marker is corrupt.
+/// - `Error::Store`: An error occurred with the data store.
+pub fn recover(
+    store: &mut dyn DataStore,
+    metadata: RepoMetadata,
+    writable: bool,
+) -> crate::Result<RepoMetadata> {
+    let marker = match CommitMarker::read(store)? {
+        Some(marker) => marker,
+        None => return Ok(metadata),
+    };
+
+    // The commit was published. If the new header is complete, it only needs to be finalized.
+    if metadata.generation == marker.generation && !blocks_are_intact(store, &marker.blocks)? {
+        let mut previous = marker.previous;
+        previous.generation = marker.generation + 1;
+        if writable {
+            let serialized_metadata =
+                to_vec(&previous).expect("Could not serialize repository metadata.");
+            store
+                .write_block(METADATA_BLOCK_ID, &serialized_metadata)
+                .map_err(crate::Error::from_store)?;
+            store
+                .remove_blocks(&marker.blocks)
+                .map_err(crate::Error::from_store)?;
+            CommitMarker::finalize(store)?;
+        }
+        return Ok(previous);
+    }
+
+    if writable {
+        // The commit was never published, so the blocks it wrote aren't referenced. If the marker
+        // is older than the metadata, its blocks may be referenced by a later commit.
+        if metadata.generation < marker.generation {
+            store
+                .remove_blocks(&marker.blocks)
+                .map_err(crate::Error::from_store)?;
+        }
+        CommitMarker::finalize(store)?;
+    }
+
+    Ok(metadata)
+}
@@ -14,13 +14,12 @@
  * limitations under the License.
  */
 
+use std::io::{Cursor, Read, Write};
+
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "compression")]
-use {
-    lz4::{Decoder as Lz4Decoder, EncoderBuilder as Lz4EncoderBuilder},
-    std::io::{Read, Write},
-};
+use lz4::{Decoder as Lz4Decoder, EncoderBuilder as Lz4EncoderBuilder};
 
 /// A data compression method.
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -58,6 +57,39 @@ impl Compression {
         }
     }
 
+    /// Compress the bytes which `write` writes and return them.
+    ///
+    /// This is like `compress`, except the uncompressed data is streamed into the compressor as it
+    /// is written instead of being buffered in memory first.
+    pub(crate) fn compress_with(
+        &self,
+        write: impl FnOnce(&mut dyn Write) -> crate::Result<()>,
+    ) -> crate::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        match self {
+            Compression::None => write(&mut output)?,
+            #[cfg(feature = "compression")]
+            Compression::Lz4 { level } => {
+                let mut encoder = Lz4EncoderBuilder::new().level(*level).build(&mut output)?;
+                write(&mut encoder)?;
+                let (_, result) = encoder.finish();
+                result?;
+            }
+        }
+        Ok(output)
+    }
+
+    /// Return a reader which decompresses the given `data` as it is read.
+    ///
+    /// This is like `decompress`, except the decompressed data is never buffered in memory.
+    pub(crate) fn decompress_reader(&self, data: Vec<u8>) -> crate::Result<Box<dyn Read>> {
+        match self {
+            Compression::None => Ok(Box::new(Cursor::new(data))),
+            #[cfg(feature = "compression")]
+            Compression::Lz4 { .. } => Ok(Box::new(Lz4Decoder::new(Cursor::new(data))?)),
+        }
+    }
+
     /// Wraps the given `reader` to decompress its bytes using this compression method.
     pub(crate) fn decompress(&self, data: &[u8]) -> crate::Result<Vec<u8>> {
         match self {
@@ -14,12 +14,16 @@
  * limitations under the License.
  */
 
+use std::time::Duration;
+
+use cdchunking::ChunkerImpl;
 use serde::{Deserialize, Serialize};
 
 use super::chunking::Chunking;
 use super::compression::Compression;
 use super::encryption::{Encryption, ResourceLimit};
 use super::packing::Packing;
+use super::parity::Parity;
 
 /// The configuration for an repository.
 ///
@@ -58,6 +62,158 @@ pub struct RepoConfig {
     ///
     /// The default value is `ResourceLimit::Interactive`.
     pub operations_limit: ResourceLimit,
+
+    /// How long a lease on the repository lasts before it expires, if leases are enabled.
+    ///
+    /// When this is `Some`, opening the repository acquires a lease which is stored in the data
+    /// store itself. This protects the repository from being opened simultaneously by multiple
+    /// processes or machines which share the same data store. The lease is renewed each time the
+    /// repository is committed and by a background thread once half of this duration has elapsed,
+    /// and it is released when the repository is dropped. If a process crashes while holding a
+    /// lease, the repository remains locked until the lease expires. Once a lease has been lost, it
+    /// can't be renewed, and committing returns `Error::Locked`.
+    ///
+    /// Leases rely on the clocks of all the machines which share the data store agreeing to within
+    /// a small fraction of this duration.
+    ///
+    /// The default value is `None`.
+    #[serde(default)]
+    pub lease_duration: Option<Duration>,
+
+    /// The number of bytes of complete chunks an [`Object`] buffers before writing them.
+    ///
+    /// Data written to an [`Object`] is split into chunks as it is written. Each chunk must be
+    /// buffered in memory until it is complete, so the chunk size limits how much data is buffered.
+    /// By default, each chunk is then written to the data store as soon as it is complete. If this
+    /// value is greater than the chunk size, complete chunks are buffered until their total size
+    /// reaches this value and are then written together. This uses more memory, but it allows
+    /// more chunks to be hashed, compressed, and encrypted at once when the `rayon` feature is
+    /// enabled, which improves throughput for bulk imports. Buffered chunks are always written
+    /// when the object is committed.
+    ///
+    /// The default value is `0`, which means chunks are not buffered.
+    ///
+    /// [`Object`]: crate::repo::Object
+    #[serde(default)]
+    pub write_buffer_size: u32,
+
+    /// The size in bytes of the buffer used to read files when splitting them into chunks.
+    ///
+    /// This is used when files are read and chunked on worker threads, like when
+    /// [`FileRepo::archive_tree`] is called with more than one thread. Each worker allocates a
+    /// buffer of this size.
+    ///
+    /// The default value is 64 KiB.
+    ///
+    /// [`FileRepo::archive_tree`]: crate::repo::file::FileRepo::archive_tree
+    #[serde(default = "default_read_buffer_size")]
+    pub read_buffer_size: u32,
+
+    /// The size in bytes below which chunks are stored inline in the repository header.
+    ///
+    /// Chunks which are smaller than this are stored in the repository header instead of in
+    /// separate blocks in the data store. The header is read into memory when the repository is
+    /// opened, so reading an object which is smaller than this never requires accessing the data
+    /// store. This is useful for repositories which store many small values, like a
+    /// [`ValueRepo`]. However, every inline chunk is kept in memory while the repository is open
+    /// and is written to the data store each time the repository is committed, so this should be
+    /// kept small. The last chunk of a larger object is also stored inline if it is small enough.
+    ///
+    /// The default value is `0`, which means chunks are never stored inline.
+    ///
+    /// [`ValueRepo`]: crate::repo::value::ValueRepo
+    #[serde(default)]
+    pub inline_threshold: u32,
+
+    /// The number of commits which write only the changes to the repository header before the
+    /// whole header is written again.
+    ///
+    /// Writing the whole header on every commit can dominate commit time for repositories with
+    /// many chunks. Instead, each commit writes a delta containing only the entries which have
+    /// changed, and the header is compacted once this many deltas have accumulated. Each delta
+    /// must be read when the repository is opened, so larger values make commits cheaper at the
+    /// cost of opening the repository.
+    ///
+    /// The default value is `16`. A value of `0` means the whole header is written on every
+    /// commit.
+    #[serde(default)]
+    pub max_header_deltas: u32,
+
+    /// The maximum size of a chunk in bytes.
+    ///
+    /// Each chunk must be buffered in memory until it is complete. `Chunking::Zpaq` produces
+    /// chunks of varying size, and some data can produce chunks which are much larger than the
+    /// average, especially with a large number of `bits`. When a chunk reaches this size, a chunk
+    /// boundary is forced, which bounds how much data is buffered for each object being written.
+    /// Large writes are also split into pieces of this size before they are chunked. This also
+    /// applies to `Chunking::Fixed`, so the chunk size is effectively the smaller of the two.
+    ///
+    /// The default value is 16 MiB. A value of `0` means the size of chunks is not limited.
+    #[serde(default)]
+    pub max_chunk_size: u32,
+
+    /// Whether to keep an audit log of operations performed on the repository.
+    ///
+    /// When this is `true`, opening, creating, committing, rolling back, restoring, and cleaning
+    /// the repository are recorded in an append-only log which is stored in the repository along
+    /// with a timestamp and the ID of the current instance. Removing objects, branches, and
+    /// instances is also recorded, and those entries are written along with the next entry of one
+    /// of the other kinds. The log is encrypted along with the rest of the repository and can be
+    /// read with [`KeyRepo::audit_log`]. Operations are not recorded when the repository is opened
+    /// in read-only mode.
+    ///
+    /// If the log can't be written to the data store, the entries are kept in memory and written
+    /// along with the next entry. Writing the log is not synchronized between writers, so entries
+    /// may be lost if the repository is opened with optimistic concurrency.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`KeyRepo::audit_log`]: crate::repo::key::KeyRepo::audit_log
+    #[serde(default)]
+    pub audit_log: bool,
+
+    /// Whether to store a checksum with each block in the data store.
+    ///
+    /// When this is `true`, CRC32C checksums are stored with each block when it is written and
+    /// verified when it is read. A block which was corrupted on disk or in transit is reported as
+    /// `Error::CorruptBlock` with the ID of the block instead of failing to decrypt or decompress.
+    /// This is independent of encryption, which also detects corrupt data but can't distinguish it
+    /// from an incorrect key.
+    ///
+    /// Each 64 KiB segment of a block has its own checksum, so reading part of a block only reads
+    /// and verifies the segments which overlap it. The checksums add 4 bytes to each block plus 4
+    /// bytes for every 64 KiB.
+    ///
+    /// The default value is `true`. Repositories created by versions of this library which didn't
+    /// support checksums have this set to `false`.
+    #[serde(default)]
+    pub block_checksums: bool,
+
+    /// The method to use for storing parity blocks.
+    ///
+    /// Parity blocks can be used to reconstruct lost or corrupted blocks with
+    /// [`KeyRepo::repair`]. Blocks which are corrupted but still have the right size can only be
+    /// detected if `block_checksums` is `true`. Parity blocks are grouped when changes are
+    /// committed, and cleaning the repository periodically regroups them to reduce overhead. They
+    /// are not maintained when the repository is opened with optimistic concurrency.
+    ///
+    /// The default value is `Parity::None`.
+    ///
+    /// [`KeyRepo::repair`]: crate::repo::key::KeyRepo::repair
+    #[serde(default)]
+    pub parity: Parity,
+}
+
+impl RepoConfig {
+    /// Return a chunker which splits data into chunks according to this config.
+    pub(super) fn to_chunker(&self) -> Box<dyn ChunkerImpl + Send + Sync> {
+        self.chunking.to_chunker(self.max_chunk_size as usize)
+    }
+}
+
+/// Return the default value of `RepoConfig::read_buffer_size`.
+fn default_read_buffer_size() -> u32 {
+    64 * 1024
 }
 
 impl Default for RepoConfig {
@@ -69,6 +225,15 @@ impl Default for RepoConfig {
             encryption: Encryption::None,
             memory_limit: ResourceLimit::Interactive,
             operations_limit: ResourceLimit::Interactive,
+            lease_duration: None,
+            write_buffer_size: 0,
+            read_buffer_size: default_read_buffer_size(),
+            inline_threshold: 0,
+            max_header_deltas: 16,
+            max_chunk_size: 16 * 1024 * 1024,
+            audit_log: false,
+            block_checksums: true,
+            parity: Parity::None,
         }
     }
 }
@@ -24,7 +24,8 @@ use {
     rand::rngs::OsRng,
     rand::RngCore,
     sodiumoxide::crypto::aead::xchacha20poly1305_ietf::{
-        gen_nonce, open, seal, Key as ChaChaKey, Nonce, KEYBYTES, NONCEBYTES,
+        gen_nonce, open, open_detached, seal, seal_detached, Key as ChaChaKey, Nonce, Tag,
+        KEYBYTES, NONCEBYTES, TAGBYTES,
     },
     sodiumoxide::crypto::pwhash::argon2id13::{
         derive_key, gen_salt, MemLimit, OpsLimit, Salt, MEMLIMIT_INTERACTIVE, MEMLIMIT_MODERATE,
@@ -138,6 +139,75 @@ impl Encryption {
     ) -> crate::Result<Vec<u8>> {
         Ok(ciphertext.to_vec())
     }
+
+    /// Encrypt the given `cleartext` with the given `key`, reusing its buffer for the ciphertext.
+    ///
+    /// This produces the same format as `encrypt`.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn encrypt_in_place(&self, mut cleartext: Vec<u8>, key: &EncryptionKey) -> Vec<u8> {
+        init();
+        match self {
+            Encryption::None => cleartext,
+            Encryption::XChaCha20Poly1305 => {
+                let nonce = gen_nonce();
+                let chacha_key = ChaChaKey::from_slice(key.expose_secret()).unwrap();
+                let tag = seal_detached(&mut cleartext, None, &nonce, &chacha_key);
+                cleartext.reserve(NONCEBYTES + TAGBYTES);
+                cleartext.extend_from_slice(tag.as_ref());
+                cleartext.splice(0..0, nonce.as_ref().iter().copied());
+                cleartext
+            }
+        }
+    }
+
+    /// Encrypt the given `cleartext` with the given `key`, reusing its buffer for the ciphertext.
+    #[cfg(not(feature = "encryption"))]
+    pub(crate) fn encrypt_in_place(&self, cleartext: Vec<u8>, _key: &EncryptionKey) -> Vec<u8> {
+        cleartext
+    }
+
+    /// Decrypt the given `ciphertext` with the given `key`, reusing its buffer for the cleartext.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn decrypt_in_place(
+        &self,
+        mut ciphertext: Vec<u8>,
+        key: &EncryptionKey,
+    ) -> crate::Result<Vec<u8>> {
+        init();
+        match self {
+            Encryption::None => Ok(ciphertext),
+            Encryption::XChaCha20Poly1305 => {
+                if ciphertext.len() < NONCEBYTES + TAGBYTES {
+                    return Err(crate::Error::InvalidData);
+                }
+                let tag_start = ciphertext.len() - TAGBYTES;
+                let nonce = Nonce::from_slice(&ciphertext[..NONCEBYTES]).unwrap();
+                let tag = Tag::from_slice(&ciphertext[tag_start..]).unwrap();
+                let chacha_key = ChaChaKey::from_slice(key.expose_secret()).unwrap();
+                open_detached(
+                    &mut ciphertext[NONCEBYTES..tag_start],
+                    None,
+                    &tag,
+                    &nonce,
+                    &chacha_key,
+                )
+                .map_err(|_| crate::Error::InvalidData)?;
+                ciphertext.truncate(tag_start);
+                ciphertext.drain(..NONCEBYTES);
+                Ok(ciphertext)
+            }
+        }
+    }
+
+    /// Decrypt the given `ciphertext` with the given `key`, reusing its buffer for the cleartext.
+    #[cfg(not(feature = "encryption"))]
+    pub(crate) fn decrypt_in_place(
+        &self,
+        ciphertext: Vec<u8>,
+        _key: &EncryptionKey,
+    ) -> crate::Result<Vec<u8>> {
+        Ok(ciphertext)
+    }
 }
 
 impl Encryption {
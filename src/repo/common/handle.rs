@@ -14,8 +14,11 @@
  * limitations under the License.
  */
 
-use std::cmp::min;
+use std::cmp::{max, min};
 use std::io::{self, Read};
+use std::iter;
+use std::ops::Range;
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -79,7 +82,9 @@ pub struct ObjectHandle {
     pub id: UniqueId,
 
     /// The extents which make up the object.
-    pub extents: Vec<Extent>,
+    ///
+    /// Handles with identical contents may share their extents, which are copied on write.
+    pub extents: Arc<Vec<Extent>>,
 }
 
 impl ObjectHandle {
@@ -95,6 +100,29 @@ impl ObjectHandle {
             Extent::Hole { .. } => None,
         })
     }
+
+    /// Return the extents which make up the given `range` of this object.
+    ///
+    /// Holes can be split, but chunks can't, so this returns `None` if the start or end of `range`
+    /// is partway through a chunk.
+    pub(crate) fn slice_extents(&self, range: Range<u64>) -> Option<Vec<Extent>> {
+        let mut extents = Vec::new();
+        let mut position = 0;
+        for extent in self.extents.iter() {
+            let extent_end = position + extent.size();
+            let start = range.start.max(position);
+            let end = range.end.min(extent_end);
+            if start < end {
+                match extent {
+                    Extent::Chunk(_) if start != position || end != extent_end => return None,
+                    Extent::Chunk(_) => extents.push(*extent),
+                    Extent::Hole { .. } => extents.push(Extent::Hole { size: end - start }),
+                }
+            }
+            position = extent_end;
+        }
+        Some(extents)
+    }
 }
 
 /// A value that represents the identity of an object.
@@ -108,7 +136,7 @@ impl ObjectHandle {
 /// # use acid_store::repo::{OpenOptions, OpenMode};
 /// # use acid_store::store::MemoryConfig;
 /// # use acid_store::repo::key::KeyRepo;
-/// let mut repo: KeyRepo<String> = OpenOptions::new()
+/// let repo: KeyRepo<String> = OpenOptions::new()
 ///    .mode(OpenMode::CreateNew)
 ///    .open(&MemoryConfig::new())
 ///    .unwrap();
@@ -175,6 +203,34 @@ impl ContentId {
         self.extents.iter().map(|extent| extent.size()).sum()
     }
 
+    /// Return the byte ranges of the contents which are holes, in order.
+    ///
+    /// Holes are regions of empty space which contain no data, like those in sparse files.
+    pub(crate) fn holes(&self) -> Vec<Range<u64>> {
+        let mut holes = Vec::new();
+        let mut position = 0;
+        for extent in &self.extents {
+            let end = position + extent.size();
+            if matches!(extent, Extent::Hole { size } if *size > 0) {
+                // Merge adjacent holes.
+                match holes.last_mut() {
+                    Some(Range { end: hole_end, .. }) if *hole_end == position => *hole_end = end,
+                    _ => holes.push(position..end),
+                }
+            }
+            position = end;
+        }
+        holes
+    }
+
+    /// Return an iterator over the chunks which make up the contents, in order.
+    pub(crate) fn chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.extents.iter().filter_map(|extent| match extent {
+            Extent::Chunk(chunk) => Some(chunk),
+            Extent::Hole { .. } => None,
+        })
+    }
+
     /// Return whether this content ID has the same contents as `other`.
     ///
     /// This compares the contents of this content ID with `other` without reading any data from the
@@ -247,4 +303,78 @@ impl ContentId {
 
         Ok(true)
     }
+
+    /// Return the byte ranges which differ between this content ID and `other`.
+    ///
+    /// This compares the contents without reading any data from the data store. The comparison is
+    /// done at the granularity of chunks, so a range may be reported as changed even if only some
+    /// of the bytes in it differ. The returned ranges are sorted, do not overlap, and are relative
+    /// to the start of the contents. If the contents have different sizes, the range past the end
+    /// of the smaller one is always reported as changed.
+    ///
+    /// Content IDs from different repositories cannot be compared, so if `other` is from a
+    /// different repository, the entire range of both contents is reported as changed.
+    pub fn diff(&self, other: &ContentId) -> Vec<Range<u64>> {
+        let total_size = max(self.size(), other.size());
+
+        if self.repo_id != other.repo_id {
+            return if total_size == 0 {
+                Vec::new()
+            } else {
+                iter::once(0..total_size).collect()
+            };
+        }
+
+        let mut changed: Vec<Range<u64>> = Vec::new();
+        let mut push_changed = |range: Range<u64>| match changed.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => changed.push(range),
+        };
+
+        let mut self_extents = self.extents.iter();
+        let mut other_extents = other.extents.iter();
+        let mut self_current = self_extents.next().map(|extent| (extent, 0, extent.size()));
+        let mut other_current = other_extents
+            .next()
+            .map(|extent| (extent, 0, extent.size()));
+
+        while let (
+            Some((self_extent, self_start, self_end)),
+            Some((other_extent, other_start, other_end)),
+        ) = (self_current, other_current)
+        {
+            let overlap_start = max(self_start, other_start);
+            let overlap_end = min(self_end, other_end);
+
+            let unchanged = match (self_extent, other_extent) {
+                (Extent::Hole { .. }, Extent::Hole { .. }) => true,
+                (Extent::Chunk(self_chunk), Extent::Chunk(other_chunk)) => {
+                    self_start == other_start && self_chunk == other_chunk
+                }
+                _ => false,
+            };
+
+            if !unchanged && overlap_start < overlap_end {
+                push_changed(overlap_start..overlap_end);
+            }
+
+            if self_end <= other_end {
+                self_current = self_extents
+                    .next()
+                    .map(|extent| (extent, self_end, self_end + extent.size()));
+            }
+            if other_end <= self_end {
+                other_current = other_extents
+                    .next()
+                    .map(|extent| (extent, other_end, other_end + extent.size()));
+            }
+        }
+
+        let common_size = min(self.size(), other.size());
+        if common_size < total_size {
+            push_changed(common_size..total_size);
+        }
+
+        changed
+    }
 }
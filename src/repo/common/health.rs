@@ -0,0 +1,82 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::time::SystemTime;
+
+/// Whether a repository is currently locked.
+///
+/// This is part of [`HealthReport`].
+///
+/// [`HealthReport`]: crate::repo::HealthReport
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum LockState {
+    /// The repository is not locked.
+    Unlocked,
+
+    /// The repository is open and locked in this process.
+    Locked,
+
+    /// A process holds a lease on the repository which expires at the given time.
+    Leased {
+        /// The time at which the lease expires unless it is renewed.
+        expires: SystemTime,
+    },
+}
+
+/// A summary of the health of a repository.
+///
+/// This is returned by [`OpenOptions::health_check`].
+///
+/// [`OpenOptions::health_check`]: crate::repo::OpenOptions::health_check
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub struct HealthReport {
+    /// Whether the repository format is supported by this version of the library.
+    ///
+    /// Repositories in a format created by a previous version of this library are supported if
+    /// they can be migrated to the current format.
+    ///
+    /// If this is `false`, the repository can't be opened and the other checks are skipped.
+    pub version_compatible: bool,
+
+    /// Whether the repository is currently locked.
+    pub lock: LockState,
+
+    /// The time at which changes were last committed to the repository.
+    ///
+    /// This is `None` if changes have never been committed or if the repository was last
+    /// committed by a version of the library which didn't record this.
+    pub last_commit: Option<SystemTime>,
+
+    /// Whether the repository header could be read and decoded.
+    pub header_intact: bool,
+
+    /// The number of data blocks which were checked for existence.
+    pub sampled_blocks: usize,
+
+    /// The number of sampled data blocks which are missing from the data store.
+    pub missing_blocks: usize,
+}
+
+impl HealthReport {
+    /// Return whether all the checks passed.
+    ///
+    /// A repository which is locked is not considered unhealthy.
+    pub fn is_healthy(&self) -> bool {
+        self.version_compatible && self.header_intact && self.missing_blocks == 0
+    }
+}
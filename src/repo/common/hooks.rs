@@ -0,0 +1,108 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Mutex;
+
+use super::poison::RecoverPoison;
+
+/// An event at a transaction boundary in a repository which hooks can be registered for.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum TransactionEvent {
+    /// Changes are committed with [`Commit::commit`].
+    ///
+    /// [`Commit::commit`]: crate::repo::Commit::commit
+    Commit,
+
+    /// Changes are rolled back with [`Commit::rollback`].
+    ///
+    /// [`Commit::rollback`]: crate::repo::Commit::rollback
+    Rollback,
+
+    /// The repository is refreshed with [`Commit::refresh`].
+    ///
+    /// [`Commit::refresh`]: crate::repo::Commit::refresh
+    Refresh,
+
+    /// The repository is restored to a savepoint with [`RestoreSavepoint`].
+    ///
+    /// The hooks which run before this event run in [`RestoreSavepoint::start_restore`], and the
+    /// hooks which run after this event run in [`RestoreSavepoint::finish_restore`].
+    ///
+    /// [`RestoreSavepoint`]: crate::repo::RestoreSavepoint
+    /// [`RestoreSavepoint::start_restore`]: crate::repo::RestoreSavepoint::start_restore
+    /// [`RestoreSavepoint::finish_restore`]: crate::repo::RestoreSavepoint::finish_restore
+    Restore,
+}
+
+/// A hook which runs before an event and can veto it.
+type BeforeHook = Box<dyn FnMut() -> anyhow::Result<()> + Send>;
+
+/// A hook which runs after an event.
+type AfterHook = Box<dyn FnMut() + Send>;
+
+/// The hooks registered with a repository.
+///
+/// Hooks are only ever run with exclusive access to the repository, but each one is wrapped in a
+/// `Mutex` so that repositories are `Sync` without requiring hooks to be `Sync`.
+#[derive(Default)]
+pub struct Hooks {
+    before: Vec<(TransactionEvent, Mutex<BeforeHook>)>,
+    after: Vec<(TransactionEvent, Mutex<AfterHook>)>,
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("before", &self.before.len())
+            .field("after", &self.after.len())
+            .finish()
+    }
+}
+
+impl Hooks {
+    /// Register a `hook` to run before the given `event`.
+    pub fn add_before(&mut self, event: TransactionEvent, hook: BeforeHook) {
+        self.before.push((event, Mutex::new(hook)));
+    }
+
+    /// Register a `hook` to run after the given `event`.
+    pub fn add_after(&mut self, event: TransactionEvent, hook: AfterHook) {
+        self.after.push((event, Mutex::new(hook)));
+    }
+
+    /// Run the hooks registered to run before the given `event` in the order they were registered.
+    ///
+    /// # Errors
+    /// - `Error::Vetoed`: One of the hooks returned an error. The remaining hooks are not run.
+    pub fn run_before(&mut self, event: TransactionEvent) -> crate::Result<()> {
+        for (hook_event, hook) in self.before.iter_mut() {
+            if *hook_event == event {
+                (hook.get_mut().recover())().map_err(crate::Error::Vetoed)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the hooks registered to run after the given `event` in the order they were registered.
+    pub fn run_after(&mut self, event: TransactionEvent) {
+        for (hook_event, hook) in self.after.iter_mut() {
+            if *hook_event == event {
+                (hook.get_mut().recover())();
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/// Information about a chunk in a repository.
+///
+/// This is returned by [`KeyRepo::inspect_chunks`].
+///
+/// [`KeyRepo::inspect_chunks`]: crate::repo::key::KeyRepo::inspect_chunks
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ChunkReport<K> {
+    /// The BLAKE3 checksum of the contents of the chunk.
+    pub hash: [u8; 32],
+
+    /// The size of the chunk in bytes before compression and encryption.
+    pub size: u32,
+
+    /// Whether the chunk is stored inline in the repository header instead of in the data store.
+    pub inline: bool,
+
+    /// The number of packs which the chunk is stored in.
+    ///
+    /// This is `0` if packing is disabled or the chunk is stored inline. A value greater than `1`
+    /// means the chunk is split between multiple packs.
+    pub packs: usize,
+
+    /// The number of objects which reference the chunk.
+    ///
+    /// This counts objects in every instance and branch of the repository.
+    pub references: usize,
+
+    /// The keys of the objects in the current instance and branch which reference the chunk.
+    pub keys: Vec<K>,
+}
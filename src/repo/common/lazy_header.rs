@@ -0,0 +1,265 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+
+use once_cell::sync::OnceCell;
+use rmp_serde::from_read;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::chunk_store::EncodeBlock;
+use super::handle::Chunk;
+use super::id_table::IdTable;
+use super::state::{ChunkInfo, InstanceInfo, PackIndex};
+
+/// The number of entries from the chunk and pack maps to store in each shard.
+const ENTRIES_PER_SHARD: usize = 4096;
+
+/// The maximum number of shards to split the chunk and pack maps into.
+const MAX_SHARDS: usize = 256;
+
+/// A portion of the chunk and pack maps from the repository header.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HeaderShard {
+    /// The chunks in this shard.
+    pub chunks: HashMap<Chunk, ChunkInfo>,
+
+    /// The pack indices of the blocks in this shard.
+    pub packs: HashMap<Uuid, Vec<PackIndex>>,
+}
+
+/// A borrowed `Header` which can be serialized without being cloned.
+#[derive(Serialize)]
+struct HeaderRef<'a> {
+    chunks: &'a HashMap<Chunk, ChunkInfo>,
+    packs: &'a HashMap<Uuid, Vec<PackIndex>>,
+    instances: &'a HashMap<Uuid, InstanceInfo>,
+    handle_table: &'a IdTable,
+}
+
+/// A borrowed `HeaderShard` which can be serialized without being cloned.
+#[derive(Default, Serialize)]
+struct HeaderShardRef<'a> {
+    chunks: HashMap<&'a Chunk, &'a ChunkInfo>,
+    packs: HashMap<&'a Uuid, &'a Vec<PackIndex>>,
+}
+
+/// Return the index of the shard which contains the key whose first byte is `first_byte`.
+fn shard_index(first_byte: u8, num_shards: usize) -> usize {
+    first_byte as usize % num_shards
+}
+
+/// Serialize and encode a repository header consisting of the given maps using `encoder`.
+///
+/// If the chunk and pack maps are large, they are split into shards which are encoded separately
+/// from the rest of the header so that they can be loaded on demand. This returns the encoded
+/// header and each of the encoded shards, which is empty if the maps are stored in the header
+/// itself.
+///
+/// # Errors
+/// - `Error::Serialize`: The header could not be serialized.
+/// - `Error::Io`: An I/O error occurred.
+pub fn encode_header(
+    chunks: &HashMap<Chunk, ChunkInfo>,
+    packs: &HashMap<Uuid, Vec<PackIndex>>,
+    instances: &HashMap<Uuid, InstanceInfo>,
+    handle_table: &IdTable,
+    encoder: &impl EncodeBlock,
+) -> crate::Result<(Vec<u8>, Vec<Vec<u8>>)> {
+    let num_entries = chunks.len() + packs.len();
+    let num_shards = min(num_entries.div_ceil(ENTRIES_PER_SHARD), MAX_SHARDS);
+
+    if num_shards <= 1 {
+        let header = HeaderRef {
+            chunks,
+            packs,
+            instances,
+            handle_table,
+        };
+        return Ok((encoder.encode_value(&header)?, Vec::new()));
+    }
+
+    let mut shards = (0..num_shards)
+        .map(|_| HeaderShardRef::default())
+        .collect::<Vec<_>>();
+    for (chunk, chunk_info) in chunks {
+        shards[shard_index(chunk.hash[0], num_shards)]
+            .chunks
+            .insert(chunk, chunk_info);
+    }
+    for (block_id, pack_indices) in packs {
+        shards[shard_index(block_id.as_bytes()[0], num_shards)]
+            .packs
+            .insert(block_id, pack_indices);
+    }
+
+    let header = HeaderRef {
+        chunks: &HashMap::new(),
+        packs: &HashMap::new(),
+        instances,
+        handle_table,
+    };
+    let encoded_header = encoder.encode_value(&header)?;
+    let encoded_shards = shards
+        .iter()
+        .map(|shard| encoder.encode_value(shard))
+        .collect::<crate::Result<Vec<_>>>()?;
+
+    Ok((encoded_header, encoded_shards))
+}
+
+/// The chunk and pack maps from a repository header, which are loaded from the data store on
+/// demand.
+///
+/// Entries which have been added or changed by deltas since the shards were written are not
+/// stored here, but entries which have been removed by deltas are tracked so that they aren't
+/// loaded from a shard.
+#[derive(Debug)]
+pub struct LazyHeader {
+    /// The IDs of the blocks which store each shard.
+    shard_ids: Vec<Uuid>,
+
+    /// Each shard, if it has been loaded.
+    shards: Vec<OnceCell<HeaderShard>>,
+
+    /// The chunks which have been removed since the shards were written.
+    removed_chunks: HashSet<Chunk>,
+
+    /// The blocks whose pack indices have been removed since the shards were written.
+    removed_packs: HashSet<Uuid>,
+}
+
+impl LazyHeader {
+    /// Create a new instance which loads shards from the blocks with the given `shard_ids`.
+    pub fn new(shard_ids: Vec<Uuid>) -> Self {
+        LazyHeader {
+            shards: shard_ids.iter().map(|_| OnceCell::new()).collect(),
+            shard_ids,
+            removed_chunks: HashSet::new(),
+            removed_packs: HashSet::new(),
+        }
+    }
+
+    /// Record whether the given `chunk` has been removed since the shards were written.
+    pub fn set_chunk_removed(&mut self, chunk: Chunk, removed: bool) {
+        if removed {
+            self.removed_chunks.insert(chunk);
+        } else {
+            self.removed_chunks.remove(&chunk);
+        }
+    }
+
+    /// Record whether the given `block_id` has been removed since the shards were written.
+    pub fn set_pack_removed(&mut self, block_id: Uuid, removed: bool) {
+        if removed {
+            self.removed_packs.insert(block_id);
+        } else {
+            self.removed_packs.remove(&block_id);
+        }
+    }
+
+    /// Return the shard with the given `index`, loading it if necessary.
+    ///
+    /// The `read_block` function is used to read a block from the data store and return a reader
+    /// which decodes it.
+    fn shard(
+        &self,
+        index: usize,
+        read_block: impl FnOnce(Uuid) -> crate::Result<Box<dyn Read>>,
+    ) -> crate::Result<&HeaderShard> {
+        self.shards[index].get_or_try_init(|| {
+            let serialized_shard = read_block(self.shard_ids[index])?;
+            from_read(serialized_shard).map_err(|_| crate::Error::Corrupt)
+        })
+    }
+
+    /// Return information about the given `chunk`, loading its shard if necessary.
+    ///
+    /// The `read_block` function is used to read a block from the data store and return a reader
+    /// which decodes it.
+    pub fn chunk_info(
+        &self,
+        chunk: &Chunk,
+        read_block: impl FnOnce(Uuid) -> crate::Result<Box<dyn Read>>,
+    ) -> crate::Result<Option<&ChunkInfo>> {
+        if self.shards.is_empty() || self.removed_chunks.contains(chunk) {
+            return Ok(None);
+        }
+        let index = shard_index(chunk.hash[0], self.shards.len());
+        Ok(self.shard(index, read_block)?.chunks.get(chunk))
+    }
+
+    /// Return the pack indices for the given `block_id`, loading its shard if necessary.
+    ///
+    /// The `read_block` function is used to read a block from the data store and return a reader
+    /// which decodes it.
+    pub fn pack_indices(
+        &self,
+        block_id: &Uuid,
+        read_block: impl FnOnce(Uuid) -> crate::Result<Box<dyn Read>>,
+    ) -> crate::Result<Option<&Vec<PackIndex>>> {
+        if self.shards.is_empty() || self.removed_packs.contains(block_id) {
+            return Ok(None);
+        }
+        let index = shard_index(block_id.as_bytes()[0], self.shards.len());
+        Ok(self.shard(index, read_block)?.packs.get(block_id))
+    }
+
+    /// Load every shard which hasn't been loaded yet.
+    ///
+    /// The `read_block` function is used to read a block from the data store and return a reader
+    /// which decodes it.
+    pub fn load_all(
+        &self,
+        mut read_block: impl FnMut(Uuid) -> crate::Result<Box<dyn Read>>,
+    ) -> crate::Result<()> {
+        for index in 0..self.shards.len() {
+            self.shard(index, &mut read_block)?;
+        }
+        Ok(())
+    }
+
+    /// Add the entries from each shard to the given `chunks` and `packs` maps.
+    ///
+    /// Entries which are already in the maps or which have been removed are skipped.
+    ///
+    /// # Panics
+    /// - Not every shard has been loaded with `load_all`.
+    pub fn merge_into(
+        self,
+        chunks: &mut HashMap<Chunk, ChunkInfo>,
+        packs: &mut HashMap<Uuid, Vec<PackIndex>>,
+    ) {
+        for shard in self.shards {
+            let shard = shard
+                .into_inner()
+                .expect("Not every shard of the header has been loaded.");
+            for (chunk, chunk_info) in shard.chunks {
+                if !self.removed_chunks.contains(&chunk) {
+                    chunks.entry(chunk).or_insert(chunk_info);
+                }
+            }
+            for (block_id, pack_indices) in shard.packs {
+                if !self.removed_packs.contains(&block_id) {
+                    packs.entry(block_id).or_insert(pack_indices);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,221 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::time::{Duration, SystemTime};
+
+use hex_literal::hex;
+use rmp_serde::{from_read, to_vec};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::store::DataStore;
+
+/// The block ID of the block which stores the current lease on the repository.
+pub const LEASE_BLOCK_ID: Uuid = Uuid::from_bytes(hex!("5b0c3a2e 8f4d 4c1e 9a7b 2d6e1f0c8b34"));
+
+/// The lease information which is stored in the data store.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct LeaseInfo {
+    /// The ID of the process which holds the lease.
+    owner: Uuid,
+
+    /// The time at which the lease expires unless it is renewed.
+    expires: SystemTime,
+}
+
+/// A lease on a repository which is stored in the data store itself.
+///
+/// Unlike a `Lock`, a lease protects a repository from concurrent access between processes and
+/// machines which share the same data store. A lease expires if it is not renewed within its
+/// duration, so a process which crashes while holding a lease does not lock the repository
+/// forever.
+///
+/// Expiration times are wall-clock times written by the process which holds the lease and
+/// compared against the clock of each process which tries to acquire it. This assumes the clocks
+/// of all the machines sharing the data store agree to within a small fraction of the lease
+/// duration. If a clock runs ahead of the others, its process can acquire a lease which another
+/// process still believes it holds.
+#[derive(Debug)]
+pub struct Lease {
+    /// The unique ID of the holder of this lease.
+    owner: Uuid,
+
+    /// How long the lease lasts after it is acquired or renewed.
+    duration: Duration,
+}
+
+impl Lease {
+    /// Read the current lease from `store`.
+    fn read_info(store: &mut dyn DataStore) -> crate::Result<Option<LeaseInfo>> {
+        match store
+            .read_block(LEASE_BLOCK_ID)
+            .map_err(crate::Error::from_store)?
+        {
+            Some(serialized_info) => Ok(Some(
+                from_read(serialized_info.as_slice()).map_err(|_| crate::Error::Corrupt)?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Write this lease to `store` with a new expiration time.
+    fn write_info(&self, store: &mut dyn DataStore) -> crate::Result<()> {
+        let info = LeaseInfo {
+            owner: self.owner,
+            expires: SystemTime::now() + self.duration,
+        };
+        let serialized_info = to_vec(&info).expect("Could not serialize the lease.");
+        store
+            .write_block(LEASE_BLOCK_ID, &serialized_info)
+            .map_err(crate::Error::from_store)
+    }
+
+    /// Return when the lease on the repository in `store` expires, or `None` if there is no
+    /// unexpired lease.
+    pub fn expiration(store: &mut dyn DataStore) -> crate::Result<Option<SystemTime>> {
+        Ok(Self::read_info(store)?
+            .map(|info| info.expires)
+            .filter(|expires| *expires > SystemTime::now()))
+    }
+
+    /// Attempt to acquire a lease with the given `duration` on the repository in `store`.
+    ///
+    /// This returns `None` if another process holds an unexpired lease.
+    ///
+    /// Data stores don't provide an atomic compare-and-swap operation, so after writing the lease,
+    /// we read it back to detect another process which acquired the lease at the same time. This
+    /// narrows the window for a race but cannot eliminate it entirely.
+    pub fn acquire(store: &mut dyn DataStore, duration: Duration) -> crate::Result<Option<Self>> {
+        if let Some(info) = Self::read_info(store)? {
+            if info.expires > SystemTime::now() {
+                return Ok(None);
+            }
+        }
+
+        let lease = Lease {
+            owner: Uuid::new_v4(),
+            duration,
+        };
+        lease.write_info(store)?;
+
+        match Self::read_info(store)? {
+            Some(info) if info.owner == lease.owner => Ok(Some(lease)),
+            _ => Ok(None),
+        }
+    }
+
+    /// How long the lease lasts after it is acquired or renewed.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Extend this lease by its duration.
+    ///
+    /// Once a lease has expired or been removed from the data store, another process may have
+    /// acquired it, so a lost lease is never renewed.
+    ///
+    /// # Errors
+    /// - `Error::Locked`: The lease expired, was removed, or was acquired by another process.
+    /// - `Error::Corrupt`: The lease in the data store is corrupt.
+    /// - `Error::Store`: An error occurred with the data store.
+    pub fn renew(&self, store: &mut dyn DataStore) -> crate::Result<()> {
+        match Self::read_info(store)? {
+            Some(info) if info.owner == self.owner && info.expires > SystemTime::now() => {}
+            _ => return Err(crate::Error::Locked),
+        }
+
+        self.write_info(store)?;
+
+        // Read the lease back in case another process acquired it just as it expired, like in
+        // `acquire`.
+        match Self::read_info(store)? {
+            Some(info) if info.owner == self.owner => Ok(()),
+            _ => Err(crate::Error::Locked),
+        }
+    }
+
+    /// Release this lease so that other processes can acquire it.
+    ///
+    /// If the lease has since been acquired by another process, this does nothing.
+    pub fn release(&self, store: &mut dyn DataStore) -> crate::Result<()> {
+        match Self::read_info(store)? {
+            Some(info) if info.owner == self.owner => store
+                .remove_block(LEASE_BLOCK_ID)
+                .map_err(crate::Error::from_store),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::store::{DataStore, MemoryConfig, OpenStore};
+
+    use super::{Lease, LEASE_BLOCK_ID};
+
+    #[test]
+    fn held_lease_is_renewed() -> anyhow::Result<()> {
+        let mut store = MemoryConfig::new().open()?;
+        let lease = Lease::acquire(&mut store, Duration::from_secs(60 * 60))?.unwrap();
+        let expiration = Lease::expiration(&mut store)?.unwrap();
+
+        thread::sleep(Duration::from_millis(10));
+        lease.renew(&mut store)?;
+
+        assert!(Lease::expiration(&mut store)?.unwrap() > expiration);
+        Ok(())
+    }
+
+    #[test]
+    fn expired_lease_is_not_renewed() -> anyhow::Result<()> {
+        let mut store = MemoryConfig::new().open()?;
+        let lease = Lease::acquire(&mut store, Duration::from_millis(10))?.unwrap();
+
+        thread::sleep(Duration::from_millis(50));
+
+        assert!(matches!(lease.renew(&mut store), Err(crate::Error::Locked)));
+        assert!(Lease::expiration(&mut store)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn lease_acquired_by_another_process_is_not_renewed() -> anyhow::Result<()> {
+        let mut store = MemoryConfig::new().open()?;
+        let lease = Lease::acquire(&mut store, Duration::from_millis(10))?.unwrap();
+
+        thread::sleep(Duration::from_millis(50));
+        let other_lease = Lease::acquire(&mut store, Duration::from_secs(60 * 60))?.unwrap();
+
+        assert!(matches!(lease.renew(&mut store), Err(crate::Error::Locked)));
+        other_lease.renew(&mut store)?;
+        Ok(())
+    }
+
+    #[test]
+    fn removed_lease_is_not_renewed() -> anyhow::Result<()> {
+        let mut store = MemoryConfig::new().open()?;
+        let lease = Lease::acquire(&mut store, Duration::from_secs(60 * 60))?.unwrap();
+
+        store.remove_block(LEASE_BLOCK_ID)?;
+
+        assert!(matches!(lease.renew(&mut store), Err(crate::Error::Locked)));
+        assert!(store.read_block(LEASE_BLOCK_ID)?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use super::poison::RecoverPoison;
+use super::state::RepoState;
+
+/// A thread which periodically renews the lease on a repository.
+///
+/// This keeps the lease from expiring while the repository is open but not being committed. The
+/// thread stops when this value is dropped.
+#[derive(Debug)]
+pub struct LeaseRenewal {
+    /// The sender for waking up the thread to stop it.
+    stop: Mutex<Sender<()>>,
+}
+
+impl LeaseRenewal {
+    /// Start renewing the lease held by the repository with the given `state` every `interval`.
+    ///
+    /// The thread stops if the repository is dropped or another process has acquired the lease.
+    /// Other errors are ignored, because the lease is renewed again after the next `interval` and
+    /// committing the repository reports any error renewing it.
+    pub fn start(state: &Arc<RwLock<RepoState>>, interval: Duration) -> Self {
+        let (stop_sender, stop_receiver) = channel::<()>();
+        let state = Arc::downgrade(state);
+
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                let state = match state.upgrade() {
+                    Some(state) => state,
+                    None => break,
+                };
+                let state_guard = state.read().recover();
+                let lease = match &state_guard.lease {
+                    Some(lease) => lease,
+                    None => break,
+                };
+                let result = lease.renew(&mut **state_guard.store.lock().recover());
+                if let Err(crate::Error::Locked) = result {
+                    break;
+                }
+            }
+        });
+
+        LeaseRenewal {
+            stop: Mutex::new(stop_sender),
+        }
+    }
+}
+
+impl Drop for LeaseRenewal {
+    fn drop(&mut self) {
+        // If the thread already stopped, there is nothing to wake up.
+        self.stop.get_mut().recover().send(()).ok();
+    }
+}
@@ -14,11 +14,88 @@
  * limitations under the License.
  */
 
+use std::cmp::min;
+use std::fmt::{self, Debug, Formatter};
 use std::hash::Hash;
 use std::sync::{Arc, Weak};
+use std::time::Duration;
 
 use weak_table::WeakHashSet;
 
+/// The delay before the first retry when waiting for a lock.
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// The maximum delay between retries when waiting for a lock.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// What to do when attempting to open a repository which is already locked.
+///
+/// The default strategy is `LockStrategy::Abort`.
+#[derive(Clone, Default)]
+pub enum LockStrategy {
+    /// Fail immediately with `Error::Locked`.
+    #[default]
+    Abort,
+
+    /// Wait until the lock is released, however long that takes.
+    ///
+    /// The lock is retried with an exponential backoff.
+    Wait,
+
+    /// Wait until the lock is released or until the given duration has elapsed.
+    ///
+    /// The lock is retried with an exponential backoff. If the lock is still held once the
+    /// duration has elapsed, this fails with `Error::Locked`.
+    WaitTimeout(Duration),
+
+    /// Decide how long to wait before each retry by calling the given function.
+    ///
+    /// The function is passed the number of failed attempts so far, starting at 1. It returns how
+    /// long to wait before trying again, or `None` to give up and fail with `Error::Locked`.
+    Custom(Arc<dyn Fn(u32) -> Option<Duration> + Send + Sync>),
+}
+
+impl Debug for LockStrategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LockStrategy::Abort => f.write_str("Abort"),
+            LockStrategy::Wait => f.write_str("Wait"),
+            LockStrategy::WaitTimeout(timeout) => {
+                f.debug_tuple("WaitTimeout").field(timeout).finish()
+            }
+            LockStrategy::Custom(_) => f.debug_tuple("Custom").field(&"..").finish(),
+        }
+    }
+}
+
+impl LockStrategy {
+    /// Return how long to wait before retrying to acquire a lock.
+    ///
+    /// `attempts` is the number of failed attempts so far and `elapsed` is the time since the
+    /// first attempt. This returns `None` if we should stop trying.
+    pub(super) fn retry_delay(&self, attempts: u32, elapsed: Duration) -> Option<Duration> {
+        match self {
+            LockStrategy::Abort => None,
+            LockStrategy::Wait => Some(backoff(attempts)),
+            LockStrategy::WaitTimeout(timeout) => {
+                let remaining = timeout.checked_sub(elapsed)?;
+                if remaining.as_nanos() == 0 {
+                    None
+                } else {
+                    Some(min(backoff(attempts), remaining))
+                }
+            }
+            LockStrategy::Custom(strategy) => strategy(attempts),
+        }
+    }
+}
+
+/// Return the exponential backoff delay after the given number of failed `attempts`.
+fn backoff(attempts: u32) -> Duration {
+    let exponent = min(attempts.saturating_sub(1), 16);
+    min(INITIAL_RETRY_DELAY * 2u32.pow(exponent), MAX_RETRY_DELAY)
+}
+
 /// A lock acquired on a resource.
 ///
 /// The lock is released when this value is dropped.
@@ -37,6 +114,11 @@ impl<T: Eq + Hash> LockTable<T> {
         Self(WeakHashSet::new())
     }
 
+    /// Return whether the given `id` is currently locked.
+    pub fn is_locked(&self, id: &T) -> bool {
+        self.0.contains(id)
+    }
+
     /// Attempt to acquire a lock on the given `id`.
     ///
     /// This returns a new lock or `None` if the resource is already locked.
@@ -15,6 +15,8 @@
  */
 
 use std::collections::HashMap;
+use std::io::Read;
+use std::time::SystemTime;
 
 use rmp_serde::from_read;
 use serde::{Deserialize, Serialize};
@@ -24,6 +26,7 @@ use super::config::RepoConfig;
 use super::encryption::KeySalt;
 use super::handle::Chunk;
 use super::id_table::IdTable;
+use super::lazy_header::LazyHeader;
 use super::repository::METADATA_BLOCK_ID;
 use super::state::{ChunkInfo, InstanceInfo, PackIndex};
 use crate::store::{DataStore, OpenStore};
@@ -44,6 +47,111 @@ pub struct Header {
     pub handle_table: IdTable,
 }
 
+/// The changes made to the repository header in a single commit.
+///
+/// Rather than writing the whole header on every commit, only the entries which have changed are
+/// written. The header is periodically written in full so that the list of deltas doesn't grow
+/// without bound.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderDelta {
+    /// The chunks which have changed, or `None` if the chunk was removed.
+    pub chunks: HashMap<Chunk, Option<ChunkInfo>>,
+
+    /// The pack indices which have changed, or `None` if the block was removed.
+    pub packs: HashMap<Uuid, Option<Vec<PackIndex>>>,
+
+    /// A map of instance IDs to information about each instance.
+    pub instances: HashMap<Uuid, InstanceInfo>,
+
+    /// The table of object handle IDs.
+    pub handle_table: IdTable,
+}
+
+impl HeaderDelta {
+    /// Apply the changes in this delta to the given `header`.
+    ///
+    /// Removed entries are also recorded in `lazy_header` so that they aren't loaded from its
+    /// shards.
+    pub fn apply(self, header: &mut Header, lazy_header: &mut LazyHeader) {
+        for (chunk, chunk_info) in self.chunks {
+            lazy_header.set_chunk_removed(chunk, chunk_info.is_none());
+            match chunk_info {
+                Some(chunk_info) => header.chunks.insert(chunk, chunk_info),
+                None => header.chunks.remove(&chunk),
+            };
+        }
+        for (block_id, pack_indices) in self.packs {
+            lazy_header.set_pack_removed(block_id, pack_indices.is_none());
+            match pack_indices {
+                Some(pack_indices) => header.packs.insert(block_id, pack_indices),
+                None => header.packs.remove(&block_id),
+            };
+        }
+        header.instances = self.instances;
+        header.handle_table = self.handle_table;
+    }
+}
+
+/// Read the block with the given `block_id` from `store` and return a reader which decodes it
+/// using `decode`.
+fn read_decoded_block<S: DataStore + ?Sized>(
+    store: &mut S,
+    block_id: Uuid,
+    decode: impl Fn(Vec<u8>) -> crate::Result<Box<dyn Read>>,
+) -> crate::Result<Box<dyn Read>> {
+    let encoded_block = store
+        .read_block(block_id)
+        .map_err(crate::Error::from_store)?
+        .ok_or(crate::Error::Corrupt)?;
+    decode(encoded_block)
+}
+
+/// Read the header described by `metadata` from the given `store` without loading its shards.
+///
+/// This reads the header which was last written in full and then applies each delta which has been
+/// committed since. If the chunk and pack maps were split into shards, the returned header only
+/// contains the entries which were changed by deltas, and the rest can be loaded on demand using
+/// the returned `LazyHeader`. Each block is decoded using `decode`.
+///
+/// # Errors
+/// - `Error::Corrupt`: The header could not be read.
+/// - `Error::Store`: An error occurred with the data store.
+pub fn read_header_lazily<S: DataStore + ?Sized>(
+    store: &mut S,
+    metadata: &RepoMetadata,
+    decode: impl Fn(Vec<u8>) -> crate::Result<Box<dyn Read>>,
+) -> crate::Result<(Header, LazyHeader)> {
+    let serialized_header = read_decoded_block(store, metadata.header_id, &decode)?;
+    let mut header: Header = from_read(serialized_header).map_err(|_| crate::Error::Corrupt)?;
+    let mut lazy_header = LazyHeader::new(metadata.header_shards.clone());
+
+    for delta_id in &metadata.header_deltas {
+        let serialized_delta = read_decoded_block(store, *delta_id, &decode)?;
+        let delta: HeaderDelta = from_read(serialized_delta).map_err(|_| crate::Error::Corrupt)?;
+        delta.apply(&mut header, &mut lazy_header);
+    }
+
+    Ok((header, lazy_header))
+}
+
+/// Read the header described by `metadata` from the given `store`.
+///
+/// This is like `read_header_lazily`, except every shard of the chunk and pack maps is loaded.
+///
+/// # Errors
+/// - `Error::Corrupt`: The header could not be read.
+/// - `Error::Store`: An error occurred with the data store.
+pub fn read_header<S: DataStore + ?Sized>(
+    store: &mut S,
+    metadata: &RepoMetadata,
+    decode: impl Fn(Vec<u8>) -> crate::Result<Box<dyn Read>>,
+) -> crate::Result<Header> {
+    let (mut header, lazy_header) = read_header_lazily(store, metadata, &decode)?;
+    lazy_header.load_all(|block_id| read_decoded_block(store, block_id, &decode))?;
+    lazy_header.merge_into(&mut header.chunks, &mut header.packs);
+    Ok(header)
+}
+
 /// Metadata for a repository.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RepoMetadata {
@@ -61,6 +169,28 @@ pub struct RepoMetadata {
 
     /// The ID of the chunk which stores the repository header.
     pub header_id: Uuid,
+
+    /// A counter which is incremented each time the repository is committed.
+    ///
+    /// This is used to detect when another writer has committed changes to the repository.
+    #[serde(default)]
+    pub generation: u64,
+
+    /// The IDs of the blocks which store the header deltas committed since the header was last
+    /// written in full, in the order they were committed.
+    #[serde(default)]
+    pub header_deltas: Vec<Uuid>,
+
+    /// The IDs of the blocks which store the shards of the chunk and pack maps from the header.
+    ///
+    /// This is empty if the chunk and pack maps are stored in the header itself.
+    #[serde(default)]
+    pub header_shards: Vec<Uuid>,
+
+    /// The time at which changes were last committed to the repository, or `None` if changes have
+    /// never been committed.
+    #[serde(default)]
+    pub last_commit: Option<SystemTime>,
 }
 
 impl RepoMetadata {
@@ -78,7 +208,7 @@ pub fn peek_info_store(store: &mut impl DataStore) -> crate::Result<RepoInfo> {
     // Read and deserialize the metadata.
     let serialized_metadata = match store
         .read_block(METADATA_BLOCK_ID)
-        .map_err(crate::Error::Store)?
+        .map_err(crate::Error::from_store)?
     {
         Some(data) => data,
         None => return Err(crate::Error::NotFound),
@@ -0,0 +1,121 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use hex_literal::hex;
+use uuid::Uuid;
+
+use crate::store::DataStore;
+
+use super::metadata::RepoMetadata;
+use super::repository::VERSION_BLOCK_ID;
+
+/// The current repository format version ID.
+///
+/// This must be changed any time a backwards-incompatible change is made to the repository
+/// format, and a migration from the previous version must be added to `MIGRATIONS`.
+pub const VERSION_ID: Uuid = Uuid::from_bytes(hex!("8abd7a7f fe36 4a57 839c 54138ab07e19"));
+
+/// The format version of repositories created by version 0.7 of this library.
+const VERSION_0_7_ID: Uuid = Uuid::from_bytes(hex!("6f1c893c e6a8 11eb a198 b7fa995cc83b"));
+
+/// A step which migrates a repository from one format version to the next.
+#[derive(Debug)]
+pub struct Migration {
+    /// The format version which this migrates from.
+    from: Uuid,
+
+    /// The format version which this migrates to.
+    to: Uuid,
+
+    /// Whether a repository in the format `from` can be read without migrating it.
+    ///
+    /// Repositories which are opened in read-only mode can't be migrated.
+    read_compatible: bool,
+
+    /// Migrate the repository in the given data store and return its new metadata.
+    ///
+    /// This is passed the current metadata of the repository, and migrations which change it must
+    /// write it to the data store. Migrations must be idempotent,
+    /// because a migration which is interrupted is run again the next time the repository is
+    /// opened.
+    migrate: fn(&mut dyn DataStore, RepoMetadata) -> crate::Result<RepoMetadata>,
+}
+
+/// The migrations from previous format versions.
+static MIGRATIONS: &[Migration] = &[Migration {
+    from: VERSION_0_7_ID,
+    to: VERSION_ID,
+    read_compatible: true,
+    migrate: migrate_from_0_7,
+}];
+
+/// Migrate a repository created by version 0.7 of this library.
+///
+/// Every field which has been added to the metadata and header since has a default, so there's
+/// nothing to rewrite. The format version is changed because older versions of this library don't
+/// know about header deltas, commit markers, or parity blocks, and would lose data if they wrote to
+/// a repository which uses them.
+fn migrate_from_0_7(
+    _store: &mut dyn DataStore,
+    metadata: RepoMetadata,
+) -> crate::Result<RepoMetadata> {
+    Ok(metadata)
+}
+
+/// Return the migrations which need to be applied in order to a repository in the format
+/// `version`, or `None` if the format is not supported.
+///
+/// This returns an empty list if the repository is already in the current format.
+pub fn migrations_from(version: Uuid) -> Option<Vec<&'static Migration>> {
+    let mut migrations = Vec::new();
+    let mut current_version = version;
+    while current_version != VERSION_ID {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.from == current_version)?;
+        migrations.push(migration);
+        current_version = migration.to;
+    }
+    Some(migrations)
+}
+
+/// Return whether a repository which needs the given `migrations` can be read without them.
+pub fn is_read_compatible(migrations: &[&Migration]) -> bool {
+    migrations.iter().all(|migration| migration.read_compatible)
+}
+
+/// Apply the given `migrations` to the repository in `store` and return its new metadata.
+///
+/// `metadata` is the current metadata of the repository. The format version is updated after each
+/// step, so if this is interrupted, the remaining steps are applied the next time the repository
+/// is opened.
+///
+/// # Errors
+/// - `Error::Corrupt`: The repository is corrupt.
+/// - `Error::Store`: An error occurred with the data store.
+pub fn migrate(
+    store: &mut dyn DataStore,
+    mut metadata: RepoMetadata,
+    migrations: &[&Migration],
+) -> crate::Result<RepoMetadata> {
+    for migration in migrations {
+        metadata = (migration.migrate)(store, metadata)?;
+        store
+            .write_block(VERSION_BLOCK_ID, migration.to.as_bytes())
+            .map_err(crate::Error::from_store)?;
+    }
+    Ok(metadata)
+}
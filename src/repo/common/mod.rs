@@ -14,38 +14,84 @@
  * limitations under the License.
  */
 
+#[cfg(feature = "async")]
+pub use self::async_repo::{AsyncObject, AsyncRepo, AsyncStream};
+pub use self::audit_log::{AuditEntry, AuditEvent};
+#[cfg(feature = "benchmark")]
+pub use self::benchmark::{benchmark, BenchmarkReport};
 pub use self::chunking::Chunking;
+pub(crate) use self::chunking::{prepare_chunks, PreparedChunk};
+pub use self::clean_report::CleanReport;
 pub use self::commit::Commit;
 pub use self::compression::Compression;
 pub use self::config::RepoConfig;
 pub use self::encryption::{Encryption, ResourceLimit};
 pub use self::handle::{ContentId, ObjectId};
+pub use self::health::{HealthReport, LockState};
+pub use self::hooks::TransactionEvent;
 pub use self::id_table::{IdTable, UniqueId};
+pub use self::inspect::ChunkReport;
 pub use self::key::Key;
+pub use self::lock::LockStrategy;
 pub use self::metadata::{peek_info, RepoInfo};
 pub use self::object::{Object, ReadOnlyObject};
 pub use self::open_options::{OpenMode, OpenOptions, DEFAULT_INSTANCE};
-pub use self::open_repo::{OpenRepo, SwitchInstance};
+pub use self::open_repo::{OpenRepo, SwitchBranch, SwitchInstance, DEFAULT_BRANCH};
 pub use self::packing::Packing;
+pub use self::parity::Parity;
+pub(crate) use self::poison::RecoverPoison;
+pub use self::progress::{CancellationToken, Operation, Progress};
+pub use self::repair_report::RepairReport;
 pub use self::repository::KeyRepo;
+pub(crate) use self::repository::KeyRepoInner;
+pub use self::retry::RetryPolicy;
 pub use self::savepoint::{Restore, RestoreSavepoint, Savepoint};
+pub use self::stats::{OperationStats, RepoStats};
 
+mod archive;
+mod async_repo;
+mod audit_log;
+mod background_clean;
+mod benchmark;
+mod cache;
+mod checksum;
 mod chunk_store;
 mod chunking;
+mod clean_report;
 mod commit;
+mod commit_marker;
 mod compression;
 mod config;
 mod encryption;
 mod handle;
+mod health;
+mod hooks;
 mod id_table;
+mod inspect;
 mod key;
+mod lazy_header;
+mod lease;
+mod lease_renewal;
 mod lock;
 mod metadata;
+mod migration;
 mod object;
+mod object_map;
 mod object_store;
 mod open_options;
 mod open_repo;
 mod packing;
+mod parallel;
+mod parity;
+mod poison;
+mod progress;
+mod repair_report;
 mod repository;
+mod retry;
 mod savepoint;
 mod state;
+mod stats;
+mod store_pool;
+#[cfg(feature = "metrics")]
+mod telemetry;
+mod verify;
@@ -19,11 +19,14 @@ use std::fmt::Debug;
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::sync::{Arc, RwLock, Weak};
 
+use bytes::Bytes;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
+use super::chunking::PreparedChunk;
 use super::handle::{ContentId, ObjectHandle, ObjectId};
 use super::object_store::ObjectStore;
+use super::poison::RecoverPoison;
 use super::state::{ObjectState, RepoState};
 
 /// A read-write view of data in a repository.
@@ -43,7 +46,8 @@ use super::state::{ObjectState, RepoState};
 /// instance already has a transaction in progress will return [`Error::TransactionInProgress`].
 /// Additionally, changes to an object are not visible to other instances until the transaction is
 /// committed. You can use [`object_id`] to determine if two `Object` or [`ReadOnlyObject`]
-/// instances refer to the same underlying object.
+/// instances refer to the same underlying object. To serve many concurrent reads of the same
+/// object, use [`ReadOnlyObject::try_clone`] to create a separate reader for each one.
 ///
 /// An object can be invalidated, in which case methods of `Object` and [`ReadOnlyObject`] will
 /// return [`Error::InvalidObject`]. An object is invalidated when:
@@ -56,7 +60,8 @@ use super::state::{ObjectState, RepoState};
 /// You can use [`is_valid`] to determine whether an object has been invalidated.
 ///
 /// Because `Object` internally buffers data when reading, there's no need to use a buffered reader
-/// like `BufReader`.
+/// like `BufReader`. To avoid copying data into a buffer of your own, use [`read_bytes`] to get a
+/// shared view of the buffered data instead.
 ///
 /// If encryption is enabled for the repository, data integrity is automatically verified as it is
 /// read and methods will return an [`Error::InvalidData`] if corrupt data is found. The [`verify`]
@@ -70,11 +75,13 @@ use super::state::{ObjectState, RepoState};
 /// [`Commit::clean`]: crate::repo::Commit::clean
 /// [`Error::TransactionInProgress`]: crate::Error::TransactionInProgress
 /// [`ReadOnlyObject`]: crate::repo::ReadOnlyObject
+/// [`ReadOnlyObject::try_clone`]: crate::repo::ReadOnlyObject::try_clone
 /// [`object_id`]: crate::repo::Object::object_id
 /// [`Error::InvalidObject`]: crate::Error::InvalidObject
 /// [`is_valid`]: crate::repo::Object::is_valid
 /// [`Error::InvalidData`]: crate::Error::InvalidData
 /// [`verify`]: crate::repo::Object::verify
+/// [`read_bytes`]: crate::repo::Object::read_bytes
 #[derive(Debug)]
 pub struct Object {
     /// The state for the object repository.
@@ -99,8 +106,8 @@ impl Object {
         handle: &Arc<RwLock<ObjectHandle>>,
         object_id: ObjectId,
     ) -> Self {
-        let metadata = &repo_state.read().unwrap().metadata;
-        let object_state = ObjectState::new(metadata.config.chunking.to_chunker());
+        let metadata = &repo_state.read().recover().metadata;
+        let object_state = ObjectState::new(metadata.config.to_chunker());
         Self {
             repo_state: Arc::downgrade(repo_state),
             handle: Arc::downgrade(handle),
@@ -184,6 +191,28 @@ impl Object {
             .set_len(size)
     }
 
+    /// Replace `len` bytes of the object starting at `offset` with a hole.
+    ///
+    /// The bytes in the range are replaced with null bytes which use no space in the backing data
+    /// store, like with a sparse file. This does not change the size of the object; any part of
+    /// the range past the end of the object is ignored. Chunks which are only partially in the
+    /// range need to be read and written again without the part in the range.
+    ///
+    /// This method starts a new transaction and commits the transaction before it returns.
+    ///
+    /// # Errors
+    /// - `Error::TransactionInProgress`: A transaction is currently in progress for this object.
+    /// - `Error::InvalidObject`: The object has been invalidated.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn punch_hole(&mut self, offset: u64, len: u64) -> crate::Result<()> {
+        ObjectStore::new(&self.repo_state, &self.handle)?
+            .writer_guard(&mut self.object_state)
+            .writer()
+            .punch_hole(offset, len)
+    }
+
     /// Serialize the given `value` and write it to the object.
     ///
     /// This is a convenience function that serializes the `value` using a space-efficient binary
@@ -225,6 +254,31 @@ impl Object {
             .deserialize()
     }
 
+    /// Read up to `size` bytes from the current seek position without copying them.
+    ///
+    /// This is like `Read::read`, except that instead of copying data into a buffer you provide,
+    /// it returns a reference-counted [`Bytes`] which shares memory with the chunk the data was
+    /// read from. The seek position is advanced by the number of bytes returned.
+    ///
+    /// The returned buffer never spans more than one chunk, so it may contain fewer than `size`
+    /// bytes even if the end of the object hasn't been reached. If the returned buffer is empty,
+    /// the end of the object has been reached.
+    ///
+    /// # Errors
+    /// - `Error::TransactionInProgress`: A transaction is currently in progress for this object.
+    /// - `Error::InvalidObject`: The object has been invalidated.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`Bytes`]: bytes::Bytes
+    pub fn read_bytes(&mut self, size: usize) -> crate::Result<Bytes> {
+        ObjectStore::new(&self.repo_state, &self.handle)?
+            .reader_guard(&mut self.object_state)
+            .reader()
+            .read_bytes(size)
+    }
+
     /// Commit changes to this object to the repository.
     ///
     /// Data written to this object via `Write` is not persisted to the repository or visible to
@@ -239,6 +293,7 @@ impl Object {
     ///
     /// # Errors
     /// - `Error::InvalidObject`: The object has been invalidated.
+    /// - `Error::ReadOnly`: The repository was opened in read-only mode.
     /// - `Error::InvalidData`: Ciphertext verification failed.
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
@@ -256,6 +311,19 @@ impl Object {
     pub fn is_valid(&self) -> bool {
         ObjectStore::new(&self.repo_state, &self.handle).is_ok()
     }
+
+    /// Append a chunk which was prepared in advance to the end of this object.
+    ///
+    /// This starts a transaction which must be completed by calling [`commit`]. This can't be mixed
+    /// with writing via `Write` in the same transaction.
+    ///
+    /// [`commit`]: crate::repo::Object::commit
+    pub(crate) fn append_prepared(&mut self, prepared: PreparedChunk) -> crate::Result<()> {
+        ObjectStore::new(&self.repo_state, &self.handle)?
+            .writer_guard(&mut self.object_state)
+            .writer()
+            .append_prepared(prepared)
+    }
 }
 
 impl Read for Object {
@@ -347,10 +415,42 @@ impl ReadOnlyObject {
         self.0.deserialize()
     }
 
+    /// Read up to `size` bytes from the current seek position without copying them.
+    ///
+    /// See [`Object::read_bytes`] for details.
+    ///
+    /// [`Object::read_bytes`]: crate::repo::Object::read_bytes
+    pub fn read_bytes(&mut self, size: usize) -> crate::Result<Bytes> {
+        self.0.read_bytes(size)
+    }
+
     /// Return whether this object is valid.
     pub fn is_valid(&self) -> bool {
         self.0.is_valid()
     }
+
+    /// Return a new `ReadOnlyObject` which reads from the same underlying object.
+    ///
+    /// The new instance starts at the same seek position as this one, but it has its own seek
+    /// position and read buffer, so the two instances can be read independently. Because readers
+    /// only need shared access to the object, any number of them can be read concurrently from
+    /// different threads, even while an [`Object`] has a transaction in progress.
+    ///
+    /// # Errors
+    /// - `Error::InvalidObject`: The object has been invalidated.
+    ///
+    /// [`Object`]: crate::repo::Object
+    pub fn try_clone(&self) -> crate::Result<Self> {
+        let repo_state = self
+            .0
+            .repo_state
+            .upgrade()
+            .ok_or(crate::Error::InvalidObject)?;
+        let handle = self.0.handle.upgrade().ok_or(crate::Error::InvalidObject)?;
+        let mut object = Object::new(&repo_state, &handle, self.0.object_id);
+        object.object_state.position = self.0.object_state.position;
+        Ok(ReadOnlyObject(object))
+    }
 }
 
 impl TryFrom<Object> for ReadOnlyObject {
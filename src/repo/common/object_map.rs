@@ -0,0 +1,118 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use rmp_serde::to_vec;
+
+use super::handle::{Extent, ObjectHandle};
+use super::id_table::UniqueId;
+use super::key::Key;
+use super::object_store::{ObjectReader, ObjectWriter};
+
+/// A map of keys to object handles in the form it is serialized in.
+///
+/// Each distinct list of extents is stored once, and each key is mapped to the ID of its handle and
+/// the index of its list of extents. This keeps the object map small when many objects have the
+/// same contents.
+///
+/// This is a tuple rather than a struct so that it can't be confused with the plain map of keys to
+/// handles that object maps used to be serialized as.
+type SerializedObjectMap<K> = (Vec<Arc<Vec<Extent>>>, HashMap<K, (UniqueId, u32)>);
+
+/// Make handles in `handles` which have identical extents share the same list of extents.
+///
+/// The extents are copied on write, so changing one of the handles doesn't affect the others.
+pub fn share_extents<'a>(handles: impl IntoIterator<Item = &'a mut ObjectHandle>) {
+    let mut distinct_extents = HashSet::new();
+    for handle in handles {
+        match distinct_extents.get(&handle.extents) {
+            Some(extents) => handle.extents = Arc::clone(extents),
+            None => {
+                distinct_extents.insert(Arc::clone(&handle.extents));
+            }
+        }
+    }
+}
+
+/// Replace the contents of the object written by `writer` with the given map of `objects`.
+pub fn write_object_map<'a, K: Key + 'a>(
+    writer: &mut ObjectWriter,
+    objects: impl IntoIterator<Item = (&'a K, &'a ObjectHandle)>,
+) -> crate::Result<()> {
+    writer.write_serialized(serialize_object_map(objects)?.as_slice())
+}
+
+/// Serialize the given map of `objects`, storing each distinct list of extents once.
+pub fn serialize_object_map<'a, K: Key + 'a>(
+    objects: impl IntoIterator<Item = (&'a K, &'a ObjectHandle)>,
+) -> crate::Result<Vec<u8>> {
+    let mut extents = Vec::new();
+    let mut extent_indices = HashMap::new();
+    let mut handles = HashMap::new();
+
+    for (key, handle) in objects {
+        let index = *extent_indices
+            .entry(handle.extents.as_slice())
+            .or_insert_with(|| {
+                extents.push(Arc::clone(&handle.extents));
+                extents.len() as u32 - 1
+            });
+        handles.insert(key, (handle.id, index));
+    }
+
+    to_vec(&(extents, handles)).map_err(|_| crate::Error::Serialize)
+}
+
+/// Read the map of objects stored in the object read by `reader`.
+///
+/// Handles in the returned map which have identical extents share them.
+pub fn read_object_map<K: Key>(
+    reader: &mut ObjectReader,
+) -> crate::Result<HashMap<K, ObjectHandle>> {
+    if let Ok((extents, handles)) = reader.deserialize::<SerializedObjectMap<K>>() {
+        return handles
+            .into_iter()
+            .map(|(key, (id, index))| {
+                let extents = extents
+                    .get(index as usize)
+                    .ok_or(crate::Error::Deserialize)?;
+                let handle = ObjectHandle {
+                    id,
+                    extents: Arc::clone(extents),
+                };
+                Ok((key, handle))
+            })
+            .collect();
+    }
+
+    // Object maps which were written before extents were shared are serialized as a plain map of
+    // keys to handles.
+    let mut objects: HashMap<K, ObjectHandle> = reader.deserialize()?;
+    share_extents(objects.values_mut());
+    Ok(objects)
+}
+
+/// Wrap each of the handles in the given map of `objects` so they can be shared with objects.
+pub fn into_shared<K: Key>(
+    objects: HashMap<K, ObjectHandle>,
+) -> HashMap<K, Arc<RwLock<ObjectHandle>>> {
+    objects
+        .into_iter()
+        .map(|(key, handle)| (key, Arc::new(RwLock::new(handle))))
+        .collect()
+}
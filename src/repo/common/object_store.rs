@@ -14,18 +14,29 @@
  * limitations under the License.
  */
 
-use std::cmp::{min, Ordering};
+use std::cmp::{max, min, Ordering};
 use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
 
+use bytes::Bytes;
 use rmp_serde::{from_read, to_vec};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 use super::chunk_store::{ReadChunk, StoreReader, StoreWriter, WriteChunk};
-use super::handle::{chunk_hash, ContentId, ObjectHandle};
-use super::state::{ExtentLocation, ObjectState, RepoState, SeekPosition};
-use crate::repo::common::handle::Extent;
+use super::chunking::{IncrementalChunker, PreparedChunk};
+use super::handle::{ContentId, ObjectHandle};
+use super::parallel::map_parallel;
+use super::poison::RecoverPoison;
+use super::state::{
+    ExtentLocation, ObjectState, RepoState, SeekPosition, StateWriteGuard, WriteState,
+};
+use super::verify::chunk_is_intact;
+use crate::repo::common::handle::{Chunk, Extent};
+
+/// The minimum number of bytes to read at once when reading part of a chunk.
+const MIN_RANGE_READ_SIZE: usize = 64 * 1024;
 
 pub struct ObjectStore {
     repo_state: Arc<RwLock<RepoState>>,
@@ -45,24 +56,24 @@ impl ObjectStore {
 
     pub fn info_guard<'a>(&'a self, object_state: &'a ObjectState) -> ObjectInfoGuard<'a> {
         ObjectInfoGuard {
-            repo_state: self.repo_state.read().unwrap(),
-            handle: self.handle.read().unwrap(),
+            repo_state: self.repo_state.read().recover(),
+            handle: self.handle.read().recover(),
             object_state,
         }
     }
 
     pub fn reader_guard<'a>(&'a self, object_state: &'a mut ObjectState) -> ObjectReaderGuard<'a> {
         ObjectReaderGuard {
-            repo_state: self.repo_state.read().unwrap(),
-            handle: self.handle.read().unwrap(),
+            repo_state: self.repo_state.read().recover(),
+            handle: self.handle.read().recover(),
             object_state,
         }
     }
 
     pub fn writer_guard<'a>(&'a self, object_state: &'a mut ObjectState) -> ObjectWriterGuard<'a> {
         ObjectWriterGuard {
-            repo_state: self.repo_state.write().unwrap(),
-            handle: self.handle.write().unwrap(),
+            repo_state: self.repo_state.write_state(),
+            handle: self.handle.write().recover(),
             object_state,
         }
     }
@@ -97,7 +108,7 @@ impl<'a> ObjectReaderGuard<'a> {
 }
 
 pub struct ObjectWriterGuard<'a> {
-    repo_state: RwLockWriteGuard<'a, RepoState>,
+    repo_state: StateWriteGuard<'a>,
     handle: RwLockWriteGuard<'a, ObjectHandle>,
     object_state: &'a mut ObjectState,
 }
@@ -155,7 +166,7 @@ impl<'a> ObjectInfo<'a> {
         }
         Ok(ContentId {
             repo_id: self.repo_state.metadata.id,
-            extents: self.handle.extents.clone(),
+            extents: self.handle.extents.to_vec(),
         })
     }
 }
@@ -193,16 +204,21 @@ impl<'a> ObjectReader<'a> {
 
         let expected_chunks = self.handle.chunks().collect::<Vec<_>>();
 
-        for chunk in expected_chunks {
-            match self.store_reader().read_chunk(chunk) {
-                Ok(data) => {
-                    if data.len() != chunk.size as usize || chunk_hash(&data) != chunk.hash {
-                        return Ok(false);
-                    }
+        // Chunks are read one batch at a time so that the chunks in each batch can be hashed in
+        // parallel if the repository has a rayon thread pool.
+        for batch in expected_chunks.chunks(self.repo_state.threads) {
+            let mut batch_data = Vec::with_capacity(batch.len());
+            for chunk in batch {
+                batch_data.push((*chunk, self.store_reader().read_chunk(*chunk)));
+            }
+
+            let results = map_parallel(self.repo_state, batch_data, |(chunk, data)| {
+                chunk_is_intact(chunk, data)
+            });
+            for result in results {
+                if !result? {
+                    return Ok(false);
                 }
-                // Ciphertext verification failed. No need to check the hash.
-                Err(crate::Error::InvalidData) => return Ok(false),
-                Err(error) => return Err(error),
             }
         }
 
@@ -235,36 +251,99 @@ impl<'a> ObjectReader<'a> {
         SeekPosition::End
     }
 
-    /// Return a slice of null bytes of the given `size`.
-    fn read_hole(&mut self, size: usize) -> &[u8] {
+    /// Start reading the chunks after `location` in the background if reading is sequential.
+    ///
+    /// This is called before reading the chunk at `location`, which is prefetched as well so that
+    /// it isn't queued behind the chunks after it. If the object isn't being read sequentially,
+    /// any prefetched chunks are discarded.
+    fn prefetch(&mut self, location: &ExtentLocation) {
+        let concurrency = match &self.repo_state.pool {
+            Some(pool) => pool.concurrency(),
+            None => return,
+        };
+
+        let sequential = location.relative_position() == 0
+            && match self.object_state.buffered_index {
+                Some(index) => location.index > index,
+                None => true,
+            };
+        if !sequential {
+            self.store_reader().clear_prefetched();
+            return;
+        }
+
+        let chunks = self.handle.extents[location.index..]
+            .iter()
+            .filter_map(|extent| match extent {
+                Extent::Chunk(chunk) => Some(*chunk),
+                Extent::Hole { .. } => None,
+            })
+            .take(concurrency + 1)
+            .collect::<Vec<_>>();
+        self.store_reader().prefetch_chunks(&chunks);
+    }
+
+    /// Return a buffer of null bytes of the given `size`.
+    fn read_hole(&mut self, size: usize) -> Bytes {
         if self.object_state.hole_buffer.len() < size {
-            self.object_state.hole_buffer.resize(size, 0);
+            self.object_state.hole_buffer = Bytes::from(vec![0u8; size]);
+        }
+        self.object_state.hole_buffer.slice(..size)
+    }
+
+    /// Read the part of `chunk` starting at `offset` into the read buffer.
+    ///
+    /// When reading a small amount of data from the middle of a large chunk, only that part of the
+    /// chunk is read from the data store if possible. Otherwise, the whole chunk is read.
+    fn buffer_chunk(&mut self, chunk: Chunk, offset: u64, size: usize) -> crate::Result<()> {
+        let range_size = max(size, MIN_RANGE_READ_SIZE) as u64;
+        if offset != 0 && range_size * 4 <= chunk.size as u64 {
+            if let Some(data) = self
+                .store_reader()
+                .read_chunk_range(chunk, offset, range_size)?
+            {
+                self.object_state.read_buffer = data;
+                self.object_state.buffered_offset = offset;
+                return Ok(());
+            }
         }
-        &self.object_state.hole_buffer[..size]
+
+        self.object_state.read_buffer = self.store_reader().read_cached_chunk(chunk)?;
+        self.object_state.buffered_offset = 0;
+        Ok(())
     }
 
-    /// Return the slice of bytes between the current seek position and the end of the extent.
+    /// Return the bytes between the current seek position and the end of the extent.
     ///
-    /// The returned slice will be no longer than `size`.
-    fn read_extent(&mut self, size: usize) -> crate::Result<&[u8]> {
+    /// The returned buffer will be no longer than `size`.
+    fn read_extent(&mut self, size: usize) -> crate::Result<Bytes> {
         // If the object is empty or we're at the end of the object, there's no data to read.
         let current_location = match self.current_position() {
-            SeekPosition::Empty | SeekPosition::End => return Ok(&[]),
+            SeekPosition::Empty | SeekPosition::End => return Ok(Bytes::new()),
             SeekPosition::Extent(location) => location,
         };
 
         match current_location.extent {
             Extent::Chunk(chunk) => {
-                // If we're reading from a new chunk, read the contents of that chunk into the read
-                // buffer.
-                if Some(chunk) != self.object_state.buffered_chunk {
+                // If the data we're reading isn't in the read buffer, read the contents of the
+                // chunk into the read buffer.
+                let position = current_location.relative_position();
+                let buffer_end =
+                    self.object_state.buffered_offset + self.object_state.read_buffer.len() as u64;
+                let is_buffered = Some(chunk) == self.object_state.buffered_chunk
+                    && position >= self.object_state.buffered_offset
+                    && position < buffer_end;
+                if !is_buffered {
+                    self.prefetch(&current_location);
+                    self.object_state.buffered_chunk = None;
+                    self.buffer_chunk(chunk, position, size)?;
                     self.object_state.buffered_chunk = Some(chunk);
-                    self.object_state.read_buffer = self.store_reader().read_chunk(chunk)?;
+                    self.object_state.buffered_index = Some(current_location.index);
                 }
 
-                let start = current_location.relative_position() as usize;
-                let end = min(start + size, chunk.size as usize);
-                Ok(&self.object_state.read_buffer[start..end])
+                let start = (position - self.object_state.buffered_offset) as usize;
+                let end = min(start + size, self.object_state.read_buffer.len());
+                Ok(self.object_state.read_buffer.slice(start..end))
             }
             Extent::Hole { size: hole_size } => {
                 let read_size = min(
@@ -276,6 +355,21 @@ impl<'a> ObjectReader<'a> {
         }
     }
 
+    /// Read up to `size` bytes from the current seek position into a shared buffer.
+    ///
+    /// The returned buffer shares its memory with the chunk it was read from, so no data is
+    /// copied. It never spans more than one chunk, so it may be shorter than `size` even if the end
+    /// of the object hasn't been reached. An empty buffer means the end of the object.
+    pub fn read_bytes(&mut self, size: usize) -> crate::Result<Bytes> {
+        if self.object_state.transaction_lock.is_some() {
+            return Err(crate::Error::TransactionInProgress);
+        }
+
+        let bytes = self.read_extent(size)?;
+        self.object_state.position += bytes.len() as u64;
+        Ok(bytes)
+    }
+
     /// Deserialize a value serialized with `ObjectWriter::serialize`.
     pub fn deserialize<T: DeserializeOwned>(&mut self) -> crate::Result<T> {
         self.seek(SeekFrom::Start(0))?;
@@ -327,14 +421,9 @@ impl<'a> Seek for ObjectReader<'a> {
 // recently read from is cached in a buffer.
 impl<'a> Read for ObjectReader<'a> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.object_state.transaction_lock.is_some() {
-            return Err(crate::Error::TransactionInProgress.into());
-        }
-
-        let next_chunk = self.read_extent(buf.len())?;
+        let next_chunk = self.read_bytes(buf.len())?;
         let bytes_read = next_chunk.len();
-        buf[..bytes_read].copy_from_slice(next_chunk);
-        self.object_state.position += bytes_read as u64;
+        buf[..bytes_read].copy_from_slice(&next_chunk);
         Ok(bytes_read)
     }
 }
@@ -403,10 +492,11 @@ impl<'a> ObjectWriter<'a> {
         };
 
         // Remove all extents including and after the final chunk.
-        self.handle.extents.drain(end_location.index..);
+        let extents = Arc::make_mut(&mut self.handle.extents);
+        extents.drain(end_location.index..);
 
         // Append the new final extent which has been sliced.
-        self.handle.extents.push(new_last_extent);
+        extents.push(new_last_extent);
 
         // Restore the seek position.
         self.object_state.position = min(original_position, size);
@@ -426,7 +516,7 @@ impl<'a> ObjectWriter<'a> {
         let hole = Extent::Hole {
             size: size - self.handle.size(),
         };
-        self.handle.extents.push(hole);
+        Arc::make_mut(&mut self.handle.extents).push(hole);
     }
 
     /// Set the length of the object.
@@ -453,21 +543,184 @@ impl<'a> ObjectWriter<'a> {
         Ok(())
     }
 
+    /// Return the part of `extent` in the given `range`, relative to the start of the extent.
+    fn slice_extent(&mut self, extent: Extent, range: Range<u64>) -> crate::Result<Extent> {
+        match extent {
+            // We can't edit chunks in-place, so we need to read the chunk, slice it, and write it
+            // back.
+            Extent::Chunk(chunk) => {
+                let chunk_data = self.store_writer().read_chunk(chunk)?;
+                let handle_id = self.handle.id;
+                Ok(Extent::Chunk(self.store_writer().write_chunk(
+                    &chunk_data[range.start as usize..range.end as usize],
+                    handle_id,
+                )?))
+            }
+            Extent::Hole { .. } => Ok(Extent::Hole {
+                size: range.end - range.start,
+            }),
+        }
+    }
+
+    /// Replace the bytes in the given `range` of the object with a hole.
+    fn replace_with_hole(&mut self, range: Range<u64>) -> crate::Result<()> {
+        let old_extents = Arc::clone(&self.handle.extents);
+        let mut new_extents = Vec::with_capacity(old_extents.len() + 2);
+        let mut position = 0;
+
+        for extent in old_extents.iter() {
+            let extent_end = position + extent.size();
+
+            if extent_end <= range.start || position >= range.end {
+                new_extents.push(*extent);
+            } else {
+                // Keep the parts of the extent which are outside the range.
+                if position < range.start {
+                    new_extents.push(self.slice_extent(*extent, 0..range.start - position)?);
+                }
+                if position <= range.start {
+                    new_extents.push(Extent::Hole {
+                        size: range.end - range.start,
+                    });
+                }
+                if extent_end > range.end {
+                    new_extents
+                        .push(self.slice_extent(*extent, range.end - position..extent.size())?);
+                }
+            }
+
+            position = extent_end;
+        }
+
+        self.handle.extents = Arc::new(new_extents);
+
+        // The offsets of the chunks in the object may have changed.
+        self.object_state.buffered_chunk = None;
+
+        Ok(())
+    }
+
+    /// Replace `len` bytes of the object starting at `offset` with a hole.
+    pub fn punch_hole(&mut self, offset: u64, len: u64) -> crate::Result<()> {
+        // Because this modifies the object, we need to start a new transaction.
+        match self.object_state.transaction_lock {
+            None => match self.repo_state.transactions.acquire_lock(self.handle.id) {
+                None => return Err(crate::Error::TransactionInProgress),
+                Some(lock) => {
+                    self.object_state.transaction_lock = Some(lock);
+                }
+            },
+            Some(_) => return Err(crate::Error::TransactionInProgress),
+        }
+
+        // The hole can't extend past the end of the object.
+        let size = self.handle.size();
+        let start = min(offset, size);
+        let end = min(offset.saturating_add(len), size);
+
+        let result = if start < end {
+            self.replace_with_hole(start..end)
+        } else {
+            Ok(())
+        };
+
+        self.object_state.transaction_lock = None;
+
+        result
+    }
+
     /// Write chunks stored in the chunker to the repository.
+    ///
+    /// If any of the chunks could not be written, the current transaction is discarded.
     fn write_chunks(&mut self) -> crate::Result<()> {
-        for chunk_data in self.object_state.chunker.chunks() {
-            let handle_id = self.handle.id;
-            let chunk = self.store_writer().write_chunk(&chunk_data, handle_id)?;
-            self.object_state.new_chunks.push(chunk);
+        let chunk_data = self.object_state.chunker.chunks();
+        let handle_id = self.handle.id;
+        match self.store_writer().upload_chunks(chunk_data, handle_id) {
+            Ok(chunks) => {
+                self.object_state.new_chunks.extend(chunks);
+                Ok(())
+            }
+            Err(error) => {
+                self.discard_transaction();
+                Err(error)
+            }
         }
+    }
+
+    /// Wait for chunks which are being written in the background and add them to the repository.
+    ///
+    /// If any of the chunks could not be written, the current transaction is discarded.
+    fn finish_uploads(&mut self) -> crate::Result<()> {
+        let result = self.store_writer().finish_uploads();
+        if result.is_err() {
+            self.discard_transaction();
+        }
+        result
+    }
+
+    /// Discard the data written in the current transaction and release the transaction lock.
+    fn discard_transaction(&mut self) {
+        self.store_writer().discard_uploads();
+        self.object_state.chunker =
+            IncrementalChunker::new(self.repo_state.metadata.config.to_chunker());
+        self.object_state.new_chunks.clear();
+        self.object_state.transaction_lock = None;
+    }
+
+    /// Append a chunk which was prepared in advance to the end of the object.
+    ///
+    /// This starts a transaction like `Write::write` does, and the transaction must be committed
+    /// with `commit`. This can't be mixed with `Write::write` in the same transaction.
+    pub fn append_prepared(&mut self, prepared: PreparedChunk) -> crate::Result<()> {
+        match self.object_state.transaction_lock {
+            None => match self.repo_state.transactions.acquire_lock(self.handle.id) {
+                None => return Err(crate::Error::TransactionInProgress),
+                Some(lock) => {
+                    self.object_state.transaction_lock = Some(lock);
+
+                    // Prepared chunks are always appended to the end of the object.
+                    self.object_state.position = self.handle.size();
+                    self.object_state.start_position = self.object_reader().current_position();
+                }
+            },
+            Some(_) => {
+                // If there is data buffered in the chunker, this transaction was started by
+                // `Write::write`.
+                if !self.object_state.chunker.is_empty() {
+                    return Err(crate::Error::TransactionInProgress);
+                }
+            }
+        }
+
+        let handle_id = self.handle.id;
+        let result =
+            self.store_writer()
+                .upload_hashed_chunk(prepared.chunk, &prepared.data, handle_id);
+        let chunk = match result {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                self.discard_transaction();
+                return Err(error);
+            }
+        };
+        self.object_state.new_chunks.push(chunk);
+        self.object_state.position += prepared.size();
+
         Ok(())
     }
 
     /// Serialize the given `value` and write it to the object.
     pub fn serialize<T: Serialize>(&mut self, value: &T) -> crate::Result<()> {
         let serialized = to_vec(value).map_err(|_| crate::Error::Serialize)?;
+        self.write_serialized(serialized.as_slice())
+    }
+
+    /// Replace the contents of the object with `serialized` data and commit it.
+    ///
+    /// This is like `serialize`, except the value has already been serialized.
+    pub fn write_serialized(&mut self, serialized: &[u8]) -> crate::Result<()> {
         self.seek(SeekFrom::Start(0))?;
-        self.write_all(serialized.as_slice())?;
+        self.write_all(serialized)?;
         self.commit()?;
         self.set_len(serialized.len() as u64)?;
         Ok(())
@@ -517,6 +770,7 @@ impl<'a> ObjectWriter<'a> {
         // Write all the remaining data in the chunker to the repository.
         self.object_state.chunker.flush()?;
         self.write_chunks()?;
+        self.finish_uploads()?;
 
         // Find the index of the first extent which is being overwritten.
         let start_index = match &self.object_state.start_position {
@@ -548,10 +802,9 @@ impl<'a> ObjectWriter<'a> {
             new_extents.push(Extent::Hole { size: hole_size });
         }
 
-        // Update extent references in the object handle to reflect changes.
-        self.handle
-            .extents
-            .splice(start_index..end_index, new_extents);
+        // Update extent references in the object handle to reflect changes. If the extents are
+        // shared with other handles, this copies them first.
+        Arc::make_mut(&mut self.handle.extents).splice(start_index..end_index, new_extents);
 
         // Release the current transaction.
         self.object_state.transaction_lock = None;
@@ -597,9 +850,23 @@ impl<'a> Write for ObjectWriter<'a> {
             }
         }
 
-        // Chunk the data and write any complete chunks to the repository.
-        self.object_state.chunker.write_all(buf)?;
-        self.write_chunks()?;
+        // Chunk the data and write complete chunks to the repository once enough are buffered. A
+        // large `buf` is chunked in pieces so that it isn't all copied into the chunker at once.
+        let piece_size = match self.repo_state.metadata.config.max_chunk_size {
+            0 => buf.len().max(1),
+            max_size => max_size as usize,
+        };
+        let write_buffer_size = self.repo_state.metadata.config.write_buffer_size as usize;
+        for piece in buf.chunks(piece_size) {
+            let chunker = &mut self.object_state.chunker;
+            self.repo_state
+                .stats
+                .chunking
+                .time(|| chunker.write_all(piece), |_| piece.len() as u64)?;
+            if self.object_state.chunker.chunked_size() >= write_buffer_size {
+                self.write_chunks()?;
+            }
+        }
 
         // Advance the seek position.
         self.object_state.position += buf.len() as u64;
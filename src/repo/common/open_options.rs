@@ -15,7 +15,12 @@
  */
 
 use std::collections::HashMap;
+#[cfg(feature = "rayon")]
+use std::io;
+use std::io::Read;
 use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Instant;
 
 use hex_literal::hex;
 use once_cell::sync::Lazy;
@@ -25,17 +30,31 @@ use uuid::Uuid;
 
 use crate::store::{DataStore, OpenStore};
 
+use super::audit_log::{AuditEvent, AuditLog};
+use super::cache::ChunkCache;
+use super::checksum::ChecksumStore;
 use super::chunking::Chunking;
+use super::commit_marker;
 use super::compression::Compression;
 use super::config::RepoConfig;
 use super::encryption::{Encryption, EncryptionKey, KeySalt, ResourceLimit};
+use super::health::{HealthReport, LockState};
+use super::hooks::Hooks;
 use super::id_table::IdTable;
-use super::lock::LockTable;
-use super::metadata::{peek_info_store, Header, RepoMetadata};
+use super::lease::Lease;
+use super::lease_renewal::LeaseRenewal;
+use super::lock::{LockStrategy, LockTable};
+use super::metadata::{peek_info_store, read_header, read_header_lazily, Header, RepoMetadata};
+use super::migration::{self, VERSION_ID};
 use super::open_repo::OpenRepo;
 use super::packing::Packing;
-use super::repository::{KeyRepo, METADATA_BLOCK_ID, VERSION_BLOCK_ID};
-use super::state::RepoState;
+use super::poison::RecoverPoison;
+use super::progress::ProgressReporter;
+use super::repository::{KeyRepoInner, METADATA_BLOCK_ID, VERSION_BLOCK_ID};
+use super::retry::{RetryPolicy, RetryStore};
+use super::state::{HeaderChanges, RepoState, WriteState};
+use super::stats::{StatsCollector, StatsStore};
+use super::store_pool::StorePool;
 
 /// The default repository instance ID.
 ///
@@ -44,12 +63,6 @@ use super::state::RepoState;
 /// [`OpenOptions`]: crate::repo::OpenOptions
 pub const DEFAULT_INSTANCE: Uuid = Uuid::from_bytes(hex!("ea978302 bfd8 11ea b92b 031a9ad75c07"));
 
-/// The current repository format version ID.
-///
-/// This must be changed any time a backwards-incompatible change is made to the repository
-/// format.
-const VERSION_ID: Uuid = Uuid::from_bytes(hex!("6f1c893c e6a8 11eb a198 b7fa995cc83b"));
-
 /// A table of locks on repositories.
 static REPO_LOCKS: Lazy<Mutex<LockTable<Uuid>>> = Lazy::new(|| Mutex::new(LockTable::new()));
 
@@ -84,7 +97,7 @@ pub enum OpenMode {
 /// use acid_store::store::DirectoryConfig;
 ///
 /// let store_config = DirectoryConfig { path: "/path/to/store".into() };
-/// let mut repo: KeyRepo<String> = OpenOptions::new()
+/// let repo: KeyRepo<String> = OpenOptions::new()
 ///     .chunking(Chunking::zpaq())
 ///     .compression(Compression::Lz4 { level: 1 })
 ///     .encryption(Encryption::XChaCha20Poly1305)
@@ -107,7 +120,7 @@ pub enum OpenMode {
 /// repo_config.packing = Packing::fixed();
 ///
 /// let store_config = DirectoryConfig { path: "/path/to/store".into() };
-/// let mut repo: KeyRepo<String> = OpenOptions::new()
+/// let repo: KeyRepo<String> = OpenOptions::new()
 ///     .config(repo_config)
 ///     .password(b"password")
 ///     .mode(OpenMode::Create)
@@ -126,6 +139,14 @@ pub struct OpenOptions {
     mode: OpenMode,
     password: Option<Vec<u8>>,
     instance: Uuid,
+    lock_strategy: LockStrategy,
+    optimistic: bool,
+    read_only: bool,
+    background_clean: bool,
+    threads: usize,
+    store_concurrency: usize,
+    retry_policy: RetryPolicy,
+    chunk_cache_size: usize,
 }
 
 impl Default for OpenOptions {
@@ -142,6 +163,14 @@ impl OpenOptions {
             mode: OpenMode::Open,
             password: None,
             instance: DEFAULT_INSTANCE,
+            lock_strategy: LockStrategy::Abort,
+            optimistic: false,
+            read_only: false,
+            background_clean: false,
+            threads: 1,
+            store_concurrency: 1,
+            retry_policy: RetryPolicy::new(),
+            chunk_cache_size: 0,
         }
     }
 
@@ -250,36 +279,246 @@ impl OpenOptions {
         self
     }
 
-    /// Open the repository, failing if it doesn't exist.
-    fn open_repo<R: OpenRepo>(&self, mut store: impl DataStore + 'static) -> crate::Result<R> {
-        // Acquire a lock on the repository.
-        let repository_id = peek_info_store(&mut store)?.id();
-        let lock = REPO_LOCKS
-            .lock()
-            .unwrap()
-            .acquire_lock(repository_id)
-            .ok_or(crate::Error::Locked)?;
+    /// What to do if the repository is already locked.
+    ///
+    /// If this is not specified, the default strategy is `LockStrategy::Abort`.
+    pub fn lock_strategy(&mut self, strategy: LockStrategy) -> &mut Self {
+        self.lock_strategy = strategy;
+        self
+    }
 
-        // Read the repository version to see if this is a compatible repository.
-        let serialized_version = store
-            .read_block(VERSION_BLOCK_ID)
-            .map_err(crate::Error::Store)?
-            .ok_or(crate::Error::NotFound)?;
-        let version =
-            Uuid::from_slice(serialized_version.as_slice()).map_err(|_| crate::Error::Corrupt)?;
-        if version != VERSION_ID {
-            return Err(crate::Error::UnsupportedRepo);
+    /// Whether to use optimistic concurrency instead of locking the repository.
+    ///
+    /// By default, a repository can only be open once at a time within a process. When optimistic
+    /// concurrency is enabled, the repository is not locked when it is opened, so it can be opened
+    /// by multiple writers simultaneously. Instead, [`Commit::commit`] checks whether another
+    /// writer has committed changes since this repository was opened or refreshed and returns
+    /// `Error::Conflict` if so. To resolve a conflict, call [`Commit::refresh`] to load the other
+    /// writer's changes, which discards any uncommitted changes, and then make your changes again.
+    ///
+    /// The data store does not provide an atomic compare-and-swap operation, so this narrows the
+    /// window for concurrent commits to race but does not eliminate it entirely. Additionally,
+    /// [`Commit::clean`] may remove data which another writer has written but not yet committed,
+    /// so it should not be called while other writers have the repository open.
+    ///
+    /// This does not bypass leases configured with [`RepoConfig::lease_duration`].
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`Commit::commit`]: crate::repo::Commit::commit
+    /// [`Commit::refresh`]: crate::repo::Commit::refresh
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    /// [`RepoConfig::lease_duration`]: crate::repo::RepoConfig::lease_duration
+    pub fn optimistic_concurrency(&mut self, optimistic: bool) -> &mut Self {
+        self.optimistic = optimistic;
+        self
+    }
+
+    /// Whether to open the repository in read-only mode.
+    ///
+    /// A repository opened in read-only mode never writes to the data store, so it does not lock
+    /// the repository or acquire a lease on it. This allows you to read from a repository while
+    /// another process is using it, such as to restore files or verify its integrity. However,
+    /// if another process commits changes while the repository is open, reads may fail or return
+    /// stale data.
+    ///
+    /// Any operation which would write to the data store, like writing to an object or calling
+    /// [`Commit::commit`] or [`Commit::clean`], returns `Error::ReadOnly`. Because switching
+    /// instances or branches writes to the data store, it is not supported in read-only mode; use
+    /// [`instance`] to open the instance you want to read instead. Changes made only in memory,
+    /// like removing a key, are allowed but can never be committed.
+    ///
+    /// In read-only mode, the parts of the repository header which track where each chunk is
+    /// stored are loaded from the data store as they're needed rather than when the repository is
+    /// opened. For large repositories, this makes opening the repository to read a few objects
+    /// much faster. Operations which need the whole header, like [`KeyRepo::verify`], load the
+    /// rest of it.
+    ///
+    /// A repository can't be created in read-only mode.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`Commit::commit`]: crate::repo::Commit::commit
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    /// [`instance`]: crate::repo::OpenOptions::instance
+    /// [`KeyRepo::verify`]: crate::repo::key::KeyRepo::verify
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Whether to remove unreferenced data from the data store in the background.
+    ///
+    /// When this is enabled, each successful [`Commit::commit`] starts a pass on a background
+    /// thread which removes the blocks that are no longer referenced by the repository, a small
+    /// batch at a time. This reclaims space continuously instead of requiring periodic calls to
+    /// [`Commit::clean`], which must stop the world while it runs. Blocks are only removed if
+    /// they aren't referenced as of the commit that started the pass, so data needed by any
+    /// instance of the repository, by savepoints, or by [`Commit::rollback`] is never removed. If
+    /// a pass is still running when the next commit happens, it keeps running and a new pass is
+    /// not started.
+    ///
+    /// When packing is enabled, only packs which don't contain any referenced data are removed in
+    /// the background. Use [`Commit::clean`] to repack the rest.
+    ///
+    /// Because blocks written by other writers can't be distinguished from unreferenced blocks,
+    /// this does nothing if [`optimistic_concurrency`] is enabled.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`Commit::commit`]: crate::repo::Commit::commit
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    /// [`Commit::rollback`]: crate::repo::Commit::rollback
+    /// [`optimistic_concurrency`]: crate::repo::OpenOptions::optimistic_concurrency
+    pub fn background_clean(&mut self, enabled: bool) -> &mut Self {
+        self.background_clean = enabled;
+        self
+    }
+
+    /// The number of worker threads to use for operations which can be done concurrently.
+    ///
+    /// This is currently used by [`FileRepo::archive_tree`] to read and chunk multiple files at
+    /// once and by [`KeyRepo::verify`] to hash multiple chunks at once. Because data stores can't
+    /// be shared between threads, data is still read from and written to the data store one block
+    /// at a time unless [`store_concurrency`] is set.
+    ///
+    /// If the `rayon` feature is enabled, a rayon thread pool with this many threads is also used
+    /// to hash, compress, and encrypt multiple chunks at once when writing to an [`Object`], to
+    /// hash multiple chunks at once in [`Object::verify`], and to serialize and encode the
+    /// repository header in [`Commit::commit`] while the data store is being accessed.
+    ///
+    /// The default value is `1`, which means all work is done on the calling thread.
+    ///
+    /// # Panics
+    /// - `threads` is zero.
+    ///
+    /// [`FileRepo::archive_tree`]: crate::repo::file::FileRepo::archive_tree
+    /// [`KeyRepo::verify`]: crate::repo::key::KeyRepo::verify
+    /// [`Commit::commit`]: crate::repo::Commit::commit
+    /// [`Object`]: crate::repo::Object
+    /// [`Object::verify`]: crate::repo::Object::verify
+    /// [`store_concurrency`]: crate::repo::OpenOptions::store_concurrency
+    pub fn threads(&mut self, threads: usize) -> &mut Self {
+        assert!(threads > 0, "The number of threads must be at least one.");
+        self.threads = threads;
+        self
+    }
+
+    /// The number of blocks to read from or write to the data store concurrently.
+    ///
+    /// If this is greater than `1`, a pool of worker threads is started when the repository is
+    /// opened, and each worker opens its own connection to the data store using the same
+    /// [`OpenStore`] value. Chunks written to an [`Object`] are then written to the data store by
+    /// the pool in the background, and [`Object::commit`] waits for them to finish. Chunks are
+    /// also read through the pool, so multiple threads can read from the data store at once, like
+    /// the workers started by [`KeyRepo::verify`]. When an [`Object`] is read sequentially, the
+    /// pool reads this many of the following chunks in the background so that fetching them
+    /// overlaps with decrypting and decompressing the current one. This can dramatically improve
+    /// throughput for data stores with high latency, like [`S3Store`], but it should only be used
+    /// with data stores which support being opened multiple times and accessed concurrently.
+    ///
+    /// If writing a chunk in the background fails, [`Object::commit`] returns the error and the
+    /// object's uncommitted changes are discarded.
+    ///
+    /// The pool is not used if packing is enabled. Chunks are never written in the background if
+    /// the repository is opened in read-only mode.
+    ///
+    /// The default value is `1`, which means blocks are read and written one at a time.
+    ///
+    /// # Panics
+    /// - `concurrency` is zero.
+    ///
+    /// [`OpenStore`]: crate::store::OpenStore
+    /// [`Object`]: crate::repo::Object
+    /// [`Object::commit`]: crate::repo::Object::commit
+    /// [`KeyRepo::verify`]: crate::repo::key::KeyRepo::verify
+    /// [`S3Store`]: crate::store::S3Store
+    pub fn store_concurrency(&mut self, concurrency: usize) -> &mut Self {
+        assert!(
+            concurrency > 0,
+            "The store concurrency must be at least one."
+        );
+        self.store_concurrency = concurrency;
+        self
+    }
+
+    /// How to retry operations on the data store which fail with a transient error.
+    ///
+    /// The policy applies to every operation the repository performs on the data store while it
+    /// is open, so applications don't need to wrap their data store to survive a flaky network
+    /// connection. Opening the data store itself with [`OpenStore::open`] is not retried.
+    ///
+    /// If this is not specified, operations are never retried.
+    ///
+    /// [`OpenStore::open`]: crate::store::OpenStore::open
+    pub fn retry_policy(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// The maximum number of bytes of chunks to cache in memory.
+    ///
+    /// If this is greater than `0`, the decrypted and decompressed contents of chunks which are
+    /// read from an [`Object`] are kept in an in-memory cache which is shared by every object in
+    /// the repository. When the cache is full, the least recently used chunks are evicted first.
+    /// This avoids reading the same data from the data store again when it is read repeatedly,
+    /// such as when files in a [`FileRepo`] are accessed through a FUSE mount.
+    ///
+    /// Verifying the integrity of data with [`Object::verify`] or [`KeyRepo::verify`] always
+    /// reads from the data store rather than the cache.
+    ///
+    /// The default value is `0`, which means chunks are not cached.
+    ///
+    /// [`Object`]: crate::repo::Object
+    /// [`FileRepo`]: crate::repo::file::FileRepo
+    /// [`Object::verify`]: crate::repo::Object::verify
+    /// [`KeyRepo::verify`]: crate::repo::key::KeyRepo::verify
+    pub fn chunk_cache_size(&mut self, size: usize) -> &mut Self {
+        self.chunk_cache_size = size;
+        self
+    }
+
+    /// Build the rayon thread pool for CPU-bound work if more than one thread was requested.
+    #[cfg(feature = "rayon")]
+    fn rayon_pool(&self) -> crate::Result<Option<Arc<rayon::ThreadPool>>> {
+        if self.threads == 1 {
+            return Ok(None);
         }
 
-        // We read the metadata again after reading the UUID to prevent a race condition when
-        // acquiring the lock.
-        let serialized_metadata = store
-            .read_block(METADATA_BLOCK_ID)
-            .map_err(crate::Error::Store)?
-            .ok_or(crate::Error::Corrupt)?;
-        let metadata: RepoMetadata =
-            from_read(serialized_metadata.as_slice()).map_err(|_| crate::Error::Corrupt)?;
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()
+            .map(|pool| Some(Arc::new(pool)))
+            .map_err(|error| crate::Error::Io(io::Error::other(error)))
+    }
+
+    /// Repeatedly call `acquire` until it returns a lock using the configured lock strategy.
+    fn acquire_lock<T>(
+        &self,
+        mut acquire: impl FnMut() -> crate::Result<Option<T>>,
+    ) -> crate::Result<T> {
+        let start = Instant::now();
+        let mut attempts = 0;
+
+        loop {
+            if let Some(lock) = acquire()? {
+                return Ok(lock);
+            }
+
+            attempts += 1;
+            match self.lock_strategy.retry_delay(attempts, start.elapsed()) {
+                Some(delay) => thread::sleep(delay),
+                None => return Err(crate::Error::Locked),
+            }
+        }
+    }
 
+    /// Decrypt the master key for the repository with the given `metadata` using the password.
+    ///
+    /// # Errors
+    /// - `Error::Password`: The password provided is invalid.
+    /// - `Error::Password`: A password was required but not provided.
+    fn master_key(&self, metadata: &RepoMetadata) -> crate::Result<EncryptionKey> {
         let password = match self.password.clone() {
             Some(password) if metadata.config.encryption != Encryption::None => Some(password),
             // Return an error if a password was required but not provided.
@@ -289,8 +528,7 @@ impl OpenOptions {
             _ => None,
         };
 
-        // Decrypt the master key for the repository.
-        let master_key = match password {
+        Ok(match password {
             Some(password_bytes) => {
                 let user_key = EncryptionKey::derive(
                     password_bytes.as_slice(),
@@ -308,24 +546,87 @@ impl OpenOptions {
                 )
             }
             None => EncryptionKey::new(Vec::new()),
+        })
+    }
+
+    /// Open the repository, failing if it doesn't exist.
+    fn open_repo<R: OpenRepo>(
+        &self,
+        mut store: impl DataStore + Send + 'static,
+        pool_stores: Vec<Box<dyn DataStore + Send>>,
+        stats: Arc<StatsCollector>,
+    ) -> crate::Result<R> {
+        // Acquire a lock on the repository unless we're using optimistic concurrency or the
+        // repository is read-only.
+        let repository_id = peek_info_store(&mut store)?.id();
+        let lock = if self.optimistic || self.read_only {
+            None
+        } else {
+            Some(self.acquire_lock(|| Ok(REPO_LOCKS.lock().recover().acquire_lock(repository_id)))?)
         };
 
-        // Read, decrypt, decompress, and deserialize the repository header.
-        let encrypted_header = store
-            .read_block(metadata.header_id)
-            .map_err(crate::Error::Store)?
+        // Read the repository version to see if this is a compatible repository. Repositories in a
+        // previous format are migrated to the current format once we have exclusive access to
+        // them, unless they can be read as-is and we're not going to write to them.
+        let serialized_version = store
+            .read_block(VERSION_BLOCK_ID)
+            .map_err(crate::Error::from_store)?
+            .ok_or(crate::Error::NotFound)?;
+        let version =
+            Uuid::from_slice(serialized_version.as_slice()).map_err(|_| crate::Error::Corrupt)?;
+        let migrations =
+            migration::migrations_from(version).ok_or(crate::Error::UnsupportedRepo)?;
+        if self.read_only && !migration::is_read_compatible(&migrations) {
+            return Err(crate::Error::UnsupportedRepo);
+        }
+
+        // We read the metadata again after reading the UUID to prevent a race condition when
+        // acquiring the lock.
+        let serialized_metadata = store
+            .read_block(METADATA_BLOCK_ID)
+            .map_err(crate::Error::from_store)?
             .ok_or(crate::Error::Corrupt)?;
-        let compressed_header = metadata
-            .config
-            .encryption
-            .decrypt(&encrypted_header, &master_key)
-            .map_err(|_| crate::Error::Corrupt)?;
-        let serialized_header = metadata
-            .config
-            .compression
-            .decompress(&compressed_header)
-            .map_err(|_| crate::Error::Corrupt)?;
-        let header = from_read(serialized_header.as_slice()).map_err(|_| crate::Error::Corrupt)?;
+        let metadata: RepoMetadata =
+            from_read(serialized_metadata.as_slice()).map_err(|_| crate::Error::Corrupt)?;
+
+        // Decrypt the master key for the repository.
+        let master_key = self.master_key(&metadata)?;
+
+        // Blocks other than the metadata and version blocks may have checksums, depending on the
+        // config.
+        let mut store = ChecksumStore::wrap(Box::new(store), &metadata.config);
+        let pool = store_pool(pool_stores, &metadata.config);
+
+        // Acquire a lease on the repository if leases are enabled. We do this before reading the
+        // header so that another process can't commit changes after we've read it.
+        let lease = match metadata.config.lease_duration {
+            Some(duration) if !self.read_only => {
+                Some(self.acquire_lock(|| Lease::acquire(&mut *store, duration))?)
+            }
+            _ => None,
+        };
+
+        // If a commit was interrupted, make sure we read the header from the last complete commit.
+        // We only modify the data store if no other writer can be committing at the same time.
+        let metadata =
+            commit_marker::recover(&mut *store, metadata, !self.read_only && !self.optimistic)?;
+
+        let metadata = if self.read_only {
+            metadata
+        } else {
+            migration::migrate(&mut *store, metadata, &migrations)?
+        };
+
+        // Read, decrypt, decompress, and deserialize the repository header and any deltas which
+        // have been committed since it was last written in full. If the repository is read-only,
+        // the shards of the chunk and pack maps are loaded on demand instead.
+        let decode = block_decoder(&metadata, &master_key);
+        let (header, lazy_header) = if self.read_only {
+            let (header, lazy_header) = read_header_lazily(&mut *store, &metadata, decode)?;
+            (header, Some(lazy_header))
+        } else {
+            (read_header(&mut *store, &metadata, decode)?, None)
+        };
 
         let Header {
             chunks,
@@ -335,29 +636,64 @@ impl OpenOptions {
         } = header;
 
         let state = Arc::new(RwLock::new(RepoState {
-            store: Mutex::new(Box::new(store)),
+            store: Mutex::new(store),
             metadata,
             chunks,
             packs,
+            header_changes: HeaderChanges::default(),
+            lazy_header,
             transactions: LockTable::new(),
             master_key,
             lock,
+            optimistic: self.optimistic,
+            read_only: self.read_only,
+            background_clean: self.background_clean,
+            threads: self.threads,
+            #[cfg(feature = "rayon")]
+            rayon_pool: self.rayon_pool()?,
+            lease,
+            lease_renewal: None,
+            pool,
+            chunk_cache: Mutex::new(ChunkCache::new(self.chunk_cache_size)),
+            stats,
+            audit_log: AuditLog::default(),
+            poisoned: false,
         }));
+        start_lease_renewal(&state);
 
-        let repo: KeyRepo<R::Key> = KeyRepo {
+        let repo: KeyRepoInner<R::Key> = KeyRepoInner {
             state,
             instance_id: self.instance,
             objects: HashMap::new(),
             instances,
             handle_table,
             transaction_id: Arc::new(Uuid::new_v4()),
+            hooks: Hooks::default(),
+            progress: ProgressReporter::default(),
+            background_clean: None,
         };
 
+        repo.audit_and_flush(AuditEvent::Open);
+
         repo.change_instance(self.instance)
     }
 
     /// Create a new repository, failing if one already exists.
-    fn create_repo<R: OpenRepo>(&self, mut store: impl DataStore + 'static) -> crate::Result<R> {
+    fn create_repo<R: OpenRepo>(
+        &self,
+        store: impl DataStore + Send + 'static,
+        pool_stores: Vec<Box<dyn DataStore + Send>>,
+        stats: Arc<StatsCollector>,
+    ) -> crate::Result<R> {
+        if self.read_only {
+            return Err(crate::Error::ReadOnly);
+        }
+
+        self.config.parity.validate()?;
+
+        let mut store = ChecksumStore::wrap(Box::new(store), &self.config);
+        let pool = store_pool(pool_stores, &self.config);
+
         let password = match self.password.clone() {
             Some(password) if self.config.encryption != Encryption::None => Some(password),
             // Return an error if a password was required but not provided.
@@ -367,18 +703,24 @@ impl OpenOptions {
             _ => None,
         };
 
-        // Acquire an exclusive lock on the repository.
+        // Acquire an exclusive lock on the repository unless we're using optimistic concurrency.
         let id = Uuid::new_v4();
-        let lock = REPO_LOCKS
-            .lock()
-            .unwrap()
-            .acquire_lock(id)
-            .ok_or(crate::Error::AlreadyExists)?;
+        let lock = if self.optimistic {
+            None
+        } else {
+            Some(
+                REPO_LOCKS
+                    .lock()
+                    .recover()
+                    .acquire_lock(id)
+                    .ok_or(crate::Error::AlreadyExists)?,
+            )
+        };
 
         // Check if the repository already exists.
         if store
             .read_block(VERSION_BLOCK_ID)
-            .map_err(crate::Error::Store)?
+            .map_err(crate::Error::from_store)?
             .is_some()
         {
             return Err(crate::Error::AlreadyExists);
@@ -431,7 +773,7 @@ impl OpenOptions {
         let header_id = Uuid::new_v4();
         store
             .write_block(header_id, &encrypted_header)
-            .map_err(crate::Error::Store)?;
+            .map_err(crate::Error::from_store)?;
 
         // Create the repository metadata with the header block references.
         let metadata = RepoMetadata {
@@ -440,19 +782,31 @@ impl OpenOptions {
             master_key: encrypted_master_key,
             salt,
             header_id,
+            generation: 0,
+            header_deltas: Vec::new(),
+            header_shards: Vec::new(),
+            last_commit: None,
         };
 
         // Write the repository metadata.
         let serialized_metadata = to_vec(&metadata).expect("Could not serialize metadata.");
         store
             .write_block(METADATA_BLOCK_ID, &serialized_metadata)
-            .map_err(crate::Error::Store)?;
+            .map_err(crate::Error::from_store)?;
+
+        // Acquire a lease on the repository if leases are enabled.
+        let lease = match self.config.lease_duration {
+            Some(duration) => {
+                Some(Lease::acquire(&mut *store, duration)?.ok_or(crate::Error::Locked)?)
+            }
+            None => None,
+        };
 
         // Write the repository version. We do this last because this signifies that the repository
         // is done being created.
         store
             .write_block(VERSION_BLOCK_ID, VERSION_ID.as_bytes())
-            .map_err(crate::Error::Store)?;
+            .map_err(crate::Error::from_store)?;
 
         let Header {
             chunks,
@@ -462,24 +816,45 @@ impl OpenOptions {
         } = header;
 
         let state = Arc::new(RwLock::new(RepoState {
-            store: Mutex::new(Box::new(store)),
+            store: Mutex::new(store),
             metadata,
             chunks,
             packs,
+            header_changes: HeaderChanges::default(),
+            lazy_header: None,
             transactions: LockTable::new(),
             master_key,
             lock,
+            optimistic: self.optimistic,
+            read_only: self.read_only,
+            background_clean: self.background_clean,
+            threads: self.threads,
+            #[cfg(feature = "rayon")]
+            rayon_pool: self.rayon_pool()?,
+            lease,
+            lease_renewal: None,
+            pool,
+            chunk_cache: Mutex::new(ChunkCache::new(self.chunk_cache_size)),
+            stats,
+            audit_log: AuditLog::default(),
+            poisoned: false,
         }));
+        start_lease_renewal(&state);
 
-        let repo: KeyRepo<R::Key> = KeyRepo {
+        let repo: KeyRepoInner<R::Key> = KeyRepoInner {
             state,
             instance_id: self.instance,
             objects: HashMap::new(),
             instances,
             handle_table,
             transaction_id: Arc::new(Uuid::new_v4()),
+            hooks: Hooks::default(),
+            progress: ProgressReporter::default(),
+            background_clean: None,
         };
 
+        repo.audit_and_flush(AuditEvent::Create);
+
         repo.change_instance(self.instance)
     }
 
@@ -487,44 +862,225 @@ impl OpenOptions {
     ///
     /// # Errors
     /// - `Error::NotFound`: There is no repository in the data store and `OpenMode::Open` was
-    /// specified.
+    ///   specified.
     /// - `Error::AlreadyExists`: A repository already exists in the data store and
-    /// `OpenMode::CreateNew` was specified.
+    ///   `OpenMode::CreateNew` was specified.
     /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
-    /// - `Error::Locked`: The repository is locked.
+    /// - `Error::Locked`: The repository is locked or another process holds a lease on it, and the
+    ///   lock strategy gave up waiting for it.
     /// - `Error::Password`: The password provided is invalid.
     /// - `Error::Password`: A password was required but not provided.
+    /// - `Error::ReadOnly`: Read-only mode was specified and the repository or instance would need
+    ///   to be created.
     /// - `Error::Deserialize`: Could not deserialize some data in the repository.
     /// - `Error::UnsupportedRepo`: The repository is an unsupported format. This can happen if the
-    /// serialized data format changed or if the data store already contains a different type of
-    /// repository.
+    ///   repository was created by a newer version of this library, if it needs to be migrated from
+    ///   an older format but read-only mode was specified, or if the data store already contains a
+    ///   different type of repository.
     /// - `Error::UnsupportedStore`: The data store is an unsupported format. This can happen if
-    /// the serialized data format changed or if the storage represented by `config` does not
-    /// contain a valid data store.
+    ///   the serialized data format changed or if the storage represented by `config` does not
+    ///   contain a valid data store.
     /// - `Error::InvalidData`: Ciphertext verification failed.
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip(self, config),
+            fields(mode = ?self.mode, instance = %self.instance, read_only = self.read_only)
+        )
+    )]
     pub fn open<R, C>(&self, config: &C) -> crate::Result<R>
     where
         R: OpenRepo,
         C: OpenStore,
     {
-        let mut store = config.open()?;
+        // Statistics are recorded around retries so that they reflect the latency seen by the
+        // repository.
+        let stats = Arc::new(StatsCollector::default());
+        let mut store = StatsStore::new(
+            RetryStore::new(config.open()?, self.retry_policy.clone()),
+            Arc::clone(&stats),
+        );
+
+        let pool_stores = if self.store_concurrency > 1 {
+            (0..self.store_concurrency)
+                .map(|_| {
+                    let store = StatsStore::new(
+                        RetryStore::new(config.open()?, self.retry_policy.clone()),
+                        Arc::clone(&stats),
+                    );
+                    Ok(Box::new(store) as Box<dyn DataStore + Send>)
+                })
+                .collect::<crate::Result<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
 
         match self.mode {
-            OpenMode::Open => self.open_repo(store),
+            OpenMode::Open => self.open_repo(store, pool_stores, stats),
             OpenMode::Create => {
                 if store
                     .read_block(VERSION_BLOCK_ID)
-                    .map_err(crate::Error::Store)?
+                    .map_err(crate::Error::from_store)?
                     .is_some()
                 {
-                    self.open_repo(store)
+                    self.open_repo(store, pool_stores, stats)
                 } else {
-                    self.create_repo(store)
+                    self.create_repo(store, pool_stores, stats)
                 }
             }
-            OpenMode::CreateNew => self.create_repo(store),
+            OpenMode::CreateNew => self.create_repo(store, pool_stores, stats),
+        }
+    }
+
+    /// Check the health of the repository without opening it.
+    ///
+    /// This is meant to be called periodically by monitoring probes. It doesn't acquire a lock or a
+    /// lease on the repository, so it can be used while the repository is open elsewhere. It reads
+    /// the repository header to check its integrity, records whether the repository is locked and
+    /// when it was last committed, and checks that the blocks storing up to `samples` chunks exist
+    /// in the data store. The chunks are sampled in an unspecified order.
+    ///
+    /// Only the password and retry policy options are used; all other options are ignored. If the
+    /// repository is encrypted, a password is required to read its header.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: There is no repository in the data store.
+    /// - `Error::Corrupt`: The repository metadata is corrupt.
+    /// - `Error::Password`: The password provided is invalid.
+    /// - `Error::Password`: A password was required but not provided.
+    /// - `Error::UnsupportedStore`: The data store is an unsupported format. This can happen if
+    ///   the serialized data format changed or if the storage represented by `config` does not
+    ///   contain a valid data store.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn health_check<C: OpenStore>(
+        &self,
+        config: &C,
+        samples: usize,
+    ) -> crate::Result<HealthReport> {
+        let mut store = RetryStore::new(config.open()?, self.retry_policy.clone());
+
+        let serialized_version = store
+            .read_block(VERSION_BLOCK_ID)
+            .map_err(crate::Error::from_store)?
+            .ok_or(crate::Error::NotFound)?;
+        let version =
+            Uuid::from_slice(serialized_version.as_slice()).map_err(|_| crate::Error::Corrupt)?;
+        let lease_expires = Lease::expiration(&mut store)?;
+
+        let mut report = HealthReport {
+            version_compatible: migration::migrations_from(version).is_some(),
+            lock: match lease_expires {
+                Some(expires) => LockState::Leased { expires },
+                None => LockState::Unlocked,
+            },
+            last_commit: None,
+            header_intact: false,
+            sampled_blocks: 0,
+            missing_blocks: 0,
+        };
+
+        // The metadata format may have changed, so we can't check anything else.
+        if !report.version_compatible {
+            return Ok(report);
+        }
+
+        let serialized_metadata = store
+            .read_block(METADATA_BLOCK_ID)
+            .map_err(crate::Error::from_store)?
+            .ok_or(crate::Error::Corrupt)?;
+        let metadata: RepoMetadata =
+            from_read(serialized_metadata.as_slice()).map_err(|_| crate::Error::Corrupt)?;
+
+        if REPO_LOCKS.lock().recover().is_locked(&metadata.id) {
+            report.lock = LockState::Locked;
+        }
+        report.last_commit = metadata.last_commit;
+
+        let master_key = self.master_key(&metadata)?;
+        let mut store = ChecksumStore::wrap(Box::new(store), &metadata.config);
+        let header = match read_header(
+            &mut *store,
+            &metadata,
+            block_decoder(&metadata, &master_key),
+        ) {
+            Ok(header) => header,
+            Err(crate::Error::Corrupt) | Err(crate::Error::CorruptBlock(_)) => return Ok(report),
+            Err(error) => return Err(error),
+        };
+        report.header_intact = true;
+
+        // Chunks stored inline in the header don't have a block. If packing is enabled, the data
+        // is stored in the packs which contain the chunk's block.
+        let sampled_blocks = header
+            .chunks
+            .values()
+            .filter(|chunk_info| chunk_info.inline.is_none())
+            .take(samples)
+            .flat_map(|chunk_info| match header.packs.get(&chunk_info.block_id) {
+                Some(pack_indices) => pack_indices.iter().map(|index| index.id).collect(),
+                None => vec![chunk_info.block_id],
+            })
+            .collect::<Vec<_>>();
+
+        for block_id in sampled_blocks {
+            report.sampled_blocks += 1;
+            if store
+                .read_block_range(block_id, 0, 0)
+                .map_err(crate::Error::from_store)?
+                .is_none()
+            {
+                report.missing_blocks += 1;
+            }
         }
+
+        Ok(report)
+    }
+}
+
+/// Start a pool of workers which use the given `stores`, or return `None` if there are none.
+///
+/// Each store is wrapped so that blocks are checksummed according to `config`.
+fn store_pool(stores: Vec<Box<dyn DataStore + Send>>, config: &RepoConfig) -> Option<StorePool> {
+    if stores.is_empty() {
+        return None;
+    }
+    Some(StorePool::new(
+        stores
+            .into_iter()
+            .map(|store| ChecksumStore::wrap(store, config))
+            .collect(),
+    ))
+}
+
+/// Start renewing the lease held by the repository with the given `state`, if it holds one.
+fn start_lease_renewal(state: &Arc<RwLock<RepoState>>) {
+    let mut state_guard = state.write_state();
+    if let Some(duration) = state_guard.lease.as_ref().map(Lease::duration) {
+        // Renew the lease halfway through its duration so that it doesn't expire while the
+        // repository is idle.
+        state_guard.lease_renewal = Some(LeaseRenewal::start(state, duration / 2));
+    }
+}
+
+/// Return a function which decrypts and decompresses blocks from the repository with the given
+/// `metadata` using `master_key`.
+fn block_decoder<'a>(
+    metadata: &'a RepoMetadata,
+    master_key: &'a EncryptionKey,
+) -> impl Fn(Vec<u8>) -> crate::Result<Box<dyn Read>> + 'a {
+    move |encrypted_block: Vec<u8>| {
+        let compressed_block = metadata
+            .config
+            .encryption
+            .decrypt_in_place(encrypted_block, master_key)
+            .map_err(|_| crate::Error::Corrupt)?;
+        metadata
+            .config
+            .compression
+            .decompress_reader(compressed_block)
+            .map_err(|_| crate::Error::Corrupt)
     }
 }
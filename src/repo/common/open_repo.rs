@@ -19,6 +19,13 @@ use uuid::Uuid;
 use super::key::Key;
 use super::repository::KeyRepo;
 
+/// The name of the branch which new instances of a repository start with.
+///
+/// See [`SwitchBranch`] for more information.
+///
+/// [`SwitchBranch`]: crate::repo::SwitchBranch
+pub const DEFAULT_BRANCH: &str = "main";
+
 /// A repository which can be opened using [`OpenOptions`].
 ///
 /// This trait represents a repository type which can be converted to and from a [`KeyRepo`].
@@ -133,7 +140,7 @@ pub trait SwitchInstance {
     ///     .unwrap();
     ///
     /// // Switch the current instance to an instance of a different type.
-    /// let mut value_repo: ValueRepo<u64> = key_repo.switch_instance(value_instance).unwrap();
+    /// let value_repo: ValueRepo<u64> = key_repo.switch_instance(value_instance).unwrap();
     ///
     /// // Commit both instances of the repository.
     /// value_repo.commit().unwrap();
@@ -162,8 +169,102 @@ impl<T: OpenRepo> SwitchInstance for T {
         R: OpenRepo,
         Self: Sized,
     {
-        let mut repo = self.into_repo()?;
+        let mut repo = self.into_repo()?.into_inner();
         repo.write_object_map()?;
         repo.change_instance(id)
     }
 }
+
+/// A repository which supports branching.
+///
+/// Each instance of a repository has one or more named branches. A branch is an independent copy of
+/// the contents of an instance which shares the same backing data store as the other branches, so
+/// creating a branch is cheap and does not require copying the bytes in any objects. New instances
+/// start with a single branch named [`DEFAULT_BRANCH`].
+///
+/// This trait is automatically implemented for all types which implement [`OpenRepo`].
+///
+/// Branches are committed and rolled back along with the rest of the repository, so creating,
+/// switching, or removing a branch does not take effect in the data store until changes are
+/// committed.
+///
+/// # Examples
+/// ```
+/// use acid_store::repo::{SwitchBranch, OpenMode, OpenOptions, value::ValueRepo, DEFAULT_BRANCH};
+/// use acid_store::store::MemoryConfig;
+///
+/// let repo: ValueRepo<String> = OpenOptions::new()
+///     .mode(OpenMode::CreateNew)
+///     .open(&MemoryConfig::new())
+///     .unwrap();
+///
+/// repo.insert(String::from("Key"), &"Production").unwrap();
+///
+/// // Create a new branch from the current state and switch to it.
+/// let repo = repo.create_branch("staging").unwrap();
+/// let repo = repo.switch_branch("staging").unwrap();
+/// repo.insert(String::from("Key"), &"Staging").unwrap();
+///
+/// // Changes made on one branch are not visible on other branches.
+/// let repo = repo.switch_branch(DEFAULT_BRANCH).unwrap();
+/// assert_eq!(repo.get::<_, String>("Key").unwrap(), "Production");
+/// ```
+///
+/// [`DEFAULT_BRANCH`]: crate::repo::DEFAULT_BRANCH
+/// [`OpenRepo`]: crate::repo::OpenRepo
+pub trait SwitchBranch {
+    /// Create a new branch named `name` from the current state of the current branch.
+    ///
+    /// This does not switch to the new branch. To do that, use [`switch_branch`].
+    ///
+    /// This does not commit or roll back changes to the repository.
+    ///
+    /// # Errors
+    /// - `Error::AlreadyExists`: There is already a branch with the given `name`.
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize data in the repository.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`switch_branch`]: crate::repo::SwitchBranch::switch_branch
+    fn create_branch(self, name: &str) -> crate::Result<Self>
+    where
+        Self: Sized;
+
+    /// Switch from the current branch to the branch named `name`.
+    ///
+    /// This does not commit or roll back changes to the repository. Uncommitted changes made on
+    /// the current branch are kept on that branch.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: There is no branch with the given `name`.
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize data in the repository.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    fn switch_branch(self, name: &str) -> crate::Result<Self>
+    where
+        Self: Sized;
+}
+
+impl<T: OpenRepo> SwitchBranch for T {
+    fn create_branch(self, name: &str) -> crate::Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut repo = self.into_repo()?.into_inner();
+        repo.fork_branch(name)?;
+        T::open_repo(KeyRepo::from_inner(repo))
+    }
+
+    fn switch_branch(self, name: &str) -> crate::Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut repo = self.into_repo()?.into_inner();
+        repo.checkout_branch(name)?;
+        T::open_repo(KeyRepo::from_inner(repo))
+    }
+}
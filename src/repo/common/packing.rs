@@ -29,6 +29,13 @@ use serde::{Deserialize, Serialize};
 ///
 /// Choosing `Packing::Fixed` provides no additional security if encryption is disabled. If
 /// encryption is not needed, you should use `Packing::None`.
+///
+/// Packing can also be used to reduce the number of blocks in the data store. Small chunks are
+/// each stored in their own block by default, which can be slow and expensive with data stores
+/// which have a high cost per request, like [`S3Store`]. Choosing `Packing::Variable` groups
+/// many chunks into larger blocks before writing them to the data store.
+///
+/// [`S3Store`]: crate::store::S3Store
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum Packing {
     /// Do not pack data into fixed-size blocks.
@@ -42,6 +49,15 @@ pub enum Packing {
     ///
     /// This typically results in worse performance than `Packing::None`.
     Fixed(u32),
+
+    /// Pack data into variable-size blocks of up to the given size in bytes.
+    ///
+    /// Chunks written to an object are buffered in memory and written to the data store together
+    /// once the buffer is full or the object is committed. Unlike `Packing::Fixed`, blocks are
+    /// not padded, so this does not hide the size of chunks, but it can dramatically reduce the
+    /// number of requests made to the data store when chunks are small. A chunk which is larger
+    /// than the given size is stored in a block of its own.
+    Variable(u32),
 }
 
 impl Packing {
@@ -49,4 +65,9 @@ impl Packing {
     pub const fn fixed() -> Self {
         Packing::Fixed(1024 * 64)
     }
+
+    /// Return a reasonable default value of `Packing::Variable`.
+    pub const fn variable() -> Self {
+        Packing::Variable(1024 * 1024 * 4)
+    }
 }
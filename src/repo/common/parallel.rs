@@ -0,0 +1,81 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::sync::RwLock;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+#[cfg(feature = "rayon")]
+use super::poison::RecoverPoison;
+use super::state::RepoState;
+
+/// Apply `function` to each of `items` and return the results in order.
+///
+/// If the `rayon` feature is enabled and the repository with the given `state` has a rayon thread
+/// pool, the items are processed in parallel on that pool. Otherwise, they are processed one at a
+/// time on the calling thread.
+#[cfg_attr(not(feature = "rayon"), allow(unused_variables))]
+pub fn map_parallel<T, U, F>(state: &RepoState, items: Vec<T>, function: F) -> Vec<U>
+where
+    T: Send,
+    U: Send,
+    F: Fn(T) -> U + Send + Sync,
+{
+    #[cfg(feature = "rayon")]
+    {
+        if let Some(pool) = &state.rayon_pool {
+            if items.len() > 1 {
+                return pool.install(|| items.into_par_iter().map(function).collect());
+            }
+        }
+    }
+
+    items.into_iter().map(function).collect()
+}
+
+/// Run `background` and `foreground` and return both of their results.
+///
+/// If the `rayon` feature is enabled and the repository with the given `state` has a rayon thread
+/// pool, `background` is run on that pool while `foreground` is run on the calling thread.
+/// Otherwise, they are run one after the other on the calling thread.
+///
+/// The lock on `state` is released before either of them is run, so they are free to acquire it.
+#[cfg_attr(not(feature = "rayon"), allow(unused_variables))]
+pub fn join<A, B, RA, RB>(state: &RwLock<RepoState>, background: A, foreground: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB,
+    RA: Send,
+{
+    #[cfg(feature = "rayon")]
+    {
+        let pool = state.read().recover().rayon_pool.clone();
+        if let Some(pool) = pool {
+            let mut background_result = None;
+            let foreground_result = pool.in_place_scope(|scope| {
+                scope.spawn(|_| background_result = Some(background()));
+                foreground()
+            });
+            // The scope doesn't return until the spawned job has finished, and if it panicked,
+            // the panic is propagated.
+            let background_result = background_result.expect("The background job didn't finish.");
+            return (background_result, foreground_result);
+        }
+    }
+
+    (background(), foreground())
+}
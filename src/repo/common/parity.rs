@@ -0,0 +1,466 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashSet;
+#[cfg(feature = "erasure-coding")]
+use std::io;
+
+use hex_literal::hex;
+#[cfg(feature = "erasure-coding")]
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use rmp_serde::from_read;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::chunk_store::EncodeBlock;
+use super::poison::RecoverPoison;
+#[cfg(feature = "erasure-coding")]
+use super::progress::Operation;
+use super::progress::ProgressReporter;
+use super::repair_report::RepairReport;
+use super::state::RepoState;
+
+/// The block ID of the block which stores the index of parity groups.
+pub const PARITY_INDEX_BLOCK_ID: Uuid =
+    Uuid::from_bytes(hex!("5b0e97d2 c4a1 4e6f 9d38 a27f10c6e845"));
+
+/// A method for storing parity blocks in a repository.
+///
+/// By default, a block which is lost or corrupted in the data store can't be recovered, and the
+/// data stored in it is lost. Repositories can be configured to store parity blocks alongside the
+/// blocks which store data so that a bounded number of lost or corrupted blocks can be
+/// reconstructed with [`KeyRepo::repair`] without keeping a full replica of the repository.
+///
+/// Parity blocks are computed when changes are committed and when the repository is cleaned.
+/// Blocks which have been written since the last commit are not protected.
+///
+/// [`KeyRepo::repair`]: crate::repo::key::KeyRepo::repair
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Parity {
+    /// Do not store parity blocks.
+    #[default]
+    None,
+
+    /// Store parity blocks computed with a Reed-Solomon code.
+    ///
+    /// Blocks in the data store are divided into groups of `data_blocks` blocks, and
+    /// `parity_blocks` parity blocks are stored for each group. Up to `parity_blocks` blocks in
+    /// each group can be reconstructed, including the parity blocks themselves. This increases
+    /// the size of the repository by roughly `parity_blocks / data_blocks`.
+    ///
+    /// Both values must be greater than zero, and their sum must be at most 256.
+    #[cfg(feature = "erasure-coding")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "erasure-coding")))]
+    ReedSolomon {
+        /// The number of data blocks in each group.
+        data_blocks: u8,
+
+        /// The number of parity blocks stored for each group.
+        parity_blocks: u8,
+    },
+}
+
+impl Parity {
+    /// Return a reasonable default value of `Parity::ReedSolomon`.
+    ///
+    /// This stores 2 parity blocks for every 10 data blocks.
+    #[cfg(feature = "erasure-coding")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "erasure-coding")))]
+    pub const fn reed_solomon() -> Self {
+        Parity::ReedSolomon {
+            data_blocks: 10,
+            parity_blocks: 2,
+        }
+    }
+
+    /// Return an error if this parity method is not valid.
+    ///
+    /// # Errors
+    /// - `Error::Io`: The number of data blocks or parity blocks is invalid.
+    pub(super) fn validate(&self) -> crate::Result<()> {
+        match self {
+            Parity::None => Ok(()),
+            #[cfg(feature = "erasure-coding")]
+            Parity::ReedSolomon {
+                data_blocks,
+                parity_blocks,
+            } => codec(usize::from(*data_blocks), usize::from(*parity_blocks)).map(|_| ()),
+        }
+    }
+}
+
+/// A group of blocks in the data store which are protected by the same parity blocks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ParityGroup {
+    /// The IDs of the data blocks in the group.
+    ///
+    /// If there are fewer data blocks than the parity method calls for, the rest are treated as
+    /// empty.
+    blocks: Vec<Uuid>,
+
+    /// The size of each data block in `blocks`.
+    sizes: Vec<u32>,
+
+    /// The IDs of the parity blocks for the group.
+    parity: Vec<Uuid>,
+}
+
+/// The parity groups in a repository.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ParityIndex {
+    groups: Vec<ParityGroup>,
+}
+
+/// Read the parity index from the data store of the repository `state`.
+fn read_index(state: &RepoState) -> crate::Result<ParityIndex> {
+    let encoded_index = match state
+        .store
+        .lock()
+        .recover()
+        .read_block(PARITY_INDEX_BLOCK_ID)
+        .map_err(crate::Error::from_store)?
+    {
+        Some(encoded_index) => encoded_index,
+        None => return Ok(ParityIndex::default()),
+    };
+    from_read(state.decode_reader(encoded_index)?).map_err(|_| crate::Error::Corrupt)
+}
+
+/// Write the parity `index` to the data store of the repository `state`.
+#[cfg(feature = "erasure-coding")]
+fn write_index(state: &RepoState, index: &ParityIndex) -> crate::Result<()> {
+    let encoded_index = state.encode_value(index)?;
+    state
+        .store
+        .lock()
+        .recover()
+        .write_block(PARITY_INDEX_BLOCK_ID, &encoded_index)
+        .map_err(crate::Error::from_store)
+}
+
+/// Return the IDs of the parity blocks in the repository `state`.
+pub fn parity_blocks(state: &RepoState) -> crate::Result<HashSet<Uuid>> {
+    if state.metadata.config.parity == Parity::None {
+        return Ok(HashSet::new());
+    }
+
+    Ok(read_index(state)?
+        .groups
+        .into_iter()
+        .flat_map(|group| group.parity)
+        .collect())
+}
+
+/// Compute parity blocks for the blocks in the repository `state` which aren't protected yet.
+///
+/// This also removes the parity blocks for groups which contain blocks that are no longer
+/// referenced. If `compact` is `true`, groups which have fewer data blocks than the parity method
+/// calls for are also regrouped.
+///
+/// Parity blocks are not maintained when using optimistic concurrency, because writing the parity
+/// index isn't synchronized between writers.
+#[cfg_attr(not(feature = "erasure-coding"), allow(unused_variables))]
+pub fn protect(state: &RepoState, compact: bool) -> crate::Result<()> {
+    if state.read_only || state.optimistic {
+        return Ok(());
+    }
+
+    match state.metadata.config.parity {
+        Parity::None => Ok(()),
+        #[cfg(feature = "erasure-coding")]
+        Parity::ReedSolomon {
+            data_blocks,
+            parity_blocks,
+        } => protect_blocks(
+            state,
+            compact,
+            usize::from(data_blocks),
+            usize::from(parity_blocks),
+        ),
+    }
+}
+
+/// Reconstruct the blocks in the repository `state` which are missing or corrupt.
+#[cfg_attr(not(feature = "erasure-coding"), allow(unused_variables))]
+pub fn repair(state: &RepoState, progress: &ProgressReporter) -> crate::Result<RepairReport> {
+    match state.metadata.config.parity {
+        Parity::None => Ok(RepairReport::default()),
+        #[cfg(feature = "erasure-coding")]
+        Parity::ReedSolomon {
+            data_blocks,
+            parity_blocks,
+        } => repair_blocks(
+            state,
+            progress,
+            usize::from(data_blocks),
+            usize::from(parity_blocks),
+        ),
+    }
+}
+
+/// Return a Reed-Solomon codec with the given number of data and parity shards.
+///
+/// # Errors
+/// - `Error::Io`: The number of data shards or parity shards is invalid.
+#[cfg(feature = "erasure-coding")]
+fn codec(data_shards: usize, parity_shards: usize) -> crate::Result<ReedSolomon> {
+    ReedSolomon::new(data_shards, parity_shards).map_err(|_| {
+        crate::Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The number of data blocks or parity blocks is invalid.",
+        ))
+    })
+}
+
+/// Return the IDs of the blocks in the data store which store chunks referenced by the repository
+/// `state`.
+///
+/// If packing is enabled, these are the IDs of packs.
+#[cfg(feature = "erasure-coding")]
+fn stored_blocks(state: &RepoState) -> HashSet<Uuid> {
+    let mut stored_blocks = HashSet::new();
+    for info in state.chunks.values() {
+        if info.inline.is_some() {
+            continue;
+        }
+        match state.packs.get(&info.block_id) {
+            Some(index_list) => {
+                stored_blocks.extend(index_list.iter().map(|pack_index| pack_index.id))
+            }
+            None => {
+                stored_blocks.insert(info.block_id);
+            }
+        }
+    }
+    stored_blocks
+}
+
+/// Read the block with the given `id` from the data store of the repository `state`.
+///
+/// This returns `None` if the block is missing or fails its checksum.
+#[cfg(feature = "erasure-coding")]
+fn read_shard(state: &RepoState, id: Uuid) -> crate::Result<Option<Vec<u8>>> {
+    let result = state.store.lock().recover().read_block(id);
+    match result {
+        Ok(data) => Ok(data),
+        Err(error) => match crate::Error::from_store(error) {
+            crate::Error::CorruptBlock(_) => Ok(None),
+            error => Err(error),
+        },
+    }
+}
+
+/// Pad each of the given `shards` to the same size and return that size.
+#[cfg(feature = "erasure-coding")]
+fn pad_shards(shards: &mut [Vec<u8>]) -> usize {
+    // The codec doesn't accept empty shards.
+    let shard_size = shards.iter().map(Vec::len).max().unwrap_or(0).max(1);
+    for shard in shards.iter_mut() {
+        shard.resize(shard_size, 0);
+    }
+    shard_size
+}
+
+/// The implementation of [`protect`] for `Parity::ReedSolomon`.
+#[cfg(feature = "erasure-coding")]
+fn protect_blocks(
+    state: &RepoState,
+    compact: bool,
+    data_shards: usize,
+    parity_shards: usize,
+) -> crate::Result<()> {
+    let codec = codec(data_shards, parity_shards)?;
+    let stored_blocks = stored_blocks(state);
+    let mut index = read_index(state)?;
+
+    // Dissolve groups which contain blocks that are no longer referenced so that their remaining
+    // blocks can be regrouped.
+    let mut obsolete_parity = Vec::new();
+    index.groups.retain(|group| {
+        let is_current = group.blocks.iter().all(|id| stored_blocks.contains(id))
+            && !(compact && group.blocks.len() < data_shards);
+        if !is_current {
+            obsolete_parity.extend_from_slice(&group.parity);
+        }
+        is_current
+    });
+
+    let protected_blocks = index
+        .groups
+        .iter()
+        .flat_map(|group| group.blocks.iter().copied())
+        .collect::<HashSet<_>>();
+    let mut unprotected_blocks = stored_blocks
+        .difference(&protected_blocks)
+        .copied()
+        .collect::<Vec<_>>();
+    unprotected_blocks.sort();
+
+    if unprotected_blocks.is_empty() && obsolete_parity.is_empty() {
+        return Ok(());
+    }
+
+    for stripe in unprotected_blocks.chunks(data_shards) {
+        let mut blocks = Vec::with_capacity(data_shards);
+        let mut shards = Vec::with_capacity(data_shards + parity_shards);
+        for id in stripe {
+            // Blocks which can't be read can't be protected.
+            if let Some(data) = read_shard(state, *id)? {
+                blocks.push(*id);
+                shards.push(data);
+            }
+        }
+        if blocks.is_empty() {
+            continue;
+        }
+
+        let sizes = shards.iter().map(|shard| shard.len() as u32).collect();
+        let shard_size = pad_shards(&mut shards);
+        shards.resize(data_shards + parity_shards, vec![0; shard_size]);
+        codec
+            .encode(&mut shards)
+            .expect("The shards do not have the same size.");
+
+        let mut parity = Vec::with_capacity(parity_shards);
+        for shard in &shards[data_shards..] {
+            let id = Uuid::new_v4();
+            state
+                .store
+                .lock()
+                .recover()
+                .write_block(id, shard)
+                .map_err(crate::Error::from_store)?;
+            parity.push(id);
+        }
+
+        index.groups.push(ParityGroup {
+            blocks,
+            sizes,
+            parity,
+        });
+    }
+
+    // Write the index before removing the old parity blocks so that every group in the index
+    // always has its parity blocks. Parity blocks which were written but aren't in the index are
+    // removed the next time the repository is cleaned.
+    write_index(state, &index)?;
+    state
+        .store
+        .lock()
+        .recover()
+        .remove_blocks(&obsolete_parity)
+        .map_err(crate::Error::from_store)?;
+
+    Ok(())
+}
+
+/// The implementation of [`repair`] for `Parity::ReedSolomon`.
+#[cfg(feature = "erasure-coding")]
+fn repair_blocks(
+    state: &RepoState,
+    progress: &ProgressReporter,
+    data_shards: usize,
+    parity_shards: usize,
+) -> crate::Result<RepairReport> {
+    let codec = codec(data_shards, parity_shards)?;
+    let stored_blocks = stored_blocks(state);
+    let index = read_index(state)?;
+
+    let mut report = RepairReport::default();
+    let total_groups = Some(index.groups.len() as u64);
+
+    for (checked_groups, group) in index.groups.iter().enumerate() {
+        // Repairing can be safely stopped between groups.
+        progress.report(Operation::Repair, checked_groups as u64, total_groups)?;
+
+        let shard_size = group.sizes.iter().copied().max().unwrap_or(0).max(1) as usize;
+
+        // A block is damaged if it's missing, fails its checksum, or has the wrong size.
+        let mut shards = Vec::with_capacity(data_shards + parity_shards);
+        for (id, size) in group.blocks.iter().zip(&group.sizes) {
+            let shard = read_shard(state, *id)?
+                .filter(|data| data.len() == *size as usize)
+                .map(|mut data| {
+                    data.resize(shard_size, 0);
+                    data
+                });
+            shards.push(shard);
+        }
+        shards.resize(data_shards, Some(vec![0; shard_size]));
+        for id in &group.parity {
+            shards.push(read_shard(state, *id)?.filter(|data| data.len() == shard_size));
+        }
+
+        let damaged_shards = shards
+            .iter()
+            .enumerate()
+            .filter(|(_, shard)| shard.is_none())
+            .map(|(position, _)| position)
+            .collect::<Vec<_>>();
+        if damaged_shards.is_empty() {
+            continue;
+        }
+
+        // Data blocks which are no longer referenced may have been removed by cleaning the
+        // repository, so they don't need to be repaired.
+        let block_at = |position: usize| {
+            if position < data_shards {
+                let id = group.blocks[position];
+                (
+                    id,
+                    group.sizes[position] as usize,
+                    stored_blocks.contains(&id),
+                )
+            } else {
+                (group.parity[position - data_shards], shard_size, true)
+            }
+        };
+
+        if codec.reconstruct(&mut shards).is_err() {
+            report.unrecoverable_blocks.extend(
+                damaged_shards
+                    .into_iter()
+                    .filter(|position| *position < data_shards)
+                    .map(block_at)
+                    .filter(|(_, _, is_referenced)| *is_referenced)
+                    .map(|(id, _, _)| id),
+            );
+            continue;
+        }
+
+        for position in damaged_shards {
+            let (id, size, is_referenced) = block_at(position);
+            if !is_referenced {
+                continue;
+            }
+            let shard = shards[position]
+                .as_ref()
+                .expect("The shard was not reconstructed.");
+            state
+                .store
+                .lock()
+                .recover()
+                .write_block(id, &shard[..size])
+                .map_err(crate::Error::from_store)?;
+            report.repaired_blocks.push(id);
+        }
+    }
+
+    report.groups = index.groups.len() as u64;
+    progress.notify(Operation::Repair, report.groups, total_groups);
+
+    Ok(report)
+}
@@ -0,0 +1,36 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::sync::{LockResult, PoisonError};
+
+/// An extension trait for acquiring locks which may have been poisoned.
+///
+/// A lock is poisoned when a thread panics while holding it. Rather than panicking on every
+/// operation after that, the repository recovers the guard and keeps using the state behind the
+/// lock. The data store is only changed by complete operations, so a panic can't leave committed
+/// data inconsistent, and any uncommitted changes which a panic left half-finished can be
+/// discarded by rolling back. A panic while the repository state is locked for writing marks the
+/// state as poisoned so that it can't be committed until then.
+pub(crate) trait RecoverPoison<T> {
+    /// Return the guard of the lock, even if the lock was poisoned.
+    fn recover(self) -> T;
+}
+
+impl<T> RecoverPoison<T> for LockResult<T> {
+    fn recover(self) -> T {
+        self.unwrap_or_else(PoisonError::into_inner)
+    }
+}
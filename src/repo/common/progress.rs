@@ -0,0 +1,175 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A long-running operation which reports its progress.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[non_exhaustive]
+pub enum Operation {
+    /// Changes are being committed with [`Commit::commit`].
+    ///
+    /// Progress is measured in steps of the commit process.
+    ///
+    /// [`Commit::commit`]: crate::repo::Commit::commit
+    Commit,
+
+    /// Unused data is being cleaned up with [`Commit::clean`].
+    ///
+    /// Progress is measured in blocks which have been removed or repacked.
+    ///
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    Clean,
+
+    /// The integrity of the repository is being verified.
+    ///
+    /// Progress is measured in chunks which have been verified.
+    Verify,
+
+    /// Missing or corrupt blocks are being reconstructed with [`KeyRepo::repair`].
+    ///
+    /// Progress is measured in parity groups which have been checked.
+    ///
+    /// [`KeyRepo::repair`]: crate::repo::key::KeyRepo::repair
+    Repair,
+
+    /// A directory tree is being copied into a [`FileRepo`].
+    ///
+    /// Progress is measured in files which have been archived. The total is not known in advance.
+    ///
+    /// [`FileRepo`]: crate::repo::file::FileRepo
+    ArchiveTree,
+
+    /// A tree of entries is being copied out of a [`FileRepo`].
+    ///
+    /// Progress is measured in entries which have been extracted.
+    ///
+    /// [`FileRepo`]: crate::repo::file::FileRepo
+    ExtractTree,
+
+    /// A directory tree is being synchronized with a [`FileRepo`].
+    ///
+    /// Progress is measured in entries which have been compared. The total is not known in
+    /// advance.
+    ///
+    /// [`FileRepo`]: crate::repo::file::FileRepo
+    SyncTree,
+}
+
+/// The progress of a long-running operation.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub struct Progress {
+    /// The operation which is in progress.
+    pub operation: Operation,
+
+    /// The number of units of work which have been completed.
+    pub completed: u64,
+
+    /// The total number of units of work, or `None` if it is not known in advance.
+    pub total: Option<u64>,
+}
+
+/// A token for cancelling long-running operations.
+///
+/// This token can be cloned and sent to other threads. Once [`cancel`] is called on any clone of
+/// the token, operations on any repository the token is registered with will stop at the next
+/// opportunity and return `Error::Cancelled`.
+///
+/// [`cancel`]: crate::repo::CancellationToken::cancel
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Create a new token which has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel all operations which use this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Return whether this token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// A progress handler.
+type ProgressHandler = Box<dyn Fn(Progress) + Send + Sync>;
+
+/// The progress handler and cancellation token registered with a repository.
+#[derive(Default)]
+pub struct ProgressReporter {
+    handler: Option<ProgressHandler>,
+    token: Option<CancellationToken>,
+}
+
+impl Debug for ProgressReporter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressReporter")
+            .field("handler", &self.handler.is_some())
+            .field("token", &self.token)
+            .finish()
+    }
+}
+
+impl ProgressReporter {
+    /// Set the `handler` which is called with the progress of long-running operations.
+    pub fn set_handler(&mut self, handler: ProgressHandler) {
+        self.handler = Some(handler);
+    }
+
+    /// Set the `token` which is used to cancel long-running operations.
+    pub fn set_token(&mut self, token: CancellationToken) {
+        self.token = Some(token);
+    }
+
+    /// Report the progress of an `operation` without allowing it to be cancelled.
+    ///
+    /// This should be used once an operation has reached a point where stopping it would leave the
+    /// repository in an inconsistent state.
+    pub fn notify(&self, operation: Operation, completed: u64, total: Option<u64>) {
+        if let Some(handler) = &self.handler {
+            handler(Progress {
+                operation,
+                completed,
+                total,
+            });
+        }
+    }
+
+    /// Report the progress of an `operation` and check whether it has been cancelled.
+    ///
+    /// # Errors
+    /// - `Error::Cancelled`: The operation was cancelled.
+    pub fn report(
+        &self,
+        operation: Operation,
+        completed: u64,
+        total: Option<u64>,
+    ) -> crate::Result<()> {
+        self.notify(operation, completed, total);
+        match &self.token {
+            Some(token) if token.is_cancelled() => Err(crate::Error::Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
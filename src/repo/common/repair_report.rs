@@ -0,0 +1,46 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use uuid::Uuid;
+
+/// A report of the blocks which were reconstructed when repairing a repository.
+///
+/// This is returned by [`KeyRepo::repair`].
+///
+/// [`KeyRepo::repair`]: crate::repo::key::KeyRepo::repair
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct RepairReport {
+    /// The number of parity groups which were checked.
+    pub groups: u64,
+
+    /// The IDs of the blocks which were missing or corrupt and were reconstructed.
+    ///
+    /// This includes parity blocks. If packing is enabled, these are the IDs of packs.
+    pub repaired_blocks: Vec<Uuid>,
+
+    /// The IDs of the blocks which were missing or corrupt and could not be reconstructed.
+    ///
+    /// If packing is enabled, these are the IDs of packs.
+    pub unrecoverable_blocks: Vec<Uuid>,
+}
+
+impl RepairReport {
+    /// Return whether every missing or corrupt block was reconstructed.
+    pub fn is_complete(&self) -> bool {
+        self.unrecoverable_blocks.is_empty()
+    }
+}
@@ -17,31 +17,55 @@
 use std::borrow::Borrow;
 use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
+use std::io::{self, Read, Write};
+use std::iter;
 use std::mem;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
 
 use hex_literal::hex;
 use rmp_serde::{from_read, to_vec};
 use secrecy::ExposeSecret;
+use serde::de::DeserializeOwned;
 use uuid::Uuid;
 
-use crate::store::DataStore;
-
+use super::archive;
+use super::audit_log::{read_audit_log, AuditEntry, AuditEvent, AUDIT_LOG_BLOCK_ID};
+use super::background_clean::BackgroundClean;
 use super::chunk_store::{
     EncodeBlock, ReadBlock, ReadChunk, StoreReader, StoreState, StoreWriter, WriteBlock,
 };
+use super::chunking::Chunking;
+use super::clean_report::CleanReport;
 use super::commit::Commit;
+use super::commit_marker::{CommitMarker, COMMIT_MARKER_BLOCK_ID};
 use super::encryption::{EncryptionKey, KeySalt};
-use super::handle::{chunk_hash, ObjectHandle, ObjectId};
+use super::handle::{Extent, ObjectHandle, ObjectId};
+use super::hooks::{Hooks, TransactionEvent};
 use super::id_table::{IdTable, UniqueId};
+use super::inspect::ChunkReport;
 use super::key::Key;
-use super::metadata::{Header, RepoInfo};
+use super::lazy_header::{self, LazyHeader};
+use super::lease::LEASE_BLOCK_ID;
+use super::metadata::{
+    read_header, read_header_lazily, Header, HeaderDelta, RepoInfo, RepoMetadata,
+};
 use super::object::Object;
+use super::object_map;
 use super::object_store::{ObjectReader, ObjectWriter};
-use super::open_repo::OpenRepo;
+use super::open_repo::{OpenRepo, DEFAULT_BRANCH};
 use super::packing::Packing;
-use super::savepoint::{KeyRestore, RestoreSavepoint, Savepoint};
-use super::state::{InstanceInfo, ObjectState, RepoState};
+use super::parallel;
+use super::parity::{self, PARITY_INDEX_BLOCK_ID};
+use super::poison::RecoverPoison;
+use super::progress::{CancellationToken, Operation, Progress, ProgressReporter};
+use super::repair_report::RepairReport;
+use super::savepoint::{KeyRestore, RefreshBackup, RestoreSavepoint, Savepoint};
+use super::state::{HeaderChanges, InstanceInfo, ObjectState, RepoState, WriteState};
+use super::stats::RepoStats;
+#[cfg(feature = "metrics")]
+use super::telemetry;
+use super::verify::{chunk_is_intact, VerifyPool};
 
 /// The block ID of the block which stores the repository metadata.
 pub(super) const METADATA_BLOCK_ID: Uuid =
@@ -51,29 +75,264 @@ pub(super) const METADATA_BLOCK_ID: Uuid =
 pub(super) const VERSION_BLOCK_ID: Uuid =
     Uuid::from_bytes(hex!("cbf28b1c 3550 11ea 8cb0 87d7a14efe10"));
 
-/// Return a list of blocks in the data store excluding those used to store metadata.
+/// The number of blocks to remove from the data store at a time when cleaning the repository.
+const REMOVE_BATCH_SIZE: usize = 256;
+
+/// Renew the lease held by the repository with the given `state`, if it holds one.
+fn renew_lease(state: &RepoState) -> crate::Result<()> {
+    match &state.lease {
+        Some(lease) => lease.renew(&mut **state.store.lock().recover()),
+        None => Ok(()),
+    }
+}
+
+/// Return an error if another writer has committed since the repository with the given `state` last
+/// read the repository metadata.
+///
+/// This only checks the data store if we're using optimistic concurrency.
+///
+/// # Errors
+/// - `Error::Conflict`: Another writer has committed.
+/// - `Error::Corrupt`: The repository metadata could not be read.
+/// - `Error::Store`: An error occurred with the data store.
+fn check_generation(state: &RepoState) -> crate::Result<()> {
+    if !state.optimistic {
+        return Ok(());
+    }
+
+    let serialized_metadata = state
+        .store
+        .lock()
+        .recover()
+        .read_block(METADATA_BLOCK_ID)
+        .map_err(crate::Error::from_store)?
+        .ok_or(crate::Error::Corrupt)?;
+    let current_metadata: RepoMetadata =
+        from_read(serialized_metadata.as_slice()).map_err(|_| crate::Error::Corrupt)?;
+    if current_metadata.generation != state.metadata.generation {
+        return Err(crate::Error::Conflict);
+    }
+
+    Ok(())
+}
+
+/// Return a list of blocks in the data store excluding those used to store metadata and parity.
 fn list_data_blocks(state: &RepoState) -> crate::Result<Vec<Uuid>> {
+    let parity_blocks = parity::parity_blocks(state)?;
     let all_blocks = state
         .store
         .lock()
-        .unwrap()
+        .recover()
         .list_blocks()
-        .map_err(crate::Error::Store)?;
+        .map_err(crate::Error::from_store)?;
 
     Ok(all_blocks
         .iter()
         .copied()
         .filter(|id| {
-            *id != METADATA_BLOCK_ID && *id != VERSION_BLOCK_ID && *id != state.metadata.header_id
+            *id != METADATA_BLOCK_ID
+                && *id != VERSION_BLOCK_ID
+                && *id != LEASE_BLOCK_ID
+                && *id != AUDIT_LOG_BLOCK_ID
+                && *id != PARITY_INDEX_BLOCK_ID
+                && *id != COMMIT_MARKER_BLOCK_ID
+                && *id != state.metadata.header_id
+                && !state.metadata.header_deltas.contains(id)
+                && !state.metadata.header_shards.contains(id)
+                && !parity_blocks.contains(id)
         })
         .collect())
 }
 
+/// Return the blocks in the data store which aren't referenced by the repository with the given
+/// `state` and can be removed without repacking any data.
+///
+/// When packing is enabled, this only includes packs which don't contain any referenced blocks.
+fn removable_blocks(state: &RepoState) -> crate::Result<Vec<Uuid>> {
+    let mut referenced_blocks = state
+        .chunks
+        .values()
+        .map(|info| info.block_id)
+        .collect::<HashSet<_>>();
+
+    // Blocks which are still being written in the background aren't referenced yet, but they
+    // will be once the objects writing them are committed.
+    if let Some(pool) = &state.pool {
+        referenced_blocks.extend(pool.unreferenced());
+    }
+
+    let data_blocks = list_data_blocks(state)?;
+
+    Ok(match &state.metadata.config.packing {
+        Packing::None => data_blocks
+            .into_iter()
+            .filter(|block_id| !referenced_blocks.contains(block_id))
+            .collect(),
+        Packing::Fixed(_) | Packing::Variable(_) => {
+            let referenced_packs = state
+                .packs
+                .iter()
+                .filter(|(block_id, _)| referenced_blocks.contains(block_id))
+                .flat_map(|(_, index_list)| index_list.iter().map(|pack_index| pack_index.id))
+                .collect::<HashSet<_>>();
+            data_blocks
+                .into_iter()
+                .filter(|pack_id| !referenced_packs.contains(pack_id))
+                .collect()
+        }
+    })
+}
+
+/// The changes which cleaning a repository would make to the data store.
+struct CleanPlan {
+    /// The blocks which are referenced by the repository or were referenced after the previous
+    /// commit.
+    referenced_blocks: HashSet<Uuid>,
+
+    /// The blocks, or packs if packing is enabled, which would be removed from the data store.
+    blocks_to_remove: Vec<Uuid>,
+
+    /// The referenced blocks which would be written to new packs before their old packs are
+    /// removed.
+    ///
+    /// This is always empty if packing is disabled.
+    blocks_to_repack: Vec<Uuid>,
+}
+
+/// Decide which blocks need to be removed or repacked to clean the repository with the given
+/// `state`, where `previous_header` is the header from the previous commit.
+fn plan_clean(state: &RepoState, previous_header: &Header) -> crate::Result<CleanPlan> {
+    // We need to find the set of blocks which are either currently referenced by the repository
+    // or were referenced after the previous commit. It's important that we don't clean up
+    // blocks which were referenced after the previous commit because that would make it
+    // impossible to roll back changes, and cleaning may happen before the repository is
+    // committed.
+    let mut referenced_blocks = state
+        .chunks
+        .values()
+        .map(|info| info.block_id)
+        .collect::<HashSet<_>>();
+    let previous_referenced_blocks = previous_header.chunks.values().map(|info| info.block_id);
+    referenced_blocks.extend(previous_referenced_blocks);
+
+    // Blocks which are still being written in the background aren't referenced yet, but they
+    // will be once the objects writing them are committed.
+    if let Some(pool) = &state.pool {
+        referenced_blocks.extend(pool.unreferenced());
+    }
+
+    match &state.metadata.config.packing {
+        Packing::None => {
+            // When packing is disabled, we can just remove the unreferenced blocks from the
+            // data store directly.
+            let blocks_to_remove = list_data_blocks(state)?
+                .into_iter()
+                .filter(|block_id| !referenced_blocks.contains(block_id))
+                .collect::<Vec<_>>();
+            Ok(CleanPlan {
+                referenced_blocks,
+                blocks_to_remove,
+                blocks_to_repack: Vec::new(),
+            })
+        }
+        Packing::Fixed(_) | Packing::Variable(_) => {
+            // When packing is enabled, we need to repack the packs which contain unreferenced
+            // blocks.
+
+            // Get an iterator of block IDs and the list of packs they're contained in.
+            let blocks_to_packs = state.packs.iter().chain(previous_header.packs.iter());
+
+            // Get a map of pack IDs to the set of blocks contained in them.
+            let mut packs_to_blocks = HashMap::new();
+            for (block_id, index_list) in blocks_to_packs {
+                for pack_index in index_list {
+                    packs_to_blocks
+                        .entry(pack_index.id)
+                        .or_insert_with(HashSet::new)
+                        .insert(*block_id);
+                }
+            }
+
+            // The list of IDs of packs which contain at least one unreferenced block.
+            let mut packs_to_remove = Vec::new();
+
+            // The list of blocks which need to be repacked. These are referenced blocks which
+            // are contained in packs which contain at least one unreferenced block.
+            let mut blocks_to_repack = Vec::new();
+
+            // Iterate over the IDs of packs which are contained in the data store.
+            for pack_id in list_data_blocks(state)? {
+                match packs_to_blocks.get(&pack_id) {
+                    Some(contained_blocks) => {
+                        let contains_unreferenced_blocks = contained_blocks
+                            .iter()
+                            .any(|block_id| !referenced_blocks.contains(block_id));
+                        if contains_unreferenced_blocks {
+                            let contained_referenced_blocks =
+                                contained_blocks.intersection(&referenced_blocks).copied();
+                            packs_to_remove.push(pack_id);
+                            blocks_to_repack.extend(contained_referenced_blocks);
+                        }
+                    }
+                    // This pack does not contain any blocks that we know about. We can remove
+                    // it.
+                    None => packs_to_remove.push(pack_id),
+                }
+            }
+
+            Ok(CleanPlan {
+                referenced_blocks,
+                blocks_to_remove: packs_to_remove,
+                blocks_to_repack,
+            })
+        }
+    }
+}
+
+/// Read the header described by `metadata` from the data store.
+///
+/// If the repository is read-only, only the parts of the header which aren't split into shards are
+/// read, and the returned `LazyHeader` is used to load the rest on demand.
+fn read_header_in(
+    state: &RepoState,
+    metadata: &RepoMetadata,
+) -> crate::Result<(Header, Option<LazyHeader>)> {
+    let mut store = state.store.lock().recover();
+    let decode = |data: Vec<u8>| state.decode_reader(data);
+    if state.read_only {
+        let (header, lazy_header) = read_header_lazily(&mut **store, metadata, decode)?;
+        Ok((header, Some(lazy_header)))
+    } else {
+        Ok((read_header(&mut **store, metadata, decode)?, None))
+    }
+}
+
+/// The encoded blocks which store a new version of the repository header.
+enum HeaderBlocks {
+    /// The whole header followed by each of the shards of its chunk and pack maps.
+    Full(Vec<u8>, Vec<Vec<u8>>),
+
+    /// A delta containing the changes to the header since the last commit.
+    Delta(Vec<u8>),
+}
+
 /// An object store which maps keys to seekable binary blobs.
 ///
 /// See [`crate::repo::key`] for more information.
+///
+/// The contents of the repository are behind one read-write lock which is global to the
+/// repository. See [the module-level documentation](crate::repo#sharing-between-threads) for how
+/// this affects sharing a repository between threads.
 #[derive(Debug)]
-pub struct KeyRepo<K: Key> {
+pub struct KeyRepo<K: Key>(RwLock<KeyRepoInner<K>>);
+
+/// The contents of a [`KeyRepo`].
+///
+/// These are protected by a single lock, rather than one lock per field, so that the repository
+/// can be modified through a shared reference. Each public method of `KeyRepo` locks the repository
+/// and calls the method of the same name on this type.
+#[derive(Debug)]
+pub(crate) struct KeyRepoInner<K: Key> {
     /// The state for this repository.
     pub(super) state: Arc<RwLock<RepoState>>,
 
@@ -97,42 +356,522 @@ pub struct KeyRepo<K: Key> {
     /// This ID changes each time the repository is opened or committed. It is used to invalidate
     /// savepoints.
     pub(super) transaction_id: Arc<Uuid>,
+
+    /// The hooks which run at transaction boundaries.
+    pub(super) hooks: Hooks,
+
+    /// The handler for reporting the progress of long-running operations.
+    pub(super) progress: ProgressReporter,
+
+    /// The pass which is removing unreferenced blocks in the background, if there is one.
+    pub(super) background_clean: Option<BackgroundClean>,
 }
 
-impl<K: Key> OpenRepo for KeyRepo<K> {
-    type Key = K;
+impl<K: Key> OpenRepo for KeyRepo<K> {
+    type Key = K;
+
+    const VERSION_ID: Uuid = Uuid::from_bytes(hex!("989a6a76 9d8b 46b7 9c05 d1c5e0d9471a"));
+
+    fn open_repo(repo: KeyRepo<Self::Key>) -> crate::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(repo)
+    }
+
+    fn create_repo(repo: KeyRepo<Self::Key>) -> crate::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(repo)
+    }
+
+    fn into_repo(self) -> crate::Result<KeyRepo<Self::Key>> {
+        Ok(self)
+    }
+}
+
+impl<K: Key> KeyRepo<K> {
+    /// Wrap the given `inner` contents of a repository in a new `KeyRepo`.
+    pub(crate) fn from_inner(inner: KeyRepoInner<K>) -> Self {
+        KeyRepo(RwLock::new(inner))
+    }
+
+    /// Consume this repository and return its contents.
+    pub(crate) fn into_inner(self) -> KeyRepoInner<K> {
+        self.0.into_inner().recover()
+    }
+
+    /// Lock the contents of this repository for reading.
+    pub(crate) fn inner(&self) -> RwLockReadGuard<'_, KeyRepoInner<K>> {
+        self.0.read().recover()
+    }
+
+    /// Lock the contents of this repository for writing.
+    pub(crate) fn inner_mut(&self) -> RwLockWriteGuard<'_, KeyRepoInner<K>> {
+        self.0.write().recover()
+    }
+
+    /// Return whether there is an object with the given `key` in this repository.
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.inner().contains(key)
+    }
+
+    /// Add a new object with the given `key` to the repository and return it.
+    ///
+    /// If another object with the same `key` already exists, it is replaced.
+    pub fn insert(&self, key: K) -> Object {
+        self.inner_mut().insert(key)
+    }
+
+    /// Remove the object with the given `key` from the repository.
+    ///
+    /// This returns `true` if the object was removed or `false` if it didn't exist.
+    ///
+    /// The space used by the given object isn't reclaimed in the backing data store until changes
+    /// are committed and [`Commit::clean`] is called.
+    ///
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    pub fn remove<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.inner_mut().remove(key)
+    }
+
+    /// Return an object for reading and writing the object with the given `key`.
+    ///
+    /// This returns `None` if there is no object with the given `key` in the repository.
+    pub fn object<Q>(&self, key: &Q) -> Option<Object>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.inner().object(key)
+    }
+
+    /// Return an iterator over all the keys of objects in this repository.
+    ///
+    /// The keys are copied out of the repository before this returns, so the repository isn't
+    /// locked while the iterator is in use. Objects which are inserted or removed afterward,
+    /// including by other threads, aren't reflected in the returned iterator.
+    pub fn keys(&self) -> impl Iterator<Item = K> {
+        self.inner().keys().cloned().collect::<Vec<_>>().into_iter()
+    }
+
+    /// Copy the object at `source` to `dest`.
+    ///
+    /// If another object already exists at `dest`, it is replaced.
+    ///
+    /// This returns `true` if the object was copied or `false` if there was no object at source.
+    ///
+    /// This is a cheap operation which does not require copying the bytes in the object. The copy
+    /// shares its list of chunks with the original until one of them is modified.
+    pub fn copy<Q>(&self, source: &Q, dest: K) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.inner_mut().copy(source, dest)
+    }
+
+    /// Export the objects with the given `keys` to a portable archive written to `writer`.
+    ///
+    /// The archive contains the raw contents of each object, so it does not depend on the
+    /// configuration of this repository. It can be read back into any `KeyRepo` with the same key
+    /// type using [`import`], or it can be parsed by other programs. Keys which don't exist in
+    /// the repository are skipped.
+    ///
+    /// # Format
+    /// The archive is a stream which starts with the eight bytes `ACIDEXP1` and is followed by a
+    /// sequence of entries. Each entry consists of:
+    ///
+    /// 1. The byte `1`.
+    /// 2. The length of the serialized key in bytes as a 32-bit little-endian integer.
+    /// 3. The key serialized using MessagePack.
+    /// 4. The length of the object's contents in bytes as a 64-bit little-endian integer.
+    /// 5. The contents of the object.
+    ///
+    /// The end of the archive is marked with the byte `0`.
+    ///
+    /// # Errors
+    /// - `Error::Serialize`: A key could not be serialized.
+    /// - `Error::TransactionInProgress`: A transaction is in progress for one of the objects.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`import`]: crate::repo::key::KeyRepo::import
+    pub fn export<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a K>,
+        writer: impl Write,
+    ) -> crate::Result<()>
+    where
+        K: 'a,
+    {
+        self.inner().export(keys, writer)
+    }
+
+    /// Import objects from an archive created with [`export`], reading it from `reader`.
+    ///
+    /// Objects in the archive replace any existing objects with the same keys. This returns the
+    /// keys of the objects which were imported.
+    ///
+    /// If this returns `Err`, some objects may have already been imported. To make this atomic,
+    /// use [`RestoreSavepoint`].
+    ///
+    /// # Errors
+    /// - `Error::Deserialize`: The archive is malformed.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred or the archive ended unexpectedly.
+    ///
+    /// [`export`]: crate::repo::key::KeyRepo::export
+    /// [`RestoreSavepoint`]: crate::repo::RestoreSavepoint
+    pub fn import(&self, reader: impl Read) -> crate::Result<Vec<K>> {
+        self.inner_mut().import(reader)
+    }
+
+    /// Verify the integrity of all the data in the current instance of the repository.
+    ///
+    /// This returns the set of keys of objects in the current instance which are corrupt.
+    ///
+    /// If you just need to verify the integrity of one object, [`Object::verify`] is faster. If you
+    /// need to verify the integrity of all the data in the repository, however, this can be more
+    /// efficient.
+    ///
+    /// If the repository was opened with more than one thread using [`OpenOptions::threads`],
+    /// chunks are read and hashed by that many worker threads. Reads from the data store are
+    /// still done one at a time unless [`OpenOptions::store_concurrency`] is also set.
+    ///
+    /// This reports its progress to the handler set with [`set_progress_handler`] and can be
+    /// cancelled with the token set with [`set_cancellation_token`].
+    ///
+    /// # Errors
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`Object::verify`]: crate::repo::Object::verify
+    /// [`OpenOptions::threads`]: crate::repo::OpenOptions::threads
+    /// [`OpenOptions::store_concurrency`]: crate::repo::OpenOptions::store_concurrency
+    /// [`set_progress_handler`]: crate::repo::key::KeyRepo::set_progress_handler
+    /// [`set_cancellation_token`]: crate::repo::key::KeyRepo::set_cancellation_token
+    pub fn verify(&self) -> crate::Result<HashSet<K>> {
+        Ok(self.inner().verify()?.into_iter().cloned().collect())
+    }
+
+    /// Return the keys in the current instance which have changed since the last commit.
+    ///
+    /// This includes keys which have been inserted, removed, or modified. A key is considered
+    /// modified if its contents are different from the previous commit, so a key which was
+    /// modified and then changed back is not included. Changes to objects with a transaction in
+    /// progress are not included until those objects are committed.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize the object map from the previous commit.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn changed_keys(&self) -> crate::Result<HashSet<K>> {
+        self.inner().changed_keys()
+    }
+
+    /// Return whether any keys in the current instance have changed since the last commit.
+    ///
+    /// See [`changed_keys`] for details.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize the object map from the previous commit.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`changed_keys`]: crate::repo::key::KeyRepo::changed_keys
+    pub fn has_changes(&self) -> crate::Result<bool> {
+        self.inner().has_changes()
+    }
+
+    /// Delete all data in the current instance of the repository.
+    ///
+    /// This does not delete data from other instances of the repository.
+    ///
+    /// This does not commit changes to the repository.
+    ///
+    /// No data is reclaimed in the backing data store until changes are committed and
+    /// [`Commit::clean`] is called.
+    ///
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    pub fn clear_instance(&self) {
+        self.inner_mut().clear_instance()
+    }
+
+    /// Return the name of the current branch of the current instance.
+    ///
+    /// See [`SwitchBranch`] for more information about branches.
+    ///
+    /// [`SwitchBranch`]: crate::repo::SwitchBranch
+    pub fn branch(&self) -> String {
+        self.inner().branch().to_string()
+    }
+
+    /// Return an iterator over the names of all the branches of the current instance.
+    ///
+    /// This includes the current branch.
+    pub fn branches(&self) -> impl Iterator<Item = String> {
+        self.inner()
+            .branches()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Remove the branch named `name` and all of its contents from the current instance.
+    ///
+    /// This does not commit changes to the repository.
+    ///
+    /// No data is reclaimed in the backing data store until changes are committed and
+    /// [`Commit::clean`] is called.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: There is no branch with the given `name`.
+    /// - `Error::CurrentBranch`: The branch is the current branch.
+    /// - `Error::Deserialize`: Could not deserialize data in the repository.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    pub fn remove_branch(&self, name: &str) -> crate::Result<()> {
+        self.inner_mut().remove_branch(name)
+    }
+
+    /// Merge changes from the branch named `source` into the current branch.
+    ///
+    /// This performs a three-way merge using the branch named `ancestor` as the common ancestor of
+    /// the two branches. Typically, `ancestor` is a branch which was created at the same time as
+    /// `source` and was not modified afterwards.
+    ///
+    /// Objects are compared by their contents. For each key, if the object was inserted, modified,
+    /// or removed in `source` but is unchanged in the current branch relative to `ancestor`, the
+    /// change is applied to the current branch. If the object was changed in both branches and the
+    /// changes are not identical, the key is a conflict and the current branch is left unchanged
+    /// for that key. This returns the keys of all conflicting objects so that they can be resolved
+    /// by the caller.
+    ///
+    /// This is a cheap operation which does not require copying the bytes in any objects.
+    ///
+    /// This does not commit changes to the repository.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: There is no branch named `source` or `ancestor`.
+    /// - `Error::Deserialize`: Could not deserialize data in the repository.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn merge_branch(&self, source: &str, ancestor: &str) -> crate::Result<Vec<K>> {
+        self.inner_mut().merge_branch(source, ancestor)
+    }
+
+    /// Change the password for this repository.
+    ///
+    /// This replaces the existing password with `new_password`. Changing the password does not
+    /// require re-encrypting any data. The change does not take effect until [`Commit::commit`] is
+    /// called.
+    ///
+    /// If encryption is disabled, this method does nothing.
+    ///
+    /// [`Commit::commit`]: crate::repo::Commit::commit
+    pub fn change_password(&self, new_password: &[u8]) {
+        self.inner_mut().change_password(new_password)
+    }
+
+    /// Renew the lease on this repository.
+    ///
+    /// If [`RepoConfig::lease_duration`] is set, the lease on the repository expires unless it is
+    /// renewed within that duration. The lease is renewed automatically each time the repository
+    /// is committed and by a background thread while the repository is open, so you only need to
+    /// call this method to check that the lease is still held or to renew it right away.
+    ///
+    /// If leases are disabled, this method does nothing.
+    ///
+    /// # Errors
+    /// - `Error::Locked`: The lease expired, was removed, or was acquired by another process.
+    /// - `Error::Corrupt`: The lease in the data store is corrupt.
+    /// - `Error::Store`: An error occurred with the data store.
+    ///
+    /// [`RepoConfig::lease_duration`]: crate::repo::RepoConfig::lease_duration
+    pub fn renew_lease(&self) -> crate::Result<()> {
+        self.inner().renew_lease()
+    }
+
+    /// Register a `hook` to run before the given `event`.
+    ///
+    /// If the hook returns `Err`, the operation is vetoed; the repository is unchanged and the
+    /// operation returns `Error::Vetoed` wrapping the hook's error. Hooks run in the order they
+    /// were registered, and once a hook vetoes an operation, the remaining hooks are not run.
+    ///
+    /// Hooks are not stored in the repository; they only apply to this value. They are kept when
+    /// switching instances or branches.
+    pub fn add_before_hook(
+        &self,
+        event: TransactionEvent,
+        hook: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.inner_mut().add_before_hook(event, hook)
+    }
+
+    /// Register a `hook` to run after the given `event` completes successfully.
+    ///
+    /// Hooks run in the order they were registered.
+    ///
+    /// Hooks are not stored in the repository; they only apply to this value. They are kept when
+    /// switching instances or branches.
+    pub fn add_after_hook(&self, event: TransactionEvent, hook: impl FnMut() + Send + 'static) {
+        self.inner_mut().add_after_hook(event, hook)
+    }
+
+    /// Set a `handler` which is called with the progress of long-running operations.
+    ///
+    /// The handler is called periodically by [`Commit::commit`], [`Commit::clean`], and
+    /// [`verify`] as they make progress. Setting a handler replaces any previous handler.
+    ///
+    /// [`Commit::commit`]: crate::repo::Commit::commit
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    /// [`verify`]: crate::repo::key::KeyRepo::verify
+    pub fn set_progress_handler(&self, handler: impl Fn(Progress) + Send + Sync + 'static) {
+        self.inner_mut().set_progress_handler(handler)
+    }
+
+    /// Set a `token` which can be used to cancel long-running operations.
+    ///
+    /// Once the token is cancelled, operations which report their progress stop at the next
+    /// opportunity and return `Error::Cancelled`. A cancelled operation leaves the repository in a
+    /// consistent state, but work it completed before it was cancelled is not undone.
+    ///
+    /// Setting a token replaces any previous token.
+    pub fn set_cancellation_token(&self, token: CancellationToken) {
+        self.inner_mut().set_cancellation_token(token)
+    }
+
+    /// Return this repository's current instance ID.
+    pub fn instance(&self) -> Uuid {
+        self.inner().instance()
+    }
+
+    /// Return information about the repository.
+    pub fn info(&self) -> RepoInfo {
+        self.inner().info()
+    }
+
+    /// Return statistics about the operations performed by the repository since it was opened.
+    ///
+    /// Statistics are shared by every instance of the repository, so switching instances does not
+    /// reset them.
+    pub fn stats(&self) -> RepoStats {
+        self.inner().stats()
+    }
 
-    const VERSION_ID: Uuid = Uuid::from_bytes(hex!("989a6a76 9d8b 46b7 9c05 d1c5e0d9471a"));
+    /// Reset the statistics returned by [`stats`] to zero.
+    ///
+    /// [`stats`]: crate::repo::key::KeyRepo::stats
+    pub fn reset_stats(&self) {
+        self.inner().reset_stats()
+    }
 
-    fn open_repo(repo: KeyRepo<Self::Key>) -> crate::Result<Self>
-    where
-        Self: Sized,
-    {
-        Ok(repo)
+    /// Report what [`Commit::clean`] would do without modifying the data store.
+    ///
+    /// The returned report lists exactly the blocks which cleaning the repository now would remove
+    /// or repack. To measure how much space would be reclaimed, this reads each block which would
+    /// be removed from the data store, so it can take as long as cleaning the repository. This
+    /// works even if the repository was opened in read-only mode.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    pub fn clean_dry_run(&self) -> crate::Result<CleanReport> {
+        self.inner().clean_dry_run()
     }
 
-    fn create_repo(repo: KeyRepo<Self::Key>) -> crate::Result<Self>
-    where
-        Self: Sized,
-    {
-        Ok(repo)
+    /// Reconstruct blocks in the data store which are missing or corrupt using parity blocks.
+    ///
+    /// This reads every block protected by parity blocks and rewrites the ones which are missing,
+    /// have the wrong size, or fail their checksum. Up to the configured number of parity blocks
+    /// can be reconstructed in each parity group. Blocks which were written since the last commit
+    /// are not protected. Corrupt blocks which still have the right size can only be detected if
+    /// [`RepoConfig::block_checksums`] is enabled.
+    ///
+    /// If the repository was not configured with [`RepoConfig::parity`], this does nothing.
+    ///
+    /// # Errors
+    /// - `Error::ReadOnly`: The repository was opened in read-only mode.
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`RepoConfig::block_checksums`]: crate::repo::RepoConfig::block_checksums
+    /// [`RepoConfig::parity`]: crate::repo::RepoConfig::parity
+    pub fn repair(&self) -> crate::Result<RepairReport> {
+        self.inner_mut().repair()
     }
 
-    fn into_repo(self) -> crate::Result<KeyRepo<Self::Key>> {
-        Ok(self)
+    /// Return information about every chunk in the repository, ordered by checksum.
+    ///
+    /// This reports the size of each chunk, how it's stored, and which objects reference it, which
+    /// can be used to analyze how data is deduplicated and fragmented. It reflects the current
+    /// state of the repository, including uncommitted changes. This does not read any data from
+    /// the data store unless the repository was opened in read-only mode.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn inspect_chunks(&self) -> crate::Result<Vec<ChunkReport<K>>> {
+        self.inner().inspect_chunks()
+    }
+
+    /// Return the entries in the audit log in the order they were recorded.
+    ///
+    /// This includes entries which haven't been written to the data store yet. The log is empty if
+    /// [`RepoConfig::audit_log`] was never enabled.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The audit log is corrupt.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`RepoConfig::audit_log`]: crate::repo::RepoConfig::audit_log
+    pub fn audit_log(&self) -> crate::Result<Vec<AuditEntry>> {
+        self.inner().audit_log()
     }
 }
 
-impl<K: Key> KeyRepo<K> {
+impl<K: Key> KeyRepoInner<K> {
     /// Return the `object_id` for the object with the given `handle_id`.
     fn object_id(&self, handle_id: UniqueId) -> ObjectId {
-        let state = self.state.read().unwrap();
+        let state = self.state.read().recover();
         let repo_id = state.metadata.id;
         ObjectId::new(repo_id, self.instance_id, handle_id)
     }
 
-    /// Return whether there is an object with the given `key` in this repository.
-    pub fn contains<Q>(&self, key: &Q) -> bool
+    pub(crate) fn contains<Q>(&self, key: &Q) -> bool
     where
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
@@ -140,16 +879,13 @@ impl<K: Key> KeyRepo<K> {
         self.objects.contains_key(key)
     }
 
-    /// Add a new object with the given `key` to the repository and return it.
-    ///
-    /// If another object with the same `key` already exists, it is replaced.
-    pub fn insert(&mut self, key: K) -> Object {
+    pub(crate) fn insert(&mut self, key: K) -> Object {
         self.remove(&key);
         let handle_id = self.handle_table.next();
         let object_id = self.object_id(handle_id);
         let handle = ObjectHandle {
             id: handle_id,
-            extents: Vec::new(),
+            extents: Arc::new(Vec::new()),
         };
         assert!(!self.objects.contains_key(&key));
         let handle = self
@@ -161,74 +897,51 @@ impl<K: Key> KeyRepo<K> {
 
     /// Remove the given object `handle` from the repository.
     fn remove_handle(&mut self, handle: &ObjectHandle) {
-        let mut state = self.state.write().unwrap();
+        let mut state = self.state.write_state();
         for chunk in handle.chunks() {
-            let chunk_info = state
-                .chunks
-                .get_mut(&chunk)
-                .expect("This chunk was not found in the repository.");
-            chunk_info.references.remove(&handle.id);
-            if chunk_info.references.is_empty() {
-                state.chunks.remove(&chunk);
-            }
+            state.remove_reference(chunk, handle.id);
         }
         self.handle_table.recycle(handle.id);
     }
 
-    /// Remove the object with the given `key` from the repository.
-    ///
-    /// This returns `true` if the object was removed or `false` if it didn't exist.
-    ///
-    /// The space used by the given object isn't reclaimed in the backing data store until changes
-    /// are committed and [`Commit::clean`] is called.
-    ///
-    /// [`Commit::clean`]: crate::repo::Commit::clean
-    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    pub(crate) fn remove<Q>(&mut self, key: &Q) -> bool
     where
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
     {
-        let handle = match self.objects.remove(key) {
-            Some(handle) => handle,
+        let (key, handle) = match self.objects.remove_entry(key) {
+            Some(entry) => entry,
             None => return false,
         };
-        let handle_guard = handle.read().unwrap();
+        let handle_guard = handle.read().recover();
         self.remove_handle(&handle_guard);
+        if let Ok(key) = to_vec(&key) {
+            self.audit(AuditEvent::Remove { key });
+        }
         true
     }
 
-    /// Return an object for reading and writing the object with the given `key`.
-    ///
-    /// This returns `None` if there is no object with the given `key` in the repository.
-    pub fn object<Q>(&self, key: &Q) -> Option<Object>
+    pub(crate) fn object<Q>(&self, key: &Q) -> Option<Object>
     where
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
     {
         let handle = self.objects.get(key)?;
-        let handle_id = handle.read().unwrap().id;
+        let handle_id = handle.read().recover().id;
         Some(Object::new(&self.state, handle, self.object_id(handle_id)))
     }
 
-    /// Return an iterator over all the keys of objects in this repository.
-    pub fn keys(&self) -> impl Iterator<Item = &K> {
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &K> {
         self.objects.keys()
     }
 
-    /// Copy the object at `source` to `dest`.
-    ///
-    /// If another object already exists at `dest`, it is replaced.
-    ///
-    /// This returns `true` if the object was copied or `false` if there was no object at source.
-    ///
-    /// This is a cheap operation which does not require copying the bytes in the object.
-    pub fn copy<Q>(&mut self, source: &Q, dest: K) -> bool
+    pub(crate) fn copy<Q>(&mut self, source: &Q, dest: K) -> bool
     where
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
     {
         let source_chunks = match self.objects.get(source) {
-            Some(handle) => handle.read().unwrap().extents.clone(),
+            Some(handle) => handle.read().recover().extents.clone(),
             None => return false,
         };
 
@@ -240,13 +953,9 @@ impl<K: Key> KeyRepo<K> {
         };
 
         // Update the chunk map to include the new handle in the list of references for each chunk.
-        let mut state = self.state.write().unwrap();
+        let mut state = self.state.write_state();
         for chunk in dest_handle.chunks() {
-            let chunk_info = state
-                .chunks
-                .get_mut(&chunk)
-                .expect("This chunk was not found in the repository.");
-            chunk_info.references.insert(dest_handle.id);
+            state.add_reference(chunk, dest_handle.id);
         }
 
         self.objects
@@ -255,9 +964,111 @@ impl<K: Key> KeyRepo<K> {
         true
     }
 
+    /// Copy `len` bytes of the object at `source` starting at `source_offset` into the object at
+    /// `dest` starting at `dest_offset` by sharing their chunks.
+    ///
+    /// This returns the number of bytes copied, which is less than `len` if the end of `source` is
+    /// reached first. If `dest_offset` is past the end of `dest`, the gap is filled with a hole.
+    ///
+    /// Chunks can't be split, so this returns `None` without changing `dest` if the start or end of
+    /// either range is partway through a chunk.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: There is no object at `source` or `dest`.
+    /// - `Error::TransactionInProgress`: A transaction is in progress for the object at `dest`.
+    /// - `Error::Io`: The end of the range in `dest` would overflow a `u64`.
+    pub(crate) fn copy_range<Q>(
+        &mut self,
+        source: &Q,
+        source_offset: u64,
+        dest: &Q,
+        dest_offset: u64,
+        len: u64,
+    ) -> crate::Result<Option<u64>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let source_handle = Arc::clone(self.objects.get(source).ok_or(crate::Error::NotFound)?);
+        let dest_handle = Arc::clone(self.objects.get(dest).ok_or(crate::Error::NotFound)?);
+
+        // The source and destination may be the same object, so we need to release the lock on
+        // the source handle before locking the destination handle.
+        let source_extents = {
+            let handle = source_handle.read().recover();
+            let source_end = source_offset.saturating_add(len).min(handle.size());
+            if source_offset >= source_end {
+                return Ok(Some(0));
+            }
+            match handle.slice_extents(source_offset..source_end) {
+                Some(extents) => extents,
+                None => return Ok(None),
+            }
+        };
+        let copied_len = source_extents.iter().map(Extent::size).sum::<u64>();
+        let dest_end = dest_offset
+            .checked_add(copied_len)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+        let mut state = self.state.write_state();
+        let mut handle = dest_handle.write().recover();
+
+        // Changing the extents of the object while it's being written would corrupt it.
+        let _transaction_lock = state
+            .transactions
+            .acquire_lock(handle.id)
+            .ok_or(crate::Error::TransactionInProgress)?;
+
+        let dest_size = handle.size();
+        let mut new_extents = match handle.slice_extents(0..dest_offset.min(dest_size)) {
+            Some(extents) => extents,
+            None => return Ok(None),
+        };
+        if dest_offset > dest_size {
+            new_extents.push(Extent::Hole {
+                size: dest_offset - dest_size,
+            });
+        }
+        new_extents.extend_from_slice(&source_extents);
+        if dest_end < dest_size {
+            match handle.slice_extents(dest_end..dest_size) {
+                Some(extents) => new_extents.extend(extents),
+                None => return Ok(None),
+            }
+        }
+
+        // Update the chunk map to include the destination handle in the list of references for
+        // each chunk.
+        for extent in &source_extents {
+            if let Extent::Chunk(chunk) = extent {
+                state.add_reference(*chunk, handle.id);
+            }
+        }
+
+        handle.extents = Arc::new(new_extents);
+
+        Ok(Some(copied_len))
+    }
+
     /// Write the map of objects for the current instance to the data store.
     pub(super) fn write_object_map(&mut self) -> crate::Result<()> {
-        let mut state = self.state.write().unwrap();
+        let serialized_objects = self.serialize_object_map()?;
+        self.write_serialized_object_map(serialized_objects.as_slice())
+    }
+
+    /// Return the serialized map of objects for the current instance.
+    fn serialize_object_map(&self) -> crate::Result<Vec<u8>> {
+        let handles = self
+            .objects
+            .iter()
+            .map(|(key, handle)| (key, handle.read().recover()))
+            .collect::<Vec<_>>();
+        object_map::serialize_object_map(handles.iter().map(|(key, handle)| (*key, &**handle)))
+    }
+
+    /// Write the given `serialized_objects` returned by `serialize_object_map` to the data store.
+    fn write_serialized_object_map(&mut self, serialized_objects: &[u8]) -> crate::Result<()> {
+        let mut state = self.state.write_state();
 
         let handle = &mut self
             .instances
@@ -265,9 +1076,9 @@ impl<K: Key> KeyRepo<K> {
             .expect("There is no instance with the given ID.")
             .objects;
 
-        let mut object_state = ObjectState::new(state.metadata.config.chunking.to_chunker());
+        let mut object_state = ObjectState::new(state.metadata.config.to_chunker());
         let mut writer = ObjectWriter::new(&mut state, &mut object_state, handle);
-        writer.serialize(&self.objects)
+        writer.write_serialized(serialized_objects)
     }
 
     /// Read the object map for the current instance from the data store and return it.
@@ -277,14 +1088,15 @@ impl<K: Key> KeyRepo<K> {
     ///
     /// This does not commit or roll back changes.
     pub(super) fn read_object_map(&self) -> crate::Result<HashMap<K, Arc<RwLock<ObjectHandle>>>> {
-        let state = self.state.read().unwrap();
+        let state = self.state.read().recover();
         match self.instances.get(&self.instance_id) {
             Some(instance_info) => {
-                let mut object_state =
-                    ObjectState::new(state.metadata.config.chunking.to_chunker());
+                let mut object_state = ObjectState::new(state.metadata.config.to_chunker());
                 let mut reader =
                     ObjectReader::new(&state, &mut object_state, &instance_info.objects);
-                reader.deserialize()
+                Ok(object_map::into_shared(object_map::read_object_map(
+                    &mut reader,
+                )?))
             }
             None => {
                 // If the current instance is not in the instance map, then this repository has not
@@ -307,22 +1119,24 @@ impl<K: Key> KeyRepo<K> {
             // instance.
             let mut handle = ObjectHandle {
                 id: self.handle_table.next(),
-                extents: Vec::new(),
+                extents: Arc::new(Vec::new()),
             };
 
             // Because this is a new instance, we return an empty object map.
             let objects = HashMap::<R::Key, Arc<RwLock<ObjectHandle>>>::new();
 
             // Write an empty object map to the object.
-            let mut state = self.state.write().unwrap();
-            let mut object_state = ObjectState::new(state.metadata.config.chunking.to_chunker());
+            let mut state = self.state.write_state();
+            let mut object_state = ObjectState::new(state.metadata.config.to_chunker());
             let mut writer = ObjectWriter::new(&mut state, &mut object_state, &mut handle);
-            writer.serialize(&objects)?;
+            object_map::write_object_map(&mut writer, iter::empty::<(&R::Key, &ObjectHandle)>())?;
 
             // Insert the instance info into the instance map.
             let instance_info = InstanceInfo {
                 version_id: R::VERSION_ID,
                 objects: handle,
+                branch: String::from(DEFAULT_BRANCH),
+                branches: HashMap::new(),
             };
             self.instances.insert(instance_id, instance_info);
 
@@ -335,20 +1149,23 @@ impl<K: Key> KeyRepo<K> {
             }
 
             // Deserialize the object map for this instance.
-            let state = self.state.read().unwrap();
-            let mut object_state = ObjectState::new(state.metadata.config.chunking.to_chunker());
+            let state = self.state.read().recover();
+            let mut object_state = ObjectState::new(state.metadata.config.to_chunker());
             let mut reader = ObjectReader::new(&state, &mut object_state, &instance_info.objects);
-            reader.deserialize()?
+            object_map::into_shared(object_map::read_object_map(&mut reader)?)
         };
 
-        let repo = KeyRepo {
+        let repo = KeyRepo::from_inner(KeyRepoInner {
             state: self.state,
             instance_id,
             objects: new_objects,
             instances: self.instances,
             handle_table: self.handle_table,
             transaction_id: self.transaction_id,
-        };
+            hooks: self.hooks,
+            progress: self.progress,
+            background_clean: self.background_clean,
+        });
 
         if is_new_instance {
             R::create_repo(repo)
@@ -357,37 +1174,303 @@ impl<K: Key> KeyRepo<K> {
         }
     }
 
-    /// Atomically encode and write the given serialized `header` to the data store.
-    fn write_serialized_header(&mut self, serialized_header: &[u8]) -> crate::Result<()> {
-        let mut state = self.state.write().unwrap();
-        // Encode the serialized header.
-        let encoded_header = state.encode_data(serialized_header)?;
+    /// Return the info for the current instance.
+    fn instance_info(&self) -> &InstanceInfo {
+        self.instances
+            .get(&self.instance_id)
+            .expect("There is no instance with the given ID.")
+    }
+
+    /// Return a map of the object handles in the branch named `name`.
+    ///
+    /// If `name` is the current branch, this returns a copy of the current object handles.
+    fn branch_objects(&self, name: &str) -> crate::Result<HashMap<K, ObjectHandle>> {
+        if self.branch() == name {
+            return Ok(self
+                .objects
+                .iter()
+                .map(|(key, handle)| (key.clone(), handle.read().recover().clone()))
+                .collect());
+        }
+
+        let map_handle = self
+            .instance_info()
+            .branches
+            .get(name)
+            .ok_or(crate::Error::NotFound)?;
+
+        let state = self.state.read().recover();
+        let mut object_state = ObjectState::new(state.metadata.config.to_chunker());
+        let mut reader = ObjectReader::new(&state, &mut object_state, map_handle);
+        object_map::read_object_map(&mut reader)
+    }
+
+    /// Insert a new object with the given `key` and `extents`, replacing any existing object.
+    fn insert_extents(&mut self, key: K, extents: Arc<Vec<Extent>>) {
+        self.remove(&key);
+
+        let handle = ObjectHandle {
+            id: self.handle_table.next(),
+            extents,
+        };
 
-        // Write the new header to a new block.
-        let header_id = Uuid::new_v4();
-        state
-            .store
-            .lock()
-            .unwrap()
-            .write_block(header_id, encoded_header.as_slice())
-            .map_err(crate::Error::Store)?;
-        state.metadata.header_id = header_id;
+        // Update the chunk map to include the new handle in the list of references for each chunk.
+        let mut state = self.state.write_state();
+        for chunk in handle.chunks() {
+            state.add_reference(chunk, handle.id);
+        }
+        drop(state);
+
+        self.objects.insert(key, Arc::new(RwLock::new(handle)));
+    }
+
+    /// Create a new branch named `name` from the current contents of the current instance.
+    ///
+    /// This copies the object handles in the current branch, so it does not require copying the
+    /// bytes in any objects.
+    pub(super) fn fork_branch(&mut self, name: &str) -> crate::Result<()> {
+        if self.branches().any(|branch| branch == name) {
+            return Err(crate::Error::AlreadyExists);
+        }
+
+        let mut state = self.state.write_state();
+
+        // Copy each object handle in the current branch, updating the chunk map to include the new
+        // handle in the list of references for each chunk.
+        let mut branch_objects = HashMap::with_capacity(self.objects.len());
+        for (key, handle) in self.objects.iter() {
+            let new_handle = ObjectHandle {
+                id: self.handle_table.next(),
+                extents: handle.read().recover().extents.clone(),
+            };
+            for chunk in new_handle.chunks() {
+                state.add_reference(chunk, new_handle.id);
+            }
+            branch_objects.insert(key.clone(), new_handle);
+        }
+
+        // Write the object map for the new branch.
+        let mut map_handle = ObjectHandle {
+            id: self.handle_table.next(),
+            extents: Arc::new(Vec::new()),
+        };
+        let mut object_state = ObjectState::new(state.metadata.config.to_chunker());
+        let mut writer = ObjectWriter::new(&mut state, &mut object_state, &mut map_handle);
+        let result = object_map::write_object_map(&mut writer, branch_objects.iter());
+        drop(state);
+
+        if let Err(error) = result {
+            for handle in branch_objects.values() {
+                self.remove_handle(handle);
+            }
+            self.remove_handle(&map_handle);
+            return Err(error);
+        }
+
+        self.instances
+            .get_mut(&self.instance_id)
+            .expect("There is no instance with the given ID.")
+            .branches
+            .insert(name.to_string(), map_handle);
+
+        Ok(())
+    }
+
+    /// Switch the current instance to the branch named `name`.
+    ///
+    /// This writes the object map for the current branch before switching.
+    pub(super) fn checkout_branch(&mut self, name: &str) -> crate::Result<()> {
+        if self.branch() == name {
+            return Ok(());
+        }
+
+        if !self.instance_info().branches.contains_key(name) {
+            return Err(crate::Error::NotFound);
+        }
+
+        self.write_object_map()?;
+
+        let instance_info = self.instances.get_mut(&self.instance_id).unwrap();
+        let new_map = instance_info.branches.remove(name).unwrap();
+        let old_map = mem::replace(&mut instance_info.objects, new_map);
+        let old_branch = mem::replace(&mut instance_info.branch, name.to_string());
+        instance_info.branches.insert(old_branch.clone(), old_map);
+
+        match self.read_object_map() {
+            Ok(objects) => {
+                self.objects = objects;
+                Ok(())
+            }
+            Err(error) => {
+                // Switch back to the old branch so the repository is unchanged.
+                let instance_info = self.instances.get_mut(&self.instance_id).unwrap();
+                let old_map = instance_info.branches.remove(&old_branch).unwrap();
+                let new_map = mem::replace(&mut instance_info.objects, old_map);
+                instance_info.branch = old_branch;
+                instance_info.branches.insert(name.to_string(), new_map);
+                Err(error)
+            }
+        }
+    }
+
+    /// Consume this repository and return a repository with a different key type.
+    ///
+    /// This reuses the objects in the current instance in place. Each key is mapped to a new key
+    /// using `f`, and objects for which `f` returns `None` are removed. The current instance is
+    /// marked as containing a repository with the given `version_id`.
+    ///
+    /// This invalidates all savepoints associated with the repository.
+    ///
+    /// # Errors
+    /// - `Error::UnsupportedRepo`: The current instance has more than one branch.
+    pub(crate) fn convert<T: Key>(
+        mut self,
+        version_id: Uuid,
+        mut f: impl FnMut(K) -> Option<T>,
+    ) -> crate::Result<KeyRepoInner<T>> {
+        // The object maps for the other branches would still use the old key type.
+        if !self.instance_info().branches.is_empty() {
+            return Err(crate::Error::UnsupportedRepo);
+        }
+
+        let mut objects = HashMap::with_capacity(self.objects.len());
+        for (key, handle) in mem::take(&mut self.objects) {
+            match f(key) {
+                Some(new_key) => {
+                    objects.insert(new_key, handle);
+                }
+                None => self.remove_handle(&handle.read().recover()),
+            }
+        }
+
+        self.instances
+            .get_mut(&self.instance_id)
+            .expect("There is no instance with the given ID.")
+            .version_id = version_id;
+
+        Ok(KeyRepoInner {
+            state: self.state,
+            instance_id: self.instance_id,
+            objects,
+            instances: self.instances,
+            handle_table: self.handle_table,
+            transaction_id: Arc::new(Uuid::new_v4()),
+            hooks: self.hooks,
+            progress: self.progress,
+            background_clean: self.background_clean,
+        })
+    }
+
+    /// Start removing unreferenced blocks in the background if background cleaning is enabled.
+    ///
+    /// This must be called right after committing, when the blocks referenced by the repository
+    /// are the same as those referenced by the commit. If the previous pass is still running, a
+    /// new one is not started.
+    fn start_background_clean(&mut self) {
+        if let Some(background_clean) = &self.background_clean {
+            if !background_clean.is_finished() {
+                return;
+            }
+        }
+
+        let state = self.state.read().recover();
+        if !state.background_clean || state.optimistic {
+            return;
+        }
+
+        // The commit has already succeeded, so if the data store can't be listed, we leave the
+        // unreferenced blocks to be removed after the next commit.
+        let blocks = match removable_blocks(&state) {
+            Ok(blocks) if !blocks.is_empty() => blocks,
+            _ => return,
+        };
+        drop(state);
+
+        self.background_clean = Some(BackgroundClean::start(&self.state, blocks));
+    }
+
+    /// Atomically write the given encoded `header` to the data store.
+    ///
+    /// A full header replaces the current header and any deltas, while a delta is applied on top of
+    /// the current header.
+    fn write_encoded_header(&mut self, encoded_header: HeaderBlocks) -> crate::Result<()> {
+        let mut state = self.state.write_state();
+        let mut metadata = state.metadata.clone();
+
+        // Choose the IDs of the blocks to write the new header to. The shards are written before
+        // the header which references them.
+        let new_blocks = match &encoded_header {
+            HeaderBlocks::Full(header, shards) => {
+                let shard_ids = shards.iter().map(|_| Uuid::new_v4()).collect::<Vec<_>>();
+                let header_id = Uuid::new_v4();
+                let mut new_blocks = shard_ids
+                    .iter()
+                    .copied()
+                    .zip(shards.iter().map(Vec::as_slice))
+                    .collect::<Vec<_>>();
+                new_blocks.push((header_id, header.as_slice()));
+                metadata.header_id = header_id;
+                metadata.header_shards = shard_ids;
+                metadata.header_deltas.clear();
+                new_blocks
+            }
+            HeaderBlocks::Delta(delta) => {
+                let delta_id = Uuid::new_v4();
+                metadata.header_deltas.push(delta_id);
+                vec![(delta_id, delta.as_slice())]
+            }
+        };
+        metadata.generation += 1;
+        metadata.last_commit = Some(SystemTime::now());
+
+        // Prepare the commit by recording it in a marker before writing the new header, so that
+        // it can be recovered if it's interrupted. Markers aren't used with optimistic concurrency
+        // because recovering from one isn't safe while other writers may be committing.
+        let marker = if state.optimistic {
+            None
+        } else {
+            Some(CommitMarker {
+                generation: metadata.generation,
+                previous: state.metadata.clone(),
+                blocks: new_blocks.iter().map(|(id, _)| *id).collect(),
+            })
+        };
 
-        // Atomically write the new repository metadata containing the new header ID.
+        {
+            let mut store = state.store.lock().recover();
+            if let Some(marker) = &marker {
+                marker.prepare(&mut **store)?;
+            }
+            store
+                .write_blocks(&new_blocks)
+                .map_err(crate::Error::from_store)?;
+        }
+
+        // Publish the commit by atomically writing the new repository metadata containing the new
+        // header IDs. We don't update the metadata in memory until this succeeds so that a failed
+        // commit doesn't advance the generation counter.
         let serialized_metadata =
-            to_vec(&state.metadata).expect("Could not serialize repository metadata.");
+            to_vec(&metadata).expect("Could not serialize repository metadata.");
         state
             .store
             .lock()
-            .unwrap()
+            .recover()
             .write_block(METADATA_BLOCK_ID, &serialized_metadata)
-            .map_err(crate::Error::Store)?;
+            .map_err(crate::Error::from_store)?;
+        state.metadata = metadata;
+
+        // Finalize the commit by removing the marker. The commit has already been published, so if
+        // this fails, the marker is removed the next time the repository is opened.
+        if marker.is_some() {
+            let _ = CommitMarker::finalize(&mut **state.store.lock().recover());
+        }
+
         Ok(())
     }
 
     /// Return a cloned `Header` representing the current state of the repository.
     fn clone_header(&self) -> Header {
-        let state = self.state.read().unwrap();
+        let state = self.state.read().recover();
         Header {
             chunks: state.chunks.clone(),
             packs: state.packs.clone(),
@@ -396,43 +1479,59 @@ impl<K: Key> KeyRepo<K> {
         }
     }
 
-    /// Return a serialized `Header` representing the current state of the repository.
+    /// Return an encoded `Header` representing the current state of the repository.
     ///
-    /// The returned data is not encoded.
-    fn serialize_header(&mut self) -> Vec<u8> {
-        let mut state = self.state.write().unwrap();
-        // Temporarily replace the values in the repository which need to be serialized so we can
-        // put them into the `Header`. This avoids the need to clone them. We'll put them back
-        // later.
-        let header = Header {
-            chunks: mem::replace(&mut state.chunks, HashMap::new()),
-            packs: mem::replace(&mut state.packs, HashMap::new()),
-            instances: mem::replace(&mut self.instances, HashMap::new()),
-            handle_table: mem::replace(&mut self.handle_table, IdTable::new()),
-        };
+    /// The header is serialized directly into the encoder so that only the encoded data is
+    /// buffered in memory.
+    fn encode_header(&self) -> crate::Result<HeaderBlocks> {
+        let state = self.state.read().recover();
+        let (encoded_header, encoded_shards) = lazy_header::encode_header(
+            &state.chunks,
+            &state.packs,
+            &self.instances,
+            &self.handle_table,
+            &*state,
+        )?;
+        Ok(HeaderBlocks::Full(encoded_header, encoded_shards))
+    }
 
-        // Serialize the header so we can write it to the data store.
-        let serialized_header =
-            to_vec(&header).expect("Could not serialize the repository header.");
+    /// Return an encoded `HeaderDelta` containing the changes to the header since the last
+    /// commit.
+    ///
+    /// This returns `None` if the whole header needs to be written instead, either because enough
+    /// deltas have accumulated that the header should be compacted or because the header was
+    /// replaced since the last commit.
+    fn encode_header_delta(&self) -> crate::Result<Option<HeaderBlocks>> {
+        let state = self.state.read().recover();
+        let max_deltas = state.metadata.config.max_header_deltas as usize;
+        if state.header_changes.replaced || state.metadata.header_deltas.len() >= max_deltas {
+            return Ok(None);
+        }
 
-        // Unpack the values from the `Header` and put them back where they originally were.
-        let Header {
-            chunks,
-            packs,
-            instances,
-            handle_table,
-        } = header;
-        state.chunks = chunks;
-        state.packs = packs;
-        self.instances = instances;
-        self.handle_table = handle_table;
+        let delta = HeaderDelta {
+            chunks: state
+                .header_changes
+                .chunks
+                .iter()
+                .map(|chunk| (*chunk, state.chunks.get(chunk).cloned()))
+                .collect(),
+            packs: state
+                .header_changes
+                .packs
+                .iter()
+                .map(|block_id| (*block_id, state.packs.get(block_id).cloned()))
+                .collect(),
+            instances: self.instances.clone(),
+            handle_table: self.handle_table.clone(),
+        };
 
-        serialized_header
+        Ok(Some(HeaderBlocks::Delta(state.encode_value(&delta)?)))
     }
 
     /// Replace the repository header with `header` and return the old one.
     fn replace_header(&mut self, header: Header) -> Header {
-        let mut state = self.state.write().unwrap();
+        let mut state = self.state.write_state();
+        state.header_changes.replaced = true;
         let old_chunks = mem::replace(&mut state.chunks, header.chunks);
         let old_packs = mem::replace(&mut state.packs, header.packs);
         let old_instances = mem::replace(&mut self.instances, header.instances);
@@ -444,16 +1543,102 @@ impl<K: Key> KeyRepo<K> {
             handle_table: old_handle_table,
         }
     }
+
+    /// Read the header from the previous commit from the data store.
+    ///
+    /// See `read_header_in` for details.
+    fn read_committed_header(&self) -> crate::Result<(Header, Option<LazyHeader>)> {
+        let state = self.state.read().recover();
+        read_header_in(&state, &state.metadata)
+    }
+
+    /// Read the object map for the current instance as of the previous commit.
+    ///
+    /// If `key` is `Some`, this also deserializes the contents of that object as of the previous
+    /// commit.
+    fn read_committed<T: DeserializeOwned>(
+        &self,
+        key: Option<&K>,
+    ) -> crate::Result<(HashMap<K, ObjectHandle>, Option<T>)> {
+        let (
+            Header {
+                chunks,
+                packs,
+                instances,
+                ..
+            },
+            lazy_header,
+        ) = self.read_committed_header()?;
+
+        // The objects from the previous commit may reference chunks which are no longer in the
+        // repository, so we temporarily replace the chunk and pack tables with the ones from the
+        // previous commit. We'll put them back later.
+        let mut state = self.state.write_state();
+        let current_chunks = mem::replace(&mut state.chunks, chunks);
+        let current_packs = mem::replace(&mut state.packs, packs);
+        let current_lazy_header = mem::replace(&mut state.lazy_header, lazy_header);
+
+        let result = self.read_objects_in(&state, &instances, key);
+
+        state.chunks = current_chunks;
+        state.packs = current_packs;
+        state.lazy_header = current_lazy_header;
+
+        result
+    }
+
+    /// Read the object map for the current instance using the given `state` and `instances`.
+    ///
+    /// If `key` is `Some`, this also deserializes the contents of that object.
+    fn read_objects_in<T: DeserializeOwned>(
+        &self,
+        state: &RepoState,
+        instances: &HashMap<Uuid, InstanceInfo>,
+        key: Option<&K>,
+    ) -> crate::Result<(HashMap<K, ObjectHandle>, Option<T>)> {
+        let objects: HashMap<K, ObjectHandle> = match instances.get(&self.instance_id) {
+            Some(instance_info) => {
+                let mut object_state = ObjectState::new(state.metadata.config.to_chunker());
+                let mut reader =
+                    ObjectReader::new(state, &mut object_state, &instance_info.objects);
+                object_map::read_object_map(&mut reader)?
+            }
+            None => HashMap::new(),
+        };
+
+        let value = match key.and_then(|key| objects.get(key)) {
+            Some(handle) => {
+                let mut object_state = ObjectState::new(state.metadata.config.to_chunker());
+                Some(ObjectReader::new(state, &mut object_state, handle).deserialize()?)
+            }
+            None => None,
+        };
+
+        Ok((objects, value))
+    }
+
+    /// Deserialize the contents of the object with the given `key` as of the previous commit.
+    ///
+    /// This returns `None` if the key did not exist as of the previous commit.
+    pub(crate) fn committed_value<T: DeserializeOwned>(&self, key: &K) -> crate::Result<Option<T>> {
+        Ok(self.read_committed(Some(key))?.1)
+    }
+
     /// Atomically restore the repository's state from the given `header`.
     ///
-    /// This restores the state of the repository using the data in the given `header` and then
-    /// reads the object map for the current instance from the data store.
+    /// This restores the state of the repository using the data in the given `header` and
+    /// `lazy_header` and then reads the object map for the current instance from the data store.
     ///
     /// If this returns `Ok`, the repository's state has been restored. If this returns `Err`, the
     /// repository is unchanged.
-    fn restore_header(&mut self, header: Header) -> crate::Result<()> {
+    fn restore_header(
+        &mut self,
+        header: Header,
+        lazy_header: Option<LazyHeader>,
+    ) -> crate::Result<()> {
         // We need to restore the repository state before we can read the object map.
         let old_header = self.replace_header(header);
+        let old_lazy_header = mem::replace(&mut self.state.write_state().lazy_header, lazy_header);
 
         // Restore the object map from the old header.
         match self.read_object_map() {
@@ -463,48 +1648,98 @@ impl<K: Key> KeyRepo<K> {
             }
             Err(error) => {
                 self.replace_header(old_header);
+                self.state.write_state().lazy_header = old_lazy_header;
                 Err(error)
             }
         }
     }
 
-    /// Verify the integrity of all the data in the current instance of the repository.
-    ///
-    /// This returns the set of keys of objects in the current instance which are corrupt.
-    ///
-    /// If you just need to verify the integrity of one object, [`Object::verify`] is faster. If you
-    /// need to verify the integrity of all the data in the repository, however, this can be more
-    /// efficient.
-    ///
-    /// # Errors
-    /// - `Error::InvalidData`: Ciphertext verification failed.
-    /// - `Error::Store`: An error occurred with the data store.
-    /// - `Error::Io`: An I/O error occurred.
-    ///
-    /// [`Object::verify`]: crate::repo::Object::verify
-    pub fn verify(&self) -> crate::Result<HashSet<&K>> {
-        let state = self.state.read().unwrap();
+    pub(crate) fn export<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a K>,
+        mut writer: impl Write,
+    ) -> crate::Result<()>
+    where
+        K: 'a,
+    {
+        archive::write_header(&mut writer)?;
+
+        for key in keys {
+            let mut object = match self.object(key) {
+                Some(object) => object,
+                None => continue,
+            };
+            let size = object.size()?;
+            archive::write_entry_header(&mut writer, key, size)?;
+            archive::copy_exact(&mut object, &mut writer, size)?;
+        }
+
+        archive::write_end(&mut writer)
+    }
+
+    pub(crate) fn import(&mut self, mut reader: impl Read) -> crate::Result<Vec<K>> {
+        archive::read_header(&mut reader)?;
+
+        let mut imported_keys = Vec::new();
+
+        while let Some((key, size)) = archive::read_entry_header::<K>(&mut reader)? {
+            let mut object = self.insert(key.clone());
+            archive::copy_exact(&mut reader, &mut object, size)?;
+            object.commit()?;
+            imported_keys.push(key);
+        }
+
+        Ok(imported_keys)
+    }
+
+    pub(crate) fn verify(&self) -> crate::Result<HashSet<&K>> {
+        // Every chunk in the repository is verified, so the whole chunk map needs to be loaded.
+        self.state.write_state().load_header()?;
 
-        let mut corrupt_chunks = HashSet::new();
-        let expected_chunks = state.chunks.keys().copied().collect::<Vec<_>>();
+        let (expected_chunks, threads) = {
+            let state = self.state.read().recover();
+            (
+                state.chunks.keys().copied().collect::<Vec<_>>(),
+                state.threads,
+            )
+        };
+        let total_chunks = expected_chunks.len() as u64;
 
         // Get the set of hashes of chunks which are corrupt.
-        let mut store_state = StoreState::new();
-        let mut store_reader = StoreReader::new(&state, &mut store_state);
-        for chunk in expected_chunks {
-            match store_reader.read_chunk(chunk) {
-                Ok(data) => {
-                    if data.len() != chunk.size as usize || chunk_hash(&data) != chunk.hash {
-                        corrupt_chunks.insert(chunk.hash);
-                    }
+        let mut corrupt_chunks = HashSet::new();
+        if threads > 1 {
+            let mut pool = VerifyPool::new(threads, &self.state);
+            for chunk in expected_chunks {
+                pool.submit(chunk);
+            }
+            pool.close();
+
+            for verified_chunks in 0..total_chunks {
+                self.report_progress(Operation::Verify, verified_chunks, Some(total_chunks))?;
+                let (chunk, intact) = pool
+                    .recv()
+                    .expect("A worker verifying chunks stopped unexpectedly.");
+                if !intact? {
+                    corrupt_chunks.insert(chunk.hash);
                 }
-                Err(crate::Error::InvalidData) => {
-                    // Ciphertext verification failed. No need to check the hash.
+            }
+        } else {
+            let state = self.state.read().recover();
+            let mut store_state = StoreState::new();
+            let mut store_reader = StoreReader::new(&state, &mut store_state);
+            for (verified_chunks, chunk) in expected_chunks.into_iter().enumerate() {
+                self.report_progress(
+                    Operation::Verify,
+                    verified_chunks as u64,
+                    Some(total_chunks),
+                )?;
+                if !chunk_is_intact(chunk, store_reader.read_chunk(chunk))? {
                     corrupt_chunks.insert(chunk.hash);
                 }
-                Err(error) => return Err(error),
-            };
+            }
         }
+        self.progress
+            .notify(Operation::Verify, total_chunks, Some(total_chunks));
 
         // If there are no corrupt chunks, there are no corrupt objects.
         if corrupt_chunks.is_empty() {
@@ -513,7 +1748,7 @@ impl<K: Key> KeyRepo<K> {
 
         let mut corrupt_keys = HashSet::new();
         for (key, handle) in &self.objects {
-            for chunk in handle.read().unwrap().chunks() {
+            for chunk in handle.read().recover().chunks() {
                 // If any one of the object's chunks is corrupt, the object is corrupt.
                 if corrupt_chunks.contains(&chunk.hash) {
                     corrupt_keys.insert(key);
@@ -525,38 +1760,133 @@ impl<K: Key> KeyRepo<K> {
         Ok(corrupt_keys)
     }
 
-    /// Delete all data in the current instance of the repository.
-    ///
-    /// This does not delete data from other instances of the repository.
-    ///
-    /// This does not commit changes to the repository.
-    ///
-    /// No data is reclaimed in the backing data store until changes are committed and
-    /// [`Commit::clean`] is called.
-    ///
-    /// [`Commit::clean`]: crate::repo::Commit::clean
-    pub fn clear_instance(&mut self) {
+    pub(crate) fn changed_keys(&self) -> crate::Result<HashSet<K>> {
+        let (committed_objects, _) = self.read_committed::<()>(None)?;
+        let mut changed_keys = HashSet::new();
+
+        for (key, handle) in self.objects.iter() {
+            let is_changed = match committed_objects.get(key) {
+                Some(committed_handle) => {
+                    handle.read().recover().extents != committed_handle.extents
+                }
+                None => true,
+            };
+            if is_changed {
+                changed_keys.insert(key.clone());
+            }
+        }
+
+        for key in committed_objects.keys() {
+            if !self.objects.contains_key(key) {
+                changed_keys.insert(key.clone());
+            }
+        }
+
+        Ok(changed_keys)
+    }
+
+    pub(crate) fn has_changes(&self) -> crate::Result<bool> {
+        Ok(!self.changed_keys()?.is_empty())
+    }
+
+    pub(crate) fn clear_instance(&mut self) {
         let handles = self
             .objects
             .drain()
             .map(|(_, handle)| handle)
             .collect::<Vec<_>>();
         for handle in handles {
-            self.remove_handle(&*handle.read().unwrap());
+            self.remove_handle(&handle.read().recover());
         }
+        self.audit(AuditEvent::ClearInstance);
     }
 
-    /// Change the password for this repository.
-    ///
-    /// This replaces the existing password with `new_password`. Changing the password does not
-    /// require re-encrypting any data. The change does not take effect until [`Commit::commit`] is
-    /// called.
-    ///
-    /// If encryption is disabled, this method does nothing.
-    ///
-    /// [`Commit::commit`]: crate::repo::Commit::commit
-    pub fn change_password(&mut self, new_password: &[u8]) {
-        let mut state = self.state.write().unwrap();
+    pub(crate) fn branch(&self) -> &str {
+        self.instance_info().branch.as_str()
+    }
+
+    pub(crate) fn branches(&self) -> impl Iterator<Item = &str> {
+        let instance_info = self.instance_info();
+        iter::once(instance_info.branch.as_str())
+            .chain(instance_info.branches.keys().map(String::as_str))
+    }
+
+    pub(crate) fn remove_branch(&mut self, name: &str) -> crate::Result<()> {
+        if self.branch() == name {
+            return Err(crate::Error::CurrentBranch);
+        }
+
+        let branch_objects = self.branch_objects(name)?;
+
+        let map_handle = self
+            .instances
+            .get_mut(&self.instance_id)
+            .unwrap()
+            .branches
+            .remove(name)
+            .unwrap();
+        for handle in branch_objects.values() {
+            self.remove_handle(handle);
+        }
+        self.remove_handle(&map_handle);
+        self.audit(AuditEvent::RemoveBranch {
+            name: name.to_owned(),
+        });
+
+        Ok(())
+    }
+
+    pub(crate) fn merge_branch(&mut self, source: &str, ancestor: &str) -> crate::Result<Vec<K>> {
+        let source_objects = self.branch_objects(source)?;
+        let ancestor_objects = self.branch_objects(ancestor)?;
+
+        // Keys which only exist in the current branch were added there, so we can ignore them.
+        let keys = source_objects
+            .keys()
+            .chain(ancestor_objects.keys())
+            .cloned()
+            .collect::<HashSet<_>>();
+
+        let mut conflicts = Vec::new();
+
+        for key in keys {
+            let ancestor_extents = ancestor_objects.get(&key).map(|handle| &handle.extents);
+            let source_extents = source_objects.get(&key).map(|handle| &handle.extents);
+
+            // The object is unchanged in `source`.
+            if source_extents == ancestor_extents {
+                continue;
+            }
+
+            let current_extents = self
+                .objects
+                .get(&key)
+                .map(|handle| handle.read().recover().extents.clone());
+
+            // The same change was made in both branches.
+            if current_extents.as_ref() == source_extents {
+                continue;
+            }
+
+            // The object was changed in both branches.
+            if current_extents.as_ref() != ancestor_extents {
+                conflicts.push(key);
+                continue;
+            }
+
+            match source_extents {
+                Some(extents) => self.insert_extents(key, extents.clone()),
+                None => {
+                    self.remove(&key);
+                }
+            }
+        }
+
+        Ok(conflicts)
+    }
+
+    pub(crate) fn change_password(&mut self, new_password: &[u8]) {
+        let mut state = self.state.write_state();
 
         let salt = KeySalt::generate();
         let user_key = EncryptionKey::derive(
@@ -577,21 +1907,206 @@ impl<K: Key> KeyRepo<K> {
         state.metadata.master_key = encrypted_master_key;
     }
 
-    /// Return this repository's current instance ID.
-    pub fn instance(&self) -> Uuid {
+    pub(crate) fn renew_lease(&self) -> crate::Result<()> {
+        renew_lease(&self.state.read().recover())
+    }
+
+    pub(crate) fn add_before_hook(
+        &mut self,
+        event: TransactionEvent,
+        hook: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.hooks.add_before(event, Box::new(hook));
+    }
+
+    pub(crate) fn add_after_hook(
+        &mut self,
+        event: TransactionEvent,
+        hook: impl FnMut() + Send + 'static,
+    ) {
+        self.hooks.add_after(event, Box::new(hook));
+    }
+
+    pub(crate) fn set_progress_handler(
+        &mut self,
+        handler: impl Fn(Progress) + Send + Sync + 'static,
+    ) {
+        self.progress.set_handler(Box::new(handler));
+    }
+
+    pub(crate) fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.progress.set_token(token);
+    }
+
+    /// Report the progress of an `operation` and check whether it has been cancelled.
+    pub(crate) fn report_progress(
+        &self,
+        operation: Operation,
+        completed: u64,
+        total: Option<u64>,
+    ) -> crate::Result<()> {
+        self.progress.report(operation, completed, total)
+    }
+
+    /// Return the method this repository uses to split data into chunks.
+    pub(crate) fn chunking(&self) -> Chunking {
+        self.state.read().recover().metadata.config.chunking.clone()
+    }
+
+    /// Return the maximum size of a chunk, or `0` if the size of chunks is not limited.
+    pub(crate) fn max_chunk_size(&self) -> usize {
+        self.state.read().recover().metadata.config.max_chunk_size as usize
+    }
+
+    /// Return the size of the buffer to use when reading data to split it into chunks.
+    pub(crate) fn read_buffer_size(&self) -> usize {
+        self.state.read().recover().metadata.config.read_buffer_size as usize
+    }
+
+    /// Return the number of worker threads to use for operations which can be done concurrently.
+    pub(crate) fn threads(&self) -> usize {
+        self.state.read().recover().threads
+    }
+
+    pub(crate) fn instance(&self) -> Uuid {
         self.instance_id
     }
 
-    /// Return information about the repository.
-    pub fn info(&self) -> RepoInfo {
-        self.state.read().unwrap().metadata.to_info()
+    pub(crate) fn info(&self) -> RepoInfo {
+        self.state.read().recover().metadata.to_info()
     }
-}
 
-impl<K: Key> RestoreSavepoint for KeyRepo<K> {
-    type Restore = KeyRestore<K>;
+    pub(crate) fn stats(&self) -> RepoStats {
+        self.state.read().recover().stats.snapshot()
+    }
+
+    pub(crate) fn reset_stats(&self) {
+        self.state.read().recover().stats.reset();
+    }
+
+    pub(crate) fn clean_dry_run(&self) -> crate::Result<CleanReport> {
+        // The plan must account for every chunk in the repository, even if the header was loaded
+        // lazily.
+        self.state.write_state().load_header()?;
+        let state = self.state.read().recover();
+
+        // Read the header from the previous commit.
+        let previous_header = read_header(
+            &mut **state.store.lock().recover(),
+            &state.metadata,
+            |data| state.decode_reader(data),
+        )?;
+
+        let plan = plan_clean(&state, &previous_header)?;
+
+        // A block may be split between multiple packs, in which case each piece is repacked.
+        let repacked_bytes = plan
+            .blocks_to_repack
+            .iter()
+            .filter_map(|block_id| {
+                state
+                    .packs
+                    .get(block_id)
+                    .or_else(|| previous_header.packs.get(block_id))
+            })
+            .flatten()
+            .map(|pack_index| u64::from(pack_index.size))
+            .sum();
+
+        let mut removed_bytes = 0u64;
+        let mut store = state.store.lock().recover();
+        for block_id in &plan.blocks_to_remove {
+            if let Some(data) = store
+                .read_block(*block_id)
+                .map_err(crate::Error::from_store)?
+            {
+                removed_bytes += data.len() as u64;
+            }
+        }
+
+        Ok(CleanReport {
+            removed_blocks: plan.blocks_to_remove,
+            repacked_blocks: plan.blocks_to_repack,
+            removed_bytes,
+            repacked_bytes,
+        })
+    }
+
+    pub(crate) fn repair(&mut self) -> crate::Result<RepairReport> {
+        let state = self.state.read().recover();
+        if state.read_only {
+            return Err(crate::Error::ReadOnly);
+        }
+        parity::repair(&state, &self.progress)
+    }
+
+    pub(crate) fn inspect_chunks(&self) -> crate::Result<Vec<ChunkReport<K>>> {
+        // Every chunk must be loaded, even if the header was loaded lazily.
+        self.state.write_state().load_header()?;
+        let state = self.state.read().recover();
+
+        let keys_by_handle = self
+            .objects
+            .iter()
+            .map(|(key, handle)| (handle.read().recover().id, key))
+            .collect::<HashMap<_, _>>();
+
+        let mut reports = state
+            .chunks
+            .iter()
+            .map(|(chunk, info)| ChunkReport {
+                hash: chunk.hash,
+                size: chunk.size,
+                inline: info.inline.is_some(),
+                packs: state
+                    .packs
+                    .get(&info.block_id)
+                    .map_or(0, |index_list| index_list.len()),
+                references: info.references.len(),
+                keys: info
+                    .references
+                    .iter()
+                    .filter_map(|id| keys_by_handle.get(id))
+                    .map(|key| (*key).clone())
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        reports.sort_by_key(|report| report.hash);
+
+        Ok(reports)
+    }
+
+    pub(crate) fn audit_log(&self) -> crate::Result<Vec<AuditEntry>> {
+        let state = self.state.read().recover();
+        let mut entries = read_audit_log(&state)?;
+        entries.extend(state.audit_log.pending());
+        Ok(entries)
+    }
+
+    /// Record `event` in the audit log if it is enabled.
+    ///
+    /// The entry is written to the data store along with the next call to `audit_and_flush`.
+    fn audit(&self, event: AuditEvent) {
+        let state = self.state.read().recover();
+        if state.metadata.config.audit_log && !state.read_only {
+            state.audit_log.record(self.instance_id, event);
+        }
+    }
+
+    /// Record `event` in the audit log if it is enabled and write any pending entries.
+    ///
+    /// Errors are ignored because the operation being recorded has already happened. Entries
+    /// which couldn't be written are written by the next call to this method.
+    pub(super) fn audit_and_flush(&self, event: AuditEvent) {
+        self.audit(event);
+        let state = self.state.read().recover();
+        let _ = state.audit_log.flush(&state);
+    }
+}
 
-    fn savepoint(&mut self) -> crate::Result<Savepoint> {
+impl<K: Key> KeyRepoInner<K> {
+    pub(crate) fn savepoint(&mut self) -> crate::Result<Savepoint> {
+        self.state.write_state().load_header()?;
         self.write_object_map()?;
 
         Ok(Savepoint {
@@ -600,7 +2115,48 @@ impl<K: Key> RestoreSavepoint for KeyRepo<K> {
         })
     }
 
-    fn start_restore(&mut self, savepoint: &Savepoint) -> crate::Result<Self::Restore> {
+    pub(crate) fn start_restore(&mut self, savepoint: &Savepoint) -> crate::Result<KeyRestore<K>> {
+        self.run_before_hooks(TransactionEvent::Restore)?;
+        self.start_restore_without_hooks(savepoint)
+    }
+
+    pub(crate) fn finish_restore(&mut self, restore: KeyRestore<K>) -> bool {
+        if !self.finish_restore_without_hooks(restore) {
+            return false;
+        }
+        self.audit_and_flush(AuditEvent::Restore);
+        self.run_after_hooks(TransactionEvent::Restore);
+        true
+    }
+}
+
+impl<K: Key> RestoreSavepoint for KeyRepo<K> {
+    type Restore = KeyRestore<K>;
+
+    fn savepoint(&self) -> crate::Result<Savepoint> {
+        self.inner_mut().savepoint()
+    }
+
+    fn start_restore(&self, savepoint: &Savepoint) -> crate::Result<Self::Restore> {
+        self.inner_mut().start_restore(savepoint)
+    }
+
+    fn finish_restore(&self, restore: Self::Restore) -> bool {
+        self.inner_mut().finish_restore(restore)
+    }
+}
+
+impl<K: Key> KeyRepoInner<K> {
+    /// Start the process of restoring the repository to the given `savepoint`.
+    ///
+    /// This is like [`RestoreSavepoint::start_restore`], except it does not run any hooks. This is
+    /// used by repositories which restore savepoints internally.
+    ///
+    /// [`RestoreSavepoint::start_restore`]: crate::repo::RestoreSavepoint::start_restore
+    pub(crate) fn start_restore_without_hooks(
+        &mut self,
+        savepoint: &Savepoint,
+    ) -> crate::Result<KeyRestore<K>> {
         match savepoint.transaction_id.upgrade() {
             None => return Err(crate::Error::InvalidSavepoint),
             Some(transaction_id) if transaction_id != self.transaction_id => {
@@ -609,6 +2165,8 @@ impl<K: Key> RestoreSavepoint for KeyRepo<K> {
             _ => (),
         }
 
+        // The current header is restored if this fails, so the whole header needs to be loaded.
+        self.state.write_state().load_header()?;
         let old_header = self.replace_header((*savepoint.header).clone());
 
         match self.read_object_map() {
@@ -625,7 +2183,13 @@ impl<K: Key> RestoreSavepoint for KeyRepo<K> {
         }
     }
 
-    fn finish_restore(&mut self, restore: Self::Restore) -> bool {
+    /// Finish the process of restoring the repository to a savepoint.
+    ///
+    /// This is like [`RestoreSavepoint::finish_restore`], except it does not run any hooks. This
+    /// is used by repositories which restore savepoints internally.
+    ///
+    /// [`RestoreSavepoint::finish_restore`]: crate::repo::RestoreSavepoint::finish_restore
+    pub(crate) fn finish_restore_without_hooks(&mut self, restore: KeyRestore<K>) -> bool {
         match restore.transaction_id.upgrade() {
             None => return false,
             Some(transaction_id) if transaction_id != self.transaction_id => return false,
@@ -641,150 +2205,264 @@ impl<K: Key> RestoreSavepoint for KeyRepo<K> {
 
         true
     }
+
+    /// Refresh the repository and return a backup which can be used to undo the refresh.
+    ///
+    /// This is like [`Commit::refresh`], but it allows repositories which are backed by a `KeyRepo`
+    /// to undo the refresh with `undo_refresh` if refreshing their own state fails.
+    ///
+    /// [`Commit::refresh`]: crate::repo::Commit::refresh
+    pub(crate) fn refresh_with_backup(&mut self) -> crate::Result<RefreshBackup<K>> {
+        // It's important that we start the restore process here so that it can be completed
+        // infallibly.
+        let savepoint = self.savepoint()?;
+        let restore = self.start_restore_without_hooks(&savepoint)?;
+        let metadata = self.state.read().recover().metadata.clone();
+
+        self.refresh()?;
+
+        Ok(RefreshBackup { restore, metadata })
+    }
+
+    /// Undo a refresh using a `backup` returned by `refresh_with_backup`.
+    ///
+    /// This restores the metadata as well as the header so that committing afterwards can't
+    /// overwrite commits which were made since the backup was taken.
+    pub(crate) fn undo_refresh(&mut self, backup: RefreshBackup<K>) {
+        self.finish_restore_without_hooks(backup.restore);
+        let mut state = self.state.write_state();
+        state.metadata = backup.metadata;
+        // The whole header was loaded when the backup was taken, so there is nothing left to load.
+        state.lazy_header = None;
+    }
+
+    /// Run the hooks registered to run before the given `event`.
+    pub(crate) fn run_before_hooks(&mut self, event: TransactionEvent) -> crate::Result<()> {
+        self.hooks.run_before(event)
+    }
+
+    /// Run the hooks registered to run after the given `event`.
+    pub(crate) fn run_after_hooks(&mut self, event: TransactionEvent) {
+        self.hooks.run_after(event)
+    }
 }
 
-impl<K: Key> Commit for KeyRepo<K> {
-    fn commit(&mut self) -> crate::Result<()> {
-        // Write the map of objects for the current instance.
-        self.write_object_map()?;
+impl<K: Key> KeyRepoInner<K> {
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub(crate) fn commit(&mut self) -> crate::Result<()> {
+        let state = self.state.read().recover();
+        if state.read_only {
+            return Err(crate::Error::ReadOnly);
+        }
+        // Changes made by a thread which panicked may be half-finished, so they can't be
+        // committed until the repository is rolled back.
+        if state.poisoned {
+            return Err(crate::Error::Poisoned);
+        }
+        drop(state);
+
+        self.run_before_hooks(TransactionEvent::Commit)?;
+
+        #[cfg(feature = "metrics")]
+        let start = std::time::Instant::now();
 
-        // Serialize the header.
-        let serialized_header = self.serialize_header();
+        // The commit process has three steps: renewing the lease, writing the object map, and
+        // writing the header.
+        const COMMIT_STEPS: Option<u64> = Some(3);
 
-        // Write the serialized header to the data store, atomically completing the commit. If this
+        // Steps which don't depend on each other are run at the same time on the repository's
+        // thread pool, if it has one, so that store I/O overlaps with serialization and encoding.
+        let state = Arc::clone(&self.state);
+
+        // Make sure we still hold the lease on the repository before committing. While the lease
+        // is being renewed, serialize the map of objects for the current instance.
+        self.report_progress(Operation::Commit, 0, COMMIT_STEPS)?;
+        let (lease_result, serialized_objects) = parallel::join(
+            &state,
+            || renew_lease(&state.read().recover()),
+            || self.serialize_object_map(),
+        );
+        lease_result?;
+
+        // Write the map of objects for the current instance.
+        self.report_progress(Operation::Commit, 1, COMMIT_STEPS)?;
+        self.write_serialized_object_map(serialized_objects?.as_slice())?;
+
+        // Encode the changes to the header, or the whole header if it needs to be compacted.
+        // While it's being encoded, check that no other writer has committed since we last read
+        // the repository metadata.
+        self.report_progress(Operation::Commit, 2, COMMIT_STEPS)?;
+        let (generation_result, header_result) = parallel::join(
+            &state,
+            || check_generation(&state.read().recover()),
+            || match self.encode_header_delta() {
+                Ok(Some(encoded_delta)) => Ok(encoded_delta),
+                Ok(None) => self.encode_header(),
+                Err(error) => Err(error),
+            },
+        );
+        generation_result?;
+        let encoded_header = header_result?;
+
+        // Write the encoded header to the data store, atomically completing the commit. If this
         // completes successfully, changes have been committed and this method MUST return `Ok`.
-        self.write_serialized_header(serialized_header.as_slice())?;
+        self.write_encoded_header(encoded_header)?;
+        self.state.write_state().header_changes = HeaderChanges::default();
+        self.progress.notify(Operation::Commit, 3, COMMIT_STEPS);
+
+        #[cfg(feature = "metrics")]
+        telemetry::commit_finished(start.elapsed());
 
         // Now that the commit has succeeded, we must invalidate all savepoints associated with this
         // repository.
         self.transaction_id = Arc::new(Uuid::new_v4());
 
+        // The commit has already succeeded, so if the parity blocks can't be written, the new
+        // blocks are protected after the next commit instead.
+        let _ = parity::protect(&self.state.read().recover(), false);
+
+        self.start_background_clean();
+
+        self.audit_and_flush(AuditEvent::Commit);
+        self.run_after_hooks(TransactionEvent::Commit);
+
         Ok(())
     }
 
-    fn rollback(&mut self) -> crate::Result<()> {
-        let state = self.state.read().unwrap();
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub(crate) fn rollback(&mut self) -> crate::Result<()> {
+        self.run_before_hooks(TransactionEvent::Rollback)?;
+
         // Read the header from the previous commit from the data store.
-        let encoded_header = state
+        let (header, lazy_header) = self.read_committed_header()?;
+
+        // Atomically restore from the deserialized header. The repository now matches the previous
+        // commit, so there are no changes to the header.
+        self.restore_header(header, lazy_header)?;
+        let mut state = self.state.write_state();
+        state.header_changes = HeaderChanges::default();
+        state.poisoned = false;
+        drop(state);
+
+        self.audit_and_flush(AuditEvent::Rollback);
+        self.run_after_hooks(TransactionEvent::Rollback);
+
+        Ok(())
+    }
+
+    pub(crate) fn refresh(&mut self) -> crate::Result<()> {
+        self.run_before_hooks(TransactionEvent::Refresh)?;
+
+        let state = self.state.read().recover();
+
+        // Read the metadata and header from the most recent commit from the data store.
+        let serialized_metadata = state
             .store
             .lock()
-            .unwrap()
-            .read_block(state.metadata.header_id)
-            .map_err(crate::Error::Store)?
+            .recover()
+            .read_block(METADATA_BLOCK_ID)
+            .map_err(crate::Error::from_store)?
             .ok_or(crate::Error::Corrupt)?;
-        let serialized_header = state.decode_data(encoded_header.as_slice())?;
-        let header: Header =
-            from_read(serialized_header.as_slice()).map_err(|_| crate::Error::Corrupt)?;
+        let metadata: RepoMetadata =
+            from_read(serialized_metadata.as_slice()).map_err(|_| crate::Error::Corrupt)?;
+        let (header, lazy_header) = read_header_in(&state, &metadata)?;
         drop(state);
 
-        // Atomically restore from the deserialized header.
-        self.restore_header(header)
+        // Atomically restore from the deserialized header. Replacing the metadata can't fail, so
+        // we can do it after the header has been restored successfully.
+        self.restore_header(header, lazy_header)?;
+        let mut state = self.state.write_state();
+        state.metadata = metadata;
+        state.header_changes = HeaderChanges::default();
+        drop(state);
+
+        self.run_after_hooks(TransactionEvent::Refresh);
+
+        Ok(())
     }
 
-    fn clean(&mut self) -> crate::Result<()> {
-        let mut state = self.state.write().unwrap();
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self)))]
+    pub(crate) fn clean(&mut self) -> crate::Result<()> {
+        // Stop removing blocks in the background so we don't race with the background pass.
+        if let Some(background_clean) = self.background_clean.take() {
+            background_clean.stop();
+        }
+
+        let mut state = self.state.write_state();
+
+        if state.read_only {
+            return Err(crate::Error::ReadOnly);
+        }
 
         // Read the header from the previous commit.
-        let encoded_header = state
-            .store
-            .lock()
-            .unwrap()
-            .read_block(state.metadata.header_id)
-            .map_err(crate::Error::Store)?
-            .ok_or(crate::Error::Corrupt)?;
-        let serialized_header = state.decode_data(encoded_header.as_slice())?;
-        let previous_header: Header =
-            from_read(serialized_header.as_slice()).map_err(|_| crate::Error::Corrupt)?;
-
-        // We need to find the set of blocks which are either currently referenced by the repository
-        // or were referenced after the previous commit. It's important that we don't clean up
-        // blocks which were referenced after the previous commit because that would make it
-        // impossible to roll back changes, and this method may be called before the repository is
-        // committed.
-        let mut referenced_blocks = state
-            .chunks
-            .values()
-            .map(|info| info.block_id)
-            .collect::<HashSet<_>>();
-        let previous_referenced_blocks = previous_header.chunks.values().map(|info| info.block_id);
-        referenced_blocks.extend(previous_referenced_blocks);
+        let previous_header = read_header(
+            &mut **state.store.lock().recover(),
+            &state.metadata,
+            |data| state.decode_reader(data),
+        )?;
+
+        let CleanPlan {
+            referenced_blocks,
+            blocks_to_remove,
+            blocks_to_repack,
+        } = plan_clean(&state, &previous_header)?;
 
         // Remove all blocks from the data store which are unreferenced.
         match &state.metadata.config.packing {
             Packing::None => {
-                // When packing is disabled, we can just remove the unreferenced blocks from the
-                // data store directly.
-                let block_ids = list_data_blocks(&state)?;
-
-                let mut store = state.store.lock().unwrap();
-                for block_id in block_ids {
-                    if !referenced_blocks.contains(&block_id) {
-                        store.remove_block(block_id).map_err(crate::Error::Store)?;
-                    }
+                let total_blocks = blocks_to_remove.len() as u64;
+
+                let mut store = state.store.lock().recover();
+                let mut removed_blocks = 0u64;
+                for batch in blocks_to_remove.chunks(REMOVE_BATCH_SIZE) {
+                    // Removing unreferenced blocks can be safely stopped at any point.
+                    self.progress
+                        .report(Operation::Clean, removed_blocks, Some(total_blocks))?;
+                    store
+                        .remove_blocks(batch)
+                        .map_err(crate::Error::from_store)?;
+                    removed_blocks += batch.len() as u64;
                 }
+                self.progress
+                    .notify(Operation::Clean, total_blocks, Some(total_blocks));
+                drop(store);
+                drop(state);
             }
-            Packing::Fixed(_) => {
-                // When packing is enabled, we need to repack the packs which contain unreferenced
-                // blocks.
-
-                // Get an iterator of block IDs and the list of packs they're contained in.
-                let blocks_to_packs = state.packs.iter().chain(previous_header.packs.iter());
-
-                // Get a map of pack IDs to the set of blocks contained in them.
-                let mut packs_to_blocks = HashMap::new();
-                for (block_id, index_list) in blocks_to_packs {
-                    for pack_index in index_list {
-                        packs_to_blocks
-                            .entry(pack_index.id)
-                            .or_insert_with(HashSet::new)
-                            .insert(*block_id);
-                    }
-                }
-
-                // The list of IDs of packs which contain at least one unreferenced block.
-                let mut packs_to_remove = Vec::new();
-
-                // The list of blocks which need to be repacked. These are referenced blocks which
-                // are contained in packs which contain at least one unreferenced block.
-                let mut blocks_to_repack = Vec::new();
-
-                // Iterate over the IDs of packs which are contained in the data store.
-                for pack_id in list_data_blocks(&state)? {
-                    match packs_to_blocks.get(&pack_id) {
-                        Some(contained_blocks) => {
-                            let contains_unreferenced_blocks = contained_blocks
-                                .iter()
-                                .any(|block_id| !referenced_blocks.contains(block_id));
-                            if contains_unreferenced_blocks {
-                                let contained_referenced_blocks =
-                                    contained_blocks.intersection(&referenced_blocks).copied();
-                                packs_to_remove.push(pack_id);
-                                blocks_to_repack.extend(contained_referenced_blocks);
-                            }
-                        }
-                        // This pack does not contain any blocks that we know about. We can remove
-                        // it.
-                        None => packs_to_remove.push(pack_id),
-                    }
-                }
-
+            Packing::Fixed(_) | Packing::Variable(_) => {
                 // For each block that needs repacking, read it from its current pack and write it
                 // to a new one.
+                let total_blocks = (blocks_to_repack.len() + blocks_to_remove.len()) as u64;
+                let mut cleaned_blocks = 0u64;
                 {
                     let mut store_state = StoreState::new();
                     let mut store_writer = StoreWriter::new(&mut state, &mut store_state);
                     for block_id in blocks_to_repack {
+                        // Repacking can be safely stopped at any point because the old packs
+                        // haven't been removed yet.
+                        self.progress.report(
+                            Operation::Clean,
+                            cleaned_blocks,
+                            Some(total_blocks),
+                        )?;
                         let block_data = store_writer.read_block(block_id)?;
                         store_writer.write_block(block_id, block_data.as_slice())?;
+                        cleaned_blocks += 1;
                     }
+                    store_writer.finish_uploads()?;
                 }
 
                 // Once all the referenced blocks have been written to new packs, remove the old
-                // packs from the data store.
+                // packs from the data store. Once we start removing old packs, we can't stop until
+                // the updated pack map has been written, so this can't be cancelled.
                 {
-                    let mut store = state.store.lock().unwrap();
-                    for pack_id in packs_to_remove {
-                        store.remove_block(pack_id).map_err(crate::Error::Store)?;
+                    let mut store = state.store.lock().recover();
+                    for batch in blocks_to_remove.chunks(REMOVE_BATCH_SIZE) {
+                        self.progress
+                            .notify(Operation::Clean, cleaned_blocks, Some(total_blocks));
+                        store
+                            .remove_blocks(batch)
+                            .map_err(crate::Error::from_store)?;
+                        cleaned_blocks += batch.len() as u64;
                     }
                 }
 
@@ -804,26 +2482,64 @@ impl<K: Key> Commit for KeyRepo<K> {
                 // Next we need to write the updated pack map to the data store. To do this, we have
                 // to write the entire header. Because this method does not commit any changes, it's
                 // important that we write the previous header, changing only the pack map.
-                {
-                    let mut previous_header = previous_header;
-
-                    // Temporarily move the pack map into the previous header just so that we can
-                    // serialize it. Once we're done, move it back. This avoids needing the clone
-                    // the pack map.
-                    previous_header.packs = mem::replace(&mut state.packs, HashMap::new());
-                    let serialized_header = to_vec(&previous_header)
-                        .expect("Could not serialize the repository header.");
-                    mem::swap(&mut previous_header.packs, &mut state.packs);
-                    drop(previous_header);
-
-                    // Encode the serialized header and write it to the data store.
-                    let encoded_header = state.encode_data(serialized_header.as_slice())?;
-                    drop(state);
-                    self.write_serialized_header(encoded_header.as_slice())?;
-                }
+                let (encoded_header, encoded_shards) = lazy_header::encode_header(
+                    &previous_header.chunks,
+                    &state.packs,
+                    &previous_header.instances,
+                    &previous_header.handle_table,
+                    &*state,
+                )?;
+                drop(previous_header);
+
+                // The blocks of the header we're replacing aren't referenced once the new header
+                // has been written, so we remove them as well. Otherwise, they would be left behind
+                // until the next time the repository is cleaned.
+                let replaced_header_blocks = iter::once(state.metadata.header_id)
+                    .chain(state.metadata.header_shards.iter().copied())
+                    .chain(state.metadata.header_deltas.iter().copied())
+                    .collect::<Vec<_>>();
+
+                // Write the encoded header to the data store.
+                check_generation(&state)?;
+                drop(state);
+                self.write_encoded_header(HeaderBlocks::Full(encoded_header, encoded_shards))?;
+                self.state
+                    .read()
+                    .recover()
+                    .store
+                    .lock()
+                    .recover()
+                    .remove_blocks(&replaced_header_blocks)
+                    .map_err(crate::Error::from_store)?;
+                self.progress
+                    .notify(Operation::Clean, cleaned_blocks, Some(total_blocks));
             }
         }
 
+        // Regroup the blocks which are protected by parity blocks now that unreferenced blocks
+        // have been removed.
+        parity::protect(&self.state.read().recover(), true)?;
+
+        self.audit_and_flush(AuditEvent::Clean);
+
         Ok(())
     }
 }
+
+impl<K: Key> Commit for KeyRepo<K> {
+    fn commit(&self) -> crate::Result<()> {
+        self.inner_mut().commit()
+    }
+
+    fn rollback(&self) -> crate::Result<()> {
+        self.inner_mut().rollback()
+    }
+
+    fn refresh(&self) -> crate::Result<()> {
+        self.inner_mut().refresh()
+    }
+
+    fn clean(&self) -> crate::Result<()> {
+        self.inner_mut().clean()
+    }
+}
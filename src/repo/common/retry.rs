@@ -0,0 +1,299 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::any::type_name;
+use std::cmp::min;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::store::{DataStore, StoreFailure};
+use crate::ErrorContext;
+
+#[cfg(feature = "metrics")]
+use super::telemetry;
+
+/// The default delay before the first retry of a failed store operation.
+const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(100);
+
+/// The default maximum delay between retries of a failed store operation.
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// How to retry operations on the data store which fail with a transient error.
+///
+/// A repository performs every operation on its data store according to this policy, including
+/// the operations performed by the workers started with [`OpenOptions::store_concurrency`]. If
+/// an operation fails with an error which is classified as retryable, it is retried with an
+/// exponential backoff until it succeeds or the maximum number of attempts is reached, at which
+/// point the last error is returned. Errors which are not retryable are
+/// returned immediately.
+///
+/// By default, an error is retryable if it would be returned as [`Error::Unavailable`], which
+/// indicates a network or I/O failure which may go away on its own, like a timeout, a connection
+/// which was reset, or a database which is busy. Use [`classify`] to decide which errors are
+/// retryable instead.
+///
+/// The default policy makes only one attempt, so operations are never retried.
+///
+/// [`OpenOptions::store_concurrency`]: crate::repo::OpenOptions::store_concurrency
+/// [`Error::Unavailable`]: crate::Error::Unavailable
+/// [`classify`]: crate::repo::RetryPolicy::classify
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+    is_retryable: Arc<dyn Fn(&anyhow::Error) -> bool + Send + Sync>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for RetryPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_delay", &self.initial_delay)
+            .field("max_delay", &self.max_delay)
+            .finish()
+    }
+}
+
+impl RetryPolicy {
+    /// Create a new `RetryPolicy` which never retries operations.
+    pub fn new() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_delay: DEFAULT_INITIAL_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+            is_retryable: Arc::new(is_transient),
+        }
+    }
+
+    /// The maximum number of times to attempt each operation, including the first attempt.
+    ///
+    /// The default value is `1`, which means operations are never retried.
+    ///
+    /// # Panics
+    /// - `attempts` is zero.
+    pub fn max_attempts(&mut self, attempts: u32) -> &mut Self {
+        assert!(attempts > 0, "The number of attempts must be at least one.");
+        self.max_attempts = attempts;
+        self
+    }
+
+    /// The delay before the first retry and the maximum delay between retries.
+    ///
+    /// The delay doubles after each failed attempt until it reaches `max`. The default values are
+    /// 100 milliseconds and 10 seconds.
+    pub fn delay(&mut self, initial: Duration, max: Duration) -> &mut Self {
+        self.initial_delay = initial;
+        self.max_delay = max;
+        self
+    }
+
+    /// Decide whether an error returned by the data store is retryable by calling `function`.
+    ///
+    /// The function is passed the error returned by the data store and returns `true` if the
+    /// operation should be retried or `false` if the error should be returned immediately.
+    pub fn classify(
+        &mut self,
+        function: impl Fn(&anyhow::Error) -> bool + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.is_retryable = Arc::new(function);
+        self
+    }
+
+    /// Call `operation` until it succeeds, fails with a fatal error, or runs out of attempts.
+    fn retry<T>(&self, mut operation: impl FnMut() -> anyhow::Result<T>) -> anyhow::Result<T> {
+        #[cfg(feature = "tracing")]
+        let start = std::time::Instant::now();
+
+        let mut attempts = 0;
+        let result = loop {
+            attempts += 1;
+            match operation() {
+                Err(error) if attempts < self.max_attempts && (self.is_retryable)(&error) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(attempts, error = %error, "retrying data store operation");
+                    thread::sleep(self.backoff(attempts));
+                }
+                result => break result,
+            }
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            attempts,
+            elapsed_us = start.elapsed().as_micros() as u64,
+            success = result.is_ok(),
+            "finished data store operation"
+        );
+
+        result
+    }
+
+    /// Return the exponential backoff delay after the given number of failed `attempts`.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let exponent = min(attempts.saturating_sub(1), 16);
+        min(self.initial_delay * 2u32.pow(exponent), self.max_delay)
+    }
+}
+
+/// Return whether `error` was caused by a failure which may go away on its own.
+fn is_transient(error: &anyhow::Error) -> bool {
+    StoreFailure::classify(error) == Some(StoreFailure::Unavailable)
+}
+
+/// A data store which retries failed operations on an inner data store according to a policy.
+///
+/// Writing and removing blocks are idempotent, so every operation is safe to retry.
+pub struct RetryStore<S> {
+    store: S,
+    policy: RetryPolicy,
+}
+
+impl<S: DataStore> RetryStore<S> {
+    /// Wrap `store` so that its operations are retried according to `policy`.
+    pub fn new(store: S, policy: RetryPolicy) -> Self {
+        Self { store, policy }
+    }
+
+    /// Return a function which attaches context about the failed `operation` to an error.
+    fn context(
+        operation: &'static str,
+        block: Option<Uuid>,
+    ) -> impl FnOnce(anyhow::Error) -> anyhow::Error {
+        move |error| {
+            error.context(ErrorContext {
+                operation,
+                block,
+                backend: type_name::<S>(),
+            })
+        }
+    }
+}
+
+impl<S: DataStore> DataStore for RetryStore<S> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, data), fields(size = data.len()))
+    )]
+    fn write_block(&mut self, id: Uuid, data: &[u8]) -> anyhow::Result<()> {
+        let store = &mut self.store;
+        self.policy
+            .retry(|| store.write_block(id, data))
+            .map_err(Self::context("write_block", Some(id)))?;
+        #[cfg(feature = "metrics")]
+        telemetry::store_bytes_written(data.len());
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    fn read_block(&mut self, id: Uuid) -> anyhow::Result<Option<Vec<u8>>> {
+        let store = &mut self.store;
+        let block = self
+            .policy
+            .retry(|| store.read_block(id))
+            .map_err(Self::context("read_block", Some(id)))?;
+        #[cfg(feature = "metrics")]
+        if let Some(data) = &block {
+            telemetry::store_bytes_read(data.len());
+        }
+        Ok(block)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    fn remove_block(&mut self, id: Uuid) -> anyhow::Result<()> {
+        let store = &mut self.store;
+        self.policy
+            .retry(|| store.remove_block(id))
+            .map_err(Self::context("remove_block", Some(id)))
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>> {
+        let store = &mut self.store;
+        self.policy
+            .retry(|| store.list_blocks())
+            .map_err(Self::context("list_blocks", None))
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, blocks), fields(count = blocks.len()))
+    )]
+    fn write_blocks(&mut self, blocks: &[(Uuid, &[u8])]) -> anyhow::Result<()> {
+        let store = &mut self.store;
+        self.policy
+            .retry(|| store.write_blocks(blocks))
+            .map_err(Self::context("write_blocks", None))?;
+        #[cfg(feature = "metrics")]
+        telemetry::store_bytes_written(blocks.iter().map(|(_, data)| data.len()).sum());
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, ids), fields(count = ids.len()))
+    )]
+    fn read_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let store = &mut self.store;
+        let blocks = self
+            .policy
+            .retry(|| store.read_blocks(ids))
+            .map_err(Self::context("read_blocks", None))?;
+        #[cfg(feature = "metrics")]
+        telemetry::store_bytes_read(blocks.iter().flatten().map(|data| data.len()).sum());
+        Ok(blocks)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    fn read_block_range(
+        &mut self,
+        id: Uuid,
+        offset: u64,
+        len: u64,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let store = &mut self.store;
+        let block = self
+            .policy
+            .retry(|| store.read_block_range(id, offset, len))
+            .map_err(Self::context("read_block_range", Some(id)))?;
+        #[cfg(feature = "metrics")]
+        if let Some(data) = &block {
+            telemetry::store_bytes_read(data.len());
+        }
+        Ok(block)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, ids), fields(count = ids.len()))
+    )]
+    fn remove_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<()> {
+        let store = &mut self.store;
+        self.policy
+            .retry(|| store.remove_blocks(ids))
+            .map_err(Self::context("remove_blocks", None))
+    }
+}
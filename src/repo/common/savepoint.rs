@@ -20,7 +20,7 @@ use std::sync::{Arc, RwLock, Weak};
 use uuid::Uuid;
 
 use super::handle::ObjectHandle;
-use super::metadata::Header;
+use super::metadata::{Header, RepoMetadata};
 
 /// A target for rolling back changes in a repository.
 ///
@@ -107,7 +107,7 @@ pub trait Restore: Clone {
 /// # use acid_store::store::MemoryConfig;
 /// # use acid_store::repo::{RestoreSavepoint, OpenOptions, OpenMode, key::KeyRepo};
 /// #
-/// # let mut repo: KeyRepo<String> = OpenOptions::new()
+/// # let repo: KeyRepo<String> = OpenOptions::new()
 /// #     .mode(OpenMode::CreateNew)
 /// #     .open(&MemoryConfig::new())
 /// #     .unwrap();
@@ -145,7 +145,7 @@ pub trait RestoreSavepoint {
     /// - `Error::Io`: An I/O error occurred.
     ///
     /// [`Savepoint`]: crate::repo::Savepoint
-    fn savepoint(&mut self) -> crate::Result<Savepoint>;
+    fn savepoint(&self) -> crate::Result<Savepoint>;
 
     /// Start the process of restoring the repository to the given `savepoint`.
     ///
@@ -155,6 +155,7 @@ pub trait RestoreSavepoint {
     /// # Errors
     /// - `Error::InvalidSavepoint`: The given savepoint is invalid or not associated with this
     /// repository.
+    /// - `Error::Vetoed`: A hook registered to run before restoring vetoed the restore.
     /// - `Error::InvalidData`: Ciphertext verification failed.
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
@@ -162,7 +163,7 @@ pub trait RestoreSavepoint {
     /// [`Restore`]: crate::repo::Restore
     /// [`finish_restore`]: crate::repo::RestoreSavepoint::finish_restore
     /// [`Savepoint`]: crate::repo::Savepoint
-    fn start_restore(&mut self, savepoint: &Savepoint) -> crate::Result<Self::Restore>;
+    fn start_restore(&self, savepoint: &Savepoint) -> crate::Result<Self::Restore>;
 
     /// Finish the process of restoring the repository to a [`Savepoint`].
     ///
@@ -176,7 +177,7 @@ pub trait RestoreSavepoint {
     /// [`Savepoint`]: crate::repo::Savepoint
     /// [`start_restore`]: crate::repo::RestoreSavepoint::start_restore
     /// [`Restore`]: crate::repo::Restore
-    fn finish_restore(&mut self, restore: Self::Restore) -> bool;
+    fn finish_restore(&self, restore: Self::Restore) -> bool;
 
     /// Restore the repository to the given `savepoint`.
     ///
@@ -185,13 +186,14 @@ pub trait RestoreSavepoint {
     /// # Errors
     /// - `Error::InvalidSavepoint`: The given savepoint is invalid or not associated with this
     /// repository.
+    /// - `Error::Vetoed`: A hook registered to run before restoring vetoed the restore.
     /// - `Error::InvalidData`: Ciphertext verification failed.
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
     ///
     /// [`start_restore`]: crate::repo::RestoreSavepoint::start_restore
     /// [`finish_restore`]: crate::repo::RestoreSavepoint::finish_restore
-    fn restore(&mut self, savepoint: &Savepoint) -> crate::Result<()> {
+    fn restore(&self, savepoint: &Savepoint) -> crate::Result<()> {
         let restore = self.start_restore(savepoint)?;
         self.finish_restore(restore);
         Ok(())
@@ -210,6 +212,13 @@ pub struct KeyRestore<K> {
     pub(super) instance_id: Uuid,
 }
 
+/// The state of a [`KeyRepo`] from before it was refreshed, which can be used to undo the refresh.
+#[derive(Debug)]
+pub struct RefreshBackup<K> {
+    pub(super) restore: KeyRestore<K>,
+    pub(super) metadata: RepoMetadata,
+}
+
 impl<K: Clone> Restore for KeyRestore<K> {
     /// Return whether the savepoint used to start this restore is valid.
     fn is_valid(&self) -> bool {
@@ -16,31 +16,50 @@
 
 use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Debug, Formatter};
-use std::sync::Mutex;
+use std::io::Read;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard};
+use std::thread;
 
+use bytes::Bytes;
 use cdchunking::ChunkerImpl;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::store::DataStore;
 
-use super::chunk_store::StoreState;
+use super::audit_log::AuditLog;
+use super::cache::ChunkCache;
+use super::chunk_store::{EncodeBlock, StoreState};
 use super::chunking::IncrementalChunker;
 use super::encryption::EncryptionKey;
 use super::handle::{Chunk, Extent, ObjectHandle};
 use super::id_table::UniqueId;
+use super::lazy_header::LazyHeader;
+use super::lease::Lease;
+use super::lease_renewal::LeaseRenewal;
 use super::lock::Lock;
 use super::lock::LockTable;
 use super::metadata::RepoMetadata;
+use super::open_repo::DEFAULT_BRANCH;
+use super::poison::RecoverPoison;
+use super::stats::StatsCollector;
+use super::store_pool::StorePool;
 
 /// Information about a chunk in a repository.
 #[derive(Debug, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
 pub struct ChunkInfo {
     /// The ID of the block in the data store which stores this chunk.
+    ///
+    /// This is the nil UUID if the chunk is stored inline.
     pub block_id: Uuid,
 
     /// The IDs of objects which reference this chunk.
     pub references: HashSet<UniqueId>,
+
+    /// The contents of the chunk if it is stored inline in the header instead of in a block.
+    #[serde(default)]
+    pub inline: Option<Vec<u8>>,
 }
 
 /// The location of a block in a pack.
@@ -98,16 +117,47 @@ pub struct InstanceInfo {
 
     /// The object handle used to store the serialized object map.
     ///
-    /// This object handle contains a serialized map of object IDs to object handles for that
-    /// instance.
+    /// This object handle contains a serialized map of object IDs to object handles for the current
+    /// branch of that instance.
     pub objects: ObjectHandle,
+
+    /// The name of the current branch of this instance.
+    #[serde(default = "default_branch")]
+    pub branch: String,
+
+    /// A map of the names of the other branches of this instance to their object maps.
+    ///
+    /// Each object handle contains a serialized map of object IDs to object handles for that
+    /// branch.
+    #[serde(default)]
+    pub branches: HashMap<String, ObjectHandle>,
+}
+
+/// Return the name of the branch which instances start with.
+fn default_branch() -> String {
+    String::from(DEFAULT_BRANCH)
+}
+
+/// The entries in the repository header which have changed since the last commit.
+#[derive(Debug, Default)]
+pub struct HeaderChanges {
+    /// The chunks which have been added, changed, or removed.
+    pub chunks: HashSet<Chunk>,
+
+    /// The blocks whose pack indices have been added, changed, or removed.
+    pub packs: HashSet<Uuid>,
+
+    /// Whether the header has been replaced since the last commit.
+    ///
+    /// If this is `true`, the changes can't be tracked and the whole header must be written.
+    pub replaced: bool,
 }
 
 /// The state associated with a `KeyRepo`.
 #[derive(Debug)]
 pub struct RepoState {
     /// The data store which backs this repository.
-    pub store: Mutex<Box<dyn DataStore>>,
+    pub store: Mutex<Box<dyn DataStore + Send>>,
 
     /// The metadata for the repository.
     pub metadata: RepoMetadata,
@@ -118,6 +168,16 @@ pub struct RepoState {
     /// A map of block IDs to their locations in packs.
     pub packs: HashMap<Uuid, Vec<PackIndex>>,
 
+    /// The entries in `chunks` and `packs` which have changed since the last commit.
+    pub header_changes: HeaderChanges,
+
+    /// The entries of `chunks` and `packs` which haven't been loaded from the data store yet.
+    ///
+    /// This is only `Some` if the repository was opened in read-only mode, in which case the
+    /// chunk and pack maps are loaded on demand. Use `chunk_info` and `pack_indices` to look up
+    /// entries which may not have been loaded yet.
+    pub lazy_header: Option<LazyHeader>,
+
     /// A table used to track current transactions for each object.
     pub transactions: LockTable<UniqueId>,
 
@@ -125,7 +185,218 @@ pub struct RepoState {
     pub master_key: EncryptionKey,
 
     /// The lock on the repository.
-    pub lock: Lock<Uuid>,
+    ///
+    /// This is `None` if the repository was opened with optimistic concurrency.
+    pub lock: Option<Lock<Uuid>>,
+
+    /// Whether to check for commits from other writers before committing.
+    pub optimistic: bool,
+
+    /// Whether the repository was opened in read-only mode.
+    pub read_only: bool,
+
+    /// Whether to remove unreferenced blocks in the background after each commit.
+    pub background_clean: bool,
+
+    /// The number of worker threads to use for operations which can be done concurrently.
+    pub threads: usize,
+
+    /// The thread pool for CPU-bound work, if more than one thread was requested.
+    #[cfg(feature = "rayon")]
+    pub rayon_pool: Option<Arc<rayon::ThreadPool>>,
+
+    /// The lease on the repository, if leases are enabled.
+    pub lease: Option<Lease>,
+
+    /// The thread which renews `lease` in the background, if leases are enabled.
+    pub lease_renewal: Option<LeaseRenewal>,
+
+    /// The pool for reading and writing blocks in the data store concurrently, if enabled.
+    pub pool: Option<StorePool>,
+
+    /// The cache of recently read chunks which is shared by every object in the repository.
+    pub chunk_cache: Mutex<ChunkCache>,
+
+    /// Statistics about the operations performed by the repository.
+    pub stats: Arc<StatsCollector>,
+
+    /// The entries in the audit log which haven't been written yet.
+    pub audit_log: AuditLog,
+
+    /// Whether a thread panicked while it was modifying this state.
+    ///
+    /// Changes which were in progress when the thread panicked may be half-finished, so the
+    /// repository can't be committed until it is rolled back.
+    pub poisoned: bool,
+}
+
+impl RepoState {
+    /// Read the block with the given `id` from the data store and return a reader which decodes
+    /// it.
+    fn read_decoded_block(&self, id: Uuid) -> crate::Result<Box<dyn Read>> {
+        let encoded_block = self
+            .store
+            .lock()
+            .recover()
+            .read_block(id)
+            .map_err(crate::Error::from_store)?
+            .ok_or(crate::Error::Corrupt)?;
+        self.decode_reader(encoded_block)
+    }
+
+    /// Return information about the given `chunk`, loading it from the data store if necessary.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository header could not be read.
+    /// - `Error::Store`: An error occurred with the data store.
+    pub fn chunk_info(&self, chunk: &Chunk) -> crate::Result<Option<&ChunkInfo>> {
+        if let Some(chunk_info) = self.chunks.get(chunk) {
+            return Ok(Some(chunk_info));
+        }
+        match &self.lazy_header {
+            Some(lazy_header) => lazy_header.chunk_info(chunk, |id| self.read_decoded_block(id)),
+            None => Ok(None),
+        }
+    }
+
+    /// Return the pack indices for the given `block_id`, loading them from the data store if
+    /// necessary.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository header could not be read.
+    /// - `Error::Store`: An error occurred with the data store.
+    pub fn pack_indices(&self, block_id: &Uuid) -> crate::Result<Option<&Vec<PackIndex>>> {
+        if let Some(pack_indices) = self.packs.get(block_id) {
+            return Ok(Some(pack_indices));
+        }
+        match &self.lazy_header {
+            Some(lazy_header) => {
+                lazy_header.pack_indices(block_id, |id| self.read_decoded_block(id))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Load the entries of the chunk and pack maps which haven't been loaded yet.
+    ///
+    /// If this returns `Err`, the repository is unchanged.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository header could not be read.
+    /// - `Error::Store`: An error occurred with the data store.
+    pub fn load_header(&mut self) -> crate::Result<()> {
+        if let Some(lazy_header) = &self.lazy_header {
+            lazy_header.load_all(|id| self.read_decoded_block(id))?;
+        }
+        if let Some(lazy_header) = self.lazy_header.take() {
+            lazy_header.merge_into(&mut self.chunks, &mut self.packs);
+        }
+        Ok(())
+    }
+
+    /// Add the object with the given `id` to the references of the given `chunk`.
+    ///
+    /// # Panics
+    /// - The chunk is not in the repository.
+    pub fn add_reference(&mut self, chunk: Chunk, id: UniqueId) {
+        // References can only change while the chunk map is partially loaded if the repository is
+        // read-only, in which case they can never be committed.
+        if self.lazy_header.is_some() {
+            return;
+        }
+        self.chunks
+            .get_mut(&chunk)
+            .expect("This chunk was not found in the repository.")
+            .references
+            .insert(id);
+        self.header_changes.chunks.insert(chunk);
+    }
+
+    /// Remove the object with the given `id` from the references of the given `chunk`.
+    ///
+    /// If the chunk is no longer referenced by any objects, it is removed from the repository.
+    ///
+    /// # Panics
+    /// - The chunk is not in the repository.
+    pub fn remove_reference(&mut self, chunk: Chunk, id: UniqueId) {
+        // References can only change while the chunk map is partially loaded if the repository is
+        // read-only, in which case they can never be committed.
+        if self.lazy_header.is_some() {
+            return;
+        }
+        let chunk_info = self
+            .chunks
+            .get_mut(&chunk)
+            .expect("This chunk was not found in the repository.");
+        chunk_info.references.remove(&id);
+        if chunk_info.references.is_empty() {
+            self.chunks.remove(&chunk);
+        }
+        self.header_changes.chunks.insert(chunk);
+    }
+}
+
+/// A write guard for a `RepoState` which marks it as poisoned if the thread panics.
+///
+/// The lock in the standard library stays poisoned forever once a thread panics while holding it,
+/// so the repository tracks poisoning itself in `RepoState::poisoned`, which is cleared when the
+/// repository is rolled back.
+pub struct StateWriteGuard<'a> {
+    guard: RwLockWriteGuard<'a, RepoState>,
+
+    /// Whether the thread was already panicking when the guard was acquired.
+    panicking: bool,
+}
+
+impl<'a> Deref for StateWriteGuard<'a> {
+    type Target = RepoState;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<'a> DerefMut for StateWriteGuard<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<'a> Drop for StateWriteGuard<'a> {
+    fn drop(&mut self) {
+        if !self.panicking && thread::panicking() {
+            self.guard.poisoned = true;
+        }
+    }
+}
+
+/// An extension trait for acquiring the write lock on a `RepoState`.
+pub trait WriteState {
+    /// Acquire the write lock, recovering the guard if the lock was poisoned.
+    fn write_state(&self) -> StateWriteGuard<'_>;
+}
+
+impl WriteState for RwLock<RepoState> {
+    fn write_state(&self) -> StateWriteGuard<'_> {
+        StateWriteGuard {
+            guard: self.write().recover(),
+            panicking: thread::panicking(),
+        }
+    }
+}
+
+impl Drop for RepoState {
+    fn drop(&mut self) {
+        // Wait for blocks which are still being written and stop renewing the lease before
+        // releasing it.
+        self.pool = None;
+        self.lease_renewal = None;
+
+        if let (Some(lease), Ok(store)) = (&self.lease, self.store.get_mut()) {
+            // If this fails, the lease will still expire eventually.
+            lease.release(&mut **store).ok();
+        }
+    }
 }
 
 /// A seek position in an object.
@@ -183,10 +454,20 @@ pub struct ObjectState {
     pub buffered_chunk: Option<Chunk>,
 
     /// The contents of the chunk which was most recently read from.
-    pub read_buffer: Vec<u8>,
+    ///
+    /// If only part of the chunk was read, this contains just that part.
+    pub read_buffer: Bytes,
+
+    /// The offset from the start of the buffered chunk where the read buffer starts.
+    pub buffered_offset: u64,
+
+    /// The index of the extent containing the chunk which was most recently read from.
+    ///
+    /// This is used to detect when the object is being read sequentially.
+    pub buffered_index: Option<usize>,
 
     /// A pre-allocated buffer of null bytes to read from when reading a hole.
-    pub hole_buffer: Vec<u8>,
+    pub hole_buffer: Bytes,
 
     /// A lock representing the current transaction if there is one.
     pub transaction_lock: Option<Lock<UniqueId>>,
@@ -197,15 +478,17 @@ pub struct ObjectState {
 
 impl ObjectState {
     /// Create a new empty state for a repository with a given chunk size.
-    pub fn new(chunker: Box<dyn ChunkerImpl>) -> Self {
+    pub fn new(chunker: Box<dyn ChunkerImpl + Send + Sync>) -> Self {
         Self {
             chunker: IncrementalChunker::new(chunker),
             new_chunks: Vec::new(),
             start_position: SeekPosition::Empty,
             position: 0,
             buffered_chunk: None,
-            read_buffer: Vec::new(),
-            hole_buffer: Vec::new(),
+            read_buffer: Bytes::new(),
+            buffered_offset: 0,
+            buffered_index: None,
+            hole_buffer: Bytes::new(),
             transaction_lock: None,
             store_state: StoreState::new(),
         }
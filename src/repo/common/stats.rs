@@ -0,0 +1,282 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::store::DataStore;
+
+/// Statistics about one type of operation performed by a repository.
+///
+/// This is part of [`RepoStats`].
+///
+/// [`RepoStats`]: crate::repo::RepoStats
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub struct OperationStats {
+    /// The number of times the operation was performed.
+    pub count: u64,
+
+    /// The total number of bytes processed by the operation.
+    pub bytes: u64,
+
+    /// The total time spent performing the operation.
+    pub duration: Duration,
+}
+
+impl OperationStats {
+    /// The average time it took to perform the operation, or `None` if it was never performed.
+    pub fn mean_duration(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(Duration::from_nanos(
+            (self.duration.as_nanos() / u128::from(self.count)) as u64,
+        ))
+    }
+
+    /// The average number of bytes processed per second, or `None` if no time was spent.
+    pub fn throughput(&self) -> Option<f64> {
+        let seconds = self.duration.as_secs_f64();
+        if seconds == 0.0 {
+            return None;
+        }
+        Some(self.bytes as f64 / seconds)
+    }
+}
+
+/// Statistics about the operations performed by a repository since it was opened.
+///
+/// These statistics can be used to tell whether time is being spent in the data store, in
+/// compression and encryption, or in chunking. Operations on the data store include any time spent
+/// retrying them according to the [`RetryPolicy`].
+///
+/// This is returned by [`KeyRepo::stats`].
+///
+/// [`RetryPolicy`]: crate::repo::RetryPolicy
+/// [`KeyRepo::stats`]: crate::repo::key::KeyRepo::stats
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub struct RepoStats {
+    /// Reading blocks from the data store.
+    pub store_read: OperationStats,
+
+    /// Writing blocks to the data store.
+    pub store_write: OperationStats,
+
+    /// Removing blocks from the data store.
+    pub store_remove: OperationStats,
+
+    /// Listing the blocks in the data store.
+    ///
+    /// The number of bytes is always `0`.
+    pub store_list: OperationStats,
+
+    /// Compressing and encrypting chunks before they're written to the data store.
+    ///
+    /// The number of bytes is measured before compression and encryption.
+    pub encode: OperationStats,
+
+    /// Decrypting and decompressing chunks after they're read from the data store.
+    ///
+    /// The number of bytes is measured before decryption and decompression.
+    pub decode: OperationStats,
+
+    /// Splitting data written to objects into chunks.
+    pub chunking: OperationStats,
+
+    /// Computing the checksums of chunks.
+    pub hashing: OperationStats,
+}
+
+/// A thread-safe counter for statistics about one type of operation.
+#[derive(Debug, Default)]
+pub struct OperationCounter {
+    count: AtomicU64,
+    bytes: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl OperationCounter {
+    /// Record that the operation processed `bytes` bytes and took `duration`.
+    pub fn record(&self, bytes: u64, duration: Duration) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Call `operation`, record how long it took, and return its result.
+    ///
+    /// The number of bytes processed is computed from the result by `bytes`.
+    pub fn time<T>(&self, operation: impl FnOnce() -> T, bytes: impl FnOnce(&T) -> u64) -> T {
+        let start = Instant::now();
+        let result = operation();
+        self.record(bytes(&result), start.elapsed());
+        result
+    }
+
+    /// Return a snapshot of the statistics.
+    fn snapshot(&self) -> OperationStats {
+        OperationStats {
+            count: self.count.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            duration: Duration::from_nanos(self.nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Reset the statistics to zero.
+    fn reset(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+        self.nanos.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Thread-safe counters for the statistics in `RepoStats`.
+#[derive(Debug, Default)]
+pub struct StatsCollector {
+    pub store_read: OperationCounter,
+    pub store_write: OperationCounter,
+    pub store_remove: OperationCounter,
+    pub store_list: OperationCounter,
+    pub encode: OperationCounter,
+    pub decode: OperationCounter,
+    pub chunking: OperationCounter,
+    pub hashing: OperationCounter,
+}
+
+impl StatsCollector {
+    /// Return a snapshot of the statistics.
+    pub fn snapshot(&self) -> RepoStats {
+        RepoStats {
+            store_read: self.store_read.snapshot(),
+            store_write: self.store_write.snapshot(),
+            store_remove: self.store_remove.snapshot(),
+            store_list: self.store_list.snapshot(),
+            encode: self.encode.snapshot(),
+            decode: self.decode.snapshot(),
+            chunking: self.chunking.snapshot(),
+            hashing: self.hashing.snapshot(),
+        }
+    }
+
+    /// Reset all the statistics to zero.
+    pub fn reset(&self) {
+        self.store_read.reset();
+        self.store_write.reset();
+        self.store_remove.reset();
+        self.store_list.reset();
+        self.encode.reset();
+        self.decode.reset();
+        self.chunking.reset();
+        self.hashing.reset();
+    }
+}
+
+/// Return the number of bytes in a block read from the data store.
+fn block_size(result: &anyhow::Result<Option<Vec<u8>>>) -> u64 {
+    match result {
+        Ok(Some(data)) => data.len() as u64,
+        _ => 0,
+    }
+}
+
+/// A data store which records statistics about the operations performed on an inner data store.
+pub struct StatsStore<S> {
+    store: S,
+    stats: Arc<StatsCollector>,
+}
+
+impl<S: DataStore> StatsStore<S> {
+    /// Wrap `store` so that statistics about its operations are recorded in `stats`.
+    pub fn new(store: S, stats: Arc<StatsCollector>) -> Self {
+        Self { store, stats }
+    }
+}
+
+impl<S: DataStore> DataStore for StatsStore<S> {
+    fn write_block(&mut self, id: Uuid, data: &[u8]) -> anyhow::Result<()> {
+        let store = &mut self.store;
+        self.stats.store_write.time(
+            || store.write_block(id, data),
+            |result| if result.is_ok() { data.len() as u64 } else { 0 },
+        )
+    }
+
+    fn read_block(&mut self, id: Uuid) -> anyhow::Result<Option<Vec<u8>>> {
+        let store = &mut self.store;
+        self.stats
+            .store_read
+            .time(|| store.read_block(id), block_size)
+    }
+
+    fn remove_block(&mut self, id: Uuid) -> anyhow::Result<()> {
+        let store = &mut self.store;
+        self.stats
+            .store_remove
+            .time(|| store.remove_block(id), |_| 0)
+    }
+
+    fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>> {
+        let store = &mut self.store;
+        self.stats.store_list.time(|| store.list_blocks(), |_| 0)
+    }
+
+    fn write_blocks(&mut self, blocks: &[(Uuid, &[u8])]) -> anyhow::Result<()> {
+        let store = &mut self.store;
+        self.stats.store_write.time(
+            || store.write_blocks(blocks),
+            |result| match result {
+                Ok(()) => blocks.iter().map(|(_, data)| data.len() as u64).sum(),
+                Err(_) => 0,
+            },
+        )
+    }
+
+    fn read_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let store = &mut self.store;
+        self.stats.store_read.time(
+            || store.read_blocks(ids),
+            |result| match result {
+                Ok(blocks) => blocks.iter().flatten().map(|data| data.len() as u64).sum(),
+                Err(_) => 0,
+            },
+        )
+    }
+
+    fn read_block_range(
+        &mut self,
+        id: Uuid,
+        offset: u64,
+        len: u64,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let store = &mut self.store;
+        self.stats
+            .store_read
+            .time(|| store.read_block_range(id, offset, len), block_size)
+    }
+
+    fn remove_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<()> {
+        let store = &mut self.store;
+        self.stats
+            .store_remove
+            .time(|| store.remove_blocks(ids), |_| 0)
+    }
+}
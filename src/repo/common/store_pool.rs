@@ -0,0 +1,277 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashSet;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use uuid::Uuid;
+
+use crate::store::DataStore;
+
+use super::poison::RecoverPoison;
+
+/// The number of blocks each worker can have waiting to be written.
+const BLOCKS_PER_WORKER: usize = 2;
+
+/// An operation which has been submitted to a `StorePool`.
+enum Job {
+    /// Write a block to the data store.
+    Write {
+        /// The ID of the block.
+        id: Uuid,
+
+        /// The encoded contents of the block.
+        data: Vec<u8>,
+
+        /// The sender for reporting whether the block was written.
+        result: Sender<anyhow::Result<()>>,
+    },
+
+    /// Read a block from the data store.
+    Read {
+        /// The ID of the block.
+        id: Uuid,
+
+        /// The sender for returning the contents of the block.
+        result: Sender<anyhow::Result<Option<Vec<u8>>>>,
+    },
+}
+
+/// A pool of worker threads which read and write blocks in the data store concurrently.
+///
+/// Data stores can't be shared between threads, so each worker owns a separate connection to the
+/// data store.
+pub struct StorePool {
+    /// The sender for submitting jobs to the workers.
+    jobs: Mutex<Option<SyncSender<Job>>>,
+
+    /// The IDs of blocks which have been submitted but which aren't yet referenced by the
+    /// repository.
+    ///
+    /// These blocks must not be removed when the repository is cleaned.
+    unreferenced: Arc<Mutex<HashSet<Uuid>>>,
+
+    /// The handles for the worker threads.
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Debug for StorePool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StorePool")
+            .field("workers", &self.workers.len())
+            .finish()
+    }
+}
+
+impl StorePool {
+    /// Start a new pool with one worker for each of the given `stores`.
+    pub fn new(stores: Vec<Box<dyn DataStore + Send>>) -> Self {
+        let (job_sender, job_receiver) = sync_channel(stores.len() * BLOCKS_PER_WORKER);
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = stores
+            .into_iter()
+            .map(|store| {
+                let job_receiver = Arc::clone(&job_receiver);
+                thread::spawn(move || run_worker(store, &job_receiver))
+            })
+            .collect();
+
+        Self {
+            jobs: Mutex::new(Some(job_sender)),
+            unreferenced: Arc::new(Mutex::new(HashSet::new())),
+            workers,
+        }
+    }
+
+    /// Submit the block with the given `id` and encoded `data` to be written.
+    ///
+    /// This blocks if the workers are already busy with as many blocks as they can buffer.
+    pub fn upload(&self, id: Uuid, data: Vec<u8>) -> PendingUpload {
+        let (result_sender, result_receiver) = channel();
+        self.unreferenced.lock().recover().insert(id);
+        let pending = PendingUpload {
+            id,
+            result: Mutex::new(result_receiver),
+            unreferenced: Arc::clone(&self.unreferenced),
+        };
+
+        self.submit(Job::Write {
+            id,
+            data,
+            result: result_sender,
+        });
+
+        pending
+    }
+
+    /// Start reading the block with the given `id` using one of the workers.
+    ///
+    /// This blocks if the workers are already busy with as many blocks as they can buffer.
+    pub fn start_read(&self, id: Uuid) -> PendingRead {
+        let (result_sender, result_receiver) = channel();
+        self.submit(Job::Read {
+            id,
+            result: result_sender,
+        });
+        PendingRead {
+            id,
+            result: Mutex::new(result_receiver),
+        }
+    }
+
+    /// Read the block with the given `id` using one of the workers.
+    ///
+    /// This blocks until the block has been read. Multiple threads can read blocks through the
+    /// same pool at once.
+    ///
+    /// # Errors
+    /// - `Error::Store`: An error occurred with the data store.
+    pub fn read(&self, id: Uuid) -> crate::Result<Option<Vec<u8>>> {
+        self.start_read(id).wait()
+    }
+
+    /// Return the number of workers in the pool.
+    pub fn concurrency(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Submit the given `job` to the workers.
+    fn submit(&self, job: Job) {
+        let jobs = self.jobs.lock().recover().clone();
+        if let Some(jobs) = jobs {
+            // This only fails if all the workers have stopped, in which case the receiver for the
+            // result reports the error.
+            jobs.send(job).ok();
+        }
+    }
+
+    /// Return the IDs of blocks which have been submitted but aren't yet referenced.
+    pub fn unreferenced(&self) -> HashSet<Uuid> {
+        self.unreferenced.lock().recover().clone()
+    }
+}
+
+impl Drop for StorePool {
+    fn drop(&mut self) {
+        // Dropping the sender causes the workers to stop once they've finished their current
+        // blocks.
+        *self.jobs.get_mut().recover() = None;
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+/// A block which is being written by a `StorePool`.
+///
+/// Once this is dropped, the block is no longer protected from being cleaned, so it must be
+/// referenced by the repository first if it was written successfully.
+pub struct PendingUpload {
+    /// The ID of the block.
+    id: Uuid,
+
+    /// The receiver for the result of writing the block.
+    result: Mutex<Receiver<anyhow::Result<()>>>,
+
+    /// The set of unreferenced blocks in the `StorePool` this block was submitted to.
+    unreferenced: Arc<Mutex<HashSet<Uuid>>>,
+}
+
+impl Debug for PendingUpload {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PendingUpload").field(&self.id).finish()
+    }
+}
+
+impl PendingUpload {
+    /// Block until the block has been written.
+    ///
+    /// # Errors
+    /// - `Error::Store`: An error occurred with the data store.
+    pub fn wait(&self) -> crate::Result<()> {
+        match self.result.lock().recover().recv() {
+            Ok(result) => result.map_err(crate::Error::from_store),
+            Err(_) => Err(crate::Error::Store(anyhow::anyhow!(
+                "The worker writing the block stopped unexpectedly."
+            ))),
+        }
+    }
+}
+
+impl Drop for PendingUpload {
+    fn drop(&mut self) {
+        self.unreferenced.lock().recover().remove(&self.id);
+    }
+}
+
+/// A block which is being read by a `StorePool`.
+///
+/// If this is dropped before the block has been read, the contents of the block are discarded.
+pub struct PendingRead {
+    /// The ID of the block.
+    id: Uuid,
+
+    /// The receiver for the contents of the block.
+    result: Mutex<Receiver<anyhow::Result<Option<Vec<u8>>>>>,
+}
+
+impl Debug for PendingRead {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PendingRead").field(&self.id).finish()
+    }
+}
+
+impl PendingRead {
+    /// Block until the block has been read and return its contents.
+    ///
+    /// # Errors
+    /// - `Error::CorruptBlock`: The block failed its checksum.
+    /// - `Error::Store`: An error occurred with the data store.
+    pub fn wait(&self) -> crate::Result<Option<Vec<u8>>> {
+        match self.result.lock().recover().recv() {
+            Ok(result) => result.map_err(crate::Error::from_store),
+            Err(_) => Err(crate::Error::Store(anyhow::anyhow!(
+                "The worker reading the block stopped unexpectedly."
+            ))),
+        }
+    }
+}
+
+/// Perform jobs received from `jobs` using `store` until the pool is dropped.
+fn run_worker(mut store: Box<dyn DataStore + Send>, jobs: &Mutex<Receiver<Job>>) {
+    loop {
+        // Only hold the lock while waiting for the next job so other workers can receive jobs
+        // while this one is busy.
+        let job = match jobs.lock().recover().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        // The receiver may have been dropped if the transaction was discarded or a prefetched
+        // block is no longer needed.
+        match job {
+            Job::Write { id, data, result } => {
+                result.send(store.write_block(id, data.as_slice())).ok();
+            }
+            Job::Read { id, result } => {
+                result.send(store.read_block(id)).ok();
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Metrics which are exported through the `metrics` facade.
+
+use std::time::Duration;
+
+/// The number of new chunks written to the repository.
+const CHUNKS_WRITTEN: &str = "acid_store_chunks_written_total";
+
+/// The number of chunks which were deduplicated against existing chunks instead of being written.
+const CHUNKS_DEDUPLICATED: &str = "acid_store_chunks_deduplicated_total";
+
+/// The number of bytes written to the data store.
+const STORE_BYTES_WRITTEN: &str = "acid_store_store_bytes_written_total";
+
+/// The number of bytes read from the data store.
+const STORE_BYTES_READ: &str = "acid_store_store_bytes_read_total";
+
+/// The time it takes to commit a transaction, in seconds.
+const COMMIT_DURATION: &str = "acid_store_commit_duration_seconds";
+
+/// Record that a new chunk was written to the repository.
+pub(super) fn chunk_written() {
+    metrics::increment_counter!(CHUNKS_WRITTEN);
+}
+
+/// Record that a chunk was deduplicated against a chunk which already exists.
+pub(super) fn chunk_deduplicated() {
+    metrics::increment_counter!(CHUNKS_DEDUPLICATED);
+}
+
+/// Record that `len` bytes were written to the data store.
+pub(super) fn store_bytes_written(len: usize) {
+    metrics::counter!(STORE_BYTES_WRITTEN, len as u64);
+}
+
+/// Record that `len` bytes were read from the data store.
+pub(super) fn store_bytes_read(len: usize) {
+    metrics::counter!(STORE_BYTES_READ, len as u64);
+}
+
+/// Record that a commit completed successfully after `duration`.
+pub(super) fn commit_finished(duration: Duration) {
+    metrics::histogram!(COMMIT_DURATION, duration.as_secs_f64());
+}
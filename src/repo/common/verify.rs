@@ -0,0 +1,148 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+
+use super::chunk_store::{ReadChunk, StoreReader, StoreState};
+use super::handle::{chunk_hash, Chunk};
+use super::poison::RecoverPoison;
+use super::state::RepoState;
+
+/// The number of results each worker can have waiting to be received.
+const RESULTS_PER_WORKER: usize = 4;
+
+/// Return whether the given `data` read from the data store matches `chunk`.
+///
+/// `data` is the result of reading the chunk, so ciphertext and checksum verification failures are
+/// counted as corruption rather than returned as errors.
+///
+/// # Errors
+/// - `Error::Store`: An error occurred with the data store.
+/// - `Error::Io`: An I/O error occurred.
+pub fn chunk_is_intact(chunk: Chunk, data: crate::Result<Vec<u8>>) -> crate::Result<bool> {
+    match data {
+        Ok(data) => Ok(data.len() == chunk.size as usize && chunk_hash(&data) == chunk.hash),
+        // Ciphertext or checksum verification failed. No need to check the hash.
+        Err(crate::Error::InvalidData) | Err(crate::Error::CorruptBlock(_)) => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// A pool of worker threads which read and verify chunks.
+///
+/// Each worker only holds a read lock on the repository state while reading a chunk, and chunks
+/// are hashed without holding the lock.
+///
+/// Unless the repository has a store pool, the data store is behind a single `Mutex` in the
+/// repository state, so the workers read from the data store one block at a time and only
+/// decrypt, decompress, and hash chunks in parallel. With a store pool, they read through the
+/// pool's connections concurrently.
+pub struct VerifyPool {
+    /// The sender for submitting chunks to the workers.
+    jobs: Option<Sender<Chunk>>,
+
+    /// The receiver for whether each chunk is intact.
+    results: Option<Receiver<(Chunk, crate::Result<bool>)>>,
+
+    /// The handles for the worker threads.
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl VerifyPool {
+    /// Start a new pool with the given number of `threads` which verify chunks in `state`.
+    pub fn new(threads: usize, state: &Arc<RwLock<RepoState>>) -> Self {
+        let (job_sender, job_receiver) = channel::<Chunk>();
+        let (result_sender, result_receiver) = sync_channel(threads * RESULTS_PER_WORKER);
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..threads)
+            .map(|_| {
+                let job_receiver = Arc::clone(&job_receiver);
+                let result_sender = result_sender.clone();
+                let state = Arc::clone(state);
+                thread::spawn(move || run_worker(&job_receiver, &result_sender, &state))
+            })
+            .collect();
+
+        Self {
+            jobs: Some(job_sender),
+            results: Some(result_receiver),
+            workers,
+        }
+    }
+
+    /// Submit the given `chunk` to be verified.
+    pub fn submit(&self, chunk: Chunk) {
+        if let Some(jobs) = &self.jobs {
+            // This only fails if all the workers have stopped, in which case `recv` returns
+            // `None`.
+            jobs.send(chunk).ok();
+        }
+    }
+
+    /// Stop accepting new chunks so that `recv` returns `None` once all chunks have been verified.
+    pub fn close(&mut self) {
+        self.jobs = None;
+    }
+
+    /// Block until the next chunk has been verified and return whether it is intact.
+    ///
+    /// This returns `None` once the pool has been closed and all chunks have been verified.
+    pub fn recv(&self) -> Option<(Chunk, crate::Result<bool>)> {
+        self.results.as_ref()?.recv().ok()
+    }
+}
+
+impl Drop for VerifyPool {
+    fn drop(&mut self) {
+        // Dropping the receiver causes workers to stop once they've finished their current chunk.
+        self.jobs = None;
+        self.results = None;
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+/// Verify chunks submitted to `jobs` and send the results to `results` until either is closed.
+fn run_worker(
+    jobs: &Mutex<Receiver<Chunk>>,
+    results: &SyncSender<(Chunk, crate::Result<bool>)>,
+    state: &RwLock<RepoState>,
+) {
+    // Each worker has its own state so that it can cache the pack it most recently read.
+    let mut store_state = StoreState::new();
+
+    loop {
+        // Release the lock on the queue before reading the chunk so other workers can proceed.
+        let next_job = jobs.lock().recover().recv();
+        let chunk = match next_job {
+            Ok(chunk) => chunk,
+            Err(_) => return,
+        };
+
+        let data = {
+            let state = state.read().recover();
+            StoreReader::new(&state, &mut store_state).read_chunk(chunk)
+        };
+
+        if results.send((chunk, chunk_is_intact(chunk, data))).is_err() {
+            return;
+        }
+    }
+}
@@ -17,15 +17,18 @@
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::io::{Read, Write};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use hex_literal::hex;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::repo::{
+    common::RecoverPoison,
     key::KeyRepo,
-    state::{ObjectKey, StateRepo},
-    Commit, OpenRepo, ReadOnlyObject, RepoInfo, RestoreSavepoint, Savepoint,
+    state::{ObjectKey, StateRepo, StateRepoInner},
+    AuditEntry, CancellationToken, CleanReport, Commit, OpenRepo, Progress, ReadOnlyObject,
+    RepairReport, RepoInfo, RepoStats, RestoreSavepoint, Savepoint, TransactionEvent,
 };
 
 use super::hash::{HashAlgorithm, BUFFER_SIZE, DEFAULT_ALGORITHM};
@@ -57,7 +60,14 @@ impl Default for RepoState {
 ///
 /// See [`crate::repo::content`] for more information.
 #[derive(Debug)]
-pub struct ContentRepo(StateRepo<RepoState>);
+pub struct ContentRepo(RwLock<ContentRepoInner>);
+
+/// The contents of a [`ContentRepo`].
+///
+/// Each public method of `ContentRepo` locks the repository and calls the method of the same name
+/// on this type.
+#[derive(Debug)]
+pub(crate) struct ContentRepoInner(StateRepoInner<RepoState>);
 
 impl OpenRepo for ContentRepo {
     type Key = <StateRepo<RepoState> as OpenRepo>::Key;
@@ -68,25 +78,47 @@ impl OpenRepo for ContentRepo {
     where
         Self: Sized,
     {
-        Ok(ContentRepo(StateRepo::open_repo(repo)?))
+        let inner = StateRepoInner::open_repo(repo.into_inner())?;
+        Ok(Self::from_inner(ContentRepoInner(inner)))
     }
 
     fn create_repo(repo: KeyRepo<Self::Key>) -> crate::Result<Self>
     where
         Self: Sized,
     {
-        Ok(ContentRepo(StateRepo::create_repo(repo)?))
+        let inner = StateRepoInner::create_repo(repo.into_inner())?;
+        Ok(Self::from_inner(ContentRepoInner(inner)))
     }
 
     fn into_repo(self) -> crate::Result<KeyRepo<Self::Key>> {
-        self.0.into_repo()
+        Ok(KeyRepo::from_inner(self.into_inner().0.into_repo()?))
     }
 }
 
 impl ContentRepo {
+    /// Wrap the given `inner` contents of a repository in a new `ContentRepo`.
+    pub(crate) fn from_inner(inner: ContentRepoInner) -> Self {
+        ContentRepo(RwLock::new(inner))
+    }
+
+    /// Consume this repository and return its contents.
+    pub(crate) fn into_inner(self) -> ContentRepoInner {
+        self.0.into_inner().recover()
+    }
+
+    /// Lock the contents of this repository for reading.
+    pub(crate) fn inner(&self) -> RwLockReadGuard<'_, ContentRepoInner> {
+        self.0.read().recover()
+    }
+
+    /// Lock the contents of this repository for writing.
+    pub(crate) fn inner_mut(&self) -> RwLockWriteGuard<'_, ContentRepoInner> {
+        self.0.write().recover()
+    }
+
     /// Return whether the repository contains an object with the given `hash`.
     pub fn contains(&self, hash: &[u8]) -> bool {
-        self.0.state().table.contains_key(hash)
+        self.inner().contains(hash)
     }
 
     /// Add the given `data` to the repository as a new object and return its hash.
@@ -95,7 +127,270 @@ impl ContentRepo {
     /// - `Error::InvalidData`: Ciphertext verification failed.
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
-    pub fn put(&mut self, mut data: impl Read) -> crate::Result<Vec<u8>> {
+    pub fn put(&self, data: impl Read) -> crate::Result<Vec<u8>> {
+        self.inner_mut().put(data)
+    }
+
+    /// Remove the object with the given `hash` from the repository.
+    ///
+    /// This returns `true` if the object was removed or `false` if it didn't exist.
+    ///
+    /// The space used by the given object isn't reclaimed in the backing data store until changes
+    /// are committed and [`Commit::clean`] is called.
+    ///
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    pub fn remove(&self, hash: &[u8]) -> bool {
+        self.inner_mut().remove(hash)
+    }
+
+    /// Return a `ReadOnlyObject` for reading the data with the given `hash`.
+    ///
+    /// This returns `None` if there is no data with the given `hash` in the repository.
+    pub fn object(&self, hash: &[u8]) -> Option<ReadOnlyObject> {
+        self.inner().object(hash)
+    }
+
+    /// Return an iterator of hashes of all the objects in this repository.
+    ///
+    /// Like [`KeyRepo::keys`], this returns a snapshot which doesn't keep the repository locked.
+    ///
+    /// [`KeyRepo::keys`]: crate::repo::key::KeyRepo::keys
+    pub fn list(&self) -> impl Iterator<Item = Vec<u8>> {
+        self.inner()
+            .list()
+            .map(<[u8]>::to_vec)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Return the hash algorithm used by this repository.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.inner().algorithm()
+    }
+
+    /// Change the hash algorithm used by this repository.
+    ///
+    /// This re-computes the hashes of all the objects in the repository. If the given hash
+    /// algorithm is the same as the current hash algorithm, this does nothing.
+    ///
+    /// # Errors
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn change_algorithm(&self, new_algorithm: HashAlgorithm) -> crate::Result<()> {
+        self.inner_mut().change_algorithm(new_algorithm)
+    }
+
+    /// Verify the integrity of all the data in the repository.
+    ///
+    /// This returns the set of hashes of objects which are corrupt.
+    ///
+    /// If you just need to verify the integrity of one object, [`Object::verify`] is faster. If you
+    /// need to verify the integrity of all the data in the repository, however, this can be more
+    /// efficient.
+    ///
+    /// # Errors
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`Object::verify`]: crate::repo::Object::verify
+    pub fn verify(&self) -> crate::Result<HashSet<Vec<u8>>> {
+        Ok(self
+            .inner()
+            .verify()?
+            .into_iter()
+            .map(<[u8]>::to_vec)
+            .collect())
+    }
+
+    /// Return the hashes of data which has been added or removed since the last commit.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize data from the previous commit.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn changed_hashes(&self) -> crate::Result<HashSet<Vec<u8>>> {
+        self.inner().changed_hashes()
+    }
+
+    /// Return whether the repository has changed since the last commit.
+    ///
+    /// See [`changed_hashes`] for details.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize data from the previous commit.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`changed_hashes`]: crate::repo::content::ContentRepo::changed_hashes
+    pub fn has_changes(&self) -> crate::Result<bool> {
+        self.inner().has_changes()
+    }
+
+    /// Delete all data in the current instance of the repository.
+    ///
+    /// See [`KeyRepo::clear_instance`] for details.
+    ///
+    /// [`KeyRepo::clear_instance`]: crate::repo::key::KeyRepo::clear_instance
+    pub fn clear_instance(&self) {
+        self.inner_mut().clear_instance()
+    }
+
+    /// Change the password for this repository.
+    ///
+    /// See [`KeyRepo::change_password`] for details.
+    ///
+    /// [`KeyRepo::change_password`]: crate::repo::key::KeyRepo::change_password
+    pub fn change_password(&self, new_password: &[u8]) {
+        self.inner_mut().change_password(new_password)
+    }
+
+    /// Renew the lease on this repository.
+    ///
+    /// See [`KeyRepo::renew_lease`] for details.
+    ///
+    /// [`KeyRepo::renew_lease`]: crate::repo::key::KeyRepo::renew_lease
+    pub fn renew_lease(&self) -> crate::Result<()> {
+        self.inner().renew_lease()
+    }
+
+    /// Register a `hook` to run before the given `event`.
+    ///
+    /// See [`KeyRepo::add_before_hook`] for details.
+    ///
+    /// [`KeyRepo::add_before_hook`]: crate::repo::key::KeyRepo::add_before_hook
+    pub fn add_before_hook(
+        &self,
+        event: TransactionEvent,
+        hook: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.inner_mut().add_before_hook(event, hook)
+    }
+
+    /// Register a `hook` to run after the given `event` completes successfully.
+    ///
+    /// See [`KeyRepo::add_after_hook`] for details.
+    ///
+    /// [`KeyRepo::add_after_hook`]: crate::repo::key::KeyRepo::add_after_hook
+    pub fn add_after_hook(&self, event: TransactionEvent, hook: impl FnMut() + Send + 'static) {
+        self.inner_mut().add_after_hook(event, hook)
+    }
+
+    /// Set a `handler` which is called with the progress of long-running operations.
+    ///
+    /// See [`KeyRepo::set_progress_handler`] for details.
+    ///
+    /// [`KeyRepo::set_progress_handler`]: crate::repo::key::KeyRepo::set_progress_handler
+    pub fn set_progress_handler(&self, handler: impl Fn(Progress) + Send + Sync + 'static) {
+        self.inner_mut().set_progress_handler(handler)
+    }
+
+    /// Set a `token` which can be used to cancel long-running operations.
+    ///
+    /// See [`KeyRepo::set_cancellation_token`] for details.
+    ///
+    /// [`KeyRepo::set_cancellation_token`]: crate::repo::key::KeyRepo::set_cancellation_token
+    pub fn set_cancellation_token(&self, token: CancellationToken) {
+        self.inner_mut().set_cancellation_token(token)
+    }
+
+    /// Return this repository's instance ID.
+    pub fn instance(&self) -> Uuid {
+        self.inner().instance()
+    }
+
+    /// Return the name of the current branch.
+    ///
+    /// See [`KeyRepo::branch`] for details.
+    ///
+    /// [`KeyRepo::branch`]: crate::repo::key::KeyRepo::branch
+    pub fn branch(&self) -> String {
+        self.inner().branch().to_string()
+    }
+
+    /// Return an iterator over the names of all the branches of the current instance.
+    ///
+    /// See [`KeyRepo::branches`] for details.
+    ///
+    /// [`KeyRepo::branches`]: crate::repo::key::KeyRepo::branches
+    pub fn branches(&self) -> impl Iterator<Item = String> {
+        self.inner()
+            .branches()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Remove the branch named `name` and all of its contents.
+    ///
+    /// See [`KeyRepo::remove_branch`] for details.
+    ///
+    /// [`KeyRepo::remove_branch`]: crate::repo::key::KeyRepo::remove_branch
+    pub fn remove_branch(&self, name: &str) -> crate::Result<()> {
+        self.inner_mut().remove_branch(name)
+    }
+
+    /// Return information about the repository.
+    pub fn info(&self) -> RepoInfo {
+        self.inner().info()
+    }
+
+    /// Return statistics about the operations performed by the repository since it was opened.
+    ///
+    /// See [`KeyRepo::stats`] for details.
+    ///
+    /// [`KeyRepo::stats`]: crate::repo::key::KeyRepo::stats
+    pub fn stats(&self) -> RepoStats {
+        self.inner().stats()
+    }
+
+    /// Reset the statistics returned by [`stats`] to zero.
+    ///
+    /// [`stats`]: crate::repo::content::ContentRepo::stats
+    pub fn reset_stats(&self) {
+        self.inner().reset_stats()
+    }
+
+    /// Report what cleaning the repository would do without modifying the data store.
+    ///
+    /// See [`KeyRepo::clean_dry_run`] for details.
+    ///
+    /// [`KeyRepo::clean_dry_run`]: crate::repo::key::KeyRepo::clean_dry_run
+    pub fn clean_dry_run(&self) -> crate::Result<CleanReport> {
+        self.inner().clean_dry_run()
+    }
+
+    /// Reconstruct blocks in the data store which are missing or corrupt using parity blocks.
+    ///
+    /// See [`KeyRepo::repair`] for details.
+    ///
+    /// [`KeyRepo::repair`]: crate::repo::key::KeyRepo::repair
+    pub fn repair(&self) -> crate::Result<RepairReport> {
+        self.inner_mut().repair()
+    }
+
+    /// Return the entries in the audit log in the order they were recorded.
+    ///
+    /// See [`KeyRepo::audit_log`] for details.
+    ///
+    /// [`KeyRepo::audit_log`]: crate::repo::key::KeyRepo::audit_log
+    pub fn audit_log(&self) -> crate::Result<Vec<AuditEntry>> {
+        self.inner().audit_log()
+    }
+}
+
+impl ContentRepoInner {
+    pub(crate) fn contains(&self, hash: &[u8]) -> bool {
+        self.0.state().table.contains_key(hash)
+    }
+
+    pub(crate) fn put(&mut self, mut data: impl Read) -> crate::Result<Vec<u8>> {
         let mut buffer = [0u8; BUFFER_SIZE];
         let mut digest = self.0.state().algorithm.digest();
         let mut bytes_read;
@@ -135,15 +430,7 @@ impl ContentRepo {
         Ok(hash)
     }
 
-    /// Remove the object with the given `hash` from the repository.
-    ///
-    /// This returns `true` if the object was removed or `false` if it didn't exist.
-    ///
-    /// The space used by the given object isn't reclaimed in the backing data store until changes
-    /// are committed and [`Commit::clean`] is called.
-    ///
-    /// [`Commit::clean`]: crate::repo::Commit::clean
-    pub fn remove(&mut self, hash: &[u8]) -> bool {
+    pub(crate) fn remove(&mut self, hash: &[u8]) -> bool {
         let object_id = match self.0.state_mut().table.remove(hash) {
             Some(object_id) => object_id,
             None => return false,
@@ -152,34 +439,20 @@ impl ContentRepo {
         true
     }
 
-    /// Return a `ReadOnlyObject` for reading the data with the given `hash`.
-    ///
-    /// This returns `None` if there is no data with the given `hash` in the repository.
-    pub fn object(&self, hash: &[u8]) -> Option<ReadOnlyObject> {
+    pub(crate) fn object(&self, hash: &[u8]) -> Option<ReadOnlyObject> {
         let object_id = *self.0.state().table.get(hash)?;
         Some(self.0.object(object_id).unwrap().try_into().unwrap())
     }
 
-    /// Return an iterator of hashes of all the objects in this repository.
-    pub fn list(&self) -> impl Iterator<Item = &[u8]> {
+    pub(crate) fn list(&self) -> impl Iterator<Item = &[u8]> {
         self.0.state().table.keys().map(|hash| hash.as_slice())
     }
 
-    /// Return the hash algorithm used by this repository.
-    pub fn algorithm(&self) -> HashAlgorithm {
+    pub(crate) fn algorithm(&self) -> HashAlgorithm {
         self.0.state().algorithm
     }
 
-    /// Change the hash algorithm used by this repository.
-    ///
-    /// This re-computes the hashes of all the objects in the repository. If the given hash
-    /// algorithm is the same as the current hash algorithm, this does nothing.
-    ///
-    /// # Errors
-    /// - `Error::InvalidData`: Ciphertext verification failed.
-    /// - `Error::Store`: An error occurred with the data store.
-    /// - `Error::Io`: An I/O error occurred.
-    pub fn change_algorithm(&mut self, new_algorithm: HashAlgorithm) -> crate::Result<()> {
+    pub(crate) fn change_algorithm(&mut self, new_algorithm: HashAlgorithm) -> crate::Result<()> {
         if new_algorithm == self.0.state().algorithm {
             return Ok(());
         }
@@ -199,21 +472,7 @@ impl ContentRepo {
         Ok(())
     }
 
-    /// Verify the integrity of all the data in the repository.
-    ///
-    /// This returns the set of hashes of objects which are corrupt.
-    ///
-    /// If you just need to verify the integrity of one object, [`Object::verify`] is faster. If you
-    /// need to verify the integrity of all the data in the repository, however, this can be more
-    /// efficient.
-    ///
-    /// # Errors
-    /// - `Error::InvalidData`: Ciphertext verification failed.
-    /// - `Error::Store`: An error occurred with the data store.
-    /// - `Error::Io`: An I/O error occurred.
-    ///
-    /// [`Object::verify`]: crate::repo::Object::verify
-    pub fn verify(&self) -> crate::Result<HashSet<&[u8]>> {
+    pub(crate) fn verify(&self) -> crate::Result<HashSet<&[u8]>> {
         let corrupt_keys = self.0.verify()?;
         Ok(self
             .0
@@ -225,61 +484,176 @@ impl ContentRepo {
             .collect())
     }
 
-    /// Delete all data in the current instance of the repository.
-    ///
-    /// See [`KeyRepo::clear_instance`] for details.
-    ///
-    /// [`KeyRepo::clear_instance`]: crate::repo::key::KeyRepo::clear_instance
-    pub fn clear_instance(&mut self) {
+    pub(crate) fn changed_hashes(&self) -> crate::Result<HashSet<Vec<u8>>> {
+        let committed_table = self.0.committed_state()?.table;
+        let current_table = &self.0.state().table;
+
+        let mut changed_hashes = current_table
+            .keys()
+            .filter(|hash| !committed_table.contains_key(*hash))
+            .cloned()
+            .collect::<HashSet<_>>();
+        changed_hashes.extend(
+            committed_table
+                .into_keys()
+                .filter(|hash| !current_table.contains_key(hash)),
+        );
+
+        Ok(changed_hashes)
+    }
+
+    pub(crate) fn has_changes(&self) -> crate::Result<bool> {
+        Ok(!self.changed_hashes()?.is_empty())
+    }
+
+    pub(crate) fn clear_instance(&mut self) {
         self.0.clear_instance()
     }
 
-    /// Change the password for this repository.
-    ///
-    /// See [`KeyRepo::change_password`] for details.
-    ///
-    /// [`KeyRepo::change_password`]: crate::repo::key::KeyRepo::change_password
-    pub fn change_password(&mut self, new_password: &[u8]) {
+    pub(crate) fn change_password(&mut self, new_password: &[u8]) {
         self.0.change_password(new_password)
     }
 
-    /// Return this repository's instance ID.
-    pub fn instance(&self) -> Uuid {
+    pub(crate) fn renew_lease(&self) -> crate::Result<()> {
+        self.0.renew_lease()
+    }
+
+    pub(crate) fn add_before_hook(
+        &mut self,
+        event: TransactionEvent,
+        hook: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.0.add_before_hook(event, hook)
+    }
+
+    pub(crate) fn add_after_hook(
+        &mut self,
+        event: TransactionEvent,
+        hook: impl FnMut() + Send + 'static,
+    ) {
+        self.0.add_after_hook(event, hook)
+    }
+
+    pub(crate) fn set_progress_handler(
+        &mut self,
+        handler: impl Fn(Progress) + Send + Sync + 'static,
+    ) {
+        self.0.set_progress_handler(handler)
+    }
+
+    pub(crate) fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.0.set_cancellation_token(token)
+    }
+
+    pub(crate) fn instance(&self) -> Uuid {
         self.0.instance()
     }
 
-    /// Return information about the repository.
-    pub fn info(&self) -> RepoInfo {
+    pub(crate) fn branch(&self) -> &str {
+        self.0.branch()
+    }
+
+    pub(crate) fn branches(&self) -> impl Iterator<Item = &str> {
+        self.0.branches()
+    }
+
+    pub(crate) fn remove_branch(&mut self, name: &str) -> crate::Result<()> {
+        self.0.remove_branch(name)
+    }
+
+    pub(crate) fn info(&self) -> RepoInfo {
         self.0.info()
     }
+
+    pub(crate) fn stats(&self) -> RepoStats {
+        self.0.stats()
+    }
+
+    pub(crate) fn reset_stats(&self) {
+        self.0.reset_stats()
+    }
+
+    pub(crate) fn clean_dry_run(&self) -> crate::Result<CleanReport> {
+        self.0.clean_dry_run()
+    }
+
+    pub(crate) fn repair(&mut self) -> crate::Result<RepairReport> {
+        self.0.repair()
+    }
+
+    pub(crate) fn audit_log(&self) -> crate::Result<Vec<AuditEntry>> {
+        self.0.audit_log()
+    }
 }
 
-impl Commit for ContentRepo {
-    fn commit(&mut self) -> crate::Result<()> {
+impl ContentRepoInner {
+    pub(crate) fn commit(&mut self) -> crate::Result<()> {
         self.0.commit()
     }
 
-    fn rollback(&mut self) -> crate::Result<()> {
+    pub(crate) fn rollback(&mut self) -> crate::Result<()> {
         self.0.rollback()
     }
 
-    fn clean(&mut self) -> crate::Result<()> {
+    pub(crate) fn refresh(&mut self) -> crate::Result<()> {
+        self.0.refresh()
+    }
+
+    pub(crate) fn clean(&mut self) -> crate::Result<()> {
         self.0.clean()
     }
 }
 
-impl RestoreSavepoint for ContentRepo {
-    type Restore = <StateRepo<RepoState> as RestoreSavepoint>::Restore;
+impl Commit for ContentRepo {
+    fn commit(&self) -> crate::Result<()> {
+        self.inner_mut().commit()
+    }
 
-    fn savepoint(&mut self) -> crate::Result<Savepoint> {
+    fn rollback(&self) -> crate::Result<()> {
+        self.inner_mut().rollback()
+    }
+
+    fn refresh(&self) -> crate::Result<()> {
+        self.inner_mut().refresh()
+    }
+
+    fn clean(&self) -> crate::Result<()> {
+        self.inner_mut().clean()
+    }
+}
+
+impl ContentRepoInner {
+    pub(crate) fn savepoint(&mut self) -> crate::Result<Savepoint> {
         self.0.savepoint()
     }
 
-    fn start_restore(&mut self, savepoint: &Savepoint) -> crate::Result<Self::Restore> {
+    pub(crate) fn start_restore(
+        &mut self,
+        savepoint: &Savepoint,
+    ) -> crate::Result<<StateRepo<RepoState> as RestoreSavepoint>::Restore> {
         self.0.start_restore(savepoint)
     }
 
-    fn finish_restore(&mut self, restore: Self::Restore) -> bool {
+    pub(crate) fn finish_restore(
+        &mut self,
+        restore: <StateRepo<RepoState> as RestoreSavepoint>::Restore,
+    ) -> bool {
         self.0.finish_restore(restore)
     }
 }
+
+impl RestoreSavepoint for ContentRepo {
+    type Restore = <StateRepo<RepoState> as RestoreSavepoint>::Restore;
+
+    fn savepoint(&self) -> crate::Result<Savepoint> {
+        self.inner_mut().savepoint()
+    }
+
+    fn start_restore(&self, savepoint: &Savepoint) -> crate::Result<Self::Restore> {
+        self.inner_mut().start_restore(savepoint)
+    }
+
+    fn finish_restore(&self, restore: Self::Restore) -> bool {
+        self.inner_mut().finish_restore(restore)
+    }
+}
@@ -0,0 +1,218 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(all(
+    target_os = "linux",
+    feature = "file-metadata",
+    any(test, feature = "fuse-mount")
+))]
+
+use std::convert::TryFrom;
+
+use nix::libc;
+
+/// The set of `fallocate` modes which are supported.
+const SUPPORTED_MODES: i32 =
+    libc::FALLOC_FL_KEEP_SIZE | libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_ZERO_RANGE;
+
+/// A validated `fallocate` request for a range of a file.
+///
+/// This is separate from the FUSE file system so that it can be tested without mounting one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocateRequest {
+    /// The offset of the start of the range.
+    offset: u64,
+
+    /// The length of the range, which is never zero.
+    ///
+    /// The offset and length both come from an `i64`, so their sum can't overflow.
+    length: u64,
+
+    /// Whether the size of the file must stay the same.
+    keep_size: bool,
+
+    /// Whether the data in the range is replaced with a hole.
+    clears_data: bool,
+}
+
+impl AllocateRequest {
+    /// Validate an `fallocate` request with the given `offset`, `length`, and `mode`.
+    ///
+    /// This returns the `errno` value to reply with if the request is invalid or unsupported.
+    pub fn new(offset: i64, length: i64, mode: i32) -> Result<Self, libc::c_int> {
+        let offset = u64::try_from(offset).map_err(|_| libc::EINVAL)?;
+        let length = u64::try_from(length)
+            .ok()
+            .filter(|length| *length > 0)
+            .ok_or(libc::EINVAL)?;
+
+        if mode & !SUPPORTED_MODES != 0 {
+            return Err(libc::EOPNOTSUPP);
+        }
+
+        let keep_size = mode & libc::FALLOC_FL_KEEP_SIZE != 0;
+        let punch_hole = mode & libc::FALLOC_FL_PUNCH_HOLE != 0;
+        let zero_range = mode & libc::FALLOC_FL_ZERO_RANGE != 0;
+
+        // Punching a hole must never change the size of the file.
+        if punch_hole && (!keep_size || zero_range) {
+            return Err(libc::EINVAL);
+        }
+
+        Ok(AllocateRequest {
+            offset,
+            length,
+            keep_size,
+            clears_data: punch_hole || zero_range,
+        })
+    }
+
+    /// Return how this request changes a file which is currently `old_size` bytes.
+    ///
+    /// Objects are sparse, so allocating space within the file is a no-op. We only need to extend
+    /// the file, zero out the range, or both.
+    pub fn plan(&self, old_size: u64) -> Allocation {
+        let new_size = if self.keep_size {
+            old_size
+        } else {
+            old_size.max(self.offset + self.length)
+        };
+        Allocation {
+            hole: if self.clears_data {
+                Some((self.offset, self.length))
+            } else {
+                None
+            },
+            old_size,
+            new_size,
+        }
+    }
+}
+
+/// The changes an `fallocate` request makes to a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allocation {
+    /// The offset and length of the range to replace with a hole, if any.
+    pub hole: Option<(u64, u64)>,
+
+    /// The size of the file before the request.
+    pub old_size: u64,
+
+    /// The size of the file after the request.
+    pub new_size: u64,
+}
+
+impl Allocation {
+    /// Return whether the request leaves the file unchanged.
+    pub fn is_noop(&self) -> bool {
+        self.hole.is_none() && self.new_size == self.old_size
+    }
+
+    /// Return the number of bytes by which the file grows, which counts against its quota.
+    pub fn growth(&self) -> u64 {
+        self.new_size.saturating_sub(self.old_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::libc;
+
+    use super::{AllocateRequest, Allocation};
+
+    #[test]
+    fn allocating_past_end_extends_file() {
+        let allocation = AllocateRequest::new(100, 50, 0).unwrap().plan(120);
+        assert_eq!(
+            allocation,
+            Allocation {
+                hole: None,
+                old_size: 120,
+                new_size: 150,
+            }
+        );
+        assert!(!allocation.is_noop());
+        assert_eq!(allocation.growth(), 30);
+    }
+
+    #[test]
+    fn allocating_within_file_is_noop() {
+        let allocation = AllocateRequest::new(10, 50, 0).unwrap().plan(120);
+        assert!(allocation.is_noop());
+        assert_eq!(allocation.growth(), 0);
+    }
+
+    #[test]
+    fn keep_size_does_not_extend_file() {
+        let allocation = AllocateRequest::new(100, 50, libc::FALLOC_FL_KEEP_SIZE)
+            .unwrap()
+            .plan(120);
+        assert!(allocation.is_noop());
+        assert_eq!(allocation.new_size, 120);
+    }
+
+    #[test]
+    fn punching_hole_keeps_size() {
+        let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+        let allocation = AllocateRequest::new(100, 50, mode).unwrap().plan(120);
+        assert_eq!(
+            allocation,
+            Allocation {
+                hole: Some((100, 50)),
+                old_size: 120,
+                new_size: 120,
+            }
+        );
+        assert!(!allocation.is_noop());
+        assert_eq!(allocation.growth(), 0);
+    }
+
+    #[test]
+    fn zeroing_range_past_end_extends_file() {
+        let allocation = AllocateRequest::new(100, 50, libc::FALLOC_FL_ZERO_RANGE)
+            .unwrap()
+            .plan(120);
+        assert_eq!(allocation.hole, Some((100, 50)));
+        assert_eq!(allocation.new_size, 150);
+        assert_eq!(allocation.growth(), 30);
+    }
+
+    #[test]
+    fn punching_hole_without_keep_size_errs() {
+        assert_eq!(
+            AllocateRequest::new(0, 10, libc::FALLOC_FL_PUNCH_HOLE),
+            Err(libc::EINVAL)
+        );
+        let mode =
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE | libc::FALLOC_FL_ZERO_RANGE;
+        assert_eq!(AllocateRequest::new(0, 10, mode), Err(libc::EINVAL));
+    }
+
+    #[test]
+    fn unsupported_modes_err() {
+        assert_eq!(
+            AllocateRequest::new(0, 10, libc::FALLOC_FL_COLLAPSE_RANGE),
+            Err(libc::EOPNOTSUPP)
+        );
+    }
+
+    #[test]
+    fn invalid_ranges_err() {
+        assert_eq!(AllocateRequest::new(-1, 10, 0), Err(libc::EINVAL));
+        assert_eq!(AllocateRequest::new(0, 0, 0), Err(libc::EINVAL));
+        assert_eq!(AllocateRequest::new(0, -10, 0), Err(libc::EINVAL));
+    }
+}
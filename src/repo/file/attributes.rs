@@ -0,0 +1,223 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+
+use relative_path::RelativePath;
+use rmp_serde::{from_read, to_vec};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::entry::EntryHandle;
+use super::metadata::FileMetadata;
+use super::repository::{FileRepo, FileRepoInner, EMPTY_PATH};
+use super::special::SpecialType;
+
+/// The application-defined attributes of an entry, mapping names to serialized values.
+type Attributes = HashMap<String, Vec<u8>>;
+
+impl<S, M> FileRepo<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    /// Return the value of the attribute `name` for the entry at `path`.
+    ///
+    /// Attributes are application-defined values which can be attached to any entry, separately
+    /// from its [`FileMetadata`]. They are never read from or written to the file system, and they
+    /// are preserved when an entry is copied or renamed.
+    ///
+    /// This returns `None` if the entry does not have an attribute named `name`.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry at `path`.
+    /// - `Error::Deserialize`: The attribute could not be deserialized as a `T`.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`FileMetadata`]: crate::repo::file::FileMetadata
+    pub fn attribute<T: DeserializeOwned>(
+        &self,
+        path: impl AsRef<RelativePath>,
+        name: &str,
+    ) -> crate::Result<Option<T>> {
+        self.inner().attribute(path, name)
+    }
+
+    /// Set the attribute `name` for the entry at `path` to `value`.
+    ///
+    /// This replaces the existing value of the attribute if there is one.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry at `path`.
+    /// - `Error::Serialize`: The attribute could not be serialized.
+    /// - `Error::Deserialize`: The existing attributes could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn set_attribute<T: Serialize + ?Sized>(
+        &self,
+        path: impl AsRef<RelativePath>,
+        name: &str,
+        value: &T,
+    ) -> crate::Result<()> {
+        self.inner_mut().set_attribute(path, name, value)
+    }
+
+    /// Remove the attribute `name` from the entry at `path`.
+    ///
+    /// This returns `true` if the attribute was removed or `false` if the entry did not have it.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry at `path`.
+    /// - `Error::Serialize`: The remaining attributes could not be serialized.
+    /// - `Error::Deserialize`: The existing attributes could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn remove_attribute(
+        &self,
+        path: impl AsRef<RelativePath>,
+        name: &str,
+    ) -> crate::Result<bool> {
+        self.inner_mut().remove_attribute(path, name)
+    }
+
+    /// Return the names of the attributes of the entry at `path`.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry at `path`.
+    /// - `Error::Deserialize`: The attributes could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn attribute_names(&self, path: impl AsRef<RelativePath>) -> crate::Result<Vec<String>> {
+        self.inner().attribute_names(path)
+    }
+}
+
+impl<S, M> FileRepoInner<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    pub(crate) fn attribute<T: DeserializeOwned>(
+        &self,
+        path: impl AsRef<RelativePath>,
+        name: &str,
+    ) -> crate::Result<Option<T>> {
+        let handle = self.attribute_handle(path.as_ref())?;
+        match self.read_attributes(&handle)?.get(name) {
+            Some(value) => Ok(Some(
+                from_read(value.as_slice()).map_err(|_| crate::Error::Deserialize)?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn set_attribute<T: Serialize + ?Sized>(
+        &mut self,
+        path: impl AsRef<RelativePath>,
+        name: &str,
+        value: &T,
+    ) -> crate::Result<()> {
+        let path = path.as_ref();
+        let handle = self.attribute_handle(path)?;
+        let serialized = to_vec(value).map_err(|_| crate::Error::Serialize)?;
+        let mut attributes = self.read_attributes(&handle)?;
+        attributes.insert(name.to_owned(), serialized);
+        self.write_attributes(path, handle, &attributes)
+    }
+
+    pub(crate) fn remove_attribute(
+        &mut self,
+        path: impl AsRef<RelativePath>,
+        name: &str,
+    ) -> crate::Result<bool> {
+        let path = path.as_ref();
+        let handle = self.attribute_handle(path)?;
+        let mut attributes = self.read_attributes(&handle)?;
+        if attributes.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.write_attributes(path, handle, &attributes)?;
+        Ok(true)
+    }
+
+    pub(crate) fn attribute_names(
+        &self,
+        path: impl AsRef<RelativePath>,
+    ) -> crate::Result<Vec<String>> {
+        let handle = self.attribute_handle(path.as_ref())?;
+        Ok(self.read_attributes(&handle)?.keys().cloned().collect())
+    }
+
+    /// Return the handle of the entry at `path` for accessing its attributes.
+    fn attribute_handle(&self, path: &RelativePath) -> crate::Result<EntryHandle> {
+        if path == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        self.0
+            .state()
+            .get(path)
+            .copied()
+            .ok_or(crate::Error::NotFound)
+    }
+
+    /// Read the attributes of the entry with the given `handle`.
+    fn read_attributes(&self, handle: &EntryHandle) -> crate::Result<Attributes> {
+        match handle.attributes {
+            Some(object_id) => self.0.object(object_id).unwrap().deserialize(),
+            None => Ok(Attributes::new()),
+        }
+    }
+
+    /// Replace the attributes of the entry at `path` which has the given `handle`.
+    ///
+    /// The object which stores the attributes is only created once the entry has attributes, and
+    /// it is removed once it has none.
+    fn write_attributes(
+        &mut self,
+        path: &RelativePath,
+        handle: EntryHandle,
+        attributes: &Attributes,
+    ) -> crate::Result<()> {
+        if attributes.is_empty() {
+            if let Some(object_id) = handle.attributes {
+                self.0.remove(object_id);
+                self.0.state_mut().get_mut(path).unwrap().attributes = None;
+            }
+            return Ok(());
+        }
+
+        let object_id = match handle.attributes {
+            Some(object_id) => object_id,
+            None => {
+                let object_id = self.0.create();
+                self.0.state_mut().get_mut(path).unwrap().attributes = Some(object_id);
+                object_id
+            }
+        };
+
+        self.0.object(object_id).unwrap().serialize(attributes)
+    }
+}
@@ -0,0 +1,696 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(all(windows, feature = "dokan-mount"))]
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dokan::{
+    CreateFileInfo, DiskSpaceInfo, Drive, FileInfo, FileSystemHandler, FillDataError, FindData,
+    OperationError, OperationInfo, VolumeInfo,
+};
+use dokan_sys::DOKAN_IO_SECURITY_CONTEXT;
+use relative_path::{RelativePath, RelativePathBuf};
+use widestring::{U16CStr, U16CString};
+use winapi::shared::ntdef::NTSTATUS;
+use winapi::shared::ntstatus::{
+    STATUS_ACCESS_DENIED, STATUS_BUFFER_OVERFLOW, STATUS_CANNOT_DELETE, STATUS_DIRECTORY_NOT_EMPTY,
+    STATUS_DISK_FULL, STATUS_FILE_IS_A_DIRECTORY, STATUS_INTERNAL_ERROR,
+    STATUS_INVALID_DEVICE_REQUEST, STATUS_INVALID_PARAMETER, STATUS_NOT_A_DIRECTORY,
+    STATUS_OBJECT_NAME_COLLISION, STATUS_OBJECT_NAME_INVALID, STATUS_OBJECT_NAME_NOT_FOUND,
+    STATUS_QUOTA_EXCEEDED,
+};
+use winapi::um::winnt::{
+    FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_NORMAL, FILE_CASE_PRESERVED_NAMES,
+    FILE_CASE_SENSITIVE_SEARCH, FILE_UNICODE_ON_DISK,
+};
+
+use super::entry::Entry;
+use super::metadata::FileMetadata;
+use super::repository::{FileRepo, EMPTY_PATH};
+use super::special::SpecialType;
+use crate::repo::common::RecoverPoison;
+use crate::repo::Commit;
+
+/// The number of bytes to report as free space.
+///
+/// Repositories don't have a fixed capacity, so we report an arbitrarily large amount of free
+/// space, like the FUSE adapter does.
+const FREE_BYTES: u64 = 1 << 50;
+
+/// The maximum length of a file name in UTF-16 code units.
+const MAX_COMPONENT_LENGTH: u32 = 255;
+
+/// The name of the volume and file system reported to Windows.
+const VOLUME_NAME: &str = "acid-store";
+
+// The create dispositions and options passed to `create_file`, which are not exported by `winapi`
+// or `dokan-sys`.
+const FILE_SUPERSEDE: u32 = 0;
+const FILE_OPEN: u32 = 1;
+const FILE_CREATE: u32 = 2;
+const FILE_OPEN_IF: u32 = 3;
+const FILE_OVERWRITE: u32 = 4;
+const FILE_OVERWRITE_IF: u32 = 5;
+const FILE_DIRECTORY_FILE: u32 = 0x0000_0001;
+const FILE_NON_DIRECTORY_FILE: u32 = 0x0000_0040;
+
+/// The result of a Dokan operation.
+type OperationResult<T> = Result<T, OperationError>;
+
+/// Return an operation error with the given `status`.
+fn status<T>(status: NTSTATUS) -> OperationResult<T> {
+    Err(OperationError::NtStatus(status))
+}
+
+impl crate::Error {
+    /// Get the `NTSTATUS` code which corresponds to this error.
+    fn to_ntstatus(&self) -> NTSTATUS {
+        match self {
+            crate::Error::AlreadyExists => STATUS_OBJECT_NAME_COLLISION,
+            crate::Error::NotFound => STATUS_OBJECT_NAME_NOT_FOUND,
+            crate::Error::InvalidPath => STATUS_OBJECT_NAME_INVALID,
+            crate::Error::NotEmpty => STATUS_DIRECTORY_NOT_EMPTY,
+            crate::Error::NotDirectory => STATUS_NOT_A_DIRECTORY,
+            crate::Error::NotFile => STATUS_FILE_IS_A_DIRECTORY,
+            crate::Error::QuotaExceeded => STATUS_QUOTA_EXCEEDED,
+            crate::Error::StoreFull(_) => STATUS_DISK_FULL,
+            crate::Error::PermissionDenied(_) => STATUS_ACCESS_DENIED,
+            _ => STATUS_INTERNAL_ERROR,
+        }
+    }
+}
+
+impl From<crate::Error> for OperationError {
+    fn from(error: crate::Error) -> Self {
+        OperationError::NtStatus(error.to_ntstatus())
+    }
+}
+
+/// Convert an I/O `error` from reading or writing an `Object` to an operation error.
+fn io_error(error: io::Error) -> OperationError {
+    crate::Error::from(error).into()
+}
+
+/// Convert an `offset` passed by Dokan to a `u64`.
+fn to_offset(offset: i64) -> OperationResult<u64> {
+    u64::try_from(offset).or_else(|_| status(STATUS_INVALID_PARAMETER))
+}
+
+/// The state of an open file or directory.
+struct EntryContext {
+    /// The path of the entry in the repository.
+    ///
+    /// This is updated when the entry is moved.
+    path: Mutex<RelativePathBuf>,
+
+    /// Whether the entry is a directory.
+    is_dir: bool,
+
+    /// Whether the file has been modified since changes were last committed.
+    modified: AtomicBool,
+}
+
+impl EntryContext {
+    /// Create a new context for the entry at `path`.
+    fn new(path: RelativePathBuf, is_dir: bool) -> Self {
+        Self {
+            path: Mutex::new(path),
+            is_dir,
+            modified: AtomicBool::new(false),
+        }
+    }
+
+    /// Return the path of the entry.
+    fn path(&self) -> RelativePathBuf {
+        self.path.lock().recover().clone()
+    }
+}
+
+/// An adapter for mounting a `FileRepo` as a file system on Windows using Dokan.
+///
+/// Dokan calls into the adapter from multiple threads, so the repository is kept behind a mutex
+/// and operations are handled one at a time.
+struct DokanAdapter<'a, S: SpecialType, M: FileMetadata> {
+    /// The repository which contains the mounted tree.
+    repo: Mutex<&'a FileRepo<S, M>>,
+
+    /// The path of the directory in the repository which is mounted as the root.
+    root: RelativePathBuf,
+}
+
+impl<'a, S: SpecialType, M: FileMetadata> DokanAdapter<'a, S, M> {
+    /// Lock the repository.
+    fn repo(&self) -> MutexGuard<&'a FileRepo<S, M>> {
+        self.repo.lock().recover()
+    }
+
+    /// Convert a Windows `file_name` relative to the mountpoint to a path in the repository.
+    fn path(&self, file_name: &U16CStr) -> OperationResult<RelativePathBuf> {
+        let file_name = match file_name.to_string() {
+            Ok(file_name) => file_name,
+            Err(_) => return status(STATUS_OBJECT_NAME_INVALID),
+        };
+
+        let mut path = self.root.clone();
+        for component in file_name.split('\\') {
+            match component {
+                "" | "." => (),
+                ".." => return status(STATUS_OBJECT_NAME_INVALID),
+                name => path.push(name),
+            }
+        }
+        Ok(path)
+    }
+
+    /// Return whether the entry at `path` is a directory.
+    ///
+    /// The root of the repository can be mounted, but it doesn't have an entry.
+    fn is_directory(repo: &FileRepo<S, M>, path: &RelativePath) -> bool {
+        path == *EMPTY_PATH || repo.is_directory(path)
+    }
+
+    /// Return the entry at `path`, treating the root of the repository as a directory.
+    fn entry(repo: &FileRepo<S, M>, path: &RelativePath) -> crate::Result<Entry<S, M>> {
+        if path == *EMPTY_PATH {
+            Ok(Entry::directory())
+        } else {
+            repo.entry(path)
+        }
+    }
+
+    /// Return the file attributes and modification time of `entry`.
+    fn attributes(entry: &Entry<S, M>) -> (u32, SystemTime) {
+        let attributes = if entry.is_directory() {
+            FILE_ATTRIBUTE_DIRECTORY
+        } else {
+            FILE_ATTRIBUTE_NORMAL
+        };
+        let modified = entry
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(UNIX_EPOCH);
+        (attributes, modified)
+    }
+
+    /// Set the size of the file at `path` to `len` bytes.
+    ///
+    /// This does not commit changes to the repository.
+    fn set_len(repo: &FileRepo<S, M>, path: &RelativePath, len: u64) -> crate::Result<()> {
+        let old_size = repo.file_size(path)?;
+        repo.check_quota(path, len.saturating_sub(old_size))?;
+
+        let mut object = repo.open(path)?;
+        object.set_len(len)?;
+        object.commit()?;
+        drop(object);
+
+        repo.inner_mut().record_size(path, len);
+        Ok(())
+    }
+}
+
+impl<'a, 'b: 'a, 'c, S, M> FileSystemHandler<'a, 'b> for DokanAdapter<'c, S, M>
+where
+    'c: 'b,
+    S: SpecialType + 'b,
+    M: FileMetadata + 'b,
+    FileRepo<S, M>: Sync,
+{
+    type Context = EntryContext;
+
+    fn create_file(
+        &'b self,
+        file_name: &U16CStr,
+        _security_context: &DOKAN_IO_SECURITY_CONTEXT,
+        _desired_access: u32,
+        _file_attributes: u32,
+        _share_access: u32,
+        create_disposition: u32,
+        create_options: u32,
+        _info: &mut OperationInfo<'a, 'b, Self>,
+    ) -> OperationResult<CreateFileInfo<Self::Context>> {
+        let path = self.path(file_name)?;
+        let repo = self.repo();
+
+        if Self::is_directory(&repo, &path) {
+            if create_options & FILE_NON_DIRECTORY_FILE != 0 {
+                return status(STATUS_FILE_IS_A_DIRECTORY);
+            }
+            if create_disposition == FILE_CREATE {
+                return status(STATUS_OBJECT_NAME_COLLISION);
+            }
+            return Ok(CreateFileInfo {
+                context: EntryContext::new(path, true),
+                is_dir: true,
+                new_file_created: false,
+            });
+        }
+
+        if repo.exists(&path) {
+            if create_options & FILE_DIRECTORY_FILE != 0 {
+                return status(STATUS_NOT_A_DIRECTORY);
+            }
+            // Special files can be listed, but their contents can't be accessed.
+            if !repo.is_file(&path) {
+                return status(STATUS_ACCESS_DENIED);
+            }
+            match create_disposition {
+                FILE_CREATE => return status(STATUS_OBJECT_NAME_COLLISION),
+                FILE_SUPERSEDE | FILE_OVERWRITE | FILE_OVERWRITE_IF => {
+                    Self::set_len(&repo, &path, 0)?;
+                    repo.commit()?;
+                }
+                _ => (),
+            }
+            return Ok(CreateFileInfo {
+                context: EntryContext::new(path, false),
+                is_dir: false,
+                new_file_created: false,
+            });
+        }
+
+        match create_disposition {
+            FILE_OPEN | FILE_OVERWRITE => return status(STATUS_OBJECT_NAME_NOT_FOUND),
+            FILE_CREATE | FILE_OPEN_IF | FILE_OVERWRITE_IF | FILE_SUPERSEDE => (),
+            _ => return status(STATUS_INVALID_PARAMETER),
+        }
+
+        let is_dir = create_options & FILE_DIRECTORY_FILE != 0;
+        let entry = if is_dir {
+            Entry::directory()
+        } else {
+            Entry::file()
+        };
+        repo.create(&path, &entry)?;
+        repo.commit()?;
+
+        Ok(CreateFileInfo {
+            context: EntryContext::new(path, is_dir),
+            is_dir,
+            new_file_created: true,
+        })
+    }
+
+    fn cleanup(
+        &'b self,
+        _file_name: &U16CStr,
+        info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Self::Context,
+    ) {
+        let path = context.path();
+        let repo = self.repo();
+
+        // Windows deletes files by opening them with delete-on-close, so the entry is removed when
+        // the last handle is cleaned up. Errors can't be reported here, and `delete_file` and
+        // `delete_directory` have already checked that the entry can be removed.
+        if info.delete_on_close() && path != self.root {
+            if repo.remove(&path).is_ok() {
+                repo.commit().ok();
+            }
+        } else if context.modified.swap(false, Ordering::SeqCst) {
+            repo.commit().ok();
+        }
+    }
+
+    fn read_file(
+        &'b self,
+        _file_name: &U16CStr,
+        offset: i64,
+        buffer: &mut [u8],
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Self::Context,
+    ) -> OperationResult<u32> {
+        if context.is_dir {
+            return status(STATUS_INVALID_DEVICE_REQUEST);
+        }
+        let offset = to_offset(offset)?;
+        let path = context.path();
+        let repo = self.repo();
+
+        let mut object = repo.open(&path)?;
+        if offset >= object.size()? {
+            return Ok(0);
+        }
+        object.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+
+        let mut bytes_read = 0;
+        while bytes_read < buffer.len() {
+            match object.read(&mut buffer[bytes_read..]).map_err(io_error)? {
+                0 => break,
+                n => bytes_read += n,
+            }
+        }
+
+        Ok(bytes_read as u32)
+    }
+
+    fn write_file(
+        &'b self,
+        _file_name: &U16CStr,
+        offset: i64,
+        buffer: &[u8],
+        info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Self::Context,
+    ) -> OperationResult<u32> {
+        if context.is_dir {
+            return status(STATUS_INVALID_DEVICE_REQUEST);
+        }
+        let path = context.path();
+        let repo = self.repo();
+
+        let old_size = repo.file_size(&path)?;
+        let offset = if info.write_to_eof() {
+            old_size
+        } else {
+            to_offset(offset)?
+        };
+
+        // Paging I/O can't extend a file, so writes past the end of the file are truncated.
+        let buffer = if info.paging_io() {
+            if offset >= old_size {
+                return Ok(0);
+            }
+            let len = buffer.len().min((old_size - offset) as usize);
+            &buffer[..len]
+        } else {
+            buffer
+        };
+
+        let end_position = offset + buffer.len() as u64;
+
+        // Check the quotas of the file's ancestors before writing anything if this write would
+        // extend the file.
+        repo.check_quota(&path, end_position.saturating_sub(old_size))?;
+
+        let mut object = repo.open(&path)?;
+
+        // It's not possible to seek past the end of an object.
+        if offset > old_size {
+            object.set_len(offset)?;
+        }
+        object.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+        object.write_all(buffer).map_err(io_error)?;
+        object.commit()?;
+        drop(object);
+
+        if end_position > old_size {
+            repo.inner_mut().record_size(&path, end_position);
+        }
+        context.modified.store(true, Ordering::SeqCst);
+
+        Ok(buffer.len() as u32)
+    }
+
+    fn flush_file_buffers(
+        &'b self,
+        _file_name: &U16CStr,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Self::Context,
+    ) -> OperationResult<()> {
+        if context.modified.swap(false, Ordering::SeqCst) {
+            self.repo().commit()?;
+        }
+        Ok(())
+    }
+
+    fn get_file_information(
+        &'b self,
+        _file_name: &U16CStr,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Self::Context,
+    ) -> OperationResult<FileInfo> {
+        let path = context.path();
+        let repo = self.repo();
+
+        let entry = Self::entry(&repo, &path)?;
+        let (attributes, modified) = Self::attributes(&entry);
+        let file_size = if entry.is_file() {
+            repo.file_size(&path)?
+        } else {
+            0
+        };
+
+        Ok(FileInfo {
+            attributes,
+            creation_time: modified,
+            last_access_time: modified,
+            last_write_time: modified,
+            file_size,
+            number_of_links: 1,
+            file_index: 0,
+        })
+    }
+
+    fn find_files(
+        &'b self,
+        _file_name: &U16CStr,
+        mut fill_find_data: impl FnMut(&FindData) -> Result<(), FillDataError>,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Self::Context,
+    ) -> OperationResult<()> {
+        if !context.is_dir {
+            return status(STATUS_NOT_A_DIRECTORY);
+        }
+        let path = context.path();
+        let repo = self.repo();
+
+        // Read the entries through the same guard rather than locking the repository again while
+        // it's being listed.
+        let inner = repo.inner();
+        for child in inner.list(&path)? {
+            let entry = inner.entry(&child)?;
+            let (attributes, modified) = Self::attributes(&entry);
+            let file_size = if entry.is_file() {
+                inner.file_size(&child)?
+            } else {
+                0
+            };
+            let file_name = match U16CString::from_str(child.file_name().unwrap()) {
+                Ok(file_name) => file_name,
+                Err(_) => continue,
+            };
+
+            let find_data = FindData {
+                attributes,
+                creation_time: modified,
+                last_access_time: modified,
+                last_write_time: modified,
+                file_size,
+                file_name,
+            };
+            match fill_find_data(&find_data) {
+                Ok(()) => (),
+                // Entries with names which Windows can't represent are skipped.
+                Err(FillDataError::NameTooLong) => (),
+                Err(FillDataError::BufferFull) => return status(STATUS_BUFFER_OVERFLOW),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delete_file(
+        &'b self,
+        _file_name: &U16CStr,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Self::Context,
+    ) -> OperationResult<()> {
+        if context.is_dir {
+            return status(STATUS_FILE_IS_A_DIRECTORY);
+        }
+        Ok(())
+    }
+
+    fn delete_directory(
+        &'b self,
+        _file_name: &U16CStr,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Self::Context,
+    ) -> OperationResult<()> {
+        let path = context.path();
+        if path == self.root {
+            return status(STATUS_CANNOT_DELETE);
+        }
+        if self.repo().list(&path)?.next().is_some() {
+            return status(STATUS_DIRECTORY_NOT_EMPTY);
+        }
+        Ok(())
+    }
+
+    fn move_file(
+        &'b self,
+        _file_name: &U16CStr,
+        new_file_name: &U16CStr,
+        replace_if_existing: bool,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Self::Context,
+    ) -> OperationResult<()> {
+        let source = context.path();
+        let dest = self.path(new_file_name)?;
+        if source == self.root || dest == self.root || dest.starts_with(&source) {
+            return status(STATUS_ACCESS_DENIED);
+        }
+
+        let repo = self.repo();
+        if repo.exists(&dest) {
+            if !replace_if_existing {
+                return status(STATUS_OBJECT_NAME_COLLISION);
+            }
+            if repo.is_directory(&dest) {
+                return status(STATUS_ACCESS_DENIED);
+            }
+            repo.remove(&dest)?;
+        }
+        repo.rename(&source, &dest)?;
+        repo.commit()?;
+
+        *context.path.lock().recover() = dest;
+
+        Ok(())
+    }
+
+    fn set_end_of_file(
+        &'b self,
+        _file_name: &U16CStr,
+        offset: i64,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Self::Context,
+    ) -> OperationResult<()> {
+        if context.is_dir {
+            return status(STATUS_INVALID_DEVICE_REQUEST);
+        }
+        let len = to_offset(offset)?;
+        let path = context.path();
+
+        Self::set_len(&self.repo(), &path, len)?;
+        context.modified.store(true, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    fn set_allocation_size(
+        &'b self,
+        _file_name: &U16CStr,
+        alloc_size: i64,
+        _info: &OperationInfo<'a, 'b, Self>,
+        context: &'a Self::Context,
+    ) -> OperationResult<()> {
+        if context.is_dir {
+            return status(STATUS_INVALID_DEVICE_REQUEST);
+        }
+        let alloc_size = to_offset(alloc_size)?;
+        let path = context.path();
+        let repo = self.repo();
+
+        // Space isn't allocated ahead of time, but shrinking the allocation truncates the file.
+        if alloc_size < repo.file_size(&path)? {
+            Self::set_len(&repo, &path, alloc_size)?;
+            context.modified.store(true, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+
+    fn get_disk_free_space(
+        &'b self,
+        _info: &OperationInfo<'a, 'b, Self>,
+    ) -> OperationResult<DiskSpaceInfo> {
+        let stats = self.repo().tree_stats(&*EMPTY_PATH)?;
+
+        // Data which is shared between files is only counted once toward the used space.
+        Ok(DiskSpaceInfo {
+            byte_count: stats.stored_size + FREE_BYTES,
+            free_byte_count: FREE_BYTES,
+            available_byte_count: FREE_BYTES,
+        })
+    }
+
+    fn get_volume_information(
+        &'b self,
+        _info: &OperationInfo<'a, 'b, Self>,
+    ) -> OperationResult<VolumeInfo> {
+        Ok(VolumeInfo {
+            name: U16CString::from_str(VOLUME_NAME).unwrap(),
+            serial_number: 0,
+            max_component_length: MAX_COMPONENT_LENGTH,
+            // Paths in a `FileRepo` are case-sensitive.
+            fs_flags: FILE_CASE_SENSITIVE_SEARCH | FILE_CASE_PRESERVED_NAMES | FILE_UNICODE_ON_DISK,
+            fs_name: U16CString::from_str(VOLUME_NAME).unwrap(),
+        })
+    }
+}
+
+impl<S, M> FileRepo<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+    FileRepo<S, M>: Sync,
+{
+    /// Mount the tree of entries at `root` as a file system on Windows using Dokan.
+    ///
+    /// This mounts the directory at `root` at `mountpoint`, which is either a drive letter like
+    /// `M:\` or an empty directory. The `root` may be an empty path to mount the whole repository.
+    /// This requires the [Dokan] driver to be installed.
+    ///
+    /// Windows file attributes and security descriptors are not stored, and special files can be
+    /// listed but not opened. Changes to a file are committed when it is flushed or closed, and
+    /// other changes are committed immediately.
+    ///
+    /// This method does not return until the file system is unmounted.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: There is no entry at `root`.
+    /// - `Error::NotDirectory`: The given `root` entry is not a directory.
+    /// - `Error::InvalidPath`: The given `mountpoint` contains a nul character.
+    /// - `Error::Io`: The file system could not be mounted.
+    ///
+    /// [Dokan]: https://dokan-dev.github.io/
+    #[cfg_attr(docsrs, doc(cfg(all(windows, feature = "dokan-mount"))))]
+    pub fn mount_dokan(
+        &self,
+        mountpoint: impl AsRef<Path>,
+        root: impl AsRef<RelativePath>,
+    ) -> crate::Result<()> {
+        let root = root.as_ref();
+        if root != *EMPTY_PATH && !self.entry(root)?.is_directory() {
+            return Err(crate::Error::NotDirectory);
+        }
+
+        let mountpoint = U16CString::from_os_str(mountpoint.as_ref().as_os_str())
+            .map_err(|_| crate::Error::InvalidPath)?;
+        let adapter = DokanAdapter {
+            repo: Mutex::new(self),
+            root: root.to_owned(),
+        };
+
+        Drive::new()
+            .mount_point(&mountpoint)
+            .mount(&adapter)
+            .map_err(|error| {
+                crate::Error::Io(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("failed to mount the file system: {:?}", error),
+                ))
+            })
+    }
+}
@@ -15,6 +15,7 @@
  */
 
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::repo::state::ObjectKey;
 
@@ -107,7 +108,7 @@ impl<S: SpecialType, M: FileMetadata> Entry<S, M> {
 }
 
 /// A type of entry handle.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 pub enum EntryType {
     File(ObjectKey),
     Directory,
@@ -115,8 +116,37 @@ pub enum EntryType {
 }
 
 /// A handle for accessing the data associated with each entry.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 pub struct EntryHandle {
     pub entry: ObjectKey,
     pub entry_type: EntryType,
+
+    /// An ID shared by entries which were hard links to the same file when they were archived.
+    #[serde(default)]
+    pub link: Option<Uuid>,
+
+    /// The size of the file in bytes if this is a file.
+    ///
+    /// This is `None` for entries which are not files and for files in repositories created
+    /// before file sizes were recorded.
+    #[serde(default)]
+    pub size: Option<u64>,
+
+    /// The object which stores the entry's application-defined attributes, if it has any.
+    #[serde(default)]
+    pub attributes: Option<ObjectKey>,
+
+    /// The maximum total size in bytes of the files beneath this directory, if it has a quota.
+    #[serde(default)]
+    pub quota: Option<u64>,
+
+    /// The inode number of the entry if the repository has been mounted as a FUSE file system.
+    ///
+    /// This is stored so that inode numbers are stable across mounts.
+    #[serde(default)]
+    pub inode: Option<u64>,
+
+    /// The generation of the inode number of the entry.
+    #[serde(default)]
+    pub generation: u64,
 }
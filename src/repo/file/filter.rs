@@ -0,0 +1,193 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::borrow::Cow;
+
+use globset::{GlobBuilder, GlobMatcher};
+use relative_path::{RelativePath, RelativePathBuf};
+
+/// A compiled include or exclude pattern.
+#[derive(Debug, Clone)]
+struct Pattern {
+    /// The matcher for the glob.
+    matcher: GlobMatcher,
+
+    /// Whether this pattern only matches directories.
+    directory_only: bool,
+}
+
+impl Pattern {
+    /// Compile a gitignore-style `pattern`.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPattern`: The given `pattern` is not a valid glob.
+    fn new(pattern: &str) -> crate::Result<Self> {
+        let directory_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+
+        // Like in gitignore files, a pattern which contains a slash is anchored to the root of the
+        // tree, while a pattern without one matches at any depth.
+        let glob = if pattern.contains('/') {
+            pattern.trim_start_matches('/').to_owned()
+        } else {
+            format!("**/{}", pattern)
+        };
+
+        if glob.is_empty() || glob == "**/" {
+            return Err(crate::Error::InvalidPattern);
+        }
+
+        let matcher = GlobBuilder::new(&glob)
+            .literal_separator(true)
+            .build()
+            .map_err(|_| crate::Error::InvalidPattern)?
+            .compile_matcher();
+
+        Ok(Self {
+            matcher,
+            directory_only,
+        })
+    }
+
+    /// Return whether this pattern matches the given `path`.
+    fn is_match(&self, path: &RelativePath, is_directory: bool) -> bool {
+        (is_directory || !self.directory_only) && self.matcher.is_match(path.as_str())
+    }
+}
+
+/// A set of include and exclude patterns for selecting the files in a tree.
+///
+/// Patterns are globs with gitignore-style semantics:
+/// - `*` matches any sequence of characters except `/`, and `**` matches any number of
+///   directories.
+/// - A pattern which contains a `/` is matched against the whole path relative to the root of the
+///   tree. A pattern without one, like `*.o` or `node_modules`, is matched against the file name at
+///   any depth.
+/// - A pattern which ends with a `/`, like `cache/`, only matches directories.
+///
+/// A file or directory is excluded if it matches any exclude pattern, and the descendants of an
+/// excluded directory are always excluded. If there are any include patterns, files which are not
+/// directories are also excluded unless they match at least one include pattern. Include patterns
+/// do not apply to directories so that files in subdirectories can still be included. The root of
+/// the tree is never excluded.
+///
+/// This is used with [`FileRepo::archive_tree_filtered`], [`FileRepo::extract_tree_filtered`], and
+/// [`FileRepo::walk_filtered`].
+///
+/// [`FileRepo::archive_tree_filtered`]: crate::repo::file::FileRepo::archive_tree_filtered
+/// [`FileRepo::extract_tree_filtered`]: crate::repo::file::FileRepo::extract_tree_filtered
+/// [`FileRepo::walk_filtered`]: crate::repo::file::FileRepo::walk_filtered
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl PathFilter {
+    /// Create a new `PathFilter` which matches every path.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only include files which match the given `pattern` or another include pattern.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPattern`: The given `pattern` is not a valid glob.
+    pub fn include(&mut self, pattern: &str) -> crate::Result<&mut Self> {
+        self.include.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Exclude files and directories which match the given `pattern`.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPattern`: The given `pattern` is not a valid glob.
+    pub fn exclude(&mut self, pattern: &str) -> crate::Result<&mut Self> {
+        self.exclude.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Return whether the given `path` is selected by this filter.
+    ///
+    /// The `path` is relative to the root of the tree. This does not check whether any of the
+    /// ancestors of `path` are excluded.
+    pub fn is_match(&self, path: impl AsRef<RelativePath>, is_directory: bool) -> bool {
+        let path = path.as_ref();
+
+        if self
+            .exclude
+            .iter()
+            .any(|pattern| pattern.is_match(path, is_directory))
+        {
+            return false;
+        }
+
+        is_directory
+            || self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| pattern.is_match(path, is_directory))
+    }
+
+    /// Return a `TreeFilter` for filtering paths yielded in depth-first order.
+    pub(super) fn tree_filter(&self) -> TreeFilter<'_> {
+        TreeFilter {
+            filter: Cow::Borrowed(self),
+            excluded: Vec::new(),
+        }
+    }
+
+    /// Consume this filter and return a `TreeFilter` for filtering paths yielded in depth-first
+    /// order.
+    pub(super) fn into_tree_filter(self) -> TreeFilter<'static> {
+        TreeFilter {
+            filter: Cow::Owned(self),
+            excluded: Vec::new(),
+        }
+    }
+}
+
+/// A `PathFilter` which also excludes the descendants of excluded directories.
+///
+/// This requires that paths are visited in depth-first order.
+#[derive(Debug)]
+pub struct TreeFilter<'a> {
+    filter: Cow<'a, PathFilter>,
+    excluded: Vec<RelativePathBuf>,
+}
+
+impl<'a> TreeFilter<'a> {
+    /// Return whether the given `path` relative to the root of the tree is selected.
+    pub fn is_match(&mut self, path: &RelativePath, is_directory: bool) -> bool {
+        if self
+            .excluded
+            .iter()
+            .any(|directory| path.starts_with(directory))
+        {
+            return false;
+        }
+
+        if self.filter.is_match(path, is_directory) {
+            true
+        } else {
+            if is_directory {
+                self.excluded.push(path.to_owned());
+            }
+            false
+        }
+    }
+}
@@ -0,0 +1,133 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::path::Path;
+
+use acid_store_ffi::ioctl::{ioctl_none, ioctl_read, ioctl_write};
+use nix::{request_code_none, request_code_read, request_code_write};
+
+use crate::repo::file::TreeStats;
+
+/// The ioctl type number used for all the commands supported by the file system.
+const IOCTL_MAGIC: u8 = b'A';
+
+/// The maximum length of a snapshot name in bytes, including the terminating null byte.
+pub const SNAPSHOT_NAME_LEN: usize = 256;
+
+/// The ioctl which commits changes to the repository.
+pub const COMMIT_IOCTL: u32 = request_code_none!(IOCTL_MAGIC, 0) as u32;
+
+/// The ioctl which creates a snapshot with the given null-terminated name.
+pub const SNAPSHOT_IOCTL: u32 = request_code_write!(IOCTL_MAGIC, 1, SNAPSHOT_NAME_LEN) as u32;
+
+/// The ioctl which returns the statistics for a tree of entries.
+pub const STATS_IOCTL: u32 =
+    request_code_read!(IOCTL_MAGIC, 2, std::mem::size_of::<[u64; 5]>()) as u32;
+
+/// Encode `stats` as the output of `STATS_IOCTL`.
+pub fn encode_stats(stats: &TreeStats) -> [u64; 5] {
+    [
+        stats.files,
+        stats.directories,
+        stats.special,
+        stats.size,
+        stats.stored_size,
+    ]
+}
+
+/// A handle for controlling a [`FileRepo`] which is mounted as a FUSE file system.
+///
+/// This allows any process which can open a file in the mounted file system to control the
+/// repository without unmounting it or having access to the process which mounted it. Commands are
+/// sent to the file system as ioctls on the opened file.
+///
+/// [`FileRepo`]: crate::repo::file::FileRepo
+#[derive(Debug)]
+pub struct MountControl(File);
+
+impl MountControl {
+    /// Open a handle for controlling the file system which contains the file at `path`.
+    ///
+    /// The `path` can be any file or directory in the mounted file system, including the
+    /// mountpoint itself.
+    ///
+    /// # Errors
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn open(path: impl AsRef<Path>) -> crate::Result<Self> {
+        Ok(Self(File::open(path)?))
+    }
+
+    /// Commit changes to the mounted repository.
+    ///
+    /// # Errors
+    /// - `Error::Io`: The file is not in a mounted repository, or an I/O error occurred.
+    pub fn commit(&self) -> crate::Result<()> {
+        ioctl_none(&self.0, IOCTL_MAGIC, 0)?;
+        Ok(())
+    }
+
+    /// Take a snapshot of the mounted directory with the given `name`.
+    ///
+    /// The snapshot is created in the directory set with [`MountOptions::snapshots`] and appears
+    /// as `.snapshots/<name>` in the mounted file system.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `name` is not a valid file name.
+    /// - `Error::Io`: The file is not in a mounted repository, the file system was not mounted
+    ///   with a snapshots directory, a snapshot named `name` already exists, or an I/O error
+    ///   occurred.
+    ///
+    /// [`MountOptions::snapshots`]: crate::repo::file::MountOptions::snapshots
+    pub fn snapshot(&self, name: &str) -> crate::Result<()> {
+        if name.is_empty()
+            || name == "."
+            || name == ".."
+            || name.contains(&['/', '\0'][..])
+            || name.len() >= SNAPSHOT_NAME_LEN
+        {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        let mut buffer = [0u8; SNAPSHOT_NAME_LEN];
+        buffer[..name.len()].copy_from_slice(name.as_bytes());
+        ioctl_write(&self.0, IOCTL_MAGIC, 1, &buffer)?;
+        Ok(())
+    }
+
+    /// Return statistics about the tree of entries at the path this handle was opened with.
+    ///
+    /// # Errors
+    /// - `Error::Io`: The file is not in a mounted repository, or an I/O error occurred.
+    pub fn stats(&self) -> crate::Result<TreeStats> {
+        let mut buffer = [0u8; std::mem::size_of::<[u64; 5]>()];
+        ioctl_read(&self.0, IOCTL_MAGIC, 2, &mut buffer)?;
+
+        let mut output = [0u64; 5];
+        for (value, bytes) in output.iter_mut().zip(buffer.chunks_exact(8)) {
+            *value = u64::from_ne_bytes(bytes.try_into().unwrap());
+        }
+        let [files, directories, special, size, stored_size] = output;
+        Ok(TreeStats {
+            files,
+            directories,
+            special,
+            size,
+            stored_size,
+        })
+    }
+}
@@ -15,47 +15,62 @@
  */
 
 use std::collections::hash_map::Entry as HashMapEntry;
+use std::convert::TryFrom;
 use std::ffi::OsStr;
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
-use std::time::SystemTime;
-
-use fuse::{
-    FileAttr, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen,
-    ReplyWrite, ReplyXattr, Request,
+use std::time::{Duration, SystemTime};
+
+use fuser::consts::{FUSE_FLOCK_LOCKS, FUSE_POSIX_LOCKS, FUSE_WRITEBACK_CACHE};
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+use fuser::ReplyLseek;
+use fuser::{
+    FileAttr, FileType as FuseFileType, Filesystem, KernelConfig, ReplyAttr, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyIoctl, ReplyLock, ReplyOpen, ReplyStatfs,
+    ReplyWrite, ReplyXattr, Request, TimeOrNow, FUSE_ROOT_ID,
 };
 use nix::fcntl::OFlag;
 use nix::libc;
 use nix::sys::stat::{self, SFlag};
 use once_cell::sync::Lazy;
 use relative_path::{RelativePath, RelativePathBuf};
-use time::Timespec;
 
 use super::acl::{Permissions, ACCESS_ACL_XATTR, DEFAULT_ACL_XATTR};
+use super::control::{encode_stats, COMMIT_IOCTL, SNAPSHOT_IOCTL, STATS_IOCTL};
 use super::handle::{DirectoryEntry, DirectoryHandle, FileHandle, HandleState, HandleTable};
 use super::inode::InodeTable;
-use super::metadata::to_timespec;
+use super::lock::{FileLock, LockTable};
+use super::metadata::to_system_time;
 use super::object::ObjectTable;
+use super::options::MountOptions;
 
-use crate::repo::file::fuse::metadata::to_system_time;
+#[cfg(target_os = "linux")]
+use crate::repo::file::allocate::AllocateRequest;
 use crate::repo::file::{
     repository::EMPTY_PATH, AccessQualifier, Entry, FileRepo, FileType, UnixMetadata,
     UnixSpecialType,
 };
 use crate::repo::{Commit, RestoreSavepoint};
 
-/// The block size used to calculate `st_blocks`.
+/// The block size used to calculate `st_blocks` and the block counts reported by `statfs`.
 const BLOCK_SIZE: u64 = 512;
 
-/// The default TTL value to use in FUSE replies.
+/// The amount of free space in bytes to report in `statfs`.
 ///
-/// Because the backing `FileRepo` can only be safely modified through the FUSE file system, we can
-/// set this to an arbitrarily large value.
-const DEFAULT_TTL: Timespec = Timespec {
-    sec: i64::MAX,
-    nsec: i32::MAX,
-};
+/// The capacity of the data store backing the repository generally isn't known, so we report a
+/// large amount of free space so that programs which check for free space before writing files
+/// don't refuse to write them.
+const STATFS_FREE_BYTES: u64 = 1 << 50;
+
+/// The number of free inodes to report in `statfs`.
+const STATFS_FREE_FILES: u64 = 1 << 32;
+
+/// The maximum length of a file name in bytes to report in `statfs`.
+const MAX_NAME_LEN: u32 = 255;
+
+/// The name of the directory in the root of the file system which contains snapshots.
+const SNAPSHOTS_DIR_NAME: &str = ".snapshots";
 
 /// The set of `open` flags which are not supported by this file system.
 static UNSUPPORTED_OPEN_FLAGS: Lazy<OFlag> = Lazy::new(|| OFlag::O_DIRECT | OFlag::O_TMPFILE);
@@ -108,7 +123,7 @@ macro_rules! try_option {
 #[derive(Debug)]
 pub struct FuseAdapter<'a> {
     /// The repository which contains the virtual file system.
-    repo: &'a mut FileRepo<UnixSpecialType, UnixMetadata>,
+    repo: &'a FileRepo<UnixSpecialType, UnixMetadata>,
 
     /// A table for allocating inodes.
     inodes: InodeTable,
@@ -118,30 +133,146 @@ pub struct FuseAdapter<'a> {
 
     /// A map of inodes to currently open file objects.
     objects: ObjectTable,
+
+    /// A table of advisory locks held on files.
+    locks: LockTable,
+
+    /// How long the kernel may cache file attributes.
+    attr_ttl: Duration,
+
+    /// How long the kernel may cache name lookups.
+    entry_ttl: Duration,
+
+    /// Whether to enable write-back caching in the kernel.
+    writeback_cache: bool,
+
+    /// Whether the file system is mounted read-only.
+    read_only: bool,
+
+    /// The path of the directory in the repository which contains read-only snapshots.
+    snapshots: Option<RelativePathBuf>,
 }
 
 impl<'a> FuseAdapter<'a> {
     /// Create a new `FuseAdapter` from the given `repo`.
     pub fn new(
-        repo: &'a mut FileRepo<UnixSpecialType, UnixMetadata>,
+        repo: &'a FileRepo<UnixSpecialType, UnixMetadata>,
         root: &RelativePath,
+        options: &MountOptions,
     ) -> crate::Result<Self> {
         if root == *EMPTY_PATH {
             return Err(crate::Error::InvalidPath);
         }
 
-        let mut inodes = InodeTable::new(root);
+        let mut paths = repo.walk(root)?.collect::<Vec<_>>();
 
-        for path in repo.walk(root)? {
-            inodes.insert(path);
+        if let Some(snapshots) = &options.snapshots {
+            if snapshots.starts_with(root) || root.starts_with(snapshots) {
+                return Err(crate::Error::InvalidPath);
+            }
+            if !repo.entry(snapshots)?.is_directory() {
+                return Err(crate::Error::NotDirectory);
+            }
+
+            paths.push(snapshots.clone());
+            paths.extend(repo.walk(snapshots)?);
         }
 
-        Ok(Self {
+        let mut adapter = Self {
             repo,
-            inodes,
+            inodes: InodeTable::new(root),
             handles: HandleTable::new(),
             objects: ObjectTable::new(),
-        })
+            locks: LockTable::new(),
+            attr_ttl: options.attr_ttl,
+            entry_ttl: options.entry_ttl,
+            writeback_cache: options.writeback_cache,
+            read_only: options.read_only,
+            snapshots: options.snapshots.clone(),
+        };
+        adapter.load_inodes(paths)?;
+
+        Ok(adapter)
+    }
+
+    /// Insert the entries at `paths` into the inode table.
+    ///
+    /// Entries keep the inodes they were assigned the last time the repository was mounted. Any
+    /// entries which are assigned new inodes have them stored in the repository, and changes are
+    /// committed.
+    fn load_inodes(&mut self, paths: Vec<RelativePathBuf>) -> crate::Result<()> {
+        let mut new_paths = Vec::new();
+        for path in paths {
+            let handle = *self
+                .repo
+                .inner()
+                .0
+                .state()
+                .get(&path)
+                .ok_or(crate::Error::NotFound)?;
+            let is_loaded = match handle.inode {
+                Some(inode) => self
+                    .inodes
+                    .insert_existing(path.clone(), inode, handle.generation),
+                None => false,
+            };
+            if !is_loaded {
+                new_paths.push(path);
+            }
+        }
+
+        if new_paths.is_empty() {
+            return Ok(());
+        }
+
+        for path in new_paths {
+            self.insert_inode(path)?;
+        }
+        self.repo.commit()
+    }
+
+    /// Allocate an inode for the entry at `path` and return it.
+    ///
+    /// The inode is stored in the entry so that it is stable across mounts. This does not commit
+    /// changes to the repository.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: There is no entry at `path`. The inode table is unchanged.
+    fn insert_inode(&mut self, path: RelativePathBuf) -> crate::Result<u64> {
+        let mut repo = self.repo.inner_mut();
+        let handle = repo
+            .0
+            .state_mut()
+            .get_mut(&path)
+            .ok_or(crate::Error::NotFound)?;
+        let inode = self.inodes.insert(path);
+        handle.inode = Some(inode);
+        handle.generation = self.inodes.generation(inode);
+        Ok(inode)
+    }
+
+    /// Return the path of the entry named `file_name` in the directory at `parent`.
+    ///
+    /// This resolves the `.snapshots` directory in the root of the file system.
+    fn child_path(&self, parent: u64, file_name: &str) -> Option<RelativePathBuf> {
+        match &self.snapshots {
+            Some(snapshots) if parent == FUSE_ROOT_ID && file_name == SNAPSHOTS_DIR_NAME => {
+                Some(snapshots.clone())
+            }
+            _ => Some(self.inodes.path(parent)?.join(file_name)),
+        }
+    }
+
+    /// Return whether the entry at `path` can't be modified.
+    ///
+    /// This is the case for every entry if the file system is mounted read-only and for entries in
+    /// the `.snapshots` directory.
+    fn is_read_only(&self, path: &RelativePath) -> bool {
+        self.read_only
+            || self
+                .snapshots
+                .as_ref()
+                .is_some_and(|snapshots| path.starts_with(snapshots))
     }
 
     /// Get the `FileAttr` for the `entry` with the given `inode`.
@@ -156,11 +287,7 @@ impl<'a> FuseAdapter<'a> {
         let metadata = entry.metadata.as_ref().unwrap_or(&default_metadata);
 
         let size = match &entry.file_type {
-            FileType::File => self
-                .objects
-                .open_commit(inode, self.repo.open(entry_path).unwrap())?
-                .size()
-                .unwrap(),
+            FileType::File => self.repo.file_size(entry_path)?,
             FileType::Directory => 0,
             FileType::Special(special) => match special {
                 // The `st_size` of a symlink should be the length of the pathname it contains.
@@ -180,20 +307,11 @@ impl<'a> FuseAdapter<'a> {
             ino: inode,
             size,
             blocks: size / BLOCK_SIZE,
-            atime: to_timespec(metadata.accessed),
-            mtime: to_timespec(metadata.modified),
-            ctime: to_timespec(metadata.changed),
-            crtime: to_timespec(SystemTime::now()),
-            kind: match &entry.file_type {
-                FileType::File => fuse::FileType::RegularFile,
-                FileType::Directory => fuse::FileType::Directory,
-                FileType::Special(special) => match special {
-                    UnixSpecialType::SymbolicLink { .. } => fuse::FileType::Symlink,
-                    UnixSpecialType::NamedPipe => fuse::FileType::NamedPipe,
-                    UnixSpecialType::BlockDevice { .. } => fuse::FileType::BlockDevice,
-                    UnixSpecialType::CharacterDevice { .. } => fuse::FileType::CharDevice,
-                },
-            },
+            atime: metadata.accessed,
+            mtime: metadata.modified,
+            ctime: metadata.changed,
+            crtime: metadata.created.unwrap_or(metadata.changed),
+            kind: entry.file_type.to_file_type(),
             perm: mode as u16,
             nlink: 1,
             uid: metadata.user,
@@ -210,6 +328,7 @@ impl<'a> FuseAdapter<'a> {
                 },
                 _ => NON_SPECIAL_RDEV,
             },
+            blksize: BLOCK_SIZE as u32,
             flags: 0,
         })
     }
@@ -224,7 +343,7 @@ impl<'a> FuseAdapter<'a> {
         entry: &Entry<UnixSpecialType, UnixMetadata>,
         req: &Request,
     ) -> crate::Result<FileAttr> {
-        let entry_inode = self.inodes.insert(path);
+        let entry_inode = self.insert_inode(path)?;
         match self.entry_attr(&entry, entry_inode, req) {
             Ok(attr) => Ok(attr),
             Err(error) => {
@@ -247,17 +366,27 @@ impl<'a> FuseAdapter<'a> {
         self.objects.commit_all()?;
 
         let savepoint = self.repo.savepoint()?;
-        let restore = self.repo.start_restore(&savepoint)?;
+        let restore = self
+            .repo
+            .inner_mut()
+            .0
+            .start_restore_without_hooks(&savepoint)?;
         match block(self) {
             Ok(result) => match self.repo.commit() {
                 Ok(()) => Ok(result),
                 Err(error) => {
-                    self.repo.finish_restore(restore);
+                    self.repo
+                        .inner_mut()
+                        .0
+                        .finish_restore_without_hooks(restore);
                     Err(error)
                 }
             },
             Err(error) => {
-                self.repo.finish_restore(restore);
+                self.repo
+                    .inner_mut()
+                    .0
+                    .finish_restore_without_hooks(restore);
                 Err(error)
             }
         }
@@ -265,9 +394,24 @@ impl<'a> FuseAdapter<'a> {
 }
 
 impl<'a> Filesystem for FuseAdapter<'a> {
+    fn init(&mut self, _req: &Request, config: &mut KernelConfig) -> Result<(), libc::c_int> {
+        // If the kernel doesn't support handling locks in the file system, it handles them itself.
+        let _ = config.add_capabilities(FUSE_POSIX_LOCKS);
+        let _ = config.add_capabilities(FUSE_FLOCK_LOCKS);
+
+        if self.writeback_cache {
+            // If the kernel doesn't support write-back caching, fall back to write-through.
+            let _ = config.add_capabilities(FUSE_WRITEBACK_CACHE);
+        }
+        Ok(())
+    }
+
     fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        let file_name = try_option!(name.to_str(), reply, libc::ENOENT);
-        let entry_path = try_option!(self.inodes.path(parent), reply, libc::ENOENT).join(file_name);
+        let file_name =
+            self.repo
+                .inner()
+                .normalize_name(try_option!(name.to_str(), reply, libc::ENOENT));
+        let entry_path = try_option!(self.child_path(parent, &file_name), reply, libc::ENOENT);
         let entry_inode = try_option!(self.inodes.inode(&entry_path), reply, libc::ENOENT);
         let entry = try_result!(self.repo.entry(&entry_path), reply);
 
@@ -275,7 +419,7 @@ impl<'a> Filesystem for FuseAdapter<'a> {
 
         let generation = self.inodes.generation(entry_inode);
 
-        reply.entry(&DEFAULT_TTL, &attr, generation);
+        reply.entry(&self.entry_ttl, &attr, generation);
     }
 
     fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
@@ -283,7 +427,7 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         let entry = try_result!(self.repo.entry(&entry_path), reply);
         let attr = try_result!(self.entry_attr(&entry, ino, req), reply);
 
-        reply.attr(&DEFAULT_TTL, &attr);
+        reply.attr(&self.attr_ttl, &attr);
     }
 
     fn setattr(
@@ -294,12 +438,13 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         uid: Option<u32>,
         gid: Option<u32>,
         size: Option<u64>,
-        atime: Option<Timespec>,
-        mtime: Option<Timespec>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        ctime: Option<SystemTime>,
         _fh: Option<u64>,
-        _crtime: Option<Timespec>,
-        chgtime: Option<Timespec>,
-        _bkuptime: Option<Timespec>,
+        crtime: Option<SystemTime>,
+        chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
         _flags: Option<u32>,
         reply: ReplyAttr,
     ) {
@@ -307,16 +452,19 @@ impl<'a> Filesystem for FuseAdapter<'a> {
 
         let entry_path = try_option!(self.inodes.path(ino), reply, libc::ENOENT).to_owned();
 
+        if self.is_read_only(&entry_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+
         // Whether the repository needs to be cleaned before this method returns.
         let mut needs_cleaned = false;
 
-        let mut entry = try_result!(self.repo.entry(&entry_path), reply);
+        let entry = try_result!(self.repo.entry(&entry_path), reply);
 
         let default_metadata = entry.default_metadata(req);
-        entry.metadata.get_or_insert(default_metadata);
-
         let file_type = entry.file_type;
-        let mut metadata = entry.metadata.unwrap();
+        let mut metadata = entry.metadata.unwrap_or(default_metadata);
 
         if let Some(mode) = mode {
             metadata.change_permissions(mode);
@@ -331,33 +479,36 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         }
 
         if let Some(atime) = atime {
-            metadata.accessed = to_system_time(atime);
+            metadata.accessed = to_system_time(atime, now);
         }
 
         if let Some(mtime) = mtime {
-            metadata.modified = to_system_time(mtime);
+            metadata.modified = to_system_time(mtime, now);
         }
 
-        if let Some(ctime) = chgtime {
-            metadata.changed = to_system_time(ctime);
-        } else {
-            metadata.changed = now;
+        if let Some(crtime) = crtime {
+            metadata.created = Some(crtime);
         }
 
+        // The change time is passed as `ctime` on Linux and `chgtime` on macOS.
+        metadata.changed = ctime.or(chgtime).unwrap_or(now);
+
         let attr = try_result!(
             self.transaction(|fs| {
-                // If `size` is no                        // Truncating the file should update its `mtime`, `atime`, and `ctime`.t `None`, that means we must truncate or extend the file.
+                // If `size` is not `None`, that means we must truncate or extend the file.
                 if let Some(new_size) = size {
-                    let object = fs
-                        .objects
-                        .open_commit(ino, fs.repo.open(&entry_path).unwrap())?;
+                    let object = fs.objects.open_commit(ino, fs.repo.open(&entry_path)?)?;
+                    let old_size = object.size()?;
 
                     // If this method truncates the file to make it smaller, we need to clean the
                     // repository to free the unused space.
-                    needs_cleaned = new_size < object.size().unwrap();
+                    needs_cleaned = new_size < old_size;
 
-                    if new_size != object.size().unwrap() {
+                    if new_size != old_size {
+                        fs.repo
+                            .check_quota(&entry_path, new_size.saturating_sub(old_size))?;
                         object.set_len(new_size)?;
+                        fs.repo.inner_mut().record_size(&entry_path, new_size);
 
                         // Truncating the file should update its `mtime`, `atime`, and `ctime`.
                         metadata.modified = now;
@@ -383,7 +534,7 @@ impl<'a> Filesystem for FuseAdapter<'a> {
             self.repo.clean().ok();
         }
 
-        reply.attr(&DEFAULT_TTL, &attr);
+        reply.attr(&self.attr_ttl, &attr);
     }
 
     fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
@@ -405,32 +556,39 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         parent: u64,
         name: &OsStr,
         mode: u32,
+        _umask: u32,
         rdev: u32,
         reply: ReplyEntry,
     ) {
-        let flags = SFlag::from_bits_truncate(mode);
-        let file_name = try_option!(name.to_str(), reply, libc::EINVAL);
+        // The file type bits overlap, so they need to be compared as a whole. For example, the
+        // bits for a socket include the bits for a regular file.
+        let file_type_bits = SFlag::from_bits_truncate(mode) & SFlag::S_IFMT;
+        let file_name =
+            self.repo
+                .inner()
+                .normalize_name(try_option!(name.to_str(), reply, libc::EINVAL));
         let parent_path = try_option!(self.inodes.path(parent), reply, libc::ENOENT).to_owned();
-        let entry_path = parent_path.join(file_name);
+        let entry_path = parent_path.join(&*file_name);
 
-        let file_type = if flags.contains(SFlag::S_IFREG) {
+        if self.is_read_only(&parent_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+
+        let file_type = if file_type_bits == SFlag::S_IFREG {
             FileType::File
-        } else if flags.contains(SFlag::S_IFCHR) {
+        } else if file_type_bits == SFlag::S_IFCHR {
             let major = stat::major(rdev as u64);
             let minor = stat::minor(rdev as u64);
             FileType::Special(UnixSpecialType::CharacterDevice { major, minor })
-        } else if flags.contains(SFlag::S_IFBLK) {
+        } else if file_type_bits == SFlag::S_IFBLK {
             let major = stat::major(rdev as u64);
             let minor = stat::minor(rdev as u64);
             FileType::Special(UnixSpecialType::BlockDevice { major, minor })
-        } else if flags.contains(SFlag::S_IFIFO) {
+        } else if file_type_bits == SFlag::S_IFIFO {
             FileType::Special(UnixSpecialType::NamedPipe)
-        } else if flags.contains(SFlag::S_IFSOCK) {
-            // Sockets aren't supported by `FileRepo`. `mknod(2)` specifies that `EPERM`
-            // should be returned if the file system doesn't support the type of node being
-            // requested.
-            reply.error(libc::EPERM);
-            return;
+        } else if file_type_bits == SFlag::S_IFSOCK {
+            FileType::Special(UnixSpecialType::Socket)
         } else {
             // Other file types aren't supported by `mknod`.
             reply.error(libc::EINVAL);
@@ -456,13 +614,29 @@ impl<'a> Filesystem for FuseAdapter<'a> {
 
         let generation = self.inodes.generation(attr.ino);
 
-        reply.entry(&DEFAULT_TTL, &attr, generation);
+        reply.entry(&self.entry_ttl, &attr, generation);
     }
 
-    fn mkdir(&mut self, req: &Request, parent: u64, name: &OsStr, mode: u32, reply: ReplyEntry) {
-        let file_name = try_option!(name.to_str(), reply, libc::EINVAL);
+    fn mkdir(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let file_name =
+            self.repo
+                .inner()
+                .normalize_name(try_option!(name.to_str(), reply, libc::EINVAL));
         let parent_path = try_option!(self.inodes.path(parent), reply, libc::ENOENT).to_owned();
-        let entry_path = parent_path.join(file_name);
+        let entry_path = parent_path.join(&*file_name);
+
+        if self.is_read_only(&parent_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
 
         let parent_entry = try_result!(self.repo.entry(&parent_path), reply);
         let entry = Entry::directory()
@@ -480,13 +654,21 @@ impl<'a> Filesystem for FuseAdapter<'a> {
 
         let generation = self.inodes.generation(attr.ino);
 
-        reply.entry(&DEFAULT_TTL, &attr, generation);
+        reply.entry(&self.entry_ttl, &attr, generation);
     }
 
     fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        let file_name = try_option!(name.to_str(), reply, libc::ENOENT);
+        let file_name =
+            self.repo
+                .inner()
+                .normalize_name(try_option!(name.to_str(), reply, libc::ENOENT));
         let parent_path = try_option!(self.inodes.path(parent), reply, libc::ENOENT).to_owned();
-        let entry_path = parent_path.join(file_name);
+        let entry_path = parent_path.join(&*file_name);
+
+        if self.is_read_only(&parent_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
         let entry_inode = try_option!(self.inodes.inode(&entry_path), reply, libc::ENOENT);
 
         if self.repo.is_directory(&entry_path) {
@@ -513,9 +695,17 @@ impl<'a> Filesystem for FuseAdapter<'a> {
     }
 
     fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        let file_name = try_option!(name.to_str(), reply, libc::ENOENT);
+        let file_name =
+            self.repo
+                .inner()
+                .normalize_name(try_option!(name.to_str(), reply, libc::ENOENT));
         let parent_path = try_option!(self.inodes.path(parent), reply, libc::ENOENT).to_owned();
-        let entry_path = parent_path.join(file_name);
+        let entry_path = parent_path.join(&*file_name);
+
+        if self.is_read_only(&parent_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
 
         if !self.repo.is_directory(&entry_path) {
             reply.error(libc::ENOTDIR);
@@ -535,8 +725,9 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         // method must return successfully once the transaction is complete.
         self.repo.clean().ok();
 
-        let entry_inode = self.inodes.inode(&entry_path).unwrap();
-        self.inodes.remove(entry_inode);
+        if let Some(entry_inode) = self.inodes.inode(&entry_path) {
+            self.inodes.remove(entry_inode);
+        }
 
         reply.ok();
     }
@@ -549,9 +740,17 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         link: &Path,
         reply: ReplyEntry,
     ) {
-        let file_name = try_option!(name.to_str(), reply, libc::EINVAL);
+        let file_name =
+            self.repo
+                .inner()
+                .normalize_name(try_option!(name.to_str(), reply, libc::EINVAL));
         let parent_path = try_option!(self.inodes.path(parent), reply, libc::ENOENT).to_owned();
-        let entry_path = parent_path.join(file_name);
+        let entry_path = parent_path.join(&*file_name);
+
+        if self.is_read_only(&parent_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
 
         let parent_entry = try_result!(self.repo.entry(&parent_path), reply);
         let entry = Entry::special(UnixSpecialType::SymbolicLink {
@@ -571,7 +770,7 @@ impl<'a> Filesystem for FuseAdapter<'a> {
 
         let generation = self.inodes.generation(attr.ino);
 
-        reply.entry(&DEFAULT_TTL, &attr, generation);
+        reply.entry(&self.entry_ttl, &attr, generation);
     }
 
     fn rename(
@@ -581,23 +780,52 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         name: &OsStr,
         newparent: u64,
         newname: &OsStr,
+        flags: u32,
         reply: ReplyEmpty,
     ) {
-        let source_name = try_option!(name.to_str(), reply, libc::ENOENT);
+        let source_name =
+            self.repo
+                .inner()
+                .normalize_name(try_option!(name.to_str(), reply, libc::ENOENT));
         let source_parent_path =
             try_option!(self.inodes.path(parent), reply, libc::ENOENT).to_owned();
-        let source_path = source_parent_path.join(source_name);
+        let source_path = source_parent_path.join(&*source_name);
 
-        let dest_name = try_option!(newname.to_str(), reply, libc::EINVAL);
+        let dest_name =
+            self.repo
+                .inner()
+                .normalize_name(try_option!(newname.to_str(), reply, libc::EINVAL));
         let dest_parent_path =
             try_option!(self.inodes.path(newparent), reply, libc::ENOENT).to_owned();
-        let dest_path = dest_parent_path.join(dest_name);
+        let dest_path = dest_parent_path.join(&*dest_name);
+
+        if self.is_read_only(&source_parent_path) || self.is_read_only(&dest_parent_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
 
         if !self.repo.exists(&source_path) {
             reply.error(libc::ENOENT);
             return;
         }
 
+        // The `renameat2` flags are only passed on Linux. We support `RENAME_NOREPLACE`, but not
+        // `RENAME_EXCHANGE` or `RENAME_WHITEOUT`.
+        #[cfg(target_os = "linux")]
+        {
+            if flags & !libc::RENAME_NOREPLACE != 0 {
+                reply.error(libc::EINVAL);
+                return;
+            }
+
+            if flags & libc::RENAME_NOREPLACE != 0 && self.repo.exists(&dest_path) {
+                reply.error(libc::EEXIST);
+                return;
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = flags;
+
         // We cannot make a directory a subdirectory of itself.
         if dest_path.starts_with(&source_path) {
             reply.error(libc::EINVAL);
@@ -605,20 +833,22 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         }
 
         // Check if the parent of the destination path is not a directory.
-        if !self.repo.is_directory(&dest_path.parent().unwrap()) {
+        if !self.repo.is_directory(&dest_parent_path) {
             reply.error(libc::ENOTDIR);
             return;
         }
 
         // Commit and close any open file objects associated with the entries in the source tree.
-        let source_inode = self.inodes.inode(&source_path).unwrap();
+        let source_inode = try_option!(self.inodes.inode(&source_path), reply, libc::ENOENT);
         try_result!(self.objects.commit(source_inode), reply);
         self.objects.close(source_inode);
         if let Ok(descendants) = self.repo.walk(&source_path) {
             for source_descendant in descendants {
-                let descendant_inode = self.inodes.inode(&source_descendant).unwrap();
-                try_result!(self.objects.commit(descendant_inode), reply);
-                self.objects.close(descendant_inode);
+                // An entry without an inode can't have an open file object.
+                if let Some(descendant_inode) = self.inodes.inode(&source_descendant) {
+                    try_result!(self.objects.commit(descendant_inode), reply);
+                    self.objects.close(descendant_inode);
+                }
             }
         }
 
@@ -629,8 +859,7 @@ impl<'a> Filesystem for FuseAdapter<'a> {
                     return Err(error);
                 }
 
-                fs.repo.copy_tree(&source_path, &dest_path)?;
-                fs.repo.remove_tree(&source_path).ok();
+                fs.repo.rename(&source_path, &dest_path)?;
 
                 fs.repo.touch_modified(&source_parent_path, req)?;
                 fs.repo.touch_modified(&dest_parent_path, req)
@@ -642,17 +871,18 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         self.inodes.rename(&source_path, dest_path.clone());
         if let Ok(descendants) = self.repo.walk(&dest_path) {
             for dest_descendant in descendants {
-                let relative_descendant = dest_descendant.strip_prefix(&dest_path).unwrap();
-                let source_descendant = source_path.join(relative_descendant);
-                self.inodes.rename(&source_descendant, dest_descendant);
+                if let Ok(relative_descendant) = dest_descendant.strip_prefix(&dest_path) {
+                    let source_descendant = source_path.join(relative_descendant);
+                    self.inodes.rename(&source_descendant, dest_descendant);
+                }
             }
         }
 
         reply.ok();
     }
 
-    fn open(&mut self, _req: &Request, ino: u64, flags: u32, reply: ReplyOpen) {
-        let flags = OFlag::from_bits_truncate(flags as i32);
+    fn open(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        let flags = OFlag::from_bits_truncate(flags);
 
         if flags.intersects(*UNSUPPORTED_OPEN_FLAGS) {
             reply.error(libc::ENOTSUP);
@@ -671,13 +901,32 @@ impl<'a> Filesystem for FuseAdapter<'a> {
             return;
         }
 
+        let is_write =
+            (flags & OFlag::O_ACCMODE) != OFlag::O_RDONLY || flags.contains(OFlag::O_TRUNC);
+        if is_write && self.is_read_only(entry_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+
         let state = HandleState::File(FileHandle { flags, position: 0 });
         let fh = self.handles.open(state);
 
         reply.opened(fh, 0);
     }
 
-    fn read(&mut self, req: &Request, ino: u64, fh: u64, offset: i64, size: u32, reply: ReplyData) {
+    fn read(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let offset = try_option!(u64::try_from(offset).ok(), reply, libc::EINVAL);
+
         // Technically, on Unix systems, a file should still be accessible via its file descriptor
         // once it's been unlinked. Because this isn't how repositories work, we will return `EBADF`
         // if the user tries to read from a file which has been unlinked since it was opened.
@@ -706,12 +955,9 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         let mut total_bytes_read = 0;
 
         {
-            let object = try_result!(
-                self.objects
-                    .open_commit(ino, self.repo.open(&entry_path).unwrap()),
-                reply
-            );
-            try_result!(object.seek(SeekFrom::Start(offset as u64)), reply);
+            let object = try_result!(self.repo.open(&entry_path), reply);
+            let object = try_result!(self.objects.open_commit(ino, object), reply);
+            try_result!(object.seek(SeekFrom::Start(offset)), reply);
 
             // `Filesystem::read` should read the exact number of bytes requested except on EOF or error.
             let mut bytes_read;
@@ -729,10 +975,11 @@ impl<'a> Filesystem for FuseAdapter<'a> {
             }
         }
 
-        state.position = offset as u64 + total_bytes_read as u64;
+        state.position = offset + total_bytes_read as u64;
 
         // Update the file's `st_atime` unless the `O_NOATIME` flag was passed.
-        if !state.flags.contains(OFlag::O_NOATIME) {
+        let noatime = state.flags.contains(OFlag::O_NOATIME);
+        if !noatime && !self.is_read_only(&entry_path) {
             try_result!(self.repo.touch_accessed(&entry_path, req), reply);
         }
 
@@ -746,9 +993,13 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         fh: u64,
         offset: i64,
         data: &[u8],
-        _flags: u32,
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
         reply: ReplyWrite,
     ) {
+        let offset = try_option!(u64::try_from(offset).ok(), reply, libc::EINVAL);
+
         // Technically, on Unix systems, a file should still be accessible via its file descriptor
         // once it's been unlinked. Because this isn't how repositories work, we will return `EBADF`
         // if the user tries to write to a file which has been unlinked since it was opened.
@@ -762,6 +1013,16 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         };
 
         let flags;
+        let end_position = try_option!(offset.checked_add(data.len() as u64), reply, libc::EFBIG);
+        let old_size = try_result!(self.repo.file_size(&entry_path), reply);
+
+        // Check the quotas of the file's ancestors before writing anything if this write would
+        // extend the file.
+        try_result!(
+            self.repo
+                .check_quota(&entry_path, end_position.saturating_sub(old_size)),
+            reply
+        );
 
         {
             let state = match self.handles.state_mut(fh) {
@@ -778,35 +1039,32 @@ impl<'a> Filesystem for FuseAdapter<'a> {
 
             flags = state.flags;
 
-            let object = if offset as u64 == state.position {
+            let object = try_result!(self.repo.open(&entry_path), reply);
+            let object = if offset == state.position {
                 // If the offset is the same as the previous offset, we don't need to seek and
                 // therefore don't need to commit changes to the object.
-                self.objects.open(ino, self.repo.open(&entry_path).unwrap())
+                self.objects.open(ino, object)
             } else {
                 // If the offset is not the same as the previous offset, we need to seek, which
                 // requires committing changes first.
-                let object = try_result!(
-                    self.objects
-                        .open_commit(ino, self.repo.open(&entry_path).unwrap()),
-                    reply
-                );
+                let object = try_result!(self.objects.open_commit(ino, object), reply);
 
-                let object_size = object.size().unwrap();
+                let object_size = try_result!(object.size(), reply);
 
                 // If the offset is past the end of the file, we need to extend it. It's not
                 // possible to seek past the end of an object.
-                if offset as u64 > object_size {
-                    try_result!(object.set_len(offset as u64), reply);
+                if offset > object_size {
+                    try_result!(object.set_len(offset), reply);
                 }
 
-                try_result!(object.seek(SeekFrom::Start(offset as u64)), reply);
+                try_result!(object.seek(SeekFrom::Start(offset)), reply);
 
                 object
             };
 
             try_result!(object.write_all(data), reply);
 
-            state.position = offset as u64 + data.len() as u64;
+            state.position = end_position;
         }
 
         // After this point, we need to be more careful about error handling. Because bytes have
@@ -821,6 +1079,11 @@ impl<'a> Filesystem for FuseAdapter<'a> {
             return;
         }
 
+        // Update the size of the file if this write extended it.
+        if end_position > old_size {
+            self.repo.inner_mut().record_size(&entry_path, end_position);
+        }
+
         // If the `O_SYNC` or `O_DSYNC` flags were passed, we need to commit changes to the object
         // *and* commit changes to the repository after each write.
         if flags.intersects(OFlag::O_SYNC | OFlag::O_DSYNC) {
@@ -840,7 +1103,9 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         reply.written(data.len() as u32);
     }
 
-    fn flush(&mut self, _req: &Request, ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+    fn flush(&mut self, _req: &Request, ino: u64, _fh: u64, lock_owner: u64, reply: ReplyEmpty) {
+        // POSIX locks are released when any file descriptor for the file is closed.
+        self.locks.release(ino, lock_owner);
         try_result!(self.objects.commit(ino), reply);
         reply.ok()
     }
@@ -850,11 +1115,15 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         _req: &Request,
         ino: u64,
         fh: u64,
-        _flags: u32,
-        _lock_owner: u64,
+        _flags: i32,
+        lock_owner: Option<u64>,
         _flush: bool,
         reply: ReplyEmpty,
     ) {
+        // BSD locks are released when the last file descriptor for the open file is closed.
+        if let Some(lock_owner) = lock_owner {
+            self.locks.release(ino, lock_owner);
+        }
         self.handles.close(fh);
         self.objects.close(ino);
         reply.ok()
@@ -866,7 +1135,7 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         reply.ok();
     }
 
-    fn opendir(&mut self, _req: &Request, ino: u64, _flags: u32, reply: ReplyOpen) {
+    fn opendir(&mut self, _req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
         let entry_path = try_option!(self.inodes.path(ino), reply, libc::ENOENT);
 
         if !self.repo.is_directory(entry_path) {
@@ -874,11 +1143,20 @@ impl<'a> Filesystem for FuseAdapter<'a> {
             return;
         }
 
+        // The `.snapshots` directory hides any entry in the root with the same name.
+        let has_snapshots = ino == FUSE_ROOT_ID && self.snapshots.is_some();
+
+        // Listing the children keeps the repository locked, so this reads each entry through the
+        // same lock.
+        let repo = self.repo.inner();
         let mut entries = Vec::new();
-        for child_path in try_result!(self.repo.list(entry_path), reply) {
-            let file_name = child_path.file_name().unwrap().to_string();
-            let inode = self.inodes.inode(&child_path).unwrap();
-            let file_type = try_result!(self.repo.entry(&child_path), reply)
+        for child_path in try_result!(repo.list(entry_path), reply) {
+            let file_name = try_option!(child_path.file_name(), reply, libc::EIO).to_string();
+            if has_snapshots && file_name == SNAPSHOTS_DIR_NAME {
+                continue;
+            }
+            let inode = try_option!(self.inodes.inode(&child_path), reply, libc::ENOENT);
+            let file_type = try_result!(repo.entry(&child_path), reply)
                 .file_type
                 .to_file_type();
             entries.push(DirectoryEntry {
@@ -888,6 +1166,14 @@ impl<'a> Filesystem for FuseAdapter<'a> {
             })
         }
 
+        if let Some(snapshots) = self.snapshots.as_ref().filter(|_| has_snapshots) {
+            entries.push(DirectoryEntry {
+                file_name: SNAPSHOTS_DIR_NAME.to_string(),
+                file_type: FuseFileType::Directory,
+                inode: try_option!(self.inodes.inode(snapshots), reply, libc::ENOENT),
+            });
+        }
+
         let state = HandleState::Directory(DirectoryHandle { entries });
         let fh = self.handles.open(state);
 
@@ -904,10 +1190,12 @@ impl<'a> Filesystem for FuseAdapter<'a> {
     ) {
         let directory_path = try_option!(self.inodes.path(ino), reply, libc::ENOENT).to_owned();
 
-        try_result!(
-            self.transaction(|fs| fs.repo.touch_accessed(&directory_path, req)),
-            reply
-        );
+        if !self.is_read_only(&directory_path) {
+            try_result!(
+                self.transaction(|fs| fs.repo.touch_accessed(&directory_path, req)),
+                reply
+            );
+        }
 
         let entries = match self.handles.state(fh) {
             None => {
@@ -921,7 +1209,8 @@ impl<'a> Filesystem for FuseAdapter<'a> {
             Some(HandleState::Directory(DirectoryHandle { entries })) => entries,
         };
 
-        for (i, dir_entry) in entries[offset as usize..].iter().enumerate() {
+        // The offset may be past the end of the entries if it wasn't returned by this method.
+        for (i, dir_entry) in entries.iter().enumerate().skip(offset as usize) {
             if reply.add(
                 dir_entry.inode,
                 (i + 1) as i64,
@@ -935,7 +1224,7 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         reply.ok();
     }
 
-    fn releasedir(&mut self, _req: &Request, _ino: u64, fh: u64, _flags: u32, reply: ReplyEmpty) {
+    fn releasedir(&mut self, _req: &Request, _ino: u64, fh: u64, _flags: i32, reply: ReplyEmpty) {
         self.handles.close(fh);
         reply.ok()
     }
@@ -952,19 +1241,47 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         reply.ok();
     }
 
+    fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
+        // Changes to open files must be committed before the repository stats can be calculated.
+        try_result!(self.objects.commit_all(), reply);
+        let stats = try_result!(self.repo.tree_stats(&*EMPTY_PATH), reply);
+
+        // Data which is shared between files is only counted once toward the used space.
+        let used_blocks = stats.stored_size.div_ceil(BLOCK_SIZE);
+        let free_blocks = STATFS_FREE_BYTES / BLOCK_SIZE;
+        let used_files = stats.files + stats.directories + stats.special;
+
+        reply.statfs(
+            used_blocks + free_blocks,
+            free_blocks,
+            free_blocks,
+            used_files + STATFS_FREE_FILES,
+            STATFS_FREE_FILES,
+            BLOCK_SIZE as u32,
+            MAX_NAME_LEN,
+            BLOCK_SIZE as u32,
+        );
+    }
+
     fn setxattr(
         &mut self,
         req: &Request,
         ino: u64,
         name: &OsStr,
         value: &[u8],
-        flags: u32,
+        flags: i32,
         _position: u32,
         reply: ReplyEmpty,
     ) {
         let attr_name = try_option!(name.to_str(), reply, libc::EINVAL).to_owned();
 
         let entry_path = try_option!(self.inodes.path(ino), reply, libc::ENOENT).to_owned();
+
+        if self.is_read_only(&entry_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+
         let mut metadata =
             try_result!(self.repo.entry(&entry_path), reply).metadata_or_default(req);
 
@@ -972,7 +1289,7 @@ impl<'a> Filesystem for FuseAdapter<'a> {
             metadata
                 .attributes
                 .insert(attr_name.clone(), value.to_vec());
-        } else if flags == libc::XATTR_CREATE as u32 {
+        } else if flags == libc::XATTR_CREATE {
             match metadata.attributes.entry(attr_name.clone()) {
                 HashMapEntry::Occupied(_) => {
                     reply.error(libc::EEXIST);
@@ -982,7 +1299,7 @@ impl<'a> Filesystem for FuseAdapter<'a> {
                     entry.insert(value.to_vec());
                 }
             }
-        } else if flags == libc::XATTR_REPLACE as u32 {
+        } else if flags == libc::XATTR_REPLACE {
             match metadata.attributes.entry(attr_name.clone()) {
                 HashMapEntry::Occupied(mut entry) => {
                     entry.insert(value.to_vec());
@@ -1071,9 +1388,27 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         let entry_path = try_option!(self.inodes.path(ino), reply, libc::ENOENT);
         let metadata = try_result!(self.repo.entry(&entry_path), reply).metadata_or_default(req);
 
+        // The ACL xattrs are generated from the ACL entries in the entry metadata, so they should
+        // be listed if and only if there are ACL entries, whether or not they are in the xattrs.
+        let acl_names = [
+            (ACCESS_ACL_XATTR, !metadata.acl.access.is_empty()),
+            (DEFAULT_ACL_XATTR, !metadata.acl.default.is_empty()),
+        ];
+        let listed_names = metadata
+            .attributes
+            .keys()
+            .map(String::as_str)
+            .filter(|attr_name| !matches!(*attr_name, ACCESS_ACL_XATTR | DEFAULT_ACL_XATTR))
+            .chain(
+                acl_names
+                    .iter()
+                    .filter(|(_, is_set)| *is_set)
+                    .map(|(attr_name, _)| *attr_name),
+            );
+
         // Construct a byte string of null-terminated attribute names.
         let mut attr_names = Vec::new();
-        for attr_name in metadata.attributes.keys() {
+        for attr_name in listed_names {
             attr_names.extend_from_slice(attr_name.as_bytes());
             attr_names.push(0u8);
         }
@@ -1095,9 +1430,25 @@ impl<'a> Filesystem for FuseAdapter<'a> {
         let attr_name = try_option!(name.to_str(), reply, libc::ENODATA).to_owned();
 
         let entry_path = try_option!(self.inodes.path(ino), reply, libc::ENOENT).to_owned();
+
+        if self.is_read_only(&entry_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+
         let mut metadata =
             try_result!(self.repo.entry(&entry_path), reply).metadata_or_default(req);
 
+        let is_set = match attr_name.as_str() {
+            ACCESS_ACL_XATTR => !metadata.acl.access.is_empty(),
+            DEFAULT_ACL_XATTR => !metadata.acl.default.is_empty(),
+            _ => metadata.attributes.contains_key(&attr_name),
+        };
+        if !is_set {
+            reply.error(libc::ENODATA);
+            return;
+        }
+
         metadata.attributes.remove(&attr_name);
 
         // Synchronize the ACL entries stored in the xattrs with the entry metadata.
@@ -1121,4 +1472,368 @@ impl<'a> Filesystem for FuseAdapter<'a> {
 
         reply.ok();
     }
+
+    fn getlk(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        reply: ReplyLock,
+    ) {
+        let lock = FileLock {
+            owner: lock_owner,
+            start,
+            end,
+            lock_type: typ,
+            pid,
+        };
+        match self.locks.conflict(ino, &lock) {
+            Some(conflict) => reply.locked(
+                conflict.start,
+                conflict.end,
+                conflict.lock_type,
+                conflict.pid,
+            ),
+            None => reply.locked(start, end, libc::F_UNLCK, 0),
+        }
+    }
+
+    fn setlk(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        sleep: bool,
+        reply: ReplyEmpty,
+    ) {
+        if typ != libc::F_RDLCK && typ != libc::F_WRLCK && typ != libc::F_UNLCK {
+            reply.error(libc::EINVAL);
+            return;
+        }
+
+        let lock = FileLock {
+            owner: lock_owner,
+            start,
+            end,
+            lock_type: typ,
+            pid,
+        };
+
+        // If the lock is blocked, the reply is sent once the conflicting lock is released.
+        if sleep {
+            self.locks.wait(ino, lock, reply);
+        } else if self.locks.set(ino, lock) {
+            reply.ok();
+        } else {
+            reply.error(libc::EAGAIN);
+        }
+    }
+
+    fn ioctl(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        _flags: u32,
+        cmd: u32,
+        in_data: &[u8],
+        _out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        match cmd {
+            COMMIT_IOCTL => {
+                try_result!(self.objects.commit_all(), reply);
+                try_result!(self.repo.commit(), reply);
+                reply.ioctl(0, &[]);
+            }
+            SNAPSHOT_IOCTL => {
+                let name_len = in_data
+                    .iter()
+                    .position(|&byte| byte == 0)
+                    .unwrap_or(in_data.len());
+                let name = try_option!(
+                    std::str::from_utf8(&in_data[..name_len]).ok(),
+                    reply,
+                    libc::EINVAL
+                );
+                if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+                    reply.error(libc::EINVAL);
+                    return;
+                }
+
+                if self.read_only {
+                    reply.error(libc::EROFS);
+                    return;
+                }
+
+                let snapshots = try_option!(self.snapshots.as_ref(), reply, libc::ENOTSUP);
+                let source =
+                    try_option!(self.inodes.path(FUSE_ROOT_ID), reply, libc::ENOENT).to_owned();
+                let dest = snapshots.join(&*self.repo.inner().normalize_name(name));
+
+                try_result!(
+                    self.transaction(|fs| {
+                        fs.repo.copy_tree(&source, &dest)?;
+                        let paths = fs.repo.walk(&dest)?.collect::<Vec<_>>();
+                        fs.insert_inode(dest)?;
+                        for path in paths {
+                            fs.insert_inode(path)?;
+                        }
+                        Ok(())
+                    }),
+                    reply
+                );
+
+                reply.ioctl(0, &[]);
+            }
+            STATS_IOCTL => {
+                let entry_path = try_option!(self.inodes.path(ino), reply, libc::ENOENT).to_owned();
+
+                // Changes to open files must be committed before the stats can be calculated.
+                try_result!(self.objects.commit_all(), reply);
+                let stats = try_result!(self.repo.tree_stats(&entry_path), reply);
+
+                let output = encode_stats(&stats)
+                    .iter()
+                    .flat_map(|value| value.to_ne_bytes())
+                    .collect::<Vec<_>>();
+                reply.ioctl(0, &output);
+            }
+            _ => reply.error(libc::ENOTTY),
+        }
+    }
+
+    fn copy_file_range(
+        &mut self,
+        req: &Request,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        flags: u32,
+        reply: ReplyWrite,
+    ) {
+        let offset_in = try_option!(u64::try_from(offset_in).ok(), reply, libc::EINVAL);
+        let offset_out = try_option!(u64::try_from(offset_out).ok(), reply, libc::EINVAL);
+
+        // No flags are currently defined for `copy_file_range`.
+        if flags != 0 {
+            reply.error(libc::EINVAL);
+            return;
+        }
+
+        let source_path = try_option!(self.inodes.path(ino_in), reply, libc::EBADF).to_owned();
+        let dest_path = try_option!(self.inodes.path(ino_out), reply, libc::EBADF).to_owned();
+
+        if self.is_read_only(&dest_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+
+        for fh in [fh_in, fh_out].iter() {
+            match self.handles.state(*fh) {
+                None => {
+                    reply.error(libc::EBADF);
+                    return;
+                }
+                Some(HandleState::Directory(_)) => {
+                    reply.error(libc::EISDIR);
+                    return;
+                }
+                Some(HandleState::File(_)) => (),
+            }
+        }
+
+        // We can only report up to `u32::MAX` bytes as written.
+        let len = len.min(u32::MAX as u64);
+
+        // This shares chunks between the files when the ranges are chunk-aligned and falls back to
+        // copying the bytes otherwise.
+        let bytes_copied = try_result!(
+            self.transaction(|fs| {
+                let bytes_copied =
+                    fs.repo
+                        .copy_range(&source_path, offset_in, &dest_path, offset_out, len)?;
+                if bytes_copied > 0 {
+                    fs.repo.touch_modified(&dest_path, req)?;
+                }
+                Ok(bytes_copied)
+            }),
+            reply
+        );
+
+        reply.written(bytes_copied as u32);
+    }
+
+    #[cfg(target_os = "linux")]
+    fn fallocate(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        let request = match AllocateRequest::new(offset, length, mode) {
+            Ok(request) => request,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+
+        let entry_path = try_option!(self.inodes.path(ino), reply, libc::EBADF).to_owned();
+
+        if self.is_read_only(&entry_path) {
+            reply.error(libc::EROFS);
+            return;
+        }
+
+        match self.handles.state(fh) {
+            None => {
+                reply.error(libc::EBADF);
+                return;
+            }
+            Some(HandleState::Directory(_)) => {
+                reply.error(libc::EISDIR);
+                return;
+            }
+            Some(HandleState::File(_)) => (),
+        }
+
+        let old_size = try_result!(self.repo.file_size(&entry_path), reply);
+        let allocation = request.plan(old_size);
+
+        if allocation.is_noop() {
+            reply.ok();
+            return;
+        }
+
+        try_result!(
+            self.transaction(|fs| {
+                let object = fs.objects.open_commit(ino, fs.repo.open(&entry_path)?)?;
+
+                if let Some((offset, length)) = allocation.hole {
+                    object.punch_hole(offset, length)?;
+                }
+
+                if allocation.growth() > 0 {
+                    fs.repo.check_quota(&entry_path, allocation.growth())?;
+                    object.set_len(allocation.new_size)?;
+                    fs.repo
+                        .inner_mut()
+                        .record_size(&entry_path, allocation.new_size);
+                }
+
+                fs.repo.touch_modified(&entry_path, req)
+            }),
+            reply
+        );
+
+        if allocation.hole.is_some() {
+            // Attempt to clean the repository to free the space used by the data which was
+            // replaced. We ignore any errors because this method must return successfully once the
+            // transaction is complete.
+            self.repo.clean().ok();
+        }
+
+        reply.ok();
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    fn lseek(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: ReplyLseek,
+    ) {
+        // The kernel handles `SEEK_SET`, `SEEK_CUR`, and `SEEK_END` itself.
+        if whence != libc::SEEK_DATA && whence != libc::SEEK_HOLE {
+            reply.error(libc::EINVAL);
+            return;
+        }
+
+        let entry_path = try_option!(self.inodes.path(ino), reply, libc::EBADF).to_owned();
+
+        match self.handles.state(fh) {
+            None => {
+                reply.error(libc::EBADF);
+                return;
+            }
+            Some(HandleState::Directory(_)) => {
+                reply.error(libc::EISDIR);
+                return;
+            }
+            Some(HandleState::File(_)) => (),
+        }
+
+        // The object needs to be committed so that its content ID reflects any writes.
+        let object = try_result!(self.repo.open(&entry_path), reply);
+        let object = try_result!(self.objects.open_commit(ino, object), reply);
+        let size = try_result!(object.size(), reply);
+        let holes = try_result!(object.content_id(), reply).holes();
+
+        let position = if whence == libc::SEEK_DATA {
+            find_data(&holes, size, offset as u64)
+        } else {
+            find_hole(&holes, size, offset as u64)
+        };
+
+        match position {
+            Some(position) => reply.offset(position as i64),
+            None => reply.error(libc::ENXIO),
+        }
+    }
+}
+
+/// Return the offset of the first byte of data at or after `offset` in a file.
+///
+/// This accepts the `holes` in the file, which must be sorted and non-overlapping, and the `size`
+/// of the file. This returns `None` if there is no data at or after `offset`.
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+fn find_data(holes: &[std::ops::Range<u64>], size: u64, offset: u64) -> Option<u64> {
+    let position = match holes.iter().find(|hole| hole.contains(&offset)) {
+        Some(hole) => hole.end,
+        None => offset,
+    };
+    if position < size {
+        Some(position)
+    } else {
+        None
+    }
+}
+
+/// Return the offset of the first hole at or after `offset` in a file.
+///
+/// This accepts the `holes` in the file, which must be sorted and non-overlapping, and the `size`
+/// of the file. There is an implicit hole at the end of every file, so this only returns `None` if
+/// `offset` is past the end of the file.
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+fn find_hole(holes: &[std::ops::Range<u64>], size: u64, offset: u64) -> Option<u64> {
+    if offset >= size {
+        return None;
+    }
+    let position = holes
+        .iter()
+        .find(|hole| hole.end > offset)
+        .map_or(size, |hole| hole.start.max(offset));
+    Some(position)
 }
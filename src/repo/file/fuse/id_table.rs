@@ -80,4 +80,21 @@ impl IdTable {
         self.unused.insert(id);
         true
     }
+
+    /// Mark the given `id` as used.
+    ///
+    /// This returns `true` if the value was marked as used or `false` if it was already used or
+    /// reserved. If `id` is above the high water mark, the values between the high water mark and
+    /// `id` are never allocated.
+    pub fn insert(&mut self, id: u64) -> bool {
+        if self.contains(id) || self.reserved.contains(&id) {
+            return false;
+        }
+        if id > self.highest {
+            self.highest = id;
+        } else {
+            self.unused.remove(&id);
+        }
+        true
+    }
 }
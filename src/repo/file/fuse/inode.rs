@@ -17,7 +17,7 @@
 use std::collections::HashMap;
 
 use bimap::BiMap;
-use fuse::FUSE_ROOT_ID;
+use fuser::FUSE_ROOT_ID;
 use relative_path::{RelativePath, RelativePathBuf};
 
 use super::id_table::IdTable;
@@ -70,6 +70,19 @@ impl InodeTable {
         inode
     }
 
+    /// Insert the given `path` into the table with an `inode` and `generation` it was previously
+    /// assigned.
+    ///
+    /// This returns `false` without changing the table if the `inode` is already in use.
+    pub fn insert_existing(&mut self, path: RelativePathBuf, inode: u64, generation: u64) -> bool {
+        if !self.id_table.insert(inode) {
+            return false;
+        }
+        self.paths.insert(inode, path);
+        self.generations.insert(inode, generation);
+        true
+    }
+
     /// Remove the given `inode` from the table.
     ///
     /// This returns the path associated with the `inode` or `None` if the given `inode` is not in
@@ -80,7 +93,7 @@ impl InodeTable {
         }
         let generation = self.generations.entry(inode).or_default();
         *generation += 1;
-        Some(self.paths.remove_by_left(&inode).unwrap().1)
+        self.paths.remove_by_left(&inode).map(|(_, path)| path)
     }
 
     /// Change the path for the inode at `source` to `dest`.
@@ -0,0 +1,248 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+
+use fuser::ReplyEmpty;
+use nix::libc;
+
+/// An advisory lock on a range of bytes in a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileLock {
+    /// The owner of the lock, as assigned by the kernel.
+    pub owner: u64,
+
+    /// The offset of the first byte in the locked range.
+    pub start: u64,
+
+    /// The offset of the last byte in the locked range, inclusive.
+    pub end: u64,
+
+    /// The type of lock, which is one of `F_RDLCK`, `F_WRLCK`, or `F_UNLCK`.
+    pub lock_type: i32,
+
+    /// The ID of the process which holds the lock.
+    pub pid: u32,
+}
+
+impl FileLock {
+    /// Return whether this lock covers any of the bytes in the range `start..=end`.
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start <= end && start <= self.end
+    }
+
+    /// Return whether this lock prevents `other` from being acquired.
+    fn conflicts_with(&self, other: &FileLock) -> bool {
+        self.owner != other.owner
+            && self.overlaps(other.start, other.end)
+            && (self.lock_type == libc::F_WRLCK || other.lock_type == libc::F_WRLCK)
+    }
+}
+
+/// A request for a lock which is waiting for a conflicting lock to be released.
+#[derive(Debug)]
+struct PendingLock {
+    inode: u64,
+    lock: FileLock,
+    reply: ReplyEmpty,
+}
+
+/// A table of the advisory locks held on files in a virtual file system.
+///
+/// This implements the semantics of POSIX record locks. Each lock owner holds at most one lock on
+/// any given byte, and acquiring a lock on a range which the owner already holds replaces the
+/// existing lock on that range.
+#[derive(Debug, Default)]
+pub struct LockTable {
+    /// A map of inodes to the locks held on them.
+    locks: HashMap<u64, Vec<FileLock>>,
+
+    /// Requests for locks which are blocked by a conflicting lock, in the order they were made.
+    pending: Vec<PendingLock>,
+}
+
+impl LockTable {
+    /// Return a new empty `LockTable`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the first lock on the file at `inode` which prevents `lock` from being acquired.
+    pub fn conflict(&self, inode: u64, lock: &FileLock) -> Option<FileLock> {
+        self.locks
+            .get(&inode)?
+            .iter()
+            .find(|existing| existing.conflicts_with(lock))
+            .copied()
+    }
+
+    /// Acquire or release `lock` on the file at `inode`.
+    ///
+    /// If `lock` is an `F_UNLCK` lock, this releases any locks its owner holds in its range. This
+    /// returns `false` without changing anything if a conflicting lock is held.
+    pub fn set(&mut self, inode: u64, lock: FileLock) -> bool {
+        let acquired = self.try_set(inode, lock);
+        if acquired {
+            self.wake();
+        }
+        acquired
+    }
+
+    /// Acquire `lock` on the file at `inode`, replying to `reply` once it has been acquired.
+    ///
+    /// If a conflicting lock is held, the request waits until it is released.
+    pub fn wait(&mut self, inode: u64, lock: FileLock, reply: ReplyEmpty) {
+        if self.set(inode, lock) {
+            reply.ok();
+        } else {
+            self.pending.push(PendingLock { inode, lock, reply });
+        }
+    }
+
+    /// Release all locks held by `owner` on the file at `inode`.
+    ///
+    /// Any requests from `owner` which are still waiting for a lock fail with `EINTR`.
+    pub fn release(&mut self, inode: u64, owner: u64) {
+        let (cancelled, pending) = self.pending.drain(..).partition::<Vec<_>, _>(|pending| {
+            pending.inode == inode && pending.lock.owner == owner
+        });
+        self.pending = pending;
+        for pending in cancelled {
+            pending.reply.error(libc::EINTR);
+        }
+
+        self.set(
+            inode,
+            FileLock {
+                owner,
+                start: 0,
+                end: u64::MAX,
+                lock_type: libc::F_UNLCK,
+                pid: 0,
+            },
+        );
+    }
+
+    /// Acquire or release `lock` without granting any waiting requests.
+    fn try_set(&mut self, inode: u64, lock: FileLock) -> bool {
+        if lock.lock_type != libc::F_UNLCK && self.conflict(inode, &lock).is_some() {
+            return false;
+        }
+
+        let locks = self.locks.entry(inode).or_default();
+        Self::unlock_range(locks, lock.owner, lock.start, lock.end);
+        if lock.lock_type != libc::F_UNLCK {
+            locks.push(lock);
+        }
+        if locks.is_empty() {
+            self.locks.remove(&inode);
+        }
+
+        true
+    }
+
+    /// Grant any waiting requests for locks which no longer conflict with a held lock.
+    fn wake(&mut self) {
+        // Granting a lock can downgrade a write lock to a read lock, which can unblock other
+        // requests, so keep going until no more requests can be granted.
+        while let Some(index) = self
+            .pending
+            .iter()
+            .position(|pending| self.conflict(pending.inode, &pending.lock).is_none())
+        {
+            let PendingLock { inode, lock, reply } = self.pending.remove(index);
+            self.try_set(inode, lock);
+            reply.ok();
+        }
+    }
+
+    /// Release the locks `owner` holds in `locks` in the range `start..=end`.
+    ///
+    /// Locks which only partially overlap the range are split.
+    fn unlock_range(locks: &mut Vec<FileLock>, owner: u64, start: u64, end: u64) {
+        let mut remaining = Vec::with_capacity(locks.len());
+        for lock in locks.drain(..) {
+            if lock.owner != owner || !lock.overlaps(start, end) {
+                remaining.push(lock);
+                continue;
+            }
+            if lock.start < start {
+                remaining.push(FileLock {
+                    end: start - 1,
+                    ..lock
+                });
+            }
+            if lock.end > end {
+                remaining.push(FileLock {
+                    start: end + 1,
+                    ..lock
+                });
+            }
+        }
+        *locks = remaining;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::libc;
+
+    use super::{FileLock, LockTable};
+
+    fn lock(owner: u64, start: u64, end: u64, lock_type: i32) -> FileLock {
+        FileLock {
+            owner,
+            start,
+            end,
+            lock_type,
+            pid: 0,
+        }
+    }
+
+    #[test]
+    fn read_locks_are_shared() {
+        let mut table = LockTable::new();
+        assert!(table.set(1, lock(1, 0, 9, libc::F_RDLCK)));
+        assert!(table.set(1, lock(2, 5, 14, libc::F_RDLCK)));
+        assert!(!table.set(1, lock(3, 9, 9, libc::F_WRLCK)));
+        assert!(table.set(1, lock(3, 15, 20, libc::F_WRLCK)));
+    }
+
+    #[test]
+    fn unlocking_part_of_a_range_splits_the_lock() {
+        let mut table = LockTable::new();
+        assert!(table.set(1, lock(1, 0, 9, libc::F_WRLCK)));
+        assert!(table.set(1, lock(1, 3, 5, libc::F_UNLCK)));
+
+        assert_eq!(table.conflict(1, &lock(2, 3, 5, libc::F_WRLCK)), None);
+        assert_eq!(
+            table.conflict(1, &lock(2, 0, 3, libc::F_RDLCK)),
+            Some(lock(1, 0, 2, libc::F_WRLCK))
+        );
+        assert_eq!(
+            table.conflict(1, &lock(2, 5, 9, libc::F_RDLCK)),
+            Some(lock(1, 6, 9, libc::F_WRLCK))
+        );
+    }
+
+    #[test]
+    fn releasing_an_owner_removes_its_locks() {
+        let mut table = LockTable::new();
+        assert!(table.set(1, lock(1, 0, u64::MAX, libc::F_WRLCK)));
+        table.release(1, 1);
+        assert!(table.set(1, lock(2, 0, u64::MAX, libc::F_WRLCK)));
+    }
+}
@@ -17,12 +17,11 @@
 use std::collections::hash_map::Entry as HashMapEntry;
 use std::collections::HashMap;
 use std::io;
-use std::time::{Duration, SystemTime};
+use std::time::SystemTime;
 
-use fuse::{FileType as FuseFileType, Request};
+use fuser::{FileType as FuseFileType, Request, TimeOrNow};
 use nix::libc;
 use relative_path::RelativePath;
-use time::Timespec;
 
 use crate::repo::file::{
     AccessMode, AccessQualifier, Acl, AclType, Entry, FileRepo, FileType, UnixMetadata,
@@ -45,6 +44,10 @@ impl crate::Error {
             crate::Error::NotEmpty => libc::ENOTEMPTY,
             crate::Error::NotDirectory => libc::ENOTDIR,
             crate::Error::NotFile => libc::EISDIR,
+            crate::Error::QuotaExceeded => libc::EDQUOT,
+            crate::Error::StoreFull(_) => libc::ENOSPC,
+            crate::Error::PermissionDenied(_) => libc::EACCES,
+            crate::Error::Unavailable(_) => libc::EAGAIN,
             crate::Error::Io(error) => match error.raw_os_error() {
                 Some(errno) => errno,
                 // Some third-party libraries use `std::io::Error` without there being an underlying
@@ -88,27 +91,11 @@ pub fn other_perm(mode: u32) -> u32 {
     mode & 0o007
 }
 
-/// Convert the given `time` to a `SystemTime`.
-pub fn to_system_time(time: Timespec) -> SystemTime {
-    let duration = Duration::new(time.sec.abs() as u64, time.nsec.abs() as u32);
-    if time.sec.is_positive() {
-        SystemTime::UNIX_EPOCH + duration
-    } else {
-        SystemTime::UNIX_EPOCH - duration
-    }
-}
-
-/// Convert the given `time` to a `Timespec`.
-pub fn to_timespec(time: SystemTime) -> Timespec {
-    match time.duration_since(SystemTime::UNIX_EPOCH) {
-        Ok(duration) => Timespec {
-            sec: duration.as_secs() as i64,
-            nsec: duration.subsec_nanos() as i32,
-        },
-        Err(error) => Timespec {
-            sec: -(error.duration().as_secs() as i64),
-            nsec: -(error.duration().subsec_nanos() as i32),
-        },
+/// Convert the given `time` to a `SystemTime`, using `now` if it is `TimeOrNow::Now`.
+pub fn to_system_time(time: TimeOrNow, now: SystemTime) -> SystemTime {
+    match time {
+        TimeOrNow::SpecificTime(time) => time,
+        TimeOrNow::Now => now,
     }
 }
 
@@ -118,16 +105,16 @@ pub fn to_timespec(time: SystemTime) -> Timespec {
 /// do not exceed the permissions granted by the given `mode`.
 fn constrain_acl(acl: &mut HashMap<AccessQualifier, AccessMode>, mode: u32) {
     if let Some(acl_mode) = acl.get_mut(&AccessQualifier::UserObj) {
-        *acl_mode = AccessMode::from_bits(acl_mode.bits() & user_perm(mode)).unwrap();
+        *acl_mode = AccessMode::from_bits_truncate(acl_mode.bits() & user_perm(mode));
     }
     if let Some(acl_mode) = match acl.get_mut(&AccessQualifier::Mask) {
         Some(acl_mode) => Some(acl_mode),
         None => acl.get_mut(&AccessQualifier::GroupObj),
     } {
-        *acl_mode = AccessMode::from_bits(acl_mode.bits() & group_perm(mode)).unwrap();
+        *acl_mode = AccessMode::from_bits_truncate(acl_mode.bits() & group_perm(mode));
     }
     if let Some(acl_mode) = acl.get_mut(&AccessQualifier::Other) {
-        *acl_mode = AccessMode::from_bits(acl_mode.bits() & other_perm(mode)).unwrap();
+        *acl_mode = AccessMode::from_bits_truncate(acl_mode.bits() & other_perm(mode));
     }
 }
 
@@ -180,7 +167,7 @@ impl UnixMetadata {
         // permissions to set the mask.
         if let HashMapEntry::Occupied(mut mode_entry) = self.acl.access.entry(AccessQualifier::Mask)
         {
-            let group_mode = AccessMode::from_bits(group_perm(self.mode)).unwrap();
+            let group_mode = AccessMode::from_bits_truncate(group_perm(self.mode));
             mode_entry.insert(group_mode);
         }
     }
@@ -236,6 +223,7 @@ impl Entry<UnixSpecialType, UnixMetadata> {
             group: req.gid(),
             attributes: HashMap::new(),
             acl: Acl::new(),
+            created: Some(now),
         }
     }
 
@@ -249,7 +237,7 @@ impl Entry<UnixSpecialType, UnixMetadata> {
 }
 
 impl FileType<UnixSpecialType> {
-    /// Convert this `FileType` to a `fuse`-compatible file type.
+    /// Convert this `FileType` to a `fuser`-compatible file type.
     pub(super) fn to_file_type(&self) -> FuseFileType {
         match self {
             FileType::File => FuseFileType::RegularFile,
@@ -258,17 +246,14 @@ impl FileType<UnixSpecialType> {
             FileType::Special(UnixSpecialType::CharacterDevice { .. }) => FuseFileType::CharDevice,
             FileType::Special(UnixSpecialType::SymbolicLink { .. }) => FuseFileType::Symlink,
             FileType::Special(UnixSpecialType::NamedPipe { .. }) => FuseFileType::NamedPipe,
+            FileType::Special(UnixSpecialType::Socket) => FuseFileType::Socket,
         }
     }
 }
 
 impl FileRepo<UnixSpecialType, UnixMetadata> {
     /// Update an entry's `mtime`, `atime`, and `ctime`.
-    pub(super) fn touch_modified(
-        &mut self,
-        path: &RelativePath,
-        req: &Request,
-    ) -> crate::Result<()> {
+    pub(super) fn touch_modified(&self, path: &RelativePath, req: &Request) -> crate::Result<()> {
         let mut metadata = self.entry(path)?.metadata_or_default(req);
         let now = SystemTime::now();
         metadata.modified = now;
@@ -278,11 +263,7 @@ impl FileRepo<UnixSpecialType, UnixMetadata> {
     }
 
     /// Update an entry's `atime` and `ctime`.
-    pub(super) fn touch_accessed(
-        &mut self,
-        path: &RelativePath,
-        req: &Request,
-    ) -> crate::Result<()> {
+    pub(super) fn touch_accessed(&self, path: &RelativePath, req: &Request) -> crate::Result<()> {
         let mut metadata = self.entry(path)?.metadata_or_default(req);
         let now = SystemTime::now();
         metadata.accessed = now;
@@ -16,12 +16,17 @@
 
 #![cfg(all(any(unix, doc), feature = "fuse-mount"))]
 
+pub use self::control::MountControl;
 pub use self::fs::FuseAdapter;
+pub use self::options::MountOptions;
 
 mod acl;
+mod control;
 mod fs;
 mod handle;
 mod id_table;
 mod inode;
+mod lock;
 mod metadata;
 mod object;
+mod options;
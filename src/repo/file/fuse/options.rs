@@ -0,0 +1,199 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::time::Duration;
+
+use fuser::MountOption;
+use relative_path::{RelativePath, RelativePathBuf};
+
+/// The name of the mounted file system when none is specified.
+const DEFAULT_FSNAME: &str = "acid-store";
+
+/// The default TTL for attributes and entries in FUSE replies.
+///
+/// Because the backing `FileRepo` can only be safely modified through the FUSE file system, we can
+/// set this to an arbitrarily large value.
+const DEFAULT_TTL: Duration = Duration::from_secs(i64::MAX as u64);
+
+/// Options for mounting a [`FileRepo`] as a FUSE file system.
+///
+/// The `default_permissions` mount option is always used so that the kernel enforces file
+/// permissions.
+///
+/// [`FileRepo`]: crate::repo::file::FileRepo
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountOptions {
+    allow_other: bool,
+    allow_root: bool,
+    pub(super) read_only: bool,
+    auto_unmount: bool,
+    fsname: Option<String>,
+    subtype: Option<String>,
+    pub(super) snapshots: Option<RelativePathBuf>,
+    pub(super) attr_ttl: Duration,
+    pub(super) entry_ttl: Duration,
+    pub(super) writeback_cache: bool,
+}
+
+impl Default for MountOptions {
+    fn default() -> Self {
+        Self {
+            allow_other: false,
+            allow_root: false,
+            read_only: false,
+            auto_unmount: false,
+            fsname: None,
+            subtype: None,
+            snapshots: None,
+            attr_ttl: DEFAULT_TTL,
+            entry_ttl: DEFAULT_TTL,
+            writeback_cache: false,
+        }
+    }
+}
+
+impl MountOptions {
+    /// Create a new `MountOptions` which mounts a writable file system accessible only by the
+    /// current user.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow all users, including root, to access the file system.
+    ///
+    /// Unless the current user is root, this requires `user_allow_other` to be set in
+    /// `/etc/fuse.conf`.
+    pub fn allow_other(&mut self, allow_other: bool) -> &mut Self {
+        self.allow_other = allow_other;
+        self
+    }
+
+    /// Allow the root user to access the file system in addition to the current user.
+    ///
+    /// Unless the current user is root, this requires `user_allow_other` to be set in
+    /// `/etc/fuse.conf`.
+    pub fn allow_root(&mut self, allow_root: bool) -> &mut Self {
+        self.allow_root = allow_root;
+        self
+    }
+
+    /// Mount the file system read-only.
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Automatically unmount the file system when the process exits.
+    pub fn auto_unmount(&mut self, auto_unmount: bool) -> &mut Self {
+        self.auto_unmount = auto_unmount;
+        self
+    }
+
+    /// Set the name of the file system shown in the mount table.
+    ///
+    /// This is `acid-store` by default.
+    pub fn fsname(&mut self, fsname: impl Into<String>) -> &mut Self {
+        self.fsname = Some(fsname.into());
+        self
+    }
+
+    /// Set the subtype of the file system shown in the mount table.
+    pub fn subtype(&mut self, subtype: impl Into<String>) -> &mut Self {
+        self.subtype = Some(subtype.into());
+        self
+    }
+
+    /// Expose the directory at `path` in the repository as a read-only `.snapshots` directory.
+    ///
+    /// The `.snapshots` directory appears in the root of the file system, and each child of the
+    /// directory at `path` appears as `.snapshots/<name>`. Snapshots can be taken cheaply with
+    /// [`FileRepo::copy_tree`], or with [`MountControl::snapshot`] while the repository is
+    /// mounted. The entries in the `.snapshots` directory can be read but not modified, so old
+    /// versions of files can be browsed and restored with ordinary file tools.
+    ///
+    /// The directory at `path` must not be inside the directory being mounted, and the directory
+    /// being mounted must not be inside it. If the directory being mounted already has a child
+    /// named `.snapshots`, it is hidden.
+    ///
+    /// [`FileRepo::copy_tree`]: crate::repo::file::FileRepo::copy_tree
+    /// [`MountControl::snapshot`]: crate::repo::file::MountControl::snapshot
+    pub fn snapshots(&mut self, path: impl AsRef<RelativePath>) -> &mut Self {
+        self.snapshots = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Set how long the kernel may cache the attributes of files.
+    ///
+    /// Because the repository can only be modified through the mounted file system while it is
+    /// mounted, this is effectively unlimited by default. Shorter TTLs only matter when another
+    /// process could observe stale attributes, such as when the file system is exported over NFS.
+    pub fn attr_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.attr_ttl = ttl;
+        self
+    }
+
+    /// Set how long the kernel may cache the results of looking up file names.
+    ///
+    /// This is effectively unlimited by default.
+    pub fn entry_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.entry_ttl = ttl;
+        self
+    }
+
+    /// Enable write-back caching in the kernel.
+    ///
+    /// This allows the kernel to buffer writes and send them to the file system in larger
+    /// batches, which can significantly improve the performance of small writes. However, writes
+    /// are not visible to the repository until they are flushed, so they may be lost if the
+    /// process exits without unmounting the file system. This is ignored if the kernel does not
+    /// support write-back caching.
+    pub fn writeback_cache(&mut self, writeback_cache: bool) -> &mut Self {
+        self.writeback_cache = writeback_cache;
+        self
+    }
+
+    /// Return the list of options to pass to `fuser`.
+    pub(crate) fn to_mount_options(&self) -> Vec<MountOption> {
+        let mut options = vec![
+            MountOption::DefaultPermissions,
+            MountOption::FSName(
+                self.fsname
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_FSNAME.to_string()),
+            ),
+        ];
+
+        if let Some(subtype) = &self.subtype {
+            options.push(MountOption::Subtype(subtype.clone()));
+        }
+        if self.allow_other {
+            options.push(MountOption::AllowOther);
+        }
+        if self.allow_root {
+            options.push(MountOption::AllowRoot);
+        }
+        if self.auto_unmount {
+            options.push(MountOption::AutoUnmount);
+        }
+        options.push(if self.read_only {
+            MountOption::RO
+        } else {
+            MountOption::RW
+        });
+
+        options
+    }
+}
@@ -0,0 +1,247 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+
+use relative_path::{RelativePath, RelativePathBuf};
+
+use crate::repo::state::ObjectKey;
+use crate::repo::Object;
+
+use super::entry::{Entry, EntryHandle, EntryType};
+use super::metadata::FileMetadata;
+use super::repository::{FileRepo, FileRepoInner, EMPTY_PATH};
+use super::special::SpecialType;
+
+/// The number of open `FileHandle` values for each entry, keyed by the ID of the entry object.
+#[derive(Debug, Default)]
+pub struct OpenHandles(HashMap<ObjectKey, usize>);
+
+/// A handle to a file in a [`FileRepo`] which is independent of its path.
+///
+/// A `FileHandle` is returned by [`FileRepo::open_with_handle`]. Like a file descriptor, it
+/// continues to refer to the same file if the file is renamed or moved, and if the file is
+/// removed, its contents and metadata remain accessible through the handle until it is closed
+/// with [`FileRepo::close_handle`].
+///
+/// [`FileRepo`]: crate::repo::file::FileRepo
+/// [`FileRepo::open_with_handle`]: crate::repo::file::FileRepo::open_with_handle
+/// [`FileRepo::close_handle`]: crate::repo::file::FileRepo::close_handle
+#[derive(Debug, PartialEq, Eq)]
+pub struct FileHandle {
+    /// The ID of the object which stores the serialized entry.
+    entry: ObjectKey,
+
+    /// The ID of the object which stores the file's contents.
+    contents: ObjectKey,
+}
+
+impl<S, M> FileRepo<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    /// Open the file at `path` and return a handle to it.
+    ///
+    /// Unlike [`open`], the returned [`FileHandle`] is not tied to `path`. Reads, writes, and
+    /// metadata updates made through the handle affect the same file even if it is renamed or
+    /// removed after it is opened. A file which is removed while it has open handles is no
+    /// longer visible in the repository, but its data is not freed until every handle to it is
+    /// closed with [`close_handle`]. If a handle is never closed, the data of a removed file is
+    /// never freed.
+    ///
+    /// Directory quotas are not enforced for writes made through the handle, and the size
+    /// returned by [`file_size`] is not updated until the handle is closed.
+    ///
+    /// Handles to files which were created since the last commit are no longer valid once the
+    /// repository is rolled back.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry with the given `path`.
+    /// - `Error::NotFile`: The entry does not represent a regular file.
+    ///
+    /// [`open`]: crate::repo::file::FileRepo::open
+    /// [`FileHandle`]: crate::repo::file::FileHandle
+    /// [`close_handle`]: crate::repo::file::FileRepo::close_handle
+    /// [`file_size`]: crate::repo::file::FileRepo::file_size
+    pub fn open_with_handle(&self, path: impl AsRef<RelativePath>) -> crate::Result<FileHandle> {
+        self.inner_mut().open_with_handle(path)
+    }
+
+    /// Return an `Object` for reading and writing the contents of the file with the given
+    /// `handle`.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: The file no longer exists because the repository was rolled back.
+    pub fn handle_object(&self, handle: &FileHandle) -> crate::Result<Object> {
+        self.inner().handle_object(handle)
+    }
+
+    /// Return the entry for the file with the given `handle`.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: The file no longer exists because the repository was rolled back.
+    /// - `Error::Deserialize`: The file metadata could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn handle_entry(&self, handle: &FileHandle) -> crate::Result<Entry<S, M>> {
+        self.inner().handle_entry(handle)
+    }
+
+    /// Set the file `metadata` for the file with the given `handle`.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: The file no longer exists because the repository was rolled back.
+    /// - `Error::Serialize`: The new file metadata could not be serialized.
+    /// - `Error::Deserialize`: The old file metadata could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn set_handle_metadata(
+        &self,
+        handle: &FileHandle,
+        metadata: Option<M>,
+    ) -> crate::Result<()> {
+        self.inner_mut().set_handle_metadata(handle, metadata)
+    }
+
+    /// Return the current path of the file with the given `handle`.
+    ///
+    /// This returns `None` if the file has been removed. This takes time proportional to the
+    /// number of entries in the repository.
+    pub fn handle_path(&self, handle: &FileHandle) -> Option<RelativePathBuf> {
+        self.inner().handle_path(handle)
+    }
+
+    /// Close the given `handle`.
+    ///
+    /// If the file still exists, this updates the size returned by [`file_size`] to account for
+    /// any changes made through the handle. If the file was removed and this was its last open
+    /// handle, its data is freed.
+    ///
+    /// # Errors
+    /// - `Error::TransactionInProgress`: The file has uncommitted changes.
+    ///
+    /// [`file_size`]: crate::repo::file::FileRepo::file_size
+    pub fn close_handle(&self, handle: FileHandle) -> crate::Result<()> {
+        self.inner_mut().close_handle(handle)
+    }
+}
+
+impl<S, M> FileRepoInner<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    pub(crate) fn open_with_handle(
+        &mut self,
+        path: impl AsRef<RelativePath>,
+    ) -> crate::Result<FileHandle> {
+        if path.as_ref() == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        let entry_handle = *self
+            .0
+            .state()
+            .get(path.as_ref())
+            .ok_or(crate::Error::NotFound)?;
+
+        match entry_handle.entry_type {
+            EntryType::File(contents) => {
+                *self.2 .0.entry(entry_handle.entry).or_insert(0) += 1;
+                Ok(FileHandle {
+                    entry: entry_handle.entry,
+                    contents,
+                })
+            }
+            _ => Err(crate::Error::NotFile),
+        }
+    }
+
+    pub(crate) fn handle_object(&self, handle: &FileHandle) -> crate::Result<Object> {
+        self.0.object(handle.contents).ok_or(crate::Error::NotFound)
+    }
+
+    pub(crate) fn handle_entry(&self, handle: &FileHandle) -> crate::Result<Entry<S, M>> {
+        let mut object = self.0.object(handle.entry).ok_or(crate::Error::NotFound)?;
+        object.deserialize()
+    }
+
+    pub(crate) fn set_handle_metadata(
+        &mut self,
+        handle: &FileHandle,
+        metadata: Option<M>,
+    ) -> crate::Result<()> {
+        let mut object = self.0.object(handle.entry).ok_or(crate::Error::NotFound)?;
+        let mut entry: Entry<S, M> = object.deserialize()?;
+        entry.metadata = metadata;
+        object.serialize(&entry)
+    }
+
+    pub(crate) fn handle_path(&self, handle: &FileHandle) -> Option<RelativePathBuf> {
+        self.0
+            .state()
+            .walk(&*EMPTY_PATH)
+            .unwrap()
+            .find(|(_, entry_handle)| entry_handle.entry == handle.entry)
+            .map(|(path, _)| path)
+    }
+
+    pub(crate) fn close_handle(&mut self, handle: FileHandle) -> crate::Result<()> {
+        let path = self.handle_path(&handle);
+
+        if let Some(path) = &path {
+            self.update_size(path)?;
+        }
+
+        if let Some(open_count) = self.2 .0.get_mut(&handle.entry) {
+            *open_count -= 1;
+            if *open_count > 0 {
+                return Ok(());
+            }
+            self.2 .0.remove(&handle.entry);
+        }
+
+        if path.is_none() {
+            self.0.remove(handle.contents);
+            self.0.remove(handle.entry);
+        }
+
+        Ok(())
+    }
+
+    /// Free the objects which store the data of an entry which was removed from the tree.
+    ///
+    /// The contents and entry object of a file which has open handles are not freed until its
+    /// last handle is closed.
+    pub(super) fn free_entry(&mut self, entry_handle: EntryHandle) {
+        if let Some(object_id) = entry_handle.attributes {
+            self.0.remove(object_id);
+        }
+
+        if self.2 .0.contains_key(&entry_handle.entry) {
+            return;
+        }
+
+        if let EntryType::File(object_id) = entry_handle.entry_type {
+            self.0.remove(object_id);
+        }
+        self.0.remove(entry_handle.entry);
+    }
+}
@@ -0,0 +1,215 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+use std::fs::hard_link;
+use std::path::{Path, PathBuf};
+
+use relative_path::{RelativePath, RelativePathBuf};
+use uuid::Uuid;
+use walkdir::DirEntry;
+
+use crate::repo::ContentId;
+
+use super::entry::EntryType;
+use super::metadata::FileMetadata;
+use super::repository::FileRepoInner;
+use super::special::SpecialType;
+use super::tree::{TreeProgress, TreeSummary};
+
+/// The hard links found while archiving a tree of files.
+#[derive(Debug, Default)]
+pub struct ArchivedLinks {
+    /// The entry path of the first file archived for each device and inode number.
+    first_paths: HashMap<(u64, u64), RelativePathBuf>,
+
+    /// The paths of entries to create as hard links and the entries they link to.
+    links: Vec<(RelativePathBuf, RelativePathBuf)>,
+}
+
+impl ArchivedLinks {
+    /// Return whether the file `entry` is a hard link to a file which was already visited.
+    ///
+    /// If it is, an entry will be created for it at `dest` by `create_archived_links` instead of
+    /// archiving it. Hard links are only detected on unix systems.
+    pub fn add(&mut self, entry: &DirEntry, dest: &RelativePath) -> bool {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let metadata = match entry.metadata() {
+                Ok(metadata) if metadata.is_file() && metadata.nlink() > 1 => metadata,
+                _ => return false,
+            };
+
+            match self.first_paths.get(&(metadata.dev(), metadata.ino())) {
+                Some(target) => {
+                    self.links.push((dest.to_owned(), target.clone()));
+                    true
+                }
+                None => {
+                    self.first_paths
+                        .insert((metadata.dev(), metadata.ino()), dest.to_owned());
+                    false
+                }
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = (entry, dest);
+            false
+        }
+    }
+}
+
+/// The hard links created while extracting a tree of entries.
+#[derive(Debug, Default)]
+pub struct ExtractedLinks {
+    /// The path and contents of the first file extracted for each link ID.
+    first_paths: HashMap<Uuid, (PathBuf, ContentId)>,
+}
+
+impl<S, M> FileRepoInner<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    /// Create the entries for the hard links found while archiving a tree.
+    ///
+    /// Each link is created as a copy of the entry it links to, and both entries are given the
+    /// same link ID so that they can be extracted as hard links. If `keep_going` is `true`, links
+    /// whose target could not be archived are recorded in `summary` instead of returning an error.
+    pub(super) fn create_archived_links(
+        &mut self,
+        links: ArchivedLinks,
+        summary: &mut TreeSummary,
+        progress: &mut dyn TreeProgress,
+        keep_going: bool,
+    ) -> crate::Result<()> {
+        for (link, target) in links.links {
+            progress.entry_started(&link);
+
+            if !self.is_file(&target) {
+                if keep_going {
+                    summary.failed.push((link, crate::Error::NotFound));
+                    continue;
+                }
+                return Err(crate::Error::NotFound);
+            }
+
+            // The link was already archived if this is an incremental archive. Copying the target
+            // is cheap, so it's simpler to replace it than to check whether it changed.
+            if self.exists(&link) {
+                self.remove_tree(&link)?;
+            }
+
+            if let Err(error) = self.copy(&target, &link) {
+                if keep_going {
+                    summary.failed.push((link, error));
+                    continue;
+                }
+                return Err(error);
+            }
+
+            let state = self.0.state_mut();
+            let link_id = state
+                .get(&target)
+                .unwrap()
+                .link
+                .unwrap_or_else(Uuid::new_v4);
+            state.get_mut(&target).unwrap().link = Some(link_id);
+            state.get_mut(&link).unwrap().link = Some(link_id);
+
+            progress.entry_finished(&link);
+            summary.entries += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Return the path of a file which the entry at `source` should be extracted as a hard link to.
+    ///
+    /// This returns `None` if the entry is not a hard link or if none of the entries it links to
+    /// have been extracted yet. Entries which were hard links when they were archived are only
+    /// extracted as hard links if their contents are still the same.
+    pub(super) fn extracted_link_target(
+        &self,
+        source: &RelativePath,
+        links: &ExtractedLinks,
+    ) -> crate::Result<Option<PathBuf>> {
+        let (link_id, content_id) = match self.link_id(source)? {
+            Some(link) => link,
+            None => return Ok(None),
+        };
+
+        match links.first_paths.get(&link_id) {
+            Some((target, target_content_id)) if *target_content_id == content_id => {
+                Ok(Some(target.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Record that the entry at `source` was extracted to `dest`.
+    pub(super) fn add_extracted_link(
+        &self,
+        source: &RelativePath,
+        dest: &Path,
+        links: &mut ExtractedLinks,
+    ) -> crate::Result<()> {
+        if let Some((link_id, content_id)) = self.link_id(source)? {
+            links
+                .first_paths
+                .entry(link_id)
+                .or_insert_with(|| (dest.to_owned(), content_id));
+        }
+        Ok(())
+    }
+
+    /// Create a hard link at `dest` to the file at `target` for the entry at `source`.
+    ///
+    /// This returns the number of bytes of file contents which were copied, which is always `0`.
+    pub(super) fn extract_link(
+        &self,
+        source: &RelativePath,
+        target: &Path,
+        dest: &Path,
+        progress: &mut dyn TreeProgress,
+    ) -> crate::Result<u64> {
+        if dest.exists() {
+            return Err(crate::Error::AlreadyExists);
+        }
+
+        progress.entry_started(source);
+        hard_link(target, dest)?;
+        progress.entry_finished(source);
+
+        Ok(0)
+    }
+
+    /// Return the link ID and content ID of the file at `path` if it is a hard link.
+    fn link_id(&self, path: &RelativePath) -> crate::Result<Option<(Uuid, ContentId)>> {
+        let handle = *self.0.state().get(path).ok_or(crate::Error::NotFound)?;
+        match (handle.entry_type, handle.link) {
+            (EntryType::File(object_id), Some(link_id)) => {
+                let content_id = self.0.object(object_id).unwrap().content_id()?;
+                Ok(Some((link_id, content_id)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
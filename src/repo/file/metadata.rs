@@ -14,12 +14,29 @@
  * limitations under the License.
  */
 
+use std::convert::TryFrom;
 use std::io;
 use std::path::Path;
+use std::time::SystemTime;
 
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+#[cfg(all(target_os = "macos", feature = "file-metadata"))]
+use acid_store_ffi::macos::chflags;
+#[cfg(feature = "file-metadata")]
+use filetime::set_file_times;
+#[cfg(all(windows, feature = "file-metadata"))]
+use {
+    acid_store_ffi::windows::{
+        get_security_descriptor, set_file_attributes, set_security_descriptor, stream_names,
+    },
+    std::fs::{self, FileTimes, OpenOptions},
+    std::os::windows::fs::{FileTimesExt, MetadataExt, OpenOptionsExt},
+    std::path::PathBuf,
+    winapi::um::winbase::FILE_FLAG_BACKUP_SEMANTICS,
+    winapi::um::winnt::{FILE_ATTRIBUTE_NORMAL, FILE_WRITE_ATTRIBUTES},
+};
 #[cfg(all(any(unix, doc), feature = "file-metadata"))]
 use {
     bitflags::bitflags,
@@ -31,8 +48,8 @@ use {
     std::time::{Duration, UNIX_EPOCH},
     users::{get_group_by_name, get_user_by_name},
 };
-#[cfg(feature = "file-metadata")]
-use {filetime::set_file_times, std::time::SystemTime};
+#[cfg(all(windows, not(doc), feature = "file-metadata"))]
+use {bitflags::bitflags, std::collections::HashMap};
 
 /// The metadata for a file in the file system.
 ///
@@ -45,6 +62,119 @@ pub trait FileMetadata: Serialize + DeserializeOwned {
 
     /// Write this metadata to the file at `path`.
     fn write_metadata(&self, path: &Path) -> io::Result<()>;
+
+    /// Write this metadata to the file at `path`, changing its owner according to `ownership`.
+    ///
+    /// The default implementation ignores `ownership` and calls [`write_metadata`], which is
+    /// correct for implementations which do not store the owner of the file.
+    ///
+    /// [`write_metadata`]: crate::repo::file::FileMetadata::write_metadata
+    fn write_metadata_with_ownership(&self, path: &Path, ownership: Ownership) -> io::Result<()> {
+        let _ = ownership;
+        self.write_metadata(path)
+    }
+
+    /// Return the time the file was last modified, if this metadata includes it.
+    ///
+    /// This is used to detect changed files without reading their contents. The default
+    /// implementation returns `None`.
+    fn modified(&self) -> Option<SystemTime> {
+        None
+    }
+
+    /// Create a new instance from the metadata in a tar `header`.
+    ///
+    /// This returns `None` if the metadata can't be represented by this type. The default
+    /// implementation always returns `None`.
+    #[cfg(feature = "file-tar")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "file-tar")))]
+    fn from_tar_header(_header: &tar::Header) -> Option<Self> {
+        None
+    }
+
+    /// Write this metadata to a tar `header`.
+    ///
+    /// The default implementation does nothing.
+    #[cfg(feature = "file-tar")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "file-tar")))]
+    fn write_tar_header(&self, _header: &mut tar::Header) {}
+}
+
+/// How the owner of a file is set when metadata is written to it.
+///
+/// This only has an effect for [`FileMetadata`] implementations which store the owner of a file,
+/// like [`UnixMetadata`]. The user and group of named entries in access control lists are not
+/// changed.
+///
+/// [`FileMetadata`]: crate::repo::file::FileMetadata
+/// [`UnixMetadata`]: crate::repo::file::UnixMetadata
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Ownership {
+    /// Set the owner to the stored user and group.
+    ///
+    /// If the current user doesn't have permission to change the owner of the file, the file is
+    /// left owned by the current user and no error is returned. This is the default.
+    #[default]
+    Preserve,
+
+    /// Don't change the owner of the file, so it is owned by the user who created it.
+    Drop,
+
+    /// Set the owner of every file to the given UID and GID.
+    Fixed {
+        /// The UID of the new owner.
+        user: u32,
+
+        /// The GID of the new owner.
+        group: u32,
+    },
+
+    /// Add the given offsets to the stored UID and GID.
+    ///
+    /// This can be used to map the owners of files into or out of the range of IDs used by a
+    /// user namespace, for example. If the resulting ID is out of range, an error is returned.
+    Offset {
+        /// The offset to add to the stored UID.
+        user: i64,
+
+        /// The offset to add to the stored GID.
+        group: i64,
+    },
+}
+
+impl Ownership {
+    /// Return the UID and GID to give a file which is stored with the given `user` and `group`.
+    ///
+    /// This returns `None` if the owner of the file should not be changed.
+    ///
+    /// # Errors
+    /// - `ErrorKind::InvalidInput`: An offset UID or GID is out of range.
+    pub fn map(self, user: u32, group: u32) -> io::Result<Option<(u32, u32)>> {
+        let offset_id = |id: u32, offset: i64| {
+            i64::from(id)
+                .checked_add(offset)
+                .and_then(|id| u32::try_from(id).ok())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "The mapped ID is out of range.",
+                    )
+                })
+        };
+
+        match self {
+            Ownership::Preserve => Ok(Some((user, group))),
+            Ownership::Drop => Ok(None),
+            Ownership::Fixed { user, group } => Ok(Some((user, group))),
+            Ownership::Offset {
+                user: user_offset,
+                group: group_offset,
+            } => Ok(Some((
+                offset_id(user, user_offset)?,
+                offset_id(group, group_offset)?,
+            ))),
+        }
+    }
 }
 
 /// A `FileMetadata` which stores no metadata.
@@ -59,6 +189,11 @@ impl FileMetadata for NoMetadata {
     fn write_metadata(&self, _path: &Path) -> io::Result<()> {
         Ok(())
     }
+
+    #[cfg(feature = "file-tar")]
+    fn from_tar_header(_header: &tar::Header) -> Option<Self> {
+        Some(NoMetadata)
+    }
 }
 
 /// A qualifier which determines who is granted a set of permissions in an access control list.
@@ -142,15 +277,34 @@ impl Acl {
 /// Construct a `SystemTime` from a unix timestamp.
 #[cfg(all(any(unix, doc), feature = "file-metadata"))]
 fn unix_file_time(secs: i64, nsec: i64) -> SystemTime {
-    let file_time = if secs.is_positive() {
+    let file_time = if secs >= 0 {
         UNIX_EPOCH + Duration::from_secs(secs as u64)
     } else {
-        UNIX_EPOCH - Duration::from_secs(secs as u64)
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
     };
-    if nsec.is_positive() {
+    if nsec >= 0 {
         file_time + Duration::from_nanos(nsec as u64)
     } else {
-        file_time - Duration::from_nanos(nsec as u64)
+        file_time - Duration::from_nanos(nsec.unsigned_abs())
+    }
+}
+
+/// Change the owner of the file at `path`, which is stored with the given `user` and `group`.
+///
+/// Permission errors are only ignored if the stored owner is being preserved.
+#[cfg(all(any(unix, doc), feature = "file-metadata"))]
+fn change_owner(path: &Path, user: u32, group: u32, ownership: Ownership) -> io::Result<()> {
+    let (user, group) = match ownership.map(user, group)? {
+        Some(owner) => owner,
+        None => return Ok(()),
+    };
+
+    match chown(path, Some(Uid::from_raw(user)), Some(Gid::from_raw(group))) {
+        Err(nix::Error::Sys(nix::errno::Errno::EPERM)) if ownership == Ownership::Preserve => {
+            Ok(())
+        }
+        Err(error) => Err(io::Error::other(error)),
+        _ => Ok(()),
     }
 }
 
@@ -282,6 +436,15 @@ pub struct UnixMetadata {
     /// [`FileMetadata::write_metadata`]: crate::repo::file::FileMetadata::write_metadata
     /// [`update_acl`]: crate::repo::file::UnixMetadata::update_acl
     pub acl: Acl,
+
+    /// The time the file was created, if the platform and file system record it.
+    ///
+    /// Most unix platforms don't support setting the creation time of a file, so this is not
+    /// restored by [`FileMetadata::write_metadata`].
+    ///
+    /// [`FileMetadata::write_metadata`]: crate::repo::file::FileMetadata::write_metadata
+    #[serde(default)]
+    pub created: Option<SystemTime>,
 }
 
 #[cfg(all(any(unix, doc), feature = "file-metadata"))]
@@ -436,10 +599,15 @@ impl FileMetadata for UnixMetadata {
             group: metadata.gid(),
             attributes,
             acl,
+            created: metadata.created().ok(),
         })
     }
 
     fn write_metadata(&self, path: &Path) -> io::Result<()> {
+        self.write_metadata_with_ownership(path, Ownership::Preserve)
+    }
+
+    fn write_metadata_with_ownership(&self, path: &Path, ownership: Ownership) -> io::Result<()> {
         // The order we do these in is important, because the mode, extended attributes, and ACLs
         // all interact when it comes to file permissions. ACLs are technically stored as xattrs,
         // and ACLs contain information which is redundant with the file mode. We want to set the
@@ -493,23 +661,298 @@ impl FileMetadata for UnixMetadata {
             }
         }
 
-        match chown(
-            path,
-            Some(Uid::from_raw(self.user)),
-            Some(Gid::from_raw(self.group)),
-        ) {
-            Err(nix::Error::Sys(nix::errno::Errno::EPERM)) => (),
-            Err(error) => return Err(io::Error::new(io::ErrorKind::Other, error)),
-            _ => (),
-        };
+        change_owner(path, self.user, self.group, ownership)?;
 
         set_file_times(path, self.accessed.into(), self.modified.into())?;
 
         Ok(())
     }
+
+    fn modified(&self) -> Option<SystemTime> {
+        Some(self.modified)
+    }
+
+    #[cfg(feature = "file-tar")]
+    fn from_tar_header(header: &tar::Header) -> Option<Self> {
+        let modified = tar_file_time(header.mtime().ok()?);
+        Some(Self {
+            mode: header.mode().ok()?,
+            modified,
+            accessed: modified,
+            changed: modified,
+            user: header.uid().ok()? as u32,
+            group: header.gid().ok()? as u32,
+            attributes: HashMap::new(),
+            acl: Acl::new(),
+            created: None,
+        })
+    }
+
+    #[cfg(feature = "file-tar")]
+    fn write_tar_header(&self, header: &mut tar::Header) {
+        // Tar headers only store the permission bits of the mode.
+        header.set_mode(self.mode & 0o7777);
+        header.set_mtime(tar_mtime(self.modified));
+        header.set_uid(self.user as u64);
+        header.set_gid(self.group as u64);
+    }
+}
+
+/// A `FileMetadata` for macOS-specific metadata.
+///
+/// In addition to the metadata stored by [`UnixMetadata`], this stores the creation time and the
+/// BSD file flags of the file. Finder information and resource forks are stored in the
+/// `com.apple.FinderInfo` and `com.apple.ResourceFork` extended attributes.
+///
+/// [`UnixMetadata`]: crate::repo::file::UnixMetadata
+#[cfg(all(any(target_os = "macos", doc), feature = "file-metadata"))]
+#[cfg_attr(docsrs, doc(cfg(all(target_os = "macos", feature = "file-metadata"))))]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct MacosMetadata {
+    /// The file mode (st_mode).
+    pub mode: u32,
+
+    /// The time the file was created (st_birthtime).
+    ///
+    /// The creation time can only be set to a time earlier than the current creation time of the
+    /// file, which is always the case for newly extracted files.
+    pub created: SystemTime,
+
+    /// The time the file was last modified (st_mtime).
+    pub modified: SystemTime,
+
+    /// The time the file was last accessed (st_atime).
+    pub accessed: SystemTime,
+
+    /// The UID of the user which owns the file (st_uid).
+    pub user: u32,
+
+    /// The GID of the group which owns the file (st_gid).
+    pub group: u32,
+
+    /// The BSD file flags (st_flags), like `UF_HIDDEN`.
+    pub flags: u32,
+
+    /// The extended attributes of the file.
+    pub attributes: HashMap<String, Vec<u8>>,
+}
+
+#[cfg(all(target_os = "macos", feature = "file-metadata"))]
+impl FileMetadata for MacosMetadata {
+    fn from_file(path: &Path) -> io::Result<Self> {
+        use std::os::macos::fs::MetadataExt as _;
+
+        let metadata = path.metadata()?;
+
+        let mut attributes = HashMap::new();
+        for attr_name in xattr::list(&path)? {
+            if let Some(attr_value) = xattr::get(&path, &attr_name)? {
+                attributes.insert(attr_name.to_string_lossy().to_string(), attr_value);
+            }
+        }
+
+        Ok(Self {
+            mode: metadata.mode(),
+            created: metadata.created()?,
+            modified: unix_file_time(metadata.mtime(), metadata.mtime_nsec()),
+            accessed: unix_file_time(metadata.atime(), metadata.atime_nsec()),
+            user: metadata.uid(),
+            group: metadata.gid(),
+            flags: metadata.st_flags(),
+            attributes,
+        })
+    }
+
+    fn write_metadata(&self, path: &Path) -> io::Result<()> {
+        self.write_metadata_with_ownership(path, Ownership::Preserve)
+    }
+
+    fn write_metadata_with_ownership(&self, path: &Path, ownership: Ownership) -> io::Result<()> {
+        // The file flags must be set last, because flags like `UF_IMMUTABLE` prevent any other
+        // changes to the file.
+
+        set_permissions(path, PermissionsExt::from_mode(self.mode))?;
+
+        for (attr_name, attr_value) in self.attributes.iter() {
+            xattr::set(&path, &attr_name, &attr_value)?;
+        }
+
+        change_owner(path, self.user, self.group, ownership)?;
+
+        // There is no portable way to set the creation time, but macOS moves the creation time
+        // back when the modification time is set to a time before it.
+        set_file_times(path, self.accessed.into(), self.created.into())?;
+        set_file_times(path, self.accessed.into(), self.modified.into())?;
+
+        chflags(path, self.flags)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        Some(self.modified)
+    }
+
+    #[cfg(feature = "file-tar")]
+    fn from_tar_header(header: &tar::Header) -> Option<Self> {
+        let modified = tar_file_time(header.mtime().ok()?);
+        Some(Self {
+            mode: header.mode().ok()?,
+            created: modified,
+            modified,
+            accessed: modified,
+            user: header.uid().ok()? as u32,
+            group: header.gid().ok()? as u32,
+            flags: 0,
+            attributes: HashMap::new(),
+        })
+    }
+
+    #[cfg(feature = "file-tar")]
+    fn write_tar_header(&self, header: &mut tar::Header) {
+        // Tar headers only store the permission bits of the mode.
+        header.set_mode(self.mode & 0o7777);
+        header.set_mtime(tar_mtime(self.modified));
+        header.set_uid(self.user as u64);
+        header.set_gid(self.group as u64);
+    }
+}
+
+#[cfg(all(any(windows, doc), feature = "file-metadata"))]
+bitflags! {
+    /// The NTFS attributes of a file which can be preserved.
+    #[cfg_attr(docsrs, doc(cfg(all(windows, feature = "file-metadata"))))]
+    #[derive(Serialize, Deserialize)]
+    pub struct FileAttributes: u32 {
+        /// The file is read-only (`FILE_ATTRIBUTE_READONLY`).
+        const READ_ONLY = 0x1;
+
+        /// The file is hidden (`FILE_ATTRIBUTE_HIDDEN`).
+        const HIDDEN = 0x2;
+
+        /// The file is used by the operating system (`FILE_ATTRIBUTE_SYSTEM`).
+        const SYSTEM = 0x4;
+
+        /// The file is marked for backup (`FILE_ATTRIBUTE_ARCHIVE`).
+        const ARCHIVE = 0x20;
+
+        /// The file is used for temporary storage (`FILE_ATTRIBUTE_TEMPORARY`).
+        const TEMPORARY = 0x100;
+
+        /// The file is not indexed by the content indexing service
+        /// (`FILE_ATTRIBUTE_NOT_CONTENT_INDEXED`).
+        const NOT_CONTENT_INDEXED = 0x2000;
+    }
+}
+
+/// A `FileMetadata` for Windows-specific metadata.
+///
+/// This stores the file's NTFS attributes, timestamps, security descriptor, and alternate data
+/// streams.
+#[cfg(all(any(windows, doc), feature = "file-metadata"))]
+#[cfg_attr(docsrs, doc(cfg(all(windows, feature = "file-metadata"))))]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct WindowsMetadata {
+    /// The file attributes.
+    pub attributes: FileAttributes,
+
+    /// The time the file was created.
+    pub created: SystemTime,
+
+    /// The time the file was last modified.
+    pub modified: SystemTime,
+
+    /// The time the file was last accessed.
+    pub accessed: SystemTime,
+
+    /// The self-relative security descriptor of the file.
+    ///
+    /// This contains the owner, primary group, and discretionary access control list (DACL) of
+    /// the file. The system access control list (SACL) is not stored because reading it requires
+    /// special privileges.
+    ///
+    /// When [`FileMetadata::write_metadata`] is called, the owner and group are only restored if
+    /// the process has permission to set them. Otherwise, only the DACL is restored.
+    ///
+    /// [`FileMetadata::write_metadata`]: crate::repo::file::FileMetadata::write_metadata
+    pub security_descriptor: Vec<u8>,
+
+    /// The alternate data streams of the file.
+    ///
+    /// This is a map of stream names to their contents. The unnamed default stream, which
+    /// contains the contents of the file, is not included.
+    pub streams: HashMap<String, Vec<u8>>,
+}
+
+/// Return the path of the alternate data stream `name` of the file at `path`.
+#[cfg(all(windows, feature = "file-metadata"))]
+fn stream_path(path: &Path, name: &str) -> PathBuf {
+    let mut stream_path = path.as_os_str().to_owned();
+    stream_path.push(":");
+    stream_path.push(name);
+    PathBuf::from(stream_path)
+}
+
+#[cfg(all(windows, feature = "file-metadata"))]
+impl FileMetadata for WindowsMetadata {
+    fn from_file(path: &Path) -> io::Result<Self> {
+        let metadata = path.metadata()?;
+
+        let mut streams = HashMap::new();
+        for name in stream_names(path)? {
+            streams.insert(name.clone(), fs::read(stream_path(path, &name))?);
+        }
+
+        Ok(Self {
+            attributes: FileAttributes::from_bits_truncate(metadata.file_attributes()),
+            created: metadata.created()?,
+            modified: metadata.modified()?,
+            accessed: metadata.accessed()?,
+            security_descriptor: get_security_descriptor(path)?,
+            streams,
+        })
+    }
+
+    fn write_metadata(&self, path: &Path) -> io::Result<()> {
+        // The order we do these in is important. Writing the alternate data streams changes the
+        // file times, so they must be written first. Setting the read-only attribute or the
+        // security descriptor could prevent us from modifying the file, so they must be set last.
+
+        for (name, contents) in &self.streams {
+            fs::write(stream_path(path, name), contents)?;
+        }
+
+        // Opening a directory requires `FILE_FLAG_BACKUP_SEMANTICS`.
+        let file = OpenOptions::new()
+            .access_mode(FILE_WRITE_ATTRIBUTES)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(path)?;
+        file.set_times(
+            FileTimes::new()
+                .set_created(self.created)
+                .set_accessed(self.accessed)
+                .set_modified(self.modified),
+        )?;
+        drop(file);
+
+        // `FILE_ATTRIBUTE_NORMAL` must be used when no other attributes are set.
+        let attributes = if self.attributes.is_empty() {
+            FILE_ATTRIBUTE_NORMAL
+        } else {
+            self.attributes.bits()
+        };
+        set_file_attributes(path, attributes)?;
+
+        set_security_descriptor(path, &self.security_descriptor)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        Some(self.modified)
+    }
 }
 
 /// A `FileMetadata` for metadata that is common to most platforms.
+///
+/// Timestamps are stored with the full precision provided by the platform, which is typically
+/// nanoseconds.
 #[cfg(feature = "file-metadata")]
 #[cfg_attr(docsrs, doc(cfg(feature = "file-metadata")))]
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -519,6 +962,15 @@ pub struct CommonMetadata {
 
     /// The time the file was last accessed.
     pub accessed: SystemTime,
+
+    /// The time the file was created, if the platform and file system record it.
+    ///
+    /// Setting the creation time of a file is not supported on most platforms, so this is not
+    /// restored by [`FileMetadata::write_metadata`].
+    ///
+    /// [`FileMetadata::write_metadata`]: crate::repo::file::FileMetadata::write_metadata
+    #[serde(default)]
+    pub created: Option<SystemTime>,
 }
 
 #[cfg(feature = "file-metadata")]
@@ -528,10 +980,47 @@ impl FileMetadata for CommonMetadata {
         Ok(Self {
             modified: metadata.modified()?,
             accessed: metadata.accessed()?,
+            created: metadata.created().ok(),
         })
     }
 
     fn write_metadata(&self, path: &Path) -> io::Result<()> {
         set_file_times(path, self.accessed.into(), self.modified.into())
     }
+
+    fn modified(&self) -> Option<SystemTime> {
+        Some(self.modified)
+    }
+
+    #[cfg(feature = "file-tar")]
+    fn from_tar_header(header: &tar::Header) -> Option<Self> {
+        let modified = tar_file_time(header.mtime().ok()?);
+        Some(Self {
+            modified,
+            accessed: modified,
+            created: None,
+        })
+    }
+
+    #[cfg(feature = "file-tar")]
+    fn write_tar_header(&self, header: &mut tar::Header) {
+        header.set_mtime(tar_mtime(self.modified));
+    }
+}
+
+/// Return the time represented by a tar `mtime`.
+#[cfg(all(feature = "file-metadata", feature = "file-tar"))]
+fn tar_file_time(mtime: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(mtime)
+}
+
+/// Return the tar `mtime` which represents `time`.
+///
+/// Tar headers only store whole seconds since the Unix epoch, so times before the epoch are
+/// clamped to the epoch.
+#[cfg(all(feature = "file-metadata", feature = "file-tar"))]
+fn tar_mtime(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
 }
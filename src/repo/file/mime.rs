@@ -0,0 +1,68 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(feature = "file-mime")]
+
+use std::io::Read;
+
+use relative_path::RelativePath;
+
+use super::metadata::FileMetadata;
+use super::repository::{FileRepo, FileRepoInner};
+use super::special::SpecialType;
+
+/// The maximum number of bytes at the start of a file which are read to detect its type.
+const SNIFF_LEN: u64 = 8192;
+
+impl<S, M> FileRepo<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    /// Detect the MIME type of the file at `path` from its contents.
+    ///
+    /// This reads the first few kilobytes of the file and compares them against the magic bytes
+    /// of known file formats. This returns `None` if the format of the file is not recognized,
+    /// which includes empty files and most plain text files. The file name is not considered.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry with the given `path`.
+    /// - `Error::NotFile`: The entry does not represent a regular file.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    #[cfg_attr(docsrs, doc(cfg(feature = "file-mime")))]
+    pub fn mime_type(&self, path: impl AsRef<RelativePath>) -> crate::Result<Option<&'static str>> {
+        self.inner().mime_type(path)
+    }
+}
+
+impl<S, M> FileRepoInner<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    pub(crate) fn mime_type(
+        &self,
+        path: impl AsRef<RelativePath>,
+    ) -> crate::Result<Option<&'static str>> {
+        let object = self.open(path)?;
+        let mut head = Vec::new();
+        object.take(SNIFF_LEN).read_to_end(&mut head)?;
+        Ok(infer::get(&head).map(|file_type| file_type.mime_type()))
+    }
+}
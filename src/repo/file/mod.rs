@@ -24,15 +24,24 @@
 //! A [`FileRepo`] is composed of [`Entry`] values which represent either a regular file, a
 //! directory, or a special file. Files in the file system can be copied into the repository using
 //! [`FileRepo::archive`] and [`FileRepo::archive_tree`], and entries in the repository can be
-//! copied to the file system using [`FileRepo::extract`] and [`FileRepo::extract_tree`]. It is also
-//! possible to manually add, remove, query, and modify entries.
+//! copied to the file system using [`FileRepo::extract`] and [`FileRepo::extract_tree`]. A tree of
+//! entries can be kept up to date with a directory in the file system using
+//! [`FileRepo::sync_tree`], which only copies files which have changed. It is also possible to
+//! manually add, remove, query, and modify entries.
 //!
 //! This repository is designed so that files archived on one platform can be extracted on another
 //! platform. Because many aspects of file systems—such as file paths, file metadata, and special
 //! file types—are heavily platform-dependent, the behavior of [`FileRepo`] can be customized
 //! through the [`FileMetadata`] and [`SpecialType`] traits.
 //!
-//! A [`FileRepo`] can be mounted as a FUSE file system using [`FileRepo::mount`].
+//! A [`FileRepo`] can be mounted as a FUSE file system on Unix-like systems using
+//! [`FileRepo::mount`]. On Windows, the `dokan-mount` cargo feature enables
+//! `FileRepo::mount_dokan`, which mounts a [`FileRepo`] using [Dokan]. The `file-webdav` cargo
+//! feature enables [`FileRepo::serve_webdav`], which serves a [`FileRepo`] over WebDAV so it can be
+//! mapped as a network drive on other platforms. Tar archives can be imported into and exported
+//! from a [`FileRepo`] using [`FileRepo::import_tar`] and [`FileRepo::export_tar`] through the
+//! `file-tar` cargo feature. The `file-mime` cargo feature enables
+//! [`FileRepo::mime_type`], which detects the type of a file from its contents.
 //!
 //! Like other repositories, changes made to the repository are not persisted to the data store
 //! until [`Commit::commit`] is called. For details about deduplication, compression, encryption,
@@ -49,6 +58,15 @@
 //! Instead, entry paths are relative paths relative to the root of the repository. A top-level
 //! directory `foo` containing a file `bar` is represented as `foo/bar`.
 //!
+//! By default, paths are compared code point by code point, so file names which look the same but
+//! use different Unicode normalization forms refer to different entries. Use
+//! [`FileRepo::set_path_normalization`] to normalize paths so that files archived on platforms
+//! which normalize file names differently resolve to the same entries.
+//!
+//! The `Object` returned by [`FileRepo::open`] is tied to the path it was opened with. To keep
+//! accessing a file after it is renamed or removed, open it with [`FileRepo::open_with_handle`]
+//! instead, which returns a [`FileHandle`] that behaves like a file descriptor.
+//!
 //! # Metadata
 //!
 //! A [`FileRepo`] accepts a [`FileMetadata`] type parameter which determines how it handles file
@@ -72,10 +90,20 @@
 //! [`FileRepo::archive_tree`]: crate::repo::file::FileRepo::archive_tree
 //! [`FileRepo::extract`]: crate::repo::file::FileRepo::extract
 //! [`FileRepo::extract_tree`]: crate::repo::file::FileRepo::extract_tree
+//! [`FileRepo::sync_tree`]: crate::repo::file::FileRepo::sync_tree
 //! [`RelativePath`]: crate::repo::file::RelativePath
+//! [`FileRepo::set_path_normalization`]: crate::repo::file::FileRepo::set_path_normalization
+//! [`FileRepo::open`]: crate::repo::file::FileRepo::open
+//! [`FileRepo::open_with_handle`]: crate::repo::file::FileRepo::open_with_handle
+//! [`FileHandle`]: crate::repo::file::FileHandle
 //! [`FileMetadata`]: crate::repo::file::FileMetadata
 //! [`SpecialType`]: crate::repo::file::SpecialType
 //! [`FileRepo::mount`]: crate::repo::file::FileRepo::mount
+//! [Dokan]: https://dokan-dev.github.io/
+//! [`FileRepo::import_tar`]: crate::repo::file::FileRepo::import_tar
+//! [`FileRepo::export_tar`]: crate::repo::file::FileRepo::export_tar
+//! [`FileRepo::mime_type`]: crate::repo::file::FileRepo::mime_type
+//! [`FileRepo::serve_webdav`]: crate::repo::file::FileRepo::serve_webdav
 //! [`Commit::commit`]: crate::repo::Commit::commit
 //! [`NoMetadata`]: crate::repo::file::NoMetadata
 //! [`NoSpecialType`]: crate::repo::file::NoSpecialType
@@ -88,16 +116,49 @@ pub use {
     self::special::UnixSpecialType,
 };
 
+#[cfg(all(unix, feature = "fuse-mount"))]
+pub use self::fuse::{MountControl, MountOptions};
+#[cfg(all(target_os = "macos", feature = "file-metadata"))]
+pub use self::metadata::MacosMetadata;
+#[cfg(all(windows, feature = "file-metadata"))]
+pub use self::metadata::{FileAttributes, WindowsMetadata};
+
 pub use self::entry::{Entry, FileType};
+pub use self::filter::PathFilter;
+pub use self::handle::FileHandle;
 #[cfg(feature = "file-metadata")]
 pub use self::metadata::CommonMetadata;
-pub use self::metadata::{FileMetadata, NoMetadata};
+pub use self::metadata::{FileMetadata, NoMetadata, Ownership};
+pub use self::normalization::PathNormalization;
 pub use self::repository::FileRepo;
 pub use self::special::{NoSpecialType, SpecialType};
+pub use self::stats::TreeStats;
+pub use self::sync::{SyncDirection, SyncOptions, SyncSummary};
+pub use self::tree::{ArchiveOptions, ExtractOptions, TreeProgress, TreeSummary};
+pub use self::walk::{Walk, WalkOptions};
+#[cfg(feature = "file-webdav")]
+pub use self::webdav::{WebDavListener, WebDavShutdown};
 
+mod allocate;
+mod attributes;
+mod dokan;
 mod entry;
+mod filter;
 mod fuse;
+mod handle;
+mod hard_link;
 mod metadata;
+mod mime;
+mod normalization;
+mod parallel;
 mod path_tree;
+mod quota;
 mod repository;
+mod sparse;
 mod special;
+mod stats;
+mod sync;
+mod tarball;
+mod tree;
+mod walk;
+mod webdav;
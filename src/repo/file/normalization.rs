@@ -0,0 +1,138 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::borrow::Cow;
+
+use relative_path::RelativePath;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{is_nfc, is_nfd, UnicodeNormalization};
+
+use super::metadata::FileMetadata;
+use super::repository::{FileRepo, FileRepoInner};
+use super::special::SpecialType;
+
+/// A Unicode normalization form for the paths of entries in a [`FileRepo`].
+///
+/// The same file name can be encoded as different sequences of code points. For example, macOS
+/// typically stores file names in NFD form, where an accented character is a base character
+/// followed by a combining mark, while Linux and Windows typically store them in NFC form, where
+/// it is a single precomposed character. Normalizing paths means that these file names refer to
+/// the same entry.
+///
+/// This is set with [`FileRepo::set_path_normalization`].
+///
+/// [`FileRepo`]: crate::repo::file::FileRepo
+/// [`FileRepo::set_path_normalization`]: crate::repo::file::FileRepo::set_path_normalization
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+pub enum PathNormalization {
+    /// Normalization Form C (canonical composition).
+    Nfc,
+
+    /// Normalization Form D (canonical decomposition).
+    Nfd,
+}
+
+impl PathNormalization {
+    /// Return `name` in this normalization form.
+    ///
+    /// This only allocates if `name` is not already in this form.
+    pub(super) fn normalize_str<'a>(self, name: &'a str) -> Cow<'a, str> {
+        match self {
+            PathNormalization::Nfc if is_nfc(name) => Cow::Borrowed(name),
+            PathNormalization::Nfc => Cow::Owned(name.nfc().collect()),
+            PathNormalization::Nfd if is_nfd(name) => Cow::Borrowed(name),
+            PathNormalization::Nfd => Cow::Owned(name.nfd().collect()),
+        }
+    }
+
+    /// Return `path` in this normalization form.
+    ///
+    /// This only allocates if `path` is not already in this form.
+    pub(super) fn normalize<'a>(self, path: &'a RelativePath) -> Cow<'a, RelativePath> {
+        match self.normalize_str(path.as_str()) {
+            Cow::Borrowed(path) => Cow::Borrowed(RelativePath::new(path)),
+            Cow::Owned(path) => Cow::Owned(path.into()),
+        }
+    }
+}
+
+impl<S, M> FileRepo<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    /// Return the Unicode normalization form used for the paths of entries in the repository.
+    ///
+    /// This returns `None` if paths are not normalized, which is the default.
+    pub fn path_normalization(&self) -> Option<PathNormalization> {
+        self.inner().path_normalization()
+    }
+
+    /// Set the Unicode normalization form used for the paths of entries in the repository.
+    ///
+    /// When this is `Some`, every path passed to the repository is converted to the given form
+    /// before it is used to create or look up an entry, so paths which differ only in their
+    /// normalization refer to the same entry. This is useful when files are archived from
+    /// platforms which normalize file names differently, such as macOS and Linux.
+    ///
+    /// The paths of existing entries are converted to the new form. Paths returned by the
+    /// repository use the normalized form for the names of entries, although paths derived from
+    /// a path which was passed in, like those returned by [`walk`], keep the prefix as given.
+    ///
+    /// Setting this to `None` stops normalizing paths but does not change the paths of existing
+    /// entries. Like other changes, this setting is persisted when the repository is committed.
+    ///
+    /// # Errors
+    /// - `Error::AlreadyExists`: Two existing entries would have the same path once normalized. In
+    ///   this case, the repository is not changed.
+    ///
+    /// [`walk`]: crate::repo::file::FileRepo::walk
+    pub fn set_path_normalization(
+        &self,
+        normalization: Option<PathNormalization>,
+    ) -> crate::Result<()> {
+        self.inner_mut().set_path_normalization(normalization)
+    }
+}
+
+impl<S, M> FileRepoInner<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    pub(crate) fn path_normalization(&self) -> Option<PathNormalization> {
+        self.0.state().normalization()
+    }
+
+    pub(crate) fn set_path_normalization(
+        &mut self,
+        normalization: Option<PathNormalization>,
+    ) -> crate::Result<()> {
+        if self.tree_mut().set_normalization(normalization) {
+            Ok(())
+        } else {
+            Err(crate::Error::AlreadyExists)
+        }
+    }
+
+    /// Return the file `name` in the normalization form used for paths in the repository.
+    pub(super) fn normalize_name<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        match self.path_normalization() {
+            Some(form) => form.normalize_str(name),
+            None => Cow::Borrowed(name),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::repo::common::{prepare_chunks, PreparedChunk, RecoverPoison};
+use crate::repo::Chunking;
+
+/// The number of prepared chunks each worker can have waiting to be written.
+const CHUNKS_PER_WORKER: usize = 4;
+
+/// A message sent from a worker thread to the thread which writes to the repository.
+pub enum Message {
+    /// A chunk of the file with the given index was prepared.
+    Chunk(usize, PreparedChunk),
+
+    /// All the chunks of the file with the given index have been sent.
+    Finished(usize),
+
+    /// The file with the given index could not be read.
+    Failed(usize, io::Error),
+}
+
+/// A pool of worker threads which read files and split them into chunks.
+///
+/// Data stores can't be shared between threads, so the workers only prepare chunks. The chunks
+/// are sent back to the thread which owns the pool to be written to the repository.
+pub struct ChunkingPool {
+    /// The sender for submitting files to the workers.
+    jobs: Option<Sender<(usize, PathBuf)>>,
+
+    /// The receiver for messages from the workers.
+    messages: Option<Receiver<Message>>,
+
+    /// The handles for the worker threads.
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ChunkingPool {
+    /// Start a new pool with the given number of `threads` which chunk files using `chunking`.
+    ///
+    /// Chunks are no larger than `max_chunk_size`, or unbounded if it is `0`. Each worker reads
+    /// files using a buffer of `buffer_size` bytes.
+    pub fn new(
+        threads: usize,
+        chunking: Chunking,
+        max_chunk_size: usize,
+        buffer_size: usize,
+    ) -> Self {
+        let (job_sender, job_receiver) = channel::<(usize, PathBuf)>();
+        let (message_sender, message_receiver) = sync_channel(threads * CHUNKS_PER_WORKER);
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..threads)
+            .map(|_| {
+                let job_receiver = Arc::clone(&job_receiver);
+                let message_sender = message_sender.clone();
+                let chunking = chunking.clone();
+                thread::spawn(move || {
+                    run_worker(
+                        &job_receiver,
+                        &message_sender,
+                        &chunking,
+                        max_chunk_size,
+                        buffer_size,
+                    )
+                })
+            })
+            .collect();
+
+        Self {
+            jobs: Some(job_sender),
+            messages: Some(message_receiver),
+            workers,
+        }
+    }
+
+    /// Submit the file at `path` with the given `index` to be chunked.
+    pub fn submit(&self, index: usize, path: PathBuf) {
+        if let Some(jobs) = &self.jobs {
+            // This only fails if all the workers have stopped, in which case the file will never
+            // be reported as finished.
+            jobs.send((index, path)).ok();
+        }
+    }
+
+    /// Stop accepting new files so that `recv` returns `None` once all files have been chunked.
+    pub fn close(&mut self) {
+        self.jobs = None;
+    }
+
+    /// Return the next message from the workers without blocking.
+    pub fn try_recv(&self) -> Option<Message> {
+        match self.messages.as_ref()?.try_recv() {
+            Ok(message) => Some(message),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => None,
+        }
+    }
+
+    /// Block until the next message from the workers is available.
+    ///
+    /// This returns `None` once the pool has been closed and all files have been chunked.
+    pub fn recv(&self) -> Option<Message> {
+        self.messages.as_ref()?.recv().ok()
+    }
+}
+
+impl Drop for ChunkingPool {
+    fn drop(&mut self) {
+        // Dropping the receiver causes workers which are blocked sending a message to stop.
+        self.jobs = None;
+        self.messages = None;
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+/// Chunk files submitted to `jobs` and send the chunks to `messages` until either is closed.
+fn run_worker(
+    jobs: &Mutex<Receiver<(usize, PathBuf)>>,
+    messages: &SyncSender<Message>,
+    chunking: &Chunking,
+    max_chunk_size: usize,
+    buffer_size: usize,
+) {
+    loop {
+        // Release the lock on the queue before chunking the file so other workers can proceed.
+        let next_job = jobs.lock().recover().recv();
+        let (index, path) = match next_job {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        let result = File::open(&path).and_then(|file| {
+            prepare_chunks(file, chunking, max_chunk_size, buffer_size, |chunk| {
+                messages.send(Message::Chunk(index, chunk)).is_ok()
+            })
+        });
+
+        let message = match result {
+            Ok(()) => Message::Finished(index),
+            Err(error) => Message::Failed(index, error),
+        };
+
+        if messages.send(message).is_err() {
+            return;
+        }
+    }
+}
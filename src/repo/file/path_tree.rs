@@ -14,12 +14,16 @@
  * limitations under the License.
  */
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::hash_map::{self, HashMap};
+use std::collections::HashSet;
 use std::iter;
 
 use relative_path::{RelativePath, RelativePathBuf};
 use serde::{Deserialize, Serialize};
 
+use super::normalization::PathNormalization;
+
 /// Recursively iterate through the tree of nodes.
 fn walk_nodes<'a, V>(
     parent: impl AsRef<RelativePath> + 'a,
@@ -43,6 +47,55 @@ fn drain_nodes<'a, V: 'a>(
     }))
 }
 
+/// An iterator over the descendants of a path in a `PathTree` which can skip subtrees.
+///
+/// Descendants are returned in depth-first order.
+pub struct Descendants<'a, V> {
+    /// The iterators over the children of each ancestor of the next path.
+    stack: Vec<(RelativePathBuf, hash_map::Iter<'a, String, PathNode<V>>)>,
+
+    /// The last path which was returned and its children, which are visited next.
+    pending: Option<(RelativePathBuf, &'a HashMap<String, PathNode<V>>)>,
+}
+
+impl<'a, V> Descendants<'a, V> {
+    /// Do not visit the descendants of the last path which was returned.
+    pub fn skip_descendants(&mut self) {
+        self.pending = None;
+    }
+
+    /// Return the depth of the last path which was returned relative to the parent path.
+    ///
+    /// Children of the parent path have a depth of `1`.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+}
+
+impl<'a, V> Iterator for Descendants<'a, V> {
+    type Item = (RelativePathBuf, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((path, children)) = self.pending.take() {
+            self.stack.push((path, children.iter()));
+        }
+
+        loop {
+            let (parent, children) = self.stack.last_mut()?;
+            match children.next() {
+                Some((name, node)) => {
+                    let path = parent.join(name);
+                    self.pending = Some((path.clone(), &node.children));
+                    return Some((path, &node.value));
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
 /// A node in a `PathTree`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct PathNode<V> {
@@ -62,16 +115,49 @@ impl<V> PathNode<V> {
     }
 }
 
+/// Return whether any two nodes in the tree would have the same name once normalized.
+fn has_normalized_conflicts<V>(
+    children: &HashMap<String, PathNode<V>>,
+    normalization: PathNormalization,
+) -> bool {
+    let mut names = HashSet::new();
+    children.iter().any(|(name, node)| {
+        !names.insert(normalization.normalize_str(name))
+            || has_normalized_conflicts(&node.children, normalization)
+    })
+}
+
+/// Normalize the names of all the nodes in the tree.
+fn normalize_nodes<V>(
+    children: &mut HashMap<String, PathNode<V>>,
+    normalization: PathNormalization,
+) {
+    *children = children
+        .drain()
+        .map(|(name, mut node)| {
+            normalize_nodes(&mut node.children, normalization);
+            (normalization.normalize_str(&name).into_owned(), node)
+        })
+        .collect();
+}
+
 /// A tree that associates file paths with values of type `V`.
+///
+/// If the tree has a `PathNormalization`, paths are normalized before they are inserted or looked
+/// up.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PathTree<V> {
     nodes: HashMap<String, PathNode<V>>,
+
+    #[serde(default)]
+    normalization: Option<PathNormalization>,
 }
 
 impl<V> Default for PathTree<V> {
     fn default() -> Self {
         Self {
             nodes: HashMap::new(),
+            normalization: None,
         }
     }
 }
@@ -81,6 +167,35 @@ impl<V> PathTree<V> {
     pub fn new() -> Self {
         PathTree {
             nodes: HashMap::new(),
+            normalization: None,
+        }
+    }
+
+    /// Return the normalization form used for paths in the tree.
+    pub fn normalization(&self) -> Option<PathNormalization> {
+        self.normalization
+    }
+
+    /// Set the normalization form used for paths in the tree and normalize the existing paths.
+    ///
+    /// This returns `false` and leaves the tree unchanged if two existing paths would be the same
+    /// once normalized. Setting this to `None` does not change the existing paths.
+    pub fn set_normalization(&mut self, normalization: Option<PathNormalization>) -> bool {
+        if let Some(form) = normalization {
+            if has_normalized_conflicts(&self.nodes, form) {
+                return false;
+            }
+            normalize_nodes(&mut self.nodes, form);
+        }
+        self.normalization = normalization;
+        true
+    }
+
+    /// Return `path` in the normalization form used for paths in the tree.
+    pub fn normalize<'a>(&self, path: &'a RelativePath) -> Cow<'a, RelativePath> {
+        match self.normalization {
+            Some(form) => form.normalize(path),
+            None => Cow::Borrowed(path),
         }
     }
 
@@ -94,10 +209,11 @@ impl<V> PathTree<V> {
     /// This returns `None` if `path` is not in the tree or does not have a value associated with
     /// it.
     pub fn get(&self, path: impl AsRef<RelativePath>) -> Option<&V> {
+        let path = self.normalize(path.as_ref());
         let mut current_nodes = &self.nodes;
         let mut current_value = None;
 
-        for segment in path.as_ref().iter() {
+        for segment in path.iter() {
             let node = current_nodes.get(segment)?;
             current_nodes = &node.children;
             current_value = Some(&node.value);
@@ -111,10 +227,11 @@ impl<V> PathTree<V> {
     /// This returns `None` if `path` is not in the tree or does not have a value associated with
     /// it.
     pub fn get_mut(&mut self, path: impl AsRef<RelativePath>) -> Option<&mut V> {
+        let path = self.normalize(path.as_ref());
         let mut current_nodes = &mut self.nodes;
         let mut current_value = None;
 
-        for segment in path.as_ref().iter() {
+        for segment in path.iter() {
             let node = current_nodes.get_mut(segment)?;
             current_nodes = &mut node.children;
             current_value = Some(&mut node.value);
@@ -130,8 +247,9 @@ impl<V> PathTree<V> {
     /// # Panics
     /// - The parent path does not exist.
     pub fn insert(&mut self, path: impl AsRef<RelativePath>, value: V) -> Option<V> {
+        let path = self.normalize(path.as_ref());
         let mut current_nodes = &mut self.nodes;
-        let mut segments = path.as_ref().iter();
+        let mut segments = path.iter();
         let mut segment = segments.next()?;
 
         for next_segment in segments {
@@ -154,17 +272,49 @@ impl<V> PathTree<V> {
     ///
     /// If the path is in the tree, this returns its value. Otherwise, this returns `None`.
     pub fn remove(&mut self, path: impl AsRef<RelativePath>) -> Option<V> {
-        let mut current_nodes = &mut self.nodes;
-        let mut segments = path.as_ref().iter();
-        let mut segment = segments.next()?;
+        Some(self.remove_node(path)?.value)
+    }
 
-        for next_segment in segments {
-            let node = current_nodes.get_mut(segment)?;
-            current_nodes = &mut node.children;
-            segment = next_segment;
+    /// Move the given `source` path and its descendants to `dest`.
+    ///
+    /// This returns `true` if the paths were moved or `false` if `source` is not in the tree, the
+    /// parent of `dest` is not in the tree, or `dest` is already in the tree.
+    ///
+    /// # Panics
+    /// - The `dest` path is a descendant of `source`.
+    pub fn rename(
+        &mut self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<RelativePath>,
+    ) -> bool {
+        let source = self.normalize(source.as_ref());
+        let dest = self.normalize(dest.as_ref());
+        if !self.contains(&source) {
+            return false;
+        }
+
+        assert!(
+            !dest.starts_with(&source),
+            "The destination path is a descendant of the source path."
+        );
+
+        let (dest_parent, dest_name) = match (dest.parent(), dest.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return false,
+        };
+        let has_parent = dest_parent.as_str().is_empty() || self.contains(dest_parent);
+        if !has_parent || self.contains(&dest) {
+            return false;
         }
 
-        Some(current_nodes.remove(segment)?.value)
+        let node = self.remove_node(&source).unwrap();
+        let mut dest_nodes = &mut self.nodes;
+        for segment in dest_parent.iter() {
+            dest_nodes = &mut dest_nodes.get_mut(segment).unwrap().children;
+        }
+        dest_nodes.insert(dest_name.to_string(), node);
+
+        true
     }
 
     /// Return an iterator of the children of `path` and their values.
@@ -178,7 +328,7 @@ impl<V> PathTree<V> {
     ) -> Option<impl Iterator<Item = (RelativePathBuf, &'a V)> + 'a> {
         let mut current_nodes = &self.nodes;
 
-        for segment in path.as_ref().iter() {
+        for segment in self.normalize(path.as_ref()).iter() {
             current_nodes = &current_nodes.get(segment)?.children;
         }
 
@@ -201,13 +351,32 @@ impl<V> PathTree<V> {
     ) -> Option<Box<dyn Iterator<Item = (RelativePathBuf, &'a V)> + 'a>> {
         let mut current_nodes = &self.nodes;
 
-        for segment in path.as_ref().iter() {
+        for segment in self.normalize(path.as_ref()).iter() {
             current_nodes = &current_nodes.get(segment)?.children;
         }
 
         Some(walk_nodes(path, current_nodes))
     }
 
+    /// Return an iterator of the descendants of `path` and their values which can skip subtrees.
+    ///
+    /// If the path is not in the tree, this returns `None`.
+    ///
+    /// This is like `walk`, except that the returned iterator can skip the descendants of the
+    /// last path it returned.
+    pub fn descendants(&self, path: impl AsRef<RelativePath>) -> Option<Descendants<'_, V>> {
+        let mut current_nodes = &self.nodes;
+
+        for segment in self.normalize(path.as_ref()).iter() {
+            current_nodes = &current_nodes.get(segment)?.children;
+        }
+
+        Some(Descendants {
+            stack: Vec::new(),
+            pending: Some((path.as_ref().to_owned(), current_nodes)),
+        })
+    }
+
     /// Drain the tree of the descendants of `path` and their values.
     ///
     /// If the path is not in the tree, this returns `None`.
@@ -218,8 +387,9 @@ impl<V> PathTree<V> {
         &'a mut self,
         path: impl AsRef<RelativePath> + 'a,
     ) -> Option<Box<dyn Iterator<Item = (RelativePathBuf, V)> + 'a>> {
+        let normalized_path = self.normalize(path.as_ref());
         let mut current_nodes = &mut self.nodes;
-        let mut segments = path.as_ref().iter();
+        let mut segments = normalized_path.iter();
         let mut segment = segments.next()?;
 
         for next_segment in segments {
@@ -238,6 +408,22 @@ impl<V> PathTree<V> {
     pub fn clear(&mut self) {
         self.nodes.clear();
     }
+
+    /// Remove the node at the given `path` from the tree and return it.
+    fn remove_node(&mut self, path: impl AsRef<RelativePath>) -> Option<PathNode<V>> {
+        let normalized_path = self.normalize(path.as_ref());
+        let mut current_nodes = &mut self.nodes;
+        let mut segments = normalized_path.iter();
+        let mut segment = segments.next()?;
+
+        for next_segment in segments {
+            let node = current_nodes.get_mut(segment)?;
+            current_nodes = &mut node.children;
+            segment = next_segment;
+        }
+
+        current_nodes.remove(segment)
+    }
 }
 
 #[cfg(test)]
@@ -248,6 +434,7 @@ mod tests {
     use relative_path::RelativePathBuf;
 
     use crate::repo::file::path_tree::PathTree;
+    use crate::repo::file::PathNormalization;
 
     #[test]
     fn tree_contains_path() {
@@ -404,4 +591,96 @@ mod tests {
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn skip_descendants() {
+        let mut tree = PathTree::new();
+        tree.insert("a", 1);
+        tree.insert("a/b", 2);
+        tree.insert("c", 3);
+        tree.insert("c/d", 4);
+
+        let mut descendants = tree.descendants("").unwrap();
+        let mut actual = HashSet::new();
+        while let Some((path, value)) = descendants.next() {
+            if path.as_str() == "a" {
+                descendants.skip_descendants();
+            }
+            actual.insert((path, value));
+        }
+
+        let expected = hashset![
+            (RelativePathBuf::from("a"), &1),
+            (RelativePathBuf::from("c"), &3),
+            (RelativePathBuf::from("c/d"), &4),
+        ];
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn rename_moves_descendants() {
+        let mut tree = PathTree::new();
+        tree.insert("a", 1);
+        tree.insert("a/b", 2);
+        tree.insert("c", 3);
+
+        assert!(tree.rename("a", "c/d"));
+        assert_eq!(tree.get("a"), None);
+        assert_eq!(tree.get("c/d"), Some(&1));
+        assert_eq!(tree.get("c/d/b"), Some(&2));
+    }
+
+    #[test]
+    fn rename_to_existing_path_fails() {
+        let mut tree = PathTree::new();
+        tree.insert("a", 1);
+        tree.insert("b", 2);
+
+        assert!(!tree.rename("a", "b"));
+        assert!(!tree.rename("a", "c/d"));
+        assert!(!tree.rename("c", "d"));
+        assert_eq!(tree.get("a"), Some(&1));
+    }
+
+    #[test]
+    fn normalized_paths_are_equal() {
+        let mut tree = PathTree::new();
+        assert!(tree.set_normalization(Some(PathNormalization::Nfc)));
+        tree.insert("cafe\u{301}", 1);
+        tree.insert("caf\u{e9}/x", 2);
+
+        assert_eq!(tree.get("caf\u{e9}"), Some(&1));
+        assert_eq!(tree.get("cafe\u{301}/x"), Some(&2));
+        assert_eq!(
+            tree.walk("").unwrap().collect::<HashSet<_>>(),
+            hashset![
+                (RelativePathBuf::from("caf\u{e9}"), &1),
+                (RelativePathBuf::from("caf\u{e9}/x"), &2),
+            ]
+        );
+    }
+
+    #[test]
+    fn setting_normalization_normalizes_existing_paths() {
+        let mut tree = PathTree::new();
+        tree.insert("cafe\u{301}", 1);
+        tree.insert("cafe\u{301}/x", 2);
+        tree.insert("caf\u{e9}", 3);
+
+        assert!(!tree.set_normalization(Some(PathNormalization::Nfd)));
+        assert_eq!(tree.normalization(), None);
+        assert_eq!(tree.get("caf\u{e9}"), Some(&3));
+
+        tree.remove("caf\u{e9}");
+
+        assert!(tree.set_normalization(Some(PathNormalization::Nfc)));
+        assert_eq!(
+            tree.walk("").unwrap().collect::<HashSet<_>>(),
+            hashset![
+                (RelativePathBuf::from("caf\u{e9}"), &1),
+                (RelativePathBuf::from("caf\u{e9}/x"), &2),
+            ]
+        );
+    }
 }
@@ -0,0 +1,358 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+use relative_path::{RelativePath, RelativePathBuf};
+
+use crate::repo::common::RecoverPoison;
+
+use super::entry::{EntryHandle, EntryType};
+use super::metadata::FileMetadata;
+use super::path_tree::PathTree;
+use super::repository::{FileRepo, FileRepoInner, EMPTY_PATH};
+use super::special::SpecialType;
+
+/// The usage of each directory with a quota, keyed by its normalized path.
+///
+/// Computing the usage of a directory requires walking all of its descendants, so it is cached
+/// between writes. The cache is cleared whenever entries are added, removed, or moved, and it is
+/// updated in place when the size of a file is recorded.
+#[derive(Debug, Default)]
+pub struct QuotaCache(Mutex<HashMap<RelativePathBuf, u64>>);
+
+impl QuotaCache {
+    /// Remove all the cached usages.
+    pub fn clear(&mut self) {
+        self.0.get_mut().recover().clear();
+    }
+}
+
+impl<S, M> FileRepo<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    /// Return the quota of the directory at `path` in bytes.
+    ///
+    /// This returns `None` if the directory does not have a quota.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry at `path`.
+    /// - `Error::NotDirectory`: The entry at `path` is not a directory.
+    pub fn quota(&self, path: impl AsRef<RelativePath>) -> crate::Result<Option<u64>> {
+        self.inner().quota(path)
+    }
+
+    /// Set the quota of the directory at `path` to `quota` bytes.
+    ///
+    /// A quota limits the total size of the files beneath a directory, as returned by
+    /// [`quota_usage`]. Once a write would make the total size exceed the quota of the directory
+    /// or any of its ancestors, it fails with `Error::QuotaExceeded`. Quotas are enforced when
+    /// files are archived with methods like [`archive`] and [`archive_tree`], when tar archives
+    /// are imported, when files are written with [`write_at`] or through a FUSE mount, and when
+    /// entries are copied or moved with [`copy`], [`copy_tree`], and [`rename`].
+    ///
+    /// Quotas are not enforced when writing to the `Object` returned by [`open`]. Applications
+    /// which write to files that way can call [`check_quota`] before writing.
+    ///
+    /// Setting a quota which is smaller than the current usage is allowed, but it prevents the
+    /// files beneath the directory from growing. Setting the quota to `None` removes it.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry at `path`.
+    /// - `Error::NotDirectory`: The entry at `path` is not a directory.
+    ///
+    /// [`quota_usage`]: crate::repo::file::FileRepo::quota_usage
+    /// [`archive`]: crate::repo::file::FileRepo::archive
+    /// [`archive_tree`]: crate::repo::file::FileRepo::archive_tree
+    /// [`write_at`]: crate::repo::file::FileRepo::write_at
+    /// [`copy`]: crate::repo::file::FileRepo::copy
+    /// [`copy_tree`]: crate::repo::file::FileRepo::copy_tree
+    /// [`rename`]: crate::repo::file::FileRepo::rename
+    /// [`open`]: crate::repo::file::FileRepo::open
+    /// [`check_quota`]: crate::repo::file::FileRepo::check_quota
+    pub fn set_quota(
+        &self,
+        path: impl AsRef<RelativePath>,
+        quota: Option<u64>,
+    ) -> crate::Result<()> {
+        self.inner_mut().set_quota(path, quota)
+    }
+
+    /// Return the total size in bytes of the files beneath the directory at `path`.
+    ///
+    /// This uses the sizes returned by [`file_size`], so it does not need to read the contents of
+    /// any files. It takes time proportional to the number of descendants of `path`.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry at `path`.
+    /// - `Error::NotDirectory`: The entry at `path` is not a directory.
+    /// - `Error::TransactionInProgress`: A file's size is not recorded and it is being written.
+    ///
+    /// [`file_size`]: crate::repo::file::FileRepo::file_size
+    pub fn quota_usage(&self, path: impl AsRef<RelativePath>) -> crate::Result<u64> {
+        self.inner().quota_usage(path)
+    }
+
+    /// Check whether `bytes` more bytes can be written to the file at `path` without exceeding
+    /// the quota of any of its ancestor directories.
+    ///
+    /// The entry at `path` does not need to exist yet, so this can be used before creating a
+    /// file. The usage of each ancestor which has a quota is cached until entries are added,
+    /// removed, or moved, so this is cheap to call before every write.
+    ///
+    /// # Errors
+    /// - `Error::QuotaExceeded`: Writing `bytes` bytes would exceed a quota.
+    /// - `Error::TransactionInProgress`: A file's size is not recorded and it is being written.
+    pub fn check_quota(&self, path: impl AsRef<RelativePath>, bytes: u64) -> crate::Result<()> {
+        self.inner().check_quota(path, bytes)
+    }
+
+    /// Write `data` to the file at `path` starting at `offset`, enforcing directory quotas.
+    ///
+    /// Unlike writing to the `Object` returned by [`open`], this fails without writing anything
+    /// if the write would make the file exceed the quota of one of its ancestor directories, and
+    /// it updates the size returned by [`file_size`]. If `offset` is past the end of the file,
+    /// the file is extended with a hole first.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry with the given `path`.
+    /// - `Error::NotFile`: The entry does not represent a regular file.
+    /// - `Error::QuotaExceeded`: The write would exceed the quota of a directory.
+    /// - `Error::TransactionInProgress`: The file is being written through another `Object`.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred or the end of the write would overflow a `u64`.
+    ///
+    /// [`open`]: crate::repo::file::FileRepo::open
+    /// [`file_size`]: crate::repo::file::FileRepo::file_size
+    pub fn write_at(
+        &self,
+        path: impl AsRef<RelativePath>,
+        offset: u64,
+        data: &[u8],
+    ) -> crate::Result<()> {
+        self.inner_mut().write_at(path, offset, data)
+    }
+}
+
+impl<S, M> FileRepoInner<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    pub(crate) fn quota(&self, path: impl AsRef<RelativePath>) -> crate::Result<Option<u64>> {
+        let path = path.as_ref();
+        if path == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        let handle = self.0.state().get(path).ok_or(crate::Error::NotFound)?;
+        match handle.entry_type {
+            EntryType::Directory => Ok(handle.quota),
+            _ => Err(crate::Error::NotDirectory),
+        }
+    }
+
+    pub(crate) fn set_quota(
+        &mut self,
+        path: impl AsRef<RelativePath>,
+        quota: Option<u64>,
+    ) -> crate::Result<()> {
+        let path = path.as_ref();
+        self.quota(path)?;
+        self.tree_mut().get_mut(path).unwrap().quota = quota;
+        Ok(())
+    }
+
+    pub(crate) fn quota_usage(&self, path: impl AsRef<RelativePath>) -> crate::Result<u64> {
+        let path = path.as_ref();
+        self.quota(path)?;
+
+        let mut usage = 0;
+        for (_, handle) in self.0.state().walk(path).unwrap() {
+            usage += match (handle.entry_type, handle.size) {
+                (EntryType::File(_), Some(size)) => size,
+                (EntryType::File(object_id), None) => self.0.object(object_id).unwrap().size()?,
+                _ => 0,
+            };
+        }
+
+        Ok(usage)
+    }
+
+    pub(crate) fn check_quota(
+        &self,
+        path: impl AsRef<RelativePath>,
+        bytes: u64,
+    ) -> crate::Result<()> {
+        self.check_quotas_except(path.as_ref(), bytes, None)
+    }
+
+    /// Check whether `bytes` more bytes can be added beneath `path` without exceeding the quota
+    /// of any of its ancestor directories.
+    ///
+    /// Ancestors of `path` which are also ancestors of `moved_from` are skipped, because their
+    /// usage already includes the bytes being moved.
+    pub(super) fn check_quotas_except(
+        &self,
+        path: &RelativePath,
+        bytes: u64,
+        moved_from: Option<&RelativePath>,
+    ) -> crate::Result<()> {
+        if bytes == 0 {
+            return Ok(());
+        }
+
+        let state = self.0.state();
+        let moved_from = moved_from.map(|source| state.normalize(source).into_owned());
+
+        let mut ancestor = path.parent();
+        while let Some(directory) = ancestor {
+            if directory == *EMPTY_PATH {
+                break;
+            }
+
+            let quota = state.get(directory).and_then(|handle| handle.quota);
+            if let Some(quota) = quota {
+                let normalized = state.normalize(directory);
+                let already_counted = match &moved_from {
+                    Some(source) => source.starts_with(&*normalized),
+                    None => false,
+                };
+                if !already_counted && self.cached_usage(&normalized)?.saturating_add(bytes) > quota
+                {
+                    return Err(crate::Error::QuotaExceeded);
+                }
+            }
+
+            ancestor = directory.parent();
+        }
+
+        Ok(())
+    }
+
+    /// Return the usage of the directory at the normalized `path`, using the cached value if
+    /// there is one.
+    fn cached_usage(&self, path: &RelativePath) -> crate::Result<u64> {
+        if let Some(usage) = self.3 .0.lock().recover().get(path) {
+            return Ok(*usage);
+        }
+        let usage = self.quota_usage(path)?;
+        self.3 .0.lock().recover().insert(path.to_owned(), usage);
+        Ok(usage)
+    }
+
+    /// Return the total size in bytes of the files in the tree at `path`.
+    ///
+    /// If `path` is a file, this is the size of that file.
+    pub(super) fn tree_size(&self, path: &RelativePath) -> crate::Result<u64> {
+        let mut size = match self.0.state().get(path) {
+            Some(handle) => self.handle_size(handle)?,
+            None => return Err(crate::Error::NotFound),
+        };
+        for (_, handle) in self.0.state().walk(path).unwrap() {
+            size += self.handle_size(handle)?;
+        }
+        Ok(size)
+    }
+
+    /// Return the size of the file with the given `handle`, or `0` if it is not a file.
+    pub(super) fn handle_size(&self, handle: &EntryHandle) -> crate::Result<u64> {
+        match (handle.entry_type, handle.size) {
+            (EntryType::File(_), Some(size)) => Ok(size),
+            (EntryType::File(object_id), None) => self.0.object(object_id).unwrap().size(),
+            _ => Ok(0),
+        }
+    }
+
+    /// Update the cached usage of the ancestors of the file at `path` when its recorded size
+    /// changes from `old_size` to `new_size`.
+    ///
+    /// If the old size is unknown, the cached usage of its ancestors is discarded instead.
+    pub(super) fn update_cached_usage(
+        &self,
+        path: &RelativePath,
+        old_size: Option<u64>,
+        new_size: u64,
+    ) {
+        let mut cache = self.3 .0.lock().recover();
+        if cache.is_empty() {
+            return;
+        }
+
+        let path = self.0.state().normalize(path);
+        let mut ancestor = path.parent();
+        while let Some(directory) = ancestor {
+            match (cache.get_mut(directory), old_size) {
+                (Some(usage), Some(old_size)) => {
+                    *usage = (*usage + new_size).saturating_sub(old_size);
+                }
+                (Some(_), None) => {
+                    cache.remove(directory);
+                }
+                (None, _) => (),
+            }
+            ancestor = directory.parent();
+        }
+    }
+
+    /// Return the tree of entries for modifying it.
+    ///
+    /// This clears the cached usage of directories with quotas, so it must be used instead of
+    /// modifying the state of the backing repository directly whenever entries are added,
+    /// removed, or moved, or their quotas or sizes change.
+    pub(super) fn tree_mut(&mut self) -> &mut PathTree<EntryHandle> {
+        self.3.clear();
+        self.0.state_mut()
+    }
+
+    pub(crate) fn write_at(
+        &mut self,
+        path: impl AsRef<RelativePath>,
+        offset: u64,
+        data: &[u8],
+    ) -> crate::Result<()> {
+        let path = path.as_ref();
+        let old_size = self.file_size(path)?;
+        let end = offset
+            .checked_add(data.len() as u64)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        self.check_quota(path, end.saturating_sub(old_size))?;
+
+        let mut object = self.open(path)?;
+        if offset > old_size {
+            object.set_len(offset)?;
+        }
+        object.seek(SeekFrom::Start(offset))?;
+        object.write_all(data)?;
+        object.commit()?;
+        drop(object);
+
+        if end > old_size {
+            self.record_size(path, end);
+        }
+
+        Ok(())
+    }
+}
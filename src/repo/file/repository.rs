@@ -14,31 +14,46 @@
  * limitations under the License.
  */
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
-use std::fs::{create_dir, create_dir_all, metadata, File, OpenOptions};
-use std::io::{self, copy};
+use std::fs::{canonicalize, create_dir, create_dir_all, metadata, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
 use std::marker::PhantomData;
 use std::path::Path;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use hex_literal::hex;
 use once_cell::sync::Lazy;
 use relative_path::{RelativePath, RelativePathBuf};
 use uuid::Uuid;
-use walkdir::WalkDir;
+use walkdir::{DirEntry, WalkDir};
 
 use crate::repo::{
-    key::KeyRepo, state::StateRepo, Commit, Object, OpenRepo, RepoInfo, RestoreSavepoint, Savepoint,
+    common::RecoverPoison,
+    key::KeyRepo,
+    state::{ObjectKey, StateRepo, StateRepoInner},
+    AuditEntry, CancellationToken, CleanReport, Commit, Object, OpenRepo, Operation, Progress,
+    RepairReport, RepoInfo, RepoStats, RestoreSavepoint, Savepoint, TransactionEvent,
 };
 
 use super::entry::{Entry, EntryHandle, EntryType, FileType};
-use super::metadata::{FileMetadata, NoMetadata};
+use super::filter::PathFilter;
+use super::handle::OpenHandles;
+use super::hard_link::{ArchivedLinks, ExtractedLinks};
+use super::metadata::{FileMetadata, NoMetadata, Ownership};
+use super::parallel::{ChunkingPool, Message};
 use super::path_tree::PathTree;
+use super::quota::QuotaCache;
+use super::sparse::{copy_from_object, copy_to_object, is_sparse_file};
 use super::special::{NoSpecialType, SpecialType};
+use super::tree::{ArchiveOptions, ExtractOptions, TreeProgress, TreeSummary};
+#[cfg(feature = "async")]
+use crate::repo::{AsyncRepo, AsyncStream};
 #[cfg(all(any(unix, doc), feature = "fuse-mount"))]
 use {
-    super::fuse::FuseAdapter, super::metadata::UnixMetadata, super::special::UnixSpecialType,
-    std::ffi::OsStr,
+    super::fuse::{FuseAdapter, MountOptions},
+    super::metadata::UnixMetadata,
+    super::special::UnixSpecialType,
 };
 
 /// The path of the root entry.
@@ -46,13 +61,62 @@ pub static EMPTY_PATH: Lazy<RelativePathBuf> = Lazy::new(|| RelativePath::new(""
 
 type RepoState = PathTree<EntryHandle>;
 
+/// A file whose contents are being chunked by a worker thread in `archive_tree_parallel`.
+struct PendingFile {
+    /// The path of the entry in the repository.
+    dest_path: RelativePathBuf,
+
+    /// The ID of the object which stores the file's contents.
+    object_id: ObjectKey,
+
+    /// The object which stores the file's contents, once it has been opened.
+    object: Option<Object>,
+
+    /// The number of bytes of the file which have been written so far.
+    bytes: u64,
+
+    /// Whether this is the root of the tree being archived.
+    is_root: bool,
+}
+
+/// Return the path of `path` relative to its ancestor `root` as a `RelativePath`.
+pub(super) fn relative_source_path<'a>(root: &Path, path: &'a Path) -> &'a RelativePath {
+    RelativePath::from_path(path.strip_prefix(root).unwrap()).expect("Not a valid relative path.")
+}
+
+/// Return whether the file system `entry` in the tree at `root` is selected by `filter`.
+///
+/// The root of the tree and paths which are not valid relative paths are always selected.
+fn is_selected(filter: &PathFilter, root: &Path, entry: &DirEntry) -> bool {
+    if entry.depth() == 0 {
+        return true;
+    }
+
+    match RelativePath::from_path(entry.path().strip_prefix(root).unwrap()) {
+        Ok(path) => filter.is_match(path, entry.file_type().is_dir()),
+        Err(_) => true,
+    }
+}
+
 /// A virtual file system.
 ///
 /// See [`crate::repo::file`] for more information.
 #[derive(Debug)]
-pub struct FileRepo<S = NoSpecialType, M = NoMetadata>(
-    pub(super) StateRepo<RepoState>,
+pub struct FileRepo<S = NoSpecialType, M = NoMetadata>(RwLock<FileRepoInner<S, M>>)
+where
+    S: SpecialType,
+    M: FileMetadata;
+
+/// The contents of a [`FileRepo`].
+///
+/// Each public method of `FileRepo` locks the repository and calls the method of the same name on
+/// this type.
+#[derive(Debug)]
+pub(crate) struct FileRepoInner<S, M>(
+    pub(super) StateRepoInner<RepoState>,
     PhantomData<(S, M)>,
+    pub(super) OpenHandles,
+    pub(super) QuotaCache,
 )
 where
     S: SpecialType,
@@ -71,18 +135,28 @@ where
     where
         Self: Sized,
     {
-        Ok(Self(StateRepo::open_repo(repo)?, PhantomData))
+        Ok(Self::from_inner(FileRepoInner(
+            StateRepoInner::open_repo(repo.into_inner())?,
+            PhantomData,
+            OpenHandles::default(),
+            QuotaCache::default(),
+        )))
     }
 
     fn create_repo(repo: KeyRepo<Self::Key>) -> crate::Result<Self>
     where
         Self: Sized,
     {
-        Ok(Self(StateRepo::create_repo(repo)?, PhantomData))
+        Ok(Self::from_inner(FileRepoInner(
+            StateRepoInner::create_repo(repo.into_inner())?,
+            PhantomData,
+            OpenHandles::default(),
+            QuotaCache::default(),
+        )))
     }
 
     fn into_repo(self) -> crate::Result<KeyRepo<Self::Key>> {
-        self.0.into_repo()
+        Ok(KeyRepo::from_inner(self.into_inner().0.into_repo()?))
     }
 }
 
@@ -91,50 +165,50 @@ where
     S: SpecialType,
     M: FileMetadata,
 {
+    /// Wrap the given `inner` contents of a repository in a new `FileRepo`.
+    pub(crate) fn from_inner(inner: FileRepoInner<S, M>) -> Self {
+        FileRepo(RwLock::new(inner))
+    }
+
+    /// Consume this repository and return its contents.
+    pub(crate) fn into_inner(self) -> FileRepoInner<S, M> {
+        self.0.into_inner().recover()
+    }
+
+    /// Lock the contents of this repository for reading.
+    pub(crate) fn inner(&self) -> RwLockReadGuard<'_, FileRepoInner<S, M>> {
+        self.0.read().recover()
+    }
+
+    /// Lock the contents of this repository for writing.
+    pub(crate) fn inner_mut(&self) -> RwLockWriteGuard<'_, FileRepoInner<S, M>> {
+        self.0.write().recover()
+    }
+
     /// Return whether there is an entry at `path`.
     pub fn exists(&self, path: impl AsRef<RelativePath>) -> bool {
-        self.0.state().contains(path.as_ref())
+        self.inner().exists(path)
     }
 
     /// Return whether the given `path` is a regular file entry.
     ///
     /// If there is no entry at `path`, this returns `false`.
     pub fn is_file(&self, path: impl AsRef<RelativePath>) -> bool {
-        match self.0.state().get(path.as_ref()) {
-            Some(entry) => matches!(entry.entry_type, EntryType::File(_)),
-            None => false,
-        }
+        self.inner().is_file(path)
     }
 
     /// Return whether the given `path` is a directory entry.
     ///
     /// If there is no entry at `path`, this returns `false`.
     pub fn is_directory(&self, path: impl AsRef<RelativePath>) -> bool {
-        match self.0.state().get(path.as_ref()) {
-            Some(entry) => matches!(entry.entry_type, EntryType::Directory),
-            None => false,
-        }
+        self.inner().is_directory(path)
     }
 
     /// Return whether the given `path` is a special file entry.
     ///
     /// If there is no entry at `path`, this returns `false`.
     pub fn is_special(&self, path: impl AsRef<RelativePath>) -> bool {
-        match self.0.state().get(path.as_ref()) {
-            Some(entry) => matches!(entry.entry_type, EntryType::Special),
-            None => false,
-        }
-    }
-
-    /// Return `true` if the given `path` has a parent directory in the repository.
-    fn has_parent(&self, path: &RelativePath) -> bool {
-        match path.parent() {
-            Some(parent) if parent != *EMPTY_PATH => match self.0.state().get(parent) {
-                Some(handle) => matches!(handle.entry_type, EntryType::Directory),
-                None => false,
-            },
-            _ => true,
-        }
+        self.inner().is_special(path)
     }
 
     /// Add a new empty file or directory entry to the repository at the given `path`.
@@ -146,7 +220,7 @@ where
     /// # use acid_store::repo::file::{FileRepo, Entry, RelativePath};
     /// # use acid_store::store::{MemoryStore, MemoryConfig};
     /// #
-    /// # let mut repo: FileRepo = OpenOptions::new()
+    /// # let repo: FileRepo = OpenOptions::new()
     /// #    .mode(OpenMode::CreateNew)
     /// #    .open(&MemoryConfig::new())
     /// #    .unwrap();
@@ -164,7 +238,7 @@ where
     /// # use acid_store::repo::file::{FileRepo, Entry, RelativePath, UnixSpecialType};
     /// # use acid_store::store::{MemoryStore, MemoryConfig};
     /// #
-    /// # let mut repo: FileRepo<UnixSpecialType> = OpenOptions::new()
+    /// # let repo: FileRepo<UnixSpecialType> = OpenOptions::new()
     /// #    .mode(OpenMode::CreateNew)
     /// #    .open(&MemoryConfig::new())
     /// #    .unwrap();
@@ -186,46 +260,8 @@ where
     /// - `Error::InvalidData`: Ciphertext verification failed.
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
-    pub fn create(
-        &mut self,
-        path: impl AsRef<RelativePath>,
-        entry: &Entry<S, M>,
-    ) -> crate::Result<()> {
-        if path.as_ref() == *EMPTY_PATH {
-            return Err(crate::Error::InvalidPath);
-        }
-
-        if self.exists(&path) {
-            return Err(crate::Error::AlreadyExists);
-        }
-
-        if !self.has_parent(path.as_ref()) {
-            return Err(crate::Error::InvalidPath);
-        }
-
-        let entry_id = self.0.create();
-        let mut object = self.0.object(entry_id).unwrap();
-        let result = object.serialize(entry);
-        drop(object);
-        if let Err(error) = result {
-            self.0.remove(entry_id);
-            return Err(error);
-        }
-
-        let entry_type = match entry.file_type {
-            FileType::File => EntryType::File(self.0.create()),
-            FileType::Directory => EntryType::Directory,
-            FileType::Special(_) => EntryType::Special,
-        };
-
-        let handle = EntryHandle {
-            entry: entry_id,
-            entry_type,
-        };
-
-        self.0.state_mut().insert(path.as_ref(), handle);
-
-        Ok(())
+    pub fn create(&self, path: impl AsRef<RelativePath>, entry: &Entry<S, M>) -> crate::Result<()> {
+        self.inner_mut().create(path, entry)
     }
 
     /// Add a new empty file or directory entry to the repository at the given `path`.
@@ -241,26 +277,11 @@ where
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
     pub fn create_parents(
-        &mut self,
+        &self,
         path: impl AsRef<RelativePath>,
         entry: &Entry<S, M>,
     ) -> crate::Result<()> {
-        let parent = match path.as_ref().parent() {
-            Some(parent) if parent != *EMPTY_PATH => parent,
-            _ => return self.create(path, entry),
-        };
-
-        let mut ancestor = RelativePathBuf::new();
-        for component in parent.iter() {
-            ancestor.push(component);
-            match self.create(&ancestor, &Entry::directory()) {
-                Err(crate::Error::AlreadyExists) => (),
-                Err(error) => return Err(error),
-                _ => (),
-            }
-        }
-
-        self.create(path, entry)
+        self.inner_mut().create_parents(path, entry)
     }
 
     /// Remove the entry with the given `path` from the repository.
@@ -274,27 +295,8 @@ where
     /// - `Error::NotEmpty`: The entry is a directory which is not empty.
     ///
     /// [`Commit::clean`]: crate::repo::Commit::clean
-    pub fn remove(&mut self, path: impl AsRef<RelativePath>) -> crate::Result<()> {
-        if path.as_ref() == *EMPTY_PATH {
-            return Err(crate::Error::InvalidPath);
-        }
-
-        match self.0.state().list(&path) {
-            Some(mut children) => {
-                if children.next().is_some() {
-                    return Err(crate::Error::NotEmpty);
-                }
-            }
-            None => return Err(crate::Error::NotFound),
-        }
-
-        let entry_handle = self.0.state_mut().remove(path.as_ref()).unwrap();
-        if let EntryType::File(object_id) = entry_handle.entry_type {
-            self.0.remove(object_id);
-        }
-        self.0.remove(entry_handle.entry);
-
-        Ok(())
+    pub fn remove(&self, path: impl AsRef<RelativePath>) -> crate::Result<()> {
+        self.inner_mut().remove(path)
     }
 
     /// Remove the entry with the given `path` and its descendants from the repository.
@@ -307,27 +309,27 @@ where
     /// - `Error::NotFound`: There is no entry with the given `path`.
     ///
     /// [`clean`]: crate::repo::Commit::clean
-    pub fn remove_tree(&mut self, path: impl AsRef<RelativePath>) -> crate::Result<()> {
-        if path.as_ref() == *EMPTY_PATH {
-            return Err(crate::Error::InvalidPath);
-        }
-
-        let handles = self
-            .0
-            .state_mut()
-            .drain(path.as_ref())
-            .ok_or(crate::Error::NotFound)?
-            .map(|(_, handle)| handle)
-            .collect::<Vec<_>>();
-
-        for handle in handles {
-            if let EntryType::File(object_id) = &handle.entry_type {
-                self.0.remove(*object_id);
-            }
-            self.0.remove(handle.entry);
-        }
+    pub fn remove_tree(&self, path: impl AsRef<RelativePath>) -> crate::Result<()> {
+        self.inner_mut().remove_tree(path)
+    }
 
-        Ok(())
+    /// Remove each directory under `parent` which does not contain any files.
+    ///
+    /// A directory is removed if it is empty or if it only contains directories which are removed.
+    /// The given `parent` itself is never removed, and it may be an empty path, in which case
+    /// directories are pruned from the whole repository. Special files count as files, so
+    /// directories which contain them are not removed.
+    ///
+    /// This returns the paths of the directories which were removed in depth-first order.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: The given `parent` does not exist.
+    /// - `Error::NotDirectory`: The given `parent` is not a directory.
+    pub fn prune_empty_dirs(
+        &self,
+        parent: impl AsRef<RelativePath>,
+    ) -> crate::Result<Vec<RelativePathBuf>> {
+        self.inner_mut().prune_empty_dirs(parent)
     }
 
     /// Return the entry at `path`.
@@ -340,16 +342,7 @@ where
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
     pub fn entry(&self, path: impl AsRef<RelativePath>) -> crate::Result<Entry<S, M>> {
-        if path.as_ref() == *EMPTY_PATH {
-            return Err(crate::Error::InvalidPath);
-        }
-        let entry_handle = &self
-            .0
-            .state()
-            .get(path.as_ref())
-            .ok_or(crate::Error::NotFound)?;
-        let mut object = self.0.object(entry_handle.entry).unwrap();
-        object.deserialize()
+        self.inner().entry(path)
     }
 
     /// Set the file `metadata` for the entry at `path`.
@@ -363,60 +356,87 @@ where
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
     pub fn set_metadata(
-        &mut self,
+        &self,
         path: impl AsRef<RelativePath>,
         metadata: Option<M>,
     ) -> crate::Result<()> {
-        if path.as_ref() == *EMPTY_PATH {
-            return Err(crate::Error::InvalidPath);
-        }
+        self.inner_mut().set_metadata(path, metadata)
+    }
 
-        let entry_handle = *self
-            .0
-            .state()
-            .get(path.as_ref())
-            .ok_or(crate::Error::NotFound)?;
-        let mut object = self.0.object(entry_handle.entry).unwrap();
-        let mut entry: Entry<S, M> = object.deserialize()?;
-        entry.metadata = metadata;
-        object.serialize(&entry)
+    /// Modify the file metadata for the entry at `path` in place by calling `update`.
+    ///
+    /// This is like calling [`entry`] followed by [`set_metadata`], except that the entry is only
+    /// deserialized once and the rest of the entry is not cloned. If the entry has no metadata,
+    /// `update` is not called and this returns `false`. Otherwise, this returns `true`.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry at `path`.
+    /// - `Error::Serialize`: The new file metadata could not be serialized.
+    /// - `Error::Deserialize`: The old file metadata could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`entry`]: crate::repo::file::FileRepo::entry
+    /// [`set_metadata`]: crate::repo::file::FileRepo::set_metadata
+    pub fn update_metadata(
+        &self,
+        path: impl AsRef<RelativePath>,
+        update: impl FnOnce(&mut M),
+    ) -> crate::Result<bool> {
+        self.inner_mut().update_metadata(path, update)
     }
 
     /// Return an `Object` for reading and writing the contents of the file at `path`.
     ///
+    /// The size of the file returned by [`file_size`] is not updated when the file is modified
+    /// through the returned `Object`. Call [`update_size`] after committing changes to the object
+    /// to update it. Directory quotas are not enforced for writes made through the returned
+    /// `Object`; use [`write_at`] to write to a file while enforcing them.
+    ///
     /// # Errors
     /// - `Error::InvalidPath`: The given `path` is empty.
     /// - `Error::NotFound`: There is no entry with the given `path`.
     /// - `Error::NotFile`: The entry does not represent a regular file.
+    ///
+    /// [`file_size`]: crate::repo::file::FileRepo::file_size
+    /// [`update_size`]: crate::repo::file::FileRepo::update_size
+    /// [`write_at`]: crate::repo::file::FileRepo::write_at
     pub fn open(&self, path: impl AsRef<RelativePath>) -> crate::Result<Object> {
-        if path.as_ref() == *EMPTY_PATH {
-            return Err(crate::Error::InvalidPath);
-        }
-
-        let entry_handle = *self
-            .0
-            .state()
-            .get(path.as_ref())
-            .ok_or(crate::Error::NotFound)?;
+        self.inner().open(path)
+    }
 
-        if let EntryType::File(object_id) = entry_handle.entry_type {
-            Ok(self.0.object(object_id).unwrap())
-        } else {
-            Err(crate::Error::NotFile)
-        }
+    /// Return the size of the file at `path` in bytes.
+    ///
+    /// The size is recorded in the entry when the file is written by this repository, so this
+    /// does not need to access the contents of the file. Files in repositories created before
+    /// sizes were recorded fall back to reading the size of their contents.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry with the given `path`.
+    /// - `Error::NotFile`: The entry does not represent a regular file.
+    /// - `Error::TransactionInProgress`: The size is not recorded and the file is being written.
+    pub fn file_size(&self, path: impl AsRef<RelativePath>) -> crate::Result<u64> {
+        self.inner().file_size(path)
     }
 
-    /// Create and return a copy of the given `EntryHandle`.
-    fn copy_entry_handle(&mut self, handle: EntryHandle) -> EntryHandle {
-        let new_entry_id = self.0.copy(handle.entry).unwrap();
-        EntryHandle {
-            entry: new_entry_id,
-            entry_type: match handle.entry_type {
-                EntryType::File(file_id) => EntryType::File(self.0.copy(file_id).unwrap()),
-                EntryType::Directory => EntryType::Directory,
-                EntryType::Special => EntryType::Special,
-            },
-        }
+    /// Update the size of the file at `path` which is returned by [`file_size`].
+    ///
+    /// This must be called after modifying the file through the `Object` returned by [`open`].
+    /// This returns the new size of the file.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: There is no entry with the given `path`.
+    /// - `Error::NotFile`: The entry does not represent a regular file.
+    /// - `Error::TransactionInProgress`: The file has uncommitted changes.
+    ///
+    /// [`file_size`]: crate::repo::file::FileRepo::file_size
+    /// [`open`]: crate::repo::file::FileRepo::open
+    pub fn update_size(&self, path: impl AsRef<RelativePath>) -> crate::Result<u64> {
+        self.inner_mut().update_size(path)
     }
 
     /// Copy the entry at `source` to `dest`.
@@ -434,36 +454,16 @@ where
     /// - `Error::InvalidPath`: The given `source` or `dest` paths are empty.
     /// - `Error::NotFound`: There is no entry at `source`.
     /// - `Error::AlreadyExists`: There is already an entry at `dest`.
+    /// - `Error::QuotaExceeded`: The copy would exceed the quota of a directory.
     ///
     /// [`archive`]: crate::repo::file::FileRepo::archive
     /// [`extract`]: crate::repo::file::FileRepo::extract
     pub fn copy(
-        &mut self,
+        &self,
         source: impl AsRef<RelativePath>,
         dest: impl AsRef<RelativePath>,
     ) -> crate::Result<()> {
-        if source.as_ref() == *EMPTY_PATH || dest.as_ref() == *EMPTY_PATH {
-            return Err(crate::Error::InvalidPath);
-        }
-
-        if self.exists(dest.as_ref()) {
-            return Err(crate::Error::AlreadyExists);
-        }
-
-        if !self.has_parent(dest.as_ref()) {
-            return Err(crate::Error::InvalidPath);
-        }
-
-        let entry_handle = *self
-            .0
-            .state()
-            .get(source.as_ref())
-            .ok_or(crate::Error::NotFound)?;
-
-        let new_handle = self.copy_entry_handle(entry_handle);
-        self.0.state_mut().insert(dest.as_ref(), new_handle);
-
-        Ok(())
+        self.inner_mut().copy(source, dest)
     }
 
     /// Copy the tree of entries at `source` to `dest`.
@@ -474,41 +474,1083 @@ where
     /// file system to the repository, see [`archive_tree`]. To copy files from the repository to
     /// the file system, see [`extract_tree`].
     ///
-    /// This is a cheap operation which does not require copying the bytes in the files.
+    /// This is a cheap operation which does not require copying the bytes in the files. The
+    /// copied files share their data with the originals until one of them is modified, so this
+    /// can be used to take an instant snapshot of a directory regardless of its size. Hard links
+    /// between entries in the tree are preserved in the copy, but the copies are not hard links to
+    /// the originals.
     ///
     /// # Errors
     /// - `Error::InvalidPath`: The parent of `dest` does not exist or is not a directory.
     /// - `Error::InvalidPath`: The given `source` or `dest` paths are empty.
     /// - `Error::NotFound`: There is no entry at `source`.
     /// - `Error::AlreadyExists`: There is already an entry at `dest`.
+    /// - `Error::QuotaExceeded`: The copy would exceed the quota of a directory.
     ///
     /// [`archive_tree`]: crate::repo::file::FileRepo::archive
     /// [`extract_tree`]: crate::repo::file::FileRepo::extract
     pub fn copy_tree(
-        &mut self,
+        &self,
         source: impl AsRef<RelativePath>,
         dest: impl AsRef<RelativePath>,
     ) -> crate::Result<()> {
-        if source.as_ref() == *EMPTY_PATH || dest.as_ref() == *EMPTY_PATH {
-            return Err(crate::Error::InvalidPath);
-        }
+        self.inner_mut().copy_tree(source, dest)
+    }
 
-        if self.exists(dest.as_ref()) {
-            return Err(crate::Error::AlreadyExists);
-        }
+    /// Copy `len` bytes of the file at `source` starting at `source_offset` into the file at `dest`
+    /// starting at `dest_offset`.
+    ///
+    /// This returns the number of bytes copied, which is less than `len` if the end of `source` is
+    /// reached first. If `dest_offset` is past the end of `dest`, the gap is filled with a hole.
+    /// `source` and `dest` can be the same file.
+    ///
+    /// If both ranges start and end on chunk boundaries, like when copying a whole file into an
+    /// empty one, this is a cheap operation which shares chunks between the files like [`copy`]
+    /// does. Otherwise, the bytes are read from `source` and written to `dest`.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `source` or `dest` paths are empty.
+    /// - `Error::NotFound`: There is no entry at `source` or `dest`.
+    /// - `Error::NotFile`: The entry at `source` or `dest` does not represent a regular file.
+    /// - `Error::QuotaExceeded`: The copy would exceed the quota of a directory.
+    /// - `Error::TransactionInProgress`: The file at `dest` is being written through an `Object`.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred or the end of the copy would overflow a `u64`.
+    ///
+    /// [`copy`]: crate::repo::file::FileRepo::copy
+    pub fn copy_range(
+        &self,
+        source: impl AsRef<RelativePath>,
+        source_offset: u64,
+        dest: impl AsRef<RelativePath>,
+        dest_offset: u64,
+        len: u64,
+    ) -> crate::Result<u64> {
+        self.inner_mut()
+            .copy_range(source, source_offset, dest, dest_offset, len)
+    }
+
+    /// Move the tree of entries at `source` to `dest`.
+    ///
+    /// If `source` is a directory entry, its descendants are also moved. If there are hard links
+    /// between entries, they are preserved.
+    ///
+    /// This only changes the paths of the entries, so it takes the same amount of time regardless
+    /// of how many descendants `source` has or how large its files are.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The parent of `dest` does not exist or is not a directory.
+    /// - `Error::InvalidPath`: The given `source` or `dest` paths are empty.
+    /// - `Error::InvalidPath`: The given `dest` path is a descendant of `source`.
+    /// - `Error::NotFound`: There is no entry at `source`.
+    /// - `Error::AlreadyExists`: There is already an entry at `dest`.
+    /// - `Error::QuotaExceeded`: Moving the entries would exceed the quota of a directory.
+    pub fn rename(
+        &self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<RelativePath>,
+    ) -> crate::Result<()> {
+        self.inner_mut().rename(source, dest)
+    }
+
+    /// Return an iterator of paths which are children of `parent`.
+    ///
+    /// The given `parent` may be an empty path, in which case the paths of top-level entries are
+    /// returned.
+    ///
+    /// Like [`KeyRepo::keys`], this returns a snapshot which doesn't keep the repository locked.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: The given `parent` does not exist.
+    /// - `Error::NotDirectory`: The given `parent` is not a directory.
+    ///
+    /// [`KeyRepo::keys`]: crate::repo::key::KeyRepo::keys
+    pub fn list(
+        &self,
+        parent: impl AsRef<RelativePath>,
+    ) -> crate::Result<impl Iterator<Item = RelativePathBuf>> {
+        Ok(self.inner().list(parent)?.collect::<Vec<_>>().into_iter())
+    }
+
+    /// Return an iterator of paths which are descendants of `parent`.
+    ///
+    /// The given `parent` may be an empty path, in which case the paths of all entries in the
+    /// repository are returned.
+    ///
+    /// The returned iterator yields paths in depth-first order, meaning that a path will always
+    /// come before its children.
+    ///
+    /// Like [`KeyRepo::keys`], this returns a snapshot which doesn't keep the repository locked.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: The given `parent` does not exist.
+    /// - `Error::NotDirectory`: The given `parent` is not a directory.
+    ///
+    /// [`KeyRepo::keys`]: crate::repo::key::KeyRepo::keys
+    pub fn walk(
+        &self,
+        parent: impl AsRef<RelativePath>,
+    ) -> crate::Result<impl Iterator<Item = RelativePathBuf>> {
+        Ok(self.inner().walk(parent)?.collect::<Vec<_>>().into_iter())
+    }
+
+    /// Return an iterator of paths which are descendants of `parent` and are selected by `filter`.
+    ///
+    /// This is like [`walk`], except that paths are matched against `filter` relative to
+    /// `parent`, and the descendants of excluded directories are not returned.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: The given `parent` does not exist.
+    /// - `Error::NotDirectory`: The given `parent` is not a directory.
+    ///
+    /// [`walk`]: crate::repo::file::FileRepo::walk
+    pub fn walk_filtered(
+        &self,
+        parent: impl AsRef<RelativePath>,
+        filter: &PathFilter,
+    ) -> crate::Result<impl Iterator<Item = RelativePathBuf>> {
+        Ok(self
+            .inner()
+            .walk_filtered(parent, filter.clone())?
+            .collect::<Vec<_>>()
+            .into_iter())
+    }
+
+    /// Copy a file from the file system into the repository.
+    ///
+    /// If `source` is a directory, its descendants are not copied.
+    ///
+    /// The `source` file's metadata will be copied to the `dest` entry according to the selected
+    /// [`FileMetadata`] implementation.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The parent of `dest` does not exist or is not a directory.
+    /// - `Error::InvalidPath`: The given `dest` path is empty.
+    /// - `Error::AlreadyExists`: There is already an entry at `dest`.
+    /// - `Error::QuotaExceeded`: A file would exceed the quota of a directory.
+    /// - `Error::FileType`: The file at `source` is not a regular file, directory, or supported
+    ///   special file.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`FileMetadata`]: crate::repo::file::FileMetadata
+    pub fn archive(
+        &self,
+        source: impl AsRef<Path>,
+        dest: impl AsRef<RelativePath>,
+    ) -> crate::Result<()> {
+        self.inner_mut().archive(source, dest)
+    }
+
+    /// Copy a directory tree from the file system into the repository.
+    ///
+    /// If `source` is a directory, this also copies its descendants. If `source` is not a
+    /// directory, this is the same as calling [`archive`]. If one of the files in the tree is not a
+    /// regular file, directory, or supported special file, it is skipped.
+    ///
+    /// The `source` file's metadata will be copied to the `dest` entry according to the selected
+    /// [`FileMetadata`] implementation.
+    ///
+    /// If the repository was opened with more than one thread using [`OpenOptions::threads`], the
+    /// contents of multiple files are read and chunked concurrently on worker threads.
+    ///
+    /// On unix systems, files in the tree which are hard links to the same file are detected. The
+    /// contents of such files are only read once, and [`extract_tree`] recreates them as hard
+    /// links.
+    ///
+    /// On Linux and FreeBSD with the `file-metadata` feature enabled, holes in sparse files are
+    /// preserved in the repository without reading them, and [`extract_tree`] recreates them.
+    ///
+    /// This reports its progress to the handler set with [`set_progress_handler`] and can be
+    /// cancelled with the token set with [`set_cancellation_token`]. If this method returns `Err`,
+    /// the files which were archived before the error occurred remain in the repository.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The parent of `dest` does not exist or is not a directory.
+    /// - `Error::InvalidPath`: The given `dest` path is empty.
+    /// - `Error::AlreadyExists`: There is already an entry at `dest`.
+    /// - `Error::QuotaExceeded`: A file would exceed the quota of a directory.
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`archive`]: crate::repo::file::FileRepo::archive
+    /// [`FileMetadata`]: crate::repo::file::FileMetadata
+    /// [`OpenOptions::threads`]: crate::repo::OpenOptions::threads
+    /// [`extract_tree`]: crate::repo::file::FileRepo::extract_tree
+    /// [`set_progress_handler`]: crate::repo::file::FileRepo::set_progress_handler
+    /// [`set_cancellation_token`]: crate::repo::file::FileRepo::set_cancellation_token
+    pub fn archive_tree(
+        &self,
+        source: impl AsRef<Path>,
+        dest: impl AsRef<RelativePath>,
+    ) -> crate::Result<()> {
+        self.inner_mut().archive_tree(source, dest)
+    }
+
+    /// Copy a directory tree from the file system into the repository, skipping some files.
+    ///
+    /// This is like [`archive_tree`], except that only the files in the tree which are selected
+    /// by `filter` are copied. Paths are matched relative to `source`, and the descendants of
+    /// excluded directories are not visited.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The parent of `dest` does not exist or is not a directory.
+    /// - `Error::InvalidPath`: The given `dest` path is empty.
+    /// - `Error::AlreadyExists`: There is already an entry at `dest`.
+    /// - `Error::QuotaExceeded`: A file would exceed the quota of a directory.
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`archive_tree`]: crate::repo::file::FileRepo::archive_tree
+    pub fn archive_tree_filtered(
+        &self,
+        source: impl AsRef<Path>,
+        dest: impl AsRef<RelativePath>,
+        filter: &PathFilter,
+    ) -> crate::Result<()> {
+        self.inner_mut().archive_tree_filtered(source, dest, filter)
+    }
+
+    /// Copy a directory tree from the file system into the repository, reporting progress.
+    ///
+    /// This is like [`archive_tree`], except that it reports each file and the number of bytes
+    /// copied to `progress` and returns a [`TreeSummary`] describing the files which were copied.
+    ///
+    /// Unlike [`archive_tree`], if a file other than `source` itself can't be copied because of an
+    /// I/O error, such as a file which can't be read, this method records the file in
+    /// [`TreeSummary::failed`] and continues with the rest of the tree. The descendants of a
+    /// directory which could not be copied are also recorded as failed.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The parent of `dest` does not exist or is not a directory.
+    /// - `Error::InvalidPath`: The given `dest` path is empty.
+    /// - `Error::AlreadyExists`: There is already an entry at `dest`.
+    /// - `Error::QuotaExceeded`: A file would exceed the quota of a directory.
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`archive_tree`]: crate::repo::file::FileRepo::archive_tree
+    /// [`TreeSummary`]: crate::repo::file::TreeSummary
+    /// [`TreeSummary::failed`]: crate::repo::file::TreeSummary::failed
+    pub fn archive_tree_with(
+        &self,
+        source: impl AsRef<Path>,
+        dest: impl AsRef<RelativePath>,
+        progress: &mut impl TreeProgress,
+    ) -> crate::Result<TreeSummary> {
+        self.inner_mut().archive_tree_with(source, dest, progress)
+    }
+
+    /// Copy a directory tree from the file system into the repository with the given `options`.
+    ///
+    /// This is like [`archive_tree_with`], except that [`ArchiveOptions`] can be used to filter
+    /// the files in the tree, to follow symbolic links, and to only copy files which have changed.
+    ///
+    /// If [`ArchiveOptions::follow_links`] is set, symbolic links which point to one of their own
+    /// ancestors are recorded in [`TreeSummary::skipped`] instead of being followed.
+    ///
+    /// If [`ArchiveOptions::incremental`] is set, files which are unchanged since they were last
+    /// archived to `dest` are counted in [`TreeSummary::unchanged`] instead of being copied.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The parent of `dest` does not exist or is not a directory.
+    /// - `Error::InvalidPath`: The given `dest` path is empty.
+    /// - `Error::AlreadyExists`: There is already an entry at `dest` and the archive is not
+    ///   incremental.
+    /// - `Error::QuotaExceeded`: A file would exceed the quota of a directory.
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`archive_tree_with`]: crate::repo::file::FileRepo::archive_tree_with
+    /// [`ArchiveOptions`]: crate::repo::file::ArchiveOptions
+    /// [`ArchiveOptions::follow_links`]: crate::repo::file::ArchiveOptions::follow_links
+    /// [`TreeSummary::skipped`]: crate::repo::file::TreeSummary::skipped
+    /// [`ArchiveOptions::incremental`]: crate::repo::file::ArchiveOptions::incremental
+    /// [`TreeSummary::unchanged`]: crate::repo::file::TreeSummary::unchanged
+    pub fn archive_tree_with_options(
+        &self,
+        source: impl AsRef<Path>,
+        dest: impl AsRef<RelativePath>,
+        options: &ArchiveOptions,
+        progress: &mut impl TreeProgress,
+    ) -> crate::Result<TreeSummary> {
+        self.inner_mut()
+            .archive_tree_with_options(source, dest, options, progress)
+    }
+
+    /// Copy multiple directory trees from the file system into the repository.
+    ///
+    /// Each of the `trees` is a pair of a `source` path and a `dest` path, and each tree is copied
+    /// as if by [`archive_tree_with_options`]. This returns a single [`TreeSummary`] describing
+    /// the files in every tree.
+    ///
+    /// If the repository was opened with more than one thread using [`OpenOptions::threads`], the
+    /// trees are archived concurrently. The contents of files from every tree are read and
+    /// chunked by the same worker threads, so copying several small source directories keeps the
+    /// disk and every worker busy instead of waiting for each tree to finish before starting the
+    /// next one. Files which are hard links to the same file are detected across trees.
+    ///
+    /// The `dest` paths must be disjoint; no `dest` path can be the same as or a descendant of
+    /// another. This is checked before any files are copied.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: Two of the `dest` paths overlap.
+    /// - `Error::InvalidPath`: The parent of a `dest` path does not exist or is not a directory.
+    /// - `Error::InvalidPath`: One of the `dest` paths is empty.
+    /// - `Error::AlreadyExists`: There is already an entry at a `dest` path and the archive is not
+    ///   incremental.
+    /// - `Error::QuotaExceeded`: A file would exceed the quota of a directory.
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`archive_tree_with_options`]: crate::repo::file::FileRepo::archive_tree_with_options
+    /// [`TreeSummary`]: crate::repo::file::TreeSummary
+    /// [`OpenOptions::threads`]: crate::repo::OpenOptions::threads
+    pub fn archive_trees<P, D>(
+        &self,
+        trees: impl IntoIterator<Item = (P, D)>,
+        options: &ArchiveOptions,
+        progress: &mut impl TreeProgress,
+    ) -> crate::Result<TreeSummary>
+    where
+        P: AsRef<Path>,
+        D: AsRef<RelativePath>,
+    {
+        self.inner_mut().archive_trees(trees, options, progress)
+    }
+
+    /// Copy an entry from the repository into the file system.
+    ///
+    /// If `source` is a directory, its descendants are not copied.
+    ///
+    /// The `source` entry's metadata will be copied to the `dest` file according to the selected
+    /// [`FileMetadata`] implementation.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `source` path is empty.
+    /// - `Error::NotFound`: The `source` entry does not exist.
+    /// - `Error::AlreadyExists`: The `dest` file already exists.
+    /// - `Error::Deserialize`: The file metadata could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`FileMetadata`]: crate::repo::file::FileMetadata
+    pub fn extract(
+        &self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<Path>,
+    ) -> crate::Result<()> {
+        self.inner().extract(source, dest)
+    }
+
+    /// Copy a tree of entries from the repository into the file system.
+    ///
+    /// If `source` is a directory, this also copies its descendants. If `source` is not a
+    /// directory, this is the same as calling [`extract`].
+    ///
+    /// The `source` entry's metadata will be copied to the `dest` file according to the selected
+    /// [`FileMetadata`] implementation.
+    ///
+    /// Entries which were archived as hard links to the same file are extracted as hard links, as
+    /// long as their contents have not been changed since they were archived.
+    ///
+    /// Holes in files are recreated as holes in the extracted files if the file system supports
+    /// sparse files.
+    ///
+    /// This reports its progress to the handler set with [`set_progress_handler`] and can be
+    /// cancelled with the token set with [`set_cancellation_token`]. If this method returns `Err`,
+    /// the files which were extracted before the error occurred remain in the file system.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `source` path is empty.
+    /// - `Error::NotFound`: The `source` entry does not exist.
+    /// - `Error::AlreadyExists`: The `dest` file already exists.
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::Deserialize`: The file metadata could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`extract`]: crate::repo::file::FileRepo::extract
+    /// [`FileMetadata`]: crate::repo::file::FileMetadata
+    /// [`set_progress_handler`]: crate::repo::file::FileRepo::set_progress_handler
+    /// [`set_cancellation_token`]: crate::repo::file::FileRepo::set_cancellation_token
+    pub fn extract_tree(
+        &self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<Path>,
+    ) -> crate::Result<()> {
+        self.inner().extract_tree(source, dest)
+    }
+
+    /// Copy a tree of entries from the repository into the file system, skipping some entries.
+    ///
+    /// This is like [`extract_tree`], except that only the entries in the tree which are selected
+    /// by `filter` are copied. Paths are matched relative to `source`, and the descendants of
+    /// excluded directories are not visited.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `source` path is empty.
+    /// - `Error::NotFound`: The `source` entry does not exist.
+    /// - `Error::AlreadyExists`: The `dest` file already exists.
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::Deserialize`: The file metadata could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`extract_tree`]: crate::repo::file::FileRepo::extract_tree
+    pub fn extract_tree_filtered(
+        &self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<Path>,
+        filter: &PathFilter,
+    ) -> crate::Result<()> {
+        self.inner().extract_tree_filtered(source, dest, filter)
+    }
+
+    /// Copy a tree of entries from the repository into the file system, reporting progress.
+    ///
+    /// This is like [`extract_tree`], except that it reports each entry and the number of bytes
+    /// copied to `progress` and returns a [`TreeSummary`] describing the entries which were
+    /// copied.
+    ///
+    /// Unlike [`extract_tree`], if an entry other than `source` itself can't be copied because of
+    /// an I/O error, such as a file which can't be created, this method records the entry in
+    /// [`TreeSummary::failed`] and continues with the rest of the tree.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `source` path is empty.
+    /// - `Error::NotFound`: The `source` entry does not exist.
+    /// - `Error::AlreadyExists`: The `dest` file already exists.
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::Deserialize`: The file metadata could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`extract_tree`]: crate::repo::file::FileRepo::extract_tree
+    /// [`TreeSummary`]: crate::repo::file::TreeSummary
+    /// [`TreeSummary::failed`]: crate::repo::file::TreeSummary::failed
+    pub fn extract_tree_with(
+        &self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<Path>,
+        progress: &mut impl TreeProgress,
+    ) -> crate::Result<TreeSummary> {
+        self.inner().extract_tree_with(source, dest, progress)
+    }
+
+    /// Copy a tree of entries from the repository into the file system with the given `options`.
+    ///
+    /// This is like [`extract_tree_with`], except that [`ExtractOptions`] can be used to filter
+    /// the entries in the tree and to change the owner of the extracted files.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `source` path is empty.
+    /// - `Error::NotFound`: The `source` entry does not exist.
+    /// - `Error::AlreadyExists`: The `dest` file already exists.
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::Deserialize`: The file metadata could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`extract_tree_with`]: crate::repo::file::FileRepo::extract_tree_with
+    /// [`ExtractOptions`]: crate::repo::file::ExtractOptions
+    pub fn extract_tree_with_options(
+        &self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<Path>,
+        options: &ExtractOptions,
+        progress: &mut impl TreeProgress,
+    ) -> crate::Result<TreeSummary> {
+        self.inner()
+            .extract_tree_with_options(source, dest, options, progress)
+    }
+
+    /// Verify the integrity of all the data in the repository.
+    ///
+    /// This returns the set of paths of files with corrupt data or metadata.
+    ///
+    /// If you just need to verify the integrity of one object, [`Object::verify`] is faster. If you
+    /// need to verify the integrity of all the data in the repository, however, this can be more
+    /// efficient.
+    ///
+    /// # Errors
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`Object::verify`]: crate::repo::Object::verify
+    pub fn verify(&self) -> crate::Result<HashSet<RelativePathBuf>> {
+        self.inner().verify()
+    }
+
+    /// Return the paths which have changed since the last commit.
+    ///
+    /// This includes entries which have been created or removed, files whose contents have been
+    /// modified, and entries whose metadata or attributes have changed.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize data from the previous commit.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn changed_paths(&self) -> crate::Result<HashSet<RelativePathBuf>> {
+        self.inner().changed_paths()
+    }
+
+    /// Return whether the repository has changed since the last commit.
+    ///
+    /// See [`changed_paths`] for details.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize data from the previous commit.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`changed_paths`]: crate::repo::file::FileRepo::changed_paths
+    pub fn has_changes(&self) -> crate::Result<bool> {
+        self.inner().has_changes()
+    }
+
+    /// Convert this repository into a `KeyRepo` which maps paths to file contents.
+    ///
+    /// Each regular file in this repository becomes an object in the returned repository whose key
+    /// is the file's path. This reuses the objects in place, so it does not require copying the
+    /// bytes in any files. Directories, special files, and file metadata are discarded.
+    ///
+    /// This changes the type of the repository stored in the current instance, but it does not
+    /// commit changes. To undo the conversion, drop the returned repository without committing it.
+    ///
+    /// # Errors
+    /// - `Error::UnsupportedRepo`: The current instance has more than one branch.
+    pub fn into_key_repo(self) -> crate::Result<KeyRepo<String>> {
+        self.into_inner().into_key_repo()
+    }
+
+    /// Delete all data in the current instance of the repository.
+    ///
+    /// See [`KeyRepo::clear_instance`] for details.
+    ///
+    /// [`KeyRepo::clear_instance`]: crate::repo::key::KeyRepo::clear_instance
+    pub fn clear_instance(&self) {
+        self.inner_mut().clear_instance()
+    }
+
+    /// Change the password for this repository.
+    ///
+    /// See [`KeyRepo::change_password`] for details.
+    ///
+    /// [`KeyRepo::change_password`]: crate::repo::key::KeyRepo::change_password
+    pub fn change_password(&self, new_password: &[u8]) {
+        self.inner_mut().change_password(new_password)
+    }
+
+    /// Renew the lease on this repository.
+    ///
+    /// See [`KeyRepo::renew_lease`] for details.
+    ///
+    /// [`KeyRepo::renew_lease`]: crate::repo::key::KeyRepo::renew_lease
+    pub fn renew_lease(&self) -> crate::Result<()> {
+        self.inner().renew_lease()
+    }
+
+    /// Register a `hook` to run before the given `event`.
+    ///
+    /// See [`KeyRepo::add_before_hook`] for details.
+    ///
+    /// [`KeyRepo::add_before_hook`]: crate::repo::key::KeyRepo::add_before_hook
+    pub fn add_before_hook(
+        &self,
+        event: TransactionEvent,
+        hook: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.inner_mut().add_before_hook(event, hook)
+    }
+
+    /// Register a `hook` to run after the given `event` completes successfully.
+    ///
+    /// See [`KeyRepo::add_after_hook`] for details.
+    ///
+    /// [`KeyRepo::add_after_hook`]: crate::repo::key::KeyRepo::add_after_hook
+    pub fn add_after_hook(&self, event: TransactionEvent, hook: impl FnMut() + Send + 'static) {
+        self.inner_mut().add_after_hook(event, hook)
+    }
+
+    /// Set a `handler` which is called with the progress of long-running operations.
+    ///
+    /// See [`KeyRepo::set_progress_handler`] for details.
+    ///
+    /// [`KeyRepo::set_progress_handler`]: crate::repo::key::KeyRepo::set_progress_handler
+    pub fn set_progress_handler(&self, handler: impl Fn(Progress) + Send + Sync + 'static) {
+        self.inner_mut().set_progress_handler(handler)
+    }
+
+    /// Set a `token` which can be used to cancel long-running operations.
+    ///
+    /// See [`KeyRepo::set_cancellation_token`] for details.
+    ///
+    /// [`KeyRepo::set_cancellation_token`]: crate::repo::key::KeyRepo::set_cancellation_token
+    pub fn set_cancellation_token(&self, token: CancellationToken) {
+        self.inner_mut().set_cancellation_token(token)
+    }
+
+    /// Return this repository's instance ID.
+    pub fn instance(&self) -> Uuid {
+        self.inner().instance()
+    }
+
+    /// Return the name of the current branch.
+    ///
+    /// See [`KeyRepo::branch`] for details.
+    ///
+    /// [`KeyRepo::branch`]: crate::repo::key::KeyRepo::branch
+    pub fn branch(&self) -> String {
+        self.inner().branch().to_string()
+    }
+
+    /// Return an iterator over the names of all the branches of the current instance.
+    ///
+    /// See [`KeyRepo::branches`] for details.
+    ///
+    /// [`KeyRepo::branches`]: crate::repo::key::KeyRepo::branches
+    pub fn branches(&self) -> impl Iterator<Item = String> {
+        self.inner()
+            .branches()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Remove the branch named `name` and all of its contents.
+    ///
+    /// See [`KeyRepo::remove_branch`] for details.
+    ///
+    /// [`KeyRepo::remove_branch`]: crate::repo::key::KeyRepo::remove_branch
+    pub fn remove_branch(&self, name: &str) -> crate::Result<()> {
+        self.inner_mut().remove_branch(name)
+    }
+
+    /// Return information about the repository.
+    pub fn info(&self) -> RepoInfo {
+        self.inner().info()
+    }
+
+    /// Return statistics about the operations performed by the repository since it was opened.
+    ///
+    /// See [`KeyRepo::stats`] for details.
+    ///
+    /// [`KeyRepo::stats`]: crate::repo::key::KeyRepo::stats
+    pub fn stats(&self) -> RepoStats {
+        self.inner().stats()
+    }
+
+    /// Reset the statistics returned by [`stats`] to zero.
+    ///
+    /// [`stats`]: crate::repo::file::FileRepo::stats
+    pub fn reset_stats(&self) {
+        self.inner().reset_stats()
+    }
+
+    /// Report what cleaning the repository would do without modifying the data store.
+    ///
+    /// See [`KeyRepo::clean_dry_run`] for details.
+    ///
+    /// [`KeyRepo::clean_dry_run`]: crate::repo::key::KeyRepo::clean_dry_run
+    pub fn clean_dry_run(&self) -> crate::Result<CleanReport> {
+        self.inner().clean_dry_run()
+    }
+
+    /// Reconstruct blocks in the data store which are missing or corrupt using parity blocks.
+    ///
+    /// See [`KeyRepo::repair`] for details.
+    ///
+    /// [`KeyRepo::repair`]: crate::repo::key::KeyRepo::repair
+    pub fn repair(&self) -> crate::Result<RepairReport> {
+        self.inner_mut().repair()
+    }
+
+    /// Return the entries in the audit log in the order they were recorded.
+    ///
+    /// See [`KeyRepo::audit_log`] for details.
+    ///
+    /// [`KeyRepo::audit_log`]: crate::repo::key::KeyRepo::audit_log
+    pub fn audit_log(&self) -> crate::Result<Vec<AuditEntry>> {
+        self.inner().audit_log()
+    }
+}
+
+impl<S, M> FileRepoInner<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    pub(crate) fn exists(&self, path: impl AsRef<RelativePath>) -> bool {
+        self.0.state().contains(path.as_ref())
+    }
+
+    pub(crate) fn is_file(&self, path: impl AsRef<RelativePath>) -> bool {
+        match self.0.state().get(path.as_ref()) {
+            Some(entry) => matches!(entry.entry_type, EntryType::File(_)),
+            None => false,
+        }
+    }
+
+    pub(crate) fn is_directory(&self, path: impl AsRef<RelativePath>) -> bool {
+        match self.0.state().get(path.as_ref()) {
+            Some(entry) => matches!(entry.entry_type, EntryType::Directory),
+            None => false,
+        }
+    }
+
+    pub(crate) fn is_special(&self, path: impl AsRef<RelativePath>) -> bool {
+        match self.0.state().get(path.as_ref()) {
+            Some(entry) => matches!(entry.entry_type, EntryType::Special),
+            None => false,
+        }
+    }
+
+    /// Return `true` if the given `path` has a parent directory in the repository.
+    fn has_parent(&self, path: &RelativePath) -> bool {
+        match path.parent() {
+            Some(parent) if parent != *EMPTY_PATH => match self.0.state().get(parent) {
+                Some(handle) => matches!(handle.entry_type, EntryType::Directory),
+                None => false,
+            },
+            _ => true,
+        }
+    }
+
+    pub(crate) fn create(
+        &mut self,
+        path: impl AsRef<RelativePath>,
+        entry: &Entry<S, M>,
+    ) -> crate::Result<()> {
+        if path.as_ref() == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        if self.exists(&path) {
+            return Err(crate::Error::AlreadyExists);
+        }
+
+        if !self.has_parent(path.as_ref()) {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        let entry_id = self.0.create();
+        let mut object = self.0.object(entry_id).unwrap();
+        let result = object.serialize(entry);
+        drop(object);
+        if let Err(error) = result {
+            self.0.remove(entry_id);
+            return Err(error);
+        }
+
+        let entry_type = match entry.file_type {
+            FileType::File => EntryType::File(self.0.create()),
+            FileType::Directory => EntryType::Directory,
+            FileType::Special(_) => EntryType::Special,
+        };
+
+        let handle = EntryHandle {
+            entry: entry_id,
+            entry_type,
+            link: None,
+            size: if entry.is_file() { Some(0) } else { None },
+            attributes: None,
+            quota: None,
+            inode: None,
+            generation: 0,
+        };
+
+        self.tree_mut().insert(path.as_ref(), handle);
+
+        Ok(())
+    }
+
+    pub(crate) fn create_parents(
+        &mut self,
+        path: impl AsRef<RelativePath>,
+        entry: &Entry<S, M>,
+    ) -> crate::Result<()> {
+        let parent = match path.as_ref().parent() {
+            Some(parent) if parent != *EMPTY_PATH => parent,
+            _ => return self.create(path, entry),
+        };
+
+        let mut ancestor = RelativePathBuf::new();
+        for component in parent.iter() {
+            ancestor.push(component);
+            match self.create(&ancestor, &Entry::directory()) {
+                Err(crate::Error::AlreadyExists) => (),
+                Err(error) => return Err(error),
+                _ => (),
+            }
+        }
+
+        self.create(path, entry)
+    }
+
+    pub(crate) fn remove(&mut self, path: impl AsRef<RelativePath>) -> crate::Result<()> {
+        if path.as_ref() == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        match self.0.state().list(&path) {
+            Some(mut children) => {
+                if children.next().is_some() {
+                    return Err(crate::Error::NotEmpty);
+                }
+            }
+            None => return Err(crate::Error::NotFound),
+        }
+
+        let entry_handle = self.tree_mut().remove(path.as_ref()).unwrap();
+        self.free_entry(entry_handle);
+
+        Ok(())
+    }
+
+    pub(crate) fn remove_tree(&mut self, path: impl AsRef<RelativePath>) -> crate::Result<()> {
+        if path.as_ref() == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        let handles = self
+            .tree_mut()
+            .drain(path.as_ref())
+            .ok_or(crate::Error::NotFound)?
+            .map(|(_, handle)| handle)
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            self.free_entry(handle);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn prune_empty_dirs(
+        &mut self,
+        parent: impl AsRef<RelativePath>,
+    ) -> crate::Result<Vec<RelativePathBuf>> {
+        let directories = self
+            .walk(parent)?
+            .filter(|path| self.is_directory(path))
+            .collect::<Vec<_>>();
+
+        // Children are visited before their parents so that directories which only contain empty
+        // directories are removed as well.
+        let mut removed = Vec::new();
+        for directory in directories.into_iter().rev() {
+            if self.0.state().list(&directory).unwrap().next().is_none() {
+                self.remove(&directory)?;
+                removed.push(directory);
+            }
+        }
+        removed.reverse();
+
+        Ok(removed)
+    }
+
+    pub(crate) fn entry(&self, path: impl AsRef<RelativePath>) -> crate::Result<Entry<S, M>> {
+        if path.as_ref() == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+        let entry_handle = &self
+            .0
+            .state()
+            .get(path.as_ref())
+            .ok_or(crate::Error::NotFound)?;
+        let mut object = self.0.object(entry_handle.entry).unwrap();
+        object.deserialize()
+    }
+
+    pub(crate) fn set_metadata(
+        &mut self,
+        path: impl AsRef<RelativePath>,
+        metadata: Option<M>,
+    ) -> crate::Result<()> {
+        if path.as_ref() == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        let entry_handle = *self
+            .0
+            .state()
+            .get(path.as_ref())
+            .ok_or(crate::Error::NotFound)?;
+        let mut object = self.0.object(entry_handle.entry).unwrap();
+        let mut entry: Entry<S, M> = object.deserialize()?;
+        entry.metadata = metadata;
+        object.serialize(&entry)
+    }
+
+    pub(crate) fn update_metadata(
+        &mut self,
+        path: impl AsRef<RelativePath>,
+        update: impl FnOnce(&mut M),
+    ) -> crate::Result<bool> {
+        if path.as_ref() == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        let entry_handle = *self
+            .0
+            .state()
+            .get(path.as_ref())
+            .ok_or(crate::Error::NotFound)?;
+        let mut object = self.0.object(entry_handle.entry).unwrap();
+        let mut entry: Entry<S, M> = object.deserialize()?;
+        match &mut entry.metadata {
+            Some(metadata) => update(metadata),
+            None => return Ok(false),
+        }
+        object.serialize(&entry)?;
+
+        Ok(true)
+    }
+
+    pub(crate) fn open(&self, path: impl AsRef<RelativePath>) -> crate::Result<Object> {
+        let object_id = self.file_object(path.as_ref())?;
+        Ok(self.0.object(object_id).unwrap())
+    }
+
+    pub(crate) fn file_size(&self, path: impl AsRef<RelativePath>) -> crate::Result<u64> {
+        if path.as_ref() == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        let entry_handle = *self
+            .0
+            .state()
+            .get(path.as_ref())
+            .ok_or(crate::Error::NotFound)?;
+
+        match (entry_handle.entry_type, entry_handle.size) {
+            (EntryType::File(_), Some(size)) => Ok(size),
+            (EntryType::File(object_id), None) => self.0.object(object_id).unwrap().size(),
+            _ => Err(crate::Error::NotFile),
+        }
+    }
+
+    pub(crate) fn update_size(&mut self, path: impl AsRef<RelativePath>) -> crate::Result<u64> {
+        let size = self.open(&path)?.size()?;
+        self.record_size(path.as_ref(), size);
+        Ok(size)
+    }
+
+    /// Record `size` as the size of the file at `path`.
+    ///
+    /// This does nothing if there is no file at `path`.
+    pub(super) fn record_size(&mut self, path: &RelativePath, size: u64) {
+        // This doesn't change which entries exist, so the cached usage of directories with quotas
+        // can be updated instead of cleared.
+        if let Some(handle) = self.0.state_mut().get_mut(path) {
+            if let EntryType::File(_) = handle.entry_type {
+                let old_size = handle.size.replace(size);
+                self.update_cached_usage(path, old_size, size);
+            }
+        }
+    }
+
+    /// Create and return a copy of the given `EntryHandle`.
+    fn copy_entry_handle(&mut self, handle: EntryHandle) -> EntryHandle {
+        let new_entry_id = self.0.copy(handle.entry).unwrap();
+        EntryHandle {
+            entry: new_entry_id,
+            entry_type: match handle.entry_type {
+                EntryType::File(file_id) => EntryType::File(self.0.copy(file_id).unwrap()),
+                EntryType::Directory => EntryType::Directory,
+                EntryType::Special => EntryType::Special,
+            },
+            link: None,
+            size: handle.size,
+            attributes: handle
+                .attributes
+                .map(|object_id| self.0.copy(object_id).unwrap()),
+            quota: handle.quota,
+            // The copy is a different file, so it needs a different inode.
+            inode: None,
+            generation: 0,
+        }
+    }
+
+    pub(crate) fn copy(
+        &mut self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<RelativePath>,
+    ) -> crate::Result<()> {
+        if source.as_ref() == *EMPTY_PATH || dest.as_ref() == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        if self.exists(dest.as_ref()) {
+            return Err(crate::Error::AlreadyExists);
+        }
+
+        if !self.has_parent(dest.as_ref()) {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        let entry_handle = *self
+            .0
+            .state()
+            .get(source.as_ref())
+            .ok_or(crate::Error::NotFound)?;
+
+        self.check_quota(dest.as_ref(), self.handle_size(&entry_handle)?)?;
+
+        let new_handle = self.copy_entry_handle(entry_handle);
+        self.tree_mut().insert(dest.as_ref(), new_handle);
+
+        Ok(())
+    }
+
+    pub(crate) fn copy_tree(
+        &mut self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<RelativePath>,
+    ) -> crate::Result<()> {
+        if source.as_ref() == *EMPTY_PATH || dest.as_ref() == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        if self.exists(dest.as_ref()) {
+            return Err(crate::Error::AlreadyExists);
+        }
 
         if !self.has_parent(dest.as_ref()) {
             return Err(crate::Error::InvalidPath);
         }
 
-        // Copy the root path.
         let source_root_handle = *self
             .0
             .state()
             .get(source.as_ref())
             .ok_or(crate::Error::NotFound)?;
-        let dest_root_handle = self.copy_entry_handle(source_root_handle);
-        self.0.state_mut().insert(dest.as_ref(), dest_root_handle);
+
+        self.check_quota(dest.as_ref(), self.tree_size(source.as_ref())?)?;
 
         // Because we can't walk the path tree and insert into it at the same time, we need to
         // construct a tree of the destination paths before inserting them back into the path table.
@@ -529,26 +1571,120 @@ where
             dest_tree.insert(dest_tree_path, *source_handle);
         }
 
-        // Move the rest of the paths from the destination tree into the path table.
+        // Entries which are hard links to each other in the source tree should be hard links to
+        // each other in the copy, but not to the originals.
+        let mut link_ids = HashMap::new();
+
+        // Move the paths from the destination tree into the path table. This happens after walking
+        // the source tree so that copying a tree into one of its own descendants doesn't also copy
+        // the new entries.
         for (dest_tree_path, source_handle) in dest_tree.drain(dest_tree_root).unwrap() {
-            let dest_handle = self.copy_entry_handle(source_handle);
+            let mut dest_handle = self.copy_entry_handle(source_handle);
+            dest_handle.link = source_handle
+                .link
+                .map(|link_id| *link_ids.entry(link_id).or_insert_with(Uuid::new_v4));
             let relative_path = dest_tree_path.strip_prefix(dest_tree_root).unwrap();
             let dest_path = dest.as_ref().join(relative_path);
-            self.0.state_mut().insert(&dest_path, dest_handle);
+            self.tree_mut().insert(&dest_path, dest_handle);
         }
 
         Ok(())
     }
 
-    /// Return an iterator of paths which are children of `parent`.
-    ///
-    /// The given `parent` may be an empty path, in which case the paths of top-level entries are
-    /// returned.
-    ///
-    /// # Errors
-    /// - `Error::NotFound`: The given `parent` does not exist.
-    /// - `Error::NotDirectory`: The given `parent` is not a directory.
-    pub fn list<'a>(
+    /// Return the key of the object which stores the contents of the file at `path`.
+    fn file_object(&self, path: &RelativePath) -> crate::Result<ObjectKey> {
+        if path == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        let entry_handle = self.0.state().get(path).ok_or(crate::Error::NotFound)?;
+        match entry_handle.entry_type {
+            EntryType::File(object_id) => Ok(object_id),
+            _ => Err(crate::Error::NotFile),
+        }
+    }
+
+    pub(crate) fn copy_range(
+        &mut self,
+        source: impl AsRef<RelativePath>,
+        source_offset: u64,
+        dest: impl AsRef<RelativePath>,
+        dest_offset: u64,
+        len: u64,
+    ) -> crate::Result<u64> {
+        let (source, dest) = (source.as_ref(), dest.as_ref());
+        let source_id = self.file_object(source)?;
+        let dest_id = self.file_object(dest)?;
+
+        let source_size = self.file_size(source)?;
+        let dest_size = self.file_size(dest)?;
+        let len = len.min(source_size.saturating_sub(source_offset));
+        if len == 0 {
+            return Ok(0);
+        }
+        let dest_end = dest_offset
+            .checked_add(len)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        self.check_quota(dest, dest_end.saturating_sub(dest_size))?;
+
+        let shared = self
+            .0
+            .copy_range(source_id, source_offset, dest_id, dest_offset, len)?;
+
+        if shared.is_none() {
+            // The chunks can't be shared, so we need to copy the bytes. Changes to `dest` aren't
+            // visible until they're committed, so this works even if the ranges overlap.
+            let mut source_object = self.0.object(source_id).unwrap();
+            let mut dest_object = self.0.object(dest_id).unwrap();
+            if dest_offset > dest_size {
+                dest_object.set_len(dest_offset)?;
+            }
+            source_object.seek(SeekFrom::Start(source_offset))?;
+            dest_object.seek(SeekFrom::Start(dest_offset))?;
+            io::copy(&mut source_object.take(len), &mut dest_object)?;
+            dest_object.commit()?;
+        }
+
+        if dest_end > dest_size {
+            self.record_size(dest, dest_end);
+        }
+
+        Ok(len)
+    }
+
+    pub(crate) fn rename(
+        &mut self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<RelativePath>,
+    ) -> crate::Result<()> {
+        let (source, dest) = (source.as_ref(), dest.as_ref());
+
+        if source == *EMPTY_PATH || dest == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        if !self.exists(source) {
+            return Err(crate::Error::NotFound);
+        }
+
+        if self.exists(dest) {
+            return Err(crate::Error::AlreadyExists);
+        }
+
+        let state = self.0.state();
+        if !self.has_parent(dest) || state.normalize(dest).starts_with(state.normalize(source)) {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        // Directories which contain both `source` and `dest` already count the moved files.
+        self.check_quotas_except(dest, self.tree_size(source)?, Some(source))?;
+
+        self.tree_mut().rename(source, dest);
+
+        Ok(())
+    }
+
+    pub(crate) fn list<'a>(
         &'a self,
         parent: impl AsRef<RelativePath> + 'a,
     ) -> crate::Result<impl Iterator<Item = RelativePathBuf> + 'a> {
@@ -566,18 +1702,7 @@ where
         Ok(self.0.state().list(parent).unwrap().map(|(path, _)| path))
     }
 
-    /// Return an iterator of paths which are descendants of `parent`.
-    ///
-    /// The given `parent` may be an empty path, in which case the paths of all entries in the
-    /// repository are returned.
-    ///
-    /// The returned iterator yields paths in depth-first order, meaning that a path will always
-    /// come before its children.
-    ///
-    /// # Errors
-    /// - `Error::NotFound`: The given `parent` does not exist.
-    /// - `Error::NotDirectory`: The given `parent` is not a directory.
-    pub fn walk<'a>(
+    pub(crate) fn walk<'a>(
         &'a self,
         parent: impl AsRef<RelativePath> + 'a,
     ) -> crate::Result<impl Iterator<Item = RelativePathBuf> + 'a> {
@@ -595,232 +1720,732 @@ where
         Ok(self.0.state().walk(parent).unwrap().map(|(path, _)| path))
     }
 
-    /// Copy a file from the file system into the repository.
-    ///
-    /// If `source` is a directory, its descendants are not copied.
-    ///
-    /// The `source` file's metadata will be copied to the `dest` entry according to the selected
-    /// [`FileMetadata`] implementation.
-    ///
-    /// # Errors
-    /// - `Error::InvalidPath`: The parent of `dest` does not exist or is not a directory.
-    /// - `Error::InvalidPath`: The given `dest` path is empty.
-    /// - `Error::AlreadyExists`: There is already an entry at `dest`.
-    /// - `Error::FileType`: The file at `source` is not a regular file, directory, or supported
-    /// special file.
-    /// - `Error::InvalidData`: Ciphertext verification failed.
-    /// - `Error::Store`: An error occurred with the data store.
-    /// - `Error::Io`: An I/O error occurred.
-    ///
-    /// [`FileMetadata`]: crate::repo::file::FileMetadata
-    pub fn archive(
+    pub(crate) fn walk_filtered<'a>(
+        &'a self,
+        parent: impl AsRef<RelativePath> + 'a,
+        filter: PathFilter,
+    ) -> crate::Result<impl Iterator<Item = RelativePathBuf> + 'a> {
+        let parent_path = parent.as_ref().to_owned();
+        let mut tree_filter = filter.into_tree_filter();
+        let all_paths = self.walk(parent)?;
+
+        Ok(all_paths.filter(move |path| {
+            let relative_path = path.strip_prefix(&parent_path).unwrap();
+            tree_filter.is_match(relative_path, self.is_directory(path))
+        }))
+    }
+
+    pub(crate) fn archive(
         &mut self,
         source: impl AsRef<Path>,
         dest: impl AsRef<RelativePath>,
     ) -> crate::Result<()> {
-        if dest.as_ref() == *EMPTY_PATH {
+        self.archive_entry(source.as_ref(), dest.as_ref(), false, &mut ())?;
+        Ok(())
+    }
+
+    /// Copy a file from the file system into the repository, reporting progress to `progress`.
+    ///
+    /// If `follow_links` is `true` and `source` is a symbolic link, the file it points to is
+    /// copied instead. This returns the number of bytes of file contents which were copied.
+    pub(super) fn archive_entry(
+        &mut self,
+        source: &Path,
+        dest: &RelativePath,
+        follow_links: bool,
+        progress: &mut dyn TreeProgress,
+    ) -> crate::Result<u64> {
+        // Write the contents of the file entry if it's a file.
+        let mut bytes_copied = 0;
+        if let Some(object_id) = self.create_archived_entry(source, dest, follow_links, progress)? {
+            bytes_copied = self.archive_contents(source, dest, object_id, progress)?;
+        }
+
+        progress.entry_finished(dest);
+
+        Ok(bytes_copied)
+    }
+
+    /// Copy the contents of the file at `source` into the object with the given `object_id`.
+    ///
+    /// Any holes in the file are preserved. This returns the number of bytes of file contents
+    /// which were copied, which does not include holes.
+    pub(super) fn archive_contents(
+        &mut self,
+        source: &Path,
+        dest: &RelativePath,
+        object_id: ObjectKey,
+        progress: &mut dyn TreeProgress,
+    ) -> crate::Result<u64> {
+        let mut object = self.0.object(object_id).unwrap();
+        let mut file = File::open(source)?;
+        let bytes_copied = copy_to_object(&mut file, &mut object, |bytes| {
+            progress.bytes_copied(dest, bytes)
+        })?;
+        let size = object.size()?;
+        drop(object);
+        self.record_size(dest, size);
+        Ok(bytes_copied)
+    }
+
+    /// Create an entry at `dest` for the file at `source` without copying its contents.
+    ///
+    /// If `follow_links` is `true` and `source` is a symbolic link, the entry is created for the
+    /// file it points to instead. If the new entry is a regular file, this returns the ID of the
+    /// object which its contents should be written to.
+    fn create_archived_entry(
+        &mut self,
+        source: &Path,
+        dest: &RelativePath,
+        follow_links: bool,
+        progress: &mut dyn TreeProgress,
+    ) -> crate::Result<Option<ObjectKey>> {
+        if dest == *EMPTY_PATH {
             return Err(crate::Error::InvalidPath);
         }
 
-        if self.exists(&dest) {
+        if self.exists(dest) {
             return Err(crate::Error::AlreadyExists);
         }
 
-        let file_metadata = metadata(&source)?;
+        // Resolve the link so the special file type and metadata are those of its target.
+        let resolved_source;
+        let source = if follow_links {
+            resolved_source = canonicalize(source)?;
+            resolved_source.as_path()
+        } else {
+            source
+        };
+
+        let file_metadata = metadata(source)?;
 
         let file_type = if file_metadata.is_file() {
+            self.check_quota(dest, file_metadata.len())?;
             FileType::File
         } else if file_metadata.is_dir() {
             FileType::Directory
         } else {
-            FileType::Special(S::from_file(source.as_ref())?.ok_or(crate::Error::FileType)?)
+            FileType::Special(S::from_file(source)?.ok_or(crate::Error::FileType)?)
+        };
+
+        let entry = Entry {
+            file_type,
+            metadata: Some(M::from_file(source)?),
         };
 
-        let entry = Entry {
-            file_type,
-            metadata: Some(M::from_file(source.as_ref())?),
-        };
+        progress.entry_started(dest);
+
+        self.create(dest, &entry)?;
+
+        match self.0.state().get(dest).unwrap().entry_type {
+            EntryType::File(object_id) => Ok(Some(object_id)),
+            _ => Ok(None),
+        }
+    }
+
+    pub(crate) fn archive_tree(
+        &mut self,
+        source: impl AsRef<Path>,
+        dest: impl AsRef<RelativePath>,
+    ) -> crate::Result<()> {
+        self.archive_tree_impl(
+            source.as_ref(),
+            dest.as_ref(),
+            &ArchiveOptions::new(),
+            &mut (),
+            false,
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn archive_tree_filtered(
+        &mut self,
+        source: impl AsRef<Path>,
+        dest: impl AsRef<RelativePath>,
+        filter: &PathFilter,
+    ) -> crate::Result<()> {
+        let mut options = ArchiveOptions::new();
+        options.filter(filter.clone());
+        self.archive_tree_impl(source.as_ref(), dest.as_ref(), &options, &mut (), false)?;
+        Ok(())
+    }
+
+    pub(crate) fn archive_tree_with(
+        &mut self,
+        source: impl AsRef<Path>,
+        dest: impl AsRef<RelativePath>,
+        progress: &mut impl TreeProgress,
+    ) -> crate::Result<TreeSummary> {
+        self.archive_tree_impl(
+            source.as_ref(),
+            dest.as_ref(),
+            &ArchiveOptions::new(),
+            progress,
+            true,
+        )
+    }
+
+    pub(crate) fn archive_tree_with_options(
+        &mut self,
+        source: impl AsRef<Path>,
+        dest: impl AsRef<RelativePath>,
+        options: &ArchiveOptions,
+        progress: &mut impl TreeProgress,
+    ) -> crate::Result<TreeSummary> {
+        self.archive_tree_impl(source.as_ref(), dest.as_ref(), options, progress, true)
+    }
+
+    pub(crate) fn archive_trees<P, D>(
+        &mut self,
+        trees: impl IntoIterator<Item = (P, D)>,
+        options: &ArchiveOptions,
+        progress: &mut impl TreeProgress,
+    ) -> crate::Result<TreeSummary>
+    where
+        P: AsRef<Path>,
+        D: AsRef<RelativePath>,
+    {
+        let trees = trees.into_iter().collect::<Vec<_>>();
+        let trees = trees
+            .iter()
+            .map(|(source, dest)| (source.as_ref(), dest.as_ref()))
+            .collect::<Vec<_>>();
+
+        let state = self.0.state();
+        for (index, (_, dest)) in trees.iter().enumerate() {
+            let dest = state.normalize(dest);
+            let overlaps = trees[index + 1..].iter().any(|(_, other)| {
+                let other = state.normalize(other);
+                dest.starts_with(&other) || other.starts_with(&dest)
+            });
+            if overlaps {
+                return Err(crate::Error::InvalidPath);
+            }
+        }
+
+        if self.0.threads() > 1 {
+            return self.archive_tree_parallel(&trees, options, progress, true);
+        }
+
+        let mut summary = TreeSummary::default();
+        for (source, dest) in trees {
+            summary.merge(self.archive_tree_impl(source, dest, options, progress, true)?);
+        }
+
+        Ok(summary)
+    }
+
+    /// Copy a directory tree from the file system into the repository.
+    ///
+    /// If `keep_going` is `true`, I/O errors for files other than `source` are recorded in the
+    /// returned summary instead of being returned.
+    fn archive_tree_impl(
+        &mut self,
+        source: &Path,
+        dest: &RelativePath,
+        options: &ArchiveOptions,
+        progress: &mut dyn TreeProgress,
+        keep_going: bool,
+    ) -> crate::Result<TreeSummary> {
+        if self.0.threads() > 1 {
+            return self.archive_tree_parallel(&[(source, dest)], options, progress, keep_going);
+        }
+
+        let mut summary = TreeSummary::default();
+        let mut links = ArchivedLinks::default();
+
+        // `WalkDir` includes `source` in the paths it iterates over.
+        // It does not error if `source` is not a directory.
+        let all_paths = WalkDir::new(source)
+            .follow_links(options.follow_links)
+            .into_iter()
+            .filter_entry(|entry| is_selected(&options.filter, source, entry));
+
+        for (archived_files, result) in all_paths.enumerate() {
+            self.0
+                .report_progress(Operation::ArchiveTree, archived_files as u64, None)?;
+
+            let (source_path, result) = match result {
+                Ok(dir_entry) => {
+                    let dest_path = dest.join(relative_source_path(source, dir_entry.path()));
+                    if links.add(&dir_entry, &dest_path) {
+                        continue;
+                    }
+                    let source_path = dir_entry.into_path();
+                    let result = match self.keep_if_incremental(&source_path, &dest_path, options) {
+                        Ok(true) => {
+                            summary.unchanged += 1;
+                            continue;
+                        }
+                        Ok(false) => self.archive_entry(
+                            &source_path,
+                            &dest_path,
+                            options.follow_links,
+                            progress,
+                        ),
+                        Err(error) => Err(error),
+                    };
+                    (source_path, result)
+                }
+                Err(error) if error.loop_ancestor().is_some() => {
+                    // This is a symbolic link which points to one of its own ancestors.
+                    let source_path = error.path().unwrap();
+                    summary
+                        .skipped
+                        .push(dest.join(relative_source_path(source, source_path)));
+                    continue;
+                }
+                Err(error) => match error.path() {
+                    Some(path) => (path.to_owned(), Err(io::Error::from(error).into())),
+                    None => return Err(io::Error::from(error).into()),
+                },
+            };
+            let dest_path = dest.join(relative_source_path(source, &source_path));
+
+            match result {
+                Ok(bytes_copied) => {
+                    summary.entries += 1;
+                    summary.bytes += bytes_copied;
+                }
+                Err(crate::Error::FileType) => summary.skipped.push(dest_path),
+                Err(error @ crate::Error::Io(_)) | Err(error @ crate::Error::InvalidPath)
+                    if keep_going && source_path != source =>
+                {
+                    summary.failed.push((dest_path, error))
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        self.create_archived_links(links, &mut summary, progress, keep_going)?;
+
+        Ok(summary)
+    }
+
+    /// Return whether the file at `source` should be skipped because it is unchanged.
+    ///
+    /// This always returns `false` unless the archive is incremental. If the file has changed
+    /// since it was archived at `dest`, the old entry is removed.
+    fn keep_if_incremental(
+        &mut self,
+        source: &Path,
+        dest: &RelativePath,
+        options: &ArchiveOptions,
+    ) -> crate::Result<bool> {
+        if options.incremental {
+            self.keep_if_unchanged(source, dest, options.follow_links, options.compare_contents)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Copy directory trees from the file system into the repository using worker threads.
+    ///
+    /// Each of the `trees` is a pair of a source path and a destination path. Entries are created
+    /// on the calling thread in the order they are visited, while the contents of files are read
+    /// and chunked on worker threads. The workers are shared by every tree, so they keep reading
+    /// files from the next tree while the previous one is still being written. The prepared chunks
+    /// are written to the repository on the calling thread as they become available.
+    fn archive_tree_parallel(
+        &mut self,
+        trees: &[(&Path, &RelativePath)],
+        options: &ArchiveOptions,
+        progress: &mut dyn TreeProgress,
+        keep_going: bool,
+    ) -> crate::Result<TreeSummary> {
+        let mut summary = TreeSummary::default();
+        let mut links = ArchivedLinks::default();
+        let mut pending = HashMap::new();
+        let mut pool = ChunkingPool::new(
+            self.0.threads(),
+            self.0.chunking(),
+            self.0.max_chunk_size(),
+            self.0.read_buffer_size(),
+        );
+        let mut next_index = 0;
+
+        for &(source, dest) in trees {
+            // `WalkDir` includes `source` in the paths it iterates over.
+            // It does not error if `source` is not a directory.
+            let all_paths = WalkDir::new(source)
+                .follow_links(options.follow_links)
+                .into_iter()
+                .filter_entry(|entry| is_selected(&options.filter, source, entry));
+
+            for result in all_paths {
+                let index = next_index;
+                next_index += 1;
+
+                self.0.report_progress(
+                    Operation::ArchiveTree,
+                    summary.entries + (summary.skipped.len() + summary.failed.len()) as u64,
+                    None,
+                )?;
+
+                let (source_path, result) = match result {
+                    Ok(dir_entry) => {
+                        let dest_path = dest.join(relative_source_path(source, dir_entry.path()));
+                        if links.add(&dir_entry, &dest_path) {
+                            continue;
+                        }
+                        let source_path = dir_entry.into_path();
+                        let result =
+                            match self.keep_if_incremental(&source_path, &dest_path, options) {
+                                Ok(true) => {
+                                    summary.unchanged += 1;
+                                    continue;
+                                }
+                                Ok(false) => self.create_archived_entry(
+                                    &source_path,
+                                    &dest_path,
+                                    options.follow_links,
+                                    progress,
+                                ),
+                                Err(error) => Err(error),
+                            };
+                        (source_path, result)
+                    }
+                    Err(error) if error.loop_ancestor().is_some() => {
+                        // This is a symbolic link which points to one of its own ancestors.
+                        let source_path = error.path().unwrap();
+                        summary
+                            .skipped
+                            .push(dest.join(relative_source_path(source, source_path)));
+                        continue;
+                    }
+                    Err(error) => match error.path() {
+                        Some(path) => (path.to_owned(), Err(io::Error::from(error).into())),
+                        None => return Err(io::Error::from(error).into()),
+                    },
+                };
+                let dest_path = dest.join(relative_source_path(source, &source_path));
+                let is_root = source_path == source;
+
+                match result {
+                    // The workers chunk files as a single stream, so sparse files are copied on
+                    // this thread to preserve their holes.
+                    Ok(Some(object_id)) if is_sparse_file(&source_path) => {
+                        match self.archive_contents(&source_path, &dest_path, object_id, progress) {
+                            Ok(bytes) => {
+                                progress.entry_finished(&dest_path);
+                                summary.entries += 1;
+                                summary.bytes += bytes;
+                            }
+                            Err(error @ crate::Error::Io(_)) if keep_going && !is_root => {
+                                self.remove(&dest_path)?;
+                                summary.failed.push((dest_path, error));
+                            }
+                            Err(error) => return Err(error),
+                        }
+                    }
+                    Ok(Some(object_id)) => {
+                        pending.insert(
+                            index,
+                            PendingFile {
+                                dest_path,
+                                object_id,
+                                object: None,
+                                bytes: 0,
+                                is_root,
+                            },
+                        );
+                        pool.submit(index, source_path);
+                    }
+                    Ok(None) => {
+                        progress.entry_finished(&dest_path);
+                        summary.entries += 1;
+                    }
+                    Err(crate::Error::FileType) => summary.skipped.push(dest_path),
+                    Err(error @ crate::Error::Io(_)) | Err(error @ crate::Error::InvalidPath)
+                        if keep_going && !is_root =>
+                    {
+                        summary.failed.push((dest_path, error))
+                    }
+                    Err(error) => return Err(error),
+                }
 
-        self.create(&dest, &entry)?;
+                // Write any chunks which are ready so the workers don't have to wait for the walk
+                // to finish.
+                while let Some(message) = pool.try_recv() {
+                    self.handle_chunking_message(
+                        message,
+                        &mut pending,
+                        &mut summary,
+                        progress,
+                        keep_going,
+                    )?;
+                }
+            }
+        }
 
-        // Write the contents of the file entry if it's a file.
-        let entry_handle = self.0.state().get(dest.as_ref()).unwrap();
-        if let EntryType::File(object_id) = entry_handle.entry_type {
-            let mut object = self.0.object(object_id).unwrap();
-            let mut file = File::open(&source)?;
-            copy(&mut file, &mut object)?;
-            object.commit()?;
+        pool.close();
+        while !pending.is_empty() {
+            let message = match pool.recv() {
+                Some(message) => message,
+                None => break,
+            };
+            self.handle_chunking_message(
+                message,
+                &mut pending,
+                &mut summary,
+                progress,
+                keep_going,
+            )?;
+            self.0.report_progress(
+                Operation::ArchiveTree,
+                summary.entries + (summary.skipped.len() + summary.failed.len()) as u64,
+                None,
+            )?;
         }
 
-        Ok(())
+        self.create_archived_links(links, &mut summary, progress, keep_going)?;
+
+        Ok(summary)
     }
 
-    /// Copy a directory tree from the file system into the repository.
-    ///
-    /// If `source` is a directory, this also copies its descendants. If `source` is not a
-    /// directory, this is the same as calling [`archive`]. If one of the files in the tree is not a
-    /// regular file, directory, or supported special file, it is skipped.
-    ///
-    /// The `source` file's metadata will be copied to the `dest` entry according to the selected
-    /// [`FileMetadata`] implementation.
-    ///
-    /// # Errors
-    /// - `Error::InvalidPath`: The parent of `dest` does not exist or is not a directory.
-    /// - `Error::InvalidPath`: The given `dest` path is empty.
-    /// - `Error::AlreadyExists`: There is already an entry at `dest`.
-    /// - `Error::InvalidData`: Ciphertext verification failed.
-    /// - `Error::Store`: An error occurred with the data store.
-    /// - `Error::Io`: An I/O error occurred.
-    ///
-    /// [`archive`]: crate::repo::file::FileRepo::archive
-    /// [`FileMetadata`]: crate::repo::file::FileMetadata
-    pub fn archive_tree(
+    /// Handle a `message` from a worker thread in `archive_tree_parallel`.
+    fn handle_chunking_message(
         &mut self,
-        source: impl AsRef<Path>,
-        dest: impl AsRef<RelativePath>,
+        message: Message,
+        pending: &mut HashMap<usize, PendingFile>,
+        summary: &mut TreeSummary,
+        progress: &mut dyn TreeProgress,
+        keep_going: bool,
     ) -> crate::Result<()> {
-        // `WalkDir` includes `source` in the paths it iterates over.
-        // It does not error if `source` is not a directory.
-        let all_paths = WalkDir::new(&source).into_iter();
-
-        for result in all_paths {
-            let dir_entry = result.map_err(io::Error::from)?;
-            let relative_path =
-                RelativePath::from_path(dir_entry.path().strip_prefix(&source).unwrap())
-                    .expect("Not a valid relative path.");
-            match self.archive(dir_entry.path(), dest.as_ref().join(relative_path)) {
-                Ok(_) => continue,
-                Err(crate::Error::FileType) => continue,
-                Err(error) => return Err(error),
+        match message {
+            Message::Chunk(index, chunk) => {
+                let file = pending.get_mut(&index).unwrap();
+                let object_id = file.object_id;
+                let repo = &self.0;
+                let object = file
+                    .object
+                    .get_or_insert_with(|| repo.object(object_id).unwrap());
+                file.bytes += chunk.size();
+                object.append_prepared(chunk)?;
+                progress.bytes_copied(&file.dest_path, file.bytes);
+            }
+            Message::Finished(index) => {
+                let file = pending.remove(&index).unwrap();
+                if let Some(mut object) = file.object {
+                    object.commit()?;
+                }
+                self.record_size(&file.dest_path, file.bytes);
+                progress.entry_finished(&file.dest_path);
+                summary.entries += 1;
+                summary.bytes += file.bytes;
+            }
+            Message::Failed(index, error) => {
+                let file = pending.remove(&index).unwrap();
+
+                // Dropping the object discards any chunks which were already appended.
+                drop(file.object);
+
+                if !keep_going || file.is_root {
+                    return Err(error.into());
+                }
+                self.remove(&file.dest_path)?;
+                summary.failed.push((file.dest_path, error.into()));
             }
         }
 
         Ok(())
     }
 
-    /// Copy an entry from the repository into the file system.
-    ///
-    /// If `source` is a directory, its descendants are not copied.
-    ///
-    /// The `source` entry's metadata will be copied to the `dest` file according to the selected
-    /// [`FileMetadata`] implementation.
-    ///
-    /// # Errors
-    /// - `Error::InvalidPath`: The given `source` path is empty.
-    /// - `Error::NotFound`: The `source` entry does not exist.
-    /// - `Error::AlreadyExists`: The `dest` file already exists.
-    /// - `Error::Deserialize`: The file metadata could not be deserialized.
-    /// - `Error::InvalidData`: Ciphertext verification failed.
-    /// - `Error::Store`: An error occurred with the data store.
-    /// - `Error::Io`: An I/O error occurred.
-    ///
-    /// [`FileMetadata`]: crate::repo::file::FileMetadata
-    pub fn extract(
+    pub(crate) fn extract(
         &self,
         source: impl AsRef<RelativePath>,
         dest: impl AsRef<Path>,
     ) -> crate::Result<()> {
-        if source.as_ref() == *EMPTY_PATH {
+        self.extract_entry(source.as_ref(), dest.as_ref(), Ownership::Preserve, &mut ())?;
+        Ok(())
+    }
+
+    /// Copy an entry from the repository into the file system, reporting progress to `progress`.
+    ///
+    /// The owner of the file is set according to `ownership`. This returns the number of bytes of
+    /// file contents which were copied.
+    pub(super) fn extract_entry(
+        &self,
+        source: &RelativePath,
+        dest: &Path,
+        ownership: Ownership,
+        progress: &mut dyn TreeProgress,
+    ) -> crate::Result<u64> {
+        if source == *EMPTY_PATH {
             return Err(crate::Error::InvalidPath);
         }
 
-        if dest.as_ref().exists() {
+        if dest.exists() {
             return Err(crate::Error::AlreadyExists);
         }
 
-        let entry = self.entry(&source)?;
+        let entry = self.entry(source)?;
+
+        progress.entry_started(source);
 
         // Create any necessary parent directories.
-        if let Some(parent) = dest.as_ref().parent() {
+        if let Some(parent) = dest.parent() {
             create_dir_all(parent)?
         }
 
         // Create the file or directory.
+        let mut bytes_copied = 0;
         match entry.file_type {
             FileType::File => {
-                let mut object = self.open(source.as_ref()).unwrap();
-                let mut file = OpenOptions::new()
-                    .write(true)
-                    .create_new(true)
-                    .open(&dest)?;
-                copy(&mut object, &mut file)?;
+                let mut object = self.open(source).unwrap();
+                let mut file = OpenOptions::new().write(true).create_new(true).open(dest)?;
+                bytes_copied = copy_from_object(&mut object, &mut file, |bytes| {
+                    progress.bytes_copied(source, bytes)
+                })?;
             }
             FileType::Directory => {
-                create_dir(&dest)?;
+                create_dir(dest)?;
             }
             FileType::Special(special_type) => {
-                special_type.create_file(dest.as_ref())?;
+                special_type.create_file(dest)?;
             }
         }
 
         // Set the file metadata.
         if let Some(metadata) = entry.metadata {
-            metadata.write_metadata(dest.as_ref())?;
+            metadata.write_metadata_with_ownership(dest, ownership)?;
         }
 
+        progress.entry_finished(source);
+
+        Ok(bytes_copied)
+    }
+
+    pub(crate) fn extract_tree(
+        &self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<Path>,
+    ) -> crate::Result<()> {
+        self.extract_tree_impl(
+            source.as_ref(),
+            dest.as_ref(),
+            &ExtractOptions::new(),
+            &mut (),
+            false,
+        )?;
         Ok(())
     }
 
-    /// Copy a tree of entries from the repository into the file system.
-    ///
-    /// If `source` is a directory, this also copies its descendants. If `source` is not a
-    /// directory, this is the same as calling [`extract`].
-    ///
-    /// The `source` entry's metadata will be copied to the `dest` file according to the selected
-    /// [`FileMetadata`] implementation.
-    ///
-    /// # Errors
-    /// - `Error::InvalidPath`: The given `source` path is empty.
-    /// - `Error::NotFound`: The `source` entry does not exist.
-    /// - `Error::AlreadyExists`: The `dest` file already exists.
-    /// - `Error::Deserialize`: The file metadata could not be deserialized.
-    /// - `Error::InvalidData`: Ciphertext verification failed.
-    /// - `Error::Store`: An error occurred with the data store.
-    /// - `Error::Io`: An I/O error occurred.
-    ///
-    /// [`extract`]: crate::repo::file::FileRepo::extract
-    /// [`FileMetadata`]: crate::repo::file::FileMetadata
-    pub fn extract_tree(
+    pub(crate) fn extract_tree_filtered(
         &self,
         source: impl AsRef<RelativePath>,
         dest: impl AsRef<Path>,
+        filter: &PathFilter,
     ) -> crate::Result<()> {
+        let mut options = ExtractOptions::new();
+        options.filter(filter.clone());
+        self.extract_tree_impl(source.as_ref(), dest.as_ref(), &options, &mut (), false)?;
+        Ok(())
+    }
+
+    pub(crate) fn extract_tree_with(
+        &self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<Path>,
+        progress: &mut impl TreeProgress,
+    ) -> crate::Result<TreeSummary> {
+        self.extract_tree_impl(
+            source.as_ref(),
+            dest.as_ref(),
+            &ExtractOptions::new(),
+            progress,
+            true,
+        )
+    }
+
+    pub(crate) fn extract_tree_with_options(
+        &self,
+        source: impl AsRef<RelativePath>,
+        dest: impl AsRef<Path>,
+        options: &ExtractOptions,
+        progress: &mut impl TreeProgress,
+    ) -> crate::Result<TreeSummary> {
+        self.extract_tree_impl(source.as_ref(), dest.as_ref(), options, progress, true)
+    }
+
+    /// Copy a tree of entries from the repository into the file system.
+    ///
+    /// If `keep_going` is `true`, I/O errors for entries other than `source` are recorded in the
+    /// returned summary instead of being returned.
+    fn extract_tree_impl(
+        &self,
+        source: &RelativePath,
+        dest: &Path,
+        options: &ExtractOptions,
+        progress: &mut dyn TreeProgress,
+        keep_going: bool,
+    ) -> crate::Result<TreeSummary> {
+        let mut summary = TreeSummary::default();
+
+        let mut tree_filter = options.filter.tree_filter();
         let relative_descendants = self
             .0
             .state()
-            .walk(&source)
+            .walk(source)
             .ok_or(crate::Error::NotFound)?
-            .map(|(path, _)| path.strip_prefix(&source).unwrap().to_owned());
+            .filter_map(|(path, handle)| {
+                let relative_path = path.strip_prefix(source).unwrap().to_owned();
+                let is_directory = matches!(handle.entry_type, EntryType::Directory);
+                if tree_filter.is_match(&relative_path, is_directory) {
+                    Some(relative_path)
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        let total_entries = Some(relative_descendants.len() as u64 + 1);
 
         // Extract the root directory.
-        self.extract(&source, &dest)?;
-
-        // Extract the descendants.
-        for descendant in relative_descendants {
-            self.extract(
-                source.as_ref().join(&descendant),
-                descendant.to_path(dest.as_ref()),
-            )?;
+        self.0
+            .report_progress(Operation::ExtractTree, 0, total_entries)?;
+        summary.bytes += self.extract_entry(source, dest, options.ownership, progress)?;
+        summary.entries += 1;
+
+        // Extract the descendants, recreating hard links between them.
+        let mut links = ExtractedLinks::default();
+        for (index, descendant) in relative_descendants.into_iter().enumerate() {
+            self.0
+                .report_progress(Operation::ExtractTree, index as u64 + 1, total_entries)?;
+            let source_path = source.join(&descendant);
+            let dest_path = descendant.to_path(dest);
+            let result = self
+                .extracted_link_target(&source_path, &links)
+                .and_then(|target| {
+                    let bytes_copied = match target {
+                        Some(target) => {
+                            self.extract_link(&source_path, &target, &dest_path, progress)?
+                        }
+                        None => self.extract_entry(
+                            &source_path,
+                            &dest_path,
+                            options.ownership,
+                            progress,
+                        )?,
+                    };
+                    self.add_extracted_link(&source_path, &dest_path, &mut links)?;
+                    Ok(bytes_copied)
+                });
+            match result {
+                Ok(bytes_copied) => {
+                    summary.entries += 1;
+                    summary.bytes += bytes_copied;
+                }
+                Err(error @ crate::Error::Io(_)) if keep_going => {
+                    summary.failed.push((source_path, error))
+                }
+                Err(error) => return Err(error),
+            }
         }
 
-        Ok(())
+        Ok(summary)
     }
 
-    /// Verify the integrity of all the data in the repository.
-    ///
-    /// This returns the set of paths of files with corrupt data or metadata.
-    ///
-    /// If you just need to verify the integrity of one object, [`Object::verify`] is faster. If you
-    /// need to verify the integrity of all the data in the repository, however, this can be more
-    /// efficient.
-    ///
-    /// # Errors
-    /// - `Error::InvalidData`: Ciphertext verification failed.
-    /// - `Error::Store`: An error occurred with the data store.
-    /// - `Error::Io`: An I/O error occurred.
-    ///
-    /// [`Object::verify`]: crate::repo::Object::verify
-    pub fn verify(&self) -> crate::Result<HashSet<RelativePathBuf>> {
+    pub(crate) fn verify(&self) -> crate::Result<HashSet<RelativePathBuf>> {
         let corrupt_keys = self.0.verify()?;
         Ok(self
             .0
@@ -833,80 +2458,277 @@ where
                     EntryType::File(object_id) => corrupt_keys.contains(object_id),
                     _ => false,
                 };
-                entry_corrupt || file_corrupt
+                let attributes_corrupt = match &entry_handle.attributes {
+                    Some(object_id) => corrupt_keys.contains(object_id),
+                    None => false,
+                };
+                entry_corrupt || file_corrupt || attributes_corrupt
             })
             .map(|(path, _)| path)
             .collect())
     }
 
-    /// Delete all data in the current instance of the repository.
-    ///
-    /// See [`KeyRepo::clear_instance`] for details.
-    ///
-    /// [`KeyRepo::clear_instance`]: crate::repo::key::KeyRepo::clear_instance
-    pub fn clear_instance(&mut self) {
+    pub(crate) fn changed_paths(&self) -> crate::Result<HashSet<RelativePathBuf>> {
+        let committed_tree = self.0.committed_state()?;
+        let changed_objects = self.0.changed_keys()?;
+        let current_tree = self.0.state();
+
+        let is_modified = |entry_handle: &EntryHandle| {
+            let entry_modified = changed_objects.contains(&entry_handle.entry);
+            let file_modified = match &entry_handle.entry_type {
+                EntryType::File(object_id) => changed_objects.contains(object_id),
+                _ => false,
+            };
+            let attributes_modified = match &entry_handle.attributes {
+                Some(object_id) => changed_objects.contains(object_id),
+                None => false,
+            };
+            entry_modified || file_modified || attributes_modified
+        };
+
+        let mut changed_paths = current_tree
+            .walk(&*EMPTY_PATH)
+            .unwrap()
+            .filter(|(path, entry_handle)| {
+                committed_tree.get(path) != Some(*entry_handle) || is_modified(entry_handle)
+            })
+            .map(|(path, _)| path)
+            .collect::<HashSet<_>>();
+        changed_paths.extend(
+            committed_tree
+                .walk(&*EMPTY_PATH)
+                .unwrap()
+                .map(|(path, _)| path)
+                .filter(|path| !current_tree.contains(path)),
+        );
+
+        Ok(changed_paths)
+    }
+
+    pub(crate) fn has_changes(&self) -> crate::Result<bool> {
+        Ok(!self.changed_paths()?.is_empty())
+    }
+
+    pub(crate) fn into_key_repo(self) -> crate::Result<KeyRepo<String>> {
+        let keys = self
+            .0
+            .state()
+            .walk(&*EMPTY_PATH)
+            .into_iter()
+            .flatten()
+            .filter_map(|(path, handle)| match handle.entry_type {
+                EntryType::File(object) => Some((object, path.into_string())),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+        self.0.into_key_repo(keys)
+    }
+
+    pub(crate) fn clear_instance(&mut self) {
         self.0.clear_instance()
     }
 
-    /// Change the password for this repository.
-    ///
-    /// See [`KeyRepo::change_password`] for details.
-    ///
-    /// [`KeyRepo::change_password`]: crate::repo::key::KeyRepo::change_password
-    pub fn change_password(&mut self, new_password: &[u8]) {
+    pub(crate) fn change_password(&mut self, new_password: &[u8]) {
         self.0.change_password(new_password);
     }
 
-    /// Return this repository's instance ID.
-    pub fn instance(&self) -> Uuid {
+    pub(crate) fn renew_lease(&self) -> crate::Result<()> {
+        self.0.renew_lease()
+    }
+
+    pub(crate) fn add_before_hook(
+        &mut self,
+        event: TransactionEvent,
+        hook: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.0.add_before_hook(event, hook)
+    }
+
+    pub(crate) fn add_after_hook(
+        &mut self,
+        event: TransactionEvent,
+        hook: impl FnMut() + Send + 'static,
+    ) {
+        self.0.add_after_hook(event, hook)
+    }
+
+    pub(crate) fn set_progress_handler(
+        &mut self,
+        handler: impl Fn(Progress) + Send + Sync + 'static,
+    ) {
+        self.0.set_progress_handler(handler)
+    }
+
+    pub(crate) fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.0.set_cancellation_token(token)
+    }
+
+    pub(crate) fn instance(&self) -> Uuid {
         self.0.instance()
     }
 
-    /// Return information about the repository.
-    pub fn info(&self) -> RepoInfo {
+    pub(crate) fn branch(&self) -> &str {
+        self.0.branch()
+    }
+
+    pub(crate) fn branches(&self) -> impl Iterator<Item = &str> {
+        self.0.branches()
+    }
+
+    pub(crate) fn remove_branch(&mut self, name: &str) -> crate::Result<()> {
+        self.0.remove_branch(name)
+    }
+
+    pub(crate) fn info(&self) -> RepoInfo {
         self.0.info()
     }
+
+    pub(crate) fn stats(&self) -> RepoStats {
+        self.0.stats()
+    }
+
+    pub(crate) fn reset_stats(&self) {
+        self.0.reset_stats()
+    }
+
+    pub(crate) fn clean_dry_run(&self) -> crate::Result<CleanReport> {
+        self.0.clean_dry_run()
+    }
+
+    pub(crate) fn repair(&mut self) -> crate::Result<RepairReport> {
+        self.0.repair()
+    }
+
+    pub(crate) fn audit_log(&self) -> crate::Result<Vec<AuditEntry>> {
+        self.0.audit_log()
+    }
 }
 
-impl<S, M> Commit for FileRepo<S, M>
+impl<S, M> FileRepoInner<S, M>
 where
     S: SpecialType,
     M: FileMetadata,
 {
-    fn commit(&mut self) -> crate::Result<()> {
+    pub(crate) fn commit(&mut self) -> crate::Result<()> {
         self.0.commit()
     }
 
-    fn rollback(&mut self) -> crate::Result<()> {
-        self.0.rollback()
+    pub(crate) fn rollback(&mut self) -> crate::Result<()> {
+        self.0.rollback()?;
+        self.3.clear();
+        Ok(())
+    }
+
+    pub(crate) fn refresh(&mut self) -> crate::Result<()> {
+        self.0.refresh()?;
+        self.3.clear();
+        Ok(())
     }
 
-    fn clean(&mut self) -> crate::Result<()> {
+    pub(crate) fn clean(&mut self) -> crate::Result<()> {
         self.0.clean()
     }
 }
-impl<S, M> RestoreSavepoint for FileRepo<S, M>
+
+impl<S, M> Commit for FileRepo<S, M>
 where
     S: SpecialType,
     M: FileMetadata,
 {
-    type Restore = <StateRepo<RepoState> as RestoreSavepoint>::Restore;
+    fn commit(&self) -> crate::Result<()> {
+        self.inner_mut().commit()
+    }
+
+    fn rollback(&self) -> crate::Result<()> {
+        self.inner_mut().rollback()
+    }
 
-    fn savepoint(&mut self) -> crate::Result<Savepoint> {
+    fn refresh(&self) -> crate::Result<()> {
+        self.inner_mut().refresh()
+    }
+
+    fn clean(&self) -> crate::Result<()> {
+        self.inner_mut().clean()
+    }
+}
+impl<S, M> FileRepoInner<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    pub(crate) fn savepoint(&mut self) -> crate::Result<Savepoint> {
         self.0.savepoint()
     }
 
-    fn start_restore(&mut self, savepoint: &Savepoint) -> crate::Result<Self::Restore> {
+    pub(crate) fn start_restore(
+        &mut self,
+        savepoint: &Savepoint,
+    ) -> crate::Result<<StateRepo<RepoState> as RestoreSavepoint>::Restore> {
         self.0.start_restore(savepoint)
     }
 
-    fn finish_restore(&mut self, restore: Self::Restore) -> bool {
-        self.0.finish_restore(restore)
+    pub(crate) fn finish_restore(
+        &mut self,
+        restore: <StateRepo<RepoState> as RestoreSavepoint>::Restore,
+    ) -> bool {
+        if !self.0.finish_restore(restore) {
+            return false;
+        }
+        self.3.clear();
+        true
+    }
+}
+
+impl<S, M> RestoreSavepoint for FileRepo<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    type Restore = <StateRepo<RepoState> as RestoreSavepoint>::Restore;
+
+    fn savepoint(&self) -> crate::Result<Savepoint> {
+        self.inner_mut().savepoint()
+    }
+
+    fn start_restore(&self, savepoint: &Savepoint) -> crate::Result<Self::Restore> {
+        self.inner_mut().start_restore(savepoint)
+    }
+
+    fn finish_restore(&self, restore: Self::Restore) -> bool {
+        self.inner_mut().finish_restore(restore)
     }
 }
 
-/// The default mount options which are always passed to libfuse.
-const DEFAULT_FUSE_MOUNT_OPTS: &[&str] = &["-o", "default_permissions"];
+#[cfg(feature = "async")]
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+impl<S, M> AsyncRepo<FileRepo<S, M>>
+where
+    S: SpecialType,
+    M: FileMetadata,
+    FileRepo<S, M>: Send + 'static,
+{
+    /// Return a stream of the paths of the descendants of `parent`.
+    ///
+    /// See [`FileRepo::walk`] and [`AsyncStream`].
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: The given `parent` does not exist.
+    /// - `Error::NotDirectory`: The given `parent` is not a directory.
+    ///
+    /// [`FileRepo::walk`]: crate::repo::file::FileRepo::walk
+    /// [`AsyncStream`]: crate::repo::AsyncStream
+    pub async fn walk(
+        &mut self,
+        parent: RelativePathBuf,
+    ) -> crate::Result<AsyncStream<'_, RelativePathBuf>> {
+        self.stream(move |repo, send| {
+            // This stops once the stream has been dropped.
+            repo.inner().walk(&parent)?.all(send);
+            Ok(())
+        })
+        .await
+    }
+}
 
 #[cfg(all(any(unix, doc), feature = "fuse-mount"))]
 #[cfg_attr(docsrs, doc(cfg(all(unix, feature = "fuse-mount"))))]
@@ -914,8 +2736,10 @@ impl FileRepo<UnixSpecialType, UnixMetadata> {
     /// Mount the `FileRepo` as a FUSE file system.
     ///
     /// This accepts the path of the `root` entry in the repository which will be mounted in the
-    /// file system at `mountpoint`. This also accepts an array of mount `options` to pass to
-    /// libfuse.
+    /// file system at `mountpoint`. The file system is mounted according to `options`.
+    ///
+    /// The inode numbers assigned to entries are stored in the repository, so they stay the same
+    /// when the repository is mounted again. This means mounting the repository commits changes.
     ///
     /// This method does not return until the file system is unmounted.
     ///
@@ -923,19 +2747,17 @@ impl FileRepo<UnixSpecialType, UnixMetadata> {
     /// - `Error::InvalidPath`: The given `root` path is empty.
     /// - `Error::NotFound`: There is no entry at `root`.
     /// - `Error::NotDirectory`: The given `root` entry is not a directory.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
     pub fn mount(
-        &mut self,
+        &self,
         mountpoint: impl AsRef<Path>,
         root: impl AsRef<RelativePath>,
-        options: &[&str],
+        options: &MountOptions,
     ) -> crate::Result<()> {
-        let adapter = FuseAdapter::new(self, root.as_ref())?;
-        let all_opts = [DEFAULT_FUSE_MOUNT_OPTS, options]
-            .concat()
-            .into_iter()
-            .map(|opt| opt.as_ref())
-            .collect::<Vec<&OsStr>>();
-        Ok(fuse::mount(adapter, &mountpoint, &all_opts)?)
+        let adapter = FuseAdapter::new(self, root.as_ref(), options)?;
+        let mount_options = options.to_mount_options();
+        Ok(fuser::mount2(adapter, &mountpoint, &mount_options)?)
     }
 }
@@ -0,0 +1,168 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::iter;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::repo::Object;
+
+use super::tree::copy_with_progress;
+
+/// Return the byte ranges of the file which contain data, in order.
+///
+/// The regions between the returned ranges are holes. If the platform or file system does not
+/// support finding holes, this returns a single range covering the whole file.
+#[cfg(all(
+    any(target_os = "linux", target_os = "freebsd"),
+    feature = "file-metadata"
+))]
+pub fn data_ranges(file: &File, size: u64) -> io::Result<Vec<Range<u64>>> {
+    use nix::errno::Errno;
+    use nix::unistd::{lseek, Whence};
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+
+    while offset < size {
+        let data_start = match lseek(fd, offset as nix::libc::off_t, Whence::SeekData) {
+            Ok(position) => position as u64,
+            // There is no more data after `offset`.
+            Err(nix::Error::Sys(Errno::ENXIO)) => break,
+            // The file system doesn't support finding holes.
+            Err(nix::Error::Sys(Errno::EINVAL)) => return Ok(iter::once(0..size).collect()),
+            Err(error) => return Err(io::Error::other(error)),
+        };
+        let data_end = match lseek(fd, data_start as nix::libc::off_t, Whence::SeekHole) {
+            Ok(position) => position as u64,
+            Err(error) => return Err(io::Error::other(error)),
+        };
+        ranges.push(data_start..data_end.min(size));
+        offset = data_end;
+    }
+
+    Ok(ranges)
+}
+
+/// Return the byte ranges of the file which contain data, in order.
+///
+/// Finding holes is not supported on this platform, so this returns a single range covering the
+/// whole file.
+#[cfg(not(all(
+    any(target_os = "linux", target_os = "freebsd"),
+    feature = "file-metadata"
+)))]
+pub fn data_ranges(_file: &File, size: u64) -> io::Result<Vec<Range<u64>>> {
+    Ok(iter::once(0..size).collect())
+}
+
+/// Return whether the file at `path` has any holes.
+///
+/// This returns `false` if the file can't be read.
+pub fn is_sparse_file(path: &Path) -> bool {
+    let is_sparse = || -> io::Result<bool> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let whole_file = 0..size;
+        Ok(size > 0 && data_ranges(&file, size)? != [whole_file])
+    };
+    is_sparse().unwrap_or(false)
+}
+
+/// Copy the contents of `file` to the empty `object`, preserving any holes.
+///
+/// This calls `on_progress` with the total number of bytes of data copied so far and returns the
+/// total number of bytes of data copied, which does not include holes. The object is committed
+/// before this returns.
+pub fn copy_to_object(
+    file: &mut File,
+    object: &mut Object,
+    mut on_progress: impl FnMut(u64),
+) -> crate::Result<u64> {
+    let size = file.metadata()?.len();
+    let mut bytes_copied = 0;
+
+    for range in data_ranges(file, size)? {
+        // Extending the object creates a hole between the end of the last range and this one.
+        object.set_len(range.start)?;
+        object.seek(SeekFrom::Start(range.start))?;
+        file.seek(SeekFrom::Start(range.start))?;
+
+        let range_reader = Read::by_ref(file).take(range.end - range.start);
+        let previous_bytes = bytes_copied;
+        bytes_copied += copy_with_progress(range_reader, &mut *object, |bytes| {
+            on_progress(previous_bytes + bytes)
+        })?;
+        object.commit()?;
+    }
+
+    // Create a hole at the end of the file if there is one.
+    object.set_len(size)?;
+
+    Ok(bytes_copied)
+}
+
+/// Copy the contents of `object` to the empty `file`, recreating any holes in the object.
+///
+/// This calls `on_progress` with the total number of bytes of data copied so far and returns the
+/// total number of bytes of data copied, which does not include holes.
+pub fn copy_from_object(
+    object: &mut Object,
+    file: &mut File,
+    mut on_progress: impl FnMut(u64),
+) -> crate::Result<u64> {
+    let holes = object.content_id()?.holes();
+    if holes.is_empty() {
+        return Ok(copy_with_progress(object, file, on_progress)?);
+    }
+
+    let size = object.size()?;
+    let mut bytes_copied = 0;
+    let mut position = 0;
+
+    // Copy the data between each hole, and then the data after the last one.
+    let data_ranges = holes
+        .iter()
+        .map(|hole| (hole.start, hole.end))
+        .chain(std::iter::once((size, size)))
+        .map(|(hole_start, hole_end)| {
+            let range = position..hole_start;
+            position = hole_end;
+            range
+        })
+        .filter(|range| !range.is_empty())
+        .collect::<Vec<_>>();
+
+    for range in data_ranges {
+        object.seek(SeekFrom::Start(range.start))?;
+        file.seek(SeekFrom::Start(range.start))?;
+
+        let range_reader = Read::by_ref(object).take(range.end - range.start);
+        let previous_bytes = bytes_copied;
+        bytes_copied += copy_with_progress(range_reader, &mut *file, |bytes| {
+            on_progress(previous_bytes + bytes)
+        })?;
+    }
+
+    // Seeking past the end of the file doesn't extend it, so this creates any trailing hole.
+    file.set_len(size)?;
+
+    Ok(bytes_copied)
+}
@@ -30,6 +30,7 @@ use {
 use {
     std::fs::read_link,
     std::os::unix::fs::{symlink, MetadataExt},
+    std::os::unix::net::UnixListener,
 };
 
 /// A special file type.
@@ -45,6 +46,29 @@ pub trait SpecialType: Serialize + DeserializeOwned {
 
     /// Create a new file of this type in the file system at `path`.
     fn create_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Create a new instance from a tar `header`.
+    ///
+    /// This returns `None` if the entry is not a special file supported by this type. The default
+    /// implementation always returns `None`.
+    ///
+    /// # Errors
+    /// - `ErrorKind::InvalidData`: The header is malformed.
+    #[cfg(feature = "file-tar")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "file-tar")))]
+    fn from_tar_header(_header: &tar::Header) -> io::Result<Option<Self>> {
+        Ok(None)
+    }
+
+    /// Write this special file to a tar `header`.
+    ///
+    /// This returns `false` if this special file can't be represented in a tar archive. The
+    /// default implementation always returns `false`.
+    #[cfg(feature = "file-tar")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "file-tar")))]
+    fn write_tar_header(&self, _header: &mut tar::Header) -> io::Result<bool> {
+        Ok(false)
+    }
 }
 
 /// A `SpecialType` which doesn't support any special file types.
@@ -66,6 +90,10 @@ impl SpecialType for NoSpecialType {
 /// If the current user does not have the necessary permissions to create a block/character device,
 /// [`create_file`] will silently ignore the error and return `Ok`.
 ///
+/// Unix domain sockets are stored without any state, since a socket only has meaning while a
+/// process is listening on it. Extracting a socket creates a new socket file which no process is
+/// listening on, much like the socket files left behind when a process exits.
+///
 /// [`create_file`]: crate::repo::file::SpecialType::create_file
 #[cfg(all(any(unix, doc), feature = "file-metadata"))]
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
@@ -82,6 +110,9 @@ pub enum UnixSpecialType {
 
     /// A character device identified by a `major` and `minor` device number.
     CharacterDevice { major: u64, minor: u64 },
+
+    /// A unix domain socket.
+    Socket,
 }
 
 #[cfg(all(any(unix, doc), feature = "file-metadata"))]
@@ -105,6 +136,8 @@ impl SpecialType for UnixSpecialType {
                 major: major(metadata.rdev()),
                 minor: minor(metadata.rdev()),
             })
+        } else if file_type.contains(SFlag::S_IFSOCK) {
+            Some(UnixSpecialType::Socket)
         } else {
             None
         };
@@ -131,8 +164,79 @@ impl SpecialType for UnixSpecialType {
                     _ => (),
                 }
             }
+            UnixSpecialType::Socket => {
+                // Not every platform supports creating sockets with `mknod`, but binding a socket
+                // creates the file and it remains after the socket is closed.
+                if mknod(path, SFlag::S_IFSOCK, Mode::S_IRWXU, 0).is_err() {
+                    UnixListener::bind(path)?;
+                }
+            }
         };
 
         Ok(())
     }
+
+    #[cfg(feature = "file-tar")]
+    fn from_tar_header(header: &tar::Header) -> io::Result<Option<Self>> {
+        let entry_type = header.entry_type();
+        let special_file = if entry_type.is_symlink() {
+            let target = header.link_name()?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Symbolic link has no target.")
+            })?;
+            Some(UnixSpecialType::SymbolicLink {
+                target: target.into_owned(),
+            })
+        } else if entry_type.is_fifo() {
+            Some(UnixSpecialType::NamedPipe)
+        } else if entry_type.is_block_special() {
+            Some(UnixSpecialType::BlockDevice {
+                major: header.device_major()?.unwrap_or(0).into(),
+                minor: header.device_minor()?.unwrap_or(0).into(),
+            })
+        } else if entry_type.is_character_special() {
+            Some(UnixSpecialType::CharacterDevice {
+                major: header.device_major()?.unwrap_or(0).into(),
+                minor: header.device_minor()?.unwrap_or(0).into(),
+            })
+        } else {
+            None
+        };
+
+        Ok(special_file)
+    }
+
+    #[cfg(feature = "file-tar")]
+    fn write_tar_header(&self, header: &mut tar::Header) -> io::Result<bool> {
+        match self {
+            UnixSpecialType::SymbolicLink { target } => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_link_name(target)?;
+            }
+            UnixSpecialType::NamedPipe => header.set_entry_type(tar::EntryType::Fifo),
+            UnixSpecialType::BlockDevice { major, minor } => {
+                header.set_entry_type(tar::EntryType::Block);
+                set_device_numbers(header, *major, *minor)?;
+            }
+            UnixSpecialType::CharacterDevice { major, minor } => {
+                header.set_entry_type(tar::EntryType::Char);
+                set_device_numbers(header, *major, *minor)?;
+            }
+            // The tar format has no entry type for sockets.
+            UnixSpecialType::Socket => return Ok(false),
+        }
+
+        Ok(true)
+    }
+}
+
+/// Set the device numbers in a tar `header`.
+#[cfg(all(any(unix, doc), feature = "file-metadata", feature = "file-tar"))]
+fn set_device_numbers(header: &mut tar::Header, major: u64, minor: u64) -> io::Result<()> {
+    use std::convert::TryFrom;
+
+    let out_of_range =
+        || io::Error::new(io::ErrorKind::InvalidInput, "Device number is too large.");
+    header.set_device_major(u32::try_from(major).map_err(|_| out_of_range())?)?;
+    header.set_device_minor(u32::try_from(minor).map_err(|_| out_of_range())?)?;
+    Ok(())
 }
@@ -0,0 +1,113 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashSet;
+
+use relative_path::RelativePath;
+
+use super::entry::EntryType;
+use super::metadata::FileMetadata;
+use super::repository::{FileRepo, FileRepoInner, EMPTY_PATH};
+use super::special::SpecialType;
+
+/// Statistics about a tree of entries in a [`FileRepo`].
+///
+/// This is returned by [`FileRepo::tree_stats`].
+///
+/// [`FileRepo`]: crate::repo::file::FileRepo
+/// [`FileRepo::tree_stats`]: crate::repo::file::FileRepo::tree_stats
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub struct TreeStats {
+    /// The number of regular files in the tree.
+    pub files: u64,
+
+    /// The number of directories in the tree.
+    pub directories: u64,
+
+    /// The number of special files in the tree.
+    pub special: u64,
+
+    /// The total size of the files in the tree in bytes.
+    pub size: u64,
+
+    /// The number of bytes of data which make up the files in the tree.
+    ///
+    /// This counts data which is shared between files, or between different parts of the same
+    /// file, only once. It does not include holes in sparse files, and it is measured before
+    /// compression and encryption.
+    pub stored_size: u64,
+}
+
+impl<S, M> FileRepo<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    /// Return statistics about the tree of entries at `path`.
+    ///
+    /// If `path` is a directory, the returned statistics include `path` and all its descendants.
+    /// If `path` is empty, they include every entry in the repository. This does not read any
+    /// data from the data store.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: There is no entry at `path`.
+    /// - `Error::TransactionInProgress`: A file in the tree has uncommitted changes.
+    pub fn tree_stats(&self, path: impl AsRef<RelativePath>) -> crate::Result<TreeStats> {
+        self.inner().tree_stats(path)
+    }
+}
+
+impl<S, M> FileRepoInner<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    pub(crate) fn tree_stats(&self, path: impl AsRef<RelativePath>) -> crate::Result<TreeStats> {
+        let path = path.as_ref();
+        let state = self.0.state();
+
+        let mut handles = Vec::new();
+        if path != *EMPTY_PATH {
+            handles.push(*state.get(path).ok_or(crate::Error::NotFound)?);
+        }
+        if path == *EMPTY_PATH || self.is_directory(path) {
+            handles.extend(state.walk(path).unwrap().map(|(_, handle)| *handle));
+        }
+
+        let mut stats = TreeStats::default();
+        let mut chunks = HashSet::new();
+
+        for handle in handles {
+            match handle.entry_type {
+                EntryType::File(object_id) => {
+                    stats.files += 1;
+                    let content_id = self.0.object(object_id).unwrap().content_id()?;
+                    stats.size += content_id.size();
+                    for chunk in content_id.chunks() {
+                        if chunks.insert(chunk.hash) {
+                            stats.stored_size += chunk.size as u64;
+                        }
+                    }
+                }
+                EntryType::Directory => stats.directories += 1,
+                EntryType::Special => stats.special += 1,
+            }
+        }
+
+        Ok(stats)
+    }
+}
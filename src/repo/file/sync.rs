@@ -0,0 +1,433 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use relative_path::{RelativePath, RelativePathBuf};
+use walkdir::WalkDir;
+
+use super::entry::EntryType;
+use super::metadata::{FileMetadata, Ownership};
+use super::repository::{relative_source_path, FileRepo, FileRepoInner, EMPTY_PATH};
+use super::special::SpecialType;
+use crate::repo::Operation;
+
+/// The direction in which [`FileRepo::sync_tree`] copies changes.
+///
+/// [`FileRepo::sync_tree`]: crate::repo::file::FileRepo::sync_tree
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum SyncDirection {
+    /// Update the entries in the repository to match the files in the file system.
+    Archive,
+
+    /// Update the files in the file system to match the entries in the repository.
+    Extract,
+}
+
+/// Options for synchronizing a directory tree with [`FileRepo::sync_tree`].
+///
+/// [`FileRepo::sync_tree`]: crate::repo::file::FileRepo::sync_tree
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    direction: SyncDirection,
+    delete: bool,
+    compare_contents: bool,
+}
+
+impl SyncOptions {
+    /// Create a new `SyncOptions` which copies changes in the given `direction`.
+    ///
+    /// By default, files which were removed from the source are not removed from the destination
+    /// and files are assumed to be unchanged if their size and modification time are the same.
+    pub fn new(direction: SyncDirection) -> Self {
+        Self {
+            direction,
+            delete: false,
+            compare_contents: false,
+        }
+    }
+
+    /// Remove files in the destination which do not exist in the source.
+    pub fn delete(&mut self, delete: bool) -> &mut Self {
+        self.delete = delete;
+        self
+    }
+
+    /// Always compare the contents of files instead of trusting their modification times.
+    ///
+    /// The contents of files in the repository are compared without reading from the data store,
+    /// but files in the file system must still be read in their entirety. Files are always
+    /// compared by their contents if the repository does not store modification times.
+    pub fn compare_contents(&mut self, compare_contents: bool) -> &mut Self {
+        self.compare_contents = compare_contents;
+        self
+    }
+}
+
+/// A summary of the changes made by [`FileRepo::sync_tree`].
+///
+/// Paths are always paths of entries in the repository, even for files which were copied to or
+/// removed from the file system.
+///
+/// [`FileRepo::sync_tree`]: crate::repo::file::FileRepo::sync_tree
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct SyncSummary {
+    /// The paths of entries which were new or changed and were copied.
+    pub copied: Vec<RelativePathBuf>,
+
+    /// The paths of entries which were removed because they no longer exist in the source.
+    pub removed: Vec<RelativePathBuf>,
+
+    /// The number of entries which were unchanged.
+    pub unchanged: u64,
+
+    /// The number of bytes of file contents which were copied.
+    pub bytes: u64,
+
+    /// The paths of entries which were skipped because they are not a supported file type.
+    pub skipped: Vec<RelativePathBuf>,
+}
+
+/// The type of a file or entry, ignoring the details of special files.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Kind {
+    File,
+    Directory,
+    Special,
+}
+
+/// Return the kind of the file at `path` or `None` if it does not exist.
+///
+/// If `follow_links` is `true`, this returns the kind of the target of a symbolic link.
+fn local_kind(path: &Path, follow_links: bool) -> io::Result<Option<Kind>> {
+    let metadata = if follow_links {
+        fs::metadata(path)
+    } else {
+        fs::symlink_metadata(path)
+    };
+
+    match metadata {
+        Ok(metadata) if metadata.is_file() => Ok(Some(Kind::File)),
+        Ok(metadata) if metadata.is_dir() => Ok(Some(Kind::Directory)),
+        Ok(_) => Ok(Some(Kind::Special)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Remove the file or directory tree of the given `kind` at `path`.
+fn remove_local(path: &Path, kind: Kind) -> io::Result<()> {
+    match kind {
+        Kind::Directory => fs::remove_dir_all(path),
+        Kind::File | Kind::Special => fs::remove_file(path),
+    }
+}
+
+impl<S, M> FileRepo<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    /// Synchronize the directory tree at `local` in the file system with the tree at `path`.
+    ///
+    /// Depending on the [`SyncDirection`] in `options`, this either updates the tree at `path` in
+    /// the repository to match the tree at `local` or vice versa. Only files which are new or
+    /// have changed are copied. Files are considered changed if their size differs or, when the
+    /// repository stores modification times, if their modification time differs and their
+    /// contents differ. If [`SyncOptions::delete`] is set, files in the destination which do not
+    /// exist in the source are removed.
+    ///
+    /// File metadata and special files are copied according to the selected [`FileMetadata`] and
+    /// [`SpecialType`] implementations. The metadata of unchanged entries is not updated. Files
+    /// which are not a regular file, directory, or supported special file are recorded in
+    /// [`SyncSummary::skipped`].
+    ///
+    /// This reports its progress to the handler set with [`set_progress_handler`] and can be
+    /// cancelled with the token set with [`set_cancellation_token`]. If this method returns `Err`,
+    /// the changes which were made before the error occurred are kept.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The parent of `path` does not exist or is not a directory.
+    /// - `Error::InvalidPath`: The given `path` is empty.
+    /// - `Error::NotFound`: The direction is [`SyncDirection::Extract`] and there is no entry at
+    ///   `path`.
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::Deserialize`: The file metadata could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`SyncDirection`]: crate::repo::file::SyncDirection
+    /// [`SyncOptions::delete`]: crate::repo::file::SyncOptions::delete
+    /// [`FileMetadata`]: crate::repo::file::FileMetadata
+    /// [`SpecialType`]: crate::repo::file::SpecialType
+    /// [`SyncSummary::skipped`]: crate::repo::file::SyncSummary::skipped
+    /// [`set_progress_handler`]: crate::repo::file::FileRepo::set_progress_handler
+    /// [`set_cancellation_token`]: crate::repo::file::FileRepo::set_cancellation_token
+    /// [`SyncDirection::Extract`]: crate::repo::file::SyncDirection::Extract
+    pub fn sync_tree(
+        &self,
+        local: impl AsRef<Path>,
+        path: impl AsRef<RelativePath>,
+        options: &SyncOptions,
+    ) -> crate::Result<SyncSummary> {
+        self.inner_mut().sync_tree(local, path, options)
+    }
+}
+
+impl<S, M> FileRepoInner<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    pub(crate) fn sync_tree(
+        &mut self,
+        local: impl AsRef<Path>,
+        path: impl AsRef<RelativePath>,
+        options: &SyncOptions,
+    ) -> crate::Result<SyncSummary> {
+        if path.as_ref() == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        match options.direction {
+            SyncDirection::Archive => self.sync_archive(local.as_ref(), path.as_ref(), options),
+            SyncDirection::Extract => self.sync_extract(path.as_ref(), local.as_ref(), options),
+        }
+    }
+
+    /// Update the tree at `dest` in the repository to match the tree at `source`.
+    fn sync_archive(
+        &mut self,
+        source: &Path,
+        dest: &RelativePath,
+        options: &SyncOptions,
+    ) -> crate::Result<SyncSummary> {
+        let mut summary = SyncSummary::default();
+        let mut visited = HashSet::new();
+
+        // `WalkDir` includes `source` in the paths it iterates over.
+        for (index, result) in WalkDir::new(source).into_iter().enumerate() {
+            self.0
+                .report_progress(Operation::SyncTree, index as u64, None)?;
+
+            let source_path = result.map_err(io::Error::from)?.into_path();
+            let dest_path = dest.join(relative_source_path(source, &source_path));
+
+            // `archive` follows symbolic links when determining the file type.
+            let source_kind = local_kind(&source_path, true)?.unwrap_or(Kind::Special);
+            if source_kind == Kind::Special && S::from_file(&source_path)?.is_none() {
+                summary.skipped.push(dest_path);
+                continue;
+            }
+
+            visited.insert(dest_path.clone());
+
+            if let Some(dest_kind) = self.entry_kind(&dest_path) {
+                if !self.is_changed(
+                    &source_path,
+                    &dest_path,
+                    source_kind,
+                    dest_kind,
+                    options.compare_contents,
+                )? {
+                    summary.unchanged += 1;
+                    continue;
+                }
+                self.remove_tree(&dest_path)?;
+            }
+
+            summary.bytes += self.archive_entry(&source_path, &dest_path, false, &mut ())?;
+            summary.copied.push(dest_path);
+        }
+
+        if options.delete && self.is_directory(dest) {
+            let stale_paths = self
+                .walk(dest)?
+                .filter(|path| !visited.contains(path))
+                .collect::<Vec<_>>();
+
+            // Removing a directory also removes its descendants.
+            for stale_path in stale_paths {
+                if self.exists(&stale_path) {
+                    self.remove_tree(&stale_path)?;
+                    summary.removed.push(stale_path);
+                }
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Update the tree at `dest` in the file system to match the tree at `source`.
+    fn sync_extract(
+        &self,
+        source: &RelativePath,
+        dest: &Path,
+        options: &SyncOptions,
+    ) -> crate::Result<SyncSummary> {
+        let mut summary = SyncSummary::default();
+        let mut visited = HashSet::new();
+
+        let mut source_paths = vec![source.to_owned()];
+        if self.is_directory(source) {
+            source_paths.extend(self.walk(source)?);
+        } else if !self.exists(source) {
+            return Err(crate::Error::NotFound);
+        }
+
+        for (index, source_path) in source_paths.into_iter().enumerate() {
+            self.0
+                .report_progress(Operation::SyncTree, index as u64, None)?;
+
+            let dest_path = source_path.strip_prefix(source).unwrap().to_path(dest);
+            visited.insert(dest_path.clone());
+
+            let source_kind = self.entry_kind(&source_path).unwrap();
+            if let Some(dest_kind) = local_kind(&dest_path, false)? {
+                if !self.is_changed(
+                    &dest_path,
+                    &source_path,
+                    dest_kind,
+                    source_kind,
+                    options.compare_contents,
+                )? {
+                    summary.unchanged += 1;
+                    continue;
+                }
+                remove_local(&dest_path, dest_kind)?;
+            }
+
+            summary.bytes +=
+                self.extract_entry(&source_path, &dest_path, Ownership::Preserve, &mut ())?;
+            summary.copied.push(source_path);
+        }
+
+        if options.delete && dest.is_dir() {
+            // `WalkDir` yields parents before their children.
+            let stale_paths = WalkDir::new(dest)
+                .min_depth(1)
+                .into_iter()
+                .map(|result| result.map(|entry| entry.into_path()))
+                .filter(|result| match result {
+                    Ok(path) => !visited.contains(path),
+                    Err(_) => true,
+                })
+                .collect::<Result<Vec<PathBuf>, _>>()
+                .map_err(io::Error::from)?;
+
+            // Removing a directory also removes its descendants.
+            for stale_path in stale_paths {
+                if let Some(kind) = local_kind(&stale_path, false)? {
+                    remove_local(&stale_path, kind)?;
+                    summary
+                        .removed
+                        .push(source.join(relative_source_path(dest, &stale_path)));
+                }
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Return the kind of the entry at `path` or `None` if it does not exist.
+    fn entry_kind(&self, path: &RelativePath) -> Option<Kind> {
+        self.0
+            .state()
+            .get(path)
+            .map(|handle| match handle.entry_type {
+                EntryType::File(_) => Kind::File,
+                EntryType::Directory => Kind::Directory,
+                EntryType::Special => Kind::Special,
+            })
+    }
+
+    /// Return whether the entry at `dest` is unchanged from the file at `source`.
+    ///
+    /// This returns `false` if there is no entry at `dest`. If there is an entry at `dest` which
+    /// has changed, it is removed so that the file can be archived again. If `follow_links` is
+    /// `true`, a symbolic link at `source` is compared using the file it points to.
+    pub(super) fn keep_if_unchanged(
+        &mut self,
+        source: &Path,
+        dest: &RelativePath,
+        follow_links: bool,
+        compare_contents: bool,
+    ) -> crate::Result<bool> {
+        let dest_kind = match self.entry_kind(dest) {
+            Some(kind) => kind,
+            None => return Ok(false),
+        };
+        let source_kind = local_kind(source, follow_links)?.ok_or(crate::Error::NotFound)?;
+
+        if self.is_changed(source, dest, source_kind, dest_kind, compare_contents)? {
+            self.remove_tree(dest)?;
+            Ok(false)
+        } else {
+            Ok(true)
+        }
+    }
+
+    /// Return whether the file at `local` differs from the entry at `path`.
+    fn is_changed(
+        &self,
+        local: &Path,
+        path: &RelativePath,
+        local_kind: Kind,
+        entry_kind: Kind,
+        compare_contents: bool,
+    ) -> crate::Result<bool> {
+        if local_kind != entry_kind {
+            return Ok(true);
+        }
+
+        if local_kind == Kind::Directory {
+            return Ok(false);
+        }
+
+        // Special files are only compared by their modification times, and regular files are
+        // checked by size first since it's cheap.
+        let local_metadata = match local_kind {
+            Kind::File => fs::metadata(local)?,
+            _ => fs::symlink_metadata(local)?,
+        };
+        if local_kind == Kind::File && self.file_size(path)? != local_metadata.len() {
+            return Ok(true);
+        }
+
+        let entry_modified = self
+            .entry(path)?
+            .metadata
+            .and_then(|metadata| metadata.modified());
+        let modified_matches = match entry_modified {
+            Some(modified) => local_metadata.modified()? == modified,
+            None => false,
+        };
+
+        match local_kind {
+            Kind::File if modified_matches && !compare_contents => Ok(false),
+            Kind::File => {
+                let content_id = self.open(path)?.content_id()?;
+                Ok(!content_id.compare_contents(File::open(local)?)?)
+            }
+            _ => Ok(!modified_matches),
+        }
+    }
+}
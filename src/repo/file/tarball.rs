@@ -0,0 +1,313 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(feature = "file-tar")]
+
+use std::io::{self, Read, Write};
+use std::path::{Component, Path};
+
+use relative_path::{RelativePath, RelativePathBuf};
+use tar::{Archive, Builder, EntryType as TarEntryType, Header};
+
+use super::entry::{Entry, FileType};
+use super::metadata::FileMetadata;
+use super::repository::{FileRepo, FileRepoInner, EMPTY_PATH};
+use super::special::SpecialType;
+use super::tree::{copy_with_progress, TreeSummary};
+
+/// The permissions of regular files in exported archives when there is no metadata.
+const DEFAULT_FILE_MODE: u32 = 0o644;
+
+/// The permissions of directories in exported archives when there is no metadata.
+const DEFAULT_DIRECTORY_MODE: u32 = 0o755;
+
+/// Convert the path of an entry in a tar archive to a relative path.
+///
+/// This returns `None` if the path is absolute or contains `..` components, since those could
+/// refer to entries outside the directory the archive is being imported into.
+fn tar_entry_path(path: &Path) -> Option<RelativePathBuf> {
+    let mut relative_path = RelativePathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => relative_path.push(name.to_str()?),
+            Component::CurDir => (),
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(relative_path)
+}
+
+impl<S, M> FileRepo<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    /// Copy the entries in a tar archive into the repository under the directory `dest`.
+    ///
+    /// This reads a tar archive from `reader` and creates an entry under `dest` for each entry in
+    /// the archive. If `dest` does not exist, it is created as a directory. Any missing parent
+    /// directories of entries in the archive are also created.
+    ///
+    /// File metadata is copied from the archive according to the selected [`FileMetadata`]
+    /// implementation, and special files are copied according to the selected [`SpecialType`]
+    /// implementation. Hard links are copied as regular files with the same contents as their
+    /// target.
+    ///
+    /// Entries which are not a regular file, directory, hard link, or supported special file are
+    /// recorded in [`TreeSummary::skipped`]. Entries with paths which are absolute, which contain
+    /// `..` components, or which are not valid UTF-8 are recorded in [`TreeSummary::failed`], as
+    /// are hard links whose target is not in the archive.
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The parent of `dest` does not exist or is not a directory.
+    /// - `Error::NotDirectory`: The entry at `dest` is not a directory.
+    /// - `Error::AlreadyExists`: An entry in the archive already exists in the repository.
+    /// - `Error::QuotaExceeded`: A file in the archive would exceed the quota of a directory.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred or the archive is malformed.
+    ///
+    /// [`FileMetadata`]: crate::repo::file::FileMetadata
+    /// [`SpecialType`]: crate::repo::file::SpecialType
+    /// [`TreeSummary::skipped`]: crate::repo::file::TreeSummary::skipped
+    /// [`TreeSummary::failed`]: crate::repo::file::TreeSummary::failed
+    #[cfg_attr(docsrs, doc(cfg(feature = "file-tar")))]
+    pub fn import_tar(
+        &self,
+        reader: impl Read,
+        dest: impl AsRef<RelativePath>,
+    ) -> crate::Result<TreeSummary> {
+        self.inner_mut().import_tar(reader, dest)
+    }
+
+    /// Write the tree of entries at `source` to `writer` as a tar archive.
+    ///
+    /// If `source` is a directory, the archive contains its descendants with paths relative to
+    /// `source`. If `source` is not a directory, the archive contains just that entry.
+    ///
+    /// File metadata is written to the archive according to the selected [`FileMetadata`]
+    /// implementation, and special files are written according to the selected [`SpecialType`]
+    /// implementation. Entries which have no metadata are given default permissions. Special
+    /// files which can't be represented in a tar archive are recorded in
+    /// [`TreeSummary::skipped`].
+    ///
+    /// # Errors
+    /// - `Error::InvalidPath`: The given `source` path is empty.
+    /// - `Error::NotFound`: There is no entry at `source`.
+    /// - `Error::Deserialize`: The file metadata could not be deserialized.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`FileMetadata`]: crate::repo::file::FileMetadata
+    /// [`SpecialType`]: crate::repo::file::SpecialType
+    /// [`TreeSummary::skipped`]: crate::repo::file::TreeSummary::skipped
+    #[cfg_attr(docsrs, doc(cfg(feature = "file-tar")))]
+    pub fn export_tar(
+        &self,
+        source: impl AsRef<RelativePath>,
+        writer: impl Write,
+    ) -> crate::Result<TreeSummary> {
+        self.inner().export_tar(source, writer)
+    }
+}
+
+impl<S, M> FileRepoInner<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    pub(crate) fn import_tar(
+        &mut self,
+        reader: impl Read,
+        dest: impl AsRef<RelativePath>,
+    ) -> crate::Result<TreeSummary> {
+        let dest = dest.as_ref();
+        let mut summary = TreeSummary::default();
+
+        if dest != *EMPTY_PATH {
+            if !self.exists(dest) {
+                self.create(dest, &Entry::directory())?;
+            } else if !self.is_directory(dest) {
+                return Err(crate::Error::NotDirectory);
+            }
+        }
+
+        let mut archive = Archive::new(reader);
+        for tar_entry in archive.entries()? {
+            let mut tar_entry = tar_entry?;
+            let raw_path = tar_entry.path()?.into_owned();
+
+            let entry_path = match tar_entry_path(&raw_path) {
+                Some(path) if path == *EMPTY_PATH => continue,
+                Some(path) => dest.join(path),
+                None => {
+                    let path = RelativePathBuf::from(raw_path.to_string_lossy().into_owned());
+                    summary.failed.push((path, crate::Error::InvalidPath));
+                    continue;
+                }
+            };
+
+            let header = tar_entry.header().clone();
+            let metadata = M::from_tar_header(&header);
+            let entry_type = header.entry_type();
+
+            if entry_type.is_hard_link() {
+                let target = tar_entry
+                    .link_name()?
+                    .and_then(|target| tar_entry_path(&target))
+                    .map(|target| dest.join(target));
+                match target {
+                    Some(target) if self.is_file(&target) => {
+                        self.create_parent_directories(&entry_path)?;
+                        self.copy(&target, &entry_path)?;
+                        summary.entries += 1;
+                    }
+                    _ => summary.failed.push((entry_path, crate::Error::NotFound)),
+                }
+                continue;
+            }
+
+            let file_type = if entry_type.is_file() {
+                FileType::File
+            } else if entry_type.is_dir() {
+                FileType::Directory
+            } else {
+                match S::from_tar_header(&header)? {
+                    Some(special_type) => FileType::Special(special_type),
+                    None => {
+                        summary.skipped.push(entry_path);
+                        continue;
+                    }
+                }
+            };
+
+            // Directories may have already been created as the parent of an earlier entry.
+            if matches!(file_type, FileType::Directory) && self.is_directory(&entry_path) {
+                self.set_metadata(&entry_path, metadata)?;
+                summary.entries += 1;
+                continue;
+            }
+
+            let is_file = matches!(file_type, FileType::File);
+            if is_file {
+                self.check_quota(&entry_path, header.size()?)?;
+            }
+            self.create_parent_directories(&entry_path)?;
+            self.create(
+                &entry_path,
+                &Entry {
+                    file_type,
+                    metadata,
+                },
+            )?;
+
+            if is_file {
+                let mut object = self.open(&entry_path)?;
+                let size = copy_with_progress(&mut tar_entry, &mut object, |_| ())?;
+                object.commit()?;
+                drop(object);
+                self.record_size(&entry_path, size);
+                summary.bytes += size;
+            }
+
+            summary.entries += 1;
+        }
+
+        Ok(summary)
+    }
+
+    pub(crate) fn export_tar(
+        &self,
+        source: impl AsRef<RelativePath>,
+        writer: impl Write,
+    ) -> crate::Result<TreeSummary> {
+        let source = source.as_ref();
+        let mut summary = TreeSummary::default();
+        let mut builder = Builder::new(writer);
+
+        if source == *EMPTY_PATH {
+            return Err(crate::Error::InvalidPath);
+        }
+
+        let paths = if self.is_directory(source) {
+            self.walk(source)?
+                .map(|path| {
+                    let archive_path = path.strip_prefix(source).unwrap().to_owned();
+                    (path, archive_path)
+                })
+                .collect::<Vec<_>>()
+        } else {
+            let file_name = source.file_name().ok_or(crate::Error::InvalidPath)?;
+            vec![(source.to_owned(), RelativePath::new(file_name).to_owned())]
+        };
+
+        for (path, archive_path) in paths {
+            let entry = self.entry(&path)?;
+            let mut header = Header::new_gnu();
+            header.set_size(0);
+
+            match &entry.file_type {
+                FileType::File => {
+                    header.set_entry_type(TarEntryType::Regular);
+                    header.set_mode(DEFAULT_FILE_MODE);
+                }
+                FileType::Directory => {
+                    header.set_entry_type(TarEntryType::Directory);
+                    header.set_mode(DEFAULT_DIRECTORY_MODE);
+                }
+                FileType::Special(special_type) => {
+                    header.set_mode(DEFAULT_FILE_MODE);
+                    if !special_type.write_tar_header(&mut header)? {
+                        summary.skipped.push(path);
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(metadata) = &entry.metadata {
+                metadata.write_tar_header(&mut header);
+            }
+
+            let archive_path = Path::new(archive_path.as_str());
+            if let FileType::File = entry.file_type {
+                let object = self.open(&path)?;
+                let size = object.size()?;
+                header.set_size(size);
+                builder.append_data(&mut header, archive_path, object)?;
+                summary.bytes += size;
+            } else {
+                builder.append_data(&mut header, archive_path, io::empty())?;
+            }
+
+            summary.entries += 1;
+        }
+
+        builder.finish()?;
+
+        Ok(summary)
+    }
+
+    /// Create any missing parent directories of `path`.
+    fn create_parent_directories(&mut self, path: &RelativePath) -> crate::Result<()> {
+        match path.parent() {
+            Some(parent) if parent != *EMPTY_PATH && !self.exists(parent) => {
+                self.create_parents(parent, &Entry::directory())
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,206 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::io::{self, Read, Write};
+
+use relative_path::{RelativePath, RelativePathBuf};
+
+use super::filter::PathFilter;
+use super::metadata::Ownership;
+
+/// The size of the buffer to use when copying file contents.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// A receiver for progress updates while copying a tree of files.
+///
+/// This is used with [`FileRepo::archive_tree_with`] and [`FileRepo::extract_tree_with`]. All
+/// methods have default implementations which do nothing, so implementations only need to
+/// override the ones they're interested in. Paths are always paths of entries in the repository.
+///
+/// [`FileRepo::archive_tree_with`]: crate::repo::file::FileRepo::archive_tree_with
+/// [`FileRepo::extract_tree_with`]: crate::repo::file::FileRepo::extract_tree_with
+pub trait TreeProgress {
+    /// Called before the entry at `path` is copied.
+    fn entry_started(&mut self, _path: &RelativePath) {}
+
+    /// Called periodically while the contents of the file at `path` are copied.
+    ///
+    /// `bytes` is the total number of bytes of the file which have been copied so far.
+    fn bytes_copied(&mut self, _path: &RelativePath, _bytes: u64) {}
+
+    /// Called after the entry at `path` has been copied successfully.
+    fn entry_finished(&mut self, _path: &RelativePath) {}
+}
+
+impl TreeProgress for () {}
+
+/// A summary of a tree of files which was copied.
+///
+/// This is returned by [`FileRepo::archive_tree_with`] and [`FileRepo::extract_tree_with`], as
+/// well as by the methods for importing and exporting tar archives. Paths are always paths of
+/// entries in the repository.
+///
+/// [`FileRepo::archive_tree_with`]: crate::repo::file::FileRepo::archive_tree_with
+/// [`FileRepo::extract_tree_with`]: crate::repo::file::FileRepo::extract_tree_with
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct TreeSummary {
+    /// The number of entries which were copied.
+    pub entries: u64,
+
+    /// The number of bytes of file contents which were copied.
+    pub bytes: u64,
+
+    /// The number of entries which were not copied because they were unchanged.
+    ///
+    /// This is only nonzero for incremental archives.
+    pub unchanged: u64,
+
+    /// The paths of entries which were skipped because they are not a supported file type.
+    pub skipped: Vec<RelativePathBuf>,
+
+    /// The paths of entries which could not be copied and the errors which occurred.
+    pub failed: Vec<(RelativePathBuf, crate::Error)>,
+}
+
+impl TreeSummary {
+    /// Add the entries counted in `other` to this summary.
+    pub(super) fn merge(&mut self, other: TreeSummary) {
+        self.entries += other.entries;
+        self.bytes += other.bytes;
+        self.unchanged += other.unchanged;
+        self.skipped.extend(other.skipped);
+        self.failed.extend(other.failed);
+    }
+}
+
+/// Options for copying a directory tree into a [`FileRepo`] with
+/// [`FileRepo::archive_tree_with_options`].
+///
+/// [`FileRepo`]: crate::repo::file::FileRepo
+/// [`FileRepo::archive_tree_with_options`]: crate::repo::file::FileRepo::archive_tree_with_options
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveOptions {
+    pub(super) filter: PathFilter,
+    pub(super) follow_links: bool,
+    pub(super) incremental: bool,
+    pub(super) compare_contents: bool,
+}
+
+impl ArchiveOptions {
+    /// Create a new `ArchiveOptions` which copies every file and does not follow symbolic links.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only copy the files which are selected by `filter`.
+    pub fn filter(&mut self, filter: PathFilter) -> &mut Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Copy the files and directories which symbolic links point to instead of the links.
+    ///
+    /// When this is `true`, the descendants of directories which are pointed to by symbolic links
+    /// are also copied. Symbolic links which point to one of their own ancestors are skipped to
+    /// avoid copying the same tree forever.
+    pub fn follow_links(&mut self, follow_links: bool) -> &mut Self {
+        self.follow_links = follow_links;
+        self
+    }
+
+    /// Only copy the files which are new or have changed since they were last archived.
+    ///
+    /// When this is `true`, the destination may already exist. Files which already have an entry
+    /// in the repository with the same size and modification time are not copied again, and
+    /// entries for files which have changed are replaced. Entries which do not have a
+    /// corresponding file in the tree are left alone. Files are always compared by their contents
+    /// if the repository does not store modification times.
+    pub fn incremental(&mut self, incremental: bool) -> &mut Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Compare the contents of files instead of trusting their modification times.
+    ///
+    /// This only has an effect on incremental archives. The contents of entries in the repository
+    /// are compared without reading from the data store, but files in the file system must still
+    /// be read in their entirety.
+    pub fn compare_contents(&mut self, compare_contents: bool) -> &mut Self {
+        self.compare_contents = compare_contents;
+        self
+    }
+}
+
+/// Options for copying a tree of entries out of a [`FileRepo`] with
+/// [`FileRepo::extract_tree_with_options`].
+///
+/// [`FileRepo`]: crate::repo::file::FileRepo
+/// [`FileRepo::extract_tree_with_options`]: crate::repo::file::FileRepo::extract_tree_with_options
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    pub(super) filter: PathFilter,
+    pub(super) ownership: Ownership,
+}
+
+impl ExtractOptions {
+    /// Create a new `ExtractOptions` which copies every entry and preserves file owners.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only copy the entries which are selected by `filter`.
+    pub fn filter(&mut self, filter: PathFilter) -> &mut Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Set the owner of extracted files according to `ownership`.
+    ///
+    /// By default, the stored owner is preserved when the current user has permission to change
+    /// the owner of files and ignored otherwise.
+    pub fn ownership(&mut self, ownership: Ownership) -> &mut Self {
+        self.ownership = ownership;
+        self
+    }
+}
+
+/// Copy all bytes from `reader` to `writer`, calling `on_progress` with the total number of bytes
+/// copied after each chunk.
+///
+/// This returns the total number of bytes copied.
+pub fn copy_with_progress(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    mut on_progress: impl FnMut(u64),
+) -> io::Result<u64> {
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut bytes_copied = 0u64;
+
+    loop {
+        let bytes_read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(bytes_read) => bytes_read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        writer.write_all(&buffer[..bytes_read])?;
+        bytes_copied += bytes_read as u64;
+        on_progress(bytes_copied);
+    }
+
+    Ok(bytes_copied)
+}
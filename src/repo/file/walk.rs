@@ -0,0 +1,214 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::iter::Peekable;
+use std::vec;
+
+use relative_path::{RelativePath, RelativePathBuf};
+
+use super::entry::{EntryHandle, EntryType};
+use super::metadata::FileMetadata;
+use super::repository::{FileRepo, FileRepoInner, EMPTY_PATH};
+use super::special::SpecialType;
+
+/// Options for walking a tree of entries with [`FileRepo::walk_with`].
+///
+/// [`FileRepo::walk_with`]: crate::repo::file::FileRepo::walk_with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalkOptions {
+    max_depth: Option<usize>,
+    files: bool,
+    directories: bool,
+    special: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WalkOptions {
+    /// Create a new `WalkOptions` which visits every descendant.
+    pub fn new() -> Self {
+        Self {
+            max_depth: None,
+            files: true,
+            directories: true,
+            special: true,
+        }
+    }
+
+    /// Do not visit entries which are more than `depth` levels below the parent.
+    ///
+    /// Children of the parent have a depth of `1`, so a `depth` of `1` only visits the children
+    /// of the parent.
+    pub fn max_depth(&mut self, depth: usize) -> &mut Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Whether to return regular files.
+    pub fn files(&mut self, files: bool) -> &mut Self {
+        self.files = files;
+        self
+    }
+
+    /// Whether to return directories.
+    ///
+    /// The descendants of directories are still visited when this is `false`.
+    pub fn directories(&mut self, directories: bool) -> &mut Self {
+        self.directories = directories;
+        self
+    }
+
+    /// Whether to return special files.
+    pub fn special(&mut self, special: bool) -> &mut Self {
+        self.special = special;
+        self
+    }
+
+    /// Return whether entries with the given `handle` are returned.
+    fn is_selected(&self, handle: &EntryHandle) -> bool {
+        match handle.entry_type {
+            EntryType::File(_) => self.files,
+            EntryType::Directory => self.directories,
+            EntryType::Special => self.special,
+        }
+    }
+}
+
+/// An iterator over the descendants of a directory in a [`FileRepo`].
+///
+/// This is returned by [`FileRepo::walk_with`]. Paths are returned in depth-first order, and the
+/// descendants of a directory can be skipped with [`skip_descendants`].
+///
+/// The paths are collected when the iterator is created, so the repository can be modified while
+/// it is in use.
+///
+/// [`FileRepo`]: crate::repo::file::FileRepo
+/// [`FileRepo::walk_with`]: crate::repo::file::FileRepo::walk_with
+/// [`skip_descendants`]: crate::repo::file::Walk::skip_descendants
+#[derive(Debug)]
+pub struct Walk {
+    /// Each descendant along with its depth and whether it is returned by the iterator.
+    descendants: Peekable<vec::IntoIter<(RelativePathBuf, usize, bool)>>,
+
+    /// The depth of the last path which was returned.
+    depth: usize,
+}
+
+impl Walk {
+    /// Do not visit the descendants of the path which was last returned by this iterator.
+    ///
+    /// This has no effect if the last path returned was not a directory.
+    pub fn skip_descendants(&mut self) {
+        while let Some((_, depth, _)) = self.descendants.peek() {
+            if *depth <= self.depth {
+                break;
+            }
+            self.descendants.next();
+        }
+    }
+
+    /// Return the depth of the path which was last returned by this iterator.
+    ///
+    /// Children of the parent have a depth of `1`.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+impl Iterator for Walk {
+    type Item = RelativePathBuf;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (path, depth, is_selected) = self.descendants.next()?;
+            self.depth = depth;
+            if is_selected {
+                return Some(path);
+            }
+        }
+    }
+}
+
+impl<S, M> FileRepo<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    /// Return an iterator of paths which are descendants of `parent` using the given `options`.
+    ///
+    /// This is like [`walk`], except that [`WalkOptions`] can limit the depth of the walk and
+    /// which types of entries are returned. The returned [`Walk`] can also skip the descendants of
+    /// any directory it returns. Descendants are visited lazily, so skipped subtrees are never
+    /// traversed.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: The given `parent` does not exist.
+    /// - `Error::NotDirectory`: The given `parent` is not a directory.
+    ///
+    /// [`walk`]: crate::repo::file::FileRepo::walk
+    /// [`WalkOptions`]: crate::repo::file::WalkOptions
+    /// [`Walk`]: crate::repo::file::Walk
+    pub fn walk_with(
+        &self,
+        parent: impl AsRef<RelativePath>,
+        options: &WalkOptions,
+    ) -> crate::Result<Walk> {
+        self.inner().walk_with(parent, options)
+    }
+}
+
+impl<S, M> FileRepoInner<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    pub(crate) fn walk_with(
+        &self,
+        parent: impl AsRef<RelativePath>,
+        options: &WalkOptions,
+    ) -> crate::Result<Walk> {
+        let parent = parent.as_ref();
+
+        if parent != *EMPTY_PATH && !self.exists(parent) {
+            return Err(crate::Error::NotFound);
+        }
+
+        if parent != *EMPTY_PATH && !self.is_directory(parent) {
+            return Err(crate::Error::NotDirectory);
+        }
+
+        let mut descendants = self.0.state().descendants(parent).unwrap();
+        let mut entries = Vec::new();
+        while let Some((path, handle)) = descendants.next() {
+            let depth = descendants.depth();
+            if let Some(max_depth) = options.max_depth {
+                if depth >= max_depth {
+                    descendants.skip_descendants();
+                }
+            }
+            entries.push((path, depth, options.is_selected(handle)));
+        }
+
+        Ok(Walk {
+            descendants: entries.into_iter().peekable(),
+            depth: 0,
+        })
+    }
+}
@@ -0,0 +1,644 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(feature = "file-webdav")]
+
+use std::fmt::{self, Debug, Formatter, Write as _};
+use std::io::{self, Read};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use relative_path::{RelativePath, RelativePathBuf};
+use tiny_http::{Header, Request, Response, Server, StatusCode};
+use uuid::Uuid;
+
+use super::entry::Entry;
+use super::metadata::FileMetadata;
+use super::repository::{FileRepo, EMPTY_PATH};
+use super::special::SpecialType;
+use super::tree::copy_with_progress;
+use crate::repo::{Commit, Object, RestoreSavepoint};
+
+/// The characters which are percent-encoded in each segment of a URL path.
+const SEGMENT_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'&')
+    .add(b'\'')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// How often the server checks whether it has been shut down while waiting for a request.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The methods which are supported by the server.
+const ALLOWED_METHODS: &str =
+    "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL, COPY, MOVE, PROPFIND, PROPPATCH, LOCK, UNLOCK";
+
+/// The response to a WebDAV request.
+enum Reply {
+    /// A response with no body.
+    Empty(u16, Vec<Header>),
+
+    /// A response with an XML body.
+    Xml(u16, Vec<Header>, String),
+
+    /// A response with the contents of a file as its body.
+    File(Box<Object>, u64, Vec<Header>),
+}
+
+/// Create a header with the given `name` and `value`.
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+/// Return the value of the header `name` in `request`, if it is present.
+fn header_value<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// Convert the path of a URL to a path relative to the root of the served tree.
+///
+/// This returns `None` if the path contains `..` components or is not valid UTF-8.
+fn url_path(url: &str) -> Option<RelativePathBuf> {
+    // Strip the scheme and authority from absolute URLs, such as in the `Destination` header.
+    let path = match url.find("://") {
+        Some(index) => {
+            let rest = &url[index + 3..];
+            &rest[rest.find('/').unwrap_or(rest.len())..]
+        }
+        None => url,
+    };
+    let path = path.split(&['?', '#'][..]).next().unwrap();
+
+    let mut relative_path = RelativePathBuf::new();
+    for segment in path.split('/') {
+        let segment = percent_decode_str(segment).decode_utf8().ok()?;
+        match segment.as_ref() {
+            "" | "." => (),
+            ".." => return None,
+            name => relative_path.push(name),
+        }
+    }
+    Some(relative_path)
+}
+
+/// Escape `value` so that it can be included in an XML document.
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Return the HTTP status code for `error`.
+fn error_status(error: &crate::Error) -> u16 {
+    match error {
+        crate::Error::NotFound => 404,
+        crate::Error::AlreadyExists => 405,
+        crate::Error::NotEmpty | crate::Error::InvalidPath | crate::Error::NotDirectory => 409,
+        crate::Error::NotFile => 405,
+        crate::Error::QuotaExceeded => 507,
+        _ => 500,
+    }
+}
+
+/// A WebDAV server which serves a tree of entries in a `FileRepo`.
+struct WebDavServer<'a, S: SpecialType, M: FileMetadata> {
+    /// The repository which contains the served tree.
+    repo: &'a FileRepo<S, M>,
+
+    /// The path of the directory in the repository which is served as the root of the server.
+    root: RelativePathBuf,
+}
+
+impl<'a, S: SpecialType, M: FileMetadata> WebDavServer<'a, S, M> {
+    /// Handle a single `request`.
+    fn handle(&mut self, request: &mut Request) -> crate::Result<Reply> {
+        let relative_path = match url_path(request.url()) {
+            Some(path) => path,
+            None => return Ok(Reply::Empty(400, Vec::new())),
+        };
+        let path = self.root.join(&relative_path);
+
+        match request.method().to_string().as_str() {
+            "OPTIONS" => Ok(Reply::Empty(
+                200,
+                vec![
+                    header("DAV", "1, 2"),
+                    header("Allow", ALLOWED_METHODS),
+                    header("MS-Author-Via", "DAV"),
+                ],
+            )),
+            "GET" | "HEAD" => self.get(&path),
+            "PUT" => self.put(&path, request),
+            "DELETE" => {
+                self.check_writable(&relative_path)?;
+                self.repo.remove_tree(&path)?;
+                self.repo.commit()?;
+                Ok(Reply::Empty(204, Vec::new()))
+            }
+            "MKCOL" => {
+                self.check_writable(&relative_path)?;
+                if self.repo.exists(&path) {
+                    return Ok(Reply::Empty(405, Vec::new()));
+                }
+                if request.body_length().unwrap_or(0) > 0 {
+                    return Ok(Reply::Empty(415, Vec::new()));
+                }
+                self.repo.create(&path, &Entry::directory())?;
+                self.repo.commit()?;
+                Ok(Reply::Empty(201, Vec::new()))
+            }
+            method @ "COPY" | method @ "MOVE" => {
+                self.copy_or_move(&path, request, method == "MOVE")
+            }
+            "PROPFIND" => self.propfind(&relative_path, request),
+            "PROPPATCH" => {
+                // Dead properties aren't stored, but clients like Windows Explorer expect setting
+                // them to succeed.
+                if !self.exists(&path) {
+                    return Ok(Reply::Empty(404, Vec::new()));
+                }
+                let mut body = String::from(
+                    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">",
+                );
+                self.write_response_start(&mut body, &relative_path);
+                body.push_str(
+                    "<D:propstat><D:prop/><D:status>HTTP/1.1 200 OK</D:status></D:propstat>\
+                     </D:response></D:multistatus>",
+                );
+                Ok(Reply::Xml(207, Vec::new(), body))
+            }
+            "LOCK" => self.lock(&path, &relative_path),
+            "UNLOCK" => Ok(Reply::Empty(204, Vec::new())),
+            _ => Ok(Reply::Empty(405, vec![header("Allow", ALLOWED_METHODS)])),
+        }
+    }
+
+    /// Return whether there is an entry at `path`.
+    ///
+    /// The root of the repository can be served, but it doesn't have an entry.
+    fn exists(&self, path: &RelativePath) -> bool {
+        path == *EMPTY_PATH || self.repo.exists(path)
+    }
+
+    /// Return whether the entry at `path` is a directory.
+    fn is_directory(&self, path: &RelativePath) -> bool {
+        path == *EMPTY_PATH || self.repo.is_directory(path)
+    }
+
+    /// Return the entry at `path`, treating the root of the repository as a directory.
+    fn entry(&self, path: &RelativePath) -> crate::Result<Entry<S, M>> {
+        if path == *EMPTY_PATH {
+            Ok(Entry::directory())
+        } else {
+            self.repo.entry(path)
+        }
+    }
+
+    /// Return an error if the entry at `relative_path` is the root of the served tree.
+    fn check_writable(&self, relative_path: &RelativePath) -> crate::Result<()> {
+        if relative_path == *EMPTY_PATH {
+            Err(crate::Error::InvalidPath)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Handle a `GET` or `HEAD` request for the entry at `path`.
+    fn get(&mut self, path: &RelativePath) -> crate::Result<Reply> {
+        let entry = self.entry(path)?;
+        if !entry.is_file() {
+            return Ok(Reply::Empty(405, vec![header("Allow", ALLOWED_METHODS)]));
+        }
+
+        let mut headers = vec![header("Content-Type", "application/octet-stream")];
+        if let Some(modified) = entry
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.modified())
+        {
+            headers.push(header("Last-Modified", &httpdate::fmt_http_date(modified)));
+        }
+
+        let size = self.repo.file_size(path)?;
+        Ok(Reply::File(Box::new(self.repo.open(path)?), size, headers))
+    }
+
+    /// Handle a `PUT` request which replaces the contents of the file at `path`.
+    fn put(&mut self, path: &RelativePath, request: &mut Request) -> crate::Result<Reply> {
+        if path == self.root {
+            return Ok(Reply::Empty(405, Vec::new()));
+        }
+
+        let status = if self.repo.exists(path) {
+            if !self.repo.is_file(path) {
+                return Ok(Reply::Empty(405, Vec::new()));
+            }
+            204
+        } else {
+            self.repo.create(path, &Entry::file())?;
+            201
+        };
+
+        if let Some(length) = request.body_length() {
+            let old_size = self.repo.file_size(path)?;
+            self.repo
+                .check_quota(path, (length as u64).saturating_sub(old_size))?;
+        }
+
+        let mut object = self.repo.open(path)?;
+        object.set_len(0)?;
+        let size = copy_with_progress(request.as_reader(), &mut object, |_| ())?;
+        object.commit()?;
+        drop(object);
+
+        self.repo.inner_mut().record_size(path, size);
+        self.repo.commit()?;
+
+        Ok(Reply::Empty(status, Vec::new()))
+    }
+
+    /// Handle a `COPY` or `MOVE` request for the entry at `source`.
+    fn copy_or_move(
+        &mut self,
+        source: &RelativePath,
+        request: &Request,
+        is_move: bool,
+    ) -> crate::Result<Reply> {
+        let dest = match header_value(request, "Destination").and_then(url_path) {
+            Some(path) => self.root.join(path),
+            None => return Ok(Reply::Empty(400, Vec::new())),
+        };
+        let overwrite = header_value(request, "Overwrite") != Some("F");
+        let shallow = header_value(request, "Depth") == Some("0");
+
+        if !self.exists(source) {
+            return Ok(Reply::Empty(404, Vec::new()));
+        }
+        if source == self.root || dest == self.root || dest.starts_with(source) {
+            return Ok(Reply::Empty(403, Vec::new()));
+        }
+
+        let status = if self.repo.exists(&dest) {
+            if !overwrite {
+                return Ok(Reply::Empty(412, Vec::new()));
+            }
+            self.repo.remove_tree(&dest)?;
+            204
+        } else {
+            201
+        };
+
+        if is_move {
+            self.repo.rename(source, &dest)?;
+        } else if shallow {
+            self.repo.copy(source, &dest)?;
+        } else {
+            self.repo.copy_tree(source, &dest)?;
+        }
+        self.repo.commit()?;
+
+        Ok(Reply::Empty(status, Vec::new()))
+    }
+
+    /// Handle a `PROPFIND` request for the entry at `relative_path`.
+    fn propfind(
+        &mut self,
+        relative_path: &RelativePath,
+        request: &Request,
+    ) -> crate::Result<Reply> {
+        let path = self.root.join(relative_path);
+        if !self.exists(&path) {
+            return Ok(Reply::Empty(404, Vec::new()));
+        }
+
+        // Listing an entire tree can be expensive, so a depth of `infinity` is treated as `1`.
+        let mut paths = vec![relative_path.to_owned()];
+        if header_value(request, "Depth") != Some("0") && self.is_directory(&path) {
+            for child in self.repo.list(&path)? {
+                paths.push(child.strip_prefix(&self.root).unwrap().to_owned());
+            }
+        }
+
+        let mut body = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">",
+        );
+        for relative_path in paths {
+            self.write_properties(&mut body, &relative_path)?;
+        }
+        body.push_str("</D:multistatus>");
+
+        Ok(Reply::Xml(207, Vec::new(), body))
+    }
+
+    /// Handle a `LOCK` request for the entry at `path`.
+    ///
+    /// Locks are not enforced, but clients like macOS Finder and Windows Explorer only allow
+    /// writing to servers which support them. Locking a path which doesn't exist creates an empty
+    /// file.
+    fn lock(&mut self, path: &RelativePath, relative_path: &RelativePath) -> crate::Result<Reply> {
+        let status = if self.exists(path) {
+            200
+        } else {
+            self.check_writable(relative_path)?;
+            self.repo.create(path, &Entry::file())?;
+            self.repo.commit()?;
+            201
+        };
+
+        let token = format!("opaquelocktoken:{}", Uuid::new_v4());
+        let body = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <D:prop xmlns:D=\"DAV:\"><D:lockdiscovery><D:activelock>\
+             <D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope>\
+             <D:depth>0</D:depth><D:timeout>Second-3600</D:timeout>\
+             <D:locktoken><D:href>{}</D:href></D:locktoken>\
+             </D:activelock></D:lockdiscovery></D:prop>",
+            token
+        );
+
+        Ok(Reply::Xml(
+            status,
+            vec![header("Lock-Token", &format!("<{}>", token))],
+            body,
+        ))
+    }
+
+    /// Write the opening of a `response` element for the entry at `relative_path` to `body`.
+    fn write_response_start(&self, body: &mut String, relative_path: &RelativePath) {
+        let mut href = String::from("/");
+        for component in relative_path.components() {
+            href.push_str(&utf8_percent_encode(component.as_str(), SEGMENT_ENCODE_SET).to_string());
+            href.push('/');
+        }
+        if !self.is_directory(&self.root.join(relative_path)) && href.len() > 1 {
+            href.pop();
+        }
+        write!(body, "<D:response><D:href>{}</D:href>", escape_xml(&href)).unwrap();
+    }
+
+    /// Write a `response` element with the properties of the entry at `relative_path` to `body`.
+    fn write_properties(
+        &self,
+        body: &mut String,
+        relative_path: &RelativePath,
+    ) -> crate::Result<()> {
+        let path = self.root.join(relative_path);
+        let entry = self.entry(&path)?;
+
+        self.write_response_start(body, relative_path);
+        body.push_str("<D:propstat><D:prop>");
+
+        let display_name = relative_path.file_name().unwrap_or("");
+        write!(
+            body,
+            "<D:displayname>{}</D:displayname>",
+            escape_xml(display_name)
+        )
+        .unwrap();
+
+        if entry.is_directory() {
+            body.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+        } else {
+            body.push_str("<D:resourcetype/>");
+        }
+
+        if entry.is_file() {
+            write!(
+                body,
+                "<D:getcontentlength>{}</D:getcontentlength>",
+                self.repo.file_size(&path)?
+            )
+            .unwrap();
+            body.push_str("<D:getcontenttype>application/octet-stream</D:getcontenttype>");
+        }
+
+        if let Some(modified) = entry
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.modified())
+        {
+            write!(
+                body,
+                "<D:getlastmodified>{}</D:getlastmodified>",
+                httpdate::fmt_http_date(modified)
+            )
+            .unwrap();
+        }
+
+        body.push_str(
+            "<D:supportedlock><D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+             <D:locktype><D:write/></D:locktype></D:lockentry></D:supportedlock>\
+             </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        );
+
+        Ok(())
+    }
+}
+
+/// Send `reply` as the response to `request`.
+fn respond(request: Request, reply: Reply, is_head: bool) -> io::Result<()> {
+    let (status, headers, body, length): (u16, Vec<Header>, Box<dyn Read>, usize) = match reply {
+        Reply::Empty(status, headers) => (status, headers, Box::new(io::empty()), 0),
+        Reply::Xml(status, mut headers, body) => {
+            headers.push(header("Content-Type", "application/xml; charset=utf-8"));
+            let length = body.len();
+            (
+                status,
+                headers,
+                Box::new(io::Cursor::new(body.into_bytes())),
+                length,
+            )
+        }
+        Reply::File(object, size, headers) => (200, headers, object, size as usize),
+    };
+
+    let body: Box<dyn Read> = if is_head { Box::new(io::empty()) } else { body };
+    request.respond(Response::new(
+        StatusCode(status),
+        headers,
+        body,
+        Some(length),
+        None,
+    ))
+}
+
+/// A socket which is listening for WebDAV requests.
+///
+/// A `WebDavListener` is passed to [`FileRepo::serve_webdav`] to serve a repository. Binding the
+/// socket before serving the repository allows you to find out which port it's listening on with
+/// [`local_addr`] and to get a [`WebDavShutdown`] for stopping the server from another thread.
+///
+/// [`FileRepo::serve_webdav`]: crate::repo::file::FileRepo::serve_webdav
+/// [`local_addr`]: crate::repo::file::WebDavListener::local_addr
+/// [`WebDavShutdown`]: crate::repo::file::WebDavShutdown
+#[cfg_attr(docsrs, doc(cfg(feature = "file-webdav")))]
+pub struct WebDavListener {
+    server: Server,
+    shutdown: WebDavShutdown,
+}
+
+impl WebDavListener {
+    /// Start listening for HTTP connections on `address`.
+    ///
+    /// If the port in `address` is `0`, the operating system picks an unused port.
+    ///
+    /// # Errors
+    /// - `Error::Io`: The socket could not be bound.
+    pub fn bind(address: impl ToSocketAddrs) -> crate::Result<Self> {
+        let server = Server::http(address).map_err(|error| io::Error::other(error.to_string()))?;
+        Ok(Self {
+            server,
+            shutdown: WebDavShutdown::default(),
+        })
+    }
+
+    /// Return the address the socket is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server.server_addr()
+    }
+
+    /// Return a handle for stopping the server which serves requests from this listener.
+    pub fn shutdown_handle(&self) -> WebDavShutdown {
+        self.shutdown.clone()
+    }
+}
+
+impl Debug for WebDavListener {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebDavListener")
+            .field("address", &self.local_addr())
+            .field("shutdown", &self.shutdown)
+            .finish()
+    }
+}
+
+/// A handle for stopping a WebDAV server.
+///
+/// This handle can be cloned and sent to other threads. Once [`shutdown`] is called on any clone
+/// of the handle, [`FileRepo::serve_webdav`] finishes the request it's currently handling and
+/// returns.
+///
+/// [`shutdown`]: crate::repo::file::WebDavShutdown::shutdown
+/// [`FileRepo::serve_webdav`]: crate::repo::file::FileRepo::serve_webdav
+#[cfg_attr(docsrs, doc(cfg(feature = "file-webdav")))]
+#[derive(Debug, Clone, Default)]
+pub struct WebDavShutdown(Arc<AtomicBool>);
+
+impl WebDavShutdown {
+    /// Stop the server.
+    pub fn shutdown(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Return whether the server has been stopped.
+    pub fn is_shutdown(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl<S, M> FileRepo<S, M>
+where
+    S: SpecialType,
+    M: FileMetadata,
+{
+    /// Serve the tree of entries at `root` over WebDAV.
+    ///
+    /// This serves the directory at `root` as a WebDAV share using an HTTP server which accepts
+    /// connections from `listener`. This allows the repository to be mapped as a network drive on
+    /// platforms which don't support FUSE, such as Windows and macOS. The `root` may be an empty
+    /// path to serve the whole repository. Changes made through the server are committed after
+    /// each request.
+    ///
+    /// The server implements WebDAV class 2, but locks are not enforced, and dead properties set
+    /// with `PROPPATCH` are not stored. Requests are handled one at a time. The server does not
+    /// support TLS or authentication, so it should only listen on a trusted interface, such as
+    /// `127.0.0.1`.
+    ///
+    /// This method does not return until the server is stopped with the [`WebDavShutdown`] from
+    /// [`WebDavListener::shutdown_handle`] or an error occurs.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: There is no entry at `root`.
+    /// - `Error::NotDirectory`: The given `root` entry is not a directory.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`WebDavShutdown`]: crate::repo::file::WebDavShutdown
+    /// [`WebDavListener::shutdown_handle`]: crate::repo::file::WebDavListener::shutdown_handle
+    #[cfg_attr(docsrs, doc(cfg(feature = "file-webdav")))]
+    pub fn serve_webdav(
+        &self,
+        listener: WebDavListener,
+        root: impl AsRef<RelativePath>,
+    ) -> crate::Result<()> {
+        let root = root.as_ref();
+        if root != *EMPTY_PATH && !self.entry(root)?.is_directory() {
+            return Err(crate::Error::NotDirectory);
+        }
+
+        let mut handler = WebDavServer {
+            repo: self,
+            root: root.to_owned(),
+        };
+
+        while !listener.shutdown.is_shutdown() {
+            // Wait for requests with a timeout so that we notice when the server is shut down.
+            let mut request = match listener.server.recv_timeout(SHUTDOWN_POLL_INTERVAL)? {
+                Some(request) => request,
+                None => continue,
+            };
+            let is_head = request.method().to_string() == "HEAD";
+            let savepoint = handler.repo.savepoint()?;
+            let reply = match handler.handle(&mut request) {
+                Ok(reply) => reply,
+                Err(error) => {
+                    // Roll back any changes made while handling the request.
+                    handler.repo.restore(&savepoint)?;
+                    Reply::Empty(error_status(&error), Vec::new())
+                }
+            };
+            // A client disconnecting shouldn't stop the server.
+            respond(request, reply, is_head).ok();
+        }
+
+        Ok(())
+    }
+}
@@ -37,16 +37,52 @@
 //! # Locking
 //! A repository cannot be open more than once simultaneously. Once a repository is opened, it is
 //! locked from further open attempts within the same process until the repository is dropped.
-//! However, **repositories can not protect against concurrent access from multiple processes or
+//! By default, attempting to open a locked repository fails immediately, but you can use
+//! [`LockStrategy`] to wait for the lock to be released instead.
+//!
+//! By default, **repositories can not protect against concurrent access from multiple processes or
 //! machines**. Opening a repository from multiple processes or machines simultaneously may cause
-//! data loss.
+//! data loss. To protect against this, you can enable leases by setting
+//! [`RepoConfig::lease_duration`] when creating the repository. A lease is stored in the data store
+//! itself, so it is respected by every process which shares that data store. Leases are renewed in
+//! the background while the repository is open and expire if they are not renewed, so a process
+//! which crashes cannot lock the repository forever.
+//!
+//! # Sharing between threads
+//! Repositories and objects are `Send` and `Sync`, and the state they share is protected by
+//! internal locks. Every method of a repository takes `&self`, so a repository can be shared
+//! between threads behind an `Arc` and modified from all of them without an external `RwLock` or
+//! `Mutex`.
+//!
+//! Each repository is protected by a single read-write lock, which is global to the repository
+//! rather than split by what an operation touches. Methods which only read from a repository,
+//! like getting an [`Object`] or listing keys, take it for reading and can run at the same time.
+//! Methods which modify a repository, like inserting objects or committing changes, take it for
+//! writing, so they wait for every other operation on the repository to finish and block every
+//! other operation until they're done. The objects a repository returns don't hold this lock, so
+//! they can be read from and written to concurrently with each other and with reads from the
+//! repository.
+//!
+//! Hooks, progress handlers, and other callbacks are called while the repository is locked, so they
+//! must not call methods of the same repository. The same goes for the references returned by
+//! [`StateRepo::state`] and [`StateRepo::state_mut`], which keep the repository locked until they
+//! are dropped. Methods which list the contents of a repository, like [`KeyRepo::keys`], copy the
+//! listing before they return, so the iterators they return don't hold the lock.
+//!
+//! If a thread panics while it holds one of these internal locks, like when a data store panics
+//! while an object is being written, the repository keeps working instead of panicking on every
+//! subsequent operation. Changes which were in progress when the thread panicked may be left
+//! half-finished, so [`Commit::commit`] returns `Error::Poisoned` until you use
+//! [`Commit::rollback`] to discard uncommitted changes.
 //!
 //! # Atomicity
 //! Changes made to a repository are not persisted to the data store until those changes are
 //! committed. Committing a repository is an atomic and consistent operation; changes cannot be
 //! partially committed and interrupting a commit will never leave the repository in an inconsistent
-//! state. If the repository is dropped or the thread panics, any uncommitted changes are rolled
-//! back automatically. You can use [`Commit::commit`] to commit changes to a repository.
+//! state, even with data stores which don't support atomic operations on multiple blocks. If a
+//! commit is interrupted, it is rolled back or completed the next time the repository is opened. If
+//! the repository is dropped or the thread panics, any uncommitted changes are rolled back
+//! automatically. You can use [`Commit::commit`] to commit changes to a repository.
 //!
 //! When data in a repository is deleted, the space is not reclaimed in the backing data store until
 //! those changes are committed and the repository is cleaned. Cleaning a repository can be an
@@ -99,27 +135,71 @@
 //! repository commits changes for all instances of that repository; it is not possible to commit
 //! changes to only a single instance. The same goes for rolling back changes.
 //!
+//! # Branches
+//! Each instance of a repository can have multiple named branches. A branch is a copy of the
+//! contents of an instance which can be modified independently of the other branches. Creating a
+//! branch is cheap because branches share the same underlying storage, and data is deduplicated
+//! between them. You can create and switch between branches using [`SwitchBranch`].
+//!
+//! # Async
+//! Repositories perform blocking I/O. If the `async` feature is enabled, you can wrap a repository
+//! in an [`AsyncRepo`] to perform operations from async code without blocking the async runtime.
+//! Objects returned by an [`AsyncRepo`] are wrapped in an [`AsyncObject`], and listings like keys
+//! and paths are returned as an [`AsyncStream`].
+//!
+//! # Repairing
+//! Blocks which are lost or corrupted in the data store normally can't be recovered. If the
+//! `erasure-coding` feature is enabled, you can configure a repository to store parity blocks
+//! alongside its data and reconstruct a bounded number of damaged blocks with
+//! [`KeyRepo::repair`]. See [`Parity`] for details.
+//!
+//! # Benchmarking
+//! The best [`RepoConfig`] depends on your data. If the `benchmark` feature is enabled, you can use
+//! [`benchmark`] to measure how fast a config chunks, compresses, encrypts, and stores a sample of
+//! your data and how well that data deduplicates and compresses.
+//!
 //! [`DataStore`]: crate::store::DataStore
 //! [`Object`]: crate::repo::Object
 //! [`ReadOnlyObject`]: crate::repo::ReadOnlyObject
 //! [`KeyRepo`]: crate::repo::key::KeyRepo
+//! [`KeyRepo::keys`]: crate::repo::key::KeyRepo::keys
 //! [`OpenOptions`]: crate::repo::OpenOptions
 //! [`Chunking`]: crate::repo::Chunking
 //! [`Commit::commit`]: crate::repo::Commit::commit
+//! [`Commit::rollback`]: crate::repo::Commit::rollback
 //! [`Commit::clean`]: crate::repo::Commit::clean
+//! [`StateRepo::state`]: crate::repo::state::StateRepo::state
+//! [`StateRepo::state_mut`]: crate::repo::state::StateRepo::state_mut
 //! [`RestoreSavepoint`]: crate::repo::RestoreSavepoint
 //! [`Packing`]: crate::repo::Packing
 //! [`RepoInfo`]: crate::repo::RepoInfo
 //! [`peek_info`]: crate::repo::peek_info
 //! [`SwitchInstance::switch_instance`]: crate::repo::SwitchInstance::switch_instance
+//! [`SwitchBranch`]: crate::repo::SwitchBranch
+//! [`LockStrategy`]: crate::repo::LockStrategy
+//! [`RepoConfig::lease_duration`]: crate::repo::RepoConfig::lease_duration
 //! [`FileRepo`]: crate::repo::file::FileRepo
 //! [`VersionRepo`]: crate::repo::version::VersionRepo
+//! [`AsyncRepo`]: crate::repo::AsyncRepo
+//! [`AsyncObject`]: crate::repo::AsyncObject
+//! [`AsyncStream`]: crate::repo::AsyncStream
+//! [`RepoConfig`]: crate::repo::RepoConfig
+//! [`benchmark`]: crate::repo::benchmark
+//! [`KeyRepo::repair`]: crate::repo::key::KeyRepo::repair
+//! [`Parity`]: crate::repo::Parity
 
+#[cfg(feature = "benchmark")]
+pub use self::common::{benchmark, BenchmarkReport};
 pub use self::common::{
-    peek_info, Chunking, Commit, Compression, ContentId, Encryption, Object, ObjectId, OpenMode,
-    OpenOptions, OpenRepo, Packing, ReadOnlyObject, RepoConfig, RepoInfo, ResourceLimit, Restore,
-    RestoreSavepoint, Savepoint, SwitchInstance, DEFAULT_INSTANCE,
+    peek_info, AuditEntry, AuditEvent, CancellationToken, ChunkReport, Chunking, CleanReport,
+    Commit, Compression, ContentId, Encryption, HealthReport, LockState, LockStrategy, Object,
+    ObjectId, OpenMode, OpenOptions, OpenRepo, Operation, OperationStats, Packing, Parity,
+    Progress, ReadOnlyObject, RepairReport, RepoConfig, RepoInfo, RepoStats, ResourceLimit,
+    Restore, RestoreSavepoint, RetryPolicy, Savepoint, SwitchBranch, SwitchInstance,
+    TransactionEvent, DEFAULT_BRANCH, DEFAULT_INSTANCE,
 };
+#[cfg(feature = "async")]
+pub use self::common::{AsyncObject, AsyncRepo, AsyncStream};
 
 /// An object store which maps keys to seekable binary blobs.
 ///
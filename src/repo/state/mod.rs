@@ -42,7 +42,8 @@
 //! [`ObjectKey`]: crate::repo::state::ObjectKey
 
 pub use self::info::ObjectKey;
-pub use self::repository::StateRepo;
+pub(crate) use self::repository::StateRepoInner;
+pub use self::repository::{StateMut, StateRef, StateRepo};
 
 mod info;
 mod repository;
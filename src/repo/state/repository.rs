@@ -14,7 +14,9 @@
  * limitations under the License.
  */
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use hex_literal::hex;
 use serde::de::DeserializeOwned;
@@ -22,22 +24,85 @@ use serde::Serialize;
 use uuid::Uuid;
 
 use super::info::{ObjectKey, RepoKey, RepoState, StateRestore};
-use crate::repo::common::{IdTable, UniqueId};
-use crate::repo::{key::KeyRepo, Commit, Object, OpenRepo, RepoInfo, RestoreSavepoint, Savepoint};
+use crate::repo::common::{IdTable, KeyRepoInner, RecoverPoison, UniqueId};
+use crate::repo::{
+    key::{Key, KeyRepo},
+    AuditEntry, CancellationToken, Chunking, CleanReport, Commit, Object, OpenRepo, Operation,
+    Progress, RepairReport, RepoInfo, RepoStats, RestoreSavepoint, Savepoint, TransactionEvent,
+};
 
 /// A low-level repository type which can be used to implement higher-level repository types
 ///
 /// See [`crate::repo::state`] for more information.
 #[derive(Debug)]
-pub struct StateRepo<State>
+pub struct StateRepo<State>(RwLock<StateRepoInner<State>>)
+where
+    State: Serialize + DeserializeOwned + Default;
+
+/// The contents of a [`StateRepo`].
+///
+/// Each public method of `StateRepo` locks the repository and calls the method of the same name on
+/// this type.
+#[derive(Debug)]
+pub(crate) struct StateRepoInner<State>
 where
     State: Serialize + DeserializeOwned + Default,
 {
-    repo: KeyRepo<RepoKey>,
+    repo: KeyRepoInner<RepoKey>,
     id_table: IdTable,
     state: State,
 }
 
+/// A reference to the state of a [`StateRepo`].
+///
+/// The repository is locked for reading until this is dropped, so other threads can't modify it
+/// in the meantime. Calling a method which modifies the repository while holding this will
+/// deadlock.
+#[derive(Debug)]
+pub struct StateRef<'a, State>(RwLockReadGuard<'a, StateRepoInner<State>>)
+where
+    State: Serialize + DeserializeOwned + Default;
+
+impl<'a, State> Deref for StateRef<'a, State>
+where
+    State: Serialize + DeserializeOwned + Default,
+{
+    type Target = State;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0.state
+    }
+}
+
+/// A mutable reference to the state of a [`StateRepo`].
+///
+/// The repository is locked for writing until this is dropped, so other threads can't access it
+/// in the meantime. Calling any other method of the repository while holding this will deadlock.
+#[derive(Debug)]
+pub struct StateMut<'a, State>(RwLockWriteGuard<'a, StateRepoInner<State>>)
+where
+    State: Serialize + DeserializeOwned + Default;
+
+impl<'a, State> Deref for StateMut<'a, State>
+where
+    State: Serialize + DeserializeOwned + Default,
+{
+    type Target = State;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0.state
+    }
+}
+
+impl<'a, State> DerefMut for StateMut<'a, State>
+where
+    State: Serialize + DeserializeOwned + Default,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0.state
+    }
+}
+
 impl<State> OpenRepo for StateRepo<State>
 where
     State: Serialize + DeserializeOwned + Default,
@@ -50,7 +115,309 @@ where
     where
         Self: Sized,
     {
-        let mut state_repo = StateRepo {
+        Ok(Self::from_inner(StateRepoInner::open_repo(
+            repo.into_inner(),
+        )?))
+    }
+
+    fn create_repo(repo: KeyRepo<Self::Key>) -> crate::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self::from_inner(StateRepoInner::create_repo(
+            repo.into_inner(),
+        )?))
+    }
+
+    fn into_repo(self) -> crate::Result<KeyRepo<Self::Key>> {
+        Ok(KeyRepo::from_inner(self.into_inner().into_repo()?))
+    }
+}
+
+impl<State> StateRepo<State>
+where
+    State: Serialize + DeserializeOwned + Default,
+{
+    /// Wrap the given `inner` contents of a repository in a new `StateRepo`.
+    pub(crate) fn from_inner(inner: StateRepoInner<State>) -> Self {
+        StateRepo(RwLock::new(inner))
+    }
+
+    /// Consume this repository and return its contents.
+    pub(crate) fn into_inner(self) -> StateRepoInner<State> {
+        self.0.into_inner().recover()
+    }
+
+    /// Lock the contents of this repository for reading.
+    pub(crate) fn inner(&self) -> RwLockReadGuard<'_, StateRepoInner<State>> {
+        self.0.read().recover()
+    }
+
+    /// Lock the contents of this repository for writing.
+    pub(crate) fn inner_mut(&self) -> RwLockWriteGuard<'_, StateRepoInner<State>> {
+        self.0.write().recover()
+    }
+
+    /// Return a reference to the encapsulated state.
+    ///
+    /// The repository is locked for reading until the returned reference is dropped.
+    pub fn state(&self) -> StateRef<'_, State> {
+        StateRef(self.inner())
+    }
+
+    /// Return a mutable reference to the encapsulated state.
+    ///
+    /// The repository is locked for writing until the returned reference is dropped.
+    pub fn state_mut(&self) -> StateMut<'_, State> {
+        StateMut(self.inner_mut())
+    }
+
+    /// Return whether there is an object with the given `key` in this repository.
+    pub fn contains(&self, key: ObjectKey) -> bool {
+        self.inner().contains(key)
+    }
+
+    /// Create a new object in the repository and returns its `ObjectKey`.
+    pub fn create(&self) -> ObjectKey {
+        self.inner_mut().create()
+    }
+
+    /// Remove the object with the given `key` from the repository.
+    ///
+    /// This returns `true` if the object was removed or `false` if it didn't exist.
+    ///
+    /// The space used by the given object isn't reclaimed in the backing data store until changes
+    /// are committed and [`Commit::clean`] is called.
+    ///
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    pub fn remove(&self, key: ObjectKey) -> bool {
+        self.inner_mut().remove(key)
+    }
+
+    /// Return an `Object` for reading and writing the object with the given `key`.
+    ///
+    /// This returns `None` if there is no object with the given `key` in the repository.
+    pub fn object(&self, key: ObjectKey) -> Option<Object> {
+        self.inner().object(key)
+    }
+
+    /// Return an iterator over all the keys of objects in this repository.
+    ///
+    /// Like [`KeyRepo::keys`], this returns a snapshot which doesn't keep the repository locked.
+    ///
+    /// [`KeyRepo::keys`]: crate::repo::key::KeyRepo::keys
+    pub fn keys(&self) -> impl Iterator<Item = ObjectKey> {
+        self.inner().keys().collect::<Vec<_>>().into_iter()
+    }
+
+    /// Create a copy of the object at `source` and return its `ObjectKey`.
+    ///
+    /// If there was no object at `source`, this returns `None`.
+    ///
+    /// This is a cheap operation which does not require copying the bytes in the object.
+    pub fn copy(&self, source: ObjectKey) -> Option<ObjectKey> {
+        self.inner_mut().copy(source)
+    }
+
+    /// Verify the integrity of all the data in the repository.
+    ///
+    /// This returns the set of keys of objects which are corrupt.
+    ///
+    /// # Errors
+    /// - `Error::Cancelled`: The operation was cancelled.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn verify(&self) -> crate::Result<HashSet<ObjectKey>> {
+        self.inner().verify()
+    }
+
+    /// Return the state of the repository as of the last commit.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize data from the previous commit.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn committed_state(&self) -> crate::Result<State> {
+        self.inner().committed_state()
+    }
+
+    /// Return the keys of objects which have changed since the last commit.
+    ///
+    /// See [`KeyRepo::changed_keys`] for details.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize data from the previous commit.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`KeyRepo::changed_keys`]: crate::repo::key::KeyRepo::changed_keys
+    pub fn changed_keys(&self) -> crate::Result<HashSet<ObjectKey>> {
+        self.inner().changed_keys()
+    }
+
+    /// Delete all data in the current instance of the repository.
+    ///
+    /// See [`KeyRepo::clear_instance`] for details.
+    ///
+    /// [`KeyRepo::clear_instance`]: crate::repo::key::KeyRepo::clear_instance
+    pub fn clear_instance(&self) {
+        self.inner_mut().clear_instance()
+    }
+
+    /// Change the password for this repository.
+    ///
+    /// See [`KeyRepo::change_password`] for details.
+    ///
+    /// [`KeyRepo::change_password`]: crate::repo::key::KeyRepo::change_password
+    pub fn change_password(&self, new_password: &[u8]) {
+        self.inner_mut().change_password(new_password)
+    }
+
+    /// Renew the lease on this repository.
+    ///
+    /// See [`KeyRepo::renew_lease`] for details.
+    ///
+    /// [`KeyRepo::renew_lease`]: crate::repo::key::KeyRepo::renew_lease
+    pub fn renew_lease(&self) -> crate::Result<()> {
+        self.inner().renew_lease()
+    }
+
+    /// Register a `hook` to run before the given `event`.
+    ///
+    /// See [`KeyRepo::add_before_hook`] for details.
+    ///
+    /// [`KeyRepo::add_before_hook`]: crate::repo::key::KeyRepo::add_before_hook
+    pub fn add_before_hook(
+        &self,
+        event: TransactionEvent,
+        hook: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.inner_mut().add_before_hook(event, hook)
+    }
+
+    /// Register a `hook` to run after the given `event` completes successfully.
+    ///
+    /// See [`KeyRepo::add_after_hook`] for details.
+    ///
+    /// [`KeyRepo::add_after_hook`]: crate::repo::key::KeyRepo::add_after_hook
+    pub fn add_after_hook(&self, event: TransactionEvent, hook: impl FnMut() + Send + 'static) {
+        self.inner_mut().add_after_hook(event, hook)
+    }
+
+    /// Set a `handler` which is called with the progress of long-running operations.
+    ///
+    /// See [`KeyRepo::set_progress_handler`] for details.
+    ///
+    /// [`KeyRepo::set_progress_handler`]: crate::repo::key::KeyRepo::set_progress_handler
+    pub fn set_progress_handler(&self, handler: impl Fn(Progress) + Send + Sync + 'static) {
+        self.inner_mut().set_progress_handler(handler)
+    }
+
+    /// Set a `token` which can be used to cancel long-running operations.
+    ///
+    /// See [`KeyRepo::set_cancellation_token`] for details.
+    ///
+    /// [`KeyRepo::set_cancellation_token`]: crate::repo::key::KeyRepo::set_cancellation_token
+    pub fn set_cancellation_token(&self, token: CancellationToken) {
+        self.inner_mut().set_cancellation_token(token)
+    }
+
+    /// Return this repository's instance ID.
+    pub fn instance(&self) -> Uuid {
+        self.inner().instance()
+    }
+
+    /// Return the name of the current branch.
+    ///
+    /// See [`KeyRepo::branch`] for details.
+    ///
+    /// [`KeyRepo::branch`]: crate::repo::key::KeyRepo::branch
+    pub fn branch(&self) -> String {
+        self.inner().branch().to_string()
+    }
+
+    /// Return an iterator over the names of all the branches of the current instance.
+    ///
+    /// See [`KeyRepo::branches`] for details.
+    ///
+    /// [`KeyRepo::branches`]: crate::repo::key::KeyRepo::branches
+    pub fn branches(&self) -> impl Iterator<Item = String> {
+        self.inner()
+            .branches()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Remove the branch named `name` and all of its contents.
+    ///
+    /// See [`KeyRepo::remove_branch`] for details.
+    ///
+    /// [`KeyRepo::remove_branch`]: crate::repo::key::KeyRepo::remove_branch
+    pub fn remove_branch(&self, name: &str) -> crate::Result<()> {
+        self.inner_mut().remove_branch(name)
+    }
+
+    /// Return information about the repository.
+    pub fn info(&self) -> RepoInfo {
+        self.inner().info()
+    }
+
+    /// Return statistics about the operations performed by the repository since it was opened.
+    ///
+    /// See [`KeyRepo::stats`] for details.
+    ///
+    /// [`KeyRepo::stats`]: crate::repo::key::KeyRepo::stats
+    pub fn stats(&self) -> RepoStats {
+        self.inner().stats()
+    }
+
+    /// Reset the statistics returned by [`stats`] to zero.
+    ///
+    /// [`stats`]: crate::repo::state::StateRepo::stats
+    pub fn reset_stats(&self) {
+        self.inner().reset_stats()
+    }
+
+    /// Report what cleaning the repository would do without modifying the data store.
+    ///
+    /// See [`KeyRepo::clean_dry_run`] for details.
+    ///
+    /// [`KeyRepo::clean_dry_run`]: crate::repo::key::KeyRepo::clean_dry_run
+    pub fn clean_dry_run(&self) -> crate::Result<CleanReport> {
+        self.inner().clean_dry_run()
+    }
+
+    /// Reconstruct blocks in the data store which are missing or corrupt using parity blocks.
+    ///
+    /// See [`KeyRepo::repair`] for details.
+    ///
+    /// [`KeyRepo::repair`]: crate::repo::key::KeyRepo::repair
+    pub fn repair(&self) -> crate::Result<RepairReport> {
+        self.inner_mut().repair()
+    }
+
+    /// Return the entries in the audit log in the order they were recorded.
+    ///
+    /// See [`KeyRepo::audit_log`] for details.
+    ///
+    /// [`KeyRepo::audit_log`]: crate::repo::key::KeyRepo::audit_log
+    pub fn audit_log(&self) -> crate::Result<Vec<AuditEntry>> {
+        self.inner().audit_log()
+    }
+}
+
+impl<State> StateRepoInner<State>
+where
+    State: Serialize + DeserializeOwned + Default,
+{
+    pub(crate) fn open_repo(repo: KeyRepoInner<RepoKey>) -> crate::Result<Self> {
+        let mut state_repo = StateRepoInner {
             repo,
             id_table: IdTable::default(),
             state: State::default(),
@@ -61,11 +428,8 @@ where
         Ok(state_repo)
     }
 
-    fn create_repo(repo: KeyRepo<Self::Key>) -> crate::Result<Self>
-    where
-        Self: Sized,
-    {
-        let mut state_repo = StateRepo {
+    pub(crate) fn create_repo(repo: KeyRepoInner<RepoKey>) -> crate::Result<Self> {
+        let mut state_repo = StateRepoInner {
             repo,
             id_table: IdTable::default(),
             state: State::default(),
@@ -74,16 +438,11 @@ where
         Ok(state_repo)
     }
 
-    fn into_repo(mut self) -> crate::Result<KeyRepo<Self::Key>> {
+    pub(crate) fn into_repo(mut self) -> crate::Result<KeyRepoInner<RepoKey>> {
         self.write_state()?;
         Ok(self.repo)
     }
-}
 
-impl<State> StateRepo<State>
-where
-    State: Serialize + DeserializeOwned + Default,
-{
     /// Read the state and ID table from the backing repository.
     fn read_state(&mut self) -> crate::Result<RepoState<State>> {
         let state = match self.repo.object(&RepoKey::State) {
@@ -128,37 +487,83 @@ where
         key.repo_id == self.repo.info().id() && key.instance_id == self.repo.instance()
     }
 
-    /// Return a reference to the encapsulated state.
-    pub fn state(&self) -> &State {
+    /// Convert the given `repo` into a `StateRepo` with the default state.
+    ///
+    /// This reuses the objects in `repo` in place and marks the instance as containing a
+    /// repository with the given `version_id`. This returns the new repository along with a map of
+    /// the keys in `repo` to the keys of the same objects in the new repository.
+    ///
+    /// # Errors
+    /// - `Error::UnsupportedRepo`: The current instance has more than one branch.
+    pub(crate) fn from_key_repo<K: Key>(
+        repo: KeyRepo<K>,
+        version_id: Uuid,
+    ) -> crate::Result<(Self, HashMap<K, ObjectKey>)> {
+        let mut id_table = IdTable::new();
+        let mut object_ids = Vec::new();
+        let repo = repo.into_inner().convert(version_id, |key| {
+            let object_id = id_table.next();
+            object_ids.push((key, object_id));
+            Some(RepoKey::Object(object_id))
+        })?;
+
+        let state_repo = StateRepoInner {
+            repo,
+            id_table,
+            state: State::default(),
+        };
+        let object_keys = object_ids
+            .into_iter()
+            .map(|(key, object_id)| (key, state_repo.new_id(object_id)))
+            .collect();
+
+        Ok((state_repo, object_keys))
+    }
+
+    /// Convert this repository into a `KeyRepo`.
+    ///
+    /// This reuses the objects in this repository in place. The objects in `keys` are mapped to
+    /// their associated keys in the new repository, and all other objects are removed.
+    ///
+    /// # Errors
+    /// - `Error::UnsupportedRepo`: The current instance has more than one branch.
+    pub(crate) fn into_key_repo<K: Key>(
+        self,
+        mut keys: HashMap<ObjectKey, K>,
+    ) -> crate::Result<KeyRepo<K>> {
+        let repo_id = self.repo.info().id();
+        let instance_id = self.repo.instance();
+        self.repo
+            .convert(KeyRepo::<K>::VERSION_ID, |repo_key| match repo_key {
+                RepoKey::Object(object_id) => keys.remove(&ObjectKey {
+                    repo_id,
+                    instance_id,
+                    object_id,
+                }),
+                _ => None,
+            })
+            .map(KeyRepo::from_inner)
+    }
+
+    pub(crate) fn state(&self) -> &State {
         &self.state
     }
 
-    /// Return a mutable reference to the encapsulated state.
-    pub fn state_mut(&mut self) -> &mut State {
+    pub(crate) fn state_mut(&mut self) -> &mut State {
         &mut self.state
     }
 
-    /// Return whether there is an object with the given `key` in this repository.
-    pub fn contains(&self, key: ObjectKey) -> bool {
+    pub(crate) fn contains(&self, key: ObjectKey) -> bool {
         self.check_key(key) && self.repo.contains(&RepoKey::Object(key.object_id))
     }
 
-    /// Create a new object in the repository and returns its `ObjectKey`.
-    pub fn create(&mut self) -> ObjectKey {
+    pub(crate) fn create(&mut self) -> ObjectKey {
         let object_id = self.id_table.next();
         self.repo.insert(RepoKey::Object(object_id));
         self.new_id(object_id)
     }
 
-    /// Remove the object with the given `key` from the repository.
-    ///
-    /// This returns `true` if the object was removed or `false` if it didn't exist.
-    ///
-    /// The space used by the given object isn't reclaimed in the backing data store until changes
-    /// are committed and [`Commit::clean`] is called.
-    ///
-    /// [`Commit::clean`]: crate::repo::Commit::clean
-    pub fn remove(&mut self, key: ObjectKey) -> bool {
+    pub(crate) fn remove(&mut self, key: ObjectKey) -> bool {
         if !self.check_key(key) {
             return false;
         }
@@ -172,10 +577,7 @@ where
         true
     }
 
-    /// Return an `Object` for reading and writing the object with the given `key`.
-    ///
-    /// This returns `None` if there is no object with the given `key` in the repository.
-    pub fn object(&self, key: ObjectKey) -> Option<Object> {
+    pub(crate) fn object(&self, key: ObjectKey) -> Option<Object> {
         if !self.check_key(key) {
             return None;
         }
@@ -183,20 +585,14 @@ where
         self.repo.object(&RepoKey::Object(key.object_id))
     }
 
-    /// Return an iterator over all the keys of objects in this repository.
-    pub fn keys(&self) -> impl Iterator<Item = ObjectKey> + '_ {
+    pub(crate) fn keys(&self) -> impl Iterator<Item = ObjectKey> + '_ {
         self.repo.keys().filter_map(move |key| match key {
             RepoKey::Object(object_id) => Some(self.new_id(*object_id)),
             _ => None,
         })
     }
 
-    /// Create a copy of the object at `source` and return its `ObjectKey`.
-    ///
-    /// If there was no object at `source`, this returns `None`.
-    ///
-    /// This is a cheap operation which does not require copying the bytes in the object.
-    pub fn copy(&mut self, source: ObjectKey) -> Option<ObjectKey> {
+    pub(crate) fn copy(&mut self, source: ObjectKey) -> Option<ObjectKey> {
         if !self.check_key(source) || !self.repo.contains(&RepoKey::Object(source.object_id)) {
             return None;
         }
@@ -209,15 +605,33 @@ where
         Some(self.new_id(dest_id))
     }
 
-    /// Verify the integrity of all the data in the repository.
+    /// Copy a range of bytes from the object at `source` to the object at `dest` by sharing chunks.
     ///
-    /// This returns the set of keys of objects which are corrupt.
+    /// This returns `None` without changing `dest` if the range doesn't start and end on chunk
+    /// boundaries. See [`KeyRepo::copy_range`] for details.
     ///
-    /// # Errors
-    /// - `Error::InvalidData`: Ciphertext verification failed.
-    /// - `Error::Store`: An error occurred with the data store.
-    /// - `Error::Io`: An I/O error occurred.
-    pub fn verify(&self) -> crate::Result<HashSet<ObjectKey>> {
+    /// [`KeyRepo::copy_range`]: crate::repo::key::KeyRepo::copy_range
+    pub(crate) fn copy_range(
+        &mut self,
+        source: ObjectKey,
+        source_offset: u64,
+        dest: ObjectKey,
+        dest_offset: u64,
+        len: u64,
+    ) -> crate::Result<Option<u64>> {
+        if !self.check_key(source) || !self.check_key(dest) {
+            return Err(crate::Error::NotFound);
+        }
+        self.repo.copy_range(
+            &RepoKey::Object(source.object_id),
+            source_offset,
+            &RepoKey::Object(dest.object_id),
+            dest_offset,
+            len,
+        )
+    }
+
+    pub(crate) fn verify(&self) -> crate::Result<HashSet<ObjectKey>> {
         Ok(self
             .repo
             .verify()?
@@ -229,53 +643,153 @@ where
             .collect())
     }
 
-    /// Delete all data in the current instance of the repository.
-    ///
-    /// See [`KeyRepo::clear_instance`] for details.
-    ///
-    /// [`KeyRepo::clear_instance`]: crate::repo::key::KeyRepo::clear_instance
-    pub fn clear_instance(&mut self) {
+    pub(crate) fn committed_state(&self) -> crate::Result<State> {
+        Ok(self
+            .repo
+            .committed_value(&RepoKey::State)?
+            .unwrap_or_default())
+    }
+
+    pub(crate) fn changed_keys(&self) -> crate::Result<HashSet<ObjectKey>> {
+        Ok(self
+            .repo
+            .changed_keys()?
+            .into_iter()
+            .filter_map(|key| match key {
+                RepoKey::Object(object_id) => Some(self.new_id(object_id)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    pub(crate) fn clear_instance(&mut self) {
         self.state = State::default();
         self.id_table = IdTable::new();
         self.repo.clear_instance();
     }
 
-    /// Change the password for this repository.
-    ///
-    /// See [`KeyRepo::change_password`] for details.
-    ///
-    /// [`KeyRepo::change_password`]: crate::repo::key::KeyRepo::change_password
-    pub fn change_password(&mut self, new_password: &[u8]) {
+    pub(crate) fn change_password(&mut self, new_password: &[u8]) {
         self.repo.change_password(new_password);
     }
 
-    /// Return this repository's instance ID.
-    pub fn instance(&self) -> Uuid {
+    pub(crate) fn renew_lease(&self) -> crate::Result<()> {
+        self.repo.renew_lease()
+    }
+
+    pub(crate) fn add_before_hook(
+        &mut self,
+        event: TransactionEvent,
+        hook: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.repo.add_before_hook(event, hook)
+    }
+
+    pub(crate) fn add_after_hook(
+        &mut self,
+        event: TransactionEvent,
+        hook: impl FnMut() + Send + 'static,
+    ) {
+        self.repo.add_after_hook(event, hook)
+    }
+
+    pub(crate) fn set_progress_handler(
+        &mut self,
+        handler: impl Fn(Progress) + Send + Sync + 'static,
+    ) {
+        self.repo.set_progress_handler(handler)
+    }
+
+    pub(crate) fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.repo.set_cancellation_token(token)
+    }
+
+    /// Report the progress of an `operation` and check whether it has been cancelled.
+    pub(crate) fn report_progress(
+        &self,
+        operation: Operation,
+        completed: u64,
+        total: Option<u64>,
+    ) -> crate::Result<()> {
+        self.repo.report_progress(operation, completed, total)
+    }
+
+    /// Return the method this repository uses to split data into chunks.
+    pub(crate) fn chunking(&self) -> Chunking {
+        self.repo.chunking()
+    }
+
+    /// Return the maximum size of a chunk, or `0` if the size of chunks is not limited.
+    pub(crate) fn max_chunk_size(&self) -> usize {
+        self.repo.max_chunk_size()
+    }
+
+    /// Return the size of the buffer to use when reading data to split it into chunks.
+    pub(crate) fn read_buffer_size(&self) -> usize {
+        self.repo.read_buffer_size()
+    }
+
+    /// Return the number of worker threads to use for operations which can be done concurrently.
+    pub(crate) fn threads(&self) -> usize {
+        self.repo.threads()
+    }
+
+    pub(crate) fn instance(&self) -> Uuid {
         self.repo.instance()
     }
 
-    /// Return information about the repository.
-    pub fn info(&self) -> RepoInfo {
+    pub(crate) fn branch(&self) -> &str {
+        self.repo.branch()
+    }
+
+    pub(crate) fn branches(&self) -> impl Iterator<Item = &str> {
+        self.repo.branches()
+    }
+
+    pub(crate) fn remove_branch(&mut self, name: &str) -> crate::Result<()> {
+        self.repo.remove_branch(name)
+    }
+
+    pub(crate) fn info(&self) -> RepoInfo {
         self.repo.info()
     }
+
+    pub(crate) fn stats(&self) -> RepoStats {
+        self.repo.stats()
+    }
+
+    pub(crate) fn reset_stats(&self) {
+        self.repo.reset_stats()
+    }
+
+    pub(crate) fn clean_dry_run(&self) -> crate::Result<CleanReport> {
+        self.repo.clean_dry_run()
+    }
+
+    pub(crate) fn repair(&mut self) -> crate::Result<RepairReport> {
+        self.repo.repair()
+    }
+
+    pub(crate) fn audit_log(&self) -> crate::Result<Vec<AuditEntry>> {
+        self.repo.audit_log()
+    }
 }
 
-impl<State> Commit for StateRepo<State>
+impl<State> StateRepoInner<State>
 where
     State: Serialize + DeserializeOwned + Default,
 {
-    fn commit(&mut self) -> crate::Result<()> {
+    pub(crate) fn commit(&mut self) -> crate::Result<()> {
         self.write_state()?;
         self.repo.commit()
     }
 
-    fn rollback(&mut self) -> crate::Result<()> {
+    pub(crate) fn rollback(&mut self) -> crate::Result<()> {
         // Create a savepoint on the backing repository so that we can undo rolling back the backing
         // repository if necessary. This is necessary to uphold the contract that if this method
         // returns `Err`, the repository is unchanged. It's important that we start the restore
         // process here so that it can be completed infallibly.
         let backup_savepoint = self.repo.savepoint()?;
-        let backup_restore = self.repo.start_restore(&backup_savepoint)?;
+        let backup_restore = self.repo.start_restore_without_hooks(&backup_savepoint)?;
 
         // Roll back the backing repository.
         self.repo.rollback()?;
@@ -290,53 +804,143 @@ where
             Err(error) => {
                 // If reading the state fails, we must finish restoring the backup so we can return
                 // `Err` and have the repository unchanged.
-                self.repo.finish_restore(backup_restore);
+                self.repo.finish_restore_without_hooks(backup_restore);
+                Err(error)
+            }
+        }
+    }
+
+    pub(crate) fn refresh(&mut self) -> crate::Result<()> {
+        // Refresh the backing repository in a way which can be undone. This is necessary to uphold
+        // the contract that if this method returns `Err`, the repository is unchanged.
+        let backup = self.repo.refresh_with_backup()?;
+
+        // Load this repository's state from the most recent commit.
+        match self.read_state() {
+            Ok(RepoState { state, id_table }) => {
+                self.state = state;
+                self.id_table = id_table;
+                Ok(())
+            }
+            Err(error) => {
+                // If reading the state fails, we must undo refreshing the backing repository,
+                // including its metadata, so we can return `Err` and have the repository
+                // unchanged.
+                self.repo.undo_refresh(backup);
                 Err(error)
             }
         }
     }
 
-    fn clean(&mut self) -> crate::Result<()> {
+    pub(crate) fn clean(&mut self) -> crate::Result<()> {
         self.repo.clean()
     }
 }
 
+impl<State> Commit for StateRepo<State>
+where
+    State: Serialize + DeserializeOwned + Default,
+{
+    fn commit(&self) -> crate::Result<()> {
+        self.inner_mut().commit()
+    }
+
+    fn rollback(&self) -> crate::Result<()> {
+        self.inner_mut().rollback()
+    }
+
+    fn refresh(&self) -> crate::Result<()> {
+        self.inner_mut().refresh()
+    }
+
+    fn clean(&self) -> crate::Result<()> {
+        self.inner_mut().clean()
+    }
+}
+
+impl<State> StateRepoInner<State>
+where
+    State: Serialize + DeserializeOwned + Default + Clone,
+{
+    pub(crate) fn savepoint(&mut self) -> crate::Result<Savepoint> {
+        self.write_state()?;
+        self.repo.savepoint()
+    }
+
+    pub(crate) fn start_restore(
+        &mut self,
+        savepoint: &Savepoint,
+    ) -> crate::Result<StateRestore<State>> {
+        self.repo.run_before_hooks(TransactionEvent::Restore)?;
+        self.start_restore_without_hooks(savepoint)
+    }
+
+    pub(crate) fn finish_restore(&mut self, restore: StateRestore<State>) -> bool {
+        if !self.finish_restore_without_hooks(restore) {
+            return false;
+        }
+        self.repo.run_after_hooks(TransactionEvent::Restore);
+        true
+    }
+}
+
 impl<State> RestoreSavepoint for StateRepo<State>
 where
     State: Serialize + DeserializeOwned + Default + Clone,
 {
     type Restore = StateRestore<State>;
 
-    fn savepoint(&mut self) -> crate::Result<Savepoint> {
-        self.write_state()?;
-        self.repo.savepoint()
+    fn savepoint(&self) -> crate::Result<Savepoint> {
+        self.inner_mut().savepoint()
     }
 
-    fn start_restore(&mut self, savepoint: &Savepoint) -> crate::Result<Self::Restore> {
+    fn start_restore(&self, savepoint: &Savepoint) -> crate::Result<Self::Restore> {
+        self.inner_mut().start_restore(savepoint)
+    }
+
+    fn finish_restore(&self, restore: Self::Restore) -> bool {
+        self.inner_mut().finish_restore(restore)
+    }
+}
+
+impl<State> StateRepoInner<State>
+where
+    State: Serialize + DeserializeOwned + Default + Clone,
+{
+    /// Start restoring the repository to `savepoint` without running any hooks.
+    ///
+    /// This is like [`RestoreSavepoint::start_restore`], except it does not run any hooks. This is
+    /// used when restoring to a savepoint is an implementation detail of another operation.
+    ///
+    /// [`RestoreSavepoint::start_restore`]: crate::repo::RestoreSavepoint::start_restore
+    pub(crate) fn start_restore_without_hooks(
+        &mut self,
+        savepoint: &Savepoint,
+    ) -> crate::Result<StateRestore<State>> {
         // Create a savepoint on the backing repository that we can restore to to undo any changes
         // we make to the repository in this method. This is necessary to uphold the contract that
         // the repository is unchanged when this method returns. It's important that we start the
         // restore process here so that it can be completed infallibly.
         let backup_savepoint = self.repo.savepoint()?;
-        let backup_restore = self.repo.start_restore(&backup_savepoint)?;
+        let backup_restore = self.repo.start_restore_without_hooks(&backup_savepoint)?;
 
         // Temporarily restore the backing repository to the given `savepoint` so we can read the
         // repository state from when the savepoint was created.
-        let restore = self.repo.start_restore(savepoint)?;
+        let restore = self.repo.start_restore_without_hooks(savepoint)?;
 
         // Note that we clone the `restore` value so that we can also use it in the returned
         // `Restore` value. This is more efficient than calling `start_restore` twice.
-        self.repo.finish_restore(restore.clone());
+        self.repo.finish_restore_without_hooks(restore.clone());
 
         // Read the repository state from the backing repository and then restore it to the state it
         // was in before this method was called.
         let state = match self.read_state() {
             Ok(state) => {
-                self.repo.finish_restore(backup_restore);
+                self.repo.finish_restore_without_hooks(backup_restore);
                 state
             }
             Err(error) => {
-                self.repo.finish_restore(backup_restore);
+                self.repo.finish_restore_without_hooks(backup_restore);
                 return Err(error);
             }
         };
@@ -344,8 +948,14 @@ where
         Ok(StateRestore { state, restore })
     }
 
-    fn finish_restore(&mut self, restore: Self::Restore) -> bool {
-        if !self.repo.finish_restore(restore.restore) {
+    /// Finish restoring the repository without running any hooks.
+    ///
+    /// This is like [`RestoreSavepoint::finish_restore`], except it does not run any hooks. This
+    /// is used when restoring to a savepoint is an implementation detail of another operation.
+    ///
+    /// [`RestoreSavepoint::finish_restore`]: crate::repo::RestoreSavepoint::finish_restore
+    pub(crate) fn finish_restore_without_hooks(&mut self, restore: StateRestore<State>) -> bool {
+        if !self.repo.finish_restore_without_hooks(restore.restore) {
             return false;
         }
         let RepoState { state, id_table } = restore.state;
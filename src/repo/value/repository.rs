@@ -18,16 +18,20 @@ use std::borrow::Borrow;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use hex_literal::hex;
+use rmp_serde::to_vec;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use uuid::Uuid;
 
 use crate::repo::{
+    common::RecoverPoison,
     key::{Key, KeyRepo},
-    state::{ObjectKey, StateRepo},
-    Commit, OpenRepo, RepoInfo, RestoreSavepoint, Savepoint,
+    state::{ObjectKey, StateRepo, StateRepoInner},
+    AuditEntry, CancellationToken, CleanReport, Commit, OpenRepo, Progress, RepairReport, RepoInfo,
+    RepoStats, RestoreSavepoint, Savepoint, TransactionEvent,
 };
 
 type RepoState<K> = HashMap<K, ObjectKey>;
@@ -36,7 +40,14 @@ type RepoState<K> = HashMap<K, ObjectKey>;
 ///
 /// See [`crate::repo::value`] for more information.
 #[derive(Debug)]
-pub struct ValueRepo<K: Key>(StateRepo<RepoState<K>>);
+pub struct ValueRepo<K: Key>(RwLock<ValueRepoInner<K>>);
+
+/// The contents of a [`ValueRepo`].
+///
+/// Each public method of `ValueRepo` locks the repository and calls the method of the same name on
+/// this type.
+#[derive(Debug)]
+pub(crate) struct ValueRepoInner<K: Key>(StateRepoInner<RepoState<K>>);
 
 impl<K: Key> OpenRepo for ValueRepo<K> {
     type Key = <StateRepo<RepoState<K>> as OpenRepo>::Key;
@@ -47,29 +58,51 @@ impl<K: Key> OpenRepo for ValueRepo<K> {
     where
         Self: Sized,
     {
-        Ok(Self(StateRepo::open_repo(repo)?))
+        let inner = StateRepoInner::open_repo(repo.into_inner())?;
+        Ok(Self::from_inner(ValueRepoInner(inner)))
     }
 
     fn create_repo(repo: KeyRepo<Self::Key>) -> crate::Result<Self>
     where
         Self: Sized,
     {
-        Ok(Self(StateRepo::create_repo(repo)?))
+        let inner = StateRepoInner::create_repo(repo.into_inner())?;
+        Ok(Self::from_inner(ValueRepoInner(inner)))
     }
 
     fn into_repo(self) -> crate::Result<KeyRepo<Self::Key>> {
-        self.0.into_repo()
+        Ok(KeyRepo::from_inner(self.into_inner().0.into_repo()?))
     }
 }
 
 impl<K: Key> ValueRepo<K> {
+    /// Wrap the given `inner` contents of a repository in a new `ValueRepo`.
+    pub(crate) fn from_inner(inner: ValueRepoInner<K>) -> Self {
+        ValueRepo(RwLock::new(inner))
+    }
+
+    /// Consume this repository and return its contents.
+    pub(crate) fn into_inner(self) -> ValueRepoInner<K> {
+        self.0.into_inner().recover()
+    }
+
+    /// Lock the contents of this repository for reading.
+    pub(crate) fn inner(&self) -> RwLockReadGuard<'_, ValueRepoInner<K>> {
+        self.0.read().recover()
+    }
+
+    /// Lock the contents of this repository for writing.
+    pub(crate) fn inner_mut(&self) -> RwLockWriteGuard<'_, ValueRepoInner<K>> {
+        self.0.write().recover()
+    }
+
     /// Return whether the given `key` exists in this repository.
     pub fn contains<Q>(&self, key: &Q) -> bool
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.0.state().contains_key(key)
+        self.inner().contains(key)
     }
 
     /// Insert a new key-value pair.
@@ -81,21 +114,8 @@ impl<K: Key> ValueRepo<K> {
     /// - `Error::InvalidData`: Ciphertext verification failed.
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
-    pub fn insert<V: Serialize>(&mut self, key: K, value: &V) -> crate::Result<()> {
-        let object_id = self.0.create();
-        let mut object = self.0.object(object_id).unwrap();
-        let result = object.serialize(value);
-        drop(object);
-        if let Err(error) = result {
-            self.0.remove(object_id);
-            return Err(error);
-        }
-
-        if let Some(prev_object_id) = self.0.state_mut().insert(key, object_id) {
-            self.0.remove(prev_object_id);
-        }
-
-        Ok(())
+    pub fn insert<V: Serialize>(&self, key: K, value: &V) -> crate::Result<()> {
+        self.inner_mut().insert(key, value)
     }
 
     /// Remove the value associated with `key` from the repository.
@@ -106,18 +126,12 @@ impl<K: Key> ValueRepo<K> {
     /// are committed and [`Commit::clean`] is called.
     ///
     /// [`Commit::clean`]: crate::repo::Commit::clean
-    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    pub fn remove<Q>(&self, key: &Q) -> bool
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        match self.0.state_mut().remove(key) {
-            Some(object_id) => {
-                self.0.remove(object_id);
-                true
-            }
-            None => false,
-        }
+        self.inner_mut().remove(key)
     }
 
     /// Return the value associated with `key`.
@@ -134,14 +148,47 @@ impl<K: Key> ValueRepo<K> {
         Q: Hash + Eq + ?Sized,
         V: DeserializeOwned,
     {
-        let object_id = self.0.state().get(key).ok_or(crate::Error::NotFound)?;
-        let mut object = self.0.object(*object_id).unwrap();
-        object.deserialize()
+        self.inner().get(key)
+    }
+
+    /// Replace the value associated with `key` only if it currently equals `expected`.
+    ///
+    /// Values are compared by their serialized representation, so `expected` does not need to be
+    /// the same type as the stored value as long as it serializes to the same bytes. If `expected`
+    /// is `None`, `new` is only inserted if there is currently no value associated with `key`.
+    ///
+    /// This returns `true` if the value was replaced or `false` if the current value did not match
+    /// `expected`, in which case the repository is unchanged.
+    ///
+    /// The current value is compared by the checksums of its chunks rather than by reading its
+    /// contents back, but this may still read the metadata of the value from the data store.
+    ///
+    /// # Errors
+    /// - `Error::Serialize`: The `expected` or `new` value could not be serialized.
+    /// - `Error::Corrupt`: The value associated with `key` is missing from the repository.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn compare_and_swap<E, V>(
+        &self,
+        key: K,
+        expected: Option<&E>,
+        new: &V,
+    ) -> crate::Result<bool>
+    where
+        E: Serialize,
+        V: Serialize,
+    {
+        self.inner_mut().compare_and_swap(key, expected, new)
     }
 
     /// Return an iterator of all the keys in this repository.
-    pub fn keys(&self) -> impl Iterator<Item = &K> {
-        self.0.state().keys()
+    ///
+    /// Like [`KeyRepo::keys`], this returns a snapshot which doesn't keep the repository locked.
+    ///
+    /// [`KeyRepo::keys`]: crate::repo::key::KeyRepo::keys
+    pub fn keys(&self) -> impl Iterator<Item = K> {
+        self.inner().keys().cloned().collect::<Vec<_>>().into_iter()
     }
 
     /// Copy the value at `source` to `dest`.
@@ -151,18 +198,12 @@ impl<K: Key> ValueRepo<K> {
     /// # Errors
     /// - `Error::NotFound`: There is no value at `source`.
     /// - `Error::AlreadyExists`: There is already a value at `dest`.
-    pub fn copy<Q>(&mut self, source: &Q, dest: K) -> crate::Result<()>
+    pub fn copy<Q>(&self, source: &Q, dest: K) -> crate::Result<()>
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        if self.0.state().contains_key(dest.borrow()) {
-            return Err(crate::Error::AlreadyExists);
-        }
-        let object_id = *self.0.state().get(source).ok_or(crate::Error::NotFound)?;
-        let new_object_id = self.0.copy(object_id).unwrap();
-        self.0.state_mut().insert(dest, new_object_id);
-        Ok(())
+        self.inner_mut().copy(source, dest)
     }
 
     /// Verify the integrity of all the data in the repository.
@@ -170,18 +211,42 @@ impl<K: Key> ValueRepo<K> {
     /// This returns the set of keys of values which are corrupt.
     ///
     /// # Errors
+    /// - `Error::Cancelled`: The operation was cancelled.
     /// - `Error::InvalidData`: Ciphertext verification failed.
     /// - `Error::Store`: An error occurred with the data store.
     /// - `Error::Io`: An I/O error occurred.
-    pub fn verify(&self) -> crate::Result<HashSet<&K>> {
-        let corrupt_keys = self.0.verify()?;
-        Ok(self
-            .0
-            .state()
-            .iter()
-            .filter(|(_, object_id)| corrupt_keys.contains(*object_id))
-            .map(|(key, _)| key)
-            .collect())
+    pub fn verify(&self) -> crate::Result<HashSet<K>> {
+        Ok(self.inner().verify()?.into_iter().cloned().collect())
+    }
+
+    /// Return the keys which have changed since the last commit.
+    ///
+    /// This includes keys which have been inserted, removed, or replaced with a different value.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize data from the previous commit.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn changed_keys(&self) -> crate::Result<HashSet<K>> {
+        self.inner().changed_keys()
+    }
+
+    /// Return whether the repository has changed since the last commit.
+    ///
+    /// See [`changed_keys`] for details.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize data from the previous commit.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`changed_keys`]: crate::repo::value::ValueRepo::changed_keys
+    pub fn has_changes(&self) -> crate::Result<bool> {
+        self.inner().has_changes()
     }
 
     /// Delete all data in the current instance of the repository.
@@ -189,8 +254,8 @@ impl<K: Key> ValueRepo<K> {
     /// See [`KeyRepo::clear_instance`] for details.
     ///
     /// [`KeyRepo::clear_instance`]: crate::repo::key::KeyRepo::clear_instance
-    pub fn clear_instance(&mut self) {
-        self.0.clear_instance()
+    pub fn clear_instance(&self) {
+        self.inner_mut().clear_instance()
     }
 
     /// Change the password for this repository.
@@ -198,47 +263,432 @@ impl<K: Key> ValueRepo<K> {
     /// See [`KeyRepo::change_password`] for details.
     ///
     /// [`KeyRepo::change_password`]: crate::repo::key::KeyRepo::change_password
-    pub fn change_password(&mut self, new_password: &[u8]) {
-        self.0.change_password(new_password);
+    pub fn change_password(&self, new_password: &[u8]) {
+        self.inner_mut().change_password(new_password)
+    }
+
+    /// Renew the lease on this repository.
+    ///
+    /// See [`KeyRepo::renew_lease`] for details.
+    ///
+    /// [`KeyRepo::renew_lease`]: crate::repo::key::KeyRepo::renew_lease
+    pub fn renew_lease(&self) -> crate::Result<()> {
+        self.inner().renew_lease()
+    }
+
+    /// Register a `hook` to run before the given `event`.
+    ///
+    /// See [`KeyRepo::add_before_hook`] for details.
+    ///
+    /// [`KeyRepo::add_before_hook`]: crate::repo::key::KeyRepo::add_before_hook
+    pub fn add_before_hook(
+        &self,
+        event: TransactionEvent,
+        hook: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.inner_mut().add_before_hook(event, hook)
+    }
+
+    /// Register a `hook` to run after the given `event` completes successfully.
+    ///
+    /// See [`KeyRepo::add_after_hook`] for details.
+    ///
+    /// [`KeyRepo::add_after_hook`]: crate::repo::key::KeyRepo::add_after_hook
+    pub fn add_after_hook(&self, event: TransactionEvent, hook: impl FnMut() + Send + 'static) {
+        self.inner_mut().add_after_hook(event, hook)
+    }
+
+    /// Set a `handler` which is called with the progress of long-running operations.
+    ///
+    /// See [`KeyRepo::set_progress_handler`] for details.
+    ///
+    /// [`KeyRepo::set_progress_handler`]: crate::repo::key::KeyRepo::set_progress_handler
+    pub fn set_progress_handler(&self, handler: impl Fn(Progress) + Send + Sync + 'static) {
+        self.inner_mut().set_progress_handler(handler)
+    }
+
+    /// Set a `token` which can be used to cancel long-running operations.
+    ///
+    /// See [`KeyRepo::set_cancellation_token`] for details.
+    ///
+    /// [`KeyRepo::set_cancellation_token`]: crate::repo::key::KeyRepo::set_cancellation_token
+    pub fn set_cancellation_token(&self, token: CancellationToken) {
+        self.inner_mut().set_cancellation_token(token)
     }
 
     /// Return this repository's instance ID.
     pub fn instance(&self) -> Uuid {
-        self.0.instance()
+        self.inner().instance()
+    }
+
+    /// Return the name of the current branch.
+    ///
+    /// See [`KeyRepo::branch`] for details.
+    ///
+    /// [`KeyRepo::branch`]: crate::repo::key::KeyRepo::branch
+    pub fn branch(&self) -> String {
+        self.inner().branch().to_string()
+    }
+
+    /// Return an iterator over the names of all the branches of the current instance.
+    ///
+    /// See [`KeyRepo::branches`] for details.
+    ///
+    /// [`KeyRepo::branches`]: crate::repo::key::KeyRepo::branches
+    pub fn branches(&self) -> impl Iterator<Item = String> {
+        self.inner()
+            .branches()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Remove the branch named `name` and all of its contents.
+    ///
+    /// See [`KeyRepo::remove_branch`] for details.
+    ///
+    /// [`KeyRepo::remove_branch`]: crate::repo::key::KeyRepo::remove_branch
+    pub fn remove_branch(&self, name: &str) -> crate::Result<()> {
+        self.inner_mut().remove_branch(name)
     }
 
     /// Return information about the repository.
     pub fn info(&self) -> RepoInfo {
+        self.inner().info()
+    }
+
+    /// Return statistics about the operations performed by the repository since it was opened.
+    ///
+    /// See [`KeyRepo::stats`] for details.
+    ///
+    /// [`KeyRepo::stats`]: crate::repo::key::KeyRepo::stats
+    pub fn stats(&self) -> RepoStats {
+        self.inner().stats()
+    }
+
+    /// Reset the statistics returned by [`stats`] to zero.
+    ///
+    /// [`stats`]: crate::repo::value::ValueRepo::stats
+    pub fn reset_stats(&self) {
+        self.inner().reset_stats()
+    }
+
+    /// Report what cleaning the repository would do without modifying the data store.
+    ///
+    /// See [`KeyRepo::clean_dry_run`] for details.
+    ///
+    /// [`KeyRepo::clean_dry_run`]: crate::repo::key::KeyRepo::clean_dry_run
+    pub fn clean_dry_run(&self) -> crate::Result<CleanReport> {
+        self.inner().clean_dry_run()
+    }
+
+    /// Reconstruct blocks in the data store which are missing or corrupt using parity blocks.
+    ///
+    /// See [`KeyRepo::repair`] for details.
+    ///
+    /// [`KeyRepo::repair`]: crate::repo::key::KeyRepo::repair
+    pub fn repair(&self) -> crate::Result<RepairReport> {
+        self.inner_mut().repair()
+    }
+
+    /// Return the entries in the audit log in the order they were recorded.
+    ///
+    /// See [`KeyRepo::audit_log`] for details.
+    ///
+    /// [`KeyRepo::audit_log`]: crate::repo::key::KeyRepo::audit_log
+    pub fn audit_log(&self) -> crate::Result<Vec<AuditEntry>> {
+        self.inner().audit_log()
+    }
+}
+
+impl<K: Key> ValueRepoInner<K> {
+    pub(crate) fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.0.state().contains_key(key)
+    }
+
+    pub(crate) fn insert<V: Serialize>(&mut self, key: K, value: &V) -> crate::Result<()> {
+        let object_id = self.0.create();
+        let mut object = self.0.object(object_id).unwrap();
+        let result = object.serialize(value);
+        drop(object);
+        if let Err(error) = result {
+            self.0.remove(object_id);
+            return Err(error);
+        }
+
+        if let Some(prev_object_id) = self.0.state_mut().insert(key, object_id) {
+            self.0.remove(prev_object_id);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn remove<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.0.state_mut().remove(key) {
+            Some(object_id) => {
+                self.0.remove(object_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn get<Q, V>(&self, key: &Q) -> crate::Result<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: DeserializeOwned,
+    {
+        let object_id = self.0.state().get(key).ok_or(crate::Error::NotFound)?;
+        let mut object = self.0.object(*object_id).unwrap();
+        object.deserialize()
+    }
+
+    pub(crate) fn compare_and_swap<E, V>(
+        &mut self,
+        key: K,
+        expected: Option<&E>,
+        new: &V,
+    ) -> crate::Result<bool>
+    where
+        E: Serialize,
+        V: Serialize,
+    {
+        let current_matches = match (self.0.state().get(&key), expected) {
+            (None, None) => true,
+            (None, Some(_)) | (Some(_), None) => false,
+            (Some(object_id), Some(expected)) => {
+                let serialized = to_vec(expected).map_err(|_| crate::Error::Serialize)?;
+                let content_id = self
+                    .0
+                    .object(*object_id)
+                    .ok_or(crate::Error::Corrupt)?
+                    .content_id()?;
+                content_id.size() == serialized.len() as u64
+                    && content_id.compare_contents(serialized.as_slice())?
+            }
+        };
+
+        if !current_matches {
+            return Ok(false);
+        }
+
+        self.insert(key, new)?;
+
+        Ok(true)
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &K> {
+        self.0.state().keys()
+    }
+
+    pub(crate) fn copy<Q>(&mut self, source: &Q, dest: K) -> crate::Result<()>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.0.state().contains_key(dest.borrow()) {
+            return Err(crate::Error::AlreadyExists);
+        }
+        let object_id = *self.0.state().get(source).ok_or(crate::Error::NotFound)?;
+        let new_object_id = self.0.copy(object_id).unwrap();
+        self.0.state_mut().insert(dest, new_object_id);
+        Ok(())
+    }
+
+    pub(crate) fn verify(&self) -> crate::Result<HashSet<&K>> {
+        let corrupt_keys = self.0.verify()?;
+        Ok(self
+            .0
+            .state()
+            .iter()
+            .filter(|(_, object_id)| corrupt_keys.contains(*object_id))
+            .map(|(key, _)| key)
+            .collect())
+    }
+
+    pub(crate) fn changed_keys(&self) -> crate::Result<HashSet<K>> {
+        let committed_state = self.0.committed_state()?;
+        let changed_objects = self.0.changed_keys()?;
+        let current_state = self.0.state();
+
+        let mut changed_keys = current_state
+            .iter()
+            .filter(|(key, object_id)| {
+                committed_state.get(*key) != Some(*object_id)
+                    || changed_objects.contains(*object_id)
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<HashSet<_>>();
+        changed_keys.extend(
+            committed_state
+                .into_keys()
+                .filter(|key| !current_state.contains_key(key)),
+        );
+
+        Ok(changed_keys)
+    }
+
+    pub(crate) fn has_changes(&self) -> crate::Result<bool> {
+        Ok(!self.changed_keys()?.is_empty())
+    }
+
+    pub(crate) fn clear_instance(&mut self) {
+        self.0.clear_instance()
+    }
+
+    pub(crate) fn change_password(&mut self, new_password: &[u8]) {
+        self.0.change_password(new_password);
+    }
+
+    pub(crate) fn renew_lease(&self) -> crate::Result<()> {
+        self.0.renew_lease()
+    }
+
+    pub(crate) fn add_before_hook(
+        &mut self,
+        event: TransactionEvent,
+        hook: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.0.add_before_hook(event, hook)
+    }
+
+    pub(crate) fn add_after_hook(
+        &mut self,
+        event: TransactionEvent,
+        hook: impl FnMut() + Send + 'static,
+    ) {
+        self.0.add_after_hook(event, hook)
+    }
+
+    pub(crate) fn set_progress_handler(
+        &mut self,
+        handler: impl Fn(Progress) + Send + Sync + 'static,
+    ) {
+        self.0.set_progress_handler(handler)
+    }
+
+    pub(crate) fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.0.set_cancellation_token(token)
+    }
+
+    pub(crate) fn instance(&self) -> Uuid {
+        self.0.instance()
+    }
+
+    pub(crate) fn branch(&self) -> &str {
+        self.0.branch()
+    }
+
+    pub(crate) fn branches(&self) -> impl Iterator<Item = &str> {
+        self.0.branches()
+    }
+
+    pub(crate) fn remove_branch(&mut self, name: &str) -> crate::Result<()> {
+        self.0.remove_branch(name)
+    }
+
+    pub(crate) fn info(&self) -> RepoInfo {
         self.0.info()
     }
+
+    pub(crate) fn stats(&self) -> RepoStats {
+        self.0.stats()
+    }
+
+    pub(crate) fn reset_stats(&self) {
+        self.0.reset_stats()
+    }
+
+    pub(crate) fn clean_dry_run(&self) -> crate::Result<CleanReport> {
+        self.0.clean_dry_run()
+    }
+
+    pub(crate) fn repair(&mut self) -> crate::Result<RepairReport> {
+        self.0.repair()
+    }
+
+    pub(crate) fn audit_log(&self) -> crate::Result<Vec<AuditEntry>> {
+        self.0.audit_log()
+    }
 }
 
-impl<K: Key> Commit for ValueRepo<K> {
-    fn commit(&mut self) -> crate::Result<()> {
+impl<K: Key> ValueRepoInner<K> {
+    pub(crate) fn commit(&mut self) -> crate::Result<()> {
         self.0.commit()
     }
 
-    fn rollback(&mut self) -> crate::Result<()> {
+    pub(crate) fn rollback(&mut self) -> crate::Result<()> {
         self.0.rollback()
     }
 
-    fn clean(&mut self) -> crate::Result<()> {
+    pub(crate) fn refresh(&mut self) -> crate::Result<()> {
+        self.0.refresh()
+    }
+
+    pub(crate) fn clean(&mut self) -> crate::Result<()> {
         self.0.clean()
     }
 }
 
-impl<K: Key> RestoreSavepoint for ValueRepo<K> {
-    type Restore = <StateRepo<RepoState<K>> as RestoreSavepoint>::Restore;
+impl<K: Key> Commit for ValueRepo<K> {
+    fn commit(&self) -> crate::Result<()> {
+        self.inner_mut().commit()
+    }
 
-    fn savepoint(&mut self) -> crate::Result<Savepoint> {
+    fn rollback(&self) -> crate::Result<()> {
+        self.inner_mut().rollback()
+    }
+
+    fn refresh(&self) -> crate::Result<()> {
+        self.inner_mut().refresh()
+    }
+
+    fn clean(&self) -> crate::Result<()> {
+        self.inner_mut().clean()
+    }
+}
+
+impl<K: Key> ValueRepoInner<K> {
+    pub(crate) fn savepoint(&mut self) -> crate::Result<Savepoint> {
         self.0.savepoint()
     }
 
-    fn start_restore(&mut self, savepoint: &Savepoint) -> crate::Result<Self::Restore> {
+    pub(crate) fn start_restore(
+        &mut self,
+        savepoint: &Savepoint,
+    ) -> crate::Result<<StateRepo<RepoState<K>> as RestoreSavepoint>::Restore> {
         self.0.start_restore(savepoint)
     }
 
-    fn finish_restore(&mut self, restore: Self::Restore) -> bool {
+    pub(crate) fn finish_restore(
+        &mut self,
+        restore: <StateRepo<RepoState<K>> as RestoreSavepoint>::Restore,
+    ) -> bool {
         self.0.finish_restore(restore)
     }
 }
+
+impl<K: Key> RestoreSavepoint for ValueRepo<K> {
+    type Restore = <StateRepo<RepoState<K>> as RestoreSavepoint>::Restore;
+
+    fn savepoint(&self) -> crate::Result<Savepoint> {
+        self.inner_mut().savepoint()
+    }
+
+    fn start_restore(&self, savepoint: &Savepoint) -> crate::Result<Self::Restore> {
+        self.inner_mut().start_restore(savepoint)
+    }
+
+    fn finish_restore(&self, restore: Self::Restore) -> bool {
+        self.inner_mut().finish_restore(restore)
+    }
+}
@@ -30,6 +30,7 @@ pub struct Version {
     pub(super) id: u32,
     pub(super) created: SystemTime,
     pub(super) content_id: ContentId,
+    pub(super) label: Option<String>,
 }
 
 impl Version {
@@ -58,20 +59,33 @@ impl Version {
     pub fn size(&self) -> u64 {
         self.content_id.size()
     }
+
+    /// The human-readable label attached to this version, if any.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
 }
 
 /// Information with a version.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct VersionInfo {
     /// The time the version was created.
     pub(super) created: SystemTime,
 
     /// The handle of the object which contains the contents of the version.
     pub(super) id: ObjectKey,
+
+    /// The human-readable label attached to the version.
+    #[serde(default)]
+    pub(super) label: Option<String>,
+
+    /// The serialized metadata attached to the version.
+    #[serde(default)]
+    pub(super) metadata: Option<Vec<u8>>,
 }
 
 /// Information associated with each key.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct KeyInfo {
     /// The map of versions of this key.
     pub versions: BTreeMap<u32, VersionInfo>,
@@ -79,3 +93,13 @@ pub struct KeyInfo {
     /// The handle of the object which contains the current contents.
     pub object: ObjectKey,
 }
+
+impl KeyInfo {
+    /// Return a map of the IDs of the versions of this key to the times they were created.
+    pub fn created_times(&self) -> BTreeMap<u32, SystemTime> {
+        self.versions
+            .iter()
+            .map(|(id, info)| (*id, info.created))
+            .collect()
+    }
+}
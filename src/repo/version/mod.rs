@@ -35,7 +35,7 @@
 //!     use acid_store::store::MemoryConfig;
 //!
 //!     fn main() -> acid_store::Result<()> {
-//!         let mut repository: VersionRepo<String> = OpenOptions::new()
+//!         let repository: VersionRepo<String> = OpenOptions::new()
 //!             .mode(OpenMode::CreateNew)
 //!             .open(&MemoryConfig::new())?;
 //!
@@ -73,6 +73,8 @@
 
 pub use self::info::Version;
 pub use self::repository::VersionRepo;
+pub use self::retention::RetentionPolicy;
 
 mod info;
 mod repository;
+mod retention;
@@ -15,23 +15,34 @@
  */
 
 use std::borrow::Borrow;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::mem;
+use std::ops::Range;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::time::SystemTime;
 
 use hex_literal::hex;
+use rmp_serde::{from_read, to_vec};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use uuid::Uuid;
 
+use crate::repo::common::RecoverPoison;
 use crate::repo::key::KeyRepo;
-use crate::repo::state::StateRepo;
+use crate::repo::state::{StateRepo, StateRepoInner};
 use crate::repo::{
-    key::Key, Commit, Object, OpenRepo, ReadOnlyObject, RepoInfo, RestoreSavepoint, Savepoint,
+    key::Key, AuditEntry, CancellationToken, CleanReport, Commit, Object, OpenRepo, Progress,
+    ReadOnlyObject, RepairReport, RepoInfo, RepoStats, RestoreSavepoint, Savepoint,
+    TransactionEvent,
 };
 
 use super::info::{KeyInfo, Version, VersionInfo};
+use super::retention::RetentionPolicy;
+#[cfg(feature = "async")]
+use crate::repo::{AsyncRepo, AsyncStream};
 
 /// The state for a `VersionRepo`.
 type RepoState<K> = HashMap<K, KeyInfo>;
@@ -43,7 +54,14 @@ const INITIAL_VERSION_ID: u32 = 1;
 ///
 /// See [`crate::repo::version`] for more information.
 #[derive(Debug)]
-pub struct VersionRepo<K: Key>(StateRepo<RepoState<K>>);
+pub struct VersionRepo<K: Key>(RwLock<VersionRepoInner<K>>);
+
+/// The contents of a [`VersionRepo`].
+///
+/// Each public method of `VersionRepo` locks the repository and calls the method of the same name
+/// on this type.
+#[derive(Debug)]
+pub(crate) struct VersionRepoInner<K: Key>(StateRepoInner<RepoState<K>>);
 
 impl<K: Key> OpenRepo for VersionRepo<K> {
     type Key = <StateRepo<RepoState<K>> as OpenRepo>::Key;
@@ -54,36 +72,521 @@ impl<K: Key> OpenRepo for VersionRepo<K> {
     where
         Self: Sized,
     {
-        Ok(Self(StateRepo::open_repo(repo)?))
+        let inner = StateRepoInner::open_repo(repo.into_inner())?;
+        Ok(Self::from_inner(VersionRepoInner(inner)))
     }
 
     fn create_repo(repo: KeyRepo<Self::Key>) -> crate::Result<Self>
     where
         Self: Sized,
     {
-        Ok(Self(StateRepo::create_repo(repo)?))
+        let inner = StateRepoInner::create_repo(repo.into_inner())?;
+        Ok(Self::from_inner(VersionRepoInner(inner)))
     }
 
     fn into_repo(self) -> crate::Result<KeyRepo<Self::Key>> {
-        self.0.into_repo()
+        Ok(KeyRepo::from_inner(self.into_inner().0.into_repo()?))
     }
 }
 
 impl<K: Key> VersionRepo<K> {
+    /// Wrap the given `inner` contents of a repository in a new `VersionRepo`.
+    pub(crate) fn from_inner(inner: VersionRepoInner<K>) -> Self {
+        VersionRepo(RwLock::new(inner))
+    }
+
+    /// Consume this repository and return its contents.
+    pub(crate) fn into_inner(self) -> VersionRepoInner<K> {
+        self.0.into_inner().recover()
+    }
+
+    /// Lock the contents of this repository for reading.
+    pub(crate) fn inner(&self) -> RwLockReadGuard<'_, VersionRepoInner<K>> {
+        self.0.read().recover()
+    }
+
+    /// Lock the contents of this repository for writing.
+    pub(crate) fn inner_mut(&self) -> RwLockWriteGuard<'_, VersionRepoInner<K>> {
+        self.0.write().recover()
+    }
+
+    /// Convert the given `KeyRepo` into a `VersionRepo`.
+    ///
+    /// Each object in `repo` becomes the current version of the same key in the returned
+    /// repository. This reuses the objects in place, so it does not require copying the bytes in
+    /// any objects. The keys in the returned repository have no past versions.
+    ///
+    /// This changes the type of the repository stored in the current instance of `repo`, but it
+    /// does not commit changes. To undo the conversion, drop the returned repository without
+    /// committing it.
+    ///
+    /// # Errors
+    /// - `Error::UnsupportedRepo`: The current instance of `repo` has more than one branch.
+    pub fn from_key_repo(repo: KeyRepo<K>) -> crate::Result<Self> {
+        Ok(Self::from_inner(VersionRepoInner::from_key_repo(repo)?))
+    }
+
     /// Return whether the given `key` exists in this repository.
     pub fn contains<Q>(&self, key: &Q) -> bool
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
     {
-        self.0.state().contains_key(key)
+        self.inner().contains(key)
     }
 
     /// Insert the given `key` into the repository and return a new object.
     ///
     /// The returned object represents the current version of the key. If the given key already
     /// exists in the repository, this returns `None`.
-    pub fn insert(&mut self, key: K) -> Option<Object> {
+    pub fn insert(&self, key: K) -> Option<Object> {
+        self.inner_mut().insert(key)
+    }
+
+    /// Remove the given `key` and all its versions from the repository.
+    ///
+    /// This returns `true` if the key was removed or `false` if it doesn't exist in the repository.
+    ///
+    /// The space used by the given object isn't reclaimed in the backing data store until changes
+    /// are committed and [`Commit::clean`] is called.
+    ///
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    pub fn remove<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner_mut().remove(key)
+    }
+
+    /// Return an `Object` for reading the current version of `key`.
+    ///
+    /// This returns `None` if the key doesn't exist in the repository.
+    pub fn object<Q>(&self, key: &Q) -> Option<Object>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner().object(key)
+    }
+
+    /// Return an iterator of all the keys in this repository.
+    ///
+    /// Like [`KeyRepo::keys`], this returns a snapshot which doesn't keep the repository locked.
+    ///
+    /// [`KeyRepo::keys`]: crate::repo::key::KeyRepo::keys
+    pub fn keys(&self) -> impl Iterator<Item = K> {
+        self.inner().keys().cloned().collect::<Vec<_>>().into_iter()
+    }
+
+    /// Create a new version of the given `key` and return it.
+    ///
+    /// This returns the the newly created version or `None` if the key does not exist in the
+    /// repository.
+    pub fn create_version<Q>(&self, key: &Q) -> Option<Version>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.inner_mut().create_version(key)
+    }
+
+    /// Remove the version of `key` with the given `version_id`.
+    ///
+    /// This returns `true` if the version was removed or `false` if it doesn't exist in the
+    /// repository.
+    pub fn remove_version<Q>(&self, key: &Q, version_id: u32) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner_mut().remove_version(key, version_id)
+    }
+
+    /// Return a `ReadOnlyObject` for reading the contents of a version.
+    ///
+    /// This returns `None` if the version doesn't exist in the repository.
+    pub fn version_object<Q>(&self, key: &Q, version_id: u32) -> Option<ReadOnlyObject>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner().version_object(key, version_id)
+    }
+
+    /// Return the version of `key` with the given `version_id`.
+    ///
+    /// This returns `None` if the version doesn't exist in the repository.
+    pub fn get_version<Q>(&self, key: &Q, version_id: u32) -> Option<Version>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner().get_version(key, version_id)
+    }
+
+    /// Return an iterator of versions for the given `key`.
+    ///
+    /// This returns `None` if the key doesn't exist in the repository.
+    ///
+    /// The versions are sorted by their version ID, which corresponds to the order they were
+    /// created in.
+    ///
+    /// Each returned [`Version`] includes its creation time, size, content ID, and label. None of
+    /// these require reading the contents of the version from the data store, so this is suitable
+    /// for rendering the version history of a key.
+    ///
+    /// Like [`KeyRepo::keys`], this returns a snapshot which doesn't keep the repository locked.
+    ///
+    /// [`Version`]: crate::repo::version::Version
+    /// [`KeyRepo::keys`]: crate::repo::key::KeyRepo::keys
+    pub fn versions<Q>(&self, key: &Q) -> Option<impl Iterator<Item = Version>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        Some(self.inner().versions(key)?.collect::<Vec<_>>().into_iter())
+    }
+
+    /// Return the byte ranges which differ between two versions of `key`.
+    ///
+    /// This returns `None` if either version doesn't exist in the repository. This does not read
+    /// the contents of either version from the data store. See [`ContentId::diff`] for details.
+    ///
+    /// [`ContentId::diff`]: crate::repo::ContentId::diff
+    pub fn diff_versions<Q>(
+        &self,
+        key: &Q,
+        old_version_id: u32,
+        new_version_id: u32,
+    ) -> Option<Vec<Range<u64>>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner()
+            .diff_versions(key, old_version_id, new_version_id)
+    }
+
+    /// Attach the given human-readable `label` to a version of `key`.
+    ///
+    /// Labels are unique among versions of the same key. If `label` is `None`, the label is removed
+    /// from the version.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: The version doesn't exist in the repository.
+    /// - `Error::AlreadyExists`: Another version of `key` already has the given `label`.
+    pub fn set_version_label<Q>(
+        &self,
+        key: &Q,
+        version_id: u32,
+        label: Option<&str>,
+    ) -> crate::Result<()>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner_mut().set_version_label(key, version_id, label)
+    }
+
+    /// Return the version of `key` with the given `label`.
+    ///
+    /// This returns `None` if there is no version of `key` with the given label.
+    pub fn find_version<Q>(&self, key: &Q, label: &str) -> Option<Version>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner().find_version(key, label)
+    }
+
+    /// Serialize the given `metadata` and attach it to a version of `key`.
+    ///
+    /// This replaces any metadata which is already attached to the version.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: The version doesn't exist in the repository.
+    /// - `Error::Serialize`: The `metadata` could not be serialized.
+    pub fn set_version_metadata<Q, V>(
+        &self,
+        key: &Q,
+        version_id: u32,
+        metadata: &V,
+    ) -> crate::Result<()>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Serialize,
+    {
+        self.inner_mut()
+            .set_version_metadata(key, version_id, metadata)
+    }
+
+    /// Return the metadata attached to a version of `key`.
+    ///
+    /// # Errors
+    /// - `Error::NotFound`: The version doesn't exist or has no metadata attached.
+    /// - `Error::Deserialize`: The metadata could not be deserialized as a value of type `V`.
+    pub fn version_metadata<Q, V>(&self, key: &Q, version_id: u32) -> crate::Result<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: DeserializeOwned,
+    {
+        self.inner().version_metadata(key, version_id)
+    }
+
+    /// Remove the versions of `key` which should not be kept under the given `policy`.
+    ///
+    /// This returns the IDs of the versions which were removed or `None` if the key doesn't exist
+    /// in the repository. The current version of the key is never removed.
+    ///
+    /// The space used by the removed versions isn't reclaimed in the backing data store until
+    /// changes are committed and [`Commit::clean`] is called.
+    ///
+    /// [`Commit::clean`]: crate::repo::Commit::clean
+    pub fn prune<Q>(&self, key: &Q, policy: &RetentionPolicy) -> Option<Vec<u32>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner_mut().prune(key, policy)
+    }
+
+    /// Remove the versions of every key which should not be kept under the given `policy`.
+    ///
+    /// This returns the total number of versions which were removed. See [`prune`] for details.
+    ///
+    /// [`prune`]: crate::repo::version::VersionRepo::prune
+    pub fn prune_all(&self, policy: &RetentionPolicy) -> usize {
+        self.inner_mut().prune_all(policy)
+    }
+
+    /// Replace the current version of `key` with the version with the given `version_id`.
+    ///
+    /// This returns `true` if the version was restored or `false` if the version doesn't exist in
+    /// the repository.
+    ///
+    /// This is a cheap operation which does not require copying the bytes in the object.
+    ///
+    /// This does not remove the old version.
+    pub fn restore_version<Q>(&self, key: &Q, version_id: u32) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner_mut().restore_version(key, version_id)
+    }
+
+    /// Return the keys which have changed since the last commit.
+    ///
+    /// This includes keys which have been inserted or removed, keys whose current contents have
+    /// been modified, and keys whose versions have been created, removed, or changed.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize data from the previous commit.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    pub fn changed_keys(&self) -> crate::Result<HashSet<K>> {
+        self.inner().changed_keys()
+    }
+
+    /// Return whether the repository has changed since the last commit.
+    ///
+    /// See [`changed_keys`] for details.
+    ///
+    /// # Errors
+    /// - `Error::Corrupt`: The repository is corrupt. This is most likely unrecoverable.
+    /// - `Error::Deserialize`: Could not deserialize data from the previous commit.
+    /// - `Error::InvalidData`: Ciphertext verification failed.
+    /// - `Error::Store`: An error occurred with the data store.
+    /// - `Error::Io`: An I/O error occurred.
+    ///
+    /// [`changed_keys`]: crate::repo::version::VersionRepo::changed_keys
+    pub fn has_changes(&self) -> crate::Result<bool> {
+        self.inner().has_changes()
+    }
+
+    /// Delete all data in the current instance of the repository.
+    ///
+    /// See [`KeyRepo::clear_instance`] for details.
+    ///
+    /// [`KeyRepo::clear_instance`]: crate::repo::key::KeyRepo::clear_instance
+    pub fn clear_instance(&self) {
+        self.inner_mut().clear_instance()
+    }
+
+    /// Change the password for this repository.
+    ///
+    /// See [`KeyRepo::change_password`] for details.
+    ///
+    /// [`KeyRepo::change_password`]: crate::repo::key::KeyRepo::change_password
+    pub fn change_password(&self, new_password: &[u8]) {
+        self.inner_mut().change_password(new_password)
+    }
+
+    /// Renew the lease on this repository.
+    ///
+    /// See [`KeyRepo::renew_lease`] for details.
+    ///
+    /// [`KeyRepo::renew_lease`]: crate::repo::key::KeyRepo::renew_lease
+    pub fn renew_lease(&self) -> crate::Result<()> {
+        self.inner().renew_lease()
+    }
+
+    /// Register a `hook` to run before the given `event`.
+    ///
+    /// See [`KeyRepo::add_before_hook`] for details.
+    ///
+    /// [`KeyRepo::add_before_hook`]: crate::repo::key::KeyRepo::add_before_hook
+    pub fn add_before_hook(
+        &self,
+        event: TransactionEvent,
+        hook: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.inner_mut().add_before_hook(event, hook)
+    }
+
+    /// Register a `hook` to run after the given `event` completes successfully.
+    ///
+    /// See [`KeyRepo::add_after_hook`] for details.
+    ///
+    /// [`KeyRepo::add_after_hook`]: crate::repo::key::KeyRepo::add_after_hook
+    pub fn add_after_hook(&self, event: TransactionEvent, hook: impl FnMut() + Send + 'static) {
+        self.inner_mut().add_after_hook(event, hook)
+    }
+
+    /// Set a `handler` which is called with the progress of long-running operations.
+    ///
+    /// See [`KeyRepo::set_progress_handler`] for details.
+    ///
+    /// [`KeyRepo::set_progress_handler`]: crate::repo::key::KeyRepo::set_progress_handler
+    pub fn set_progress_handler(&self, handler: impl Fn(Progress) + Send + Sync + 'static) {
+        self.inner_mut().set_progress_handler(handler)
+    }
+
+    /// Set a `token` which can be used to cancel long-running operations.
+    ///
+    /// See [`KeyRepo::set_cancellation_token`] for details.
+    ///
+    /// [`KeyRepo::set_cancellation_token`]: crate::repo::key::KeyRepo::set_cancellation_token
+    pub fn set_cancellation_token(&self, token: CancellationToken) {
+        self.inner_mut().set_cancellation_token(token)
+    }
+
+    /// Return this repository's instance ID.
+    pub fn instance(&self) -> Uuid {
+        self.inner().instance()
+    }
+
+    /// Return the name of the current branch.
+    ///
+    /// See [`KeyRepo::branch`] for details.
+    ///
+    /// [`KeyRepo::branch`]: crate::repo::key::KeyRepo::branch
+    pub fn branch(&self) -> String {
+        self.inner().branch().to_string()
+    }
+
+    /// Return an iterator over the names of all the branches of the current instance.
+    ///
+    /// See [`KeyRepo::branches`] for details.
+    ///
+    /// [`KeyRepo::branches`]: crate::repo::key::KeyRepo::branches
+    pub fn branches(&self) -> impl Iterator<Item = String> {
+        self.inner()
+            .branches()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Remove the branch named `name` and all of its contents.
+    ///
+    /// See [`KeyRepo::remove_branch`] for details.
+    ///
+    /// [`KeyRepo::remove_branch`]: crate::repo::key::KeyRepo::remove_branch
+    pub fn remove_branch(&self, name: &str) -> crate::Result<()> {
+        self.inner_mut().remove_branch(name)
+    }
+
+    /// Return information about the repository.
+    pub fn info(&self) -> RepoInfo {
+        self.inner().info()
+    }
+
+    /// Return statistics about the operations performed by the repository since it was opened.
+    ///
+    /// See [`KeyRepo::stats`] for details.
+    ///
+    /// [`KeyRepo::stats`]: crate::repo::key::KeyRepo::stats
+    pub fn stats(&self) -> RepoStats {
+        self.inner().stats()
+    }
+
+    /// Reset the statistics returned by [`stats`] to zero.
+    ///
+    /// [`stats`]: crate::repo::version::VersionRepo::stats
+    pub fn reset_stats(&self) {
+        self.inner().reset_stats()
+    }
+
+    /// Report what cleaning the repository would do without modifying the data store.
+    ///
+    /// See [`KeyRepo::clean_dry_run`] for details.
+    ///
+    /// [`KeyRepo::clean_dry_run`]: crate::repo::key::KeyRepo::clean_dry_run
+    pub fn clean_dry_run(&self) -> crate::Result<CleanReport> {
+        self.inner().clean_dry_run()
+    }
+
+    /// Reconstruct blocks in the data store which are missing or corrupt using parity blocks.
+    ///
+    /// See [`KeyRepo::repair`] for details.
+    ///
+    /// [`KeyRepo::repair`]: crate::repo::key::KeyRepo::repair
+    pub fn repair(&self) -> crate::Result<RepairReport> {
+        self.inner_mut().repair()
+    }
+
+    /// Return the entries in the audit log in the order they were recorded.
+    ///
+    /// See [`KeyRepo::audit_log`] for details.
+    ///
+    /// [`KeyRepo::audit_log`]: crate::repo::key::KeyRepo::audit_log
+    pub fn audit_log(&self) -> crate::Result<Vec<AuditEntry>> {
+        self.inner().audit_log()
+    }
+}
+
+impl<K: Key> VersionRepoInner<K> {
+    pub(crate) fn from_key_repo(repo: KeyRepo<K>) -> crate::Result<Self> {
+        let (mut state_repo, object_keys) =
+            StateRepoInner::from_key_repo(repo, VersionRepo::<K>::VERSION_ID)?;
+        *state_repo.state_mut() = object_keys
+            .into_iter()
+            .map(|(key, object)| {
+                let key_info = KeyInfo {
+                    versions: BTreeMap::new(),
+                    object,
+                };
+                (key, key_info)
+            })
+            .collect();
+        Ok(Self(state_repo))
+    }
+
+    pub(crate) fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.0.state().contains_key(key)
+    }
+
+    pub(crate) fn insert(&mut self, key: K) -> Option<Object> {
         if self.0.state().contains_key(&key) {
             return None;
         }
@@ -98,15 +601,7 @@ impl<K: Key> VersionRepo<K> {
         self.0.object(object_id)
     }
 
-    /// Remove the given `key` and all its versions from the repository.
-    ///
-    /// This returns `true` if the key was removed or `false` if it doesn't exist in the repository.
-    ///
-    /// The space used by the given object isn't reclaimed in the backing data store until changes
-    /// are committed and [`Commit::clean`] is called.
-    ///
-    /// [`Commit::clean`]: crate::repo::Commit::clean
-    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    pub(crate) fn remove<Q>(&mut self, key: &Q) -> bool
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
@@ -125,10 +620,7 @@ impl<K: Key> VersionRepo<K> {
         true
     }
 
-    /// Return an `Object` for reading the current version of `key`.
-    ///
-    /// This returns `None` if the key doesn't exist in the repository.
-    pub fn object<Q>(&self, key: &Q) -> Option<Object>
+    pub(crate) fn object<Q>(&self, key: &Q) -> Option<Object>
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
@@ -137,16 +629,11 @@ impl<K: Key> VersionRepo<K> {
         Some(self.0.object(object_id).unwrap())
     }
 
-    /// Return an iterator of all the keys in this repository.
-    pub fn keys(&self) -> impl Iterator<Item = &K> {
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &K> {
         self.0.state().keys()
     }
 
-    /// Create a new version of the given `key` and return it.
-    ///
-    /// This returns the the newly created version or `None` if the key does not exist in the
-    /// repository.
-    pub fn create_version<Q>(&mut self, key: &Q) -> Option<Version>
+    pub(crate) fn create_version<Q>(&mut self, key: &Q) -> Option<Version>
     where
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
@@ -170,6 +657,8 @@ impl<K: Key> VersionRepo<K> {
         let version_info = VersionInfo {
             created: SystemTime::now(),
             id: version_object_id,
+            label: None,
+            metadata: None,
         };
 
         let version = Version {
@@ -181,6 +670,7 @@ impl<K: Key> VersionRepo<K> {
                 .unwrap()
                 .content_id()
                 .unwrap(),
+            label: None,
         };
 
         self.0
@@ -192,11 +682,7 @@ impl<K: Key> VersionRepo<K> {
         Some(version)
     }
 
-    /// Remove the version of `key` with the given `version_id`.
-    ///
-    /// This returns `true` if the version was removed or `false` if it doesn't exist in the
-    /// repository.
-    pub fn remove_version<Q>(&mut self, key: &Q, version_id: u32) -> bool
+    pub(crate) fn remove_version<Q>(&mut self, key: &Q, version_id: u32) -> bool
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
@@ -215,10 +701,7 @@ impl<K: Key> VersionRepo<K> {
         true
     }
 
-    /// Return a `ReadOnlyObject` for reading the contents of a version.
-    ///
-    /// This returns `None` if the version doesn't exist in the repository.
-    pub fn version_object<Q>(&self, key: &Q, version_id: u32) -> Option<ReadOnlyObject>
+    pub(crate) fn version_object<Q>(&self, key: &Q, version_id: u32) -> Option<ReadOnlyObject>
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
@@ -228,10 +711,7 @@ impl<K: Key> VersionRepo<K> {
         Some(self.0.object(version_info.id).unwrap().try_into().unwrap())
     }
 
-    /// Return the version of `key` with the given `version_id`.
-    ///
-    /// This returns `None` if the version doesn't exist in the repository.
-    pub fn get_version<Q>(&self, key: &Q, version_id: u32) -> Option<Version>
+    pub(crate) fn get_version<Q>(&self, key: &Q, version_id: u32) -> Option<Version>
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
@@ -246,16 +726,11 @@ impl<K: Key> VersionRepo<K> {
                 .unwrap()
                 .content_id()
                 .unwrap(),
+            label: version_info.label.clone(),
         })
     }
 
-    /// Return an iterator of versions for the given `key`.
-    ///
-    /// This returns `None` if the key doesn't exist in the repository.
-    ///
-    /// The versions are sorted by their version ID, which corresponds to the order they were
-    /// created in.
-    pub fn versions<'a, Q>(&'a self, key: &Q) -> Option<impl Iterator<Item = Version> + 'a>
+    pub(crate) fn versions<'a, Q>(&'a self, key: &Q) -> Option<impl Iterator<Item = Version> + 'a>
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
@@ -265,18 +740,150 @@ impl<K: Key> VersionRepo<K> {
             id: *id,
             created: info.created,
             content_id: self.0.object(info.id).unwrap().content_id().unwrap(),
+            label: info.label.clone(),
         }))
     }
 
-    /// Replace the current version of `key` with the version with the given `version_id`.
-    ///
-    /// This returns `true` if the version was restored or `false` if the version doesn't exist in
-    /// the repository.
-    ///
-    /// This is a cheap operation which does not require copying the bytes in the object.
-    ///
-    /// This does not remove the old version.
-    pub fn restore_version<Q>(&mut self, key: &Q, version_id: u32) -> bool
+    pub(crate) fn diff_versions<Q>(
+        &self,
+        key: &Q,
+        old_version_id: u32,
+        new_version_id: u32,
+    ) -> Option<Vec<Range<u64>>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let old_version = self.get_version(key, old_version_id)?;
+        let new_version = self.get_version(key, new_version_id)?;
+        Some(old_version.content_id().diff(new_version.content_id()))
+    }
+
+    pub(crate) fn set_version_label<Q>(
+        &mut self,
+        key: &Q,
+        version_id: u32,
+        label: Option<&str>,
+    ) -> crate::Result<()>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let key_info = self
+            .0
+            .state_mut()
+            .get_mut(key)
+            .ok_or(crate::Error::NotFound)?;
+
+        if !key_info.versions.contains_key(&version_id) {
+            return Err(crate::Error::NotFound);
+        }
+
+        if let Some(label) = label {
+            let label_taken = key_info
+                .versions
+                .iter()
+                .any(|(id, info)| *id != version_id && info.label.as_deref() == Some(label));
+            if label_taken {
+                return Err(crate::Error::AlreadyExists);
+            }
+        }
+
+        key_info.versions.get_mut(&version_id).unwrap().label = label.map(String::from);
+
+        Ok(())
+    }
+
+    pub(crate) fn find_version<Q>(&self, key: &Q, label: &str) -> Option<Version>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (version_id, _) = self
+            .0
+            .state()
+            .get(key)?
+            .versions
+            .iter()
+            .find(|(_, info)| info.label.as_deref() == Some(label))?;
+        self.get_version(key, *version_id)
+    }
+
+    pub(crate) fn set_version_metadata<Q, V>(
+        &mut self,
+        key: &Q,
+        version_id: u32,
+        metadata: &V,
+    ) -> crate::Result<()>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Serialize,
+    {
+        let serialized = to_vec(metadata).map_err(|_| crate::Error::Serialize)?;
+        let version_info = self
+            .0
+            .state_mut()
+            .get_mut(key)
+            .and_then(|key_info| key_info.versions.get_mut(&version_id))
+            .ok_or(crate::Error::NotFound)?;
+        version_info.metadata = Some(serialized);
+        Ok(())
+    }
+
+    pub(crate) fn version_metadata<Q, V>(&self, key: &Q, version_id: u32) -> crate::Result<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: DeserializeOwned,
+    {
+        let serialized = self
+            .0
+            .state()
+            .get(key)
+            .and_then(|key_info| key_info.versions.get(&version_id))
+            .and_then(|version_info| version_info.metadata.as_ref())
+            .ok_or(crate::Error::NotFound)?;
+        from_read(serialized.as_slice()).map_err(|_| crate::Error::Deserialize)
+    }
+
+    pub(crate) fn prune<Q>(&mut self, key: &Q, policy: &RetentionPolicy) -> Option<Vec<u32>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let key_info = self.0.state_mut().get_mut(key)?;
+        let expired = policy.expired(&key_info.created_times(), SystemTime::now());
+        let removed_objects = expired
+            .iter()
+            .map(|version_id| key_info.versions.remove(version_id).unwrap().id)
+            .collect::<Vec<_>>();
+
+        for object_id in removed_objects {
+            assert!(self.0.remove(object_id));
+        }
+
+        Some(expired)
+    }
+
+    pub(crate) fn prune_all(&mut self, policy: &RetentionPolicy) -> usize {
+        let now = SystemTime::now();
+        let mut removed_objects = Vec::new();
+
+        for key_info in self.0.state_mut().values_mut() {
+            for version_id in policy.expired(&key_info.created_times(), now) {
+                removed_objects.push(key_info.versions.remove(&version_id).unwrap().id);
+            }
+        }
+
+        for object_id in removed_objects.iter() {
+            assert!(self.0.remove(*object_id));
+        }
+
+        removed_objects.len()
+    }
+
+    pub(crate) fn restore_version<Q>(&mut self, key: &Q, version_id: u32) -> bool
     where
         K: Borrow<Q>,
         Q: Hash + Eq + ?Sized,
@@ -301,61 +908,226 @@ impl<K: Key> VersionRepo<K> {
         true
     }
 
-    /// Delete all data in the current instance of the repository.
-    ///
-    /// See [`KeyRepo::clear_instance`] for details.
-    ///
-    /// [`KeyRepo::clear_instance`]: crate::repo::key::KeyRepo::clear_instance
-    pub fn clear_instance(&mut self) {
+    pub(crate) fn changed_keys(&self) -> crate::Result<HashSet<K>> {
+        let committed_state = self.0.committed_state()?;
+        let changed_objects = self.0.changed_keys()?;
+        let current_state = self.0.state();
+
+        let mut changed_keys = current_state
+            .iter()
+            .filter(|(key, key_info)| {
+                committed_state.get(*key) != Some(*key_info)
+                    || changed_objects.contains(&key_info.object)
+                    || key_info
+                        .versions
+                        .values()
+                        .any(|version_info| changed_objects.contains(&version_info.id))
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<HashSet<_>>();
+        changed_keys.extend(
+            committed_state
+                .into_keys()
+                .filter(|key| !current_state.contains_key(key)),
+        );
+
+        Ok(changed_keys)
+    }
+
+    pub(crate) fn has_changes(&self) -> crate::Result<bool> {
+        Ok(!self.changed_keys()?.is_empty())
+    }
+
+    pub(crate) fn clear_instance(&mut self) {
         self.0.clear_instance()
     }
 
-    /// Change the password for this repository.
-    ///
-    /// See [`KeyRepo::change_password`] for details.
-    ///
-    /// [`KeyRepo::change_password`]: crate::repo::key::KeyRepo::change_password
-    pub fn change_password(&mut self, new_password: &[u8]) {
+    pub(crate) fn change_password(&mut self, new_password: &[u8]) {
         self.0.change_password(new_password);
     }
 
-    /// Return this repository's instance ID.
-    pub fn instance(&self) -> Uuid {
+    pub(crate) fn renew_lease(&self) -> crate::Result<()> {
+        self.0.renew_lease()
+    }
+
+    pub(crate) fn add_before_hook(
+        &mut self,
+        event: TransactionEvent,
+        hook: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.0.add_before_hook(event, hook)
+    }
+
+    pub(crate) fn add_after_hook(
+        &mut self,
+        event: TransactionEvent,
+        hook: impl FnMut() + Send + 'static,
+    ) {
+        self.0.add_after_hook(event, hook)
+    }
+
+    pub(crate) fn set_progress_handler(
+        &mut self,
+        handler: impl Fn(Progress) + Send + Sync + 'static,
+    ) {
+        self.0.set_progress_handler(handler)
+    }
+
+    pub(crate) fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.0.set_cancellation_token(token)
+    }
+
+    pub(crate) fn instance(&self) -> Uuid {
         self.0.instance()
     }
 
-    /// Return information about the repository.
-    pub fn info(&self) -> RepoInfo {
+    pub(crate) fn branch(&self) -> &str {
+        self.0.branch()
+    }
+
+    pub(crate) fn branches(&self) -> impl Iterator<Item = &str> {
+        self.0.branches()
+    }
+
+    pub(crate) fn remove_branch(&mut self, name: &str) -> crate::Result<()> {
+        self.0.remove_branch(name)
+    }
+
+    pub(crate) fn info(&self) -> RepoInfo {
         self.0.info()
     }
+
+    pub(crate) fn stats(&self) -> RepoStats {
+        self.0.stats()
+    }
+
+    pub(crate) fn reset_stats(&self) {
+        self.0.reset_stats()
+    }
+
+    pub(crate) fn clean_dry_run(&self) -> crate::Result<CleanReport> {
+        self.0.clean_dry_run()
+    }
+
+    pub(crate) fn repair(&mut self) -> crate::Result<RepairReport> {
+        self.0.repair()
+    }
+
+    pub(crate) fn audit_log(&self) -> crate::Result<Vec<AuditEntry>> {
+        self.0.audit_log()
+    }
 }
 
-impl<K: Key> Commit for VersionRepo<K> {
-    fn commit(&mut self) -> crate::Result<()> {
+#[cfg(feature = "async")]
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+impl<K: Key + Send + 'static> AsyncRepo<VersionRepo<K>> {
+    /// Return a stream of all the keys in this repository.
+    ///
+    /// See [`VersionRepo::keys`] and [`AsyncStream`].
+    ///
+    /// [`VersionRepo::keys`]: crate::repo::version::VersionRepo::keys
+    /// [`AsyncStream`]: crate::repo::AsyncStream
+    pub async fn keys(&mut self) -> AsyncStream<'_, K> {
+        self.start_stream(
+            |repo, send| {
+                // This stops once the stream has been dropped.
+                repo.inner().keys().cloned().all(send);
+                Ok(())
+            },
+            None,
+        )
+    }
+
+    /// Return a stream of the versions of the object with the given `key`.
+    ///
+    /// This returns `None` if the key does not exist in the repository.
+    ///
+    /// See [`VersionRepo::versions`] and [`AsyncStream`].
+    ///
+    /// [`VersionRepo::versions`]: crate::repo::version::VersionRepo::versions
+    /// [`AsyncStream`]: crate::repo::AsyncStream
+    pub async fn versions(&mut self, key: K) -> Option<AsyncStream<'_, Version>> {
+        self.stream(move |repo, send| {
+            // This stops once the stream has been dropped.
+            repo.inner()
+                .versions(&key)
+                .ok_or(crate::Error::NotFound)?
+                .all(send);
+            Ok(())
+        })
+        .await
+        .ok()
+    }
+}
+
+impl<K: Key> VersionRepoInner<K> {
+    pub(crate) fn commit(&mut self) -> crate::Result<()> {
         self.0.commit()
     }
 
-    fn rollback(&mut self) -> crate::Result<()> {
+    pub(crate) fn rollback(&mut self) -> crate::Result<()> {
         self.0.rollback()
     }
 
-    fn clean(&mut self) -> crate::Result<()> {
+    pub(crate) fn refresh(&mut self) -> crate::Result<()> {
+        self.0.refresh()
+    }
+
+    pub(crate) fn clean(&mut self) -> crate::Result<()> {
         self.0.clean()
     }
 }
 
-impl<K: Key> RestoreSavepoint for VersionRepo<K> {
-    type Restore = <StateRepo<RepoState<K>> as RestoreSavepoint>::Restore;
+impl<K: Key> Commit for VersionRepo<K> {
+    fn commit(&self) -> crate::Result<()> {
+        self.inner_mut().commit()
+    }
+
+    fn rollback(&self) -> crate::Result<()> {
+        self.inner_mut().rollback()
+    }
+
+    fn refresh(&self) -> crate::Result<()> {
+        self.inner_mut().refresh()
+    }
 
-    fn savepoint(&mut self) -> crate::Result<Savepoint> {
+    fn clean(&self) -> crate::Result<()> {
+        self.inner_mut().clean()
+    }
+}
+
+impl<K: Key> VersionRepoInner<K> {
+    pub(crate) fn savepoint(&mut self) -> crate::Result<Savepoint> {
         self.0.savepoint()
     }
 
-    fn start_restore(&mut self, savepoint: &Savepoint) -> crate::Result<Self::Restore> {
+    pub(crate) fn start_restore(
+        &mut self,
+        savepoint: &Savepoint,
+    ) -> crate::Result<<StateRepo<RepoState<K>> as RestoreSavepoint>::Restore> {
         self.0.start_restore(savepoint)
     }
 
-    fn finish_restore(&mut self, restore: Self::Restore) -> bool {
+    pub(crate) fn finish_restore(
+        &mut self,
+        restore: <StateRepo<RepoState<K>> as RestoreSavepoint>::Restore,
+    ) -> bool {
         self.0.finish_restore(restore)
     }
 }
+
+impl<K: Key> RestoreSavepoint for VersionRepo<K> {
+    type Restore = <StateRepo<RepoState<K>> as RestoreSavepoint>::Restore;
+
+    fn savepoint(&self) -> crate::Result<Savepoint> {
+        self.inner_mut().savepoint()
+    }
+
+    fn start_restore(&self, savepoint: &Savepoint) -> crate::Result<Self::Restore> {
+        self.inner_mut().start_restore(savepoint)
+    }
+
+    fn finish_restore(&self, restore: Self::Restore) -> bool {
+        self.inner_mut().finish_restore(restore)
+    }
+}
@@ -0,0 +1,253 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// The number of seconds in a day.
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
+/// A policy for deciding which versions of a key to keep in a [`VersionRepo`].
+///
+/// This type implements `Default` to provide a policy which keeps every version. The `keep_*`
+/// rules are additive: a version is kept if it is selected by at least one of them. If none of the
+/// `keep_*` rules are set, every version is selected. Versions selected by the `keep_*` rules are
+/// still removed if they are older than `max_age`.
+///
+/// Periods like days, weeks, and months are calculated in UTC. Weeks start on Monday. For each of
+/// the periodic rules, the most recent version in each period is kept.
+///
+/// [`VersionRepo`]: crate::repo::version::VersionRepo
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct RetentionPolicy {
+    /// Keep the `n` most recent versions.
+    ///
+    /// The default value is `None`.
+    pub keep_last: Option<usize>,
+
+    /// Keep the most recent version from each of the last `n` days which have versions.
+    ///
+    /// The default value is `None`.
+    pub keep_daily: Option<usize>,
+
+    /// Keep the most recent version from each of the last `n` weeks which have versions.
+    ///
+    /// The default value is `None`.
+    pub keep_weekly: Option<usize>,
+
+    /// Keep the most recent version from each of the last `n` months which have versions.
+    ///
+    /// The default value is `None`.
+    pub keep_monthly: Option<usize>,
+
+    /// Remove versions which were created longer ago than this duration.
+    ///
+    /// The default value is `None`.
+    pub max_age: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    /// Return the IDs of the versions which should be removed under this policy.
+    ///
+    /// `versions` maps the ID of each version to the time it was created.
+    pub(super) fn expired(
+        &self,
+        versions: &BTreeMap<u32, SystemTime>,
+        now: SystemTime,
+    ) -> Vec<u32> {
+        let has_keep_rules = self.keep_last.is_some()
+            || self.keep_daily.is_some()
+            || self.keep_weekly.is_some()
+            || self.keep_monthly.is_some();
+
+        let mut kept = HashSet::new();
+
+        if has_keep_rules {
+            // Version IDs increase with each version, so iterating in reverse visits versions from
+            // newest to oldest.
+            if let Some(count) = self.keep_last {
+                kept.extend(versions.keys().rev().take(count).copied());
+            }
+            if let Some(count) = self.keep_daily {
+                kept.extend(newest_per_period(versions, count, day_number));
+            }
+            if let Some(count) = self.keep_weekly {
+                kept.extend(newest_per_period(versions, count, week_number));
+            }
+            if let Some(count) = self.keep_monthly {
+                kept.extend(newest_per_period(versions, count, month_number));
+            }
+        } else {
+            kept.extend(versions.keys().copied());
+        }
+
+        if let Some(max_age) = self.max_age {
+            kept.retain(|id| match now.duration_since(versions[id]) {
+                Ok(age) => age <= max_age,
+                // The version was created in the future.
+                Err(_) => true,
+            });
+        }
+
+        versions
+            .keys()
+            .filter(|id| !kept.contains(id))
+            .copied()
+            .collect()
+    }
+}
+
+/// Return the IDs of the newest version in each of the `count` most recent periods.
+fn newest_per_period(
+    versions: &BTreeMap<u32, SystemTime>,
+    count: usize,
+    period: fn(SystemTime) -> i64,
+) -> Vec<u32> {
+    let mut selected = Vec::new();
+    let mut last_period = None;
+
+    for (id, created) in versions.iter().rev() {
+        if selected.len() >= count {
+            break;
+        }
+        let current_period = period(*created);
+        if last_period != Some(current_period) {
+            selected.push(*id);
+            last_period = Some(current_period);
+        }
+    }
+
+    selected
+}
+
+/// Return the number of days between the Unix epoch and `time`.
+fn day_number(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (duration.as_secs() / SECONDS_PER_DAY) as i64,
+        Err(error) => -((error.duration().as_secs() / SECONDS_PER_DAY) as i64) - 1,
+    }
+}
+
+/// Return the number of Monday-based weeks between the Unix epoch and `time`.
+fn week_number(time: SystemTime) -> i64 {
+    // The Unix epoch was a Thursday.
+    (day_number(time) + 3).div_euclid(7)
+}
+
+/// Return the number of months between the Unix epoch and `time`.
+fn month_number(time: SystemTime) -> i64 {
+    // This is the `civil_from_days` algorithm by Howard Hinnant.
+    let days = day_number(time) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    year * 12 + month
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{RetentionPolicy, SECONDS_PER_DAY};
+
+    /// Return the time `hours` hours after the start of day `day` since the Unix epoch.
+    fn time(day: u64, hours: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(day * SECONDS_PER_DAY + hours * 60 * 60)
+    }
+
+    #[test]
+    fn keep_daily_keeps_newest_version_per_day() {
+        let versions: BTreeMap<u32, SystemTime> = vec![
+            (1, time(100, 1)),
+            (2, time(100, 2)),
+            (3, time(100, 23)),
+            (4, time(101, 0)),
+            (5, time(101, 12)),
+            (6, time(103, 6)),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut policy = RetentionPolicy {
+            keep_daily: Some(2),
+            ..RetentionPolicy::default()
+        };
+
+        assert_eq!(policy.expired(&versions, time(104, 0)), vec![1, 2, 3, 4]);
+
+        policy.keep_daily = Some(7);
+
+        assert_eq!(policy.expired(&versions, time(104, 0)), vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn keep_weekly_and_monthly_use_calendar_periods() {
+        // Day 0 is Thursday, January 1, 1970, so day 3 is a Sunday, day 4 is the following Monday,
+        // and day 31 is the first day of February.
+        let versions: BTreeMap<u32, SystemTime> = vec![
+            (1, time(2, 0)),
+            (2, time(3, 0)),
+            (3, time(4, 0)),
+            (4, time(31, 0)),
+        ]
+        .into_iter()
+        .collect();
+
+        let policy = RetentionPolicy {
+            keep_weekly: Some(10),
+            ..RetentionPolicy::default()
+        };
+
+        assert_eq!(policy.expired(&versions, time(40, 0)), vec![1]);
+
+        let policy = RetentionPolicy {
+            keep_monthly: Some(10),
+            ..RetentionPolicy::default()
+        };
+
+        assert_eq!(policy.expired(&versions, time(40, 0)), vec![1, 2]);
+    }
+
+    #[test]
+    fn max_age_removes_old_versions() {
+        let versions: BTreeMap<u32, SystemTime> =
+            vec![(1, time(10, 0)), (2, time(12, 0)), (3, time(20, 0))]
+                .into_iter()
+                .collect();
+
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::from_secs(5 * SECONDS_PER_DAY)),
+            ..RetentionPolicy::default()
+        };
+
+        assert_eq!(policy.expired(&versions, time(16, 0)), vec![1]);
+        assert_eq!(policy.expired(&versions, time(30, 0)), vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,153 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(feature = "async")]
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use tokio::task;
+use uuid::Uuid;
+
+use super::data_store::DataStore;
+
+/// A persistent store for blocks of data with an asynchronous interface.
+///
+/// This is the asynchronous counterpart to [`DataStore`]. The semantics of each method are the
+/// same as the corresponding method in [`DataStore`], but the methods return futures so that they
+/// can be used from an async runtime without blocking its worker threads.
+///
+/// Data stores which are backed by a network service implement this trait natively. Any other
+/// [`DataStore`] can be used as an `AsyncDataStore` by wrapping it in a [`BlockingAdapter`].
+///
+/// [`DataStore`]: crate::store::DataStore
+/// [`BlockingAdapter`]: crate::store::BlockingAdapter
+#[async_trait]
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+pub trait AsyncDataStore: Send {
+    /// Write the given `data` as a new block with the given `id`.
+    ///
+    /// See [`DataStore::write_block`].
+    ///
+    /// [`DataStore::write_block`]: crate::store::DataStore::write_block
+    async fn write_block(&mut self, id: Uuid, data: &[u8]) -> anyhow::Result<()>;
+
+    /// Return the bytes of the block with the given `id`.
+    ///
+    /// See [`DataStore::read_block`].
+    ///
+    /// [`DataStore::read_block`]: crate::store::DataStore::read_block
+    async fn read_block(&mut self, id: Uuid) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Remove the block with the given `id` from the store.
+    ///
+    /// See [`DataStore::remove_block`].
+    ///
+    /// [`DataStore::remove_block`]: crate::store::DataStore::remove_block
+    async fn remove_block(&mut self, id: Uuid) -> anyhow::Result<()>;
+
+    /// Return a list of IDs of blocks in the store.
+    ///
+    /// See [`DataStore::list_blocks`].
+    ///
+    /// [`DataStore::list_blocks`]: crate::store::DataStore::list_blocks
+    async fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>>;
+}
+
+#[async_trait]
+impl AsyncDataStore for Box<dyn AsyncDataStore> {
+    async fn write_block(&mut self, id: Uuid, data: &[u8]) -> anyhow::Result<()> {
+        self.as_mut().write_block(id, data).await
+    }
+
+    async fn read_block(&mut self, id: Uuid) -> anyhow::Result<Option<Vec<u8>>> {
+        self.as_mut().read_block(id).await
+    }
+
+    async fn remove_block(&mut self, id: Uuid) -> anyhow::Result<()> {
+        self.as_mut().remove_block(id).await
+    }
+
+    async fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>> {
+        self.as_mut().list_blocks().await
+    }
+}
+
+impl Debug for dyn AsyncDataStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "AsyncDataStore")
+    }
+}
+
+/// An adapter which implements [`AsyncDataStore`] for any [`DataStore`].
+///
+/// Each operation on the wrapped data store is run on tokio's blocking thread pool so that it
+/// doesn't block the worker threads of the async runtime. Operations on the wrapped data store
+/// are still performed one at a time.
+///
+/// This must be used from within a tokio runtime.
+///
+/// [`AsyncDataStore`]: crate::store::AsyncDataStore
+/// [`DataStore`]: crate::store::DataStore
+#[derive(Debug)]
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+pub struct BlockingAdapter<S: DataStore + Send + 'static>(Arc<Mutex<S>>);
+
+impl<S: DataStore + Send + 'static> BlockingAdapter<S> {
+    /// Wrap the given `store` in a new `BlockingAdapter`.
+    pub fn new(store: S) -> Self {
+        BlockingAdapter(Arc::new(Mutex::new(store)))
+    }
+
+    /// Consume this adapter and return the wrapped data store.
+    pub fn into_inner(self) -> S {
+        match Arc::try_unwrap(self.0) {
+            Ok(mutex) => mutex.into_inner().unwrap(),
+            Err(_) => panic!("A blocking operation on the data store is still running."),
+        }
+    }
+
+    /// Run the given function with the wrapped data store on the blocking thread pool.
+    async fn run<T, F>(&self, function: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut S) -> anyhow::Result<T> + Send + 'static,
+    {
+        let store = Arc::clone(&self.0);
+        task::spawn_blocking(move || function(&mut store.lock().unwrap())).await?
+    }
+}
+
+#[async_trait]
+impl<S: DataStore + Send + 'static> AsyncDataStore for BlockingAdapter<S> {
+    async fn write_block(&mut self, id: Uuid, data: &[u8]) -> anyhow::Result<()> {
+        let data = data.to_vec();
+        self.run(move |store| store.write_block(id, &data)).await
+    }
+
+    async fn read_block(&mut self, id: Uuid) -> anyhow::Result<Option<Vec<u8>>> {
+        self.run(move |store| store.read_block(id)).await
+    }
+
+    async fn remove_block(&mut self, id: Uuid) -> anyhow::Result<()> {
+        self.run(move |store| store.remove_block(id)).await
+    }
+
+    async fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>> {
+        self.run(|store| store.list_blocks()).await
+    }
+}
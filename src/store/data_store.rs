@@ -14,6 +14,7 @@
  * limitations under the License.
  */
 
+use std::cmp::min;
 use std::fmt::{self, Debug, Formatter};
 use uuid::Uuid;
 
@@ -21,6 +22,19 @@ use uuid::Uuid;
 ///
 /// A `DataStore` persistently stores blocks of data uniquely identified by UUIDs. Data stores are
 /// used as the storage backend for repositories in the [`crate::repo`] module.
+///
+/// When an error returned by a data store is caused by an [`io::Error`] or an error from one of the
+/// supported backends which indicates that the store is full, denied access, or is unavailable,
+/// the repository returns it as [`Error::StoreFull`], [`Error::PermissionDenied`], or
+/// [`Error::Unavailable`]. Implementations can also return these errors directly by converting an
+/// [`Error`] into an `anyhow::Error`. All other errors are returned as [`Error::Store`].
+///
+/// [`io::Error`]: std::io::Error
+/// [`Error`]: crate::Error
+/// [`Error::StoreFull`]: crate::Error::StoreFull
+/// [`Error::PermissionDenied`]: crate::Error::PermissionDenied
+/// [`Error::Unavailable`]: crate::Error::Unavailable
+/// [`Error::Store`]: crate::Error::Store
 pub trait DataStore {
     /// Write the given `data` as a new block with the given `id`.
     ///
@@ -52,6 +66,66 @@ pub trait DataStore {
 
     /// Return a list of IDs of blocks in the store.
     fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>>;
+
+    /// Write each of the given `blocks`, which are pairs of block IDs and their data.
+    ///
+    /// This is equivalent to calling `write_block` for each block, which is what the default
+    /// implementation does. Implementations can override this to write the blocks in fewer round
+    /// trips.
+    ///
+    /// Writing each block is an atomic operation, but writing the batch as a whole is not. If this
+    /// method returns `Err`, some of the blocks may have been written.
+    fn write_blocks(&mut self, blocks: &[(Uuid, &[u8])]) -> anyhow::Result<()> {
+        for (id, data) in blocks {
+            self.write_block(*id, data)?;
+        }
+        Ok(())
+    }
+
+    /// Return the bytes of each of the blocks with the given `ids` in the same order.
+    ///
+    /// This is equivalent to calling `read_block` for each block, which is what the default
+    /// implementation does. Implementations can override this to read the blocks in fewer round
+    /// trips.
+    fn read_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        ids.iter().map(|id| self.read_block(*id)).collect()
+    }
+
+    /// Return `len` bytes of the block with the given `id` starting at `offset`.
+    ///
+    /// If the block ends before `offset + len`, this returns the bytes between `offset` and the end
+    /// of the block, which is empty if `offset` is past the end of the block. If there is no block
+    /// with the given `id`, this returns `None`.
+    ///
+    /// The default implementation reads the whole block with `read_block` and returns the requested
+    /// range. Implementations can override this to only read the requested range from the store.
+    fn read_block_range(
+        &mut self,
+        id: Uuid,
+        offset: u64,
+        len: u64,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.read_block(id)?.map(|block| {
+            let start = min(offset, block.len() as u64) as usize;
+            let end = min(offset.saturating_add(len), block.len() as u64) as usize;
+            block[start..end].to_vec()
+        }))
+    }
+
+    /// Remove each of the blocks with the given `ids` from the store.
+    ///
+    /// This is equivalent to calling `remove_block` for each block, which is what the default
+    /// implementation does. Implementations can override this to remove the blocks in fewer round
+    /// trips.
+    ///
+    /// Removing each block is an atomic operation, but removing the batch as a whole is not. If
+    /// this method returns `Err`, some of the blocks may have been removed.
+    fn remove_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<()> {
+        for id in ids {
+            self.remove_block(*id)?;
+        }
+        Ok(())
+    }
 }
 
 impl DataStore for Box<dyn DataStore> {
@@ -70,6 +144,27 @@ impl DataStore for Box<dyn DataStore> {
     fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>> {
         self.as_mut().list_blocks()
     }
+
+    fn write_blocks(&mut self, blocks: &[(Uuid, &[u8])]) -> anyhow::Result<()> {
+        self.as_mut().write_blocks(blocks)
+    }
+
+    fn read_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        self.as_mut().read_blocks(ids)
+    }
+
+    fn read_block_range(
+        &mut self,
+        id: Uuid,
+        offset: u64,
+        len: u64,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        self.as_mut().read_block_range(id, offset, len)
+    }
+
+    fn remove_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<()> {
+        self.as_mut().remove_blocks(ids)
+    }
 }
 
 impl Debug for dyn DataStore {
@@ -77,3 +172,9 @@ impl Debug for dyn DataStore {
         write!(f, "DataStore")
     }
 }
+
+impl Debug for dyn DataStore + Send {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "DataStore")
+    }
+}
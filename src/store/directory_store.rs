@@ -17,8 +17,8 @@
 #![cfg(feature = "store-directory")]
 
 use std::fs::{create_dir_all, read_dir, remove_file, rename, File};
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 
 use uuid::Uuid;
 
@@ -49,18 +49,18 @@ impl OpenStore for DirectoryConfig {
     fn open(&self) -> crate::Result<Self::Store> {
         // Create the blocks directory in the data store.
         create_dir_all(&self.path)
-            .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
         create_dir_all(self.path.join(BLOCKS_DIRECTORY))
-            .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
         create_dir_all(self.path.join(STAGING_DIRECTORY))
-            .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
 
         let version_path = self.path.join(VERSION_FILE);
 
         if version_path.exists() {
             // Read the version ID file.
             let mut version_file = File::open(&version_path)
-                .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
             let mut version_id = String::new();
             version_file.read_to_string(&mut version_id)?;
 
@@ -71,8 +71,18 @@ impl OpenStore for DirectoryConfig {
         } else {
             // Write the version ID file.
             let mut version_file = File::create(&version_path)
-                .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
             version_file.write_all(CURRENT_VERSION.as_bytes())?;
+            version_file.sync_all()?;
+            sync_directory(&self.path)?;
+        }
+
+        // Remove any staging files left behind by writes which were interrupted.
+        for entry in read_dir(self.path.join(STAGING_DIRECTORY))
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?
+        {
+            remove_file(entry?.path())
+                .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
         }
 
         Ok(DirectoryStore {
@@ -83,6 +93,12 @@ impl OpenStore for DirectoryConfig {
 
 /// A `DataStore` which stores data in a directory in the local file system.
 ///
+/// Each block is written to a staging file which is flushed to disk and then atomically renamed
+/// into place, and the directory containing it is flushed to disk as well. This means a block
+/// which has been written survives a crash or power loss, and a block is never left partially
+/// written. Because the repository writes its metadata block last when committing, a commit which
+/// is interrupted leaves the repository as it was before the commit.
+///
 /// You can use [`DirectoryConfig`] to open a data store of this type.
 ///
 /// [`DirectoryConfig`]: crate::store::DirectoryConfig
@@ -109,23 +125,41 @@ impl DirectoryStore {
     }
 }
 
+/// Flush the entries of the directory at `path` to disk.
+///
+/// This makes files which were created in or renamed into the directory durable.
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> io::Result<()> {
+    File::open(path)?.sync_all()
+}
+
+/// Flush the entries of the directory at `path` to disk.
+///
+/// Directories can't be opened as files on this platform, so this does nothing.
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
 impl DataStore for DirectoryStore {
     fn write_block(&mut self, id: Uuid, data: &[u8]) -> anyhow::Result<()> {
         let staging_path = self.staging_path(id);
         let block_path = self.block_path(id);
+        let block_directory = block_path.parent().unwrap();
 
         // If this is the first block its sub-directory, the directory needs to be created.
-        create_dir_all(&block_path.parent().unwrap())?;
+        if !block_directory.exists() {
+            create_dir_all(block_directory)?;
+            sync_directory(&self.path.join(BLOCKS_DIRECTORY))?;
+        }
 
-        // Write to a staging file and then atomically move it to its final destination.
+        // Write to a staging file, flush it to disk, and then atomically move it to its final
+        // destination. The rename isn't durable until the directory has been flushed to disk.
         let mut staging_file = File::create(&staging_path)?;
         staging_file.write_all(data)?;
+        staging_file.sync_all()?;
         rename(&staging_path, &block_path)?;
-
-        // Remove any unused staging files.
-        for entry in read_dir(self.path.join(STAGING_DIRECTORY))? {
-            remove_file(entry?.path())?;
-        }
+        sync_directory(block_directory)?;
 
         Ok(())
     }
@@ -167,4 +201,24 @@ impl DataStore for DirectoryStore {
 
         Ok(block_ids)
     }
+
+    fn read_block_range(
+        &mut self,
+        id: Uuid,
+        offset: u64,
+        len: u64,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let block_path = self.block_path(id);
+
+        if block_path.exists() {
+            // Seeking past the end of the file is allowed, and reading from there reads no bytes.
+            let mut file = File::open(block_path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            let mut buffer = Vec::new();
+            file.take(len).read_to_end(&mut buffer)?;
+            Ok(Some(buffer))
+        } else {
+            Ok(None)
+        }
+    }
 }
@@ -0,0 +1,180 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::error::Error as StdError;
+use std::io;
+
+/// OS error codes which mean that a disk is full or a quota was exceeded.
+#[cfg(target_os = "linux")]
+const STORAGE_FULL_CODES: &[i32] = &[28, 122];
+#[cfg(all(unix, not(target_os = "linux")))]
+const STORAGE_FULL_CODES: &[i32] = &[28, 69];
+#[cfg(windows)]
+const STORAGE_FULL_CODES: &[i32] = &[39, 112];
+#[cfg(not(any(unix, windows)))]
+const STORAGE_FULL_CODES: &[i32] = &[];
+
+/// A kind of failure which is common to many data stores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StoreFailure {
+    /// The data store ran out of space or exceeded a quota.
+    Full,
+
+    /// The data store denied access.
+    PermissionDenied,
+
+    /// The data store couldn't be reached or was too busy, and the operation may succeed if it's
+    /// retried.
+    Unavailable,
+}
+
+impl StoreFailure {
+    /// Return the kind of failure which caused the given data store `error`, if it's known.
+    ///
+    /// This looks at every error in the chain of causes of `error`.
+    pub(crate) fn classify(error: &anyhow::Error) -> Option<Self> {
+        let classifiers: &[fn(&(dyn StdError + 'static)) -> Option<Self>] = &[
+            crate_failure,
+            io_failure,
+            #[cfg(feature = "store-sqlite")]
+            sqlite_failure,
+            #[cfg(feature = "store-redis")]
+            redis_failure,
+            #[cfg(feature = "store-sftp")]
+            sftp_failure,
+        ];
+        error
+            .chain()
+            .find_map(|cause| classifiers.iter().find_map(|classify| classify(cause)))
+    }
+
+    /// Convert the given data store `error` caused by this kind of failure into an `Error`.
+    pub(crate) fn into_error(self, error: anyhow::Error) -> crate::Error {
+        match self {
+            StoreFailure::Full => crate::Error::StoreFull(error),
+            StoreFailure::PermissionDenied => crate::Error::PermissionDenied(error),
+            StoreFailure::Unavailable => crate::Error::Unavailable(error),
+        }
+    }
+}
+
+/// Classify an `Error` which was returned directly by a data store.
+fn crate_failure(error: &(dyn StdError + 'static)) -> Option<StoreFailure> {
+    match error.downcast_ref::<crate::Error>()? {
+        crate::Error::StoreFull(_) => Some(StoreFailure::Full),
+        crate::Error::PermissionDenied(_) => Some(StoreFailure::PermissionDenied),
+        crate::Error::Unavailable(_) => Some(StoreFailure::Unavailable),
+        _ => None,
+    }
+}
+
+/// Classify an `io::Error`.
+fn io_failure(error: &(dyn StdError + 'static)) -> Option<StoreFailure> {
+    let error = error.downcast_ref::<io::Error>()?;
+    if let Some(code) = error.raw_os_error() {
+        if STORAGE_FULL_CODES.contains(&code) {
+            return Some(StoreFailure::Full);
+        }
+    }
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => Some(StoreFailure::PermissionDenied),
+        io::ErrorKind::Interrupted
+        | io::ErrorKind::TimedOut
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::NotConnected
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::UnexpectedEof => Some(StoreFailure::Unavailable),
+        _ => None,
+    }
+}
+
+/// Classify an error from SQLite.
+#[cfg(feature = "store-sqlite")]
+fn sqlite_failure(error: &(dyn StdError + 'static)) -> Option<StoreFailure> {
+    use rusqlite::ErrorCode;
+
+    match error.downcast_ref::<rusqlite::Error>()? {
+        rusqlite::Error::SqliteFailure(failure, _) => match failure.code {
+            ErrorCode::DiskFull => Some(StoreFailure::Full),
+            ErrorCode::PermissionDenied | ErrorCode::ReadOnly => {
+                Some(StoreFailure::PermissionDenied)
+            }
+            ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked => Some(StoreFailure::Unavailable),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Classify an error from Redis.
+#[cfg(feature = "store-redis")]
+fn redis_failure(error: &(dyn StdError + 'static)) -> Option<StoreFailure> {
+    use redis::ErrorKind;
+
+    let error = error.downcast_ref::<redis::RedisError>()?;
+    if error.is_timeout() || error.is_connection_refusal() || error.is_connection_dropped() {
+        return Some(StoreFailure::Unavailable);
+    }
+    match error.kind() {
+        ErrorKind::AuthenticationFailed => Some(StoreFailure::PermissionDenied),
+        ErrorKind::BusyLoadingError
+        | ErrorKind::TryAgain
+        | ErrorKind::ClusterDown
+        | ErrorKind::MasterDown => Some(StoreFailure::Unavailable),
+        // Redis rejects writes with this code when it reaches its memory limit.
+        ErrorKind::ExtensionError if error.code() == Some("OOM") => Some(StoreFailure::Full),
+        _ => None,
+    }
+}
+
+/// Classify an error from an SFTP server.
+#[cfg(feature = "store-sftp")]
+fn sftp_failure(error: &(dyn StdError + 'static)) -> Option<StoreFailure> {
+    use ssh2::ErrorCode;
+
+    // These are the status codes defined by the SFTP protocol and the error codes defined by
+    // libssh2.
+    const SFTP_PERMISSION_DENIED: i32 = 3;
+    const SFTP_NO_CONNECTION: i32 = 6;
+    const SFTP_CONNECTION_LOST: i32 = 7;
+    const SFTP_WRITE_PROTECT: i32 = 12;
+    const SFTP_NO_SPACE_ON_FILESYSTEM: i32 = 14;
+    const SFTP_QUOTA_EXCEEDED: i32 = 15;
+    const SESSION_SOCKET_SEND: i32 = -7;
+    const SESSION_TIMEOUT: i32 = -9;
+    const SESSION_SOCKET_DISCONNECT: i32 = -13;
+    const SESSION_AUTHENTICATION_FAILED: i32 = -18;
+    const SESSION_SOCKET_RECV: i32 = -43;
+
+    match error.downcast_ref::<ssh2::Error>()?.code() {
+        ErrorCode::SFTP(SFTP_NO_SPACE_ON_FILESYSTEM) | ErrorCode::SFTP(SFTP_QUOTA_EXCEEDED) => {
+            Some(StoreFailure::Full)
+        }
+        ErrorCode::SFTP(SFTP_PERMISSION_DENIED)
+        | ErrorCode::SFTP(SFTP_WRITE_PROTECT)
+        | ErrorCode::Session(SESSION_AUTHENTICATION_FAILED) => Some(StoreFailure::PermissionDenied),
+        ErrorCode::SFTP(SFTP_NO_CONNECTION)
+        | ErrorCode::SFTP(SFTP_CONNECTION_LOST)
+        | ErrorCode::Session(SESSION_SOCKET_SEND)
+        | ErrorCode::Session(SESSION_TIMEOUT)
+        | ErrorCode::Session(SESSION_SOCKET_DISCONNECT)
+        | ErrorCode::Session(SESSION_SOCKET_RECV) => Some(StoreFailure::Unavailable),
+        _ => None,
+    }
+}
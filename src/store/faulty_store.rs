@@ -0,0 +1,285 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(feature = "testing")]
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use super::data_store::DataStore;
+use super::open_store::OpenStore;
+
+/// A type of operation on a [`DataStore`].
+///
+/// [`DataStore`]: crate::store::DataStore
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+pub enum StoreOperation {
+    /// Writing a block.
+    Write,
+
+    /// Reading a block.
+    Read,
+
+    /// Removing a block.
+    Remove,
+
+    /// Listing the blocks in the store.
+    List,
+}
+
+/// A fault which can be injected into an operation on a [`FaultyStore`].
+///
+/// [`FaultyStore`]: crate::store::FaultyStore
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+#[non_exhaustive]
+pub enum Fault {
+    /// Fail the operation with an [`io::Error`] of the given kind without performing it.
+    ///
+    /// [`io::Error`]: std::io::Error
+    Error(io::ErrorKind),
+
+    /// Wait for the given duration before performing the operation.
+    Delay(Duration),
+
+    /// Corrupt the data which is written or read by the operation.
+    ///
+    /// A byte in the middle of the block is changed. This has no effect on operations which don't
+    /// transfer any data or on empty blocks.
+    Corrupt,
+}
+
+/// A rule describing which operations on a [`FaultyStore`] a fault is injected into.
+///
+/// [`FaultyStore`]: crate::store::FaultyStore
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+#[non_exhaustive]
+pub struct FaultRule {
+    /// The type of operation to inject the fault into.
+    pub operation: StoreOperation,
+
+    /// The fault to inject.
+    pub fault: Fault,
+
+    /// The ID of the block to inject the fault into, or `None` to match every block.
+    ///
+    /// Operations which list blocks don't have a block ID, so they only match if this is `None`.
+    pub block: Option<Uuid>,
+
+    /// The number of matching operations to let through before injecting the fault.
+    pub skip: u32,
+
+    /// The number of times to inject the fault, or `None` to inject it indefinitely.
+    pub times: Option<u32>,
+}
+
+impl FaultRule {
+    /// Create a rule which injects `fault` into every operation of the given type.
+    pub fn new(operation: StoreOperation, fault: Fault) -> Self {
+        Self {
+            operation,
+            fault,
+            block: None,
+            skip: 0,
+            times: None,
+        }
+    }
+
+    /// Return whether this rule matches an operation on the block with the given `id`.
+    fn matches(&self, operation: StoreOperation, id: Option<Uuid>) -> bool {
+        self.operation == operation && (self.block.is_none() || self.block == id)
+    }
+}
+
+/// A handle for programming the faults injected into a [`FaultyStore`].
+///
+/// Clones of this value share the same set of rules, so a test can keep a clone to change which
+/// faults are injected after the store has been passed to a repository.
+///
+/// [`FaultyStore`]: crate::store::FaultyStore
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+pub struct FaultInjector(Arc<Mutex<Vec<FaultRule>>>);
+
+impl FaultInjector {
+    /// Create a new `FaultInjector` with no rules.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a rule for injecting a fault.
+    ///
+    /// When an operation matches multiple rules, every matching fault is injected in the order the
+    /// rules were added.
+    pub fn inject(&self, rule: FaultRule) -> &Self {
+        self.0.lock().unwrap().push(rule);
+        self
+    }
+
+    /// Remove all rules so that no more faults are injected.
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+
+    /// Return the faults to inject into an operation on the block with the given `id`.
+    fn faults(&self, operation: StoreOperation, id: Option<Uuid>) -> Vec<Fault> {
+        let mut rules = self.0.lock().unwrap();
+        let mut faults = Vec::new();
+        for rule in rules.iter_mut() {
+            if !rule.matches(operation, id) || rule.times == Some(0) {
+                continue;
+            }
+            if rule.skip > 0 {
+                rule.skip -= 1;
+                continue;
+            }
+            if let Some(times) = &mut rule.times {
+                *times -= 1;
+            }
+            faults.push(rule.fault);
+        }
+        rules.retain(|rule| rule.times != Some(0));
+        faults
+    }
+}
+
+/// Change a byte in the middle of `data`.
+fn corrupt(data: &mut [u8]) {
+    if !data.is_empty() {
+        let index = data.len() / 2;
+        data[index] ^= 0xff;
+    }
+}
+
+/// Inject the given `faults` other than `Fault::Corrupt` and return whether the data should be
+/// corrupted.
+fn inject(faults: &[Fault]) -> anyhow::Result<bool> {
+    let mut should_corrupt = false;
+    for fault in faults {
+        match fault {
+            Fault::Error(kind) => {
+                return Err(
+                    io::Error::new(*kind, "A fault was injected into the data store.").into(),
+                )
+            }
+            Fault::Delay(duration) => thread::sleep(*duration),
+            Fault::Corrupt => should_corrupt = true,
+        }
+    }
+    Ok(should_corrupt)
+}
+
+/// The configuration for opening a [`FaultyStore`].
+///
+/// Every store opened with this config shares the same [`FaultInjector`].
+///
+/// [`FaultyStore`]: crate::store::FaultyStore
+/// [`FaultInjector`]: crate::store::FaultInjector
+#[derive(Debug, Clone)]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+pub struct FaultyConfig<C> {
+    /// The config for opening the inner data store.
+    pub config: C,
+
+    /// The handle for programming which faults are injected.
+    pub injector: FaultInjector,
+}
+
+impl<C: OpenStore> OpenStore for FaultyConfig<C> {
+    type Store = FaultyStore<C::Store>;
+
+    fn open(&self) -> crate::Result<Self::Store> {
+        Ok(FaultyStore::new(self.config.open()?, self.injector.clone()))
+    }
+}
+
+/// A `DataStore` which wraps another data store and injects faults into its operations.
+///
+/// This is useful for testing how an application handles errors, latency, and corrupt data from
+/// a data store. Which faults are injected is programmed using a [`FaultInjector`], which can be
+/// changed while the store is in use.
+///
+/// Batch operations are performed one block at a time so that faults can be injected into
+/// individual blocks.
+///
+/// You can use [`FaultyConfig`] to open a data store of this type.
+///
+/// [`FaultInjector`]: crate::store::FaultInjector
+/// [`FaultyConfig`]: crate::store::FaultyConfig
+#[derive(Debug)]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+pub struct FaultyStore<S> {
+    store: S,
+    injector: FaultInjector,
+}
+
+impl<S: DataStore> FaultyStore<S> {
+    /// Wrap `store` so that faults are injected into it according to the rules in `injector`.
+    pub fn new(store: S, injector: FaultInjector) -> Self {
+        Self { store, injector }
+    }
+
+    /// Return the handle for programming which faults are injected.
+    pub fn injector(&self) -> &FaultInjector {
+        &self.injector
+    }
+
+    /// Consume this store and return the inner data store.
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+}
+
+impl<S: DataStore> DataStore for FaultyStore<S> {
+    fn write_block(&mut self, id: Uuid, data: &[u8]) -> anyhow::Result<()> {
+        let faults = self.injector.faults(StoreOperation::Write, Some(id));
+        if inject(&faults)? {
+            let mut corrupt_data = data.to_vec();
+            corrupt(&mut corrupt_data);
+            self.store.write_block(id, &corrupt_data)
+        } else {
+            self.store.write_block(id, data)
+        }
+    }
+
+    fn read_block(&mut self, id: Uuid) -> anyhow::Result<Option<Vec<u8>>> {
+        let faults = self.injector.faults(StoreOperation::Read, Some(id));
+        let should_corrupt = inject(&faults)?;
+        let mut data = self.store.read_block(id)?;
+        if let (true, Some(data)) = (should_corrupt, &mut data) {
+            corrupt(data);
+        }
+        Ok(data)
+    }
+
+    fn remove_block(&mut self, id: Uuid) -> anyhow::Result<()> {
+        let faults = self.injector.faults(StoreOperation::Remove, Some(id));
+        inject(&faults)?;
+        self.store.remove_block(id)
+    }
+
+    fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>> {
+        let faults = self.injector.faults(StoreOperation::List, None);
+        inject(&faults)?;
+        self.store.list_blocks()
+    }
+}
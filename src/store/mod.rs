@@ -29,13 +29,30 @@
 //! config types with [`OpenOptions`] to open repositories. You'll almost never need to use the
 //! [`OpenStore`] or [`DataStore`] traits directly.
 //!
+//! If the `async` feature is enabled, data stores can also implement [`AsyncDataStore`], which
+//! provides the same operations as futures. Data stores backed by a network service implement it
+//! natively, and any other data store can be wrapped in a [`BlockingAdapter`].
+//!
+//! If the `testing` feature is enabled, any data store can be wrapped in a [`FaultyStore`], which
+//! can be programmed to fail, delay, or corrupt operations so that you can test how your
+//! application handles a misbehaving data store.
+//!
 //! [`DataStore`]: crate::store::DataStore
 //! [`OpenStore`]: crate::store::OpenStore
 //! [`OpenOptions`]: crate::repo::OpenOptions
+//! [`AsyncDataStore`]: crate::store::AsyncDataStore
+//! [`BlockingAdapter`]: crate::store::BlockingAdapter
+//! [`FaultyStore`]: crate::store::FaultyStore
 
+#[cfg(feature = "async")]
+pub use self::async_store::{AsyncDataStore, BlockingAdapter};
 pub use self::data_store::DataStore;
 #[cfg(feature = "store-directory")]
 pub use self::directory_store::{DirectoryConfig, DirectoryStore};
+#[cfg(feature = "testing")]
+pub use self::faulty_store::{
+    Fault, FaultInjector, FaultRule, FaultyConfig, FaultyStore, StoreOperation,
+};
 pub use self::memory_store::{MemoryConfig, MemoryStore};
 pub use self::open_store::OpenStore;
 #[cfg(feature = "store-rclone")]
@@ -49,8 +66,13 @@ pub use self::sftp_store::{SftpAuth, SftpConfig, SftpStore};
 #[cfg(feature = "store-sqlite")]
 pub use self::sqlite_store::{SqliteConfig, SqliteStore};
 
+pub(crate) use self::failure::StoreFailure;
+
+mod async_store;
 mod data_store;
 mod directory_store;
+mod failure;
+mod faulty_store;
 mod memory_store;
 mod open_store;
 mod rclone_store;
@@ -19,7 +19,7 @@ use crate::store::DataStore;
 /// A value which can be used to open a `DataStore`.
 pub trait OpenStore {
     /// The type of `DataStore` which this value can be used to open.
-    type Store: DataStore + 'static;
+    type Store: DataStore + Send + 'static;
 
     /// Open or create a data store of type `Store`.
     ///
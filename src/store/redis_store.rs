@@ -19,7 +19,9 @@
 use std::fmt::{self, Debug, Formatter};
 use std::path::PathBuf;
 
-use redis::{Client, Commands, Connection, ConnectionAddr, ConnectionInfo, IntoConnectionInfo};
+use redis::{
+    pipe, Client, Commands, Connection, ConnectionAddr, ConnectionInfo, IntoConnectionInfo,
+};
 use uuid::Uuid;
 
 use super::data_store::DataStore;
@@ -119,13 +121,13 @@ impl Debug for RedisStore {
 impl RedisStore {
     fn from_connection_info(info: ConnectionInfo) -> crate::Result<Self> {
         let mut connection = Client::open(info)
-            .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?
             .get_connection()
-            .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
 
         let version_response: Option<String> = connection
             .get("version")
-            .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
 
         match version_response {
             Some(version) => {
@@ -135,7 +137,7 @@ impl RedisStore {
             }
             None => connection
                 .set("version", CURRENT_VERSION)
-                .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?,
+                .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?,
         }
 
         Ok(RedisStore { connection })
@@ -172,4 +174,45 @@ impl DataStore for RedisStore {
             .collect();
         Ok(blocks)
     }
+
+    fn write_blocks(&mut self, blocks: &[(Uuid, &[u8])]) -> anyhow::Result<()> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+
+        // Pipelining the commands sends them all in a single round trip.
+        let mut pipeline = pipe();
+        for (id, data) in blocks {
+            let key_id = id.to_hyphenated().to_string();
+            pipeline.set(format!("block:{}", key_id), *data).ignore();
+        }
+        pipeline.query(&mut self.connection)?;
+        Ok(())
+    }
+
+    fn read_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut pipeline = pipe();
+        for id in ids {
+            let key_id = id.to_hyphenated().to_string();
+            pipeline.get(format!("block:{}", key_id));
+        }
+        Ok(pipeline.query(&mut self.connection)?)
+    }
+
+    fn remove_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let keys = ids
+            .iter()
+            .map(|id| format!("block:{}", id.to_hyphenated()))
+            .collect::<Vec<_>>();
+        self.connection.del(keys)?;
+        Ok(())
+    }
 }
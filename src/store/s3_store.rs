@@ -17,7 +17,10 @@
 #![cfg(feature = "store-s3")]
 
 use std::env;
+use std::future::Future;
 
+#[cfg(feature = "async")]
+use async_trait::async_trait;
 use hex_literal::hex;
 use s3::bucket::Bucket;
 use s3::creds::Credentials;
@@ -25,6 +28,8 @@ use s3::region::Region;
 use tokio::runtime::Runtime;
 use uuid::Uuid;
 
+#[cfg(feature = "async")]
+use super::async_store::AsyncDataStore;
 use super::data_store::DataStore;
 use super::open_store::OpenStore;
 
@@ -46,6 +51,19 @@ const BINARY_CONTENT_TYPE: &str = "application/octet-stream";
 /// The HTTP status code for an object which does not exist.
 const NOT_FOUND_CODE: u16 = 404;
 
+/// The HTTP status code for a range request which starts past the end of an object.
+const RANGE_NOT_SATISFIABLE_CODE: u16 = 416;
+
+/// The HTTP status code for a request which was denied.
+const FORBIDDEN_CODE: u16 = 403;
+
+/// The HTTP status codes for requests which failed because the service is overloaded or
+/// unreachable.
+const UNAVAILABLE_CODES: &[u16] = &[429, 500, 502, 503, 504];
+
+/// The maximum number of requests to send concurrently when operating on multiple blocks.
+const MAX_CONCURRENT_REQUESTS: usize = 16;
+
 /// The environment variable for the AWS access key.
 const ACCESS_KEY_ENV: &str = "AWS_ACCESS_KEY_ID";
 
@@ -378,24 +396,41 @@ impl OpenStore for S3Config {
 
         match runtime.block_on(bucket.get_object(&version_key)) {
             Ok((_, code)) if code == NOT_FOUND_CODE => {
-                runtime
+                let (_, code) = runtime
                     .block_on(bucket.put_object(&version_key, CURRENT_VERSION.as_bytes()))
-                    .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                    .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
+                check_status(code).map_err(crate::Error::from_store)?;
             }
-            Ok((version_bytes, _)) => {
+            Ok((version_bytes, code)) => {
+                check_status(code).map_err(crate::Error::from_store)?;
                 let version = Uuid::from_slice(version_bytes.as_slice())
                     .map_err(|_| crate::Error::UnsupportedStore)?;
                 if version != CURRENT_VERSION {
                     return Err(crate::Error::UnsupportedStore);
                 }
             }
-            Err(error) => return Err(crate::Error::Store(anyhow::Error::from(error))),
+            Err(error) => return Err(crate::Error::from_store(anyhow::Error::from(error))),
         };
 
         Ok(S3Store { bucket, prefix })
     }
 }
 
+/// Return an error if the HTTP status `code` of a response does not indicate success.
+fn check_status(code: u16) -> anyhow::Result<()> {
+    if (200..300).contains(&code) {
+        return Ok(());
+    }
+    let error = anyhow::anyhow!("The S3 request failed with HTTP status code {}.", code);
+    if code == FORBIDDEN_CODE {
+        Err(crate::Error::PermissionDenied(error).into())
+    } else if UNAVAILABLE_CODES.contains(&code) {
+        Err(crate::Error::Unavailable(error).into())
+    } else {
+        Err(error)
+    }
+}
+
 /// A `DataStore` which stores data in an Amazon S3 bucket.
 ///
 /// You can use [`S3Config`] to open a data store of this type.
@@ -413,6 +448,51 @@ impl S3Store {
     fn block_path(&self, id: Uuid) -> String {
         join_key!(self.prefix, BLOCK_PREFIX, id.to_hyphenated().to_string())
     }
+
+    /// Return the key prefix of block objects.
+    fn blocks_path(&self) -> String {
+        join_key!(self.prefix, BLOCK_PREFIX) + SEPARATOR
+    }
+
+    /// Send the request returned by `request` for each of the blocks with the given `ids`.
+    ///
+    /// S3 has no API for operating on many objects in a single request, so the requests are sent
+    /// concurrently instead. This returns the result of each request in the same order as `ids`.
+    fn send_concurrently<T, F>(
+        &self,
+        ids: &[Uuid],
+        request: impl Fn(Bucket, String, usize) -> F,
+    ) -> anyhow::Result<Vec<T>>
+    where
+        T: Send + 'static,
+        F: Future<Output = anyhow::Result<T>> + Send + 'static,
+    {
+        let mut runtime = Runtime::new().unwrap();
+        let mut results = Vec::with_capacity(ids.len());
+
+        for (batch_index, batch) in ids.chunks(MAX_CONCURRENT_REQUESTS).enumerate() {
+            let handles = batch
+                .iter()
+                .enumerate()
+                .map(|(index, id)| {
+                    let future = request(
+                        self.bucket.clone(),
+                        self.block_path(*id),
+                        batch_index * MAX_CONCURRENT_REQUESTS + index,
+                    );
+                    runtime.spawn(future)
+                })
+                .collect::<Vec<_>>();
+            runtime.block_on(async {
+                for handle in handles {
+                    results.push(handle.await??);
+                }
+                Ok::<_, anyhow::Error>(())
+            })?;
+        }
+
+        Ok(results)
+    }
 }
 
 impl DataStore for S3Store {
@@ -420,8 +500,8 @@ impl DataStore for S3Store {
         let mut runtime = Runtime::new().unwrap();
 
         let block_path = self.block_path(id);
-        runtime.block_on(self.bucket.put_object(&block_path, data))?;
-        Ok(())
+        let (_, code) = runtime.block_on(self.bucket.put_object(&block_path, data))?;
+        check_status(code)
     }
 
     fn read_block(&mut self, id: Uuid) -> anyhow::Result<Option<Vec<u8>>> {
@@ -432,6 +512,7 @@ impl DataStore for S3Store {
         if code == NOT_FOUND_CODE {
             Ok(None)
         } else {
+            check_status(code)?;
             Ok(Some(bytes))
         }
     }
@@ -440,14 +521,14 @@ impl DataStore for S3Store {
         let mut runtime = Runtime::new().unwrap();
 
         let block_path = self.block_path(id);
-        runtime.block_on(self.bucket.delete_object(&block_path))?;
-        Ok(())
+        let (_, code) = runtime.block_on(self.bucket.delete_object(&block_path))?;
+        check_status(code)
     }
 
     fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>> {
         let mut runtime = Runtime::new().unwrap();
 
-        let blocks_path = join_key!(self.prefix, BLOCK_PREFIX) + SEPARATOR;
+        let blocks_path = self.blocks_path();
         let block_ids = runtime
             .block_on(self.bucket.list(blocks_path.clone(), None))?
             .into_iter()
@@ -459,4 +540,104 @@ impl DataStore for S3Store {
             .collect::<Vec<_>>();
         Ok(block_ids)
     }
+
+    fn write_blocks(&mut self, blocks: &[(Uuid, &[u8])]) -> anyhow::Result<()> {
+        let ids = blocks.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        self.send_concurrently(&ids, |bucket, block_path, index| {
+            let data = blocks[index].1.to_vec();
+            async move {
+                let (_, code) = bucket.put_object(&block_path, &data).await?;
+                check_status(code)
+            }
+        })?;
+        Ok(())
+    }
+
+    fn read_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        self.send_concurrently(ids, |bucket, block_path, _| async move {
+            let (bytes, code) = bucket.get_object(&block_path).await?;
+            if code == NOT_FOUND_CODE {
+                Ok(None)
+            } else {
+                check_status(code)?;
+                Ok(Some(bytes))
+            }
+        })
+    }
+
+    fn read_block_range(
+        &mut self,
+        id: Uuid,
+        offset: u64,
+        len: u64,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let mut runtime = Runtime::new().unwrap();
+
+        // The end of an HTTP range is inclusive, so we request at least one byte and discard it if
+        // no bytes were requested.
+        let block_path = self.block_path(id);
+        let end = offset.saturating_add(len.max(1) - 1);
+        let (mut bytes, code) =
+            runtime.block_on(self.bucket.get_object_range(&block_path, offset, Some(end)))?;
+        match code {
+            NOT_FOUND_CODE => Ok(None),
+            RANGE_NOT_SATISFIABLE_CODE => Ok(Some(Vec::new())),
+            _ => {
+                check_status(code)?;
+                bytes.truncate(len as usize);
+                Ok(Some(bytes))
+            }
+        }
+    }
+
+    fn remove_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<()> {
+        self.send_concurrently(ids, |bucket, block_path, _| async move {
+            let (_, code) = bucket.delete_object(&block_path).await?;
+            check_status(code)
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl AsyncDataStore for S3Store {
+    async fn write_block(&mut self, id: Uuid, data: &[u8]) -> anyhow::Result<()> {
+        let block_path = self.block_path(id);
+        let (_, code) = self.bucket.put_object(&block_path, data).await?;
+        check_status(code)
+    }
+
+    async fn read_block(&mut self, id: Uuid) -> anyhow::Result<Option<Vec<u8>>> {
+        let block_path = self.block_path(id);
+        let (bytes, code) = self.bucket.get_object(&block_path).await?;
+        if code == NOT_FOUND_CODE {
+            Ok(None)
+        } else {
+            check_status(code)?;
+            Ok(Some(bytes))
+        }
+    }
+
+    async fn remove_block(&mut self, id: Uuid) -> anyhow::Result<()> {
+        let block_path = self.block_path(id);
+        let (_, code) = self.bucket.delete_object(&block_path).await?;
+        check_status(code)
+    }
+
+    async fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>> {
+        let blocks_path = self.blocks_path();
+        let block_ids = self
+            .bucket
+            .list(blocks_path.clone(), None)
+            .await?
+            .into_iter()
+            .flat_map(|list| list.contents)
+            .map(|object| {
+                Uuid::parse_str(object.key.trim_start_matches(&blocks_path))
+                    .expect("Could not parse UUID.")
+            })
+            .collect::<Vec<_>>();
+        Ok(block_ids)
+    }
 }
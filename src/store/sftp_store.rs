@@ -99,20 +99,20 @@ impl OpenStore for SftpConfig {
     fn open(&self) -> crate::Result<Self::Store> {
         // Connect to the SSH server.
         let stream = TcpStream::connect(&self.addr)
-            .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
         let mut session =
-            Session::new().map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+            Session::new().map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
         session.set_tcp_stream(stream);
         session
             .handshake()
-            .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
 
         // Perform authentication.
         match &self.auth {
             SftpAuth::Password { username, password } => {
                 session
                     .userauth_password(username, password)
-                    .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                    .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
             }
             SftpAuth::Key {
                 username,
@@ -127,22 +127,22 @@ impl OpenStore for SftpConfig {
                         private_key,
                         password.as_ref().map(|str| str.as_str()),
                     )
-                    .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                    .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
             }
             SftpAuth::Agent { username, comment } => match comment {
                 Some(comment) => {
                     let mut agent = session
                         .agent()
-                        .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                        .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
                     agent
                         .connect()
-                        .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                        .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
                     agent
                         .list_identities()
-                        .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                        .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
                     let identities = agent
                         .identities()
-                        .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                        .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
                     let key = identities
                         .iter()
                         .find(|key| key.comment() == comment)
@@ -150,19 +150,19 @@ impl OpenStore for SftpConfig {
                         .map_err(crate::Error::Store)?;
                     agent
                         .userauth(username, key)
-                        .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                        .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
                 }
                 None => {
                     session
                         .userauth_agent(username)
-                        .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                        .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
                 }
             },
         }
 
         let sftp = session
             .sftp()
-            .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
 
         // Create the directories if they don't exist.
         let directories = &[
@@ -173,7 +173,7 @@ impl OpenStore for SftpConfig {
         for directory in directories {
             if sftp.stat(&directory).is_err() {
                 sftp.mkdir(&directory, 0o755)
-                    .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                    .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
             }
         }
 
@@ -183,7 +183,7 @@ impl OpenStore for SftpConfig {
             // Read the version ID file.
             let mut version_file = sftp
                 .open(&version_path)
-                .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
             let mut version_id = String::new();
             version_file.read_to_string(&mut version_id)?;
 
@@ -195,7 +195,7 @@ impl OpenStore for SftpConfig {
             // Write the version ID file.
             let mut version_file = sftp
                 .create(&version_path)
-                .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
             version_file.write_all(CURRENT_VERSION.as_bytes())?;
         }
 
@@ -43,7 +43,7 @@ impl OpenStore for SqliteConfig {
 
     fn open(&self) -> crate::Result<Self::Store> {
         let connection = Connection::open(&self.path)
-            .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
 
         connection
             .execute_batch(
@@ -59,7 +59,7 @@ impl OpenStore for SqliteConfig {
                     );
                 "#,
             )
-            .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
 
         let version_bytes: Option<Vec<u8>> = connection
             .query_row(
@@ -71,7 +71,7 @@ impl OpenStore for SqliteConfig {
                 |row| row.get(0),
             )
             .optional()
-            .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+            .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
 
         match version_bytes {
             Some(bytes) => {
@@ -90,7 +90,7 @@ impl OpenStore for SqliteConfig {
                     "#,
                         params![&CURRENT_VERSION.as_bytes()[..]],
                     )
-                    .map_err(|error| crate::Error::Store(anyhow::Error::from(error)))?;
+                    .map_err(|error| crate::Error::from_store(anyhow::Error::from(error)))?;
             }
         }
 
@@ -162,4 +162,82 @@ impl DataStore for SqliteStore {
 
         Ok(result)
     }
+
+    fn write_blocks(&mut self, blocks: &[(Uuid, &[u8])]) -> anyhow::Result<()> {
+        // Writing all the blocks in one transaction avoids syncing the database for each block.
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached(
+                r#"
+                    REPLACE INTO Blocks (uuid, data)
+                    VALUES (?1, ?2);
+                "#,
+            )?;
+            for (id, data) in blocks {
+                statement.execute(params![&id.as_bytes()[..], *data])?;
+            }
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn read_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let mut statement = self.connection.prepare_cached(
+            r#"
+                SELECT data FROM Blocks
+                WHERE uuid = ?1;
+            "#,
+        )?;
+
+        let mut blocks = Vec::with_capacity(ids.len());
+        for id in ids {
+            let block = statement
+                .query_row(params![&id.as_bytes()[..]], |row| row.get(0))
+                .optional()?;
+            blocks.push(block);
+        }
+
+        Ok(blocks)
+    }
+
+    fn read_block_range(
+        &mut self,
+        id: Uuid,
+        offset: u64,
+        len: u64,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        // SQLite uses 1-based indices for substrings.
+        let start = offset.saturating_add(1).min(i64::MAX as u64) as i64;
+        let len = len.min(i64::MAX as u64) as i64;
+        Ok(self
+            .connection
+            .query_row(
+                r#"
+                    SELECT substr(data, ?2, ?3) FROM Blocks
+                    WHERE uuid = ?1;
+                "#,
+                params![&id.as_bytes()[..], start, len],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn remove_blocks(&mut self, ids: &[Uuid]) -> anyhow::Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached(
+                r#"
+                    DELETE FROM Blocks
+                    WHERE uuid = ?1;
+                "#,
+            )?;
+            for id in ids {
+                statement.execute(params![&id.as_bytes()[..]])?;
+            }
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,110 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(all(feature = "async", feature = "encryption", feature = "compression"))]
+
+#[cfg(feature = "store-s3")]
+use serial_test::serial;
+use tokio::runtime::Runtime;
+use uuid::Uuid;
+
+use acid_store::store::{AsyncDataStore, BlockingAdapter};
+#[cfg(feature = "store-s3")]
+use common::s3_store;
+use common::{assert_contains_all, memory_store, random_buffer};
+
+mod common;
+
+async fn read_write_block(mut store: impl AsyncDataStore) -> anyhow::Result<()> {
+    let id = Uuid::new_v4();
+
+    assert_eq!(store.read_block(id).await?, None);
+
+    let expected_block = random_buffer();
+    store.write_block(id, expected_block.as_slice()).await?;
+
+    assert_eq!(store.read_block(id).await?, Some(expected_block));
+
+    Ok(())
+}
+
+#[test]
+fn blocking_read_write_block() -> anyhow::Result<()> {
+    let store = BlockingAdapter::new(memory_store()?);
+    Runtime::new()?.block_on(read_write_block(store))
+}
+
+#[test]
+#[serial(s3)]
+#[cfg(feature = "store-s3")]
+fn s3_read_write_block() {
+    let store = s3_store().unwrap();
+    Runtime::new()
+        .unwrap()
+        .block_on(read_write_block(store))
+        .unwrap();
+}
+
+async fn remove_and_list_blocks(mut store: impl AsyncDataStore) -> anyhow::Result<()> {
+    let id = Uuid::new_v4();
+    let removed_id = Uuid::new_v4();
+
+    store.write_block(id, random_buffer().as_slice()).await?;
+    store
+        .write_block(removed_id, random_buffer().as_slice())
+        .await?;
+    store.remove_block(removed_id).await?;
+
+    // Removing a nonexistent block should return `Ok`.
+    store.remove_block(Uuid::new_v4()).await?;
+
+    assert_eq!(store.read_block(removed_id).await?, None);
+    assert_contains_all(store.list_blocks().await?, vec![id]);
+
+    Ok(())
+}
+
+#[test]
+fn blocking_remove_and_list_blocks() -> anyhow::Result<()> {
+    let store = BlockingAdapter::new(memory_store()?);
+    Runtime::new()?.block_on(remove_and_list_blocks(store))
+}
+
+#[test]
+#[serial(s3)]
+#[cfg(feature = "store-s3")]
+fn s3_remove_and_list_blocks() {
+    let store = s3_store().unwrap();
+    Runtime::new()
+        .unwrap()
+        .block_on(remove_and_list_blocks(store))
+        .unwrap();
+}
+
+#[test]
+fn blocking_adapter_returns_inner_store() -> anyhow::Result<()> {
+    let mut store = BlockingAdapter::new(memory_store()?);
+    let id = Uuid::new_v4();
+    Runtime::new()?.block_on(store.write_block(id, b"data"))?;
+
+    let mut inner = store.into_inner();
+    assert_eq!(
+        acid_store::store::DataStore::read_block(&mut inner, id)?,
+        Some(b"data".to_vec())
+    );
+
+    Ok(())
+}
@@ -0,0 +1,203 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(feature = "async")]
+
+use std::collections::HashSet;
+use std::io::SeekFrom;
+use std::thread;
+use std::time::Duration;
+
+use futures::{StreamExt, TryStreamExt};
+use relative_path::RelativePathBuf;
+use tokio::runtime::Runtime;
+
+use acid_store::repo::file::{Entry, FileRepo};
+use acid_store::repo::key::KeyRepo;
+use acid_store::repo::version::VersionRepo;
+use acid_store::repo::{AsyncRepo, OpenMode, OpenOptions};
+use acid_store::store::MemoryConfig;
+use common::random_buffer;
+
+mod common;
+
+fn create_repo(store_config: &MemoryConfig) -> acid_store::Result<AsyncRepo<KeyRepo<String>>> {
+    let repo = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .open(store_config)?;
+    Ok(AsyncRepo::new(repo))
+}
+
+#[test]
+fn write_and_read_object() -> anyhow::Result<()> {
+    let mut repo = create_repo(&MemoryConfig::new())?;
+    let expected_data = random_buffer();
+
+    Runtime::new()?.block_on(async {
+        let mut object = repo.insert(String::from("test")).await;
+        object.write_all(expected_data.as_slice()).await?;
+        object.commit().await?;
+
+        object.seek(SeekFrom::Start(0)).await?;
+        assert_eq!(object.read_to_end().await?, expected_data);
+        assert_eq!(object.size().await?, expected_data.len() as u64);
+
+        Ok::<_, anyhow::Error>(())
+    })
+}
+
+#[test]
+fn committed_changes_are_persisted() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let mut repo = create_repo(&store_config)?;
+    let expected_data = random_buffer();
+
+    Runtime::new()?.block_on(async {
+        let mut object = repo.insert(String::from("test")).await;
+        object.write_all(expected_data.as_slice()).await?;
+        object.commit().await?;
+        drop(object);
+
+        repo.commit().await?;
+        Ok::<_, anyhow::Error>(())
+    })?;
+    drop(repo);
+
+    let mut repo = AsyncRepo::new(
+        OpenOptions::new()
+            .mode(OpenMode::Open)
+            .open::<KeyRepo<String>, _>(&store_config)?,
+    );
+    Runtime::new()?.block_on(async {
+        let mut object = repo
+            .object(String::from("test"))
+            .await
+            .expect("The object was not found.");
+        assert_eq!(object.read_to_end().await?, expected_data);
+        Ok::<_, anyhow::Error>(())
+    })
+}
+
+#[test]
+fn rollback_discards_changes() -> anyhow::Result<()> {
+    let mut repo = create_repo(&MemoryConfig::new())?;
+
+    Runtime::new()?.block_on(async move {
+        repo.insert(String::from("test")).await;
+        repo.rollback().await?;
+        assert!(!repo.into_inner().await.contains("test"));
+        Ok::<_, anyhow::Error>(())
+    })
+}
+
+#[test]
+fn into_inner_waits_for_cancelled_operations() -> anyhow::Result<()> {
+    let mut repo = create_repo(&MemoryConfig::new())?;
+
+    Runtime::new()?.block_on(async move {
+        // Start an operation and then cancel it by dropping its future.
+        let mut operation = Box::pin(repo.run(|repo| {
+            thread::sleep(Duration::from_millis(100));
+            repo.insert(String::from("test"));
+        }));
+        assert!(futures::poll!(&mut operation).is_pending());
+        drop(operation);
+
+        assert!(repo.into_inner().await.contains("test"));
+        Ok::<_, anyhow::Error>(())
+    })
+}
+
+#[test]
+fn keys_are_streamed() -> anyhow::Result<()> {
+    let mut repo = create_repo(&MemoryConfig::new())?;
+
+    Runtime::new()?.block_on(async {
+        for i in 0..200 {
+            repo.insert(format!("test{}", i)).await;
+        }
+
+        let expected_keys = (0..200)
+            .map(|i| format!("test{}", i))
+            .collect::<HashSet<_>>();
+        let actual_keys = repo.keys().await.try_collect::<HashSet<_>>().await?;
+        assert_eq!(actual_keys, expected_keys);
+
+        // Dropping a stream early releases the repository.
+        let first_key = repo.keys().await.next().await;
+        assert!(matches!(first_key, Some(Ok(_))));
+        repo.commit().await?;
+
+        Ok::<_, anyhow::Error>(())
+    })
+}
+
+#[test]
+fn walk_is_streamed() -> anyhow::Result<()> {
+    let repo: FileRepo = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .open(&MemoryConfig::new())?;
+    repo.create_parents("root/dir/file", &Entry::file())?;
+    let mut repo = AsyncRepo::new(repo);
+
+    Runtime::new()?.block_on(async {
+        let actual_paths = repo
+            .walk(RelativePathBuf::from("root"))
+            .await?
+            .try_collect::<HashSet<_>>()
+            .await?;
+        let expected_paths = vec![
+            RelativePathBuf::from("root/dir"),
+            RelativePathBuf::from("root/dir/file"),
+        ]
+        .into_iter()
+        .collect::<HashSet<_>>();
+        assert_eq!(actual_paths, expected_paths);
+
+        assert!(matches!(
+            repo.walk(RelativePathBuf::from("nonexistent")).await,
+            Err(acid_store::Error::NotFound)
+        ));
+
+        Ok::<_, anyhow::Error>(())
+    })
+}
+
+#[test]
+fn versions_are_streamed() -> anyhow::Result<()> {
+    let repo: VersionRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .open(&MemoryConfig::new())?;
+    repo.insert(String::from("test"));
+    repo.create_version("test");
+    repo.create_version("test");
+    let mut repo = AsyncRepo::new(repo);
+
+    Runtime::new()?.block_on(async {
+        let version_ids = repo
+            .versions(String::from("test"))
+            .await
+            .expect("The key was not found.")
+            .map_ok(|version| version.id())
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(version_ids, vec![1, 2]);
+
+        assert!(repo.versions(String::from("nonexistent")).await.is_none());
+
+        Ok::<_, anyhow::Error>(())
+    })
+}
@@ -0,0 +1,97 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(all(feature = "benchmark", feature = "encryption", feature = "compression"))]
+
+use test_case::test_case;
+
+use acid_store::repo::{benchmark, Compression, RepoConfig};
+use acid_store::store::{DataStore, MemoryConfig, OpenStore};
+use common::{random_bytes, ENCODING_CONFIG, FIXED_CONFIG, VARIABLE_PACKING_CONFIG, ZPAQ_CONFIG};
+
+mod common;
+
+#[test_case(FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
+#[test_case(ENCODING_CONFIG.to_owned(); "with encryption and compression")]
+#[test_case(ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
+#[test_case(VARIABLE_PACKING_CONFIG.to_owned(); "with packing")]
+fn benchmark_measures_each_stage(config: RepoConfig) -> anyhow::Result<()> {
+    let sample = random_bytes(1024 * 64);
+    let report = benchmark(&config, &MemoryConfig::new(), &sample)?;
+
+    assert_eq!(report.sample_size, sample.len() as u64);
+    assert!(report.chunks > 0);
+    assert!(report.unique_chunks <= report.chunks);
+    assert_eq!(report.chunking.bytes, sample.len() as u64);
+    assert_eq!(report.hashing.count, report.chunks);
+    assert_eq!(report.hashing.bytes, sample.len() as u64);
+    assert_eq!(report.compression.count, report.unique_chunks);
+    assert_eq!(report.compression.bytes, report.unique_size);
+    assert_eq!(report.encryption.count, report.unique_chunks);
+    assert_eq!(report.store_write.count, report.unique_chunks);
+    assert_eq!(report.store_write.bytes, report.encoded_size);
+    assert_eq!(report.store_read.count, report.unique_chunks);
+    assert_eq!(report.store_read.bytes, report.encoded_size);
+    assert_eq!(
+        report.mean_chunk_size(),
+        Some(sample.len() as u64 / report.chunks)
+    );
+    Ok(())
+}
+
+#[test]
+fn benchmark_reports_deduplication() -> anyhow::Result<()> {
+    let sample = random_bytes(256).repeat(16);
+    let report = benchmark(&FIXED_CONFIG, &MemoryConfig::new(), &sample)?;
+
+    assert_eq!(report.chunks, 16);
+    assert_eq!(report.unique_chunks, 1);
+    assert_eq!(report.unique_size, 256);
+    assert_eq!(report.deduplication_ratio(), Some(1.0 / 16.0));
+    Ok(())
+}
+
+#[test]
+fn benchmark_reports_compression() -> anyhow::Result<()> {
+    let mut config = FIXED_CONFIG.to_owned();
+    config.compression = Compression::Lz4 { level: 1 };
+    let sample = vec![0u8; 256];
+    let report = benchmark(&config, &MemoryConfig::new(), &sample)?;
+
+    assert!(report.compression_ratio().unwrap() < 1.0);
+    Ok(())
+}
+
+#[test]
+fn benchmark_with_empty_sample() -> anyhow::Result<()> {
+    let report = benchmark(&FIXED_CONFIG, &MemoryConfig::new(), &[])?;
+
+    assert_eq!(report.chunks, 0);
+    assert_eq!(report.mean_chunk_size(), None);
+    assert_eq!(report.deduplication_ratio(), None);
+    assert_eq!(report.compression_ratio(), None);
+    Ok(())
+}
+
+#[test]
+fn benchmark_removes_blocks_from_store() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let sample = random_bytes(1024 * 16);
+    benchmark(&ENCODING_CONFIG, &store_config, &sample)?;
+
+    assert!(store_config.open()?.list_blocks()?.is_empty());
+    Ok(())
+}
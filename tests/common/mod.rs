@@ -91,6 +91,14 @@ pub static FIXED_PACKING_LARGE_CONFIG: Lazy<RepoConfig> = Lazy::new(|| {
     config
 });
 
+/// The repository config used for testing packing into variable-size blocks.
+pub static VARIABLE_PACKING_CONFIG: Lazy<RepoConfig> = Lazy::new(|| {
+    let mut config = FIXED_CONFIG.to_owned();
+    // Larger than the chunk size and not a multiple of it.
+    config.packing = Packing::Variable(1000);
+    config
+});
+
 /// The repository config used for testing packing with ZPAQ chunking.
 pub static ZPAQ_PACKING_CONFIG: Lazy<RepoConfig> = Lazy::new(|| {
     let mut config = ZPAQ_CONFIG.to_owned();
@@ -34,7 +34,7 @@ fn create_repo(config: &MemoryConfig) -> acid_store::Result<ContentRepo> {
 #[test]
 fn open_repository() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.commit()?;
     drop(repository);
     OpenOptions::new().open::<ContentRepo, _>(&config)?;
@@ -44,7 +44,7 @@ fn open_repository() -> anyhow::Result<()> {
 #[test]
 fn switching_instance_does_not_roll_back() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     let hash = repo.put(random_buffer().as_slice())?;
 
@@ -60,12 +60,12 @@ fn switching_instance_does_not_roll_back() -> anyhow::Result<()> {
 #[test]
 fn switching_instance_does_not_commit() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     let hash = repo.put(random_buffer().as_slice())?;
 
     let repo: ContentRepo = repo.switch_instance(Uuid::new_v4())?;
-    let mut repo: ContentRepo = repo.switch_instance(DEFAULT_INSTANCE)?;
+    let repo: ContentRepo = repo.switch_instance(DEFAULT_INSTANCE)?;
     repo.rollback()?;
 
     assert!(!repo.contains(&hash));
@@ -77,7 +77,7 @@ fn switching_instance_does_not_commit() -> anyhow::Result<()> {
 #[test]
 fn put_object() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     let data = random_buffer();
     let hash = repository.put(data.as_slice())?;
 
@@ -88,7 +88,7 @@ fn put_object() -> anyhow::Result<()> {
 #[test]
 fn remove_object() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     let data = random_buffer();
     let hash = repository.put(data.as_slice())?;
     repository.remove(&hash);
@@ -100,7 +100,7 @@ fn remove_object() -> anyhow::Result<()> {
 #[test]
 fn get_object() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     let expected_data = random_buffer();
     let hash = repository.put(expected_data.as_slice())?;
 
@@ -116,7 +116,7 @@ fn get_object() -> anyhow::Result<()> {
 #[test]
 fn list_objects() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     let hash1 = repository.put(random_buffer().as_slice())?;
     let hash2 = repository.put(random_buffer().as_slice())?;
     let expected_hashes = vec![hash1, hash2].into_iter().collect::<HashSet<_>>();
@@ -132,7 +132,7 @@ fn list_objects() -> anyhow::Result<()> {
 #[test]
 fn change_algorithm() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     let expected_data = b"Data";
     repository.put(&expected_data[..])?;
 
@@ -151,7 +151,7 @@ fn change_algorithm() -> anyhow::Result<()> {
 #[test]
 fn objects_removed_on_rollback() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     let hash = repository.put(random_buffer().as_slice())?;
 
     repository.rollback()?;
@@ -166,7 +166,7 @@ fn objects_removed_on_rollback() -> anyhow::Result<()> {
 #[test]
 fn clear_instance_removes_keys() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     let hash = repo.put(random_buffer().as_slice())?;
 
@@ -182,7 +182,7 @@ fn clear_instance_removes_keys() -> anyhow::Result<()> {
 #[test]
 fn rollback_after_clear_instance() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     let hash = repo.put(random_buffer().as_slice())?;
 
@@ -200,7 +200,7 @@ fn rollback_after_clear_instance() -> anyhow::Result<()> {
 #[test]
 fn verify_valid_repository_is_valid() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.put(random_buffer().as_slice())?;
 
     assert!(repository.verify()?.is_empty());
@@ -29,6 +29,8 @@ use uuid::Uuid;
 
 use acid_store::store::DataStore;
 #[cfg(feature = "store-directory")]
+use acid_store::store::{DirectoryConfig, OpenStore};
+#[cfg(feature = "store-directory")]
 use common::directory_store;
 #[cfg(feature = "store-rclone")]
 use common::rclone_store;
@@ -74,6 +76,30 @@ fn directory_read_block() -> anyhow::Result<()> {
     read_block(store)
 }
 
+#[test]
+#[cfg(feature = "store-directory")]
+fn directory_interrupted_writes_are_removed_on_open() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let mut store = directory_store(temp_dir.as_ref())?;
+    let id = Uuid::new_v4();
+    store.write_block(id, b"block")?;
+    drop(store);
+
+    // Simulate a write which was interrupted before the staging file was moved into place.
+    let staging_directory = temp_dir.as_ref().join("store").join("stage");
+    std::fs::write(staging_directory.join("interrupted"), b"partial")?;
+
+    let config = DirectoryConfig {
+        path: temp_dir.as_ref().join("store"),
+    };
+    let mut store = config.open()?;
+
+    assert_eq!(std::fs::read_dir(&staging_directory)?.count(), 0);
+    assert_eq!(store.list_blocks()?, vec![id]);
+    assert_eq!(store.read_block(id)?, Some(b"block".to_vec()));
+    Ok(())
+}
+
 #[test]
 #[cfg(feature = "store-sqlite")]
 fn sqlite_read_block() -> anyhow::Result<()> {
@@ -315,3 +341,125 @@ fn rclone_list_block() {
     let store = rclone_store().unwrap();
     list_blocks(store).unwrap();
 }
+
+fn batch_blocks(mut store: impl DataStore) -> anyhow::Result<()> {
+    let ids = (0..4).map(|_| Uuid::new_v4()).collect::<Vec<_>>();
+    let missing_id = Uuid::new_v4();
+    let expected_blocks = ids.iter().map(|_| random_buffer()).collect::<Vec<_>>();
+
+    // Batches of no blocks are allowed.
+    store.write_blocks(&[])?;
+    assert!(store.read_blocks(&[])?.is_empty());
+    store.remove_blocks(&[])?;
+
+    let blocks = ids
+        .iter()
+        .copied()
+        .zip(expected_blocks.iter().map(Vec::as_slice))
+        .collect::<Vec<_>>();
+    store.write_blocks(&blocks)?;
+
+    let mut read_ids = ids.clone();
+    read_ids.push(missing_id);
+    let mut expected_read = expected_blocks.into_iter().map(Some).collect::<Vec<_>>();
+    expected_read.push(None);
+    assert_eq!(store.read_blocks(&read_ids)?, expected_read);
+
+    store.remove_blocks(&ids[..2])?;
+    assert_eq!(store.read_block(ids[0])?, None);
+    assert_eq!(store.read_block(ids[1])?, None);
+    assert_contains_all(store.list_blocks()?, ids[2..].iter().copied());
+
+    Ok(())
+}
+
+#[test]
+fn memory_batch_blocks() -> anyhow::Result<()> {
+    batch_blocks(memory_store()?)
+}
+
+#[test]
+#[cfg(feature = "store-directory")]
+fn directory_batch_blocks() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let store = directory_store(temp_dir.as_ref())?;
+    batch_blocks(store)
+}
+
+#[test]
+#[cfg(feature = "store-sqlite")]
+fn sqlite_batch_blocks() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let store = sqlite_store(temp_dir.as_ref())?;
+    batch_blocks(store)
+}
+
+#[test]
+#[serial(redis)]
+#[cfg(feature = "store-redis")]
+fn redis_batch_blocks() {
+    let store = redis_store().unwrap();
+    batch_blocks(store).unwrap();
+}
+
+#[test]
+#[serial(s3)]
+#[cfg(feature = "store-s3")]
+fn s3_batch_blocks() {
+    let store = s3_store().unwrap();
+    batch_blocks(store).unwrap();
+}
+
+fn read_block_range(mut store: impl DataStore) -> anyhow::Result<()> {
+    let id = Uuid::new_v4();
+
+    assert_eq!(store.read_block_range(id, 0, 10)?, None);
+
+    let block = random_buffer();
+    store.write_block(id, block.as_slice())?;
+
+    assert_eq!(
+        store.read_block_range(id, 10, 20)?,
+        Some(block[10..30].to_vec())
+    );
+    assert_eq!(store.read_block_range(id, 10, 0)?, Some(Vec::new()));
+
+    // Ranges which extend past the end of the block are truncated.
+    let end = block.len() as u64;
+    assert_eq!(
+        store.read_block_range(id, end - 5, 10)?,
+        Some(block[block.len() - 5..].to_vec())
+    );
+    assert_eq!(store.read_block_range(id, end + 5, 10)?, Some(Vec::new()));
+
+    Ok(())
+}
+
+#[test]
+fn memory_read_block_range() -> anyhow::Result<()> {
+    read_block_range(memory_store()?)
+}
+
+#[test]
+#[cfg(feature = "store-directory")]
+fn directory_read_block_range() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let store = directory_store(temp_dir.as_ref())?;
+    read_block_range(store)
+}
+
+#[test]
+#[cfg(feature = "store-sqlite")]
+fn sqlite_read_block_range() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let store = sqlite_store(temp_dir.as_ref())?;
+    read_block_range(store)
+}
+
+#[test]
+#[serial(s3)]
+#[cfg(feature = "store-s3")]
+fn s3_read_block_range() {
+    let store = s3_store().unwrap();
+    read_block_range(store).unwrap();
+}
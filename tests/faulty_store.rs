@@ -0,0 +1,510 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(all(feature = "testing", feature = "encryption", feature = "compression"))]
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use acid_store::repo::key::KeyRepo;
+use acid_store::repo::{Commit, OpenMode, OpenOptions, RepoConfig, RetryPolicy};
+use acid_store::store::{
+    DataStore, Fault, FaultInjector, FaultRule, FaultyConfig, FaultyStore, MemoryConfig,
+    MemoryStore, OpenStore, StoreOperation,
+};
+use common::random_buffer;
+
+mod common;
+
+fn faulty_store() -> anyhow::Result<FaultyStore<impl DataStore>> {
+    Ok(FaultyStore::new(
+        MemoryConfig::new().open()?,
+        FaultInjector::new(),
+    ))
+}
+
+#[test]
+fn injected_errors_are_returned() -> anyhow::Result<()> {
+    let mut store = faulty_store()?;
+    let id = Uuid::new_v4();
+    store.write_block(id, b"data")?;
+
+    let mut rule = FaultRule::new(StoreOperation::Read, Fault::Error(io::ErrorKind::TimedOut));
+    rule.times = Some(1);
+    store.injector().inject(rule);
+
+    let error = store.read_block(id).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<io::Error>().map(|error| error.kind()),
+        Some(io::ErrorKind::TimedOut)
+    );
+    assert_eq!(store.read_block(id)?, Some(b"data".to_vec()));
+    Ok(())
+}
+
+#[test]
+fn faults_can_target_blocks() -> anyhow::Result<()> {
+    let mut store = faulty_store()?;
+    let faulty_id = Uuid::new_v4();
+    let healthy_id = Uuid::new_v4();
+
+    let mut rule = FaultRule::new(StoreOperation::Write, Fault::Error(io::ErrorKind::Other));
+    rule.block = Some(faulty_id);
+    rule.skip = 1;
+    store.injector().inject(rule);
+
+    store.write_block(faulty_id, b"first")?;
+    store.write_block(healthy_id, b"data")?;
+    assert!(store.write_block(faulty_id, b"second").is_err());
+    assert_eq!(store.read_block(faulty_id)?, Some(b"first".to_vec()));
+
+    store.injector().clear();
+    store.write_block(faulty_id, b"second")?;
+    assert_eq!(store.read_block(faulty_id)?, Some(b"second".to_vec()));
+    Ok(())
+}
+
+#[test]
+fn corrupt_reads_change_data() -> anyhow::Result<()> {
+    let mut store = faulty_store()?;
+    let id = Uuid::new_v4();
+    store.write_block(id, b"data")?;
+
+    store
+        .injector()
+        .inject(FaultRule::new(StoreOperation::Read, Fault::Corrupt));
+
+    assert_ne!(store.read_block(id)?, Some(b"data".to_vec()));
+    assert_eq!(store.into_inner().read_block(id)?, Some(b"data".to_vec()));
+    Ok(())
+}
+
+#[test]
+fn delays_are_injected() -> anyhow::Result<()> {
+    let mut store = faulty_store()?;
+    let delay = Duration::from_millis(50);
+    store
+        .injector()
+        .inject(FaultRule::new(StoreOperation::List, Fault::Delay(delay)));
+
+    let start = Instant::now();
+    store.list_blocks()?;
+    assert!(start.elapsed() >= delay);
+    Ok(())
+}
+
+#[test]
+fn transient_faults_are_retried_by_repository() -> anyhow::Result<()> {
+    let config = FaultyConfig {
+        config: MemoryConfig::new(),
+        injector: FaultInjector::new(),
+    };
+    let mut policy = RetryPolicy::new();
+    policy
+        .max_attempts(3)
+        .delay(Duration::from_millis(1), Duration::from_millis(1));
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .retry_policy(policy)
+        .open(&config)?;
+
+    let mut rule = FaultRule::new(StoreOperation::Write, Fault::Error(io::ErrorKind::TimedOut));
+    rule.times = Some(2);
+    config.injector.inject(rule);
+
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(b"data")?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+    Ok(())
+}
+
+#[test]
+fn corrupt_data_is_detected_by_repository() -> anyhow::Result<()> {
+    let config = FaultyConfig {
+        config: MemoryConfig::new(),
+        injector: FaultInjector::new(),
+    };
+    let expected_data = random_buffer();
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(common::ENCODING_CONFIG.to_owned())
+        .password(b"password")
+        .mode(OpenMode::CreateNew)
+        .open(&config)?;
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(expected_data.as_slice())?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+    drop(repo);
+
+    let repo: KeyRepo<String> = OpenOptions::new().password(b"password").open(&config)?;
+    config
+        .injector
+        .inject(FaultRule::new(StoreOperation::Read, Fault::Corrupt));
+
+    let mut actual_data = Vec::new();
+    assert!(repo
+        .object("test")
+        .unwrap()
+        .read_to_end(&mut actual_data)
+        .is_err());
+    Ok(())
+}
+
+/// Write an object to a new repository with the given `repo_config` and return the error from
+/// reading it back while every read from the data store is corrupted.
+fn corrupt_read_error(repo_config: RepoConfig) -> anyhow::Result<acid_store::Error> {
+    let config = FaultyConfig {
+        config: MemoryConfig::new(),
+        injector: FaultInjector::new(),
+    };
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(repo_config)
+        .password(b"password")
+        .mode(OpenMode::CreateNew)
+        .open(&config)?;
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(random_buffer().as_slice())?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+    drop(repo);
+
+    let repo: KeyRepo<String> = OpenOptions::new().password(b"password").open(&config)?;
+    config
+        .injector
+        .inject(FaultRule::new(StoreOperation::Read, Fault::Corrupt));
+
+    let mut actual_data = Vec::new();
+    let error = repo
+        .object("test")
+        .unwrap()
+        .read_to_end(&mut actual_data)
+        .unwrap_err();
+    Ok(acid_store::Error::from(error))
+}
+
+#[test]
+fn corrupt_blocks_fail_their_checksum() -> anyhow::Result<()> {
+    let error = corrupt_read_error(common::ENCODING_CONFIG.to_owned())?;
+    assert!(matches!(error, acid_store::Error::CorruptBlock(_)));
+    Ok(())
+}
+
+#[test]
+fn corrupt_blocks_without_checksums_fail_decryption() -> anyhow::Result<()> {
+    let mut repo_config = common::ENCODING_CONFIG.to_owned();
+    repo_config.block_checksums = false;
+    let error = corrupt_read_error(repo_config)?;
+    assert!(matches!(error, acid_store::Error::InvalidData));
+    Ok(())
+}
+
+/// Return the error from committing a new repository while every write to the data store fails
+/// with an I/O error of the given `kind`.
+fn commit_error(kind: io::ErrorKind) -> anyhow::Result<acid_store::Error> {
+    let config = FaultyConfig {
+        config: MemoryConfig::new(),
+        injector: FaultInjector::new(),
+    };
+    let mut policy = RetryPolicy::new();
+    policy
+        .max_attempts(2)
+        .delay(Duration::from_millis(1), Duration::from_millis(1));
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .retry_policy(policy)
+        .open(&config)?;
+    write_object(&repo, "test")?;
+    config
+        .injector
+        .inject(FaultRule::new(StoreOperation::Write, Fault::Error(kind)));
+
+    Ok(repo.commit().unwrap_err())
+}
+
+#[test]
+fn denied_writes_return_permission_denied() -> anyhow::Result<()> {
+    let error = commit_error(io::ErrorKind::PermissionDenied)?;
+    assert!(matches!(error, acid_store::Error::PermissionDenied(_)));
+    Ok(())
+}
+
+#[test]
+fn transient_faults_return_unavailable_after_retries() -> anyhow::Result<()> {
+    let error = commit_error(io::ErrorKind::ConnectionRefused)?;
+    assert!(matches!(error, acid_store::Error::Unavailable(_)));
+    Ok(())
+}
+
+#[test]
+fn unclassified_faults_return_store_error() -> anyhow::Result<()> {
+    let error = commit_error(io::ErrorKind::Other)?;
+    assert!(matches!(error, acid_store::Error::Store(_)));
+    Ok(())
+}
+
+#[test]
+fn store_errors_have_code_context_and_source() -> anyhow::Result<()> {
+    let error = commit_error(io::ErrorKind::PermissionDenied)?;
+    assert_eq!(error.code(), "permission_denied");
+
+    let context = error.context().expect("The error has no context.");
+    assert!(context.operation.starts_with("write_block"));
+    assert!(context.backend.contains("FaultyStore"));
+
+    let mut source = std::error::Error::source(&error);
+    let mut io_error_kind = None;
+    while let Some(cause) = source {
+        if let Some(io_error) = cause.downcast_ref::<io::Error>() {
+            io_error_kind = Some(io_error.kind());
+        }
+        source = cause.source();
+    }
+    assert_eq!(io_error_kind, Some(io::ErrorKind::PermissionDenied));
+    Ok(())
+}
+
+#[test]
+fn errors_outside_store_have_no_context() {
+    let error = acid_store::Error::NotFound;
+    assert_eq!(error.code(), "not_found");
+    assert!(error.context().is_none());
+}
+
+/// The ID of the block which stores the marker for a commit in progress.
+const COMMIT_MARKER_BLOCK_ID: &str = "a4e2d87c-1f35-4b90-8c6e-03b7f9d25a18";
+
+/// Return the contents of every block in the data store opened by `config`.
+fn snapshot_store(config: &MemoryConfig) -> anyhow::Result<HashMap<Uuid, Vec<u8>>> {
+    let mut store = config.open()?;
+    let mut blocks = HashMap::new();
+    for id in store.list_blocks()? {
+        blocks.insert(id, store.read_block(id)?.unwrap());
+    }
+    Ok(blocks)
+}
+
+/// Insert an object with the given `key` into `repo` and write random data to it.
+fn write_object(repo: &KeyRepo<String>, key: &str) -> anyhow::Result<()> {
+    let mut object = repo.insert(key.to_string());
+    object.write_all(random_buffer().as_slice())?;
+    object.commit()?;
+    Ok(())
+}
+
+/// Commit an object with the key "first" and then commit an object with the key "second" without
+/// finalizing the second commit.
+///
+/// This returns the contents of the data store from before the second commit.
+fn commit_without_finalizing(
+    config: &FaultyConfig<MemoryConfig>,
+) -> anyhow::Result<HashMap<Uuid, Vec<u8>>> {
+    let repo: KeyRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(config)?;
+    write_object(&repo, "first")?;
+    repo.commit()?;
+    write_object(&repo, "second")?;
+
+    let snapshot = snapshot_store(&config.config)?;
+    config.injector.inject(FaultRule::new(
+        StoreOperation::Remove,
+        Fault::Error(io::ErrorKind::PermissionDenied),
+    ));
+    repo.commit()?;
+    drop(repo);
+    config.injector.clear();
+
+    Ok(snapshot)
+}
+
+#[test]
+fn unfinalized_commit_is_completed_on_open() -> anyhow::Result<()> {
+    let config = FaultyConfig {
+        config: MemoryConfig::new(),
+        injector: FaultInjector::new(),
+    };
+    let marker_id = Uuid::parse_str(COMMIT_MARKER_BLOCK_ID)?;
+    commit_without_finalizing(&config)?;
+    assert!(config.config.open()?.read_block(marker_id)?.is_some());
+
+    let repo: KeyRepo<String> = OpenOptions::new().open(&config)?;
+
+    assert!(repo.contains("first"));
+    assert!(repo.contains("second"));
+    assert!(config.config.open()?.read_block(marker_id)?.is_none());
+    Ok(())
+}
+
+#[test]
+fn unpublished_commit_is_rolled_back_on_open() -> anyhow::Result<()> {
+    let config = FaultyConfig {
+        config: MemoryConfig::new(),
+        injector: FaultInjector::new(),
+    };
+    let marker_id = Uuid::parse_str(COMMIT_MARKER_BLOCK_ID)?;
+    let snapshot = commit_without_finalizing(&config)?;
+
+    // Restore the blocks from before the commit as if it was interrupted before the new metadata
+    // was written.
+    let mut store = config.config.open()?;
+    for (id, data) in &snapshot {
+        store.write_block(*id, data)?;
+    }
+    let blocks_before_open = store.list_blocks()?.len();
+
+    let repo: KeyRepo<String> = OpenOptions::new().open(&config)?;
+
+    assert!(repo.contains("first"));
+    assert!(!repo.contains("second"));
+    assert!(store.read_block(marker_id)?.is_none());
+    assert!(store.list_blocks()?.len() < blocks_before_open - 1);
+    Ok(())
+}
+
+#[test]
+fn incomplete_published_commit_is_rolled_back_on_open() -> anyhow::Result<()> {
+    let config = FaultyConfig {
+        config: MemoryConfig::new(),
+        injector: FaultInjector::new(),
+    };
+    let marker_id = Uuid::parse_str(COMMIT_MARKER_BLOCK_ID)?;
+    let snapshot = commit_without_finalizing(&config)?;
+
+    // Remove the blocks written by the commit as if the data store lost them after the new
+    // metadata was written.
+    let mut store = config.config.open()?;
+    for id in store.list_blocks()? {
+        if !snapshot.contains_key(&id) && id != marker_id {
+            store.remove_block(id)?;
+        }
+    }
+
+    let repo: KeyRepo<String> = OpenOptions::new().open(&config)?;
+
+    assert!(repo.contains("first"));
+    assert!(!repo.contains("second"));
+    assert!(store.read_block(marker_id)?.is_none());
+
+    // The restored commit can be built upon.
+    repo.insert(String::from("third"));
+    repo.commit()?;
+    Ok(())
+}
+
+/// A data store which panics the next time a block is written once `panic` is set.
+struct PanickingStore {
+    store: MemoryStore,
+    panic: Arc<AtomicBool>,
+}
+
+impl DataStore for PanickingStore {
+    fn write_block(&mut self, id: Uuid, data: &[u8]) -> anyhow::Result<()> {
+        if self.panic.swap(false, Ordering::SeqCst) {
+            panic!("The data store panicked.");
+        }
+        self.store.write_block(id, data)
+    }
+
+    fn read_block(&mut self, id: Uuid) -> anyhow::Result<Option<Vec<u8>>> {
+        self.store.read_block(id)
+    }
+
+    fn remove_block(&mut self, id: Uuid) -> anyhow::Result<()> {
+        self.store.remove_block(id)
+    }
+
+    fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>> {
+        self.store.list_blocks()
+    }
+}
+
+struct PanickingConfig {
+    config: MemoryConfig,
+    panic: Arc<AtomicBool>,
+}
+
+impl OpenStore for PanickingConfig {
+    type Store = PanickingStore;
+
+    fn open(&self) -> acid_store::Result<Self::Store> {
+        Ok(PanickingStore {
+            store: self.config.open()?,
+            panic: Arc::clone(&self.panic),
+        })
+    }
+}
+
+#[test]
+fn repository_is_usable_after_panic_in_data_store() -> anyhow::Result<()> {
+    let config = PanickingConfig {
+        config: MemoryConfig::new(),
+        panic: Arc::new(AtomicBool::new(false)),
+    };
+    let repo: KeyRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    write_object(&repo, "first")?;
+    repo.commit()?;
+
+    config.panic.store(true, Ordering::SeqCst);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| write_object(&repo, "second")));
+    assert!(result.is_err());
+
+    repo.rollback()?;
+    write_object(&repo, "third")?;
+    repo.commit()?;
+    drop(repo);
+
+    let repo: KeyRepo<String> = OpenOptions::new().open(&config)?;
+    assert!(repo.contains("first"));
+    assert!(!repo.contains("second"));
+    assert!(repo.contains("third"));
+    assert!(repo.verify()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn committing_after_panic_in_data_store_errs_until_rollback() -> anyhow::Result<()> {
+    let config = PanickingConfig {
+        config: MemoryConfig::new(),
+        panic: Arc::new(AtomicBool::new(false)),
+    };
+    let repo: KeyRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    write_object(&repo, "first")?;
+    repo.commit()?;
+
+    config.panic.store(true, Ordering::SeqCst);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| write_object(&repo, "second")));
+    assert!(result.is_err());
+
+    assert!(matches!(repo.commit(), Err(acid_store::Error::Poisoned)));
+
+    repo.rollback()?;
+    repo.commit()?;
+    assert!(repo.contains("first"));
+    assert!(!repo.contains("second"));
+    Ok(())
+}
@@ -23,18 +23,24 @@ use std::io::{Read, Write};
 #[cfg(all(target_os = "linux", feature = "file-metadata"))]
 use exacl::{AclEntry, AclEntryKind, AclOption, Flag, Perm};
 use maplit::hashmap;
-use relative_path::RelativePathBuf;
+use relative_path::{RelativePath, RelativePathBuf};
 use tempfile::tempdir;
 
-use acid_store::repo::file::{Entry, FileRepo, NoMetadata, NoSpecialType};
-use acid_store::repo::{Commit, OpenMode, OpenOptions, SwitchInstance, DEFAULT_INSTANCE};
+use acid_store::repo::file::{
+    ArchiveOptions, Entry, FileRepo, NoMetadata, NoSpecialType, PathFilter, PathNormalization,
+    SyncDirection, SyncOptions, TreeProgress, WalkOptions,
+};
+use acid_store::repo::{
+    CancellationToken, Commit, OpenMode, OpenOptions, Operation, SwitchInstance, DEFAULT_INSTANCE,
+};
 use acid_store::store::MemoryConfig;
 use acid_store::uuid::Uuid;
 use common::{assert_contains_all, random_buffer};
 #[cfg(all(unix, feature = "file-metadata"))]
 use {
     acid_store::repo::file::{
-        AccessMode, AccessQualifier, Acl, CommonMetadata, FileType, UnixMetadata, UnixSpecialType,
+        AccessMode, AccessQualifier, Acl, CommonMetadata, ExtractOptions, FileType, Ownership,
+        UnixMetadata, UnixSpecialType,
     },
     nix::sys::stat::{Mode, SFlag},
     nix::unistd::mkfifo,
@@ -53,7 +59,7 @@ fn create_repo(config: &MemoryConfig) -> acid_store::Result<FileRepo> {
 #[test]
 fn open_repository() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
     repo.commit()?;
     drop(repo);
     OpenOptions::new().open::<FileRepo, _>(&config)?;
@@ -63,7 +69,7 @@ fn open_repository() -> anyhow::Result<()> {
 #[test]
 fn switching_instance_does_not_roll_back() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     repo.create("file", &Entry::file())?;
     let mut object = repo.open("file")?;
@@ -83,7 +89,7 @@ fn switching_instance_does_not_roll_back() -> anyhow::Result<()> {
 #[test]
 fn switching_instance_does_not_commit() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     repo.create("file", &Entry::file())?;
     let mut object = repo.open("file")?;
@@ -92,7 +98,7 @@ fn switching_instance_does_not_commit() -> anyhow::Result<()> {
     drop(object);
 
     let repo: FileRepo = repo.switch_instance(Uuid::new_v4())?;
-    let mut repo: FileRepo = repo.switch_instance(DEFAULT_INSTANCE)?;
+    let repo: FileRepo = repo.switch_instance(DEFAULT_INSTANCE)?;
     repo.rollback()?;
 
     assert!(!repo.exists("file"));
@@ -120,7 +126,7 @@ fn empty_path_does_not_exist() -> anyhow::Result<()> {
 #[test]
 fn creating_existing_file_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.create("home", &Entry::directory())?;
     let result = repository.create("home", &Entry::directory());
@@ -135,7 +141,7 @@ fn creating_existing_file_errs() -> anyhow::Result<()> {
 #[test]
 fn creating_file_without_parent_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     // Creating a directory without a parent fails.
     let result = repository.create("home/lostatc", &Entry::directory());
@@ -158,7 +164,7 @@ fn creating_file_without_parent_errs() -> anyhow::Result<()> {
 #[test]
 fn creating_empty_path_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     assert!(matches!(
         repository.create("", &Entry::file()),
@@ -175,7 +181,7 @@ fn creating_empty_path_errs() -> anyhow::Result<()> {
 #[test]
 fn create_parents() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.create_parents("home/lostatc/test", &Entry::file())?;
 
@@ -189,7 +195,7 @@ fn create_parents() -> anyhow::Result<()> {
 #[test]
 fn create_parent_of_top_level_file() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.create_parents("home", &Entry::directory())?;
 
@@ -200,7 +206,7 @@ fn create_parent_of_top_level_file() -> anyhow::Result<()> {
 #[test]
 fn removing_nonexistent_path_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     let result = repository.remove("home");
 
     assert!(matches!(result, Err(acid_store::Error::NotFound)));
@@ -210,7 +216,7 @@ fn removing_nonexistent_path_errs() -> anyhow::Result<()> {
 #[test]
 fn removing_non_empty_directory_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create_parents("home/lostatc", &Entry::directory())?;
     let result = repository.remove("home");
 
@@ -221,7 +227,7 @@ fn removing_non_empty_directory_errs() -> anyhow::Result<()> {
 #[test]
 fn removing_emtpy_path_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     assert!(matches!(
         repository.remove(""),
@@ -238,7 +244,7 @@ fn removing_emtpy_path_errs() -> anyhow::Result<()> {
 #[test]
 fn remove_file() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create("home", &Entry::directory())?;
     repository.remove("home")?;
 
@@ -249,7 +255,7 @@ fn remove_file() -> anyhow::Result<()> {
 #[test]
 fn remove_tree() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create_parents("home/lostatc/test", &Entry::file())?;
     repository.remove_tree("home")?;
 
@@ -262,7 +268,7 @@ fn remove_tree() -> anyhow::Result<()> {
 #[test]
 fn remove_tree_without_descendants() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create("home", &Entry::directory())?;
     repository.remove_tree("home")?;
 
@@ -270,6 +276,34 @@ fn remove_tree_without_descendants() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn prune_empty_dirs_keeps_directories_with_files() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.create_parents("root/empty/nested", &Entry::directory())?;
+    repository.create_parents("root/full/nested", &Entry::directory())?;
+    repository.create("root/full/file", &Entry::file())?;
+    repository.create("root/sibling", &Entry::directory())?;
+
+    let removed = repository.prune_empty_dirs("root")?;
+
+    assert_contains_all(
+        removed,
+        vec![
+            RelativePathBuf::from("root/empty"),
+            RelativePathBuf::from("root/empty/nested"),
+            RelativePathBuf::from("root/full/nested"),
+            RelativePathBuf::from("root/sibling"),
+        ],
+    );
+    assert!(repository.exists("root"));
+    assert!(repository.exists("root/full/file"));
+    assert!(!repository.exists("root/empty"));
+    assert!(!repository.exists("root/full/nested"));
+    assert!(!repository.exists("root/sibling"));
+    Ok(())
+}
+
 #[test]
 fn getting_empty_path_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
@@ -286,7 +320,7 @@ fn getting_empty_path_errs() -> anyhow::Result<()> {
 #[test]
 fn setting_metadata_on_nonexistent_file_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     let result = repository.set_metadata("file", None);
 
     assert!(matches!(result, Err(acid_store::Error::NotFound)));
@@ -296,7 +330,7 @@ fn setting_metadata_on_nonexistent_file_errs() -> anyhow::Result<()> {
 #[test]
 fn setting_metadata_on_empty_path_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     assert!(matches!(
         repository.set_metadata("", None),
@@ -310,12 +344,13 @@ fn setting_metadata_on_empty_path_errs() -> anyhow::Result<()> {
 #[cfg(feature = "file-metadata")]
 fn set_metadata() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository: FileRepo<NoSpecialType, CommonMetadata> =
+    let repository: FileRepo<NoSpecialType, CommonMetadata> =
         OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
 
     let expected_metadata = CommonMetadata {
         modified: SystemTime::UNIX_EPOCH,
         accessed: SystemTime::UNIX_EPOCH,
+        created: None,
     };
     repository.create("file", &Entry::file())?;
     repository.set_metadata("file", Some(expected_metadata.clone()))?;
@@ -325,10 +360,41 @@ fn set_metadata() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+#[cfg(feature = "file-metadata")]
+fn update_metadata() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository: FileRepo<NoSpecialType, CommonMetadata> =
+        OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+
+    let metadata = CommonMetadata {
+        modified: SystemTime::UNIX_EPOCH,
+        accessed: SystemTime::UNIX_EPOCH,
+        created: None,
+    };
+    repository.create("file", &Entry::file())?;
+    repository.create("no-metadata", &Entry::file())?;
+    repository.set_metadata("file", Some(metadata.clone()))?;
+
+    let modified = SystemTime::now();
+    assert!(repository.update_metadata("file", |metadata| metadata.modified = modified)?);
+    assert!(!repository.update_metadata("no-metadata", |_| panic!())?);
+
+    assert_eq!(
+        repository.entry("file")?.metadata,
+        Some(CommonMetadata {
+            modified,
+            ..metadata
+        })
+    );
+    assert_eq!(repository.entry("no-metadata")?.metadata, None);
+    Ok(())
+}
+
 #[test]
 fn open_file() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create("file", &Entry::file())?;
     let mut object = repository.open("file")?;
 
@@ -344,6 +410,68 @@ fn open_file() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn file_size_is_recorded() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+    File::create(&source_path)?.write_all(b"archived data")?;
+
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.archive(&source_path, "archived")?;
+    repository.create("created", &Entry::file())?;
+    repository.create("directory", &Entry::directory())?;
+
+    assert_eq!(repository.file_size("archived")?, 13);
+    assert_eq!(repository.file_size("created")?, 0);
+    assert!(matches!(
+        repository.file_size("directory"),
+        Err(acid_store::Error::NotFile)
+    ));
+
+    let mut object = repository.open("created")?;
+    object.write_all(b"data")?;
+    object.commit()?;
+    drop(object);
+
+    assert_eq!(repository.update_size("created")?, 4);
+    assert_eq!(repository.file_size("created")?, 4);
+    Ok(())
+}
+
+#[test]
+fn tree_stats_counts_deduplicated_data() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let data = random_buffer();
+
+    repository.create_parents("directory/first", &Entry::file())?;
+    repository.create("directory/second", &Entry::file())?;
+    repository.create("outside", &Entry::file())?;
+    for path in &["directory/first", "directory/second", "outside"] {
+        let mut object = repository.open(path)?;
+        object.write_all(&data)?;
+        object.commit()?;
+    }
+
+    let stats = repository.tree_stats("directory")?;
+    assert_eq!(stats.files, 2);
+    assert_eq!(stats.directories, 1);
+    assert_eq!(stats.special, 0);
+    assert_eq!(stats.size, data.len() as u64 * 2);
+    assert_eq!(stats.stored_size, data.len() as u64);
+
+    let stats = repository.tree_stats("")?;
+    assert_eq!(stats.files, 3);
+    assert_eq!(stats.stored_size, data.len() as u64);
+
+    assert!(matches!(
+        repository.tree_stats("nonexistent"),
+        Err(acid_store::Error::NotFound)
+    ));
+    Ok(())
+}
+
 #[test]
 fn opening_empty_path_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
@@ -360,7 +488,7 @@ fn opening_empty_path_errs() -> anyhow::Result<()> {
 #[test]
 fn copied_file_has_same_contents() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     let expected_data = b"expected";
     let mut actual_data = Vec::new();
@@ -385,7 +513,7 @@ fn copied_file_has_same_contents() -> anyhow::Result<()> {
 #[test]
 fn copy_file_with_invalid_destination() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create("source", &Entry::file())?;
 
     assert!(matches!(
@@ -403,10 +531,51 @@ fn copy_file_with_invalid_destination() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn copy_range_between_files() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let data = random_buffer();
+    repository.create("source", &Entry::file())?;
+    repository.create("dest", &Entry::file())?;
+    repository.write_at("source", 0, &data)?;
+
+    // Copying the whole file shares its chunks.
+    let len = data.len() as u64;
+    assert_eq!(repository.copy_range("source", 0, "dest", 0, len * 2)?, len);
+
+    // Copying a range which isn't chunk-aligned past the end of the file leaves a hole.
+    assert_eq!(
+        repository.copy_range("source", 1, "dest", len + 10, len - 2)?,
+        len - 2
+    );
+
+    let mut expected_data = data.clone();
+    expected_data.resize(data.len() + 10, 0);
+    expected_data.extend_from_slice(&data[1..data.len() - 1]);
+
+    let mut actual_data = Vec::new();
+    repository.open("dest")?.read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+    assert_eq!(repository.file_size("dest")?, expected_data.len() as u64);
+
+    // Copying an overlapping range within the same file copies the original bytes.
+    repository.copy_range("source", 0, "source", 1, len - 1)?;
+
+    let mut expected_data = data.clone();
+    expected_data[1..].copy_from_slice(&data[..data.len() - 1]);
+
+    let mut actual_data = Vec::new();
+    repository.open("source")?.read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+
+    Ok(())
+}
+
 #[test]
 fn copy_tree() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create_parents("source/file1", &Entry::file())?;
     repository.create_parents("source/directory/file2", &Entry::file())?;
 
@@ -418,10 +587,274 @@ fn copy_tree() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn copied_tree_is_independent_of_source() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.create_parents("source/file", &Entry::file())?;
+    let mut object = repository.open("source/file")?;
+    object.write_all(b"original")?;
+    object.commit()?;
+    drop(object);
+
+    repository.copy_tree("source", "source/snapshot")?;
+
+    let mut object = repository.open("source/file")?;
+    object.write_all(b"modified")?;
+    object.commit()?;
+    drop(object);
+
+    let mut actual_data = Vec::new();
+    repository
+        .open("source/snapshot/file")?
+        .read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, b"original");
+    assert!(!repository.exists("source/snapshot/snapshot"));
+
+    Ok(())
+}
+
+#[test]
+fn rename_tree() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.create_parents("source/directory/file", &Entry::file())?;
+    let mut object = repository.open("source/directory/file")?;
+    object.write_all(b"data")?;
+    object.commit()?;
+    drop(object);
+
+    repository.create("dest", &Entry::directory())?;
+    repository.rename("source/directory", "dest/renamed")?;
+
+    assert!(!repository.exists("source/directory"));
+    assert!(repository.is_directory("dest/renamed"));
+    let mut actual_data = Vec::new();
+    repository
+        .open("dest/renamed/file")?
+        .read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, b"data");
+
+    Ok(())
+}
+
+#[test]
+fn renaming_into_descendant_errs() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.create_parents("source/directory", &Entry::directory())?;
+
+    assert!(matches!(
+        repository.rename("source", "source/directory/dest"),
+        Err(acid_store::Error::InvalidPath)
+    ));
+    assert!(matches!(
+        repository.rename("source", "source/directory"),
+        Err(acid_store::Error::AlreadyExists)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn entry_attributes() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.create("file", &Entry::file())?;
+
+    assert_eq!(repository.attribute::<String>("file", "status")?, None);
+
+    repository.set_attribute("file", "status", "reviewed")?;
+    repository.set_attribute("file", "checksum", &[1u8, 2, 3])?;
+    repository.copy("file", "copy")?;
+    repository.set_attribute("copy", "status", "pending")?;
+
+    assert_eq!(
+        repository.attribute::<String>("file", "status")?,
+        Some(String::from("reviewed"))
+    );
+    assert_eq!(
+        repository.attribute::<Vec<u8>>("file", "checksum")?,
+        Some(vec![1, 2, 3])
+    );
+    assert_eq!(
+        repository.attribute::<String>("copy", "status")?,
+        Some(String::from("pending"))
+    );
+
+    assert!(repository.remove_attribute("file", "status")?);
+    assert!(!repository.remove_attribute("file", "status")?);
+    assert_eq!(
+        repository.attribute_names("file")?,
+        vec![String::from("checksum")]
+    );
+    assert!(matches!(
+        repository.attribute::<String>("missing", "status"),
+        Err(acid_store::Error::NotFound)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn file_handles_survive_rename_and_remove() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.create("source", &Entry::file())?;
+    let handle = repository.open_with_handle("source")?;
+
+    repository.rename("source", "dest")?;
+    let expected_data = random_buffer();
+    let mut object = repository.handle_object(&handle)?;
+    object.write_all(&expected_data)?;
+    object.commit()?;
+    drop(object);
+
+    assert_eq!(
+        repository.handle_path(&handle),
+        Some(RelativePathBuf::from("dest"))
+    );
+    let mut actual_data = Vec::new();
+    repository.open("dest")?.read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+
+    repository.remove("dest")?;
+    assert!(!repository.exists("dest"));
+    assert_eq!(repository.handle_path(&handle), None);
+    assert!(repository.handle_entry(&handle)?.is_file());
+
+    let mut actual_data = Vec::new();
+    repository
+        .handle_object(&handle)?
+        .read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+
+    repository.close_handle(handle)?;
+
+    Ok(())
+}
+
+#[test]
+fn archiving_beyond_quota_errs() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+    File::create(&source_path)?.write_all(b"12345678")?;
+
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.create_parents("limited/nested", &Entry::directory())?;
+    repository.set_quota("limited", Some(10))?;
+
+    repository.archive(&source_path, "limited/nested/first")?;
+    assert_eq!(repository.quota("limited")?, Some(10));
+    assert_eq!(repository.quota_usage("limited")?, 8);
+    assert!(matches!(
+        repository.archive(&source_path, "limited/nested/second"),
+        Err(acid_store::Error::QuotaExceeded)
+    ));
+    assert!(!repository.exists("limited/nested/second"));
+
+    repository.set_quota("limited", None)?;
+    repository.archive(&source_path, "limited/nested/second")?;
+    assert_eq!(repository.quota_usage("limited")?, 16);
+
+    Ok(())
+}
+
+#[test]
+fn writing_copying_and_renaming_beyond_quota_errs() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.create_parents("limited/nested", &Entry::directory())?;
+    repository.create_parents("outside/tree", &Entry::directory())?;
+    repository.create("outside/tree/file", &Entry::file())?;
+    repository.write_at("outside/tree/file", 0, b"12345678")?;
+    repository.create("limited/nested/file", &Entry::file())?;
+    repository.set_quota("limited", Some(10))?;
+
+    repository.write_at("limited/nested/file", 0, b"123456")?;
+    assert_eq!(repository.quota_usage("limited")?, 6);
+    assert!(matches!(
+        repository.write_at("limited/nested/file", 4, b"1234567"),
+        Err(acid_store::Error::QuotaExceeded)
+    ));
+    repository.write_at("limited/nested/file", 8, b"12")?;
+    assert_eq!(repository.file_size("limited/nested/file")?, 10);
+    assert_eq!(repository.quota_usage("limited")?, 10);
+
+    assert!(matches!(
+        repository.copy("outside/tree/file", "limited/copy"),
+        Err(acid_store::Error::QuotaExceeded)
+    ));
+    assert!(matches!(
+        repository.copy_tree("outside/tree", "limited/tree"),
+        Err(acid_store::Error::QuotaExceeded)
+    ));
+    assert!(matches!(
+        repository.rename("outside/tree", "limited/tree"),
+        Err(acid_store::Error::QuotaExceeded)
+    ));
+    assert!(!repository.exists("limited/copy"));
+    assert!(!repository.exists("limited/tree"));
+
+    // Moving entries within the directory does not change its usage.
+    repository.rename("limited/nested", "limited/renamed")?;
+    assert_eq!(repository.quota_usage("limited")?, 10);
+
+    repository.set_quota("limited", None)?;
+    repository.copy_tree("outside/tree", "limited/tree")?;
+    assert_eq!(repository.quota_usage("limited")?, 18);
+
+    Ok(())
+}
+
+#[test]
+fn normalized_paths_resolve_to_same_entry() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.set_path_normalization(Some(PathNormalization::Nfc))?;
+
+    // The same file name in NFD form, as macOS stores it, and in NFC form.
+    repository.create("cafe\u{301}", &Entry::directory())?;
+    assert!(repository.is_directory("caf\u{e9}"));
+    assert!(matches!(
+        repository.create("caf\u{e9}", &Entry::directory()),
+        Err(acid_store::Error::AlreadyExists)
+    ));
+
+    repository.commit()?;
+    drop(repository);
+    let repository = OpenOptions::new().open::<FileRepo, _>(&config)?;
+
+    assert_eq!(
+        repository.path_normalization(),
+        Some(PathNormalization::Nfc)
+    );
+    assert!(repository.is_directory("cafe\u{301}"));
+
+    Ok(())
+}
+
+#[test]
+fn setting_path_normalization_with_conflicts_errs() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.create("cafe\u{301}", &Entry::file())?;
+    repository.create("caf\u{e9}", &Entry::file())?;
+
+    assert!(matches!(
+        repository.set_path_normalization(Some(PathNormalization::Nfc)),
+        Err(acid_store::Error::AlreadyExists)
+    ));
+    assert_eq!(repository.path_normalization(), None);
+
+    Ok(())
+}
+
 #[test]
 fn copy_subdirectory_tree() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create_parents("root/source/file1", &Entry::file())?;
 
     repository.copy_tree("root/source", "root/dest")?;
@@ -434,7 +867,7 @@ fn copy_subdirectory_tree() -> anyhow::Result<()> {
 #[test]
 fn copy_tree_which_is_a_file() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create("source", &Entry::file())?;
 
     repository.copy_tree("source", "dest")?;
@@ -447,7 +880,7 @@ fn copy_tree_which_is_a_file() -> anyhow::Result<()> {
 #[test]
 fn copying_with_empty_path_as_source_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     assert!(matches!(
         repository.copy("", "test"),
@@ -464,7 +897,7 @@ fn copying_with_empty_path_as_source_errs() -> anyhow::Result<()> {
 #[test]
 fn copying_with_empty_path_as_dest_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.create("test", &Entry::file())?;
 
@@ -483,7 +916,7 @@ fn copying_with_empty_path_as_dest_errs() -> anyhow::Result<()> {
 #[test]
 fn list_children() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create_parents("root/child1", &Entry::file())?;
     repository.create_parents("root/child2/descendant", &Entry::file())?;
 
@@ -511,7 +944,7 @@ fn list_children_of_nonexistent_directory() -> anyhow::Result<()> {
 #[test]
 fn list_children_of_a_file() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create("file", &Entry::file())?;
 
     let result = repository.list("file");
@@ -524,7 +957,7 @@ fn list_children_of_a_file() -> anyhow::Result<()> {
 #[test]
 fn list_children_of_empty_path() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create("file1", &Entry::file())?;
     repository.create("file2", &Entry::file())?;
 
@@ -542,7 +975,7 @@ fn list_children_of_empty_path() -> anyhow::Result<()> {
 #[test]
 fn walk_descendants() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create_parents("root/child1", &Entry::file())?;
     repository.create_parents("root/child2/descendant", &Entry::file())?;
 
@@ -558,20 +991,60 @@ fn walk_descendants() -> anyhow::Result<()> {
 }
 
 #[test]
-fn walk_descendants_of_nonexistent_directory() -> anyhow::Result<()> {
+fn walk_with_options() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
     let repository = create_repo(&config)?;
-    let result = repository.walk("nonexistent");
+    repository.create_parents("a/b/c/file", &Entry::file())?;
+    repository.create_parents("skipped/file", &Entry::file())?;
+    repository.create("a/file", &Entry::file())?;
 
-    assert!(matches!(result, Err(acid_store::Error::NotFound)));
-
-    Ok(())
-}
+    let mut options = WalkOptions::new();
+    options.max_depth(2).directories(false);
+    let actual = repository.walk_with("", &options)?.collect::<Vec<_>>();
+    let expected = vec![
+        RelativePathBuf::from("a/file"),
+        RelativePathBuf::from("skipped/file"),
+    ];
+    assert_eq!(actual.len(), 2);
+    assert_contains_all(actual, expected);
 
-#[test]
+    let mut walk = repository.walk_with("", &WalkOptions::new())?;
+    let mut actual = Vec::new();
+    while let Some(path) = walk.next() {
+        if path == RelativePath::new("skipped") {
+            walk.skip_descendants();
+        }
+        actual.push(path);
+    }
+    let expected = vec![
+        RelativePathBuf::from("a"),
+        RelativePathBuf::from("a/b"),
+        RelativePathBuf::from("a/b/c"),
+        RelativePathBuf::from("a/b/c/file"),
+        RelativePathBuf::from("a/file"),
+        RelativePathBuf::from("skipped"),
+    ];
+    assert_eq!(actual.len(), 6);
+    assert_contains_all(actual, expected);
+
+    Ok(())
+}
+
+#[test]
+fn walk_descendants_of_nonexistent_directory() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let result = repository.walk("nonexistent");
+
+    assert!(matches!(result, Err(acid_store::Error::NotFound)));
+
+    Ok(())
+}
+
+#[test]
 fn walk_descendants_of_a_file() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create("file", &Entry::file())?;
 
     let result = repository.walk("file");
@@ -584,7 +1057,7 @@ fn walk_descendants_of_a_file() -> anyhow::Result<()> {
 #[test]
 fn walk_descendants_of_empty_path() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create_parents("directory/file", &Entry::file())?;
 
     let actual = repository.walk("")?;
@@ -607,7 +1080,7 @@ fn archive_file() -> anyhow::Result<()> {
     source_file.flush()?;
 
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.archive(&source_path, "dest")?;
 
     let mut object = repository.open("dest")?;
@@ -630,7 +1103,7 @@ fn archive_unix_special_files() -> anyhow::Result<()> {
     symlink("/dev/null", &symlink_path)?;
 
     let config = MemoryConfig::new();
-    let mut repository: FileRepo<_, NoMetadata> =
+    let repository: FileRepo<_, NoMetadata> =
         OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
 
     repository.create("dest", &Entry::directory())?;
@@ -664,7 +1137,7 @@ fn archiving_file_with_existing_dest_errs() -> anyhow::Result<()> {
     File::create(&source_path)?;
 
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create("dest", &Entry::file())?;
     let result = repository.archive(&source_path, "dest");
 
@@ -683,7 +1156,7 @@ fn archive_tree() -> anyhow::Result<()> {
     File::create(&source_path.join("directory/file2"))?;
 
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.archive_tree(&source_path, "dest")?;
 
     assert!(repository.entry("dest")?.is_directory());
@@ -693,6 +1166,499 @@ fn archive_tree() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn archive_tree_with_multiple_threads() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+
+    create_dir(&source_path)?;
+    create_dir(source_path.join("directory"))?;
+    let mut expected_data = HashMap::new();
+    for name in &["file1", "file2", "directory/file3", "directory/file4"] {
+        let data = random_buffer();
+        File::create(source_path.join(name))?.write_all(&data)?;
+        expected_data.insert(format!("dest/{}", name), data);
+    }
+
+    let config = MemoryConfig::new();
+    let repository: FileRepo = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .threads(4)
+        .open(&config)?;
+    repository.archive_tree(&source_path, "dest")?;
+
+    assert!(repository.entry("dest/directory")?.is_directory());
+    for (path, data) in expected_data {
+        let mut actual_data = Vec::new();
+        repository.open(&path)?.read_to_end(&mut actual_data)?;
+        assert_eq!(actual_data, data);
+    }
+    Ok(())
+}
+
+#[test]
+fn archive_trees_with_multiple_threads() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let mut trees = Vec::new();
+    let mut expected_data = HashMap::new();
+
+    for tree in &["source1", "source2", "source3"] {
+        let source_path = temp_dir.as_ref().join(tree);
+        create_dir(&source_path)?;
+        for name in &["file1", "file2"] {
+            let data = random_buffer();
+            File::create(source_path.join(name))?.write_all(&data)?;
+            expected_data.insert(format!("dest/{}/{}", tree, name), data);
+        }
+        trees.push((source_path, format!("dest/{}", tree)));
+    }
+
+    let config = MemoryConfig::new();
+    let repository: FileRepo = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .threads(4)
+        .open(&config)?;
+    repository.create("dest", &Entry::directory())?;
+    let summary = repository.archive_trees(trees, &ArchiveOptions::new(), &mut ())?;
+
+    assert_eq!(summary.entries, 9);
+    for (path, data) in expected_data {
+        let mut actual_data = Vec::new();
+        repository.open(&path)?.read_to_end(&mut actual_data)?;
+        assert_eq!(actual_data, data);
+    }
+    Ok(())
+}
+
+#[test]
+fn archive_trees_with_overlapping_destinations_errs() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+
+    let trees = vec![
+        (temp_dir.as_ref().to_owned(), "dest"),
+        (temp_dir.as_ref().to_owned(), "dest/child"),
+    ];
+    assert!(matches!(
+        repository.archive_trees(trees, &ArchiveOptions::new(), &mut ()),
+        Err(acid_store::Error::InvalidPath)
+    ));
+    assert!(!repository.exists("dest"));
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "file-tar")]
+fn export_and_import_tar() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let mut expected_data = HashMap::new();
+
+    repository.create_parents("source/directory", &Entry::directory())?;
+    for name in &["file", "directory/file"] {
+        let data = random_buffer();
+        repository.create(format!("source/{}", name), &Entry::file())?;
+        let mut object = repository.open(format!("source/{}", name))?;
+        object.write_all(&data)?;
+        object.commit()?;
+        expected_data.insert(format!("dest/{}", name), data);
+    }
+
+    let mut archive = Vec::new();
+    let export_summary = repository.export_tar("source", &mut archive)?;
+    let import_summary = repository.import_tar(archive.as_slice(), "dest")?;
+
+    assert_eq!(export_summary.entries, 3);
+    assert_eq!(import_summary.entries, 3);
+    assert!(import_summary.failed.is_empty());
+    assert!(repository.is_directory("dest/directory"));
+    for (path, data) in expected_data {
+        let mut actual_data = Vec::new();
+        repository.open(&path)?.read_to_end(&mut actual_data)?;
+        assert_eq!(actual_data, data);
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "file-tar")]
+fn importing_tar_with_parent_paths_records_failure() -> anyhow::Result<()> {
+    let mut archive = Vec::new();
+    {
+        let mut builder = tar::Builder::new(&mut archive);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Regular);
+        builder.append_data(&mut header, "safe", std::io::empty())?;
+
+        // The `tar` crate refuses to write paths with `..` components, so write it manually.
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..9].copy_from_slice(b"../escape");
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        builder.append(&header, std::io::empty())?;
+        builder.finish()?;
+    }
+
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let summary = repository.import_tar(archive.as_slice(), "dest")?;
+
+    assert!(repository.is_file("dest/safe"));
+    assert!(!repository.exists("escape"));
+    assert_eq!(summary.failed.len(), 1);
+    assert!(matches!(
+        summary.failed[0].1,
+        acid_store::Error::InvalidPath
+    ));
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "file-mime")]
+fn mime_type_is_detected_from_contents() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.create("image.txt", &Entry::file())?;
+    repository.create("text.png", &Entry::file())?;
+    repository.create("empty", &Entry::file())?;
+    repository.create("directory", &Entry::directory())?;
+
+    let mut object = repository.open("image.txt")?;
+    object.write_all(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR")?;
+    object.commit()?;
+    drop(object);
+
+    let mut object = repository.open("text.png")?;
+    object.write_all(b"Plain text")?;
+    object.commit()?;
+    drop(object);
+
+    assert_eq!(repository.mime_type("image.txt")?, Some("image/png"));
+    assert_eq!(repository.mime_type("text.png")?, None);
+    assert_eq!(repository.mime_type("empty")?, None);
+    assert!(matches!(
+        repository.mime_type("directory"),
+        Err(acid_store::Error::NotFile)
+    ));
+    Ok(())
+}
+
+#[test]
+fn sync_tree_archives_changed_files() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+
+    create_dir(&source_path)?;
+    File::create(source_path.join("unchanged"))?.write_all(b"unchanged")?;
+    File::create(source_path.join("changed"))?.write_all(b"old")?;
+    File::create(source_path.join("removed"))?.write_all(b"removed")?;
+
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let mut options = SyncOptions::new(SyncDirection::Archive);
+    options.delete(true);
+    let summary = repository.sync_tree(&source_path, "dest", &options)?;
+    assert_eq!(summary.copied.len(), 4);
+
+    File::create(source_path.join("changed"))?.write_all(b"new contents")?;
+    File::create(source_path.join("added"))?.write_all(b"added")?;
+    std::fs::remove_file(source_path.join("removed"))?;
+    let summary = repository.sync_tree(&source_path, "dest", &options)?;
+
+    let mut copied = summary.copied.clone();
+    copied.sort();
+    assert_eq!(
+        copied,
+        vec![
+            RelativePathBuf::from("dest/added"),
+            RelativePathBuf::from("dest/changed")
+        ]
+    );
+    assert_eq!(summary.removed, vec![RelativePathBuf::from("dest/removed")]);
+    assert_eq!(summary.unchanged, 2);
+
+    let mut actual_data = Vec::new();
+    repository
+        .open("dest/changed")?
+        .read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, b"new contents");
+    Ok(())
+}
+
+#[test]
+fn incremental_archive_tree_skips_unchanged_files() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+
+    create_dir(&source_path)?;
+    File::create(source_path.join("unchanged"))?.write_all(b"unchanged")?;
+    File::create(source_path.join("changed"))?.write_all(b"old")?;
+
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let mut options = ArchiveOptions::new();
+    options.incremental(true);
+    let summary = repository.archive_tree_with_options(&source_path, "dest", &options, &mut ())?;
+    assert_eq!(summary.entries, 3);
+    assert_eq!(summary.unchanged, 0);
+
+    File::create(source_path.join("changed"))?.write_all(b"new contents")?;
+    File::create(source_path.join("added"))?.write_all(b"added")?;
+    let summary = repository.archive_tree_with_options(&source_path, "dest", &options, &mut ())?;
+    assert_eq!(summary.entries, 2);
+    assert_eq!(summary.unchanged, 2);
+    assert_eq!(summary.bytes, 17);
+
+    let mut actual_data = Vec::new();
+    repository
+        .open("dest/changed")?
+        .read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, b"new contents");
+    assert!(repository.is_file("dest/added"));
+    Ok(())
+}
+
+#[test]
+fn sync_tree_extracts_changed_files() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let dest_path = temp_dir.as_ref().join("dest");
+
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.create("source", &Entry::directory())?;
+    repository.create("source/file", &Entry::file())?;
+    let mut object = repository.open("source/file")?;
+    object.write_all(b"old")?;
+    object.commit()?;
+    drop(object);
+
+    let mut options = SyncOptions::new(SyncDirection::Extract);
+    options.delete(true);
+    repository.sync_tree(&dest_path, "source", &options)?;
+
+    File::create(dest_path.join("extra"))?;
+    let mut object = repository.open("source/file")?;
+    object.write_all(b"new contents")?;
+    object.commit()?;
+    drop(object);
+    let summary = repository.sync_tree(&dest_path, "source", &options)?;
+
+    assert_eq!(summary.copied, vec![RelativePathBuf::from("source/file")]);
+    assert_eq!(summary.removed, vec![RelativePathBuf::from("source/extra")]);
+    assert!(!dest_path.join("extra").exists());
+    assert_eq!(std::fs::read(dest_path.join("file"))?, b"new contents");
+    Ok(())
+}
+
+#[test]
+fn archive_tree_filtered_skips_excluded_files() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+
+    create_dir(&source_path)?;
+    create_dir(source_path.join("node_modules"))?;
+    create_dir(source_path.join("src"))?;
+    File::create(source_path.join("node_modules/package.json"))?;
+    File::create(source_path.join("src/main.rs"))?;
+    File::create(source_path.join("src/debug.log"))?;
+    File::create(source_path.join("README.md"))?;
+
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let mut filter = PathFilter::new();
+    filter.exclude("node_modules/")?.exclude("*.log")?;
+    repository.archive_tree_filtered(&source_path, "dest", &filter)?;
+
+    let expected = vec![
+        RelativePathBuf::from("dest/README.md"),
+        RelativePathBuf::from("dest/src"),
+        RelativePathBuf::from("dest/src/main.rs"),
+    ];
+    let mut actual = repository.walk("dest")?.collect::<Vec<_>>();
+    actual.sort();
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[test]
+fn walk_filtered_applies_include_patterns() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.create_parents("root/src/lib.rs", &Entry::file())?;
+    repository.create_parents("root/target/lib.rs", &Entry::file())?;
+    repository.create("root/notes.txt", &Entry::file())?;
+
+    let mut filter = PathFilter::new();
+    filter.include("*.rs")?.exclude("/target")?;
+
+    let mut actual = repository
+        .walk_filtered("root", &filter)?
+        .collect::<Vec<_>>();
+    actual.sort();
+    assert_eq!(
+        actual,
+        vec![
+            RelativePathBuf::from("root/src"),
+            RelativePathBuf::from("root/src/lib.rs")
+        ]
+    );
+    assert!(matches!(
+        PathFilter::new().include("[invalid"),
+        Err(acid_store::Error::InvalidPattern)
+    ));
+    Ok(())
+}
+
+#[test]
+fn cancelled_archive_tree_errs() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+
+    create_dir(&source_path)?;
+    File::create(source_path.join("file1"))?;
+    File::create(source_path.join("file2"))?;
+    File::create(source_path.join("file3"))?;
+
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let token = CancellationToken::new();
+    let token_clone = token.clone();
+    repository.set_cancellation_token(token);
+    repository.set_progress_handler(move |progress| {
+        assert_eq!(progress.operation, Operation::ArchiveTree);
+        if progress.completed == 2 {
+            token_clone.cancel();
+        }
+    });
+
+    assert!(matches!(
+        repository.archive_tree(&source_path, "dest"),
+        Err(acid_store::Error::Cancelled)
+    ));
+    assert!(repository.exists("dest"));
+    assert_eq!(repository.walk("dest")?.count(), 1);
+    Ok(())
+}
+
+#[cfg(all(unix, feature = "file-metadata"))]
+#[test]
+fn archive_tree_with_summarizes_skipped_and_failed_files() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+
+    create_dir(&source_path)?;
+    File::create(source_path.join("file"))?.write_all(b"data")?;
+    mkfifo(&source_path.join("fifo"), Mode::S_IRWXU)?;
+    symlink("nonexistent", source_path.join("broken"))?;
+
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let summary = repository.archive_tree_with(&source_path, "dest", &mut ())?;
+
+    assert_eq!(summary.entries, 2);
+    assert_eq!(summary.bytes, 4);
+    assert_eq!(summary.skipped, vec![RelativePathBuf::from("dest/fifo")]);
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, RelativePathBuf::from("dest/broken"));
+    assert!(repository.is_file("dest/file"));
+    Ok(())
+}
+
+#[cfg(all(unix, feature = "file-metadata"))]
+#[test]
+fn archive_tree_follows_links_and_skips_loops() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+    let target_path = temp_dir.as_ref().join("target");
+
+    create_dir(&source_path)?;
+    create_dir(&target_path)?;
+    File::create(target_path.join("file"))?.write_all(b"data")?;
+    symlink(&target_path, source_path.join("link"))?;
+    symlink(&source_path, source_path.join("loop"))?;
+
+    let config = MemoryConfig::new();
+    let repository: FileRepo<UnixSpecialType, NoMetadata> =
+        OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    let mut options = ArchiveOptions::new();
+    options.follow_links(true);
+    let summary = repository.archive_tree_with_options(&source_path, "dest", &options, &mut ())?;
+
+    assert!(repository.is_directory("dest/link"));
+    let mut actual_data = Vec::new();
+    repository
+        .open("dest/link/file")?
+        .read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, b"data");
+    assert_eq!(summary.skipped, vec![RelativePathBuf::from("dest/loop")]);
+    assert!(!repository.exists("dest/loop"));
+    Ok(())
+}
+
+#[cfg(all(unix, feature = "file-metadata"))]
+#[test]
+fn hard_links_are_preserved() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+    let dest_path = temp_dir.as_ref().join("dest");
+
+    create_dir(&source_path)?;
+    File::create(source_path.join("original"))?.write_all(b"data")?;
+    std::fs::hard_link(source_path.join("original"), source_path.join("link"))?;
+
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let summary = repository.archive_tree_with(&source_path, "source", &mut ())?;
+    assert_eq!(summary.entries, 3);
+    assert_eq!(summary.bytes, 4);
+
+    repository.extract_tree("source", &dest_path)?;
+
+    let original_metadata = dest_path.join("original").metadata()?;
+    let link_metadata = dest_path.join("link").metadata()?;
+    assert_eq!(original_metadata.ino(), link_metadata.ino());
+    assert_eq!(std::fs::read(dest_path.join("link"))?, b"data");
+    Ok(())
+}
+
+#[cfg(all(target_os = "linux", feature = "file-metadata"))]
+#[test]
+fn sparse_files_are_preserved() -> anyhow::Result<()> {
+    use std::io::{Seek, SeekFrom};
+
+    const HOLE_SIZE: u64 = 16 * 1024 * 1024;
+
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+    let dest_path = temp_dir.as_ref().join("dest");
+
+    create_dir(&source_path)?;
+    let mut file = File::create(source_path.join("sparse"))?;
+    file.write_all(b"start")?;
+    file.seek(SeekFrom::Start(HOLE_SIZE))?;
+    file.write_all(b"middle")?;
+    file.set_len(HOLE_SIZE * 2)?;
+    drop(file);
+
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let summary = repository.archive_tree_with(&source_path, "source", &mut ())?;
+    assert!(summary.bytes < HOLE_SIZE);
+    assert_eq!(repository.open("source/sparse")?.size()?, HOLE_SIZE * 2);
+
+    repository.extract_tree("source", &dest_path)?;
+
+    let source_contents = std::fs::read(source_path.join("sparse"))?;
+    let dest_contents = std::fs::read(dest_path.join("sparse"))?;
+    let dest_metadata = dest_path.join("sparse").metadata()?;
+    assert_eq!(source_contents, dest_contents);
+    assert!(dest_metadata.blocks() * 512 < HOLE_SIZE);
+    Ok(())
+}
+
 #[test]
 fn archiving_to_empty_path_errs() -> anyhow::Result<()> {
     let temp_dir = tempdir()?;
@@ -700,7 +1666,7 @@ fn archiving_to_empty_path_errs() -> anyhow::Result<()> {
     File::create(&source_path)?;
 
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     assert!(matches!(
         repository.archive(&source_path, ""),
@@ -714,13 +1680,65 @@ fn archiving_to_empty_path_errs() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[derive(Default)]
+struct RecordingProgress {
+    started: Vec<RelativePathBuf>,
+    finished: Vec<RelativePathBuf>,
+    bytes: HashMap<RelativePathBuf, u64>,
+}
+
+impl TreeProgress for RecordingProgress {
+    fn entry_started(&mut self, path: &RelativePath) {
+        self.started.push(path.to_owned());
+    }
+
+    fn bytes_copied(&mut self, path: &RelativePath, bytes: u64) {
+        self.bytes.insert(path.to_owned(), bytes);
+    }
+
+    fn entry_finished(&mut self, path: &RelativePath) {
+        self.finished.push(path.to_owned());
+    }
+}
+
+#[test]
+fn extract_tree_with_reports_progress() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let dest_path = temp_dir.as_ref().join("dest");
+
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let data = random_buffer();
+    repository.create("source", &Entry::directory())?;
+    repository.create("source/file", &Entry::file())?;
+    let mut object = repository.open("source/file")?;
+    object.write_all(data.as_slice())?;
+    object.commit()?;
+    drop(object);
+
+    let mut progress = RecordingProgress::default();
+    let summary = repository.extract_tree_with("source", &dest_path, &mut progress)?;
+
+    assert_eq!(summary.entries, 2);
+    assert_eq!(summary.bytes, data.len() as u64);
+    assert!(summary.skipped.is_empty());
+    assert!(summary.failed.is_empty());
+    assert_eq!(progress.started, progress.finished);
+    assert_eq!(progress.started.len(), 2);
+    assert_eq!(
+        progress.bytes.get(RelativePath::new("source/file")),
+        Some(&(data.len() as u64))
+    );
+    Ok(())
+}
+
 #[test]
 fn extract_file() -> anyhow::Result<()> {
     let temp_dir = tempdir()?;
     let dest_path = temp_dir.as_ref().join("dest");
 
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.create("source", &Entry::file())?;
     let mut object = repository.open("source")?;
@@ -746,7 +1764,7 @@ fn extract_unix_special_files() -> anyhow::Result<()> {
     let device_path = temp_dir.as_ref().join("device");
 
     let config = MemoryConfig::new();
-    let mut repository: FileRepo<_, NoMetadata> =
+    let repository: FileRepo<_, NoMetadata> =
         OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
 
     repository.create("fifo", &Entry::special(UnixSpecialType::NamedPipe))?;
@@ -782,6 +1800,34 @@ fn extract_unix_special_files() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+#[cfg(all(unix, feature = "file-metadata"))]
+fn archive_and_extract_unix_socket() -> anyhow::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+    let dest_path = temp_dir.as_ref().join("dest");
+    let listener = UnixListener::bind(&source_path)?;
+
+    let config = MemoryConfig::new();
+    let repository: FileRepo<_, NoMetadata> =
+        OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+
+    repository.archive(&source_path, "socket")?;
+    drop(listener);
+    repository.extract("socket", &dest_path)?;
+
+    assert_eq!(
+        repository.entry("socket")?.file_type,
+        UnixSpecialType::Socket.into()
+    );
+    assert!(dest_path.symlink_metadata()?.file_type().is_socket());
+
+    Ok(())
+}
+
 #[test]
 fn extracting_file_with_existing_dest_errs() -> anyhow::Result<()> {
     let temp_dir = tempdir()?;
@@ -789,7 +1835,7 @@ fn extracting_file_with_existing_dest_errs() -> anyhow::Result<()> {
     File::create(&dest_path)?;
 
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.create("source", &Entry::file())?;
     let result = repository.extract("source", &dest_path);
@@ -804,7 +1850,7 @@ fn extract_tree() -> anyhow::Result<()> {
     let dest_path = temp_dir.as_ref().join("dest");
 
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.create("source", &Entry::directory())?;
     repository.create("source/file1", &Entry::file())?;
@@ -846,7 +1892,7 @@ fn write_unix_metadata() -> anyhow::Result<()> {
     let dest_path = temp_dir.as_ref().join("dest");
 
     let config = MemoryConfig::new();
-    let mut repository: FileRepo<NoSpecialType, UnixMetadata> =
+    let repository: FileRepo<NoSpecialType, UnixMetadata> =
         OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
 
     // This does not test extended attributes because user extended attributes are not supported
@@ -864,6 +1910,7 @@ fn write_unix_metadata() -> anyhow::Result<()> {
             access: hashmap! { AccessQualifier::User(65533) => AccessMode::READ | AccessMode::WRITE | AccessMode::EXECUTE },
             default: HashMap::new(),
         },
+        created: None,
     };
     let entry = Entry {
         file_type: FileType::File,
@@ -953,7 +2000,7 @@ fn read_unix_metadata() -> anyhow::Result<()> {
     }
 
     let config = MemoryConfig::new();
-    let mut repository: FileRepo<NoSpecialType, UnixMetadata> =
+    let repository: FileRepo<NoSpecialType, UnixMetadata> =
         OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
 
     repository.archive(&source_path, "dest")?;
@@ -983,6 +2030,87 @@ fn read_unix_metadata() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+#[cfg(all(target_os = "macos", feature = "file-metadata"))]
+fn macos_metadata_is_preserved() -> anyhow::Result<()> {
+    use acid_store::repo::file::MacosMetadata;
+    use std::os::macos::fs::MetadataExt as _;
+
+    // The `UF_HIDDEN` file flag.
+    const HIDDEN_FLAG: u32 = 0x8000;
+
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+    let dest_path = temp_dir.as_ref().join("dest");
+    File::create(&source_path)?;
+
+    let config = MemoryConfig::new();
+    let repository: FileRepo<NoSpecialType, MacosMetadata> =
+        OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    repository.archive(&source_path, "source")?;
+
+    let mut metadata = repository.entry("source")?.metadata.unwrap();
+    metadata.created = SystemTime::UNIX_EPOCH;
+    metadata.flags |= HIDDEN_FLAG;
+    metadata
+        .attributes
+        .insert("com.apple.ResourceFork".to_string(), b"resource".to_vec());
+    repository.set_metadata("source", Some(metadata))?;
+
+    repository.extract("source", &dest_path)?;
+
+    let dest_metadata = dest_path.metadata()?;
+    assert_ne!(dest_metadata.st_flags() & HIDDEN_FLAG, 0);
+    assert_eq!(dest_metadata.created()?, SystemTime::UNIX_EPOCH);
+    assert_eq!(
+        xattr::get(&dest_path, "com.apple.ResourceFork")?,
+        Some(b"resource".to_vec())
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(windows, feature = "file-metadata"))]
+fn windows_metadata_is_preserved() -> anyhow::Result<()> {
+    use acid_store::repo::file::{FileAttributes, WindowsMetadata};
+    use std::os::windows::fs::MetadataExt;
+
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+    let dest_path = temp_dir.as_ref().join("dest");
+    std::fs::write(&source_path, b"contents")?;
+    std::fs::write(temp_dir.as_ref().join("source:stream"), b"stream contents")?;
+
+    let config = MemoryConfig::new();
+    let repository: FileRepo<NoSpecialType, WindowsMetadata> =
+        OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    repository.archive(&source_path, "source")?;
+
+    let mut metadata = repository.entry("source")?.metadata.unwrap();
+    assert_eq!(
+        metadata.streams.get("stream").map(Vec::as_slice),
+        Some(&b"stream contents"[..])
+    );
+    metadata.attributes |= FileAttributes::HIDDEN;
+    repository.set_metadata("source", Some(metadata))?;
+
+    repository.extract("source", &dest_path)?;
+
+    let dest_metadata = dest_path.metadata()?;
+    assert_ne!(
+        dest_metadata.file_attributes() & FileAttributes::HIDDEN.bits(),
+        0
+    );
+    assert_eq!(
+        std::fs::read(temp_dir.as_ref().join("dest:stream"))?,
+        b"stream contents"
+    );
+    assert_eq!(std::fs::read(&dest_path)?, b"contents");
+
+    Ok(())
+}
+
 #[test]
 #[cfg(all(unix, feature = "file-metadata"))]
 fn write_common_metadata() -> anyhow::Result<()> {
@@ -990,12 +2118,13 @@ fn write_common_metadata() -> anyhow::Result<()> {
     let dest_path = temp_dir.as_ref().join("dest");
 
     let config = MemoryConfig::new();
-    let mut repository: FileRepo<NoSpecialType, CommonMetadata> =
+    let repository: FileRepo<NoSpecialType, CommonMetadata> =
         OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
 
     let entry_metadata = CommonMetadata {
         modified: SystemTime::UNIX_EPOCH,
         accessed: SystemTime::UNIX_EPOCH,
+        created: None,
     };
     let entry = Entry {
         file_type: FileType::File,
@@ -1012,6 +2141,89 @@ fn write_common_metadata() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+#[cfg(all(unix, feature = "file-metadata"))]
+fn timestamps_keep_nanosecond_precision() -> anyhow::Result<()> {
+    use filetime::{set_file_times, FileTime};
+
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+    let dest_path = temp_dir.as_ref().join("dest");
+    File::create(&source_path)?;
+    let file_time = FileTime::from_unix_time(1_000_000_000, 123_456_789);
+    set_file_times(&source_path, file_time, file_time)?;
+
+    let config = MemoryConfig::new();
+    let repository: FileRepo<NoSpecialType, UnixMetadata> =
+        OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    repository.archive(&source_path, "file")?;
+    repository.extract("file", &dest_path)?;
+
+    let source_metadata = source_path.metadata()?;
+    let dest_metadata = dest_path.metadata()?;
+    let entry_metadata = repository.entry("file")?.metadata.unwrap();
+
+    assert_eq!(source_metadata.mtime_nsec(), 123_456_789);
+    assert_eq!(entry_metadata.modified, source_metadata.modified()?);
+    assert_eq!(entry_metadata.created, source_metadata.created().ok());
+    assert_eq!(dest_metadata.modified()?, source_metadata.modified()?);
+    assert_eq!(dest_metadata.accessed()?, entry_metadata.accessed);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(unix, feature = "file-metadata"))]
+fn extract_tree_remaps_ownership() -> anyhow::Result<()> {
+    let temp_dir = tempdir()?;
+    let source_path = temp_dir.as_ref().join("source");
+    File::create(&source_path)?;
+    let source_metadata = source_path.metadata()?;
+
+    let config = MemoryConfig::new();
+    let repository: FileRepo<NoSpecialType, UnixMetadata> =
+        OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    repository.archive(&source_path, "file")?;
+
+    // Store an owner which is offset from the current user, as if it was archived in a container.
+    let mut entry_metadata = repository.entry("file")?.metadata.unwrap();
+    entry_metadata.user += 100_000;
+    entry_metadata.group += 100_000;
+    repository.set_metadata("file", Some(entry_metadata))?;
+
+    let mut options = ExtractOptions::new();
+    options.ownership(Ownership::Offset {
+        user: -100_000,
+        group: -100_000,
+    });
+    let offset_path = temp_dir.as_ref().join("offset");
+    repository.extract_tree_with_options("file", &offset_path, &options, &mut ())?;
+    let offset_metadata = offset_path.metadata()?;
+    assert_eq!(offset_metadata.uid(), source_metadata.uid());
+    assert_eq!(offset_metadata.gid(), source_metadata.gid());
+
+    options.ownership(Ownership::Drop);
+    let drop_path = temp_dir.as_ref().join("drop");
+    repository.extract_tree_with_options("file", &drop_path, &options, &mut ())?;
+    assert_eq!(drop_path.metadata()?.uid(), source_metadata.uid());
+
+    options.ownership(Ownership::Offset {
+        user: -i64::from(u32::MAX),
+        group: 0,
+    });
+    assert!(matches!(
+        repository.extract_tree_with_options(
+            "file",
+            temp_dir.as_ref().join("invalid"),
+            &options,
+            &mut ()
+        ),
+        Err(acid_store::Error::Io(_))
+    ));
+
+    Ok(())
+}
+
 #[test]
 #[cfg(all(unix, feature = "file-metadata"))]
 fn read_common_metadata() -> anyhow::Result<()> {
@@ -1020,7 +2232,7 @@ fn read_common_metadata() -> anyhow::Result<()> {
     File::create(&source_path)?;
 
     let config = MemoryConfig::new();
-    let mut repository: FileRepo<NoSpecialType, CommonMetadata> =
+    let repository: FileRepo<NoSpecialType, CommonMetadata> =
         OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
 
     repository.archive(&source_path, "dest")?;
@@ -1036,7 +2248,7 @@ fn read_common_metadata() -> anyhow::Result<()> {
 #[test]
 fn entries_removed_on_rollback() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create("file", &Entry::file())?;
 
     let mut object = repository.open("file")?;
@@ -1054,7 +2266,7 @@ fn entries_removed_on_rollback() -> anyhow::Result<()> {
 #[test]
 fn clear_instance_removes_paths() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     repo.create("test", &Entry::file())?;
     let mut object = repo.open("test")?;
@@ -1076,7 +2288,7 @@ fn clear_instance_removes_paths() -> anyhow::Result<()> {
 #[test]
 fn rollback_after_clear_instance() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     repo.create("test", &Entry::file())?;
     let mut object = repo.open("test")?;
@@ -1097,9 +2309,60 @@ fn rollback_after_clear_instance() -> anyhow::Result<()> {
 #[test]
 fn verify_valid_repository_is_valid() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.create_parents("home/lostatc/file", &Entry::file())?;
 
     assert!(repository.verify()?.is_empty());
     Ok(())
 }
+
+#[test]
+fn convert_file_repo_into_key_repo() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repo = create_repo(&config)?;
+    let expected_data = random_buffer();
+
+    repo.create_parents("dir/file", &Entry::file())?;
+    let mut object = repo.open("dir/file")?;
+    object.write_all(expected_data.as_slice())?;
+    object.commit()?;
+    drop(object);
+
+    let repo = repo.into_key_repo()?;
+    let mut object = repo.object("dir/file").unwrap();
+    let mut actual_data = Vec::new();
+    object.read_to_end(&mut actual_data)?;
+
+    assert!(!repo.contains("dir"));
+    assert_eq!(actual_data, expected_data);
+    Ok(())
+}
+
+#[test]
+fn changed_paths_are_reported_until_commit() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repo = create_repo(&config)?;
+
+    repo.create("file", &Entry::file())?;
+    repo.create("removed", &Entry::file())?;
+    repo.commit()?;
+
+    assert!(!repo.has_changes()?);
+
+    let mut object = repo.open("file")?;
+    object.write_all(random_buffer().as_slice())?;
+    object.commit()?;
+    drop(object);
+    repo.remove("removed")?;
+    repo.create("directory", &Entry::directory())?;
+
+    let changed_paths = repo.changed_paths()?;
+    assert_eq!(changed_paths.len(), 3);
+    assert!(changed_paths.contains(&RelativePathBuf::from("file")));
+    assert!(changed_paths.contains(&RelativePathBuf::from("removed")));
+    assert!(changed_paths.contains(&RelativePathBuf::from("directory")));
+
+    repo.commit()?;
+    assert!(!repo.has_changes()?);
+    Ok(())
+}
@@ -17,17 +17,20 @@
 #![cfg(feature = "encryption")]
 
 use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
 
 use test_case::test_case;
 use uuid::Uuid;
 
 use acid_store::repo::key::KeyRepo;
 use acid_store::repo::{
-    peek_info, Commit, Encryption, OpenMode, OpenOptions, RepoConfig, RestoreSavepoint,
-    SwitchInstance,
+    peek_info, AuditEvent, CancellationToken, Commit, Encryption, OpenMode, OpenOptions, Operation,
+    Packing, RepoConfig, RestoreSavepoint, SwitchInstance, TransactionEvent,
 };
 use acid_store::store::{DataStore, MemoryConfig, OpenStore};
-use common::{assert_contains_all, random_buffer};
+use common::{assert_contains_all, random_buffer, random_bytes, truncate_store};
 
 mod common;
 
@@ -56,7 +59,7 @@ fn open_repo(
 #[test]
 fn opening_with_wrong_key_type_errs() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -74,7 +77,7 @@ fn opening_with_wrong_key_type_errs() -> anyhow::Result<()> {
 #[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
 fn contains_key(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     repo.insert(String::from("test"));
 
     assert!(repo.contains("test"));
@@ -85,7 +88,7 @@ fn contains_key(repo_config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
 fn remove_key(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     repo.insert(String::from("test"));
 
     assert!(repo.remove("test"));
@@ -98,7 +101,7 @@ fn remove_key(repo_config: RepoConfig) -> anyhow::Result<()> {
 #[test]
 fn list_keys() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(RepoConfig::default(), &store_config)?;
+    let repo = create_repo(RepoConfig::default(), &store_config)?;
 
     repo.insert(String::from("test1"));
     repo.insert(String::from("test2"));
@@ -110,7 +113,7 @@ fn list_keys() -> anyhow::Result<()> {
         String::from("test3"),
     ];
 
-    let actual = repo.keys().cloned().collect::<Vec<_>>();
+    let actual = repo.keys().collect::<Vec<_>>();
 
     assert_contains_all(actual, expected);
 
@@ -120,7 +123,7 @@ fn list_keys() -> anyhow::Result<()> {
 #[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
 fn can_not_get_object_from_removed_key(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     repo.insert(String::from("test"));
     repo.remove("test");
 
@@ -132,7 +135,7 @@ fn can_not_get_object_from_removed_key(repo_config: RepoConfig) -> anyhow::Resul
 #[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
 fn removing_copy_does_not_affect_original(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     repo.insert(String::from("original"));
     repo.copy("original", String::from("copy"));
 
@@ -147,10 +150,11 @@ fn removing_copy_does_not_affect_original(repo_config: RepoConfig) -> anyhow::Re
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn copy_has_same_contents(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     let mut object = repo.insert(String::from("original"));
 
     object.write_all(b"Data")?;
@@ -172,7 +176,7 @@ fn copy_has_same_contents(repo_config: RepoConfig) -> anyhow::Result<()> {
 #[test]
 fn copied_object_must_exist() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(RepoConfig::default(), &store_config)?;
+    let repo = create_repo(RepoConfig::default(), &store_config)?;
 
     assert!(!repo.copy("nonexistent", String::from("copy")));
 
@@ -184,10 +188,11 @@ fn copied_object_must_exist() -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn copying_overwrites_destination(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
 
     let expected_data = random_buffer();
 
@@ -213,15 +218,63 @@ fn copying_overwrites_destination(repo_config: RepoConfig) -> anyhow::Result<()>
     Ok(())
 }
 
+#[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
+#[test_case(common::ENCODING_CONFIG.to_owned(); "with encryption and compression")]
+#[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
+fn modifying_identical_object_does_not_affect_others(
+    repo_config: RepoConfig,
+) -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(repo_config.clone(), &store_config)?;
+
+    let original_data = random_buffer();
+    let new_data = random_buffer();
+
+    // Write two objects with identical contents and copy one of them.
+    for key in &["first", "second"] {
+        let mut object = repo.insert(key.to_string());
+        object.write_all(original_data.as_slice())?;
+        object.commit()?;
+    }
+    assert!(repo.copy("first", String::from("copy")));
+    repo.commit()?;
+    drop(repo);
+
+    // Objects with identical contents share their extents once the repository is reopened.
+    let repo = open_repo(repo_config.clone(), &store_config)?;
+    let mut object = repo.object("second").unwrap();
+    object.write_all(new_data.as_slice())?;
+    object.commit()?;
+    object.set_len(new_data.len() as u64)?;
+    drop(object);
+    repo.commit()?;
+    drop(repo);
+
+    let repo = open_repo(repo_config, &store_config)?;
+    for (key, expected_data) in &[
+        ("first", &original_data),
+        ("second", &new_data),
+        ("copy", &original_data),
+    ] {
+        let mut object = repo.object(*key).unwrap();
+        let mut actual_data = Vec::new();
+        object.read_to_end(&mut actual_data)?;
+        assert_eq!(&actual_data, *expected_data);
+    }
+
+    Ok(())
+}
+
 #[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
 #[test_case(common::ENCODING_CONFIG.to_owned(); "with encryption and compression")]
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn existing_key_is_replaced(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
 
     let mut object = repo.insert(String::from("test"));
     object.write_all(b"Data")?;
@@ -237,7 +290,7 @@ fn existing_key_is_replaced(repo_config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
 fn copy_nonexistent_object(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
 
     assert!(!repo.copy("nonexistent1", String::from("nonexistent2")));
 
@@ -247,7 +300,7 @@ fn copy_nonexistent_object(repo_config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
 fn object_is_not_accessible_from_another_instance(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     repo.insert(String::from("test"));
 
     assert!(repo.contains("test"));
@@ -266,6 +319,7 @@ fn object_is_not_accessible_from_another_instance(repo_config: RepoConfig) -> an
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn committing_commits_all_instances(repo_config: RepoConfig) -> anyhow::Result<()> {
     let instance_1 = Uuid::new_v4();
@@ -274,10 +328,10 @@ fn committing_commits_all_instances(repo_config: RepoConfig) -> anyhow::Result<(
     let store_config = MemoryConfig::new();
     let repo = create_repo(repo_config.clone(), &store_config)?;
 
-    let mut repo: KeyRepo<String> = repo.switch_instance(instance_1)?;
+    let repo: KeyRepo<String> = repo.switch_instance(instance_1)?;
     repo.insert(String::from("test1"));
 
-    let mut repo: KeyRepo<String> = repo.switch_instance(instance_2)?;
+    let repo: KeyRepo<String> = repo.switch_instance(instance_2)?;
     repo.insert(String::from("test2"));
 
     repo.commit()?;
@@ -296,7 +350,7 @@ fn committing_commits_all_instances(repo_config: RepoConfig) -> anyhow::Result<(
 #[test]
 fn change_password() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .encryption(Encryption::XChaCha20Poly1305)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -330,10 +384,11 @@ fn peek_info_succeeds() -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn committed_changes_are_persisted(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config.clone(), &store_config)?;
+    let repo = create_repo(repo_config.clone(), &store_config)?;
     let mut object = repo.insert(String::from("test"));
 
     // Write some data to the repository.
@@ -363,10 +418,11 @@ fn committed_changes_are_persisted(repo_config: RepoConfig) -> anyhow::Result<()
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn uncommitted_changes_are_not_persisted(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config.clone(), &store_config)?;
+    let repo = create_repo(repo_config.clone(), &store_config)?;
     let mut object = repo.insert(String::from("test"));
 
     // Write some data to the repository.
@@ -390,10 +446,11 @@ fn uncommitted_changes_are_not_persisted(repo_config: RepoConfig) -> anyhow::Res
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn objects_are_removed_on_rollback(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     let mut object = repo.insert(String::from("test"));
 
     object.write_all(random_buffer().as_slice())?;
@@ -413,10 +470,11 @@ fn objects_are_removed_on_rollback(repo_config: RepoConfig) -> anyhow::Result<()
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn object_contents_are_modified_on_rollback(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     repo.insert(String::from("test"));
 
     repo.commit()?;
@@ -441,7 +499,7 @@ fn object_contents_are_modified_on_rollback(repo_config: RepoConfig) -> anyhow::
 #[test]
 fn rollback_before_first_commit_succeeds() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(RepoConfig::default(), &store_config)?;
+    let repo = create_repo(RepoConfig::default(), &store_config)?;
     repo.commit()?;
     Ok(())
 }
@@ -451,10 +509,11 @@ fn rollback_before_first_commit_succeeds() -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn objects_are_removed_on_restore(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
 
     let savepoint = repo.savepoint()?;
 
@@ -478,10 +537,11 @@ fn objects_are_removed_on_restore(repo_config: RepoConfig) -> anyhow::Result<()>
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn object_contents_are_modified_on_restore(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     repo.insert(String::from("test"));
 
     let savepoint = repo.savepoint()?;
@@ -507,7 +567,7 @@ fn object_contents_are_modified_on_restore(repo_config: RepoConfig) -> anyhow::R
 
 #[test]
 fn restore_can_redo_changes() -> anyhow::Result<()> {
-    let mut repo = create_repo(RepoConfig::default(), &MemoryConfig::new())?;
+    let repo = create_repo(RepoConfig::default(), &MemoryConfig::new())?;
 
     let mut object = repo.insert(String::from("test"));
     object.write_all(random_buffer().as_slice())?;
@@ -537,7 +597,7 @@ fn restore_can_redo_changes() -> anyhow::Result<()> {
 
 #[test]
 fn committing_repo_invalidates_savepoint() -> anyhow::Result<()> {
-    let mut repo = create_repo(RepoConfig::default(), &MemoryConfig::new())?;
+    let repo = create_repo(RepoConfig::default(), &MemoryConfig::new())?;
 
     let before_savepoint = repo.savepoint()?;
     repo.commit()?;
@@ -559,7 +619,7 @@ fn committing_repo_invalidates_savepoint() -> anyhow::Result<()> {
 #[test]
 fn dropping_repo_invalidates_savepoint() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(RepoConfig::default(), &store_config)?;
+    let repo = create_repo(RepoConfig::default(), &store_config)?;
 
     let savepoint = repo.savepoint()?;
     drop(repo);
@@ -571,8 +631,8 @@ fn dropping_repo_invalidates_savepoint() -> anyhow::Result<()> {
 
 #[test]
 fn savepoint_must_be_associated_with_repo() -> anyhow::Result<()> {
-    let mut first_repo = create_repo(RepoConfig::default(), &MemoryConfig::new())?;
-    let mut second_repo = create_repo(RepoConfig::default(), &MemoryConfig::new())?;
+    let first_repo = create_repo(RepoConfig::default(), &MemoryConfig::new())?;
+    let second_repo = create_repo(RepoConfig::default(), &MemoryConfig::new())?;
 
     let savepoint = first_repo.savepoint()?;
 
@@ -590,10 +650,11 @@ fn savepoint_must_be_associated_with_repo() -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn unused_data_is_reclaimed_on_commit(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config.clone(), &store_config)?;
+    let repo = create_repo(repo_config.clone(), &store_config)?;
     let mut object = repo.insert(String::from("test"));
 
     object.write_all(random_buffer().as_slice())?;
@@ -606,7 +667,7 @@ fn unused_data_is_reclaimed_on_commit(repo_config: RepoConfig) -> anyhow::Result
     let original_blocks = store.list_blocks()?.len();
     drop(store);
 
-    let mut repo = open_repo(repo_config, &store_config)?;
+    let repo = open_repo(repo_config, &store_config)?;
     repo.remove("test");
     repo.commit()?;
     repo.clean()?;
@@ -619,15 +680,114 @@ fn unused_data_is_reclaimed_on_commit(repo_config: RepoConfig) -> anyhow::Result
     Ok(())
 }
 
+#[test]
+fn inline_objects_are_read_without_the_data_store() -> anyhow::Result<()> {
+    let mut repo_config = common::FIXED_CONFIG.to_owned();
+    repo_config.inline_threshold = 128;
+    let store_config = MemoryConfig::new();
+    let expected_data = random_bytes(100);
+
+    let repo = create_repo(repo_config.clone(), &store_config)?;
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(&expected_data)?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+    drop(repo);
+
+    let repo = open_repo(repo_config, &store_config)?;
+    truncate_store(&mut store_config.open()?)?;
+
+    let mut actual_data = Vec::new();
+    let mut object = repo.object("test").unwrap();
+    object.read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+    assert!(object.verify()?);
+    Ok(())
+}
+
+#[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
+#[test_case(common::ENCODING_CONFIG.to_owned(); "with encryption and compression")]
+#[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
+fn header_deltas_are_applied_on_open(mut repo_config: RepoConfig) -> anyhow::Result<()> {
+    repo_config.max_header_deltas = 2;
+    let store_config = MemoryConfig::new();
+    let mut repo = create_repo(repo_config.clone(), &store_config)?;
+    let mut expected = Vec::new();
+
+    // Commit enough times that the header is compacted at least once, reopening the repository
+    // and cleaning it after each commit.
+    for index in 0..5 {
+        let key = format!("test{}", index);
+        let data = random_bytes(100 * (index + 1));
+        let mut object = repo.insert(key.clone());
+        object.write_all(&data)?;
+        object.commit()?;
+        drop(object);
+        expected.push((key, data));
+
+        if index == 2 {
+            let (key, _) = expected.remove(0);
+            repo.remove(&key);
+        }
+
+        repo.commit()?;
+        repo.clean()?;
+        drop(repo);
+
+        repo = open_repo(repo_config.clone(), &store_config)?;
+        assert_eq!(repo.keys().count(), expected.len());
+        for (key, data) in &expected {
+            let mut actual_data = Vec::new();
+            repo.object(key).unwrap().read_to_end(&mut actual_data)?;
+            assert_eq!(&actual_data, data);
+        }
+        assert!(repo.verify()?.is_empty());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn variable_packing_writes_fewer_blocks() -> anyhow::Result<()> {
+    let data = random_buffer();
+    let mut block_counts = Vec::new();
+
+    for packing in &[Packing::None, Packing::variable()] {
+        let mut repo_config = common::FIXED_CONFIG.to_owned();
+        repo_config.packing = packing.clone();
+        let store_config = MemoryConfig::new();
+        let repo = create_repo(repo_config.clone(), &store_config)?;
+        let mut object = repo.insert(String::from("test"));
+        object.write_all(&data)?;
+        object.commit()?;
+        drop(object);
+        repo.commit()?;
+        drop(repo);
+
+        block_counts.push(store_config.open()?.list_blocks()?.len());
+
+        let repo = open_repo(repo_config, &store_config)?;
+        let mut actual_data = Vec::new();
+        repo.object("test").unwrap().read_to_end(&mut actual_data)?;
+        assert_eq!(actual_data, data);
+    }
+
+    assert!(block_counts[1] < block_counts[0]);
+    Ok(())
+}
+
 #[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
 #[test_case(common::ENCODING_CONFIG.to_owned(); "with encryption and compression")]
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn clean_before_commit_does_not_prevent_rollback(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     let mut object = repo.insert(String::from("test"));
 
     let expected_data = random_buffer();
@@ -661,10 +821,63 @@ fn clean_before_commit_does_not_prevent_rollback(repo_config: RepoConfig) -> any
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
+#[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
+fn clean_dry_run_does_not_modify_store(repo_config: RepoConfig) -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(repo_config, &store_config)?;
+    let expected_data = random_buffer();
+
+    let mut object = repo.insert(String::from("keep"));
+    object.write_all(expected_data.as_slice())?;
+    object.commit()?;
+    drop(object);
+
+    let mut object = repo.insert(String::from("remove"));
+    object.write_all(random_buffer().as_slice())?;
+    object.commit()?;
+    drop(object);
+
+    repo.commit()?;
+    repo.remove("remove");
+    repo.commit()?;
+
+    let mut blocks_before = store_config.open()?.list_blocks()?;
+    let report = repo.clean_dry_run()?;
+    let mut blocks_after = store_config.open()?.list_blocks()?;
+    blocks_before.sort();
+    blocks_after.sort();
+
+    assert!(!report.is_empty());
+    assert!(report.removed_bytes > 0);
+    assert_eq!(blocks_before, blocks_after);
+
+    // Cleaning the repository should remove exactly the blocks in the report.
+    repo.clean()?;
+    let remaining_blocks = store_config.open()?.list_blocks()?;
+    assert!(report
+        .removed_blocks
+        .iter()
+        .all(|block_id| !remaining_blocks.contains(block_id)));
+    assert!(repo.clean_dry_run()?.removed_blocks.is_empty());
+
+    let mut actual_data = Vec::new();
+    repo.object("keep").unwrap().read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+
+    Ok(())
+}
+
+#[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
+#[test_case(common::ENCODING_CONFIG.to_owned(); "with encryption and compression")]
+#[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
+#[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
+#[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn clear_instance_deletes_objects(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     let mut object = repo.insert(String::from("test"));
 
     object.write_all(random_buffer().as_slice())?;
@@ -684,10 +897,11 @@ fn clear_instance_deletes_objects(repo_config: RepoConfig) -> anyhow::Result<()>
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn rollback_after_clear_instance(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     let mut object = repo.insert(String::from("test"));
 
     object.write_all(random_buffer().as_slice())?;
@@ -709,10 +923,11 @@ fn rollback_after_clear_instance(repo_config: RepoConfig) -> anyhow::Result<()>
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn verify_valid_repository_is_valid(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
     let mut object = repo.insert(String::from("test"));
 
     object.write_all(random_buffer().as_slice())?;
@@ -723,3 +938,375 @@ fn verify_valid_repository_is_valid(repo_config: RepoConfig) -> anyhow::Result<(
 
     Ok(())
 }
+
+#[test]
+fn export_and_import_between_configs() -> anyhow::Result<()> {
+    let source_repo = create_repo(common::FIXED_CONFIG.to_owned(), &MemoryConfig::new())?;
+    let dest_repo = create_repo(common::ZPAQ_PACKING_CONFIG.to_owned(), &MemoryConfig::new())?;
+
+    let expected_data = random_buffer();
+    let mut object = source_repo.insert(String::from("exported"));
+    object.write_all(&expected_data)?;
+    object.commit()?;
+    drop(object);
+    source_repo.insert(String::from("not exported"));
+
+    let mut archive = Vec::new();
+    let keys = vec![String::from("exported"), String::from("nonexistent")];
+    source_repo.export(&keys, &mut archive)?;
+
+    let imported = dest_repo.import(archive.as_slice())?;
+
+    assert_eq!(imported, vec![String::from("exported")]);
+    assert!(!dest_repo.contains("not exported"));
+
+    let mut actual_data = Vec::new();
+    dest_repo
+        .object("exported")
+        .unwrap()
+        .read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+
+    Ok(())
+}
+
+#[test]
+fn import_truncated_archive_errs() -> anyhow::Result<()> {
+    let repo = create_repo(common::FIXED_CONFIG.to_owned(), &MemoryConfig::new())?;
+
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(&random_buffer())?;
+    object.commit()?;
+    drop(object);
+
+    let mut archive = Vec::new();
+    repo.export(&repo.keys().collect::<Vec<_>>(), &mut archive)?;
+    archive.truncate(archive.len() - 10);
+
+    assert!(matches!(
+        repo.import(archive.as_slice()),
+        Err(acid_store::Error::Io(_))
+    ));
+    assert!(matches!(
+        repo.import(&b"not an archive"[..]),
+        Err(acid_store::Error::Deserialize)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn import_archive_with_oversized_key_errs() -> anyhow::Result<()> {
+    let repo = create_repo(common::FIXED_CONFIG.to_owned(), &MemoryConfig::new())?;
+
+    // An entry which claims to have a 4 GiB key but ends immediately.
+    let mut archive = b"ACIDEXP1".to_vec();
+    archive.push(1);
+    archive.extend_from_slice(&u32::MAX.to_le_bytes());
+    archive.extend_from_slice(b"short");
+
+    assert!(matches!(
+        repo.import(archive.as_slice()),
+        Err(acid_store::Error::Io(_))
+    ));
+    assert!(repo.keys().next().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn changed_keys_are_reported_until_commit() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(RepoConfig::default(), &store_config)?;
+
+    let mut object = repo.insert(String::from("modified"));
+    object.write_all(random_buffer().as_slice())?;
+    object.commit()?;
+    drop(object);
+    repo.insert(String::from("removed"));
+    repo.insert(String::from("unchanged"));
+    repo.commit()?;
+
+    assert!(!repo.has_changes()?);
+
+    let mut object = repo.object("modified").unwrap();
+    object.write_all(random_buffer().as_slice())?;
+    object.commit()?;
+    drop(object);
+    repo.remove("removed");
+    repo.insert(String::from("inserted"));
+
+    let changed_keys = repo.changed_keys()?;
+    assert_eq!(changed_keys.len(), 3);
+    assert!(changed_keys.contains("modified"));
+    assert!(changed_keys.contains("removed"));
+    assert!(changed_keys.contains("inserted"));
+
+    repo.commit()?;
+    assert!(!repo.has_changes()?);
+    Ok(())
+}
+
+#[test]
+fn before_hook_vetoes_commit() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(RepoConfig::default(), &store_config)?;
+    repo.add_before_hook(TransactionEvent::Commit, || {
+        Err(anyhow::anyhow!("Commits are not allowed."))
+    });
+
+    repo.insert(String::from("test"));
+
+    assert!(matches!(repo.commit(), Err(acid_store::Error::Vetoed(_))));
+    assert!(repo.contains("test"));
+
+    repo.rollback()?;
+    assert!(!repo.contains("test"));
+    Ok(())
+}
+
+#[test]
+fn after_hooks_run_for_matching_events() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(RepoConfig::default(), &store_config)?;
+
+    let commits = Arc::new(AtomicUsize::new(0));
+    let restores = Arc::new(AtomicUsize::new(0));
+    let commits_clone = Arc::clone(&commits);
+    let restores_clone = Arc::clone(&restores);
+    repo.add_after_hook(TransactionEvent::Commit, move || {
+        commits_clone.fetch_add(1, Ordering::SeqCst);
+    });
+    repo.add_after_hook(TransactionEvent::Restore, move || {
+        restores_clone.fetch_add(1, Ordering::SeqCst);
+    });
+
+    repo.commit()?;
+    repo.commit()?;
+    repo.rollback()?;
+    let savepoint = repo.savepoint()?;
+    repo.restore(&savepoint)?;
+
+    assert_eq!(commits.load(Ordering::SeqCst), 2);
+    assert_eq!(restores.load(Ordering::SeqCst), 1);
+    Ok(())
+}
+
+#[test]
+fn verify_reports_progress() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(RepoConfig::default(), &store_config)?;
+
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(random_buffer().as_slice())?;
+    object.commit()?;
+    drop(object);
+
+    let reports = Arc::new(AtomicUsize::new(0));
+    let reports_clone = Arc::clone(&reports);
+    repo.set_progress_handler(move |progress| {
+        assert_eq!(progress.operation, Operation::Verify);
+        assert!(progress.total.is_some());
+        assert!(progress.completed <= progress.total.unwrap());
+        reports_clone.fetch_add(1, Ordering::SeqCst);
+    });
+
+    assert!(repo.verify()?.is_empty());
+    assert!(reports.load(Ordering::SeqCst) > 1);
+    Ok(())
+}
+
+#[test]
+fn cancelled_commit_errs() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(RepoConfig::default(), &store_config)?;
+    let token = CancellationToken::new();
+    repo.set_cancellation_token(token.clone());
+
+    repo.insert(String::from("test"));
+    token.cancel();
+
+    assert!(matches!(repo.commit(), Err(acid_store::Error::Cancelled)));
+
+    repo.set_cancellation_token(CancellationToken::new());
+    repo.commit()?;
+    drop(repo);
+
+    let repo = open_repo(RepoConfig::default(), &store_config)?;
+    assert!(repo.contains("test"));
+    Ok(())
+}
+
+#[test]
+fn objects_can_be_read_from_multiple_threads() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(RepoConfig::default(), &store_config)?;
+    let expected_data = random_buffer();
+
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(expected_data.as_slice())?;
+    object.commit()?;
+    drop(object);
+
+    let repo = Arc::new(repo);
+    let expected_data = Arc::new(expected_data);
+    let threads = (0..4)
+        .map(|_| {
+            let repo = Arc::clone(&repo);
+            let expected_data = Arc::clone(&expected_data);
+            thread::spawn(move || {
+                let mut object = repo.object("test").unwrap();
+                let mut actual_data = Vec::new();
+                object.read_to_end(&mut actual_data).unwrap();
+                assert_eq!(&actual_data, expected_data.as_ref());
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    Ok(())
+}
+
+#[test]
+fn repository_can_be_modified_from_multiple_threads() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = Arc::new(create_repo(RepoConfig::default(), &store_config)?);
+
+    let threads = (0..4)
+        .map(|index| {
+            let repo = Arc::clone(&repo);
+            thread::spawn(move || {
+                let mut object = repo.insert(format!("test{}", index));
+                object.write_all(b"data").unwrap();
+                object.commit().unwrap();
+                drop(object);
+                repo.commit().unwrap();
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    drop(repo);
+    let repo = open_repo(RepoConfig::default(), &store_config)?;
+    let expected = (0..4).map(|index| format!("test{}", index));
+    assert_contains_all(repo.keys(), expected);
+
+    Ok(())
+}
+
+#[test]
+fn stats_record_repository_operations() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(RepoConfig::default(), &store_config)?;
+    let expected_data = random_buffer();
+
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(expected_data.as_slice())?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+
+    let stats = repo.stats();
+    assert!(stats.store_write.count > 0);
+    assert!(stats.store_write.bytes > 0);
+    assert!(stats.chunking.bytes >= expected_data.len() as u64);
+    assert!(stats.hashing.bytes >= expected_data.len() as u64);
+    assert!(stats.encode.bytes > 0);
+    drop(repo);
+
+    let repo = open_repo(RepoConfig::default(), &store_config)?;
+    repo.reset_stats();
+    let mut object = repo.object("test").unwrap();
+    let mut actual_data = Vec::new();
+    object.read_to_end(&mut actual_data)?;
+
+    let stats = repo.stats();
+    assert!(stats.store_read.count > 0);
+    assert!(stats.decode.bytes > 0);
+    assert_eq!(stats.store_write.count, 0);
+    assert_eq!(stats.chunking.count, 0);
+    Ok(())
+}
+
+#[test]
+fn audit_log_records_operations() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let mut repo_config = RepoConfig::default();
+    repo_config.audit_log = true;
+    let repo = create_repo(repo_config.clone(), &store_config)?;
+
+    repo.insert(String::from("test"));
+    repo.commit()?;
+    repo.remove("test");
+    repo.commit()?;
+    repo.rollback()?;
+    drop(repo);
+
+    let repo = open_repo(repo_config, &store_config)?;
+    let entries = repo.audit_log()?;
+
+    assert_eq!(entries.len(), 6);
+    for entry in &entries {
+        assert_eq!(entry.instance, repo.instance());
+    }
+    assert!(matches!(entries[0].event, AuditEvent::Create));
+    assert!(matches!(entries[1].event, AuditEvent::Commit));
+    assert!(matches!(entries[2].event, AuditEvent::Remove { .. }));
+    assert!(matches!(entries[3].event, AuditEvent::Commit));
+    assert!(matches!(entries[4].event, AuditEvent::Rollback));
+    assert!(matches!(entries[5].event, AuditEvent::Open));
+    Ok(())
+}
+
+#[test]
+fn audit_log_is_empty_when_disabled() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(RepoConfig::default(), &store_config)?;
+    repo.insert(String::from("test"));
+    repo.commit()?;
+    assert!(repo.audit_log()?.is_empty());
+    Ok(())
+}
+
+#[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
+#[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
+fn inspect_chunks_reports_references(repo_config: RepoConfig) -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(repo_config, &store_config)?;
+    let shared_data = random_buffer();
+
+    for key in &["first", "second"] {
+        let mut object = repo.insert(key.to_string());
+        object.write_all(shared_data.as_slice())?;
+        object.commit()?;
+    }
+
+    let mut object = repo.insert(String::from("unique"));
+    object.write_all(random_buffer().as_slice())?;
+    object.commit()?;
+    drop(object);
+
+    let reports = repo.inspect_chunks()?;
+    let total_size = reports.iter().map(|report| report.size as u64).sum::<u64>();
+
+    assert!(!reports.is_empty());
+    assert!(total_size < shared_data.len() as u64 * 3);
+    for report in &reports {
+        if report.keys.contains(&String::from("first")) {
+            assert!(report.keys.contains(&String::from("second")));
+            assert_eq!(report.references, 2);
+        }
+        if report.keys.contains(&String::from("unique")) {
+            assert_eq!(report.references, 1);
+        }
+    }
+
+    Ok(())
+}
@@ -18,13 +18,14 @@
 
 use std::convert::TryFrom;
 use std::io::{Read, Seek, SeekFrom, Write};
+use std::thread;
 
 use test_case::test_case;
 
 use acid_store::repo::key::KeyRepo;
 use acid_store::repo::{
-    Chunking, Commit, Compression, Encryption, OpenMode, OpenOptions, ReadOnlyObject, RepoConfig,
-    RestoreSavepoint,
+    Chunking, Commit, Compression, Encryption, OpenMode, OpenOptions, Packing, ReadOnlyObject,
+    RepoConfig, RestoreSavepoint,
 };
 use acid_store::store::MemoryConfig;
 use common::{random_buffer, random_bytes, MIN_BUFFER_SIZE};
@@ -36,10 +37,11 @@ mod common;
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn read_written_data(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -64,10 +66,11 @@ fn read_written_data(config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn append_to_object(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -103,10 +106,11 @@ fn append_to_object(config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn seek_and_read_data(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -146,10 +150,53 @@ fn seek_and_read_data(config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
+#[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
+fn read_shared_bytes(config: RepoConfig) -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(config)
+        .password(b"Password")
+        .mode(OpenMode::CreateNew)
+        .open(&store_config)?;
+    let mut object = repo.insert(String::from("test"));
+
+    let data = random_buffer();
+    object.write_all(&data)?;
+    object.commit()?;
+    object.set_len(data.len() as u64 * 2)?;
+
+    let mut expected_data = data.clone();
+    expected_data.resize(data.len() * 2, 0);
+
+    // Read the data back without copying it into a buffer of our own.
+    let mut actual_data = Vec::new();
+    object.seek(SeekFrom::Start(10))?;
+    loop {
+        let bytes = object.read_bytes(MIN_BUFFER_SIZE)?;
+        if bytes.is_empty() {
+            break;
+        }
+        assert!(bytes.len() <= MIN_BUFFER_SIZE);
+        actual_data.extend_from_slice(&bytes);
+    }
+
+    assert_eq!(actual_data, &expected_data[10..]);
+    assert_eq!(object.stream_position()?, expected_data.len() as u64);
+
+    Ok(())
+}
+
+#[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
+#[test_case(common::ENCODING_CONFIG.to_owned(); "with encryption and compression")]
+#[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
+#[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
+#[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn seek_to_negative_offset(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -170,10 +217,11 @@ fn seek_to_negative_offset(config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn overwrite_written_data(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -205,10 +253,11 @@ fn overwrite_written_data(config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn partially_overwrite_written_data(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -238,15 +287,99 @@ fn partially_overwrite_written_data(config: RepoConfig) -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test_case(common::FIXED_CONFIG.to_owned(), Packing::None; "without packing")]
+#[test_case(common::ENCODING_CONFIG.to_owned(), Packing::None; "with encryption and compression")]
+#[test_case(common::FIXED_CONFIG.to_owned(), Packing::Fixed(1000 * 1000); "with a pack size smaller than the chunk size")]
+#[test_case(common::FIXED_CONFIG.to_owned(), Packing::Fixed(3000 * 1000); "with a pack size larger than the chunk size")]
+fn read_part_of_large_chunk(mut config: RepoConfig, packing: Packing) -> anyhow::Result<()> {
+    // Chunks must be large for only part of them to be read.
+    let chunk_size = 1024 * 1024;
+    config.chunking = Chunking::Fixed { size: chunk_size };
+    config.packing = packing;
+    let store_config = MemoryConfig::new();
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(config)
+        .password(b"Password")
+        .mode(OpenMode::CreateNew)
+        .open(&store_config)?;
+    let mut object = repo.insert(String::from("test"));
+
+    let expected_data = random_bytes(chunk_size as usize * 2);
+    object.write_all(&expected_data)?;
+    object.commit()?;
+
+    // Read a small amount of data from the middle of a chunk.
+    let mut actual_data = vec![0u8; MIN_BUFFER_SIZE];
+    object.seek(SeekFrom::Start(300_000))?;
+    object.read_exact(&mut actual_data)?;
+    assert_eq!(
+        actual_data,
+        &expected_data[300_000..300_000 + MIN_BUFFER_SIZE]
+    );
+
+    // Read past the part of the chunk which was read and into the next chunk.
+    let mut actual_data = Vec::new();
+    object.read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, &expected_data[300_000 + MIN_BUFFER_SIZE..]);
+
+    // Read the whole object.
+    let mut actual_data = Vec::new();
+    object.seek(SeekFrom::Start(0))?;
+    object.read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+
+    Ok(())
+}
+
+#[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
+#[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
+#[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
+#[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
+fn write_with_max_chunk_size(mut config: RepoConfig) -> anyhow::Result<()> {
+    // Smaller than the chunk size and not a factor of it.
+    config.max_chunk_size = 100;
+    let store_config = MemoryConfig::new();
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(config)
+        .password(b"Password")
+        .mode(OpenMode::CreateNew)
+        .open(&store_config)?;
+    let mut object = repo.insert(String::from("test"));
+
+    // Write initial data to the object.
+    let initial_data = random_buffer();
+    object.write_all(initial_data.as_slice())?;
+    object.commit()?;
+
+    // Overwrite data in the middle of the object.
+    let new_data = random_bytes(MIN_BUFFER_SIZE / 2);
+    object.seek(SeekFrom::Start(150))?;
+    object.write_all(new_data.as_slice())?;
+    object.commit()?;
+    object.seek(SeekFrom::Start(0))?;
+
+    // Read all the data.
+    let mut expected_data = initial_data;
+    expected_data[150..150 + new_data.len()].copy_from_slice(new_data.as_slice());
+    let mut actual_data = Vec::new();
+    object.read_to_end(&mut actual_data)?;
+
+    assert_eq!(actual_data, expected_data);
+    assert!(object.verify()?);
+
+    Ok(())
+}
+
 #[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
 #[test_case(common::ENCODING_CONFIG.to_owned(); "with encryption and compression")]
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn partially_overwrite_and_grow_data(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -283,10 +416,11 @@ fn partially_overwrite_and_grow_data(config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn truncate_object(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -321,10 +455,11 @@ fn truncate_object(config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn extend_object(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -361,7 +496,7 @@ fn extend_object(config: RepoConfig) -> anyhow::Result<()> {
 #[test]
 fn extend_to_absurd_size() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
     let mut object = repo.insert(String::from("test"));
@@ -380,10 +515,11 @@ fn extend_to_absurd_size() -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn extend_then_append(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -426,10 +562,11 @@ fn extend_then_append(config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn extend_then_write_in_hole(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -469,10 +606,58 @@ fn extend_then_write_in_hole(config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
+#[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
+fn punch_hole_in_middle(config: RepoConfig) -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(config)
+        .password(b"Password")
+        .mode(OpenMode::CreateNew)
+        .open(&store_config)?;
+    let mut object = repo.insert(String::from("test"));
+
+    let data = random_buffer();
+    object.write_all(&data)?;
+    object.commit()?;
+
+    // Punch a hole which starts and ends partway through chunks.
+    let hole_start = data.len() / 3;
+    let hole_end = hole_start * 2;
+    object.punch_hole(hole_start as u64, (hole_end - hole_start) as u64)?;
+
+    let mut expected_data = data.clone();
+    expected_data[hole_start..hole_end].fill(0);
+
+    let mut actual_data = Vec::new();
+    object.seek(SeekFrom::Start(0))?;
+    object.read_to_end(&mut actual_data)?;
+
+    assert_eq!(actual_data, expected_data);
+
+    // A hole which extends past the end of the object does not change its size.
+    object.punch_hole(hole_end as u64, data.len() as u64)?;
+    expected_data[hole_end..].fill(0);
+
+    let mut actual_data = Vec::new();
+    object.seek(SeekFrom::Start(0))?;
+    object.read_to_end(&mut actual_data)?;
+
+    assert_eq!(actual_data, expected_data);
+
+    Ok(())
+}
+
+#[test_case(common::FIXED_CONFIG.to_owned(); "with fixed-size chunking")]
+#[test_case(common::ENCODING_CONFIG.to_owned(); "with encryption and compression")]
+#[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
+#[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
+#[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn compare_content_ids(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -512,10 +697,11 @@ fn compare_content_ids(config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn compare_contents_with_are_equal(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -540,10 +726,11 @@ fn compare_contents_with_are_equal(config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn compare_unequal_contents_with_same_size(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -569,10 +756,11 @@ fn compare_unequal_contents_with_same_size(config: RepoConfig) -> anyhow::Result
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn compare_contents_which_are_smaller(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -596,10 +784,11 @@ fn compare_contents_which_are_smaller(config: RepoConfig) -> anyhow::Result<()>
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn compare_contents_which_are_larger(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -625,10 +814,11 @@ fn compare_contents_which_are_larger(config: RepoConfig) -> anyhow::Result<()> {
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn verify_valid_object_is_valid(config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .config(config)
         .password(b"Password")
         .mode(OpenMode::CreateNew)
@@ -647,7 +837,7 @@ fn write_buffer_with_same_size_as_fixed_chunk_size() -> anyhow::Result<()> {
     let chunk_size = 1024 * 1024;
 
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .chunking(Chunking::Fixed { size: chunk_size })
         .encryption(Encryption::None)
         .compression(Compression::None)
@@ -665,7 +855,7 @@ fn write_buffer_with_same_size_as_fixed_chunk_size() -> anyhow::Result<()> {
 #[test]
 fn reading_seeking_with_uncommitted_changes_errs() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -689,7 +879,7 @@ fn reading_seeking_with_uncommitted_changes_errs() -> anyhow::Result<()> {
 #[test]
 fn accessing_with_uncommitted_changes_errs() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -717,7 +907,7 @@ fn accessing_with_uncommitted_changes_errs() -> anyhow::Result<()> {
 #[test]
 fn truncating_with_uncommitted_changes_errs() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -735,7 +925,7 @@ fn truncating_with_uncommitted_changes_errs() -> anyhow::Result<()> {
 #[test]
 fn writing_from_another_instance_with_uncommitted_changes_errs() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -759,7 +949,7 @@ fn writing_from_another_instance_with_uncommitted_changes_errs() -> anyhow::Resu
 #[test]
 fn truncating_from_another_instance_with_uncommitted_changes_errs() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -785,7 +975,7 @@ fn truncating_from_another_instance_with_uncommitted_changes_errs() -> anyhow::R
 #[test]
 fn extending_from_another_instance_with_uncommitted_changes_errs() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -809,7 +999,7 @@ fn extending_from_another_instance_with_uncommitted_changes_errs() -> anyhow::Re
 #[test]
 fn reading_seeking_from_another_instance_with_uncommitted_changes_is_ok() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -829,7 +1019,7 @@ fn reading_seeking_from_another_instance_with_uncommitted_changes_is_ok() -> any
 #[test]
 fn accessing_from_another_instance_with_uncommitted_changes_is_ok() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -849,7 +1039,7 @@ fn accessing_from_another_instance_with_uncommitted_changes_is_ok() -> anyhow::R
 #[test]
 fn uncommitted_changes_are_not_visible_from_other_instances() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -876,7 +1066,7 @@ fn uncommitted_changes_are_not_visible_from_other_instances() -> anyhow::Result<
 #[test]
 fn accessing_once_repo_is_dropped_errs() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -919,7 +1109,7 @@ fn accessing_once_repo_is_dropped_errs() -> anyhow::Result<()> {
 #[test]
 fn accessing_once_object_is_removed_errs() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -962,7 +1152,7 @@ fn accessing_once_object_is_removed_errs() -> anyhow::Result<()> {
 #[test]
 fn converting_to_read_only_with_uncommitted_changes_errs() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -977,10 +1167,63 @@ fn converting_to_read_only_with_uncommitted_changes_errs() -> anyhow::Result<()>
     Ok(())
 }
 
+#[test]
+fn cloned_read_only_objects_read_concurrently() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .open(&store_config)?;
+
+    let expected_data = random_buffer();
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(expected_data.as_slice())?;
+    object.commit()?;
+
+    // Start a transaction which readers shouldn't see.
+    object.write_all(b"uncommitted data")?;
+
+    let reader = ReadOnlyObject::try_from(repo.object("test").unwrap())?;
+    let handles = (0..4)
+        .map(|_| {
+            let mut reader = reader.try_clone()?;
+            let expected_data = expected_data.clone();
+            Ok(thread::spawn(move || {
+                let mut actual_data = Vec::new();
+                reader.read_to_end(&mut actual_data).unwrap();
+                assert_eq!(actual_data, expected_data);
+            }))
+        })
+        .collect::<acid_store::Result<Vec<_>>>()?;
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cloning_invalid_read_only_object_errs() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .open(&store_config)?;
+
+    repo.insert(String::from("test"));
+    let reader = ReadOnlyObject::try_from(repo.object("test").unwrap())?;
+    repo.remove("test");
+
+    assert!(matches!(
+        reader.try_clone(),
+        Err(acid_store::Error::InvalidObject)
+    ));
+
+    Ok(())
+}
+
 #[test]
 fn rolling_back_repo_invalidates_objects() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -1002,7 +1245,7 @@ fn rolling_back_repo_invalidates_objects() -> anyhow::Result<()> {
 #[test]
 fn restoring_to_savepoint_invalidates_objects() -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo: KeyRepo<String> = OpenOptions::new()
+    let repo: KeyRepo<String> = OpenOptions::new()
         .mode(OpenMode::CreateNew)
         .open(&store_config)?;
 
@@ -16,15 +16,94 @@
 
 #![cfg(feature = "encryption")]
 
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
 use acid_store::repo::key::KeyRepo;
 use acid_store::repo::value::ValueRepo;
 use acid_store::repo::{
-    Chunking, Commit, Compression, Encryption, OpenMode, OpenOptions, RepoConfig, ResourceLimit,
+    Chunking, Commit, Compression, Encryption, LockState, LockStrategy, OpenMode, OpenOptions,
+    RepoConfig, ResourceLimit, RetryPolicy,
 };
-use acid_store::store::MemoryConfig;
+use acid_store::store::{DataStore, MemoryConfig, MemoryStore, OpenStore};
+use acid_store::uuid::Uuid;
+use common::{random_buffer, random_bytes, truncate_store};
 
 mod common;
 
+/// Copy every block in the store at `source` into a new store.
+///
+/// This simulates a second machine which shares the same data store.
+fn copy_store(source: &MemoryConfig) -> anyhow::Result<MemoryConfig> {
+    let mut source_store = source.open()?;
+    let dest = MemoryConfig::new();
+    let mut dest_store = dest.open()?;
+    for id in source_store.list_blocks()? {
+        if let Some(data) = source_store.read_block(id)? {
+            dest_store.write_block(id, &data)?;
+        }
+    }
+    Ok(dest)
+}
+
+/// A data store which fails every other operation with an I/O error of the given kind.
+struct FlakyStore {
+    store: MemoryStore,
+    kind: io::ErrorKind,
+    failed: bool,
+}
+
+impl FlakyStore {
+    fn fail(&mut self) -> anyhow::Result<()> {
+        self.failed = !self.failed;
+        if self.failed {
+            Err(io::Error::from(self.kind).into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl DataStore for FlakyStore {
+    fn write_block(&mut self, id: Uuid, data: &[u8]) -> anyhow::Result<()> {
+        self.fail()?;
+        self.store.write_block(id, data)
+    }
+
+    fn read_block(&mut self, id: Uuid) -> anyhow::Result<Option<Vec<u8>>> {
+        self.fail()?;
+        self.store.read_block(id)
+    }
+
+    fn remove_block(&mut self, id: Uuid) -> anyhow::Result<()> {
+        self.fail()?;
+        self.store.remove_block(id)
+    }
+
+    fn list_blocks(&mut self) -> anyhow::Result<Vec<Uuid>> {
+        self.fail()?;
+        self.store.list_blocks()
+    }
+}
+
+struct FlakyConfig(MemoryConfig, io::ErrorKind);
+
+impl OpenStore for FlakyConfig {
+    type Store = FlakyStore;
+
+    fn open(&self) -> acid_store::Result<Self::Store> {
+        Ok(FlakyStore {
+            store: self.0.open()?,
+            kind: self.1,
+            failed: false,
+        })
+    }
+}
+
 #[test]
 fn set_existing_config_and_create_new_repo() -> anyhow::Result<()> {
     // These are random config values for testing. This should not be used as an example config.
@@ -156,7 +235,7 @@ fn opening_without_password_errs() -> anyhow::Result<()> {
 fn opening_locked_repo_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
 
-    let mut repo: KeyRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    let repo: KeyRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
     repo.commit()?;
 
     let new_repo: Result<KeyRepo<String>, _> = OpenOptions::new().open(&config);
@@ -165,6 +244,294 @@ fn opening_locked_repo_errs() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn opening_locked_repo_with_timeout_errs() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+
+    let repo: KeyRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    repo.commit()?;
+
+    let new_repo: Result<KeyRepo<String>, _> = OpenOptions::new()
+        .lock_strategy(LockStrategy::WaitTimeout(Duration::from_millis(50)))
+        .open(&config);
+
+    assert!(matches!(new_repo, Err(acid_store::Error::Locked)));
+    Ok(())
+}
+
+#[test]
+fn opening_locked_repo_waits_for_lock() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let thread_config = config.clone();
+    let (sender, receiver) = mpsc::channel();
+
+    let handle = thread::spawn(move || {
+        let repo: KeyRepo<String> = OpenOptions::new()
+            .mode(OpenMode::CreateNew)
+            .open(&thread_config)
+            .unwrap();
+        repo.commit().unwrap();
+        sender.send(()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+    });
+
+    receiver.recv()?;
+    let new_repo: Result<KeyRepo<String>, _> = OpenOptions::new()
+        .lock_strategy(LockStrategy::WaitTimeout(Duration::from_secs(10)))
+        .open(&config);
+    handle.join().unwrap();
+
+    assert!(new_repo.is_ok());
+    Ok(())
+}
+
+#[test]
+fn custom_lock_strategy_is_called_for_each_attempt() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+
+    let repo: KeyRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    repo.commit()?;
+
+    let attempts = Arc::new(AtomicU32::new(0));
+    let strategy_attempts = Arc::clone(&attempts);
+    let new_repo: Result<KeyRepo<String>, _> = OpenOptions::new()
+        .lock_strategy(LockStrategy::Custom(Arc::new(move |attempt| {
+            strategy_attempts.store(attempt, Ordering::SeqCst);
+            if attempt < 3 {
+                Some(Duration::from_millis(1))
+            } else {
+                None
+            }
+        })))
+        .open(&config);
+
+    assert!(matches!(new_repo, Err(acid_store::Error::Locked)));
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    Ok(())
+}
+
+#[test]
+fn lease_is_released_when_repo_is_dropped() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let mut repo_config = RepoConfig::default();
+    repo_config.lease_duration = Some(Duration::from_secs(60 * 60));
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(repo_config)
+        .mode(OpenMode::CreateNew)
+        .open(&config)?;
+    repo.commit()?;
+    drop(repo);
+
+    let new_repo: Result<KeyRepo<String>, _> = OpenOptions::new().open(&config);
+
+    assert!(new_repo.is_ok());
+    Ok(())
+}
+
+#[test]
+fn opening_repo_with_unexpired_lease_errs() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let mut repo_config = RepoConfig::default();
+    repo_config.lease_duration = Some(Duration::from_secs(60 * 60));
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(repo_config)
+        .mode(OpenMode::CreateNew)
+        .open(&config)?;
+    repo.commit()?;
+    let other_config = copy_store(&config)?;
+    drop(repo);
+
+    let new_repo: Result<KeyRepo<String>, _> = OpenOptions::new().open(&other_config);
+
+    assert!(matches!(new_repo, Err(acid_store::Error::Locked)));
+    Ok(())
+}
+
+#[test]
+fn opening_repo_with_expired_lease_succeeds() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let mut repo_config = RepoConfig::default();
+    repo_config.lease_duration = Some(Duration::from_millis(50));
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(repo_config)
+        .mode(OpenMode::CreateNew)
+        .open(&config)?;
+    repo.commit()?;
+    let other_config = copy_store(&config)?;
+    drop(repo);
+
+    let new_repo: Result<KeyRepo<String>, _> = OpenOptions::new()
+        .lock_strategy(LockStrategy::WaitTimeout(Duration::from_secs(10)))
+        .open(&other_config);
+
+    assert!(new_repo.is_ok());
+    Ok(())
+}
+
+#[test]
+fn lease_is_renewed_while_repo_is_idle() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let mut repo_config = RepoConfig::default();
+    repo_config.lease_duration = Some(Duration::from_millis(500));
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(repo_config)
+        .mode(OpenMode::CreateNew)
+        .open(&config)?;
+    repo.commit()?;
+
+    // Wait for several times the lease duration without committing or renewing the lease.
+    thread::sleep(Duration::from_millis(2000));
+    let other_config = copy_store(&config)?;
+    let new_repo: Result<KeyRepo<String>, _> = OpenOptions::new().open(&other_config);
+
+    assert!(matches!(new_repo, Err(acid_store::Error::Locked)));
+    assert!(repo.commit().is_ok());
+    Ok(())
+}
+
+#[test]
+fn commit_with_optimistic_concurrency_detects_conflicts() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+
+    let first_repo: KeyRepo<String> = OpenOptions::new()
+        .optimistic_concurrency(true)
+        .mode(OpenMode::CreateNew)
+        .open(&config)?;
+    first_repo.commit()?;
+
+    let second_repo: KeyRepo<String> = OpenOptions::new()
+        .optimistic_concurrency(true)
+        .open(&config)?;
+
+    first_repo.insert(String::from("first"));
+    first_repo.commit()?;
+
+    second_repo.insert(String::from("second"));
+    assert!(matches!(
+        second_repo.commit(),
+        Err(acid_store::Error::Conflict)
+    ));
+
+    second_repo.refresh()?;
+    assert!(second_repo.contains("first"));
+    assert!(!second_repo.contains("second"));
+
+    second_repo.insert(String::from("second"));
+    second_repo.commit()?;
+
+    assert!(matches!(
+        first_repo.commit(),
+        Err(acid_store::Error::Conflict)
+    ));
+    Ok(())
+}
+
+#[test]
+fn read_only_repo_can_be_opened_while_locked() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+
+    let repo: KeyRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(b"data")?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+
+    let read_only_repo: KeyRepo<String> = OpenOptions::new().read_only(true).open(&config)?;
+    let mut object = read_only_repo.object("test").unwrap();
+    let mut actual_data = Vec::new();
+    object.read_to_end(&mut actual_data)?;
+
+    assert_eq!(actual_data, b"data");
+    Ok(())
+}
+
+#[test]
+fn read_only_repo_reads_large_header_on_demand() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let mut repo_config = RepoConfig::default();
+    repo_config.chunking = Chunking::Fixed { size: 64 };
+
+    // Write enough chunks that the chunk map is split into shards.
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(repo_config)
+        .mode(OpenMode::CreateNew)
+        .open(&config)?;
+    let large_data = random_bytes(64 * 10_000);
+    let mut object = repo.insert(String::from("large"));
+    object.write_all(&large_data)?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+
+    // Commit a delta on top of the sharded header.
+    let mut object = repo.insert(String::from("small"));
+    object.write_all(b"data")?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+    drop(repo);
+
+    let read_only_repo: KeyRepo<String> = OpenOptions::new().read_only(true).open(&config)?;
+
+    let mut actual_data = Vec::new();
+    read_only_repo
+        .object("small")
+        .unwrap()
+        .read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, b"data");
+
+    read_only_repo.remove("small");
+    read_only_repo.rollback()?;
+
+    let mut actual_data = Vec::new();
+    read_only_repo
+        .object("large")
+        .unwrap()
+        .read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, large_data);
+    assert!(read_only_repo.contains("small"));
+    assert!(read_only_repo.verify()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn read_only_repo_forbids_writes() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+
+    let repo: KeyRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    repo.commit()?;
+    drop(repo);
+
+    let repo: KeyRepo<String> = OpenOptions::new().read_only(true).open(&config)?;
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(b"data")?;
+    let flush_result = object.commit();
+    drop(object);
+
+    assert!(matches!(flush_result, Err(acid_store::Error::ReadOnly)));
+    assert!(matches!(repo.commit(), Err(acid_store::Error::ReadOnly)));
+    assert!(matches!(repo.clean(), Err(acid_store::Error::ReadOnly)));
+    Ok(())
+}
+
+#[test]
+fn creating_read_only_repo_errs() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+
+    let repo: Result<KeyRepo<String>, _> = OpenOptions::new()
+        .read_only(true)
+        .mode(OpenMode::Create)
+        .open(&config);
+
+    assert!(matches!(repo, Err(acid_store::Error::ReadOnly)));
+    Ok(())
+}
+
 #[test]
 fn open_or_create_existing_repo() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
@@ -189,10 +556,524 @@ fn open_or_create_nonexistent_repo() -> anyhow::Result<()> {
 #[test]
 fn opening_existing_repo_of_different_type_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo: ValueRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    let repo: ValueRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    repo.commit()?;
+    drop(repo);
+    let result = OpenOptions::new().open::<KeyRepo<String>, _>(&config);
+    assert!(matches!(result, Err(acid_store::Error::UnsupportedRepo)));
+    Ok(())
+}
+
+/// The ID of the block which stores the repository format version.
+const VERSION_BLOCK_ID: &str = "cbf28b1c-3550-11ea-8cb0-87d7a14efe10";
+
+/// The format version of repositories created by version 0.7 of this library.
+const VERSION_0_7_ID: &str = "6f1c893c-e6a8-11eb-a198-b7fa995cc83b";
+
+/// Create a repository containing an object and then change its format version to `version`.
+///
+/// This returns the contents of the object.
+fn create_repo_with_version(config: &MemoryConfig, version: &str) -> anyhow::Result<Vec<u8>> {
+    let repo: KeyRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(config)?;
+    let expected_data = random_buffer();
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(&expected_data)?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+    drop(repo);
+
+    config.open()?.write_block(
+        Uuid::parse_str(VERSION_BLOCK_ID)?,
+        Uuid::parse_str(version)?.as_bytes(),
+    )?;
+
+    Ok(expected_data)
+}
+
+/// Return the format version of the repository in the store opened by `config`.
+fn read_version(config: &MemoryConfig) -> anyhow::Result<Uuid> {
+    let serialized_version = config
+        .open()?
+        .read_block(Uuid::parse_str(VERSION_BLOCK_ID)?)?
+        .unwrap();
+    Ok(Uuid::from_slice(&serialized_version)?)
+}
+
+/// The directory containing the blocks of a repository created by version 0.7 of this library.
+///
+/// Each file is named after the ID of the block it contains.
+const REPO_0_7_FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/repo_0_7");
+
+/// The contents of the object `test` in the repository in `REPO_0_7_FIXTURE`.
+const REPO_0_7_DATA: &[u8] = b"This object was written by version 0.7 of acid-store.";
+
+/// Copy the blocks of the repository in `REPO_0_7_FIXTURE` into a new store.
+fn repo_0_7_fixture() -> anyhow::Result<MemoryConfig> {
+    let config = MemoryConfig::new();
+    let mut store = config.open()?;
+    for entry in fs::read_dir(REPO_0_7_FIXTURE)? {
+        let entry = entry?;
+        let id = Uuid::parse_str(&entry.file_name().to_string_lossy())?;
+        store.write_block(id, &fs::read(entry.path())?)?;
+    }
+    Ok(config)
+}
+
+#[test]
+fn repo_in_previous_format_is_migrated() -> anyhow::Result<()> {
+    let config = repo_0_7_fixture()?;
+    assert_eq!(read_version(&config)?, Uuid::parse_str(VERSION_0_7_ID)?);
+
+    let repo: KeyRepo<String> = OpenOptions::new().open(&config)?;
+    let mut actual_data = Vec::new();
+    repo.object("test").unwrap().read_to_end(&mut actual_data)?;
     repo.commit()?;
     drop(repo);
+
+    assert_eq!(actual_data, REPO_0_7_DATA);
+    assert_ne!(read_version(&config)?, Uuid::parse_str(VERSION_0_7_ID)?);
+
+    // The migrated repository can be opened again and still contains the object.
+    let repo: KeyRepo<String> = OpenOptions::new().open(&config)?;
+    let mut actual_data = Vec::new();
+    repo.object("test").unwrap().read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, REPO_0_7_DATA);
+    assert!(repo.verify()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn repo_in_previous_format_is_not_migrated_when_read_only() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let expected_data = create_repo_with_version(&config, VERSION_0_7_ID)?;
+
+    let repo: KeyRepo<String> = OpenOptions::new().read_only(true).open(&config)?;
+    let mut actual_data = Vec::new();
+    repo.object("test").unwrap().read_to_end(&mut actual_data)?;
+
+    assert_eq!(actual_data, expected_data);
+    assert_eq!(read_version(&config)?, Uuid::parse_str(VERSION_0_7_ID)?);
+    Ok(())
+}
+
+#[test]
+fn repo_in_unknown_format_errs() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    create_repo_with_version(&config, &Uuid::new_v4().to_string())?;
+
     let result = OpenOptions::new().open::<KeyRepo<String>, _>(&config);
     assert!(matches!(result, Err(acid_store::Error::UnsupportedRepo)));
+    let report = OpenOptions::new().health_check(&config, 0)?;
+    assert!(!report.version_compatible);
+    Ok(())
+}
+
+#[test]
+fn objects_written_with_store_concurrency_are_committed() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let expected_data = vec![0x42u8; 1024 * 64];
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .chunking(Chunking::Fixed { size: 1024 })
+        .store_concurrency(4)
+        .open(&config)?;
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(&expected_data)?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+    repo.clean()?;
+    drop(repo);
+
+    let repo: KeyRepo<String> = OpenOptions::new().open(&config)?;
+    let mut object = repo.object("test").unwrap();
+    let mut actual_data = Vec::new();
+    object.read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+    assert!(repo.verify()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn cleaning_keeps_chunks_being_uploaded() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .chunking(Chunking::Fixed { size: 1024 })
+        .store_concurrency(4)
+        .open(&config)?;
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(&[0x42u8; 1024 * 16])?;
+
+    repo.clean()?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+
+    assert!(repo.verify()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn background_clean_removes_unreferenced_blocks() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let mut store = config.open()?;
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .chunking(Chunking::Fixed { size: 1024 })
+        .background_clean(true)
+        .open(&config)?;
+    let mut object = repo.insert(String::from("removed"));
+    object.write_all(&random_bytes(1024 * 32))?;
+    object.commit()?;
+    drop(object);
+    let mut object = repo.insert(String::from("kept"));
+    object.write_all(&random_bytes(1024 * 4))?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+    let committed_blocks = store.list_blocks()?.len();
+
+    repo.remove("removed");
+    repo.commit()?;
+
+    // Wait for the blocks of the removed object to be removed in the background.
+    let mut remaining_blocks = store.list_blocks()?.len();
+    for _ in 0..100 {
+        if remaining_blocks + 16 < committed_blocks {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+        remaining_blocks = store.list_blocks()?.len();
+    }
+    assert!(remaining_blocks + 16 < committed_blocks);
+
+    repo.rollback()?;
+    assert!(repo.verify()?.is_empty());
+    drop(repo);
+
+    let repo: KeyRepo<String> = OpenOptions::new().open(&config)?;
+    assert!(!repo.contains("removed"));
+    assert!(repo.verify()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn verify_with_multiple_threads_checks_every_chunk() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .chunking(Chunking::Fixed { size: 1024 })
+        .threads(4)
+        .store_concurrency(4)
+        .open(&config)?;
+    for i in 0..4u8 {
+        let mut object = repo.insert(format!("test{}", i));
+        object.write_all(&[i; 1024 * 16])?;
+        object.commit()?;
+    }
+    repo.commit()?;
+
+    let completed = Arc::new(AtomicU32::new(0));
+    let total = Arc::new(AtomicU32::new(0));
+    let completed_clone = Arc::clone(&completed);
+    let total_clone = Arc::clone(&total);
+    repo.set_progress_handler(move |progress| {
+        completed_clone.fetch_max(progress.completed as u32, Ordering::SeqCst);
+        total_clone.fetch_max(progress.total.unwrap_or(0) as u32, Ordering::SeqCst);
+    });
+
+    // The chunks of the object map are verified along with the chunks of each object.
+    assert!(repo.verify()?.is_empty());
+    assert!(total.load(Ordering::SeqCst) >= 4);
+    assert_eq!(
+        completed.load(Ordering::SeqCst),
+        total.load(Ordering::SeqCst)
+    );
+    Ok(())
+}
+
+#[test]
+fn prefetched_chunks_are_read_correctly() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let expected_data = (0..1024 * 64).map(|i| (i / 1024) as u8).collect::<Vec<_>>();
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .chunking(Chunking::Fixed { size: 1024 })
+        .store_concurrency(4)
+        .open(&config)?;
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(&expected_data)?;
+    object.commit()?;
+
+    // Read sequentially, then seek to the middle of a chunk and read again.
+    object.seek(SeekFrom::Start(0))?;
+    let mut actual_data = Vec::new();
+    object.read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+
+    object.seek(SeekFrom::Start(1024 * 10 + 512))?;
+    let mut actual_data = Vec::new();
+    object.read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, &expected_data[1024 * 10 + 512..]);
+    Ok(())
+}
+
+#[test]
+fn commit_with_multiple_threads_persists_changes() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .encryption(Encryption::XChaCha20Poly1305)
+        .compression(Compression::Lz4 { level: 2 })
+        .password(b"password")
+        .threads(4)
+        .open(&config)?;
+    for i in 0..100 {
+        let mut object = repo.insert(format!("test{}", i));
+        object.write_all(format!("data{}", i).as_bytes())?;
+        object.commit()?;
+    }
+    repo.commit()?;
+    drop(repo);
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .password(b"password")
+        .threads(4)
+        .open(&config)?;
+    for i in 0..100 {
+        let mut object = repo.object(&format!("test{}", i)).unwrap();
+        let mut actual_data = Vec::new();
+        object.read_to_end(&mut actual_data)?;
+        assert_eq!(actual_data, format!("data{}", i).as_bytes());
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "rayon")]
+fn objects_written_with_rayon_are_committed() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let mut expected_data = vec![0u8; 1024 * 64];
+    expected_data.extend((0..1024 * 64).map(|i| (i / 1024) as u8));
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .chunking(Chunking::Fixed { size: 1024 })
+        .encryption(Encryption::XChaCha20Poly1305)
+        .compression(Compression::Lz4 { level: 2 })
+        .password(b"password")
+        .threads(4)
+        .open(&config)?;
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(&expected_data)?;
+    object.commit()?;
+
+    let mut actual_data = Vec::new();
+    object.seek(SeekFrom::Start(0))?;
+    object.read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+    assert!(object.verify()?);
+    Ok(())
+}
+
+#[test]
+fn transient_store_errors_are_retried() -> anyhow::Result<()> {
+    let config = FlakyConfig(MemoryConfig::new(), io::ErrorKind::TimedOut);
+    let mut policy = RetryPolicy::new();
+    policy
+        .max_attempts(2)
+        .delay(Duration::from_millis(1), Duration::from_millis(1));
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .retry_policy(policy.clone())
+        .store_concurrency(2)
+        .open(&config)?;
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(b"data")?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+    drop(repo);
+
+    let repo: KeyRepo<String> = OpenOptions::new().retry_policy(policy).open(&config)?;
+    let mut actual_data = Vec::new();
+    repo.object("test").unwrap().read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, b"data");
+    Ok(())
+}
+
+#[test]
+fn fatal_store_errors_are_not_retried() -> anyhow::Result<()> {
+    let config = FlakyConfig(MemoryConfig::new(), io::ErrorKind::PermissionDenied);
+    let mut policy = RetryPolicy::new();
+    policy
+        .max_attempts(2)
+        .delay(Duration::from_millis(1), Duration::from_millis(1));
+
+    let result = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .retry_policy(policy)
+        .open::<KeyRepo<String>, _>(&config);
+    assert!(matches!(
+        result,
+        Err(acid_store::Error::PermissionDenied(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn cached_chunks_are_read_without_the_data_store() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let expected_data = random_buffer();
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .chunking(Chunking::Fixed { size: 1024 })
+        .chunk_cache_size(expected_data.len())
+        .open(&config)?;
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(&expected_data)?;
+    object.commit()?;
+    drop(object);
+
+    let mut actual_data = Vec::new();
+    repo.object("test").unwrap().read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+
+    // Every chunk is now in the cache, so they can be read even if the data store is empty.
+    truncate_store(&mut config.open()?)?;
+
+    let mut actual_data = Vec::new();
+    repo.object("test").unwrap().read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+    Ok(())
+}
+
+#[test]
+fn buffered_chunks_are_written_on_commit() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let mut repo_config = RepoConfig::default();
+    repo_config.chunking = Chunking::Fixed { size: 256 };
+    repo_config.write_buffer_size = 1024 * 64;
+    let expected_data = random_buffer();
+
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .config(repo_config)
+        .open(&config)?;
+    let mut object = repo.insert(String::from("test"));
+    for piece in expected_data.chunks(100) {
+        object.write_all(piece)?;
+    }
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+    drop(repo);
+
+    let repo: KeyRepo<String> = OpenOptions::new().open(&config)?;
+    assert_eq!(repo.info().config().write_buffer_size, 1024 * 64);
+    let mut actual_data = Vec::new();
+    repo.object("test").unwrap().read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+    Ok(())
+}
+
+#[test]
+fn health_check_reports_healthy_repo() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .chunking(Chunking::Fixed { size: 256 })
+        .open(&config)?;
+
+    let report = OpenOptions::new().health_check(&config, 10)?;
+    assert!(report.is_healthy());
+    assert_eq!(report.lock, LockState::Locked);
+    assert_eq!(report.last_commit, None);
+
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(&random_buffer())?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+    drop(repo);
+
+    let report = OpenOptions::new().health_check(&config, 5)?;
+    assert!(report.is_healthy());
+    assert!(report.version_compatible);
+    assert!(report.header_intact);
+    assert_eq!(report.lock, LockState::Unlocked);
+    assert!(report.last_commit.is_some());
+    assert_eq!(report.sampled_blocks, 5);
+    assert_eq!(report.missing_blocks, 0);
+    Ok(())
+}
+
+#[test]
+fn health_check_detects_missing_blocks() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .chunking(Chunking::Fixed { size: 256 })
+        .open(&config)?;
+    repo.commit()?;
+    let empty_blocks = config.open()?.list_blocks()?;
+
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(&random_buffer())?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+    drop(repo);
+
+    // Remove each new block in turn. Some of them store data and some store the header.
+    let mut found_missing_block = false;
+    let mut found_corrupt_header = false;
+    for block_id in config.open()?.list_blocks()? {
+        if empty_blocks.contains(&block_id) {
+            continue;
+        }
+        let damaged_config = copy_store(&config)?;
+        damaged_config.open()?.remove_block(block_id)?;
+        let report = OpenOptions::new().health_check(&damaged_config, usize::MAX)?;
+        assert!(!report.is_healthy());
+        found_missing_block |= report.header_intact && report.missing_blocks == 1;
+        found_corrupt_header |= !report.header_intact;
+    }
+
+    assert!(found_missing_block);
+    assert!(found_corrupt_header);
+    Ok(())
+}
+
+#[test]
+fn health_check_reports_lease() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let mut repo_config = RepoConfig::default();
+    repo_config.lease_duration = Some(Duration::from_secs(60 * 60));
+
+    // Use optimistic concurrency so that the repository isn't also locked in this process.
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .config(repo_config)
+        .optimistic_concurrency(true)
+        .open(&config)?;
+
+    let report = OpenOptions::new().health_check(&config, 10)?;
+    assert!(matches!(report.lock, LockState::Leased { .. }));
+    assert!(report.is_healthy());
+
+    drop(repo);
+    let report = OpenOptions::new().health_check(&config, 10)?;
+    assert_eq!(report.lock, LockState::Unlocked);
     Ok(())
 }
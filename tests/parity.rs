@@ -0,0 +1,176 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(all(
+    feature = "erasure-coding",
+    feature = "encryption",
+    feature = "compression"
+))]
+
+use std::io::{Read, Write};
+
+use uuid::Uuid;
+
+use acid_store::repo::key::KeyRepo;
+use acid_store::repo::{Commit, OpenMode, OpenOptions, Parity};
+use acid_store::store::{DataStore, MemoryConfig, OpenStore};
+use common::{random_bytes, FIXED_CONFIG};
+
+mod common;
+
+/// The size of the chunks produced by `FIXED_CONFIG`.
+const CHUNK_SIZE: usize = 256;
+
+/// Create a repository with parity blocks which contains a single committed object.
+///
+/// This returns the repository and the contents of the object, which spans four data blocks. Each
+/// parity group is large enough to hold those blocks along with the blocks of the object map, so
+/// every block in the repository is in the same group.
+fn create_repo(config: &MemoryConfig) -> anyhow::Result<(KeyRepo<String>, Vec<u8>)> {
+    let mut repo_config = FIXED_CONFIG.to_owned();
+    repo_config.parity = Parity::ReedSolomon {
+        data_blocks: 8,
+        parity_blocks: 2,
+    };
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(repo_config)
+        .mode(OpenMode::CreateNew)
+        .open(config)?;
+
+    let expected_data = random_bytes(CHUNK_SIZE * 4);
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(expected_data.as_slice())?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+
+    Ok((repo, expected_data))
+}
+
+/// Return the IDs of the blocks in the data store which store each chunk of `data`.
+///
+/// This only works when data is not encrypted, compressed, or packed.
+fn data_blocks(config: &MemoryConfig, data: &[u8]) -> anyhow::Result<Vec<Uuid>> {
+    let mut store = config.open()?;
+    let mut blocks = Vec::new();
+    for chunk in data.chunks(CHUNK_SIZE) {
+        for id in store.list_blocks()? {
+            if store.read_block(id)?.unwrap().starts_with(chunk) {
+                blocks.push(id);
+            }
+        }
+    }
+    Ok(blocks)
+}
+
+fn read_object(repo: &KeyRepo<String>) -> anyhow::Result<Vec<u8>> {
+    let mut actual_data = Vec::new();
+    repo.object("test").unwrap().read_to_end(&mut actual_data)?;
+    Ok(actual_data)
+}
+
+#[test]
+fn repair_reconstructs_missing_blocks() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let (repo, expected_data) = create_repo(&config)?;
+
+    let damaged_blocks = data_blocks(&config, &expected_data)?[..2].to_vec();
+    let mut store = config.open()?;
+    for id in &damaged_blocks {
+        store.remove_block(*id)?;
+    }
+
+    let report = repo.repair()?;
+
+    assert!(report.is_complete());
+    assert_eq!(report.groups, 1);
+    common::assert_contains_all(report.repaired_blocks, damaged_blocks);
+    assert_eq!(read_object(&repo)?, expected_data);
+    Ok(())
+}
+
+#[test]
+fn repair_reconstructs_corrupt_blocks() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let (repo, expected_data) = create_repo(&config)?;
+
+    let damaged_block = data_blocks(&config, &expected_data)?[0];
+    let mut store = config.open()?;
+    let mut corrupt_data = store.read_block(damaged_block)?.unwrap();
+    corrupt_data[0] ^= 0xff;
+    store.write_block(damaged_block, &corrupt_data)?;
+
+    let report = repo.repair()?;
+
+    assert!(report.is_complete());
+    assert_eq!(report.repaired_blocks, vec![damaged_block]);
+    assert_eq!(read_object(&repo)?, expected_data);
+    Ok(())
+}
+
+#[test]
+fn repair_reports_unrecoverable_blocks() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let (repo, expected_data) = create_repo(&config)?;
+
+    let damaged_blocks = data_blocks(&config, &expected_data)?[..3].to_vec();
+    let mut store = config.open()?;
+    for id in &damaged_blocks {
+        store.remove_block(*id)?;
+    }
+
+    let report = repo.repair()?;
+
+    assert!(!report.is_complete());
+    assert!(report.repaired_blocks.is_empty());
+    common::assert_contains_all(report.unrecoverable_blocks, damaged_blocks);
+    Ok(())
+}
+
+#[test]
+fn parity_blocks_are_not_cleaned() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let (repo, expected_data) = create_repo(&config)?;
+    repo.clean()?;
+
+    let damaged_block = data_blocks(&config, &expected_data)?[0];
+    config.open()?.remove_block(damaged_block)?;
+
+    let report = repo.repair()?;
+
+    assert_eq!(report.repaired_blocks, vec![damaged_block]);
+    assert_eq!(read_object(&repo)?, expected_data);
+    Ok(())
+}
+
+#[test]
+fn repair_without_parity_does_nothing() -> anyhow::Result<()> {
+    let repo: KeyRepo<String> = OpenOptions::new()
+        .config(FIXED_CONFIG.to_owned())
+        .mode(OpenMode::CreateNew)
+        .open(&MemoryConfig::new())?;
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(random_bytes(CHUNK_SIZE).as_slice())?;
+    object.commit()?;
+    drop(object);
+    repo.commit()?;
+
+    let report = repo.repair()?;
+
+    assert_eq!(report.groups, 0);
+    assert!(report.repaired_blocks.is_empty());
+    Ok(())
+}
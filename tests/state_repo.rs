@@ -29,7 +29,7 @@ fn create_repo(config: &MemoryConfig) -> acid_store::Result<StateRepo<String>> {
 #[test]
 fn open_repository() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.commit()?;
     drop(repository);
     OpenOptions::new().open::<StateRepo<String>, _>(&config)?;
@@ -39,14 +39,14 @@ fn open_repository() -> anyhow::Result<()> {
 #[test]
 fn state_is_persisted_on_commit() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     *repo.state_mut() = String::from("New state");
     repo.commit()?;
     drop(repo);
     let repo: StateRepo<String> = OpenOptions::new().open(&config)?;
 
-    assert_eq!(repo.state(), "New state");
+    assert_eq!(*repo.state(), "New state");
 
     Ok(())
 }
@@ -54,14 +54,14 @@ fn state_is_persisted_on_commit() -> anyhow::Result<()> {
 #[test]
 fn state_is_rolled_back() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     *repo.state_mut() = String::from("Initial state");
     repo.commit()?;
     *repo.state_mut() = String::from("New state");
     repo.rollback()?;
 
-    assert_eq!(repo.state(), "Initial state");
+    assert_eq!(*repo.state(), "Initial state");
 
     Ok(())
 }
@@ -69,14 +69,14 @@ fn state_is_rolled_back() -> anyhow::Result<()> {
 #[test]
 fn state_is_restored_by_savepoint() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     *repo.state_mut() = String::from("Initial state");
     let savepoint = repo.savepoint()?;
     *repo.state_mut() = String::from("New state");
     repo.restore(&savepoint)?;
 
-    assert_eq!(repo.state(), "Initial state");
+    assert_eq!(*repo.state(), "Initial state");
 
     Ok(())
 }
@@ -84,14 +84,14 @@ fn state_is_restored_by_savepoint() -> anyhow::Result<()> {
 #[test]
 fn state_is_defaulted_on_clear_instance() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     *repo.state_mut() = String::from("Initial state");
     repo.commit()?;
 
     repo.clear_instance();
 
-    assert_eq!(repo.state(), &String::default());
+    assert_eq!(*repo.state(), String::default());
 
     Ok(())
 }
@@ -99,11 +99,11 @@ fn state_is_defaulted_on_clear_instance() -> anyhow::Result<()> {
 #[test]
 fn ids_from_different_instances_are_not_valid() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     let id = repo.create();
 
-    let mut repo: StateRepo<String> = repo.switch_instance(Uuid::new_v4())?;
+    let repo: StateRepo<String> = repo.switch_instance(Uuid::new_v4())?;
 
     assert!(!repo.contains(id));
     assert!(!repo.remove(id));
@@ -0,0 +1,205 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(all(feature = "encryption", feature = "compression"))]
+
+use std::io::{Read, Write};
+
+use acid_store::repo::key::KeyRepo;
+use acid_store::repo::value::ValueRepo;
+use acid_store::repo::{Commit, OpenMode, OpenOptions, SwitchBranch, DEFAULT_BRANCH};
+use acid_store::store::MemoryConfig;
+
+use common::random_buffer;
+
+mod common;
+
+fn create_repo(store_config: &MemoryConfig) -> acid_store::Result<KeyRepo<String>> {
+    OpenOptions::new()
+        .config(common::FIXED_CONFIG.to_owned())
+        .mode(OpenMode::CreateNew)
+        .open(store_config)
+}
+
+#[test]
+fn new_repo_has_default_branch() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(&store_config)?;
+
+    assert_eq!(repo.branch(), DEFAULT_BRANCH);
+    assert_eq!(repo.branches().collect::<Vec<_>>(), vec![DEFAULT_BRANCH]);
+
+    Ok(())
+}
+
+#[test]
+fn branch_contains_copy_of_contents() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(&store_config)?;
+
+    let expected_data = random_buffer();
+    let mut object = repo.insert(String::from("test"));
+    object.write_all(&expected_data)?;
+    object.commit()?;
+    drop(object);
+
+    let repo = repo.create_branch("staging")?;
+    assert_eq!(repo.branch(), DEFAULT_BRANCH);
+
+    let repo = repo.switch_branch("staging")?;
+    assert_eq!(repo.branch(), "staging");
+
+    let mut actual_data = Vec::new();
+    repo.object("test").unwrap().read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, expected_data);
+
+    repo.remove("test");
+    let repo = repo.switch_branch(DEFAULT_BRANCH)?;
+    assert!(repo.contains("test"));
+
+    Ok(())
+}
+
+#[test]
+fn creating_existing_branch_errs() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(&store_config)?;
+
+    let repo = repo.create_branch("staging")?;
+
+    assert!(matches!(
+        repo.create_branch("staging"),
+        Err(acid_store::Error::AlreadyExists)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn switching_to_nonexistent_branch_errs() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(&store_config)?;
+
+    assert!(matches!(
+        repo.switch_branch("staging"),
+        Err(acid_store::Error::NotFound)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn remove_branch() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(&store_config)?;
+
+    let repo = repo.create_branch("staging")?;
+
+    assert!(matches!(
+        repo.remove_branch(DEFAULT_BRANCH),
+        Err(acid_store::Error::CurrentBranch)
+    ));
+
+    repo.remove_branch("staging")?;
+
+    assert_eq!(repo.branches().collect::<Vec<_>>(), vec![DEFAULT_BRANCH]);
+    assert!(matches!(
+        repo.remove_branch("staging"),
+        Err(acid_store::Error::NotFound)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn branches_are_persisted_on_commit() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo: ValueRepo<String> = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .open(&store_config)?;
+
+    repo.insert(String::from("key"), &"production")?;
+    let repo = repo.create_branch("staging")?;
+    let repo = repo.switch_branch("staging")?;
+    repo.insert(String::from("key"), &"staging")?;
+    repo.commit()?;
+    drop(repo);
+
+    let repo: ValueRepo<String> = OpenOptions::new().open(&store_config)?;
+    assert_eq!(repo.branch(), "staging");
+    assert_eq!(repo.get::<_, String>("key")?, "staging");
+
+    let repo = repo.switch_branch(DEFAULT_BRANCH)?;
+    assert_eq!(repo.get::<_, String>("key")?, "production");
+
+    Ok(())
+}
+
+#[test]
+fn merge_branch_applies_non_conflicting_changes() -> anyhow::Result<()> {
+    let store_config = MemoryConfig::new();
+    let repo = create_repo(&store_config)?;
+
+    for key in &["unchanged", "modified", "removed", "conflict"] {
+        let mut object = repo.insert(key.to_string());
+        object.write_all(&random_buffer())?;
+        object.commit()?;
+    }
+
+    let repo = repo.create_branch("base")?;
+    let repo = repo.create_branch("feature")?;
+    let repo = repo.switch_branch("feature")?;
+
+    let modified_data = random_buffer();
+    let mut object = repo.insert(String::from("modified"));
+    object.write_all(&modified_data)?;
+    object.commit()?;
+    drop(object);
+    repo.remove("removed");
+    repo.insert(String::from("added"));
+    let mut object = repo.insert(String::from("conflict"));
+    object.write_all(&random_buffer())?;
+    object.commit()?;
+    drop(object);
+
+    let repo = repo.switch_branch(DEFAULT_BRANCH)?;
+    let current_conflict_data = random_buffer();
+    let mut object = repo.insert(String::from("conflict"));
+    object.write_all(&current_conflict_data)?;
+    object.commit()?;
+    drop(object);
+
+    let conflicts = repo.merge_branch("feature", "base")?;
+
+    assert_eq!(conflicts, vec![String::from("conflict")]);
+    assert!(repo.contains("unchanged"));
+    assert!(repo.contains("added"));
+    assert!(!repo.contains("removed"));
+
+    let mut actual_data = Vec::new();
+    repo.object("modified")
+        .unwrap()
+        .read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, modified_data);
+
+    let mut actual_data = Vec::new();
+    repo.object("conflict")
+        .unwrap()
+        .read_to_end(&mut actual_data)?;
+    assert_eq!(actual_data, current_conflict_data);
+
+    Ok(())
+}
@@ -44,10 +44,11 @@ fn create_repo(
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn switching_instance_does_not_roll_back(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
 
     let mut object = repo.insert(String::from("test"));
     object.write_all(random_buffer().as_slice())?;
@@ -68,10 +69,11 @@ fn switching_instance_does_not_roll_back(repo_config: RepoConfig) -> anyhow::Res
 #[test_case(common::ZPAQ_CONFIG.to_owned(); "with ZPAQ chunking")]
 #[test_case(common::FIXED_PACKING_SMALL_CONFIG.to_owned(); "with a pack size smaller than the chunk size")]
 #[test_case(common::FIXED_PACKING_LARGE_CONFIG.to_owned(); "with a pack size larger than the chunk size")]
+#[test_case(common::VARIABLE_PACKING_CONFIG.to_owned(); "with variable-size packing")]
 #[test_case(common::ZPAQ_PACKING_CONFIG.to_owned(); "with packing and ZPAQ chunking")]
 fn switching_instance_does_not_commit(repo_config: RepoConfig) -> anyhow::Result<()> {
     let store_config = MemoryConfig::new();
-    let mut repo = create_repo(repo_config, &store_config)?;
+    let repo = create_repo(repo_config, &store_config)?;
 
     let mut object = repo.insert(String::from("test"));
     object.write_all(random_buffer().as_slice())?;
@@ -79,7 +81,7 @@ fn switching_instance_does_not_commit(repo_config: RepoConfig) -> anyhow::Result
     drop(object);
 
     let repo: KeyRepo<String> = repo.switch_instance(Uuid::new_v4())?;
-    let mut repo: KeyRepo<String> = repo.switch_instance(DEFAULT_INSTANCE)?;
+    let repo: KeyRepo<String> = repo.switch_instance(DEFAULT_INSTANCE)?;
     repo.rollback()?;
 
     assert!(!repo.contains("test"));
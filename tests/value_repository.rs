@@ -34,7 +34,7 @@ fn create_repo(config: &MemoryConfig) -> acid_store::Result<ValueRepo<String>> {
 #[test]
 fn open_repository() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.commit()?;
     drop(repository);
     OpenOptions::new().open::<ValueRepo<String>, _>(&config)?;
@@ -44,7 +44,7 @@ fn open_repository() -> anyhow::Result<()> {
 #[test]
 fn switching_instance_does_not_roll_back() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     repo.insert("test".to_string(), &SERIALIZABLE_VALUE)?;
 
@@ -60,12 +60,12 @@ fn switching_instance_does_not_roll_back() -> anyhow::Result<()> {
 #[test]
 fn switching_instance_does_not_commit() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     repo.insert("test".to_string(), &SERIALIZABLE_VALUE)?;
 
     let repo: ValueRepo<String> = repo.switch_instance(Uuid::new_v4())?;
-    let mut repo: ValueRepo<String> = repo.switch_instance(DEFAULT_INSTANCE)?;
+    let repo: ValueRepo<String> = repo.switch_instance(DEFAULT_INSTANCE)?;
     repo.rollback()?;
 
     assert!(!repo.contains("test"));
@@ -80,7 +80,7 @@ fn switching_instance_does_not_commit() -> anyhow::Result<()> {
 #[test]
 fn insert_value() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.insert("Key".into(), &SERIALIZABLE_VALUE)?;
     let actual: (bool, i32) = repository.get("Key")?;
     assert_eq!(actual, SERIALIZABLE_VALUE);
@@ -90,7 +90,7 @@ fn insert_value() -> anyhow::Result<()> {
 #[test]
 fn remove_value() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     assert!(!repository.remove("Key"));
     assert!(!repository.contains("Key"));
@@ -107,7 +107,7 @@ fn remove_value() -> anyhow::Result<()> {
 #[test]
 fn deserializing_value_to_wrong_type_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.insert("Key".into(), &SERIALIZABLE_VALUE)?;
     let actual = repository.get::<_, String>("Key");
     assert!(matches!(actual, Err(acid_store::Error::Deserialize)));
@@ -117,13 +117,13 @@ fn deserializing_value_to_wrong_type_errs() -> anyhow::Result<()> {
 #[test]
 fn list_keys() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.insert("Key1".into(), &SERIALIZABLE_VALUE)?;
     repository.insert("Key2".into(), &SERIALIZABLE_VALUE)?;
     repository.insert("Key3".into(), &SERIALIZABLE_VALUE)?;
 
     let expected = vec!["Key1".to_string(), "Key2".to_string(), "Key3".to_string()];
-    let actual = repository.keys().cloned().collect::<Vec<_>>();
+    let actual = repository.keys().collect::<Vec<_>>();
 
     assert_contains_all(actual, expected);
     Ok(())
@@ -132,7 +132,7 @@ fn list_keys() -> anyhow::Result<()> {
 #[test]
 fn values_removed_on_rollback() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.insert("test".into(), &SERIALIZABLE_VALUE)?;
 
     repository.rollback()?;
@@ -150,7 +150,7 @@ fn values_removed_on_rollback() -> anyhow::Result<()> {
 #[test]
 fn clear_instance_removes_keys() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     repo.insert("test".into(), &SERIALIZABLE_VALUE)?;
 
@@ -168,7 +168,7 @@ fn clear_instance_removes_keys() -> anyhow::Result<()> {
 #[test]
 fn rollback_after_clear_instance() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     repo.insert("test".into(), &SERIALIZABLE_VALUE)?;
 
@@ -185,9 +185,47 @@ fn rollback_after_clear_instance() -> anyhow::Result<()> {
 #[test]
 fn verify_valid_repository_is_valid() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.insert("Test".into(), &SERIALIZABLE_VALUE)?;
 
     assert!(repository.verify()?.is_empty());
     Ok(())
 }
+
+#[test]
+fn compare_and_swap_replaces_matching_value() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repo = create_repo(&config)?;
+    repo.insert("Key".into(), &SERIALIZABLE_VALUE)?;
+
+    assert!(repo.compare_and_swap("Key".into(), Some(&SERIALIZABLE_VALUE), &(false, 7))?);
+    assert_eq!(repo.get::<_, (bool, i32)>("Key")?, (false, 7));
+
+    Ok(())
+}
+
+#[test]
+fn compare_and_swap_does_not_replace_mismatched_value() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repo = create_repo(&config)?;
+    repo.insert("Key".into(), &SERIALIZABLE_VALUE)?;
+
+    assert!(!repo.compare_and_swap("Key".into(), Some(&(false, 0)), &(false, 7))?);
+    assert!(!repo.compare_and_swap::<(bool, i32), _>("Key".into(), None, &(false, 7))?);
+    assert_eq!(repo.get::<_, (bool, i32)>("Key")?, SERIALIZABLE_VALUE);
+
+    Ok(())
+}
+
+#[test]
+fn compare_and_swap_with_none_inserts_missing_value() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repo = create_repo(&config)?;
+
+    assert!(repo.compare_and_swap::<(bool, i32), _>("Key".into(), None, &SERIALIZABLE_VALUE)?);
+    assert!(!repo.compare_and_swap("Other".into(), Some(&SERIALIZABLE_VALUE), &(false, 7))?);
+    assert_eq!(repo.get::<_, (bool, i32)>("Key")?, SERIALIZABLE_VALUE);
+    assert!(!repo.contains("Other"));
+
+    Ok(())
+}
@@ -16,13 +16,15 @@
 
 #![cfg(all(feature = "encryption", feature = "compression"))]
 
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::iter;
 
-use acid_store::repo::version::VersionRepo;
+use acid_store::repo::key::KeyRepo;
+use acid_store::repo::version::{RetentionPolicy, VersionRepo};
 use acid_store::repo::{Commit, OpenMode, OpenOptions, SwitchInstance, DEFAULT_INSTANCE};
 use acid_store::store::MemoryConfig;
 use acid_store::uuid::Uuid;
-use common::{assert_contains_all, random_buffer};
+use common::{assert_contains_all, random_buffer, random_bytes};
 
 mod common;
 
@@ -33,7 +35,7 @@ fn create_repo(config: &MemoryConfig) -> acid_store::Result<VersionRepo<String>>
 #[test]
 fn open_repository() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
     repository.commit()?;
     drop(repository);
     OpenOptions::new().open::<VersionRepo<String>, _>(&config)?;
@@ -43,7 +45,7 @@ fn open_repository() -> anyhow::Result<()> {
 #[test]
 fn switching_instance_does_not_roll_back() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     let mut object = repo.insert("test".to_string()).unwrap();
     object.write_all(random_buffer().as_slice())?;
@@ -62,7 +64,7 @@ fn switching_instance_does_not_roll_back() -> anyhow::Result<()> {
 #[test]
 fn switching_instance_does_not_commit() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     let mut object = repo.insert("test".to_string()).unwrap();
     object.write_all(random_buffer().as_slice())?;
@@ -70,7 +72,7 @@ fn switching_instance_does_not_commit() -> anyhow::Result<()> {
     drop(object);
 
     let repo: VersionRepo<String> = repo.switch_instance(Uuid::new_v4())?;
-    let mut repo: VersionRepo<String> = repo.switch_instance(DEFAULT_INSTANCE)?;
+    let repo: VersionRepo<String> = repo.switch_instance(DEFAULT_INSTANCE)?;
     repo.rollback()?;
 
     assert!(!repo.contains("test"));
@@ -82,7 +84,7 @@ fn switching_instance_does_not_commit() -> anyhow::Result<()> {
 #[test]
 fn read_version() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     // Add a new object and write data to it.
     let expected_data = random_buffer();
@@ -110,7 +112,7 @@ fn read_version() -> anyhow::Result<()> {
 #[test]
 fn list_versions() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.insert("Key".into()).unwrap();
     let version1 = repository.create_version("Key").unwrap();
@@ -129,7 +131,7 @@ fn list_versions() -> anyhow::Result<()> {
 #[test]
 fn remove_version() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.insert(String::from("Key")).unwrap();
     let version = repository.create_version("Key").unwrap();
@@ -142,7 +144,7 @@ fn remove_version() -> anyhow::Result<()> {
 #[test]
 fn remove_and_list_versions() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.insert("Key".into()).unwrap();
     let version1 = repository.create_version("Key").unwrap();
@@ -162,7 +164,7 @@ fn remove_and_list_versions() -> anyhow::Result<()> {
 #[test]
 fn remove_and_get_version() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.insert("Key".into()).unwrap();
     let version = repository.create_version("Key").unwrap();
@@ -180,7 +182,7 @@ fn remove_and_get_version() -> anyhow::Result<()> {
 #[test]
 fn versioning_nonexistent_key_errs() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     assert!(repository.create_version("Key").is_none());
     assert!(!repository.remove_version("Key", 1));
@@ -191,7 +193,7 @@ fn versioning_nonexistent_key_errs() -> anyhow::Result<()> {
 #[test]
 fn removing_key_removes_versions() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.insert("Key".into()).unwrap();
     let version = repository.create_version("Key").unwrap();
@@ -206,7 +208,7 @@ fn removing_key_removes_versions() -> anyhow::Result<()> {
 #[test]
 fn restore_version() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     let expected_data = random_buffer();
 
@@ -240,7 +242,7 @@ fn restore_version() -> anyhow::Result<()> {
 #[test]
 fn modifying_object_doesnt_modify_versions() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     repository.insert(String::from("Key")).unwrap();
     let version = repository.create_version("Key").unwrap();
@@ -259,7 +261,7 @@ fn modifying_object_doesnt_modify_versions() -> anyhow::Result<()> {
 #[test]
 fn objects_removed_on_rollback() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repository = create_repo(&config)?;
+    let repository = create_repo(&config)?;
 
     let mut object = repository.insert("test".into()).unwrap();
     object.write_all(random_buffer().as_slice())?;
@@ -280,7 +282,7 @@ fn objects_removed_on_rollback() -> anyhow::Result<()> {
 #[test]
 fn clear_instance_removes_keys() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     let mut object = repo.insert("test".into()).unwrap();
     object.write_all(random_buffer().as_slice())?;
@@ -299,7 +301,7 @@ fn clear_instance_removes_keys() -> anyhow::Result<()> {
 #[test]
 fn rollback_after_clear_instance() -> anyhow::Result<()> {
     let config = MemoryConfig::new();
-    let mut repo = create_repo(&config)?;
+    let repo = create_repo(&config)?;
 
     let mut object = repo.insert("test".into()).unwrap();
     object.write_all(random_buffer().as_slice())?;
@@ -316,3 +318,244 @@ fn rollback_after_clear_instance() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn prune_keeps_last_versions() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.insert("Key".into()).unwrap();
+
+    for _ in 0..5 {
+        repository.create_version("Key").unwrap();
+    }
+
+    let mut policy = RetentionPolicy::default();
+    policy.keep_last = Some(2);
+
+    assert_eq!(repository.prune("Key", &policy), Some(vec![1, 2, 3]));
+    let remaining = repository
+        .versions("Key")
+        .unwrap()
+        .map(|version| version.id())
+        .collect::<Vec<_>>();
+    assert_eq!(remaining, vec![4, 5]);
+    assert!(repository.object("Key").is_some());
+
+    Ok(())
+}
+
+#[test]
+fn prune_all_removes_versions_of_every_key() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.insert("Key1".into()).unwrap();
+    repository.insert("Key2".into()).unwrap();
+
+    for _ in 0..3 {
+        repository.create_version("Key1").unwrap();
+        repository.create_version("Key2").unwrap();
+    }
+
+    let mut policy = RetentionPolicy::default();
+    policy.keep_last = Some(1);
+
+    assert_eq!(repository.prune_all(&policy), 4);
+    assert!(repository.get_version("Key1", 3).is_some());
+    assert!(repository.get_version("Key2", 3).is_some());
+    assert!(repository.get_version("Key1", 1).is_none());
+    assert!(repository.get_version("Key2", 2).is_none());
+
+    Ok(())
+}
+
+#[test]
+fn default_retention_policy_keeps_all_versions() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.insert("Key".into()).unwrap();
+    repository.create_version("Key").unwrap();
+    repository.create_version("Key").unwrap();
+
+    assert_eq!(
+        repository.prune("Key", &RetentionPolicy::default()),
+        Some(Vec::new())
+    );
+    assert_eq!(
+        repository.prune("Missing", &RetentionPolicy::default()),
+        None
+    );
+    assert_eq!(repository.versions("Key").unwrap().count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn find_version_by_label() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.insert("Key".into()).unwrap();
+    let first = repository.create_version("Key").unwrap();
+    let second = repository.create_version("Key").unwrap();
+
+    repository.set_version_label("Key", first.id(), Some("v1.0"))?;
+    repository.set_version_label("Key", second.id(), Some("v1.1"))?;
+
+    let found = repository.find_version("Key", "v1.1").unwrap();
+    assert_eq!(found.id(), second.id());
+    assert_eq!(found.label(), Some("v1.1"));
+    assert!(repository.find_version("Key", "v2.0").is_none());
+
+    repository.set_version_label("Key", second.id(), None)?;
+    assert!(repository.find_version("Key", "v1.1").is_none());
+
+    Ok(())
+}
+
+#[test]
+fn duplicate_version_label_errs() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.insert("Key".into()).unwrap();
+    let first = repository.create_version("Key").unwrap();
+    let second = repository.create_version("Key").unwrap();
+
+    repository.set_version_label("Key", first.id(), Some("release"))?;
+
+    assert!(matches!(
+        repository.set_version_label("Key", second.id(), Some("release")),
+        Err(acid_store::Error::AlreadyExists)
+    ));
+    assert!(matches!(
+        repository.set_version_label("Key", 100, Some("other")),
+        Err(acid_store::Error::NotFound)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn version_metadata_is_persisted() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    repository.insert("Key".into()).unwrap();
+    let version = repository.create_version("Key").unwrap();
+
+    assert!(matches!(
+        repository.version_metadata::<_, String>("Key", version.id()),
+        Err(acid_store::Error::NotFound)
+    ));
+
+    repository.set_version_metadata("Key", version.id(), &"pre-migration".to_string())?;
+    repository.commit()?;
+    drop(repository);
+
+    let repository: VersionRepo<String> = OpenOptions::new().open(&config)?;
+    let metadata: String = repository.version_metadata("Key", version.id())?;
+    assert_eq!(metadata, "pre-migration");
+
+    Ok(())
+}
+
+#[test]
+fn diff_versions_reports_changed_ranges() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository: VersionRepo<String> = OpenOptions::new()
+        .config(common::FIXED_CONFIG.to_owned())
+        .mode(OpenMode::CreateNew)
+        .open(&config)?;
+
+    let mut object = repository.insert("Key".into()).unwrap();
+    object.write_all(&random_bytes(1024))?;
+    object.commit()?;
+    drop(object);
+    let original = repository.create_version("Key").unwrap();
+
+    let mut object = repository.object("Key").unwrap();
+    object.seek(SeekFrom::End(0))?;
+    object.write_all(&random_bytes(256))?;
+    object.commit()?;
+    drop(object);
+    let extended = repository.create_version("Key").unwrap();
+
+    let mut object = repository.object("Key").unwrap();
+    object.set_len(512)?;
+    drop(object);
+    let truncated = repository.create_version("Key").unwrap();
+
+    assert_eq!(
+        repository.diff_versions("Key", original.id(), extended.id()),
+        Some(iter::once(1024..1280).collect())
+    );
+    assert_eq!(
+        repository.diff_versions("Key", original.id(), truncated.id()),
+        Some(iter::once(512..1024).collect())
+    );
+    assert_eq!(
+        repository.diff_versions("Key", original.id(), original.id()),
+        Some(Vec::new())
+    );
+    assert_eq!(repository.diff_versions("Key", original.id(), 100), None);
+
+    Ok(())
+}
+
+#[test]
+fn versions_include_size_and_content_id() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let repository = create_repo(&config)?;
+    let mut object = repository.insert("Key".into()).unwrap();
+
+    let mut expected_sizes = Vec::new();
+    for _ in 0..3 {
+        let data = random_buffer();
+        object.set_len(0)?;
+        object.seek(SeekFrom::Start(0))?;
+        object.write_all(&data)?;
+        object.commit()?;
+        expected_sizes.push(data.len() as u64);
+        repository.create_version("Key").unwrap();
+    }
+    drop(object);
+
+    let versions = repository.versions("Key").unwrap().collect::<Vec<_>>();
+    let actual_sizes = versions
+        .iter()
+        .map(|version| version.size())
+        .collect::<Vec<_>>();
+
+    assert_eq!(actual_sizes, expected_sizes);
+    assert!(versions
+        .windows(2)
+        .all(|pair| pair[0].created() <= pair[1].created()));
+    for version in versions {
+        let version_object = repository.version_object("Key", version.id()).unwrap();
+        assert_eq!(&version_object.content_id()?, version.content_id());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn convert_key_repo_into_version_repo() -> anyhow::Result<()> {
+    let config = MemoryConfig::new();
+    let key_repo: KeyRepo<String> = OpenOptions::new().mode(OpenMode::CreateNew).open(&config)?;
+    let expected_data = random_buffer();
+
+    let mut object = key_repo.insert(String::from("test"));
+    object.write_all(expected_data.as_slice())?;
+    object.commit()?;
+    drop(object);
+
+    let repo = VersionRepo::from_key_repo(key_repo)?;
+    repo.commit()?;
+    drop(repo);
+
+    let repo: VersionRepo<String> = OpenOptions::new().open(&config)?;
+    let mut object = repo.object("test").unwrap();
+    let mut actual_data = Vec::new();
+    object.read_to_end(&mut actual_data)?;
+
+    assert_eq!(actual_data, expected_data);
+    assert_eq!(repo.versions("test").unwrap().count(), 0);
+    Ok(())
+}
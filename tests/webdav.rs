@@ -0,0 +1,136 @@
+/*
+ * Copyright 2019-2021 Wren Powell
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+#![cfg(feature = "file-webdav")]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::str;
+use std::thread;
+
+use acid_store::repo::file::{Entry, FileRepo, WebDavListener};
+use acid_store::repo::{OpenMode, OpenOptions};
+use acid_store::store::MemoryConfig;
+
+/// Send an HTTP request to the server at `address` and return the status and body of the response.
+fn request(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> anyhow::Result<(u16, String)> {
+    let mut stream = TcpStream::connect(address)?;
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        address,
+        body.len()
+    )?;
+    for (name, value) in headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+    stream.write_all(b"\r\n")?;
+    stream.write_all(body)?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = str::from_utf8(&response)?;
+
+    let (head, body) = response.split_at(response.find("\r\n\r\n").unwrap() + 4);
+    let status = head.split(' ').nth(1).unwrap().parse()?;
+    Ok((status, body.to_owned()))
+}
+
+#[test]
+fn webdav_requests_modify_repository() -> anyhow::Result<()> {
+    let repo: FileRepo = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .open(&MemoryConfig::new())?;
+    let listener = WebDavListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr();
+    let shutdown = listener.shutdown_handle();
+
+    // The root of the repository can be served.
+    let server = thread::spawn(move || -> anyhow::Result<FileRepo> {
+        repo.serve_webdav(listener, "")?;
+        Ok(repo)
+    });
+
+    assert_eq!(request(address, "MKCOL", "/dir", &[], b"")?.0, 201);
+    assert_eq!(request(address, "PUT", "/dir/file", &[], b"data")?.0, 201);
+    assert_eq!(
+        request(address, "GET", "/dir/file", &[], b"")?,
+        (200, String::from("data"))
+    );
+
+    let (status, body) = request(address, "PROPFIND", "/", &[("Depth", "1")], b"")?;
+    assert_eq!(status, 207);
+    assert!(body.contains("<D:href>/dir/</D:href>"));
+
+    let (status, body) = request(address, "PROPFIND", "/dir", &[("Depth", "1")], b"")?;
+    assert_eq!(status, 207);
+    assert!(body.contains("<D:href>/dir/file</D:href>"));
+    assert!(body.contains("<D:getcontentlength>4</D:getcontentlength>"));
+
+    let destination = format!("http://{}/dir/moved", address);
+    assert_eq!(
+        request(
+            address,
+            "MOVE",
+            "/dir/file",
+            &[("Destination", &destination)],
+            b""
+        )?
+        .0,
+        201
+    );
+    assert_eq!(request(address, "GET", "/dir/file", &[], b"")?.0, 404);
+    assert_eq!(
+        request(address, "GET", "/dir/moved", &[], b"")?,
+        (200, String::from("data"))
+    );
+
+    assert_eq!(request(address, "DELETE", "/dir/moved", &[], b"")?.0, 204);
+    assert_eq!(request(address, "GET", "/dir/moved", &[], b"")?.0, 404);
+
+    shutdown.shutdown();
+    let repo = server.join().unwrap()?;
+
+    assert!(repo.is_directory("dir"));
+    assert!(!repo.exists("dir/file"));
+    assert!(!repo.exists("dir/moved"));
+
+    Ok(())
+}
+
+#[test]
+fn serving_file_over_webdav_errs() -> anyhow::Result<()> {
+    let repo: FileRepo = OpenOptions::new()
+        .mode(OpenMode::CreateNew)
+        .open(&MemoryConfig::new())?;
+    repo.create("file", &Entry::file())?;
+
+    let listener = WebDavListener::bind("127.0.0.1:0")?;
+    assert!(matches!(
+        repo.serve_webdav(listener, "file"),
+        Err(acid_store::Error::NotDirectory)
+    ));
+
+    Ok(())
+}